@@ -1,4 +1,7 @@
+include!("../usb_identity.rs");
+
 fn main() {
     println!("cargo:rerun-if-changed=layout.ld");
     println!("cargo:rerun-if-changed=../../kernel_layout.ld");
+    write_usb_identity();
 }
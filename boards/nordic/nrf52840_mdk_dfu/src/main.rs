@@ -39,20 +39,8 @@ const UART_RXD: Pin = Pin::P0_19;
 /// UART Writer
 pub mod io;
 
-const VENDOR_ID: u16 = 0x1915; // Nordic Semiconductor
-const PRODUCT_ID: u16 = 0x521f; // nRF52840 Dongle (PCA10059)
-static STRINGS: &'static [&'static str] = &[
-    // Manufacturer
-    "Nordic Semiconductor ASA",
-    // Product
-    "OpenSK",
-    // Serial number
-    "v1.0",
-    // Interface description + main HID string
-    "FIDO2",
-    // vendor HID string
-    "Vendor HID",
-];
+// VENDOR_ID, PRODUCT_ID and STRINGS, see `usb_identity.rs` of the boards.
+include!(concat!(env!("OUT_DIR"), "/usb_identity.rs"));
 
 // State for loading and holding applications.
 // How should the kernel respond when a process faults.
@@ -2,9 +2,12 @@ use std::env;
 use std::fs;
 use std::path::Path;
 
+include!("../usb_identity.rs");
+
 fn main() {
     println!("cargo:rerun-if-changed=layout.ld");
     println!("cargo:rerun-if-changed=../../kernel_layout.ld");
+    write_usb_identity();
 
     let out_dir = env::var_os("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("locations.rs");
@@ -0,0 +1,70 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// USB identity of the OpenSK boards, included by their build scripts.
+//
+// Vendors set the environment variables below when building the kernel to ship branded devices,
+// and the same IDs when running the tools, so that they find the device. Reports keep 64 bytes,
+// the packet size of CTAPHID on full speed USB. The vendor HID interface is added with the
+// `vendor_hid` feature.
+
+/// Writes `usb_identity.rs` with the IDs and strings of the USB descriptors.
+fn write_usb_identity() {
+    println!("cargo:rerun-if-changed=../usb_identity.rs");
+    // Nordic Semiconductor, nRF52840 Dongle (PCA10059).
+    let vendor_id = usb_id("OPENSK_USB_VENDOR_ID", 0x1915);
+    let product_id = usb_id("OPENSK_USB_PRODUCT_ID", 0x521F);
+    let strings = [
+        usb_string("OPENSK_USB_MANUFACTURER", "Nordic Semiconductor ASA"),
+        usb_string("OPENSK_USB_PRODUCT", "OpenSK"),
+        usb_string("OPENSK_USB_SERIAL_NUMBER", "v1.0"),
+        // Interface description and main HID string.
+        String::from("FIDO2"),
+        String::from("Vendor HID"),
+    ];
+    let out_dir = std::env::var_os("OUT_DIR").unwrap();
+    let dest_path = std::path::Path::new(&out_dir).join("usb_identity.rs");
+    std::fs::write(
+        &dest_path,
+        format!(
+            "const VENDOR_ID: u16 = 0x{:04X};\n\
+             const PRODUCT_ID: u16 = 0x{:04X};\n\
+             static STRINGS: &'static [&'static str] = &{:?};\n",
+            vendor_id, product_id, strings
+        ),
+    )
+    .unwrap();
+}
+
+/// Reads a hexadecimal ID from the environment, with or without the `0x` prefix.
+fn usb_id(name: &str, default: u16) -> u16 {
+    println!("cargo:rerun-if-env-changed={}", name);
+    match std::env::var(name) {
+        Err(_) => default,
+        Ok(value) => u16::from_str_radix(value.trim_start_matches("0x"), 16)
+            .unwrap_or_else(|_| panic!("{} must be a 16 bit hexadecimal number", name)),
+    }
+}
+
+fn usb_string(name: &str, default: &str) -> String {
+    println!("cargo:rerun-if-env-changed={}", name);
+    let value = std::env::var(name).unwrap_or_else(|_| String::from(default));
+    // String descriptors have a one byte length, including the two byte header.
+    assert!(
+        !value.is_empty() && value.encode_utf16().count() <= 126,
+        "{} must have between 1 and 126 characters",
+        name
+    );
+    value
+}
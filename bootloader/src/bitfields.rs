@@ -114,5 +114,23 @@ register_bitfields! [u32,
     pub PaddingConfig [
         /// Enable Padding generation. must be reset upon completion of padding.
         DO_PAD OFFSET(2) NUMBITS(1)
+    ],
+
+    // NVMC register bitfields
+    pub NvmcReady [
+        /// Cleared while the flash is written or erased
+        READY OFFSET(0) NUMBITS(1) [
+            Busy = 0,
+            Ready = 1
+        ]
+    ],
+
+    pub NvmcConfig [
+        /// Program memory access mode
+        WEN OFFSET(0) NUMBITS(2) [
+            Ren = 0,
+            Wen = 1,
+            Een = 2
+        ]
     ]
 ];
@@ -17,6 +17,7 @@
 
 mod bitfields;
 mod crypto_cell;
+mod nvmc;
 mod registers;
 mod static_ref;
 
@@ -35,6 +36,14 @@ use rtt_target::{rprintln, rtt_init_print};
 /// Size of a flash page in bytes.
 const PAGE_SIZE: usize = 0x1000;
 const METADATA_SIGN_OFFSET: usize = 0x800;
+/// Words of the boot status, between the signature and the signed metadata.
+///
+/// They are only ever cleared, and a new bundle resets them. See `upgrade_helper` of OpenSK.
+const CONFIRMED_OFFSET: usize = 0x100;
+const ROLLBACK_OFFSET: usize = 0x104;
+const BOOT_ATTEMPTS_OFFSET: usize = 0x108;
+/// Boot attempts of an unconfirmed image before falling back to the confirmed one.
+const MAX_BOOT_ATTEMPTS: usize = 3;
 
 /// A flash page.
 type Page = [u8; PAGE_SIZE];
@@ -52,20 +61,56 @@ struct Metadata {
     _signature: [u8; 64],
     version: u64,
     address: u32,
+    confirmed: bool,
+    rollback_requested: bool,
+    boot_attempts: usize,
 }
 
 /// Reads the metadata from a flash page.
 impl From<Page> for Metadata {
     fn from(page: Page) -> Self {
+        let is_cleared = |offset: usize| page[offset..][..4] != [0xFF; 4];
         Metadata {
             checksum: page[0..32].try_into().unwrap(),
             _signature: page[32..96].try_into().unwrap(),
             version: LittleEndian::read_u64(&page[METADATA_SIGN_OFFSET..][..8]),
             address: LittleEndian::read_u32(&page[METADATA_SIGN_OFFSET + 8..][..4]),
+            confirmed: is_cleared(CONFIRMED_OFFSET),
+            rollback_requested: is_cleared(ROLLBACK_OFFSET),
+            boot_attempts: (0..MAX_BOOT_ATTEMPTS)
+                .filter(|i| is_cleared(BOOT_ATTEMPTS_OFFSET + 4 * i))
+                .count(),
         }
     }
 }
 
+impl Metadata {
+    /// Whether boots of this image are counted, because the other image is a good fallback.
+    ///
+    /// Images are only on trial next to a confirmed one, so that devices whose host never
+    /// confirms images keep booting the newest.
+    fn is_on_trial(&self, other: &Metadata) -> bool {
+        !self.confirmed && other.confirmed && !other.rollback_requested
+    }
+
+    /// Whether this image may boot instead of the other valid one.
+    fn is_usable(&self, other: &Metadata) -> bool {
+        let failed = self.is_on_trial(other) && self.boot_attempts >= MAX_BOOT_ATTEMPTS;
+        !self.rollback_requested && !failed
+    }
+
+    /// Whether to boot this image over the other valid one.
+    ///
+    /// Usable images win, and among those the newest.
+    fn is_preferred(&self, other: &Metadata) -> bool {
+        let usable = self.is_usable(other);
+        if usable != other.is_usable(self) {
+            return usable;
+        }
+        self.version >= other.version
+    }
+}
+
 /// Location of a firmware partition's data.
 struct BootPartition {
     firmware_address: usize,
@@ -75,8 +120,8 @@ struct BootPartition {
 impl BootPartition {
     const FIRMWARE_LENGTH: usize = 0x00040000;
 
-    /// Reads the metadata, returns the firmware version if all checks pass.
-    pub fn read_version(&self) -> Result<u64, ()> {
+    /// Reads the metadata, returns it if all checks pass.
+    pub fn read_metadata(&self) -> Result<Metadata, ()> {
         let metadata_page = unsafe { read_page(self.metadata_address) };
         let hash_value = self.compute_upgrade_hash(&metadata_page);
         let metadata = Metadata::from(metadata_page);
@@ -94,7 +139,16 @@ impl BootPartition {
             rprintln!("Hash mismatch");
             return Err(());
         }
-        Ok(metadata.version)
+        Ok(metadata)
+    }
+
+    /// Uses up a boot attempt of the image, before booting it on trial.
+    pub fn record_boot_attempt(&self, metadata: &Metadata) {
+        if metadata.boot_attempts >= MAX_BOOT_ATTEMPTS {
+            return;
+        }
+        let address = self.metadata_address + BOOT_ATTEMPTS_OFFSET + 4 * metadata.boot_attempts;
+        unsafe { nvmc::Nvmc::new().write_word(address, 0) };
     }
 
     /// Computes the SHA256 of metadata information and partition data.
@@ -158,18 +212,22 @@ fn main() -> ! {
     };
     #[cfg(debug_assertions)]
     rprintln!("Reading partition A");
-    let version_a = partition_a.read_version();
+    let metadata_a = partition_a.read_metadata();
     #[cfg(debug_assertions)]
     rprintln!("Reading partition B");
-    let version_b = partition_b.read_version();
+    let metadata_b = partition_b.read_metadata();
 
-    match (version_a, version_b) {
-        (Ok(t1), Ok(t2)) => {
-            if t1 >= t2 {
-                partition_a.boot()
+    match (metadata_a, metadata_b) {
+        (Ok(a), Ok(b)) => {
+            let (partition, metadata, other) = if a.is_preferred(&b) {
+                (partition_a, a, b)
             } else {
-                partition_b.boot()
+                (partition_b, b, a)
+            };
+            if metadata.is_on_trial(&other) {
+                partition.record_boot_attempt(&metadata);
             }
+            partition.boot()
         }
         (Ok(_), Err(_)) => partition_a.boot(),
         (Err(_), Ok(_)) => partition_b.boot(),
@@ -0,0 +1,53 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Non-volatile memory controller, to clear words of the boot status in flash.
+
+use super::bitfields::{NvmcConfig, NvmcReady};
+use super::registers::NvmcRegisters;
+use super::static_ref::StaticRef;
+use core::ptr;
+use tock_registers::interfaces::{Readable, Writeable};
+
+const NVMC_BASE: StaticRef<NvmcRegisters> =
+    unsafe { StaticRef::new(0x4001E000 as *const NvmcRegisters) };
+
+pub struct Nvmc {
+    registers: StaticRef<NvmcRegisters>,
+}
+
+impl Nvmc {
+    pub const fn new() -> Self {
+        Nvmc {
+            registers: NVMC_BASE,
+        }
+    }
+
+    fn wait_ready(&self) {
+        while !self.registers.ready.is_set(NvmcReady::READY) {}
+    }
+
+    /// Writes a word of flash without erasing, which can only clear bits.
+    ///
+    /// # Safety
+    ///
+    /// The address must be word aligned in flash outside the bootloader.
+    pub unsafe fn write_word(&self, address: usize, value: u32) {
+        self.registers.config.write(NvmcConfig::WEN::Wen);
+        self.wait_ready();
+        ptr::write_volatile(address as *mut u32, value);
+        self.wait_ready();
+        self.registers.config.write(NvmcConfig::WEN::Ren);
+    }
+}
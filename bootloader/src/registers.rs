@@ -13,7 +13,8 @@
 // limitations under the License.
 
 use super::bitfields::{
-    Busy, CryptoMode, HashControl, Interrupts, LliWord1, PaddingConfig, RgfEndianness, Task,
+    Busy, CryptoMode, HashControl, Interrupts, LliWord1, NvmcConfig, NvmcReady, PaddingConfig,
+    RgfEndianness, Task,
 };
 use tock_registers::register_structs;
 use tock_registers::registers::{ReadOnly, ReadWrite, WriteOnly};
@@ -135,3 +136,15 @@ register_structs! {
         (0x0C30 => @END),
     }
 }
+
+register_structs! {
+    pub NvmcRegisters {
+        (0x0000 => _reserved0),
+        /// Ready flag
+        (0x0400 => pub ready: ReadOnly<u32, NvmcReady::Register>),
+        (0x0404 => _reserved1),
+        /// Configuration register
+        (0x0504 => pub config: ReadWrite<u32, NvmcConfig::Register>),
+        (0x0508 => @END),
+    }
+}
@@ -21,28 +21,99 @@ use std::path::Path;
 use std::{env, fs};
 use uuid::Uuid;
 
+/// FIDO metadata service entry of the authenticator, optional.
+const METADATA_FILE: &str = "crypto_data/metadata.json";
+
+/// Status reports of certified authenticators, in the order of the getInfo certification levels.
+const CERTIFICATION_STATUSES: &[&str] = &[
+    "FIDO_CERTIFIED_L1",
+    "FIDO_CERTIFIED_L1plus",
+    "FIDO_CERTIFIED_L2",
+    "FIDO_CERTIFIED_L2plus",
+    "FIDO_CERTIFIED_L3",
+    "FIDO_CERTIFIED_L3plus",
+];
+
 fn main() {
     const UPGRADE_FILE: &str = "crypto_data/opensk_upgrade_pub.pem";
+    const PROVISIONING_FILE: &str = "crypto_data/opensk_provisioning_pub.pem";
     println!("cargo:rerun-if-changed=crypto_data/aaguid.txt");
+    println!("cargo:rerun-if-changed={METADATA_FILE}");
     println!("cargo:rerun-if-changed={UPGRADE_FILE}");
+    println!("cargo:rerun-if-changed={PROVISIONING_FILE}");
     println!("cargo:rerun-if-changed=layout.ld");
     println!("cargo:rerun-if-changed=nrf52840_layout.ld");
     println!("cargo:rerun-if-changed=nrf52840_layout_a.ld");
     println!("cargo:rerun-if-changed=nrf52840_layout_b.ld");
 
     let out_dir = env::var_os("OUT_DIR").unwrap();
-    let aaguid_bin_path = Path::new(&out_dir).join("opensk_aaguid.bin");
-
-    let mut aaguid_bin_file = File::create(aaguid_bin_path).unwrap();
     let mut aaguid_txt_file = File::open("crypto_data/aaguid.txt").unwrap();
     let mut content = String::new();
     aaguid_txt_file.read_to_string(&mut content).unwrap();
     content.truncate(36);
     let aaguid = Uuid::parse_str(&content).unwrap();
-    aaguid_bin_file.write_all(aaguid.as_bytes()).unwrap();
+    write_metadata(&aaguid, &out_dir, "opensk_metadata.rs");
+
+    write_ipc_grants(&out_dir, "opensk_ipc_grants.rs");
+
+    write_public_key(UPGRADE_FILE, &out_dir, "opensk_upgrade_pubkey.bin");
+    write_public_key(
+        PROVISIONING_FILE,
+        &out_dir,
+        "opensk_provisioning_pubkey.bin",
+    );
+}
+
+/// Writes the constants of the metadata module to the output directory.
+///
+/// Without a metadata file, the authenticator is uncertified and described as OpenSK.
+fn write_metadata(aaguid: &Uuid, out_dir: &std::ffi::OsStr, rs_file: &str) {
+    let mut description = String::from("OpenSK");
+    let mut certification_level = None;
+    if Path::new(METADATA_FILE).exists() {
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&fs::read(METADATA_FILE).unwrap()).unwrap();
+        let statement = &metadata["metadataStatement"];
+        // Relying parties look up the statement by the AAGUID the firmware reports.
+        let statement_aaguid = Uuid::parse_str(statement["aaguid"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            &statement_aaguid, aaguid,
+            "The AAGUID of {METADATA_FILE} differs from crypto_data/aaguid.txt."
+        );
+        description = String::from(statement["description"].as_str().unwrap());
+        certification_level = metadata["statusReports"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|report| report["status"].as_str())
+            .filter_map(|status| CERTIFICATION_STATUSES.iter().position(|s| *s == status))
+            .max()
+            .map(|index| index as u64 + 1);
+    }
+    let metadata_path = Path::new(out_dir).join(rs_file);
+    let mut metadata_file = File::create(metadata_path).unwrap();
+    writeln!(
+        metadata_file,
+        "pub const AAGUID: &[u8; AAGUID_LENGTH] = &{:?};",
+        aaguid.as_bytes()
+    )
+    .unwrap();
+    writeln!(
+        metadata_file,
+        "pub const CERTIFICATION_LEVEL: Option<u64> = {certification_level:?};"
+    )
+    .unwrap();
+    writeln!(
+        metadata_file,
+        "pub const DESCRIPTION: &str = {description:?};"
+    )
+    .unwrap();
+}
 
+/// Writes the uncompressed encoding of a PEM public key to the output directory.
+fn write_public_key(pem_file: &str, out_dir: &std::ffi::OsStr, bin_file: &str) {
     // COSE encoding the public key, then write it out.
-    let pem_bytes = fs::read(UPGRADE_FILE).unwrap();
+    let pem_bytes = fs::read(pem_file).unwrap();
     let ec_key = ec::EcKey::public_key_from_pem(&pem_bytes).ok().unwrap();
     let group = ec::EcGroup::from_curve_name(nid::Nid::X9_62_PRIME256V1).unwrap();
     let conversion_form = ec::PointConversionForm::UNCOMPRESSED;
@@ -51,7 +122,43 @@ fn main() {
         .public_key()
         .to_bytes(&group, conversion_form, &mut ctx)
         .unwrap();
-    let upgrade_pubkey_path = Path::new(&out_dir).join("opensk_upgrade_pubkey.bin");
-    let mut upgrade_pub_bin_file = File::create(upgrade_pubkey_path).unwrap();
-    upgrade_pub_bin_file.write_all(&raw_bytes).unwrap();
+    let pubkey_path = Path::new(out_dir).join(bin_file);
+    let mut pub_bin_file = File::create(pubkey_path).unwrap();
+    pub_bin_file.write_all(&raw_bytes).unwrap();
+}
+
+/// Writes the IPC grants of `OPENSK_IPC_GRANTS` to the output directory.
+///
+/// Grants are separated by commas, each a process index and the capabilities of that app, e.g.
+/// `1:sign-statement+bbs-proof,2:bbs-proof`. Without grants, apps are denied all IPC commands.
+fn write_ipc_grants(out_dir: &std::ffi::OsStr, rs_file: &str) {
+    println!("cargo:rerun-if-env-changed=OPENSK_IPC_GRANTS");
+    let grants = env::var("OPENSK_IPC_GRANTS").unwrap_or_default();
+    let mut entries = Vec::new();
+    for grant in grants.split(',').filter(|grant| !grant.is_empty()) {
+        let (client, capabilities) = grant
+            .split_once(':')
+            .unwrap_or_else(|| panic!("Invalid grant {grant:?} in OPENSK_IPC_GRANTS."));
+        let client: u32 = client
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid process index {client:?} in OPENSK_IPC_GRANTS."));
+        let mut expression = String::from("IpcCapabilities::NONE");
+        for capability in capabilities.split('+') {
+            let name = match capability {
+                "sign-statement" => "SIGN_STATEMENT",
+                "bbs-proof" => "BBS_PROOF",
+                _ => panic!("Unknown capability {capability:?} in OPENSK_IPC_GRANTS."),
+            };
+            expression.push_str(&format!(".union(IpcCapabilities::{name})"));
+        }
+        entries.push(format!("({client}, {expression})"));
+    }
+    let grants_path = Path::new(out_dir).join(rs_file);
+    let mut grants_file = File::create(grants_path).unwrap();
+    writeln!(
+        grants_file,
+        "pub const IPC_GRANTS: &[(u32, IpcCapabilities)] = &[{}];",
+        entries.join(", ")
+    )
+    .unwrap();
 }
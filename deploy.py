@@ -1040,6 +1040,14 @@ if __name__ == "__main__":
       help=("The console will be used to output allocator statistics every "
             "time an allocation/deallocation happens."),
   )
+  main_parser.add_argument(
+      "--heap-stats",
+      action="append_const",
+      const="heap_stats",
+      dest="features",
+      help=("Tracks the current and peak heap usage, and reports them "
+            "through a vendor command."),
+  )
   main_parser.add_argument(
       "--verbose",
       action="append_const",
@@ -1056,6 +1064,14 @@ if __name__ == "__main__":
       help=("Compiles the OpenSK application without backward compatible "
             "support for U2F/CTAP1 protocol."),
   )
+  main_parser.add_argument(
+      "--no-bbs",
+      action=RemoveConstAction,
+      const="bbs",
+      dest="features",
+      help=("Compiles the OpenSK application without the BBS vendor commands "
+            "and link secret, for a smaller FIDO-only firmware."),
+  )
   main_parser.add_argument(
       "--no-config-command",
       action=RemoveConstAction,
@@ -1070,6 +1086,14 @@ if __name__ == "__main__":
       dest="features",
       help=("Compiles the OpenSK application with RustCrypto implementations."),
   )
+  main_parser.add_argument(
+      "--hardened",
+      action="append_const",
+      const="hardened",
+      dest="features",
+      help=("Blinds ECDSA scalars and verifies each signature before releasing "
+            "it, as countermeasures to side channels and fault injection."),
+  )
   main_parser.add_argument(
       "--nfc",
       action="append_const",
@@ -1091,6 +1115,39 @@ if __name__ == "__main__":
       dest="features",
       help=("Compiles the OpenSK application to support two HID usage pages."),
   )
+  main_parser.add_argument(
+      "--compression",
+      action="append_const",
+      const="compression",
+      dest="features",
+      help=("Compiles the OpenSK application with LZSS compression of vendor "
+            "command payloads, negotiated through the vendor info command."),
+  )
+  main_parser.add_argument(
+      "--dual-store",
+      action="append_const",
+      const="dual_store",
+      dest="features",
+      help=("Compiles the OpenSK application with PIN hash, master keys and "
+            "link secret in a store of their own. Changing this option "
+            "requires erasing the persistent storage."),
+  )
+  main_parser.add_argument(
+      "--ccid",
+      action="append_const",
+      const="ccid",
+      dest="features",
+      help=("Compiles the OpenSK application with a USB smart card (CCID) "
+            "interface. The board must expose the CCID driver."),
+  )
+  main_parser.add_argument(
+      "--ipc",
+      action="append_const",
+      const="ipc",
+      dest="features",
+      help=("Compiles the OpenSK application with an IPC service, so that "
+            "other apps can request signed statements and BBS proofs."),
+  )
   main_parser.add_argument(
       "--regen-keys",
       action="store_true",
@@ -1146,7 +1203,7 @@ if __name__ == "__main__":
       help=("Firmware version that is built."),
   )
 
-  main_parser.set_defaults(features=["with_ctap1", "config_command"])
+  main_parser.set_defaults(features=["with_ctap1", "config_command", "bbs"])
 
   # Start parsing to know if we're going to list things or not.
   partial_args, _ = main_parser.parse_known_args()
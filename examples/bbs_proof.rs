@@ -6,9 +6,16 @@ extern crate lang_items;
 
 use alloc::format;
 use alloc::vec::Vec;
-use bbs::{generate_proof, BBSCommitmentBlindFactor, BBSPublicKey, BBSSignature, LinkSecret};
+use bbs::{
+    generate_proof_with_progress, BBSCommitmentBlindFactor, BBSPublicKey, BBSSignature, LinkSecret,
+    ProofStage,
+};
 use ctap2::env::tock::TockRng;
 use libtock_console::Console;
+use libtock_drivers::result::FlexUnwrap;
+use libtock_drivers::timer;
+use libtock_drivers::timer::Timestamp;
+use libtock_platform::DefaultConfig;
 use libtock_runtime::{set_main, stack_size, TockSyscalls};
 
 stack_size! {0x4000}
@@ -48,8 +55,15 @@ pub fn main() {
     )
     .unwrap();
 
-    // Generate proof
-    let proof_response = generate_proof(
+    // Setup the timer with a dummy callback, we only read the current time.
+    let mut with_callback = timer::with_callback::<Syscalls, DefaultConfig, _>(|_| {});
+    let timer = with_callback.init().flex_unwrap();
+    let now =
+        || Timestamp::<f64>::from_clock_value(timer.get_current_counter_ticks().flex_unwrap());
+
+    // Generate proof, timing each stage
+    let mut last = now();
+    let proof_response = generate_proof_with_progress(
         &mut rng,
         &pk,
         &messages,
@@ -59,6 +73,13 @@ pub fn main() {
         Some(presentation_header),
         &disclosed_indexes,
         Some(&secret_prover_blind),
+        |stage| {
+            let time = now();
+            if stage != ProofStage::Started {
+                write_str(&format!("{:?} after {} ms\n", stage, (time - last).ms()));
+            }
+            last = time;
+        },
     )
     .unwrap();
 
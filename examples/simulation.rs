@@ -0,0 +1,202 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runs OpenSK on the desktop, behind a virtual HID device created through Linux `uhid`.
+//!
+//! Browsers and the BBS wallet find it like a real key, with the same vendor and product IDs. The
+//! storage lives in RAM, so credentials are lost when the simulation stops. User presence is
+//! always confirmed.
+//!
+//! Run it with `cargo run --example simulation --features std,bbs,vendor_hid`, with write access
+//! to `/dev/uhid`.
+
+use ctap2::env::tock::TockEnv;
+use libtock_unittest::fake;
+use opensk::api::user_presence::{UserPresenceError, UserPresenceSource};
+use opensk::ctap::hid::HidPacket;
+use opensk::ctap::log::Level;
+use opensk::{log_ctap, Ctap, Transport};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+const UHID_PATH: &str = "/dev/uhid";
+// Event types and layouts of `linux/uhid.h`.
+const UHID_DESTROY: u32 = 1;
+const UHID_OUTPUT: u32 = 6;
+const UHID_CREATE2: u32 = 11;
+const UHID_INPUT2: u32 = 12;
+const UHID_DATA_MAX: usize = 4096;
+const UHID_EVENT_SIZE: usize = 4376;
+const BUS_USB: u16 = 0x03;
+
+// Same as the firmware, so that clients don't need to know about the simulation.
+const OPENSK_VID: u32 = 0x1915;
+const OPENSK_PID: u32 = 0x521F;
+const PACKET_SIZE: usize = 64;
+const ALARM_FREQUENCY_HZ: u32 = 32768;
+
+/// CTAPHID report descriptor, 64 bytes in and out.
+const FIDO_REPORT_DESCRIPTOR: [u8; 34] = [
+    0x06, 0xD0, 0xF1, 0x09, 0x01, 0xA1, 0x01, 0x09, 0x20, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08,
+    0x95, 0x40, 0x81, 0x02, 0x09, 0x21, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x40, 0x91,
+    0x02, 0xC0,
+];
+/// Same as CTAPHID, on the vendor usage page that only OpenSK tools open.
+#[cfg(feature = "vendor_hid")]
+const VENDOR_REPORT_DESCRIPTOR: [u8; 34] = [
+    0x06, 0x00, 0xFF, 0x09, 0x01, 0xA1, 0x01, 0x09, 0x20, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08,
+    0x95, 0x40, 0x81, 0x02, 0x09, 0x21, 0x15, 0x00, 0x26, 0xFF, 0x00, 0x75, 0x08, 0x95, 0x40, 0x91,
+    0x02, 0xC0,
+];
+
+/// Confirms user presence without waiting, there is no button to press.
+struct AlwaysPresent;
+
+impl UserPresenceSource for AlwaysPresent {
+    fn poll(&mut self) -> Result<bool, UserPresenceError> {
+        Ok(true)
+    }
+}
+
+/// A virtual HID device, destroyed when dropped.
+struct UhidDevice {
+    file: File,
+}
+
+impl UhidDevice {
+    fn create(name: &str, report_descriptor: &[u8]) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(UHID_PATH)?;
+        let mut event = [0; UHID_EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_CREATE2.to_ne_bytes());
+        event[4..][..name.len()].copy_from_slice(name.as_bytes());
+        event[260..262].copy_from_slice(&(report_descriptor.len() as u16).to_ne_bytes());
+        event[262..264].copy_from_slice(&BUS_USB.to_ne_bytes());
+        event[264..268].copy_from_slice(&OPENSK_VID.to_ne_bytes());
+        event[268..272].copy_from_slice(&OPENSK_PID.to_ne_bytes());
+        event[280..][..report_descriptor.len()].copy_from_slice(report_descriptor);
+        file.write_all(&event)?;
+        Ok(UhidDevice { file })
+    }
+
+    /// Blocks until the host sends a packet, skipping other events.
+    fn receive(&mut self) -> io::Result<HidPacket> {
+        let mut event = [0; UHID_EVENT_SIZE];
+        loop {
+            self.file.read(&mut event)?;
+            if u32::from_ne_bytes([event[0], event[1], event[2], event[3]]) != UHID_OUTPUT {
+                continue;
+            }
+            let size_offset = 4 + UHID_DATA_MAX;
+            let size = u16::from_ne_bytes([event[size_offset], event[size_offset + 1]]) as usize;
+            // Hosts prefix the report ID 0 on devices without numbered reports.
+            let data = match size {
+                PACKET_SIZE => &event[4..][..PACKET_SIZE],
+                s if s == PACKET_SIZE + 1 => &event[5..][..PACKET_SIZE],
+                _ => continue,
+            };
+            let mut packet = [0; PACKET_SIZE];
+            packet.copy_from_slice(data);
+            return Ok(packet);
+        }
+    }
+
+    fn send(&mut self, packet: &HidPacket) -> io::Result<()> {
+        let mut event = [0; UHID_EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_INPUT2.to_ne_bytes());
+        event[4..6].copy_from_slice(&(PACKET_SIZE as u16).to_ne_bytes());
+        event[6..][..PACKET_SIZE].copy_from_slice(packet);
+        self.file.write_all(&event)
+    }
+
+    /// Returns a handle to the same device, for sending while another thread receives.
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(UhidDevice {
+            file: self.file.try_clone()?,
+        })
+    }
+}
+
+impl Drop for UhidDevice {
+    fn drop(&mut self) {
+        let mut event = [0; UHID_EVENT_SIZE];
+        event[..4].copy_from_slice(&UHID_DESTROY.to_ne_bytes());
+        self.file.write_all(&event).ok();
+    }
+}
+
+/// Forwards the packets of a device to the main loop, tagged with their transport.
+fn spawn_receiver(
+    mut device: UhidDevice,
+    transport: Transport,
+    sender: mpsc::Sender<(Transport, HidPacket)>,
+) {
+    thread::spawn(move || loop {
+        match device.receive() {
+            Ok(packet) => {
+                if sender.send((transport, packet)).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                eprintln!("Reading {:?} failed: {}", transport, e);
+                return;
+            }
+        }
+    });
+}
+
+fn main() -> io::Result<()> {
+    // The environment reaches the clock and LEDs through fake syscalls.
+    let kernel = fake::Kernel::new();
+    let alarm = fake::Alarm::new(ALARM_FREQUENCY_HZ);
+    kernel.add_driver(&alarm);
+    let leds = fake::Leds::<4>::new();
+    kernel.add_driver(&leds);
+    let start = Instant::now();
+
+    let mut env = TockEnv::<fake::Syscalls>::default();
+    env.add_user_presence_source(Box::new(AlwaysPresent));
+    let mut ctap = Ctap::new(env);
+
+    let (sender, receiver) = mpsc::channel();
+    let mut main_device = UhidDevice::create("OpenSK simulation", &FIDO_REPORT_DESCRIPTOR)?;
+    spawn_receiver(main_device.try_clone()?, Transport::MainHid, sender.clone());
+    #[cfg(feature = "vendor_hid")]
+    let mut vendor_device =
+        UhidDevice::create("OpenSK simulation vendor", &VENDOR_REPORT_DESCRIPTOR)?;
+    #[cfg(feature = "vendor_hid")]
+    spawn_receiver(vendor_device.try_clone()?, Transport::VendorHid, sender);
+    #[cfg(not(feature = "vendor_hid"))]
+    drop(sender);
+    println!("OpenSK simulation running, stop it with Ctrl-C.");
+
+    for (transport, packet) in receiver {
+        let ticks = start.elapsed().as_secs_f64() * ALARM_FREQUENCY_HZ as f64;
+        alarm.set_value(ticks as u64 as u32);
+        let device = match transport {
+            Transport::MainHid => &mut main_device,
+            #[cfg(feature = "vendor_hid")]
+            Transport::VendorHid => &mut vendor_device,
+        };
+        for reply in ctap.process_hid_packet(&packet, transport) {
+            if let Err(e) = device.send(&reply) {
+                log_ctap!(ctap.env(), Level::Warn, "Sending failed: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
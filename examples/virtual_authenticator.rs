@@ -0,0 +1,227 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runs OpenSK on the desktop, behind a localhost socket, for CI containers without HID access.
+//!
+//! Each CTAPHID packet travels as 64 raw bytes over TCP, or as one binary message over WebSocket
+//! for browser-based test suites. The FIDO interface listens on the given port, and with
+//! `vendor_hid` the vendor interface on the next one. Several clients can connect at once, and
+//! keep their CTAPHID channels apart like on USB. Like the `uhid` simulation, the storage lives in
+//! RAM and user presence is always confirmed.
+//!
+//! Run it with `cargo run --example virtual_authenticator --features std,bbs,vendor_hid`, and
+//! append `-- --websocket` or `-- --port PORT` if needed.
+
+use ctap2::env::tock::TockEnv;
+use libtock_unittest::fake;
+use opensk::api::user_presence::{UserPresenceError, UserPresenceSource};
+use opensk::ctap::hid::HidPacket;
+use opensk::{Ctap, Transport};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::process::exit;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+use tungstenite::{Message, WebSocket};
+
+const DEFAULT_PORT: u16 = 8079;
+const PACKET_SIZE: usize = 64;
+const ALARM_FREQUENCY_HZ: u32 = 32768;
+
+/// Confirms user presence without waiting, there is no button to press.
+struct AlwaysPresent;
+
+impl UserPresenceSource for AlwaysPresent {
+    fn poll(&mut self) -> Result<bool, UserPresenceError> {
+        Ok(true)
+    }
+}
+
+/// A packet from a client, with the channel to send its replies to.
+struct Request {
+    transport: Transport,
+    packet: HidPacket,
+    replies: mpsc::Sender<Vec<HidPacket>>,
+}
+
+/// A connected client, in the framing of its listener.
+enum Client {
+    Tcp(TcpStream),
+    WebSocket(Box<WebSocket<TcpStream>>),
+}
+
+impl Client {
+    fn accept(stream: TcpStream, websocket: bool) -> io::Result<Self> {
+        // Packets are small and answered one by one, they shouldn't wait for more data.
+        stream.set_nodelay(true)?;
+        if !websocket {
+            return Ok(Client::Tcp(stream));
+        }
+        let socket = tungstenite::accept(stream)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        Ok(Client::WebSocket(Box::new(socket)))
+    }
+
+    /// Returns None once the client disconnects.
+    fn receive(&mut self) -> io::Result<Option<HidPacket>> {
+        match self {
+            Client::Tcp(stream) => {
+                let mut packet = [0; PACKET_SIZE];
+                match stream.read_exact(&mut packet) {
+                    Ok(()) => Ok(Some(packet)),
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            Client::WebSocket(socket) => loop {
+                match socket.read() {
+                    Ok(Message::Binary(data)) => {
+                        let packet = HidPacket::try_from(&data[..]).map_err(|_| {
+                            io::Error::new(io::ErrorKind::InvalidData, "packets have 64 bytes")
+                        })?;
+                        return Ok(Some(packet));
+                    }
+                    Ok(Message::Close(_)) | Err(tungstenite::Error::ConnectionClosed) => {
+                        return Ok(None)
+                    }
+                    // The library answers pings, and text has no meaning here.
+                    Ok(_) => (),
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+                }
+            },
+        }
+    }
+
+    fn send(&mut self, packet: &HidPacket) -> io::Result<()> {
+        match self {
+            Client::Tcp(stream) => stream.write_all(packet),
+            Client::WebSocket(socket) => socket
+                .send(Message::Binary(packet.to_vec()))
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string())),
+        }
+    }
+}
+
+/// Forwards the packets of a client to the main loop, and sends back its replies.
+fn serve(
+    mut client: Client,
+    transport: Transport,
+    sender: mpsc::Sender<Request>,
+) -> io::Result<()> {
+    let (reply_sender, reply_receiver) = mpsc::channel();
+    while let Some(packet) = client.receive()? {
+        let request = Request {
+            transport,
+            packet,
+            replies: reply_sender.clone(),
+        };
+        if sender.send(request).is_err() {
+            return Ok(());
+        }
+        // The main loop answers every request, possibly without any packet.
+        let replies = reply_receiver
+            .recv()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        for reply in replies {
+            client.send(&reply)?;
+        }
+    }
+    Ok(())
+}
+
+/// Accepts clients of an interface on localhost, each in its own thread.
+fn spawn_listener(
+    port: u16,
+    transport: Transport,
+    websocket: bool,
+    sender: mpsc::Sender<Request>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    println!("Serving {:?} on {}.", transport, listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let result = stream
+                    .and_then(|stream| Client::accept(stream, websocket))
+                    .and_then(|client| serve(client, transport, sender));
+                if let Err(e) = result {
+                    eprintln!("Client of {:?} failed: {}", transport, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!("usage: virtual_authenticator [--websocket] [--port PORT]");
+    exit(2)
+}
+
+/// Returns the port of the FIDO interface, and whether clients use WebSocket.
+fn parse_args() -> (u16, bool) {
+    let mut port = DEFAULT_PORT;
+    let mut websocket = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--websocket" => websocket = true,
+            // The vendor interface needs the next port too.
+            "--port" => match args.next().and_then(|p| p.parse().ok()) {
+                Some(p) if p > 0 && p < u16::MAX => port = p,
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    (port, websocket)
+}
+
+fn main() -> io::Result<()> {
+    let (port, websocket) = parse_args();
+
+    // The environment reaches the clock and LEDs through fake syscalls.
+    let kernel = fake::Kernel::new();
+    let alarm = fake::Alarm::new(ALARM_FREQUENCY_HZ);
+    kernel.add_driver(&alarm);
+    let leds = fake::Leds::<4>::new();
+    kernel.add_driver(&leds);
+    let start = Instant::now();
+
+    let mut env = TockEnv::<fake::Syscalls>::default();
+    env.add_user_presence_source(Box::new(AlwaysPresent));
+    let mut ctap = Ctap::new(env);
+
+    let (sender, receiver) = mpsc::channel();
+    spawn_listener(port, Transport::MainHid, websocket, sender.clone())?;
+    #[cfg(feature = "vendor_hid")]
+    spawn_listener(port + 1, Transport::VendorHid, websocket, sender)?;
+    #[cfg(not(feature = "vendor_hid"))]
+    drop(sender);
+    println!("OpenSK virtual authenticator running, stop it with Ctrl-C.");
+
+    for request in receiver {
+        let ticks = start.elapsed().as_secs_f64() * ALARM_FREQUENCY_HZ as f64;
+        alarm.set_value(ticks as u64 as u32);
+        let replies = ctap
+            .process_hid_packet(&request.packet, request.transport)
+            .collect();
+        // The client may have disconnected in the meantime.
+        request.replies.send(replies).ok();
+    }
+    Ok(())
+}
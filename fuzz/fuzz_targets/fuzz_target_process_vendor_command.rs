@@ -0,0 +1,13 @@
+#![no_main]
+
+use ctap2::env::tock::TockEnv;
+use libfuzzer_sys::fuzz_target;
+use libtock_unittest::fake::Syscalls;
+use opensk::ctap::Channel;
+use opensk::env::Env;
+
+// Fuzz inputs as vendor commands, parsed and processed by the Tock environment.
+fuzz_target!(|data: &[u8]| {
+    let mut env = TockEnv::<Syscalls>::default();
+    env.process_vendor_command(data, Channel::MainHid([0x12, 0x34, 0x56, 0x78]));
+});
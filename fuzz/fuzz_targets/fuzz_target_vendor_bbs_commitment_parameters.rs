@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use opensk::ctap::cbor_read;
+use opensk::vendor::parameters::VendorBBSCommitmentParameters;
+use std::convert::TryFrom;
+
+// Fuzz inputs as BBS commitment parameters.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cbor_value) = cbor_read(data) {
+        VendorBBSCommitmentParameters::try_from(cbor_value).ok();
+    }
+});
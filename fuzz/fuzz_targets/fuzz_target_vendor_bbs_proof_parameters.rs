@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use opensk::ctap::cbor_read;
+use opensk::vendor::parameters::VendorBBSProofParameters;
+use std::convert::TryFrom;
+
+// Fuzz inputs as BBS proof parameters.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(cbor_value) = cbor_read(data) {
+        VendorBBSProofParameters::try_from(cbor_value).ok();
+    }
+});
@@ -174,6 +174,14 @@ impl Value {
         }
     }
 
+    /// Borrows the byte string, for callers that only read it.
+    pub fn as_byte_string(&self) -> Option<&[u8]> {
+        match self {
+            Value(ValueImpl::ByteString(byte_string)) => Some(byte_string),
+            _ => None,
+        }
+    }
+
     pub fn extract_text_string(self) -> Option<String> {
         match self {
             Value(ValueImpl::TextString(text_string)) => Some(text_string),
@@ -188,6 +196,14 @@ impl Value {
         }
     }
 
+    /// Borrows the array, for callers that only read it.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value(ValueImpl::Array(array)) => Some(array),
+            _ => None,
+        }
+    }
+
     pub fn extract_map(self) -> Option<Vec<(Value, Value)>> {
         match self {
             Value(ValueImpl::Map(map)) => Some(map),
@@ -555,6 +571,15 @@ mod test {
         assert_eq!(cbor_bool!(false).extract_byte_string(), None);
     }
 
+    #[test]
+    fn test_as_byte_string() {
+        assert_eq!(cbor_int!(1).as_byte_string(), None);
+        assert_eq!(cbor_bytes!(vec![]).as_byte_string(), Some(&[][..]));
+        assert_eq!(cbor_bytes_lit!(b"bar").as_byte_string(), Some(&b"bar"[..]));
+        assert_eq!(cbor_text!("").as_byte_string(), None);
+        assert_eq!(cbor_array![].as_byte_string(), None);
+    }
+
     #[test]
     fn test_extract_text_string() {
         assert_eq!(cbor_int!(1).extract_text_string(), None);
@@ -584,6 +609,18 @@ mod test {
         assert_eq!(cbor_bool!(false).extract_array(), None);
     }
 
+    #[test]
+    fn test_as_array() {
+        assert_eq!(cbor_int!(1).as_array(), None);
+        assert_eq!(cbor_bytes!(vec![]).as_array(), None);
+        assert_eq!(cbor_array![].as_array(), Some(&[][..]));
+        assert_eq!(
+            cbor_array![cbor_int!(1)].as_array(),
+            Some(&[cbor_int!(1)][..])
+        );
+        assert_eq!(cbor_map! {}.as_array(), None);
+    }
+
     #[test]
     fn test_extract_map() {
         assert_eq!(cbor_int!(1).extract_map(), None);
@@ -110,6 +110,34 @@ impl SecKey {
         }
     }
 
+    /// Creates a deterministic ECDSA signature based on RFC 6979, with blinded scalars.
+    ///
+    /// The signature is the same as with `sign_rfc6979`, but the nonce and the private key only
+    /// enter the computation multiplied by fresh random factors. Repeated signatures of the same
+    /// message then don't repeat intermediate values for side-channel analysis. The provided RNG
+    /// must be cryptographically secure.
+    pub fn sign_rfc6979_blinded<H, R>(&self, msg: &[u8], rng: &mut R) -> Signature
+    where
+        H: Hash256,
+        R: RngCore,
+    {
+        let m = ExponentP256::modn(Int256::from_bin(&H::hash(msg)));
+
+        let mut rfc_6979 = Rfc6979::<H>::new(self, &msg);
+        loop {
+            let k = NonZeroExponentP256::from_int_checked(rfc_6979.next());
+            // The branching here is fine, see `sign_rfc6979`.
+            if bool::from(k.is_none()) {
+                continue;
+            }
+            let k = k.unwrap();
+
+            if let Some(sign) = self.try_sign_blinded(&k, &m, rng) {
+                return sign;
+            }
+        }
+    }
+
     /// Try signing a curve element given a randomization parameter k.
     ///
     /// If no signature can be obtained from this k, None is returned and the
@@ -136,6 +164,46 @@ impl SecKey {
         Some(Signature { r, s })
     }
 
+    /// Like `try_sign`, but with blinded scalars.
+    ///
+    /// For random a and b, the point k.G is computed as (k/a).(a.G), and s as
+    /// (k.b)^-1 * (b.m + r.(b.d)).
+    fn try_sign_blinded<R>(
+        &self,
+        k: &NonZeroExponentP256,
+        msg: &ExponentP256,
+        rng: &mut R,
+    ) -> Option<Signature>
+    where
+        R: RngCore,
+    {
+        let a = NonZeroExponentP256::gen_uniform(rng);
+        let b = NonZeroExponentP256::gen_uniform(rng);
+
+        let blinded_base = PointP256::base_point_mul(a.as_exponent());
+        let blinded_k = k * &a.inv();
+        let r = ExponentP256::modn(blinded_base.mul(blinded_k.as_exponent()).getx().to_int());
+        // The branching here is fine because all this reveals is that k generated an unsuitable r.
+        let r = r.non_zero();
+        if bool::from(r.is_none()) {
+            return None;
+        }
+        let r = r.unwrap();
+
+        let blinded_msg = msg * b.as_exponent();
+        let (s, top) = &(&r * &(&self.k * &b)).to_int() + &blinded_msg.to_int();
+        let s = (k * &b).inv().as_exponent().mul_top(&s, top);
+
+        // The branching here is fine because all this reveals is that k generated an unsuitable s.
+        let s = s.non_zero();
+        if bool::from(s.is_none()) {
+            return None;
+        }
+        let s = s.unwrap();
+
+        Some(Signature { r, s })
+    }
+
     #[cfg(test)]
     pub fn get_k_rfc6979<H>(&self, msg: &[u8]) -> NonZeroExponentP256
     where
@@ -433,6 +501,10 @@ mod test {
         let sign = sk.sign_rfc6979::<Sha256>(msg.as_bytes());
         assert_eq!(sign.r.to_int(), int256_from_hex(r));
         assert_eq!(sign.s.to_int(), int256_from_hex(s));
+        let mut rng = OsRng::default();
+        let sign = sk.sign_rfc6979_blinded::<Sha256, _>(msg.as_bytes(), &mut rng);
+        assert_eq!(sign.r.to_int(), int256_from_hex(r));
+        assert_eq!(sign.s.to_int(), int256_from_hex(s));
     }
 
     #[test]
@@ -0,0 +1,221 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! LZSS compression in the bit format of heatshrink, for small devices.
+//!
+//! The stream is a sequence of bits, most significant first. A 1 bit is followed by a literal
+//! byte. A 0 bit is followed by a back reference, the offset minus one in `WINDOW_BITS` bits and
+//! the length minus one in `LOOKAHEAD_BITS` bits. The last byte is padded with zeros. Unlike
+//! DEFLATE, neither side needs more memory than its input and output.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::cmp;
+
+/// Bits of a back reference offset, for a window of 256 bytes.
+pub const WINDOW_BITS: u8 = 8;
+
+/// Bits of a back reference length, for up to 16 bytes.
+pub const LOOKAHEAD_BITS: u8 = 4;
+
+const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+const MAX_LENGTH: usize = 1 << LOOKAHEAD_BITS;
+
+/// Shorter references take more bits than the literals they replace.
+const MIN_LENGTH: usize = 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The stream ends within a literal or back reference.
+    Truncated,
+    /// A back reference points before the start of the data.
+    InvalidReference,
+    /// The data is longer than the limit.
+    TooLarge,
+}
+
+/// Compresses the data, preferring the longest and then the closest matches.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    let mut position = 0;
+    while position < data.len() {
+        let (offset, length) = longest_match(data, position);
+        if length >= MIN_LENGTH {
+            writer.write(0, 1);
+            writer.write((offset - 1) as u16, WINDOW_BITS);
+            writer.write((length - 1) as u16, LOOKAHEAD_BITS);
+            position += length;
+        } else {
+            writer.write(1, 1);
+            writer.write(data[position] as u16, 8);
+            position += 1;
+        }
+    }
+    writer.finish()
+}
+
+/// Decompresses a stream, failing once the data exceeds the limit.
+pub fn decompress(stream: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut reader = BitReader::new(stream);
+    let mut data = Vec::new();
+    while !reader.at_padding() {
+        if reader.read(1)? == 1 {
+            data.push(reader.read(8)? as u8);
+        } else {
+            let offset = reader.read(WINDOW_BITS)? as usize + 1;
+            let length = reader.read(LOOKAHEAD_BITS)? as usize + 1;
+            let start = data
+                .len()
+                .checked_sub(offset)
+                .ok_or(Error::InvalidReference)?;
+            // References may overlap the bytes they produce.
+            for i in start..start + length {
+                data.push(data[i]);
+            }
+        }
+        if data.len() > limit {
+            return Err(Error::TooLarge);
+        }
+    }
+    Ok(data)
+}
+
+/// Returns the offset and length of the longest earlier match at the position.
+fn longest_match(data: &[u8], position: usize) -> (usize, usize) {
+    let max_length = cmp::min(MAX_LENGTH, data.len() - position);
+    let mut best = (0, 0);
+    for offset in 1..=cmp::min(WINDOW_SIZE, position) {
+        let start = position - offset;
+        let length = (0..max_length)
+            .take_while(|&i| data[start + i] == data[position + i])
+            .count();
+        if length > best.1 {
+            best = (offset, length);
+            if length == max_length {
+                break;
+            }
+        }
+    }
+    best
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    used: u8,
+}
+
+impl BitWriter {
+    /// Appends the lowest bits of the value, most significant first.
+    fn write(&mut self, value: u16, count: u8) {
+        for i in (0..count).rev() {
+            self.current = (self.current << 1) | ((value >> i) & 1) as u8;
+            self.used += 1;
+            if self.used == 8 {
+                self.bytes.push(self.current);
+                self.current = 0;
+                self.used = 0;
+            }
+        }
+    }
+
+    /// Pads the last byte with zeros, which is shorter than any literal or reference.
+    fn finish(mut self) -> Vec<u8> {
+        if self.used > 0 {
+            self.bytes.push(self.current << (8 - self.used));
+        }
+        self.bytes
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0 }
+    }
+
+    fn bit(&self, position: usize) -> u16 {
+        ((self.bytes[position / 8] >> (7 - position % 8)) & 1) as u16
+    }
+
+    /// Returns whether only zero bits of the last byte remain.
+    fn at_padding(&self) -> bool {
+        let end = self.bytes.len() * 8;
+        end - self.position < 8 && (self.position..end).all(|position| self.bit(position) == 0)
+    }
+
+    fn read(&mut self, count: u8) -> Result<u16, Error> {
+        if self.position + count as usize > self.bytes.len() * 8 {
+            return Err(Error::Truncated);
+        }
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.bit(self.position);
+            self.position += 1;
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_compress_known_stream() {
+        // A literal, then a reference of offset 1 and length 3.
+        assert_eq!(compress(b"aaaa"), vec![0xB0, 0x80, 0x08]);
+        assert_eq!(decompress(&[0xB0, 0x80, 0x08], 4), Ok(b"aaaa".to_vec()));
+        assert_eq!(compress(&[]), vec![]);
+        assert_eq!(decompress(&[], 0), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut pseudo_random = vec![];
+        let mut state = 0x1234_5678u32;
+        for _ in 0..1000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            pseudo_random.push((state >> 16) as u8);
+        }
+        let repetitive = b"attribute=value;".repeat(100);
+        for data in [&pseudo_random[..], &repetitive[..], &[0x00; 7609][..], b"a"] {
+            let stream = compress(data);
+            assert_eq!(decompress(&stream, data.len()), Ok(data.to_vec()));
+        }
+        assert!(compress(&repetitive).len() < repetitive.len() / 4);
+        // Incompressible data grows by an eighth.
+        assert!(compress(&pseudo_random).len() <= pseudo_random.len() * 9 / 8 + 1);
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        let stream = compress(b"aaaa");
+        assert_eq!(decompress(&stream, 3), Err(Error::TooLarge));
+        assert_eq!(decompress(&stream[..1], 4), Err(Error::Truncated));
+        // A literal tag is never padding.
+        assert_eq!(decompress(&[0x80], 1), Err(Error::Truncated));
+        // A reference as first element points before the data.
+        assert_eq!(decompress(&[0x00, 0x00], 16), Err(Error::InvalidReference));
+    }
+}
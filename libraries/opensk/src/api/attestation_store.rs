@@ -17,7 +17,10 @@ use crate::ctap::secret::Secret;
 use crate::env::Env;
 use alloc::vec;
 use alloc::vec::Vec;
+#[cfg(feature = "bbs")]
 use bbs::LinkSecret;
+#[cfg(feature = "bbs")]
+use core::convert::TryFrom;
 use persistent_store::{StoreError, StoreUpdate};
 
 /// Identifies an attestation.
@@ -27,12 +30,12 @@ pub enum Id {
     Enterprise,
 }
 
+#[derive(Clone)]
 #[cfg_attr(feature = "std", derive(Debug, PartialEq, Eq))]
 pub struct Attestation {
     /// ECDSA private key (big-endian).
     pub private_key: Secret<[u8; EC_FIELD_SIZE]>,
     pub certificate: Vec<u8>,
-    pub link_secret: LinkSecret,
 }
 
 /// Stores enterprise or batch attestations.
@@ -47,6 +50,18 @@ pub trait AttestationStore {
     ///
     /// This function may not be supported.
     fn set(&mut self, id: &Id, attestation: Option<&Attestation>) -> Result<(), Error>;
+
+    /// Returns the link secret of BBS credentials, if it exists.
+    ///
+    /// The link secret is provisioned independently of attestations.
+    #[cfg(feature = "bbs")]
+    fn get_link_secret(&mut self) -> Result<Option<LinkSecret>, Error>;
+
+    /// Sets the link secret of BBS credentials.
+    ///
+    /// This function may not be supported.
+    #[cfg(feature = "bbs")]
+    fn set_link_secret(&mut self, link_secret: Option<&LinkSecret>) -> Result<(), Error>;
 }
 
 /// Attestation store errors.
@@ -59,31 +74,25 @@ pub enum Error {
 }
 
 /// Keys of the environment store reserved for the attestation store.
+///
+/// The last key holds the link secret. It stays reserved without the `bbs` feature, so that the
+/// storage layout doesn't depend on it.
 pub const STORAGE_KEYS: &[usize] = &[1, 2, 3];
 
 pub fn helper_get(env: &mut impl Env) -> Result<Option<Attestation>, Error> {
     let private_key = env.store().find(PRIVATE_KEY_STORAGE_KEY)?;
     let certificate = env.store().find(CERTIFICATE_STORAGE_KEY)?;
-    let link_secret = env.store().find(LINK_SECRET_STORAGE_KEY)?;
-    let (private_key, certificate, link_secret) = match (private_key, certificate, link_secret) {
-        (Some(x), Some(y), Some(z)) => (x, y, z),
-        (None, None, None) => return Ok(None),
+    let (private_key, certificate) = match (private_key, certificate) {
+        (Some(x), Some(y)) => (Secret::from_exposed_secret(x), y),
+        (None, None) => return Ok(None),
         _ => return Err(Error::Internal),
     };
     if private_key.len() != EC_FIELD_SIZE {
         return Err(Error::Internal);
     }
-    let link_secret = if link_secret.len() == LinkSecret::SIZE {
-        let mut array = [0u8; LinkSecret::SIZE];
-        array.copy_from_slice(&link_secret);
-        LinkSecret::from_bytes(array)
-    } else {
-        return Err(Error::Internal);
-    };
     Ok(Some(Attestation {
         private_key: Secret::from_exposed_secret(*array_ref![private_key, 0, EC_FIELD_SIZE]),
         certificate,
-        link_secret,
     }))
 }
 
@@ -96,33 +105,51 @@ pub fn helper_set(env: &mut impl Env, attestation: Option<&Attestation>) -> Resu
             StoreUpdate::Remove {
                 key: CERTIFICATE_STORAGE_KEY,
             },
-            StoreUpdate::Remove {
-                key: LINK_SECRET_STORAGE_KEY,
+        ],
+        Some(attestation) => vec![
+            StoreUpdate::Insert {
+                key: PRIVATE_KEY_STORAGE_KEY,
+                value: attestation.private_key[..].to_vec(),
+            },
+            StoreUpdate::Insert {
+                key: CERTIFICATE_STORAGE_KEY,
+                value: attestation.certificate.clone(),
             },
         ],
-        Some(attestation) => {
-            let link_secret_bytes = attestation.link_secret.to_bytes().to_vec();
-            vec![
-                StoreUpdate::Insert {
-                    key: PRIVATE_KEY_STORAGE_KEY,
-                    value: attestation.private_key[..].to_vec(),
-                },
-                StoreUpdate::Insert {
-                    key: CERTIFICATE_STORAGE_KEY,
-                    value: attestation.certificate.clone(),
-                },
-                StoreUpdate::Insert {
-                    key: LINK_SECRET_STORAGE_KEY,
-                    value: link_secret_bytes,
-                },
-            ]
-        }
     };
     Ok(env.store().transaction(&updates)?)
 }
 
+#[cfg(feature = "bbs")]
+pub fn helper_get_link_secret(env: &mut impl Env) -> Result<Option<LinkSecret>, Error> {
+    let link_secret = match env.secrets_store().find(LINK_SECRET_STORAGE_KEY)? {
+        None => return Ok(None),
+        Some(link_secret) => Secret::from_exposed_secret(link_secret),
+    };
+    let mut bytes: Secret<[u8; LinkSecret::SIZE]> = Secret::default();
+    bytes.copy_from_slice(
+        <&[u8; LinkSecret::SIZE]>::try_from(&link_secret[..]).map_err(|_| Error::Internal)?,
+    );
+    Ok(Some(LinkSecret::from_bytes(*bytes)))
+}
+
+#[cfg(feature = "bbs")]
+pub fn helper_set_link_secret(
+    env: &mut impl Env,
+    link_secret: Option<&LinkSecret>,
+) -> Result<(), Error> {
+    match link_secret {
+        None => env.secrets_store().remove(LINK_SECRET_STORAGE_KEY)?,
+        Some(link_secret) => env
+            .secrets_store()
+            .insert(LINK_SECRET_STORAGE_KEY, &link_secret.to_bytes())?,
+    }
+    Ok(())
+}
+
 const PRIVATE_KEY_STORAGE_KEY: usize = STORAGE_KEYS[0];
 const CERTIFICATE_STORAGE_KEY: usize = STORAGE_KEYS[1];
+#[cfg(feature = "bbs")]
 const LINK_SECRET_STORAGE_KEY: usize = STORAGE_KEYS[2];
 
 impl From<StoreError> for Error {
@@ -136,3 +163,34 @@ impl From<StoreError> for Error {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+
+    #[test]
+    fn test_attestation_without_link_secret() {
+        let mut env = TestEnv::default();
+        let attestation = Attestation {
+            private_key: Secret::from_exposed_secret([0x41; EC_FIELD_SIZE]),
+            certificate: vec![0xdd; 20],
+        };
+        helper_set(&mut env, Some(&attestation)).unwrap();
+        assert_eq!(helper_get(&mut env), Ok(Some(attestation)));
+        #[cfg(feature = "bbs")]
+        assert_eq!(helper_get_link_secret(&mut env), Ok(None));
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_link_secret_without_attestation() {
+        let mut env = TestEnv::default();
+        let link_secret = LinkSecret::from_bytes([0x42; LinkSecret::SIZE]);
+        helper_set_link_secret(&mut env, Some(&link_secret)).unwrap();
+        assert_eq!(helper_get(&mut env), Ok(None));
+        assert_eq!(helper_get_link_secret(&mut env), Ok(Some(link_secret)));
+        helper_set_link_secret(&mut env, None).unwrap();
+        assert_eq!(helper_get_link_secret(&mut env), Ok(None));
+    }
+}
@@ -0,0 +1,29 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::crypto::HASH_SIZE;
+
+/// What the boot loader measured before handing off to the firmware.
+///
+/// The firmware can't vouch for itself, so these values must come from an earlier boot stage,
+/// e.g. a reserved RAM region written by the boot loader.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BootInfo {
+    /// Hash of the firmware that the boot loader verified.
+    pub measurement: [u8; HASH_SIZE],
+    /// Version of the booted firmware, as checked by the boot loader.
+    pub firmware_version: u64,
+    /// Lowest firmware version that the boot loader still boots. It never decreases.
+    pub rollback_counter: u64,
+}
@@ -31,6 +31,12 @@ pub trait Clock {
     /// function after the first time it returns true.
     fn is_elapsed(&mut self, timer: &Self::Timer) -> bool;
 
+    /// Returns the time since boot in milliseconds.
+    ///
+    /// The value never decreases while the device is running. Combined with the persisted boot
+    /// counter, it orders events without a real-time clock.
+    fn uptime_ms(&mut self) -> u64;
+
     /// Timestamp in microseconds.
     ///
     /// Normal operation only needs relative time, absolute timestamps are useful for debugging.
@@ -42,6 +42,14 @@ pub trait SecretKey: Sized {
     /// For hashing, SHA256 is used implicitly.
     fn sign(&self, message: &[u8]) -> Self::Signature;
 
+    /// Signs the message, with randomness available as a side-channel countermeasure.
+    ///
+    /// The signature is the same as with `sign`. By default, the randomness is ignored.
+    fn sign_with_rng(&self, rng: &mut impl Rng, message: &[u8]) -> Self::Signature {
+        let _ = rng;
+        self.sign(message)
+    }
+
     /// Writes the signing key bytes into the passed in parameter.
     fn to_slice(&self, bytes: &mut [u8; EC_FIELD_SIZE]);
 }
@@ -117,6 +117,22 @@ mod test {
         assert!(public_key.verify(&message, &signature));
     }
 
+    #[test]
+    fn test_sign_with_rng() {
+        let mut env = TestEnv::default();
+        let private_key = SoftwareEcdsaSecretKey::random(env.rng());
+        let public_key = private_key.public_key();
+        let message = [0x12, 0x34, 0x56, 0x78];
+        let signature = private_key.sign_with_rng(env.rng(), &message);
+        assert!(public_key.verify(&message, &signature));
+        // Blinding doesn't change the deterministic signature.
+        let mut signature_bytes = [0; EC_SIGNATURE_SIZE];
+        signature.to_slice(&mut signature_bytes);
+        let mut expected_bytes = [0; EC_SIGNATURE_SIZE];
+        private_key.sign(&message).to_slice(&mut expected_bytes);
+        assert_eq!(signature_bytes, expected_bytes);
+    }
+
     #[test]
     fn test_sign_verify_hash() {
         let mut env = TestEnv::default();
@@ -23,7 +23,7 @@ use crate::api::crypto::{
 use crate::api::rng::Rng;
 use alloc::vec::Vec;
 use crypto::Hash256;
-use zeroize::Zeroize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 /// Cryptography implementation using our own library of primitives.
 ///
@@ -85,7 +85,7 @@ impl ecdh::PublicKey for SoftwareEcdhPublicKey {
     }
 }
 
-#[derive(Zeroize)]
+#[derive(Zeroize, ZeroizeOnDrop)]
 pub struct SoftwareEcdhSharedSecret {
     shared_secret: [u8; EC_FIELD_SIZE],
 }
@@ -126,6 +126,17 @@ impl ecdsa::SecretKey for SoftwareEcdsaSecretKey {
 
     fn sign(&self, message: &[u8]) -> Self::Signature {
         let signature = self.sec_key.sign_rfc6979::<crypto::sha256::Sha256>(message);
+        #[cfg(feature = "hardened")]
+        check_signature(&self.sec_key, message, &signature);
+        SoftwareEcdsaSignature { signature }
+    }
+
+    #[cfg(feature = "hardened")]
+    fn sign_with_rng(&self, rng: &mut impl Rng, message: &[u8]) -> Self::Signature {
+        let signature = self
+            .sec_key
+            .sign_rfc6979_blinded::<crypto::sha256::Sha256, _>(message, rng);
+        check_signature(&self.sec_key, message, &signature);
         SoftwareEcdsaSignature { signature }
     }
 
@@ -134,6 +145,24 @@ impl ecdsa::SecretKey for SoftwareEcdsaSecretKey {
     }
 }
 
+/// Verifies a fresh signature, to detect faults injected during signing.
+///
+/// A faulty signature of a deterministic nonce may reveal the private key, so it never leaves
+/// the device.
+#[cfg(feature = "hardened")]
+fn check_signature(
+    sec_key: &crypto::ecdsa::SecKey,
+    message: &[u8],
+    signature: &crypto::ecdsa::Signature,
+) {
+    if !sec_key
+        .genpk()
+        .verify_vartime::<crypto::sha256::Sha256>(message, signature)
+    {
+        panic!("Fault detected in ECDSA signature");
+    }
+}
+
 pub struct SoftwareEcdsaPublicKey {
     pub_key: crypto::ecdsa::PubKey,
 }
@@ -17,6 +17,9 @@
 //! If you adapt them, make sure to run the tests before flashing the firmware.
 //! Our deploy script enforces the invariants.
 
+use crate::api::user_feedback::{
+    FeedbackPattern, FeedbackPatterns, FeedbackState, DEFAULT_FEEDBACK_PATTERNS,
+};
 use crate::ctap::data_formats::{CredentialProtectionPolicy, EnterpriseAttestationMode};
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -27,6 +30,26 @@ pub trait Customization {
     /// Authenticator Attestation Global Unique Identifier
     fn aaguid(&self) -> &'static [u8; AAGUID_LENGTH];
 
+    /// FIDO Authenticator Certification Level, advertised in the certifications of getInfo.
+    ///
+    /// # Invariant
+    ///
+    /// - The level is between 1 and 6, for L1, L1+, L2, L2+, L3 and L3+.
+    ///
+    /// Only set a level if this firmware was certified, as relying parties may trust it.
+    fn certification_level(&self) -> Option<u64>;
+
+    /// Description of the authenticator in its FIDO metadata statement.
+    ///
+    /// The vendor info command returns it, so that hosts find the statement of a device.
+    fn metadata_description(&self) -> &'static str;
+
+    /// Whether a customer AAGUID may be provisioned with the configure vendor command.
+    ///
+    /// The provisioned AAGUID replaces aaguid() everywhere, so that a customer can register its
+    /// own metadata statement for the devices it deploys.
+    fn allows_custom_aaguid(&self) -> bool;
+
     // ###########################################################################
     // Constants for adjusting privacy and protection levels.
     // ###########################################################################
@@ -53,6 +76,9 @@ pub trait Customization {
     /// Resident credentials are discoverable with user verification only.
     ///
     /// This can improve privacy, but can make usage less comfortable.
+    ///
+    /// Credentials requesting a lower level through the extension are raised to the default.
+    /// CTAP1 registrations have no user verification and are not affected.
     fn default_cred_protect(&self) -> Option<CredentialProtectionPolicy>;
 
     /// Sets the initial minimum PIN length in code points.
@@ -143,6 +169,8 @@ pub trait Customization {
     /// Larger values are preferred, as that allows more parameters in commands.
     /// If long commands are too unreliable on your hardware, consider decreasing
     /// this value.
+    /// It bounds responses too. Longer ones fail with CTAP2_ERR_REQUEST_TOO_LARGE,
+    /// e.g. BBS proofs of many attributes, see `max_bbs_proof_size`.
     fn max_msg_size(&self) -> usize;
 
     /// Sets the number of consecutive failed PINs before blocking interaction.
@@ -155,6 +183,31 @@ pub trait Customization {
     /// The fail retry counter is reset after entering the correct PIN.
     fn max_pin_retries(&self) -> u8;
 
+    /// Number of consecutive failed PINs after which PINs are refused until a power cycle.
+    ///
+    /// # Invariant
+    ///
+    /// - The threshold must be positive.
+    ///
+    /// CTAP uses 3. Having to replug the device slows down guessing, without using up retries.
+    fn pin_mismatches_before_power_cycle(&self) -> u8;
+
+    /// Whether the authenticator resets itself once the PIN is blocked.
+    ///
+    /// A blocked PIN normally locks the credentials until the user resets the authenticator.
+    /// Deployments that prefer to erase them right away, e.g. for lost devices, enable this.
+    fn resets_when_pin_blocked(&self) -> bool;
+
+    /// Number of consecutive failed built-in user verifications before it is blocked.
+    ///
+    /// # Invariant
+    ///
+    /// - The number must be positive.
+    ///
+    /// Built-in user verification may block earlier on its own. Once blocked, entering the PIN
+    /// resets the retries.
+    fn max_uv_retries(&self) -> u8;
+
     /// Enables or disables basic attestation for FIDO2.
     ///
     /// # Invariant
@@ -180,9 +233,28 @@ pub trait Customization {
     /// persistent storage, we might need a flash friendly implementation. This
     /// solution is a compromise to be compatible with U2F and not wasting storage.
     ///
+    /// A global counter lets relying parties correlate their users by comparing
+    /// counter values. See `use_rp_signature_counters` for an alternative.
+    ///
     /// https://www.w3.org/TR/webauthn/#signature-counter
     fn use_signature_counter(&self) -> bool;
 
+    /// Enables or disables signature counters per relying party.
+    ///
+    /// # Invariant
+    ///
+    /// - The global signature counter is disabled.
+    ///
+    /// Each relying party sees its own counter, shared by all its credentials.
+    /// A cloned credential still lets the counters of the original and the clone
+    /// diverge, so relying parties detect it, but counter values can't link
+    /// users across relying parties. Counters are stored for a bounded number of
+    /// relying parties. Others get a counter of 0, meaning that the authenticator
+    /// doesn't support counters for them. A reset forgets all counters.
+    ///
+    /// https://www.w3.org/TR/webauthn/#sctn-sign-counter
+    fn use_rp_signature_counters(&self) -> bool;
+
     // ###########################################################################
     // Constants for performance optimization or adapting to different hardware.
     //
@@ -252,11 +324,129 @@ pub trait Customization {
     /// With P=20 and K=150, we have I=2M which is enough for 500 increments per day
     /// for 10 years.
     fn max_supported_resident_keys(&self) -> usize;
+
+    /// Words of the store that are kept writable without compaction.
+    ///
+    /// Compacting a page erases flash, which stalls the write that triggers it. While no client
+    /// is talking to the device, pages are compacted ahead of time until that many words are
+    /// writable, so that the next ceremony is less likely to stall while the user waits. A resident
+    /// key takes about 130 words. Use 0 to only compact when writes need it.
+    fn store_compaction_threshold(&self) -> usize;
+
+    /// Chooses how each authenticator state is signaled to the user.
+    ///
+    /// Boards differ in their LEDs and may have a buzzer, so you might want to adapt the patterns.
+    /// Environments ignore the parts of a pattern their hardware can't show.
+    fn feedback_pattern(&self, state: FeedbackState) -> FeedbackPattern;
+
+    /// How long the authenticator winks to identify itself, in milliseconds.
+    ///
+    /// Applies to CTAPHID_WINK and the identify vendor command. Users with several plugged-in
+    /// devices look for the blinking one, so a longer duration makes it easier to find.
+    fn wink_duration_ms(&self) -> usize;
+
+    /// Wall-clock budget of a vendor command, in milliseconds.
+    ///
+    /// # Invariant
+    ///
+    /// - The budget must be positive if set.
+    ///
+    /// Commands whose estimated duration exceeds the budget fail upfront, instead of running into
+    /// the timeout of the host halfway through. Only BBS proofs are estimated for now. With None,
+    /// commands run however long they take.
+    fn max_command_duration_ms(&self) -> Option<usize>;
+
+    /// Limits the number of messages in a BBS credential the authenticator proves.
+    ///
+    /// # Invariant
+    ///
+    /// - The limit must be at least 1.
+    ///
+    /// Proof generation time and heap usage grow with the number of messages.
+    fn max_bbs_messages(&self) -> usize;
+
+    /// Limits the number of BBS credentials a wallet stores for this authenticator.
+    ///
+    /// # Invariant
+    ///
+    /// - The limit must be at least 1.
+    ///
+    /// Credentials are stored by the client, so the authenticator only advertises this value
+    /// through the BBS info vendor command.
+    fn max_bbs_credentials(&self) -> usize;
+
+    /// Limits the size of a BBS proof in bytes.
+    ///
+    /// # Invariant
+    ///
+    /// - The size must be positive and smaller than max_msg_size(), so that the proof fits into
+    ///   a response.
+    ///
+    /// Each undisclosed message adds 32 bytes to a proof.
+    fn max_bbs_proof_size(&self) -> usize;
+
+    /// Whether BBS commitments and proofs always require user verification.
+    ///
+    /// Proofs require user verification anyway if the authenticator supports it.
+    fn bbs_requires_uv(&self) -> bool;
+
+    /// Number of disclosed attributes from which a BBS proof needs a second touch.
+    ///
+    /// Presence sources only report presses, not how long they last, so a second touch stands in
+    /// for a long press. With None, one touch is always enough.
+    fn bbs_second_touch_threshold(&self) -> Option<usize>;
+
+    /// Number of disclosed attributes from which a BBS proof needs user verification.
+    ///
+    /// Proofs above this threshold fail on authenticators without built-in user verification.
+    /// With None, only bbs_requires_uv() and the availability of user verification decide.
+    fn bbs_uv_threshold(&self) -> Option<usize>;
+
+    /// Maximum number of BBS proofs a presentation token authorizes.
+    ///
+    /// After one user verification, a token lets the next proofs skip the touch and verification,
+    /// e.g. for kiosks presenting a credential repeatedly. Tokens live in RAM, so they end when
+    /// the authenticator is unplugged. With 0, tokens are disabled.
+    fn max_bbs_presentation_token_proofs(&self) -> usize;
+
+    /// Maximum lifetime of a BBS presentation token, in milliseconds.
+    ///
+    /// # Invariant
+    ///
+    /// - The lifetime must be positive if tokens are enabled.
+    fn max_bbs_presentation_token_ms(&self) -> usize;
+
+    /// Whether the link secret may be provisioned with the configure vendor command.
+    ///
+    /// If false, the authenticator ignores the provided link secret and generates its own, so that
+    /// it never exists outside the device.
+    fn allows_external_link_secret(&self) -> bool;
+
+    /// Whether setPIN and changePIN reject trivial PINs.
+    ///
+    /// Trivial PINs repeat one character, like "0000", or count up or down, like "1234" or
+    /// "fedc". Attackers try them first.
+    fn rejects_trivial_pins(&self) -> bool;
+
+    /// PINs that setPIN and changePIN reject, e.g. from an enterprise password policy.
+    ///
+    /// Comparisons ignore ASCII case.
+    fn pin_blocklist(&self) -> Vec<String>;
+
+    /// Whether PINs must contain both letters and digits.
+    ///
+    /// Only the hash of the current PIN is stored, so it can't be checked against a requirement
+    /// that a firmware update enabled. Instead, the authenticator sets forcePINChange when it
+    /// boots, like it does for PINs shorter than the minimum PIN length.
+    fn requires_alphanumeric_pin(&self) -> bool;
 }
 
 #[derive(Clone)]
 pub struct CustomizationImpl {
     pub aaguid: &'static [u8; AAGUID_LENGTH],
+    pub certification_level: Option<u64>,
+    pub metadata_description: &'static str,
+    pub allows_custom_aaguid: bool,
     pub allows_pin_protocol_v1: bool,
     pub default_cred_protect: Option<CredentialProtectionPolicy>,
     pub default_min_pin_length: u8,
@@ -266,17 +456,40 @@ pub struct CustomizationImpl {
     pub enterprise_rp_id_list: &'static [&'static str],
     pub max_msg_size: usize,
     pub max_pin_retries: u8,
+    pub pin_mismatches_before_power_cycle: u8,
+    pub resets_when_pin_blocked: bool,
+    pub max_uv_retries: u8,
     pub use_batch_attestation: bool,
     pub use_signature_counter: bool,
+    pub use_rp_signature_counters: bool,
     pub max_cred_blob_length: usize,
     pub max_credential_count_in_list: Option<usize>,
     pub max_large_blob_array_size: usize,
     pub max_rp_ids_length: usize,
     pub max_supported_resident_keys: usize,
+    pub store_compaction_threshold: usize,
+    pub feedback_patterns: FeedbackPatterns,
+    pub wink_duration_ms: usize,
+    pub max_command_duration_ms: Option<usize>,
+    pub max_bbs_messages: usize,
+    pub max_bbs_credentials: usize,
+    pub max_bbs_proof_size: usize,
+    pub bbs_requires_uv: bool,
+    pub bbs_second_touch_threshold: Option<usize>,
+    pub bbs_uv_threshold: Option<usize>,
+    pub max_bbs_presentation_token_proofs: usize,
+    pub max_bbs_presentation_token_ms: usize,
+    pub allows_external_link_secret: bool,
+    pub rejects_trivial_pins: bool,
+    pub pin_blocklist: &'static [&'static str],
+    pub requires_alphanumeric_pin: bool,
 }
 
 pub const DEFAULT_CUSTOMIZATION: CustomizationImpl = CustomizationImpl {
     aaguid: &[0; AAGUID_LENGTH],
+    certification_level: None,
+    metadata_description: "OpenSK",
+    allows_custom_aaguid: true,
     allows_pin_protocol_v1: true,
     default_cred_protect: None,
     default_min_pin_length: 4,
@@ -286,13 +499,33 @@ pub const DEFAULT_CUSTOMIZATION: CustomizationImpl = CustomizationImpl {
     enterprise_rp_id_list: &[],
     max_msg_size: 7609,
     max_pin_retries: 8,
+    pin_mismatches_before_power_cycle: 3,
+    resets_when_pin_blocked: false,
+    max_uv_retries: 8,
     use_batch_attestation: false,
     use_signature_counter: true,
+    use_rp_signature_counters: false,
     max_cred_blob_length: 32,
     max_credential_count_in_list: None,
     max_large_blob_array_size: 2048,
     max_rp_ids_length: 8,
     max_supported_resident_keys: 150,
+    store_compaction_threshold: 256,
+    feedback_patterns: DEFAULT_FEEDBACK_PATTERNS,
+    wink_duration_ms: 5000,
+    max_command_duration_ms: None,
+    max_bbs_messages: 32,
+    max_bbs_credentials: 16,
+    max_bbs_proof_size: 2048,
+    bbs_requires_uv: false,
+    bbs_second_touch_threshold: None,
+    bbs_uv_threshold: None,
+    max_bbs_presentation_token_proofs: 0,
+    max_bbs_presentation_token_ms: 300_000,
+    allows_external_link_secret: true,
+    rejects_trivial_pins: false,
+    pin_blocklist: &[],
+    requires_alphanumeric_pin: false,
 };
 
 impl Customization for CustomizationImpl {
@@ -300,6 +533,18 @@ impl Customization for CustomizationImpl {
         self.aaguid
     }
 
+    fn certification_level(&self) -> Option<u64> {
+        self.certification_level
+    }
+
+    fn metadata_description(&self) -> &'static str {
+        self.metadata_description
+    }
+
+    fn allows_custom_aaguid(&self) -> bool {
+        self.allows_custom_aaguid
+    }
+
     fn allows_pin_protocol_v1(&self) -> bool {
         self.allows_pin_protocol_v1
     }
@@ -347,6 +592,18 @@ impl Customization for CustomizationImpl {
         self.max_pin_retries
     }
 
+    fn pin_mismatches_before_power_cycle(&self) -> u8 {
+        self.pin_mismatches_before_power_cycle
+    }
+
+    fn resets_when_pin_blocked(&self) -> bool {
+        self.resets_when_pin_blocked
+    }
+
+    fn max_uv_retries(&self) -> u8 {
+        self.max_uv_retries
+    }
+
     fn use_batch_attestation(&self) -> bool {
         self.use_batch_attestation
     }
@@ -355,6 +612,10 @@ impl Customization for CustomizationImpl {
         self.use_signature_counter
     }
 
+    fn use_rp_signature_counters(&self) -> bool {
+        self.use_rp_signature_counters
+    }
+
     fn max_cred_blob_length(&self) -> usize {
         self.max_cred_blob_length
     }
@@ -374,6 +635,73 @@ impl Customization for CustomizationImpl {
     fn max_supported_resident_keys(&self) -> usize {
         self.max_supported_resident_keys
     }
+
+    fn store_compaction_threshold(&self) -> usize {
+        self.store_compaction_threshold
+    }
+
+    fn feedback_pattern(&self, state: FeedbackState) -> FeedbackPattern {
+        self.feedback_patterns.get(state)
+    }
+
+    fn wink_duration_ms(&self) -> usize {
+        self.wink_duration_ms
+    }
+
+    fn max_command_duration_ms(&self) -> Option<usize> {
+        self.max_command_duration_ms
+    }
+
+    fn max_bbs_messages(&self) -> usize {
+        self.max_bbs_messages
+    }
+
+    fn max_bbs_credentials(&self) -> usize {
+        self.max_bbs_credentials
+    }
+
+    fn max_bbs_proof_size(&self) -> usize {
+        self.max_bbs_proof_size
+    }
+
+    fn bbs_requires_uv(&self) -> bool {
+        self.bbs_requires_uv
+    }
+
+    fn bbs_second_touch_threshold(&self) -> Option<usize> {
+        self.bbs_second_touch_threshold
+    }
+
+    fn bbs_uv_threshold(&self) -> Option<usize> {
+        self.bbs_uv_threshold
+    }
+
+    fn max_bbs_presentation_token_proofs(&self) -> usize {
+        self.max_bbs_presentation_token_proofs
+    }
+
+    fn max_bbs_presentation_token_ms(&self) -> usize {
+        self.max_bbs_presentation_token_ms
+    }
+
+    fn allows_external_link_secret(&self) -> bool {
+        self.allows_external_link_secret
+    }
+
+    fn rejects_trivial_pins(&self) -> bool {
+        self.rejects_trivial_pins
+    }
+
+    fn pin_blocklist(&self) -> Vec<String> {
+        self.pin_blocklist
+            .iter()
+            .map(|s| String::from(*s))
+            .collect()
+    }
+
+    fn requires_alphanumeric_pin(&self) -> bool {
+        self.requires_alphanumeric_pin
+    }
 }
 
 #[cfg(feature = "std")]
@@ -382,6 +710,13 @@ pub fn is_valid(customization: &impl Customization) -> bool {
     // - storage.rs: if max_large_blob_array_size() fits the shards
     // - storage/key.rs: if max_supported_resident_keys() fits CREDENTIALS
 
+    // Certification levels go from L1 to L3+.
+    if let Some(level) = customization.certification_level() {
+        if !(1..=6).contains(&level) {
+            return false;
+        }
+    }
+
     // Max message size must be between 1024 and 7609.
     if customization.max_msg_size() < 1024 || customization.max_msg_size() > 7609 {
         return false;
@@ -417,11 +752,22 @@ pub fn is_valid(customization: &impl Customization) -> bool {
         return false;
     }
 
+    // Counters per relying party replace the global counter, they can't be used together.
+    if customization.use_signature_counter() && customization.use_rp_signature_counters() {
+        return false;
+    }
+
     // Max pin retries must be less or equal than 8.
     if customization.max_pin_retries() > 8 {
         return false;
     }
 
+    // PIN and UV retry limits must allow at least one attempt.
+    if customization.pin_mismatches_before_power_cycle() == 0 || customization.max_uv_retries() == 0
+    {
+        return false;
+    }
+
     // Max cred blob length should be at least 32, and at most 64.
     if customization.max_cred_blob_length() < 32 || customization.max_cred_blob_length() > 64 {
         return false;
@@ -446,6 +792,35 @@ pub fn is_valid(customization: &impl Customization) -> bool {
         return false;
     }
 
+    // Wink duration must be positive, or the device can't be identified.
+    if customization.wink_duration_ms() == 0 {
+        return false;
+    }
+
+    // A command budget must leave time to run commands.
+    if customization.max_command_duration_ms() == Some(0) {
+        return false;
+    }
+
+    // BBS message and credential limits must be positive.
+    if customization.max_bbs_messages() < 1 || customization.max_bbs_credentials() < 1 {
+        return false;
+    }
+
+    // Max BBS proof size must be positive and fit into a message.
+    if customization.max_bbs_proof_size() == 0
+        || customization.max_bbs_proof_size() >= customization.max_msg_size()
+    {
+        return false;
+    }
+
+    // Enabled presentation tokens must last some time.
+    if customization.max_bbs_presentation_token_proofs() > 0
+        && customization.max_bbs_presentation_token_ms() == 0
+    {
+        return false;
+    }
+
     true
 }
 
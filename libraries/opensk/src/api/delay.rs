@@ -0,0 +1,26 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Longest random delay after a failed authentication, in milliseconds.
+pub const FAILURE_DELAY_MS: u32 = 100;
+
+/// Waits for random durations.
+///
+/// Responses to failed PIN attempts and failed vendor authentications are delayed by a random
+/// duration, so that their timing tells less about where the check failed. The duration must come
+/// from a true random source, or its pattern can be averaged out.
+pub trait Delay {
+    /// Blocks for a random duration of at most `max_ms` milliseconds.
+    fn random_delay(&mut self, max_ms: u32);
+}
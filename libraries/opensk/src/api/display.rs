@@ -0,0 +1,57 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayError {
+    /// The screen could not be updated.
+    Fail,
+}
+
+/// An operation the user approves with their presence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Transaction {
+    /// Creating a credential.
+    Registration {
+        rp_id: String,
+        rp_name: Option<String>,
+        user_name: Option<String>,
+    },
+    /// Signing in with an existing credential.
+    Authentication {
+        rp_id: String,
+        user_name: Option<String>,
+    },
+    /// Generating a BBS proof that reveals the listed attributes.
+    Disclosure { attributes: Vec<Vec<u8>> },
+}
+
+/// A screen, like an e-paper or OLED, showing what the user is about to approve.
+///
+/// The transaction is shown before user presence is accepted, so that users don't have to trust
+/// the client about what they confirm.
+pub trait Display {
+    /// Returns whether a screen is attached.
+    ///
+    /// If not, the other functions are never called.
+    fn is_supported(&self) -> bool;
+
+    /// Shows the transaction until [`Self::clear`] is called.
+    fn show(&mut self, transaction: &Transaction) -> Result<(), DisplayError>;
+
+    /// Removes the transaction from the screen.
+    fn clear(&mut self);
+}
@@ -28,6 +28,7 @@ use persistent_store::StoreError;
 use rand_core::RngCore;
 use sk_cbor as cbor;
 use sk_cbor::{cbor_map_options, destructure_cbor_map};
+use zeroize::ZeroizeOnDrop;
 
 // CBOR credential IDs consist of
 // - 1   byte : version number
@@ -145,7 +146,7 @@ impl<T: Helper> KeyStore for T {
     /// Other information, such as a user name, are not stored. Since encrypted credential IDs are
     /// stored server-side, this information is already available (unencrypted).
     fn wrap_credential(&mut self, credential: CredentialSource) -> Result<Vec<u8>, Error> {
-        let mut payload = Vec::new();
+        let mut payload = Secret::from_exposed_secret(Vec::new());
         let wrap_key = self.wrap_key::<T>()?;
         let private_key_cbor = credential
             .private_key
@@ -241,11 +242,13 @@ impl<T: Helper> KeyStore for T {
 
     fn reset(&mut self) -> Result<(), Error> {
         // The storage also removes `STORAGE_KEY`, but this makes KeyStore more self-sufficient.
-        Ok(self.store().remove(STORAGE_KEY)?)
+        Ok(self.secrets_store().remove(STORAGE_KEY)?)
     }
 }
 
 /// Wrapper for master keys.
+///
+/// All fields are secrets, so the keys are zeroized on drop.
 struct MasterKeys {
     /// Master encryption key.
     encryption: Secret<[u8; 32]>,
@@ -257,14 +260,16 @@ struct MasterKeys {
     cred_random: [Secret<[u8; 32]>; 2],
 }
 
+impl ZeroizeOnDrop for MasterKeys {}
+
 fn get_master_keys(env: &mut impl Env) -> Result<MasterKeys, Error> {
-    let master_keys = match env.store().find(STORAGE_KEY)? {
-        Some(x) if x.len() == 128 => x,
+    let master_keys = match env.secrets_store().find(STORAGE_KEY)? {
+        Some(x) if x.len() == 128 => Secret::from_exposed_secret(x),
         Some(_) => return Err(Error),
         None => {
-            let mut master_keys = vec![0; 128];
+            let mut master_keys = Secret::from_exposed_secret(vec![0; 128]);
             env.rng().fill_bytes(&mut master_keys);
-            env.store().insert(STORAGE_KEY, &master_keys)?;
+            env.secrets_store().insert(STORAGE_KEY, &master_keys)?;
             master_keys
         }
     };
@@ -18,12 +18,19 @@
 //! by a trait. This module gathers the API of those components.
 
 pub mod attestation_store;
+pub mod boot_info;
 pub mod clock;
 pub mod connection;
 pub mod crypto;
 pub mod customization;
+pub mod delay;
+pub mod display;
 pub mod firmware_protection;
 pub mod key_store;
 pub mod private_key;
 pub mod rng;
+pub mod upgrade_storage;
+pub mod user_feedback;
 pub mod user_presence;
+pub mod user_verification;
+pub mod watchdog;
@@ -109,9 +109,15 @@ impl PrivateKey {
     }
 
     /// Returns the encoded signature for a given message.
-    pub fn sign_and_encode<E: Env>(&self, message: &[u8]) -> Result<Vec<u8>, Ctap2StatusCode> {
+    pub fn sign_and_encode<E: Env>(
+        &self,
+        env: &mut E,
+        message: &[u8],
+    ) -> Result<Vec<u8>, Ctap2StatusCode> {
         Ok(match self {
-            PrivateKey::Ecdsa(bytes) => ecdsa_key_from_bytes::<E>(bytes)?.sign(message).to_der(),
+            PrivateKey::Ecdsa(bytes) => ecdsa_key_from_bytes::<E>(bytes)?
+                .sign_with_rng(env.rng(), message)
+                .to_der(),
             #[cfg(feature = "ed25519")]
             PrivateKey::Ed25519(ed25519_key) => ed25519_key.sign(message, None).to_vec(),
         })
@@ -241,7 +247,7 @@ mod test {
         let ecdsa_key = private_key.ecdsa_key::<TestEnv>().unwrap();
         let signature = ecdsa_key.sign(&message).to_der();
         assert_eq!(
-            private_key.sign_and_encode::<TestEnv>(&message),
+            private_key.sign_and_encode(&mut env, &message),
             Ok(signature)
         );
     }
@@ -0,0 +1,129 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::crypto::HASH_SIZE;
+use crate::vendor::{BundleVerification, PartitionStatus};
+use alloc::vec::Vec;
+use persistent_store::{StorageError, StorageResult};
+
+/// Storage for firmware bundles, written by the upgrade vendor commands.
+///
+/// Devices have two partitions: the running image, and the other one that upgrades write. The
+/// bootloader boots the other partition once it holds a valid image. Long operations stop with an
+/// error once `keep_going` returns false, so that clients can cancel them.
+pub trait UpgradeStorage {
+    /// Identifies the partition that upgrades write, so that hosts pick the matching bundle.
+    fn bundle_identifier(&self) -> u32;
+
+    /// Returns the boot status of the running partition.
+    fn running_status(&self) -> PartitionStatus;
+
+    /// Returns the boot status of the partition that upgrades write.
+    fn bundle_status(&self) -> PartitionStatus;
+
+    /// Writes a bundle chunk at `offset`.
+    ///
+    /// Bundles older than `min_version` are refused. Returns the version of the bundle once its
+    /// last chunk is written and the whole bundle is verified.
+    fn write_bundle(
+        &mut self,
+        offset: usize,
+        data: Vec<u8>,
+        min_version: u64,
+        keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<Option<u64>>;
+
+    /// Returns the firmware hash in the metadata of the written bundle.
+    ///
+    /// Encrypted chunks derive their IV from it, so the metadata is written first.
+    fn bundle_hash(&mut self) -> StorageResult<[u8; HASH_SIZE]>;
+
+    /// Verifies the written bundle like its last chunk, without changing the partition.
+    fn verify_bundle(
+        &mut self,
+        min_version: u64,
+        keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<BundleVerification>;
+
+    /// Marks the running image good, so that the bootloader stops counting its boot attempts.
+    fn mark_running_good(&mut self) -> StorageResult<()>;
+
+    /// Asks the bootloader to boot the other partition from the next boot on.
+    ///
+    /// The other image must be complete, healthy and at least `min_version`.
+    fn request_rollback(
+        &mut self,
+        min_version: u64,
+        keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<()>;
+}
+
+/// Upgrade storage for devices that can't be upgraded through vendor commands.
+#[derive(Debug, Default)]
+pub struct NoUpgradeStorage;
+
+impl UpgradeStorage for NoUpgradeStorage {
+    fn bundle_identifier(&self) -> u32 {
+        0
+    }
+
+    fn running_status(&self) -> PartitionStatus {
+        ERASED_STATUS
+    }
+
+    fn bundle_status(&self) -> PartitionStatus {
+        ERASED_STATUS
+    }
+
+    fn write_bundle(
+        &mut self,
+        _offset: usize,
+        _data: Vec<u8>,
+        _min_version: u64,
+        _keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<Option<u64>> {
+        Err(StorageError::CustomError)
+    }
+
+    fn bundle_hash(&mut self) -> StorageResult<[u8; HASH_SIZE]> {
+        Err(StorageError::CustomError)
+    }
+
+    fn verify_bundle(
+        &mut self,
+        _min_version: u64,
+        _keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<BundleVerification> {
+        Ok(BundleVerification::Missing)
+    }
+
+    fn mark_running_good(&mut self) -> StorageResult<()> {
+        Err(StorageError::CustomError)
+    }
+
+    fn request_rollback(
+        &mut self,
+        _min_version: u64,
+        _keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<()> {
+        Err(StorageError::CustomError)
+    }
+}
+
+const ERASED_STATUS: PartitionStatus = PartitionStatus {
+    version: None,
+    boot_attempts: 0,
+    confirmed: false,
+    rollback_requested: false,
+};
@@ -0,0 +1,173 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::customization::Customization;
+use crate::env::Env;
+
+/// States of the authenticator that are signaled to the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedbackState {
+    /// Nothing to report.
+    Idle,
+    /// The client requested the device to identify itself.
+    Wink,
+    /// A command is blocked until the user confirms presence.
+    AwaitingTouch,
+    /// A long computation, like a BBS proof, is running.
+    Processing,
+    /// A firmware upgrade is being written.
+    Upgrading,
+    /// The last command failed in a way the user should notice.
+    Error,
+}
+
+/// How LEDs animate over consecutive ticks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlinkPattern {
+    /// All LEDs are switched off.
+    Off,
+    /// All LEDs are switched on.
+    Solid,
+    /// All LEDs are toggled on and off together.
+    Flash,
+    /// Alternating LEDs, the historical user presence pattern.
+    Alternate,
+    /// A "snake" circling through the LEDs, the historical wink pattern.
+    Snake,
+}
+
+/// Color hint for boards with multi-color LEDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    White,
+    Red,
+    Green,
+    Blue,
+}
+
+/// Describes how a single state is signaled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeedbackPattern {
+    pub blink: BlinkPattern,
+    pub color: Color,
+    /// Whether a buzzer, if present, should sound.
+    pub buzz: bool,
+}
+
+impl FeedbackPattern {
+    pub const OFF: FeedbackPattern = FeedbackPattern {
+        blink: BlinkPattern::Off,
+        color: Color::White,
+        buzz: false,
+    };
+}
+
+/// Associates a pattern to every feedback state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeedbackPatterns {
+    pub idle: FeedbackPattern,
+    pub wink: FeedbackPattern,
+    pub awaiting_touch: FeedbackPattern,
+    pub processing: FeedbackPattern,
+    pub upgrading: FeedbackPattern,
+    pub error: FeedbackPattern,
+}
+
+impl FeedbackPatterns {
+    pub fn get(&self, state: FeedbackState) -> FeedbackPattern {
+        match state {
+            FeedbackState::Idle => self.idle,
+            FeedbackState::Wink => self.wink,
+            FeedbackState::AwaitingTouch => self.awaiting_touch,
+            FeedbackState::Processing => self.processing,
+            FeedbackState::Upgrading => self.upgrading,
+            FeedbackState::Error => self.error,
+        }
+    }
+}
+
+pub const DEFAULT_FEEDBACK_PATTERNS: FeedbackPatterns = FeedbackPatterns {
+    idle: FeedbackPattern::OFF,
+    wink: FeedbackPattern {
+        blink: BlinkPattern::Snake,
+        color: Color::White,
+        buzz: false,
+    },
+    awaiting_touch: FeedbackPattern {
+        blink: BlinkPattern::Alternate,
+        color: Color::White,
+        buzz: false,
+    },
+    processing: FeedbackPattern {
+        blink: BlinkPattern::Solid,
+        color: Color::Green,
+        buzz: false,
+    },
+    upgrading: FeedbackPattern {
+        blink: BlinkPattern::Solid,
+        color: Color::Blue,
+        buzz: false,
+    },
+    error: FeedbackPattern {
+        blink: BlinkPattern::Flash,
+        color: Color::Red,
+        buzz: true,
+    },
+};
+
+/// Signals the authenticator state to the user, e.g. with LEDs or a buzzer.
+pub trait UserFeedback {
+    /// Shows the pattern for the given state.
+    ///
+    /// The tick is incremented by the caller each time the same state is shown again, so that
+    /// implementations can animate patterns. Features the hardware lacks, like colors on single
+    /// color LEDs, may be ignored.
+    fn show(&mut self, state: FeedbackState, pattern: FeedbackPattern, tick: usize);
+}
+
+/// Shows the customized pattern for the given state.
+pub fn signal(env: &mut impl Env, state: FeedbackState, tick: usize) {
+    let pattern = env.customization().feedback_pattern(state);
+    env.user_feedback().show(state, pattern, tick);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+
+    #[test]
+    fn test_patterns_get() {
+        let patterns = DEFAULT_FEEDBACK_PATTERNS;
+        assert_eq!(patterns.get(FeedbackState::Idle), FeedbackPattern::OFF);
+        assert_eq!(
+            patterns.get(FeedbackState::Error).blink,
+            BlinkPattern::Flash
+        );
+    }
+
+    #[test]
+    fn test_signal() {
+        let mut env = TestEnv::default();
+        signal(&mut env, FeedbackState::Processing, 3);
+        assert_eq!(
+            env.user_feedback().last(),
+            Some((
+                FeedbackState::Processing,
+                DEFAULT_FEEDBACK_PATTERNS.processing,
+                3
+            ))
+        );
+    }
+}
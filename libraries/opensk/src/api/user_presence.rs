@@ -12,6 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Default timeout for a user presence check, in milliseconds.
+pub const DEFAULT_TIMEOUT_MS: usize = 30000;
+
+/// Groups commands that share a user presence timeout policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandClass {
+    /// Registration and authentication, i.e. MakeCredential and GetAssertion.
+    Credential,
+    /// Erasing all credentials with the Reset command.
+    Reset,
+    /// Picking an authenticator with the Selection command.
+    Selection,
+    /// Vendor commands, like configuration or proof generation.
+    Vendor,
+}
+
 #[derive(Debug)]
 pub enum UserPresenceError {
     /// User explicitly declined user presence check.
@@ -41,4 +60,131 @@ pub trait UserPresence {
     ///
     /// Must be called after [`Self::check_init`].
     fn check_complete(&mut self);
+
+    /// Returns how long a check for the given command class may block, in milliseconds.
+    ///
+    /// Boards override this to shorten or extend the wait for some commands.
+    fn timeout_ms(&self, _class: CommandClass) -> usize {
+        DEFAULT_TIMEOUT_MS
+    }
+}
+
+/// A single hardware source of user presence, like a GPIO button or a capacitive touch sensor.
+///
+/// Sources are polled, so that [`UserPresence`] implementations can combine several of them
+/// through [`UserPresenceSources`].
+pub trait UserPresenceSource {
+    /// Starts listening for user presence, e.g. by enabling interrupts.
+    fn enable(&mut self) {}
+
+    /// Returns whether the user confirmed presence since the source was enabled.
+    fn poll(&mut self) -> Result<bool, UserPresenceError>;
+
+    /// Stops listening for user presence.
+    fn disable(&mut self) {}
+}
+
+/// Combines sources, any of which confirms user presence.
+#[derive(Default)]
+pub struct UserPresenceSources {
+    sources: Vec<Box<dyn UserPresenceSource>>,
+}
+
+impl UserPresenceSources {
+    pub fn push(&mut self, source: Box<dyn UserPresenceSource>) {
+        self.sources.push(source);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn enable(&mut self) {
+        for source in &mut self.sources {
+            source.enable();
+        }
+    }
+
+    /// Polls all sources, and returns whether any of them confirmed presence.
+    ///
+    /// All sources are polled, even after one confirmed, so that pending events are consumed.
+    pub fn poll(&mut self) -> Result<bool, UserPresenceError> {
+        let mut present = false;
+        for source in &mut self.sources {
+            present |= source.poll()?;
+        }
+        Ok(present)
+    }
+
+    pub fn disable(&mut self) {
+        for source in &mut self.sources {
+            source.disable();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    struct FakeSource {
+        present: Rc<Cell<bool>>,
+        enabled: Rc<Cell<bool>>,
+    }
+
+    impl UserPresenceSource for FakeSource {
+        fn enable(&mut self) {
+            self.enabled.set(true);
+        }
+
+        fn poll(&mut self) -> Result<bool, UserPresenceError> {
+            Ok(self.present.get())
+        }
+
+        fn disable(&mut self) {
+            self.enabled.set(false);
+        }
+    }
+
+    #[test]
+    fn test_sources_any() {
+        let button = Rc::new(Cell::new(false));
+        let touch = Rc::new(Cell::new(false));
+        let enabled = Rc::new(Cell::new(false));
+        let mut sources = UserPresenceSources::default();
+        assert!(sources.is_empty());
+        sources.push(Box::new(FakeSource {
+            present: button.clone(),
+            enabled: enabled.clone(),
+        }));
+        sources.push(Box::new(FakeSource {
+            present: touch.clone(),
+            enabled: enabled.clone(),
+        }));
+        sources.enable();
+        assert!(enabled.get());
+        assert!(!sources.poll().unwrap());
+        touch.set(true);
+        assert!(sources.poll().unwrap());
+        touch.set(false);
+        button.set(true);
+        assert!(sources.poll().unwrap());
+        sources.disable();
+        assert!(!enabled.get());
+    }
+
+    #[test]
+    fn test_sources_error() {
+        struct FailingSource;
+        impl UserPresenceSource for FailingSource {
+            fn poll(&mut self) -> Result<bool, UserPresenceError> {
+                Err(UserPresenceError::Fail)
+            }
+        }
+        let mut sources = UserPresenceSources::default();
+        sources.push(Box::new(FailingSource));
+        assert!(matches!(sources.poll(), Err(UserPresenceError::Fail)));
+    }
 }
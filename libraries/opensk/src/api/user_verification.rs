@@ -0,0 +1,80 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserVerificationError {
+    /// The user failed verification, e.g. entered a wrong code or presented a wrong finger.
+    Invalid,
+    /// Too many failed attempts, built-in verification is disabled.
+    Blocked,
+    /// User verification was canceled by User Agent.
+    Canceled,
+    /// User verification timed out.
+    Timeout,
+    /// Unexpected (e.g., hardware) failures
+    Fail,
+}
+
+pub type UserVerificationResult = Result<(), UserVerificationError>;
+
+/// Built-in user verification, e.g. with a PIN pad or a biometric sensor signaling over GPIO.
+///
+/// The interface mirrors [`UserPresence`](super::user_presence::UserPresence), so that
+/// keepalives are sent while waiting for the user.
+pub trait UserVerification {
+    /// Returns whether the device has a built-in user verification method.
+    ///
+    /// If not, the other functions are never called.
+    fn is_supported(&self) -> bool;
+
+    /// Returns the number of remaining attempts before verification is blocked.
+    fn retries(&mut self) -> usize;
+
+    /// Initializes for a user verification.
+    ///
+    /// Must eventually be followed by a call to [`Self::check_complete`].
+    fn check_init(&mut self);
+
+    /// Waits until the user is verified, fails verification, or the given timeout expires.
+    ///
+    /// Implementations count failed attempts towards [`Self::retries`].
+    fn wait_with_timeout(&mut self, timeout_ms: usize) -> UserVerificationResult;
+
+    /// Finalizes a user verification.
+    ///
+    /// Must be called after [`Self::check_init`].
+    fn check_complete(&mut self);
+}
+
+/// User verification for devices without a built-in method.
+#[derive(Debug, Default)]
+pub struct NoUserVerification;
+
+impl UserVerification for NoUserVerification {
+    fn is_supported(&self) -> bool {
+        false
+    }
+
+    fn retries(&mut self) -> usize {
+        0
+    }
+
+    fn check_init(&mut self) {}
+
+    fn wait_with_timeout(&mut self, _timeout_ms: usize) -> UserVerificationResult {
+        Err(UserVerificationError::Fail)
+    }
+
+    fn check_complete(&mut self) {}
+}
@@ -0,0 +1,87 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::customization::Customization;
+
+/// Covers everything between two feeds, except the generation of BBS proofs.
+const BASE_TIMEOUT_MS: usize = 30000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchdogError {
+    /// The hardware has no watchdog, or it is not exposed.
+    Unsupported,
+    /// The watchdog could not be configured.
+    Fail,
+}
+
+/// Resets the device if it hangs.
+///
+/// Once started, the watchdog must be fed regularly. The CTAP implementation feeds it while
+/// processing packets and waiting for user presence, and long computations feed it whenever they
+/// check their `CancellationToken`. Start it with [`timeout_ms`], so that the steps of the longest
+/// computation finish within the timeout.
+pub trait Watchdog {
+    /// Arms the watchdog.
+    ///
+    /// After this call, the device reboots unless [`Self::feed`] is called within the timeout.
+    /// Some hardware can't be stopped or reconfigured once started.
+    fn start(&mut self, timeout_ms: usize) -> Result<(), WatchdogError>;
+
+    /// Postpones the reboot by another full timeout.
+    ///
+    /// Does nothing if the watchdog is not started.
+    fn feed(&mut self);
+
+    /// Reboots the device, e.g. to recover from persistent transport failures.
+    ///
+    /// Implementations without a dedicated mechanism may stop feeding the watchdog and wait.
+    fn reboot(&mut self) -> !;
+}
+
+/// Returns a timeout that the longest command stays within, between two feeds.
+///
+/// Nothing feeds the watchdog while zkryptium computes a BBS proof, which takes longer the more
+/// messages the customization allows.
+pub fn timeout_ms(customization: &impl Customization) -> usize {
+    #[cfg(feature = "bbs")]
+    let longest_ms =
+        crate::vendor::parameters::max_bbs_proof_duration_ms(customization.max_bbs_messages());
+    #[cfg(not(feature = "bbs"))]
+    let longest_ms = {
+        let _ = customization;
+        0
+    };
+    // The estimate is for the nRF52840, leave a margin for slower boards.
+    BASE_TIMEOUT_MS.max(2 * longest_ms)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::customization::DEFAULT_CUSTOMIZATION;
+
+    #[test]
+    fn test_timeout_ms() {
+        assert_eq!(timeout_ms(&DEFAULT_CUSTOMIZATION), BASE_TIMEOUT_MS);
+        #[cfg(feature = "bbs")]
+        {
+            let customization = crate::api::customization::CustomizationImpl {
+                max_bbs_messages: 256,
+                ..DEFAULT_CUSTOMIZATION
+            };
+            let longest_ms = crate::vendor::parameters::max_bbs_proof_duration_ms(256);
+            assert!(timeout_ms(&customization) > longest_ms);
+        }
+    }
+}
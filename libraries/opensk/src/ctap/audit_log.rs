@@ -0,0 +1,200 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Persistent log of security relevant events.
+//!
+//! Devices usually have no real-time clock. Events are therefore timestamped with the number of
+//! boots and the uptime, which is enough for tooling to order events and estimate ages.
+
+use super::data_formats::{extract_array, extract_unsigned};
+use super::status_code::Ctap2StatusCode;
+use super::storage;
+use crate::api::clock::Clock;
+use crate::env::Env;
+use alloc::vec;
+use alloc::vec::Vec;
+use arrayref::array_ref;
+use core::convert::TryFrom;
+use sk_cbor as cbor;
+use sk_cbor::cbor_array;
+
+/// Point in time relative to the boot counter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    /// Number of boots, including the current one.
+    pub boot_count: u32,
+    /// Time since boot in milliseconds.
+    pub uptime_ms: u64,
+}
+
+impl Timestamp {
+    /// Returns the current time.
+    pub fn now(env: &mut impl Env) -> Result<Self, Ctap2StatusCode> {
+        Ok(Timestamp {
+            boot_count: storage::boot_counter(env)?,
+            uptime_ms: env.clock().uptime_ms(),
+        })
+    }
+}
+
+impl From<Timestamp> for cbor::Value {
+    fn from(timestamp: Timestamp) -> Self {
+        cbor_array![timestamp.boot_count as u64, timestamp.uptime_ms]
+    }
+}
+
+impl TryFrom<cbor::Value> for Timestamp {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        let array = extract_array(cbor_value)?;
+        if array.len() != 2 {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR);
+        }
+        let mut values = array.into_iter().map(extract_unsigned);
+        let boot_count = u32::try_from(values.next().unwrap()?)
+            .map_err(|_| Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)?;
+        let uptime_ms = values.next().unwrap()?;
+        Ok(Timestamp {
+            boot_count,
+            uptime_ms,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditEvent {
+    /// The authenticator was reset.
+    Reset = 0x01,
+    /// A discoverable credential was created.
+    CredentialCreated = 0x02,
+    /// A discoverable credential was imported.
+    CredentialImported = 0x03,
+}
+
+impl TryFrom<u8> for AuditEvent {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(value: u8) -> Result<Self, Ctap2StatusCode> {
+        match value {
+            0x01 => Ok(AuditEvent::Reset),
+            0x02 => Ok(AuditEvent::CredentialCreated),
+            0x03 => Ok(AuditEvent::CredentialImported),
+            _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub event: AuditEvent,
+    pub timestamp: Timestamp,
+}
+
+/// Length of a serialized entry: event, boot count and uptime.
+pub const AUDIT_ENTRY_LENGTH: usize = 13;
+
+impl AuditEntry {
+    pub fn to_bytes(self) -> [u8; AUDIT_ENTRY_LENGTH] {
+        let mut bytes = [0; AUDIT_ENTRY_LENGTH];
+        bytes[0] = self.event as u8;
+        bytes[1..5].copy_from_slice(&self.timestamp.boot_count.to_ne_bytes());
+        bytes[5..].copy_from_slice(&self.timestamp.uptime_ms.to_ne_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Ctap2StatusCode> {
+        if bytes.len() != AUDIT_ENTRY_LENGTH {
+            return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+        }
+        Ok(AuditEntry {
+            event: AuditEvent::try_from(bytes[0])?,
+            timestamp: Timestamp {
+                boot_count: u32::from_ne_bytes(*array_ref!(bytes, 1, 4)),
+                uptime_ms: u64::from_ne_bytes(*array_ref!(bytes, 5, 8)),
+            },
+        })
+    }
+}
+
+/// Appends the event to the log, overwriting the oldest entry if full.
+pub fn record(env: &mut impl Env, event: AuditEvent) -> Result<(), Ctap2StatusCode> {
+    let timestamp = Timestamp::now(env)?;
+    storage::append_audit_entry(env, AuditEntry { event, timestamp })
+}
+
+/// Returns the logged events, oldest first.
+pub fn entries(env: &mut impl Env) -> Result<Vec<AuditEntry>, Ctap2StatusCode> {
+    let mut entries = storage::audit_entries(env)?;
+    entries.sort_by_key(|entry| entry.timestamp);
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+
+    #[test]
+    fn test_timestamp_cbor() {
+        let timestamp = Timestamp {
+            boot_count: 3,
+            uptime_ms: 12345,
+        };
+        let cbor_value = cbor::Value::from(timestamp);
+        assert_eq!(Timestamp::try_from(cbor_value), Ok(timestamp));
+        assert_eq!(
+            Timestamp::try_from(cbor_array![1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)
+        );
+    }
+
+    #[test]
+    fn test_entry_bytes() {
+        let entry = AuditEntry {
+            event: AuditEvent::CredentialCreated,
+            timestamp: Timestamp {
+                boot_count: 7,
+                uptime_ms: 0x0102_0304_0506,
+            },
+        };
+        assert_eq!(AuditEntry::from_bytes(&entry.to_bytes()), Ok(entry));
+        assert!(AuditEntry::from_bytes(&[0x01]).is_err());
+    }
+
+    #[test]
+    fn test_record_wraps() {
+        let mut env = TestEnv::default();
+        storage::incr_boot_counter(&mut env).unwrap();
+        for _ in 0..storage::MAX_AUDIT_ENTRIES {
+            env.clock().advance(10);
+            record(&mut env, AuditEvent::CredentialCreated).unwrap();
+        }
+        env.clock().advance(10);
+        record(&mut env, AuditEvent::Reset).unwrap();
+        let entries = entries(&mut env).unwrap();
+        assert_eq!(entries.len(), storage::MAX_AUDIT_ENTRIES);
+        // The oldest entry was replaced.
+        assert_eq!(entries[0].timestamp.uptime_ms, 20);
+        let last = entries.last().unwrap();
+        assert_eq!(last.event, AuditEvent::Reset);
+        assert_eq!(
+            last.timestamp,
+            Timestamp {
+                boot_count: 1,
+                uptime_ms: 10 * (storage::MAX_AUDIT_ENTRIES as u64 + 1),
+            }
+        );
+    }
+}
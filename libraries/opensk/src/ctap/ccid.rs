@@ -0,0 +1,388 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! USB smart card (CCID) transport.
+//!
+//! Clients select the FIDO applet and send CTAP2 commands as NFCCTAP_MSG APDUs, like on NFC.
+//! Other applets, e.g. PIV, are forwarded to [`Env::process_vendor_apdu`].
+
+use super::apdu::{Apdu, ApduInstructions, ApduStatusCode};
+use super::{Channel, CtapState};
+use crate::env::Env;
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+use core::convert::TryFrom;
+
+const CCID_HEADER_LEN: usize = 10;
+
+const PC_TO_RDR_ICC_POWER_ON: u8 = 0x62;
+const PC_TO_RDR_ICC_POWER_OFF: u8 = 0x63;
+const PC_TO_RDR_GET_SLOT_STATUS: u8 = 0x65;
+const PC_TO_RDR_XFR_BLOCK: u8 = 0x6F;
+const RDR_TO_PC_DATA_BLOCK: u8 = 0x80;
+const RDR_TO_PC_SLOT_STATUS: u8 = 0x81;
+
+// Bits of bStatus.
+const ICC_ACTIVE: u8 = 0x00;
+const ICC_INACTIVE: u8 = 0x01;
+const COMMAND_FAILED: u8 = 0x40;
+
+// Values of bError, if the command failed.
+const ERROR_CMD_NOT_SUPPORTED: u8 = 0x00;
+/// Offset of the dwLength field, which is inconsistent with the message.
+const ERROR_BAD_LENGTH: u8 = 0x01;
+/// Offset of the bSlot field, since we only have slot 0.
+const ERROR_BAD_SLOT: u8 = 0x05;
+const ERROR_ICC_MUTE: u8 = 0xFE;
+
+/// Answer to reset, announcing the T=1 protocol and no historical bytes.
+const ATR: [u8; 5] = [0x3B, 0x80, 0x80, 0x01, 0x01];
+
+const FIDO_AID: [u8; 8] = [0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01];
+const FIDO_2_VERSION: &[u8] = b"FIDO_2_0";
+const CLA_NFCCTAP: u8 = 0x80;
+const CLA_CHAINING: u8 = 0x10;
+const INS_NFCCTAP_MSG: u8 = 0x10;
+const SELECT_BY_NAME: u8 = 0x04;
+/// Short APDUs return at most that many bytes, the rest is fetched with GET RESPONSE.
+const MAX_RESPONSE_CHUNK: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Applet {
+    Fido,
+    Vendor,
+}
+
+/// State of the CCID interface, with a single slot.
+#[derive(Default)]
+pub struct Ccid {
+    powered: bool,
+    applet: Option<Applet>,
+    /// Command data of chained APDUs, until the last one is received.
+    chained_data: Vec<u8>,
+    /// Response data that is not yet fetched with GET RESPONSE.
+    pending_response: Vec<u8>,
+}
+
+impl Ccid {
+    /// Processes a bulk-out message, and returns the bulk-in message to answer.
+    ///
+    /// Returns an empty message if the input is too short to answer.
+    pub fn process_message<E: Env>(
+        &mut self,
+        env: &mut E,
+        message: &[u8],
+        ctap_state: &mut CtapState<E>,
+    ) -> Vec<u8> {
+        if message.len() < CCID_HEADER_LEN {
+            return Vec::new();
+        }
+        let message_type = message[0];
+        let length = LittleEndian::read_u32(&message[1..5]) as usize;
+        let slot = message[5];
+        let seq = message[6];
+        let payload = &message[CCID_HEADER_LEN..];
+        if slot != 0 {
+            return self.slot_status(slot, seq, ERROR_BAD_SLOT);
+        }
+        if payload.len() != length {
+            return self.slot_status(slot, seq, ERROR_BAD_LENGTH);
+        }
+        match message_type {
+            PC_TO_RDR_ICC_POWER_ON => {
+                self.reset();
+                self.powered = true;
+                data_block(slot, seq, &ATR)
+            }
+            PC_TO_RDR_ICC_POWER_OFF => {
+                self.reset();
+                self.powered = false;
+                self.slot_status_ok(slot, seq)
+            }
+            PC_TO_RDR_GET_SLOT_STATUS => self.slot_status_ok(slot, seq),
+            PC_TO_RDR_XFR_BLOCK => {
+                if !self.powered {
+                    return self.slot_status(slot, seq, ERROR_ICC_MUTE);
+                }
+                let response = self.process_apdu(env, payload, ctap_state);
+                data_block(slot, seq, &response)
+            }
+            _ => self.slot_status(slot, seq, ERROR_CMD_NOT_SUPPORTED),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.applet = None;
+        self.chained_data.clear();
+        self.pending_response.clear();
+    }
+
+    fn icc_status(&self) -> u8 {
+        if self.powered {
+            ICC_ACTIVE
+        } else {
+            ICC_INACTIVE
+        }
+    }
+
+    fn slot_status_ok(&self, slot: u8, seq: u8) -> Vec<u8> {
+        let mut message = header(RDR_TO_PC_SLOT_STATUS, 0, slot, seq);
+        message.extend_from_slice(&[self.icc_status(), 0x00, 0x00]);
+        message
+    }
+
+    fn slot_status(&self, slot: u8, seq: u8, error: u8) -> Vec<u8> {
+        let mut message = header(RDR_TO_PC_SLOT_STATUS, 0, slot, seq);
+        message.extend_from_slice(&[COMMAND_FAILED | self.icc_status(), error, 0x00]);
+        message
+    }
+
+    /// Processes a command APDU, and returns the response APDU including the status word.
+    fn process_apdu<E: Env>(
+        &mut self,
+        env: &mut E,
+        frame: &[u8],
+        ctap_state: &mut CtapState<E>,
+    ) -> Vec<u8> {
+        let apdu = match Apdu::try_from(frame) {
+            Ok(apdu) => apdu,
+            Err(status) => return status_word(status),
+        };
+        if apdu.header.ins == ApduInstructions::Select as u8 && apdu.header.p1 == SELECT_BY_NAME {
+            self.reset();
+            if apdu.data == FIDO_AID {
+                self.applet = Some(Applet::Fido);
+                let mut response = FIDO_2_VERSION.to_vec();
+                response.extend(status_word(ApduStatusCode::SW_SUCCESS));
+                return response;
+            }
+            return match env.process_vendor_apdu(frame) {
+                Some(response) => {
+                    if response.ends_with(&status_word(ApduStatusCode::SW_SUCCESS)) {
+                        self.applet = Some(Applet::Vendor);
+                    }
+                    response
+                }
+                None => status_word(ApduStatusCode::SW_FILE_NOT_FOUND),
+            };
+        }
+        match self.applet {
+            Some(Applet::Fido) => self.process_fido_apdu(env, apdu, ctap_state),
+            Some(Applet::Vendor) => env
+                .process_vendor_apdu(frame)
+                .unwrap_or_else(|| status_word(ApduStatusCode::SW_INS_INVALID)),
+            None => status_word(ApduStatusCode::SW_COND_USE_NOT_SATISFIED),
+        }
+    }
+
+    fn process_fido_apdu<E: Env>(
+        &mut self,
+        env: &mut E,
+        apdu: Apdu,
+        ctap_state: &mut CtapState<E>,
+    ) -> Vec<u8> {
+        if apdu.header.ins == ApduInstructions::GetResponse as u8 {
+            return self.next_response_chunk();
+        }
+        if apdu.header.cla & !CLA_CHAINING != CLA_NFCCTAP {
+            return status_word(ApduStatusCode::SW_CLA_INVALID);
+        }
+        if apdu.header.ins != INS_NFCCTAP_MSG {
+            return status_word(ApduStatusCode::SW_INS_INVALID);
+        }
+        self.pending_response.clear();
+        self.chained_data.extend_from_slice(&apdu.data);
+        if apdu.header.cla & CLA_CHAINING != 0 {
+            return status_word(ApduStatusCode::SW_SUCCESS);
+        }
+        let command = core::mem::take(&mut self.chained_data);
+        self.pending_response = ctap_state.process_command(env, &command, Channel::Ccid);
+        self.next_response_chunk()
+    }
+
+    fn next_response_chunk(&mut self) -> Vec<u8> {
+        let chunk_len = core::cmp::min(self.pending_response.len(), MAX_RESPONSE_CHUNK);
+        let mut response: Vec<u8> = self.pending_response.drain(..chunk_len).collect();
+        let remaining = self.pending_response.len();
+        if remaining == 0 {
+            response.extend(status_word(ApduStatusCode::SW_SUCCESS));
+        } else {
+            // 0x00 means 256 or more bytes remaining.
+            response.extend_from_slice(&[0x61, if remaining > 0xFF { 0 } else { remaining as u8 }]);
+        }
+        response
+    }
+}
+
+fn header(message_type: u8, length: usize, slot: u8, seq: u8) -> Vec<u8> {
+    let mut message = Vec::with_capacity(CCID_HEADER_LEN + length);
+    message.push(message_type);
+    message.extend_from_slice(&(length as u32).to_le_bytes());
+    message.extend_from_slice(&[slot, seq]);
+    message
+}
+
+fn data_block(slot: u8, seq: u8, data: &[u8]) -> Vec<u8> {
+    let mut message = header(RDR_TO_PC_DATA_BLOCK, data.len(), slot, seq);
+    message.extend_from_slice(&[ICC_ACTIVE, 0x00, 0x00]);
+    message.extend_from_slice(data);
+    message
+}
+
+fn status_word(status: ApduStatusCode) -> Vec<u8> {
+    u16::from(status).to_be_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+    use alloc::vec;
+
+    fn message(message_type: u8, seq: u8, payload: &[u8]) -> Vec<u8> {
+        let mut message = header(message_type, payload.len(), 0, seq);
+        message.extend_from_slice(&[0x00; 3]);
+        message.extend_from_slice(payload);
+        message
+    }
+
+    fn select_fido() -> Vec<u8> {
+        let mut apdu = vec![0x00, 0xA4, 0x04, 0x00, FIDO_AID.len() as u8];
+        apdu.extend_from_slice(&FIDO_AID);
+        message(PC_TO_RDR_XFR_BLOCK, 2, &apdu)
+    }
+
+    fn powered_ccid(env: &mut TestEnv, ctap_state: &mut CtapState<TestEnv>) -> Ccid {
+        let mut ccid = Ccid::default();
+        let response =
+            ccid.process_message(env, &message(PC_TO_RDR_ICC_POWER_ON, 1, &[]), ctap_state);
+        assert_eq!(response[0], RDR_TO_PC_DATA_BLOCK);
+        assert_eq!(&response[CCID_HEADER_LEN..], &ATR);
+        ccid
+    }
+
+    #[test]
+    fn test_slot_status() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let mut ccid = Ccid::default();
+        let response = ccid.process_message(
+            &mut env,
+            &message(PC_TO_RDR_GET_SLOT_STATUS, 7, &[]),
+            &mut ctap_state,
+        );
+        assert_eq!(
+            response,
+            vec![RDR_TO_PC_SLOT_STATUS, 0, 0, 0, 0, 0, 7, ICC_INACTIVE, 0, 0]
+        );
+
+        let mut bad_slot = message(PC_TO_RDR_GET_SLOT_STATUS, 8, &[]);
+        bad_slot[5] = 1;
+        let response = ccid.process_message(&mut env, &bad_slot, &mut ctap_state);
+        assert_eq!(response[7], COMMAND_FAILED | ICC_INACTIVE);
+        assert_eq!(response[8], ERROR_BAD_SLOT);
+    }
+
+    #[test]
+    fn test_xfr_block_unpowered() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let mut ccid = Ccid::default();
+        let response = ccid.process_message(&mut env, &select_fido(), &mut ctap_state);
+        assert_eq!(response[0], RDR_TO_PC_SLOT_STATUS);
+        assert_eq!(response[8], ERROR_ICC_MUTE);
+    }
+
+    #[test]
+    fn test_select_and_get_info() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let mut ccid = powered_ccid(&mut env, &mut ctap_state);
+
+        let response = ccid.process_message(&mut env, &select_fido(), &mut ctap_state);
+        assert_eq!(&response[CCID_HEADER_LEN..], b"FIDO_2_0\x90\x00");
+
+        let get_info = message(
+            PC_TO_RDR_XFR_BLOCK,
+            3,
+            &[0x80, 0x10, 0x00, 0x00, 0x01, 0x04],
+        );
+        let response = ccid.process_message(&mut env, &get_info, &mut ctap_state);
+        assert_eq!(response[0], RDR_TO_PC_DATA_BLOCK);
+        assert_eq!(response[CCID_HEADER_LEN], 0x00);
+    }
+
+    #[test]
+    fn test_get_response() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let mut ccid = powered_ccid(&mut env, &mut ctap_state);
+        ccid.process_message(&mut env, &select_fido(), &mut ctap_state);
+
+        ccid.pending_response = vec![0x55; MAX_RESPONSE_CHUNK + 10];
+        let get_response = message(PC_TO_RDR_XFR_BLOCK, 3, &[0x00, 0xC0, 0x00, 0x00, 0x00]);
+        let response = ccid.process_message(&mut env, &get_response, &mut ctap_state);
+        let apdu_response = &response[CCID_HEADER_LEN..];
+        assert_eq!(apdu_response.len(), MAX_RESPONSE_CHUNK + 2);
+        assert_eq!(&apdu_response[MAX_RESPONSE_CHUNK..], &[0x61, 10]);
+
+        let response = ccid.process_message(&mut env, &get_response, &mut ctap_state);
+        let mut expected = vec![0x55; 10];
+        expected.extend_from_slice(&[0x90, 0x00]);
+        assert_eq!(&response[CCID_HEADER_LEN..], &expected[..]);
+    }
+
+    #[test]
+    fn test_command_chaining() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let mut ccid = powered_ccid(&mut env, &mut ctap_state);
+        ccid.process_message(&mut env, &select_fido(), &mut ctap_state);
+
+        // An empty chained APDU, followed by the actual GetInfo command.
+        let chained = message(PC_TO_RDR_XFR_BLOCK, 3, &[0x90, 0x10, 0x00, 0x00]);
+        let response = ccid.process_message(&mut env, &chained, &mut ctap_state);
+        assert_eq!(&response[CCID_HEADER_LEN..], &[0x90, 0x00]);
+        let last = message(
+            PC_TO_RDR_XFR_BLOCK,
+            4,
+            &[0x80, 0x10, 0x00, 0x00, 0x01, 0x04],
+        );
+        let response = ccid.process_message(&mut env, &last, &mut ctap_state);
+        assert_eq!(response[CCID_HEADER_LEN], 0x00);
+    }
+
+    #[test]
+    fn test_unknown_applet() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let mut ccid = powered_ccid(&mut env, &mut ctap_state);
+
+        let select_piv = message(
+            PC_TO_RDR_XFR_BLOCK,
+            2,
+            &[0x00, 0xA4, 0x04, 0x00, 0x05, 0xA0, 0x00, 0x00, 0x03, 0x08],
+        );
+        let response = ccid.process_message(&mut env, &select_piv, &mut ctap_state);
+        assert_eq!(&response[CCID_HEADER_LEN..], &[0x6A, 0x82]);
+
+        let get_info = message(
+            PC_TO_RDR_XFR_BLOCK,
+            3,
+            &[0x80, 0x10, 0x00, 0x00, 0x01, 0x04],
+        );
+        let response = ccid.process_message(&mut env, &get_info, &mut ctap_state);
+        assert_eq!(&response[CCID_HEADER_LEN..], &[0x69, 0x85]);
+    }
+}
@@ -12,10 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::audit_log::{self, AuditEvent};
 use super::command::AuthenticatorClientPinParameters;
 use super::data_formats::{
     ok_or_missing, ClientPinSubCommand, CoseKey, GetAssertionHmacSecretInput, PinUvAuthProtocol,
 };
+use super::pin_policy;
 use super::pin_protocol::{verify_pin_uv_auth_token, PinProtocol, SharedSecret};
 use super::response::{AuthenticatorClientPinResponse, ResponseData};
 use super::secret::Secret;
@@ -26,8 +28,10 @@ use crate::api::crypto::ecdh::SecretKey as _;
 use crate::api::crypto::hmac256::Hmac256;
 use crate::api::crypto::sha256::Sha256;
 use crate::api::customization::Customization;
+use crate::api::delay::{Delay, FAILURE_DELAY_MS};
 use crate::api::key_store::KeyStore;
-use crate::ctap::storage;
+use crate::api::user_verification::UserVerification;
+use crate::ctap::{check_user_verification, storage, Channel};
 #[cfg(test)]
 use crate::env::EcdhSk;
 use crate::env::{Env, Hmac, Sha};
@@ -93,10 +97,12 @@ fn check_and_store_new_pin<E: Env>(
 ) -> Result<(), Ctap2StatusCode> {
     let pin = decrypt_pin(shared_secret, new_pin_enc)?;
     let min_pin_length = storage::min_pin_length(env)? as usize;
-    let pin_length = str::from_utf8(&pin).unwrap_or("").chars().count();
+    let pin_str = str::from_utf8(&pin).unwrap_or("");
+    let pin_length = pin_str.chars().count();
     if pin_length < min_pin_length || pin.len() == PIN_PADDED_LENGTH {
         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
     }
+    pin_policy::check(env, pin_str)?;
     let mut pin_hash = Secret::default();
     Sha::<E>::digest_mut(&pin, &mut pin_hash);
     let pin_hash = env
@@ -111,7 +117,24 @@ fn check_and_store_new_pin<E: Env>(
     Ok(())
 }
 
+/// Returns the number of remaining PIN retries.
+pub fn pin_retries<E: Env>(env: &mut E) -> Result<u8, Ctap2StatusCode> {
+    storage::pin_retries(env)
+}
+
+/// Returns the number of remaining built-in user verification retries.
+///
+/// Both the authenticator and the built-in method itself limit them.
+pub fn uv_retries<E: Env>(env: &mut E) -> Result<usize, Ctap2StatusCode> {
+    let uv_retries = storage::uv_retries(env)? as usize;
+    Ok(core::cmp::min(
+        uv_retries,
+        env.user_verification().retries(),
+    ))
+}
+
 #[cfg_attr(test, derive(IntoEnumIterator))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PinPermission {
     // All variants should use integers with a single bit set.
     MakeCredential = 0x01,
@@ -121,6 +144,47 @@ pub enum PinPermission {
     LargeBlobWrite = 0x10,
     #[cfg(feature = "config_command")]
     AuthenticatorConfiguration = 0x20,
+    // Vendor-defined permissions use the upper half, away from the bits CTAP assigns.
+    /// Authorizes BBS presentations, through the vendor commands of the environment.
+    #[cfg(feature = "bbs")]
+    BbsPresentation = 0x0001_0000,
+}
+
+/// Checks pinUvAuthParams of vendor commands against the pinUvAuthToken.
+///
+/// The token never leaves the CTAP state, so environments receive this check instead.
+pub trait PinUvAuthCheck {
+    /// Verifies the pinUvAuthParam over the contents, and the permission of the token.
+    ///
+    /// Vendor permissions are not bound to an RP ID, so tokens for one RP ID are refused.
+    fn check_pin_uv_auth(
+        &self,
+        permission: PinPermission,
+        hmac_contents: &[u8],
+        pin_uv_auth_param: &[u8],
+        pin_uv_auth_protocol: PinUvAuthProtocol,
+    ) -> Result<(), Ctap2StatusCode>;
+
+    /// Returns the number of failed PINs since the last correct PIN or power cycle.
+    fn consecutive_pin_mismatches(&self) -> u8;
+}
+
+impl<E: Env> PinUvAuthCheck for ClientPin<E> {
+    fn check_pin_uv_auth(
+        &self,
+        permission: PinPermission,
+        hmac_contents: &[u8],
+        pin_uv_auth_param: &[u8],
+        pin_uv_auth_protocol: PinUvAuthProtocol,
+    ) -> Result<(), Ctap2StatusCode> {
+        self.verify_pin_uv_auth_token(hmac_contents, pin_uv_auth_param, pin_uv_auth_protocol)?;
+        self.has_permission(permission)?;
+        self.has_no_rp_id_permission()
+    }
+
+    fn consecutive_pin_mismatches(&self) -> u8 {
+        self.consecutive_pin_mismatches
+    }
 }
 
 pub struct ClientPin<E: Env> {
@@ -183,7 +247,7 @@ impl<E: Env> ClientPin<E> {
     ) -> Result<(), Ctap2StatusCode> {
         match storage::pin_hash(env)? {
             Some(pin_hash) => {
-                if self.consecutive_pin_mismatches >= 3 {
+                if self.needs_power_cycle(env) {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED);
                 }
                 storage::decr_pin_retries(env)?;
@@ -195,11 +259,17 @@ impl<E: Env> ClientPin<E> {
                 if !bool::from(pin_hash.ct_eq(&pin_hash_dec)) {
                     self.get_mut_pin_protocol(pin_uv_auth_protocol)
                         .regenerate(env);
+                    env.delay().random_delay(FAILURE_DELAY_MS);
                     if storage::pin_retries(env)? == 0 {
+                        if env.customization().resets_when_pin_blocked() {
+                            storage::reset(env)?;
+                            audit_log::record(env, AuditEvent::Reset)?;
+                            self.reset(env);
+                        }
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED);
                     }
                     self.consecutive_pin_mismatches += 1;
-                    if self.consecutive_pin_mismatches >= 3 {
+                    if self.needs_power_cycle(env) {
                         return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED);
                     }
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID);
@@ -209,10 +279,17 @@ impl<E: Env> ClientPin<E> {
             None => return Err(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED),
         }
         storage::reset_pin_retries(env)?;
+        // The PIN is the fallback of built-in user verification, and unblocks it.
+        storage::reset_uv_retries(env)?;
         self.consecutive_pin_mismatches = 0;
         Ok(())
     }
 
+    /// Returns whether PINs are refused until the next power cycle.
+    fn needs_power_cycle(&self, env: &mut E) -> bool {
+        self.consecutive_pin_mismatches >= env.customization().pin_mismatches_before_power_cycle()
+    }
+
     fn process_get_pin_retries(
         &self,
         env: &mut E,
@@ -221,7 +298,8 @@ impl<E: Env> ClientPin<E> {
             key_agreement: None,
             pin_uv_auth_token: None,
             retries: Some(storage::pin_retries(env)? as u64),
-            power_cycle_state: Some(self.consecutive_pin_mismatches >= 3),
+            power_cycle_state: Some(self.needs_power_cycle(env)),
+            uv_retries: None,
         })
     }
 
@@ -238,6 +316,7 @@ impl<E: Env> ClientPin<E> {
             pin_uv_auth_token: None,
             retries: None,
             power_cycle_state: None,
+            uv_retries: None,
         })
     }
 
@@ -345,22 +424,77 @@ impl<E: Env> ClientPin<E> {
             pin_uv_auth_token: Some(pin_uv_auth_token),
             retries: None,
             power_cycle_state: None,
+            uv_retries: None,
         })
     }
 
     fn process_get_pin_uv_auth_token_using_uv_with_permissions(
-        &self,
-        // If you want to support local user verification, implement this function.
-        // Lacking a fingerprint reader, this subcommand is currently unsupported.
-        _client_pin_params: AuthenticatorClientPinParameters,
+        &mut self,
+        env: &mut E,
+        client_pin_params: AuthenticatorClientPinParameters,
+        channel: Channel,
     ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        // User verification is only supported through PIN currently.
-        Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        // Without built-in user verification, this subcommand is unsupported.
+        if !env.user_verification().is_supported() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND);
+        }
+        let AuthenticatorClientPinParameters {
+            pin_uv_auth_protocol,
+            key_agreement,
+            permissions,
+            permissions_rp_id,
+            ..
+        } = client_pin_params;
+        let key_agreement = ok_or_missing(key_agreement)?;
+        let permissions = ok_or_missing(permissions)?;
+
+        if permissions == 0 {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        // This check is not mentioned protocol steps, but mentioned in a side note.
+        if permissions & 0x03 != 0 && permissions_rp_id.is_none() {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+
+        let shared_secret = self.get_shared_secret(pin_uv_auth_protocol, key_agreement)?;
+        check_user_verification(env, channel)?;
+
+        self.pin_protocol_v1.reset_pin_uv_auth_token(env);
+        self.pin_protocol_v2.reset_pin_uv_auth_token(env);
+        self.pin_uv_auth_token_state
+            .begin_using_pin_uv_auth_token(env);
+        self.pin_uv_auth_token_state.set_permissions(permissions);
+        self.pin_uv_auth_token_state
+            .set_permissions_rp_id(permissions_rp_id);
+        let pin_uv_auth_token = shared_secret.encrypt(
+            env,
+            self.get_pin_protocol(pin_uv_auth_protocol)
+                .get_pin_uv_auth_token(),
+        )?;
+
+        Ok(AuthenticatorClientPinResponse {
+            key_agreement: None,
+            pin_uv_auth_token: Some(pin_uv_auth_token),
+            retries: None,
+            power_cycle_state: None,
+            uv_retries: None,
+        })
     }
 
-    fn process_get_uv_retries(&self) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
-        // User verification is only supported through PIN currently.
-        Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+    fn process_get_uv_retries(
+        &self,
+        env: &mut E,
+    ) -> Result<AuthenticatorClientPinResponse, Ctap2StatusCode> {
+        if !env.user_verification().is_supported() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND);
+        }
+        Ok(AuthenticatorClientPinResponse {
+            key_agreement: None,
+            pin_uv_auth_token: None,
+            retries: None,
+            power_cycle_state: None,
+            uv_retries: Some(uv_retries(env)? as u64),
+        })
     }
 
     fn process_get_pin_uv_auth_token_using_pin_with_permissions(
@@ -395,6 +529,7 @@ impl<E: Env> ClientPin<E> {
         &mut self,
         env: &mut E,
         client_pin_params: AuthenticatorClientPinParameters,
+        channel: Channel,
     ) -> Result<ResponseData, Ctap2StatusCode> {
         if !env.customization().allows_pin_protocol_v1()
             && client_pin_params.pin_uv_auth_protocol == PinUvAuthProtocol::V1
@@ -418,9 +553,13 @@ impl<E: Env> ClientPin<E> {
                 Some(self.process_get_pin_token(env, client_pin_params)?)
             }
             ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions => Some(
-                self.process_get_pin_uv_auth_token_using_uv_with_permissions(client_pin_params)?,
+                self.process_get_pin_uv_auth_token_using_uv_with_permissions(
+                    env,
+                    client_pin_params,
+                    channel,
+                )?,
             ),
-            ClientPinSubCommand::GetUvRetries => Some(self.process_get_uv_retries()?),
+            ClientPinSubCommand::GetUvRetries => Some(self.process_get_uv_retries(env)?),
             ClientPinSubCommand::GetPinUvAuthTokenUsingPinWithPermissions => Some(
                 self.process_get_pin_uv_auth_token_using_pin_with_permissions(
                     env,
@@ -586,7 +725,7 @@ impl<E: Env> ClientPin<E> {
             PinUvAuthProtocol::V2 => (random_key, key_agreement_key),
         };
         let mut pin_uv_auth_token_state = PinUvAuthTokenState::new();
-        pin_uv_auth_token_state.set_permissions(0xFF);
+        pin_uv_auth_token_state.set_permissions(u32::MAX);
         pin_uv_auth_token_state.begin_using_pin_uv_auth_token(env);
         Self {
             pin_protocol_v1: PinProtocol::new_test(key_agreement_key_v1, pin_uv_auth_token),
@@ -602,10 +741,13 @@ mod test {
     use super::super::pin_protocol::authenticate_pin_uv_auth_token;
     use super::*;
     use crate::api::crypto::HASH_SIZE;
+    use crate::api::user_verification::UserVerificationError;
     use crate::env::test::TestEnv;
     use crate::env::EcdhSk;
     use alloc::vec;
 
+    const DUMMY_CHANNEL: Channel = Channel::MainHid([0x12, 0x34, 0x56, 0x78]);
+
     /// Stores a PIN hash corresponding to the dummy PIN "1234".
     fn set_standard_pin(env: &mut TestEnv) {
         let mut pin = [0u8; 64];
@@ -758,6 +900,7 @@ mod test {
             ),
             Ok(())
         );
+        assert!(env.delay().requested().is_empty());
 
         let pin_hash_enc = vec![0xEE; 16];
         assert_eq!(
@@ -769,6 +912,8 @@ mod test {
             ),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
+        // Mismatches respond after a random delay.
+        assert_eq!(env.delay().requested(), &[FAILURE_DELAY_MS]);
 
         let pin_hash_enc = shared_secret.encrypt(&mut env, &pin_hash).unwrap();
         client_pin.consecutive_pin_mismatches = 3;
@@ -816,6 +961,139 @@ mod test {
         test_helper_verify_pin_hash_enc(PinUvAuthProtocol::V2);
     }
 
+    #[test]
+    fn test_verify_pin_hash_enc_power_cycle_threshold() {
+        let mut env = TestEnv::default();
+        env.customization_mut()
+            .set_pin_mismatches_before_power_cycle(5);
+        let mut client_pin = ClientPin::<TestEnv>::new(&mut env);
+        let shared_secret = client_pin
+            .get_pin_protocol(PinUvAuthProtocol::V2)
+            .decapsulate(
+                client_pin
+                    .get_pin_protocol(PinUvAuthProtocol::V2)
+                    .get_public_key(),
+                PinUvAuthProtocol::V2,
+            )
+            .unwrap();
+        let pin_hash = [0x55; PIN_AUTH_LENGTH];
+        storage::set_pin(&mut env, &pin_hash, 4).unwrap();
+
+        for _ in 0..4 {
+            assert_eq!(
+                client_pin.verify_pin_hash_enc(
+                    &mut env,
+                    PinUvAuthProtocol::V2,
+                    &shared_secret,
+                    vec![0xEE; 16]
+                ),
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
+            );
+        }
+        assert_eq!(client_pin.consecutive_pin_mismatches(), 4);
+        assert_eq!(
+            client_pin.verify_pin_hash_enc(
+                &mut env,
+                PinUvAuthProtocol::V2,
+                &shared_secret,
+                vec![0xEE; 16]
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
+        );
+        // Even the correct PIN waits for the power cycle, without using up a retry.
+        let pin_hash_enc = shared_secret.encrypt(&mut env, &pin_hash).unwrap();
+        assert_eq!(
+            client_pin.verify_pin_hash_enc(
+                &mut env,
+                PinUvAuthProtocol::V2,
+                &shared_secret,
+                pin_hash_enc
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_BLOCKED)
+        );
+        assert_eq!(storage::pin_retries(&mut env), Ok(3));
+    }
+
+    #[test]
+    fn test_verify_pin_hash_enc_resets_when_blocked() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_resets_when_pin_blocked(true);
+        env.customization_mut()
+            .set_pin_mismatches_before_power_cycle(8);
+        let mut client_pin = ClientPin::<TestEnv>::new(&mut env);
+        let shared_secret = client_pin
+            .get_pin_protocol(PinUvAuthProtocol::V2)
+            .decapsulate(
+                client_pin
+                    .get_pin_protocol(PinUvAuthProtocol::V2)
+                    .get_public_key(),
+                PinUvAuthProtocol::V2,
+            )
+            .unwrap();
+        storage::set_pin(&mut env, &[0x55; PIN_AUTH_LENGTH], 4).unwrap();
+
+        for _ in 1..env.customization().max_pin_retries() {
+            assert_eq!(
+                client_pin.verify_pin_hash_enc(
+                    &mut env,
+                    PinUvAuthProtocol::V2,
+                    &shared_secret,
+                    vec![0xEE; 16]
+                ),
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
+            );
+        }
+        assert!(storage::pin_hash(&mut env).unwrap().is_some());
+        assert_eq!(
+            client_pin.verify_pin_hash_enc(
+                &mut env,
+                PinUvAuthProtocol::V2,
+                &shared_secret,
+                vec![0xEE; 16]
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED)
+        );
+        assert_eq!(storage::pin_hash(&mut env), Ok(None));
+        assert_eq!(
+            storage::pin_retries(&mut env),
+            Ok(env.customization().max_pin_retries())
+        );
+        assert_eq!(client_pin.consecutive_pin_mismatches(), 0);
+    }
+
+    #[test]
+    fn test_verify_pin_hash_enc_resets_uv_retries() {
+        let mut env = TestEnv::default();
+        let mut client_pin = ClientPin::<TestEnv>::new(&mut env);
+        let shared_secret = client_pin
+            .get_pin_protocol(PinUvAuthProtocol::V2)
+            .decapsulate(
+                client_pin
+                    .get_pin_protocol(PinUvAuthProtocol::V2)
+                    .get_public_key(),
+                PinUvAuthProtocol::V2,
+            )
+            .unwrap();
+        let pin_hash = [0x55; PIN_AUTH_LENGTH];
+        storage::set_pin(&mut env, &pin_hash, 4).unwrap();
+        storage::decr_uv_retries(&mut env).unwrap();
+
+        let pin_hash_enc = shared_secret.encrypt(&mut env, &pin_hash).unwrap();
+        assert_eq!(
+            client_pin.verify_pin_hash_enc(
+                &mut env,
+                PinUvAuthProtocol::V2,
+                &shared_secret,
+                pin_hash_enc
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            storage::uv_retries(&mut env),
+            Ok(env.customization().max_uv_retries())
+        );
+    }
+
     fn test_helper_process_get_pin_retries(pin_uv_auth_protocol: PinUvAuthProtocol) {
         let (mut client_pin, params) = create_client_pin_and_parameters(
             pin_uv_auth_protocol,
@@ -827,9 +1105,10 @@ mod test {
             pin_uv_auth_token: None,
             retries: Some(storage::pin_retries(&mut env).unwrap() as u64),
             power_cycle_state: Some(false),
+            uv_retries: None,
         });
         assert_eq!(
-            client_pin.process_command(&mut env, params.clone()),
+            client_pin.process_command(&mut env, params.clone(), DUMMY_CHANNEL),
             Ok(ResponseData::AuthenticatorClientPin(expected_response))
         );
 
@@ -839,9 +1118,10 @@ mod test {
             pin_uv_auth_token: None,
             retries: Some(storage::pin_retries(&mut env).unwrap() as u64),
             power_cycle_state: Some(true),
+            uv_retries: None,
         });
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Ok(ResponseData::AuthenticatorClientPin(expected_response))
         );
     }
@@ -867,9 +1147,10 @@ mod test {
             pin_uv_auth_token: None,
             retries: None,
             power_cycle_state: None,
+            uv_retries: None,
         });
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Ok(ResponseData::AuthenticatorClientPin(expected_response))
         );
     }
@@ -893,7 +1174,7 @@ mod test {
         let mut env = TestEnv::default();
         env.customization_mut().set_allows_pin_protocol_v1(false);
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
     }
@@ -903,7 +1184,7 @@ mod test {
             create_client_pin_and_parameters(pin_uv_auth_protocol, ClientPinSubCommand::SetPin);
         let mut env = TestEnv::default();
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Ok(ResponseData::AuthenticatorClientPin(None))
         );
     }
@@ -936,14 +1217,14 @@ mod test {
         let pin_uv_auth_param = shared_secret.authenticate(&auth_param_data);
         params.pin_uv_auth_param = Some(pin_uv_auth_param);
         assert_eq!(
-            client_pin.process_command(&mut env, params.clone()),
+            client_pin.process_command(&mut env, params.clone(), DUMMY_CHANNEL),
             Ok(ResponseData::AuthenticatorClientPin(None))
         );
 
         let mut bad_params = params.clone();
         bad_params.pin_hash_enc = Some(vec![0xEE; 16]);
         assert_eq!(
-            client_pin.process_command(&mut env, bad_params),
+            client_pin.process_command(&mut env, bad_params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         );
 
@@ -951,7 +1232,7 @@ mod test {
             storage::decr_pin_retries(&mut env).unwrap();
         }
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_BLOCKED)
         );
     }
@@ -982,7 +1263,7 @@ mod test {
         set_standard_pin(&mut env);
 
         let response = client_pin
-            .process_command(&mut env, params.clone())
+            .process_command(&mut env, params.clone(), DUMMY_CHANNEL)
             .unwrap();
         let encrypted_token = match response {
             ResponseData::AuthenticatorClientPin(Some(response)) => {
@@ -1018,7 +1299,7 @@ mod test {
         let mut bad_params = params;
         bad_params.pin_hash_enc = Some(vec![0xEE; 16]);
         assert_eq!(
-            client_pin.process_command(&mut env, bad_params),
+            client_pin.process_command(&mut env, bad_params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
@@ -1043,7 +1324,7 @@ mod test {
 
         assert_eq!(storage::force_pin_change(&mut env), Ok(()));
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID),
         );
     }
@@ -1076,7 +1357,7 @@ mod test {
         set_standard_pin(&mut env);
 
         let response = client_pin
-            .process_command(&mut env, params.clone())
+            .process_command(&mut env, params.clone(), DUMMY_CHANNEL)
             .unwrap();
         let encrypted_token = match response {
             ResponseData::AuthenticatorClientPin(Some(response)) => {
@@ -1112,21 +1393,21 @@ mod test {
         let mut bad_params = params.clone();
         bad_params.permissions = Some(0x00);
         assert_eq!(
-            client_pin.process_command(&mut env, bad_params),
+            client_pin.process_command(&mut env, bad_params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
 
         let mut bad_params = params.clone();
         bad_params.permissions_rp_id = None;
         assert_eq!(
-            client_pin.process_command(&mut env, bad_params),
+            client_pin.process_command(&mut env, bad_params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
 
         let mut bad_params = params;
         bad_params.pin_hash_enc = Some(vec![0xEE; 16]);
         assert_eq!(
-            client_pin.process_command(&mut env, bad_params),
+            client_pin.process_command(&mut env, bad_params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
@@ -1153,7 +1434,7 @@ mod test {
 
         assert_eq!(storage::force_pin_change(&mut env), Ok(()));
         assert_eq!(
-            client_pin.process_command(&mut env, params),
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_INVALID)
         );
     }
@@ -1172,6 +1453,113 @@ mod test {
         );
     }
 
+    fn test_helper_process_get_pin_uv_auth_token_using_uv_with_permissions(
+        pin_uv_auth_protocol: PinUvAuthProtocol,
+    ) {
+        let (mut client_pin, params) = create_client_pin_and_parameters(
+            pin_uv_auth_protocol,
+            ClientPinSubCommand::GetPinUvAuthTokenUsingUvWithPermissions,
+        );
+        let shared_secret = client_pin
+            .get_pin_protocol(pin_uv_auth_protocol)
+            .decapsulate(
+                params.key_agreement.clone().unwrap(),
+                params.pin_uv_auth_protocol,
+            )
+            .unwrap();
+        let mut env = TestEnv::default();
+        assert_eq!(
+            client_pin.process_command(&mut env, params.clone(), DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        );
+
+        env.user_verification().set(|| Ok(()));
+        let response = client_pin
+            .process_command(&mut env, params.clone(), DUMMY_CHANNEL)
+            .unwrap();
+        let encrypted_token = match response {
+            ResponseData::AuthenticatorClientPin(Some(response)) => {
+                response.pin_uv_auth_token.unwrap()
+            }
+            _ => panic!("Invalid response type"),
+        };
+        assert_eq!(
+            &*shared_secret.decrypt(&encrypted_token).unwrap(),
+            client_pin
+                .get_pin_protocol(pin_uv_auth_protocol)
+                .get_pin_uv_auth_token()
+        );
+        assert_eq!(
+            client_pin
+                .pin_uv_auth_token_state
+                .has_permission(PinPermission::MakeCredential),
+            Ok(())
+        );
+        assert_eq!(
+            client_pin
+                .pin_uv_auth_token_state
+                .has_permissions_rp_id("example.com"),
+            Ok(())
+        );
+
+        let mut bad_params = params.clone();
+        bad_params.permissions = Some(0x00);
+        assert_eq!(
+            client_pin.process_command(&mut env, bad_params, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        env.user_verification()
+            .set(|| Err(UserVerificationError::Invalid));
+        assert_eq!(
+            client_pin.process_command(&mut env, params.clone(), DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+        );
+
+        env.user_verification().set_retries(0);
+        assert_eq!(
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+        );
+    }
+
+    #[test]
+    fn test_process_get_pin_uv_auth_token_using_uv_with_permissions_v1() {
+        test_helper_process_get_pin_uv_auth_token_using_uv_with_permissions(PinUvAuthProtocol::V1);
+    }
+
+    #[test]
+    fn test_process_get_pin_uv_auth_token_using_uv_with_permissions_v2() {
+        test_helper_process_get_pin_uv_auth_token_using_uv_with_permissions(PinUvAuthProtocol::V2);
+    }
+
+    #[test]
+    fn test_process_get_uv_retries() {
+        let (mut client_pin, params) = create_client_pin_and_parameters(
+            PinUvAuthProtocol::V2,
+            ClientPinSubCommand::GetUvRetries,
+        );
+        let mut env = TestEnv::default();
+        assert_eq!(
+            client_pin.process_command(&mut env, params.clone(), DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_SUBCOMMAND)
+        );
+
+        env.user_verification().set(|| Ok(()));
+        env.user_verification().set_retries(5);
+        let expected_response = Some(AuthenticatorClientPinResponse {
+            key_agreement: None,
+            pin_uv_auth_token: None,
+            retries: None,
+            power_cycle_state: None,
+            uv_retries: Some(5),
+        });
+        assert_eq!(
+            client_pin.process_command(&mut env, params, DUMMY_CHANNEL),
+            Ok(ResponseData::AuthenticatorClientPin(expected_response))
+        );
+    }
+
     fn test_helper_decrypt_pin(pin_uv_auth_protocol: PinUvAuthProtocol) {
         let mut env = TestEnv::default();
         let pin_protocol = PinProtocol::<TestEnv>::new(&mut env);
@@ -1268,6 +1656,35 @@ mod test {
         test_helper_check_and_store_new_pin(PinUvAuthProtocol::V2);
     }
 
+    #[test]
+    fn test_check_and_store_new_pin_policy() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_rejects_trivial_pins(true);
+        env.customization_mut().set_requires_alphanumeric_pin(true);
+        let pin_protocol = PinProtocol::<TestEnv>::new(&mut env);
+        let shared_secret = pin_protocol
+            .decapsulate(pin_protocol.get_public_key(), PinUvAuthProtocol::V2)
+            .unwrap();
+
+        for pin in [b"abcd".to_vec(), b"4729".to_vec()] {
+            let new_pin_enc = encrypt_pin(&shared_secret, pin);
+            assert_eq!(
+                check_and_store_new_pin(&mut env, &shared_secret, new_pin_enc),
+                Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+            );
+            assert_eq!(storage::pin_hash(&mut env), Ok(None));
+        }
+        let new_pin_enc = encrypt_pin(&shared_secret, b"s3cret".to_vec());
+        assert_eq!(
+            check_and_store_new_pin(&mut env, &shared_secret, new_pin_enc),
+            Ok(())
+        );
+        assert_eq!(
+            storage::pin_requirements(&mut env),
+            Ok(Some(pin_policy::ALPHANUMERIC))
+        );
+    }
+
     /// Generates valid inputs for process_hmac_secret and returns the output.
     fn get_process_hmac_secret_decrypted_output(
         pin_uv_auth_protocol: PinUvAuthProtocol,
@@ -1413,7 +1830,7 @@ mod test {
     fn test_has_permission() {
         let mut env = TestEnv::default();
         let mut client_pin = ClientPin::<TestEnv>::new(&mut env);
-        client_pin.pin_uv_auth_token_state.set_permissions(0x7F);
+        client_pin.pin_uv_auth_token_state.set_permissions(u32::MAX);
         for permission in PinPermission::into_enum_iter() {
             assert_eq!(
                 client_pin
@@ -1598,6 +2015,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_check_pin_uv_auth() {
+        let mut env = TestEnv::default();
+        let mut client_pin = ClientPin::<TestEnv>::new(&mut env);
+        let message = [0xAA];
+        client_pin
+            .pin_uv_auth_token_state
+            .begin_using_pin_uv_auth_token(&mut env);
+        client_pin
+            .pin_uv_auth_token_state
+            .set_permissions(PinPermission::GetAssertion as u32);
+        let pin_uv_auth_token = client_pin
+            .get_pin_protocol(PinUvAuthProtocol::V2)
+            .get_pin_uv_auth_token();
+        let pin_uv_auth_param =
+            authenticate_pin_uv_auth_token(pin_uv_auth_token, &message, PinUvAuthProtocol::V2);
+
+        assert_eq!(
+            client_pin.check_pin_uv_auth(
+                PinPermission::GetAssertion,
+                &message,
+                &pin_uv_auth_param,
+                PinUvAuthProtocol::V2
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            client_pin.check_pin_uv_auth(
+                PinPermission::GetAssertion,
+                &[0xBB],
+                &pin_uv_auth_param,
+                PinUvAuthProtocol::V2
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            client_pin.check_pin_uv_auth(
+                PinPermission::MakeCredential,
+                &message,
+                &pin_uv_auth_param,
+                PinUvAuthProtocol::V2
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        // Tokens bound to a relying party can't authorize commands without one.
+        client_pin
+            .pin_uv_auth_token_state
+            .set_permissions_rp_id(Some("example.com".to_string()));
+        assert_eq!(
+            client_pin.check_pin_uv_auth(
+                PinPermission::GetAssertion,
+                &message,
+                &pin_uv_auth_param,
+                PinUvAuthProtocol::V2
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
     #[test]
     fn test_reset() {
         let mut env = TestEnv::default();
@@ -1638,9 +2114,11 @@ mod test {
         );
         let mut env = TestEnv::default();
         set_standard_pin(&mut env);
-        params.permissions = Some(0xFF);
+        params.permissions = Some(u32::MAX);
 
-        assert!(client_pin.process_command(&mut env, params).is_ok());
+        assert!(client_pin
+            .process_command(&mut env, params, DUMMY_CHANNEL)
+            .is_ok());
         for permission in PinPermission::into_enum_iter() {
             assert_eq!(
                 client_pin
@@ -1682,9 +2160,11 @@ mod test {
         );
         let mut env = TestEnv::default();
         set_standard_pin(&mut env);
-        params.permissions = Some(0xFF);
+        params.permissions = Some(u32::MAX);
 
-        assert!(client_pin.process_command(&mut env, params).is_ok());
+        assert!(client_pin
+            .process_command(&mut env, params, DUMMY_CHANNEL)
+            .is_ok());
         for permission in PinPermission::into_enum_iter() {
             assert_eq!(
                 client_pin
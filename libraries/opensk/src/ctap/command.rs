@@ -50,6 +50,7 @@ pub enum Command {
     AuthenticatorLargeBlobs(AuthenticatorLargeBlobsParameters),
     #[cfg(feature = "config_command")]
     AuthenticatorConfig(AuthenticatorConfigParameters),
+    AuthenticatorVendorIdentify,
 }
 
 impl Command {
@@ -71,6 +72,8 @@ impl Command {
     // vendor command for legacy and compatibility reasons. See
     // https://github.com/Yubico/libfido2/issues/628 for more information.
     const AUTHENTICATOR_VENDOR_CREDENTIAL_MANAGEMENT: u8 = 0x41;
+    // Blinks like CTAPHID_WINK, for transports without HID commands.
+    const AUTHENTICATOR_VENDOR_IDENTIFY: u8 = 0x45;
     const _AUTHENTICATOR_VENDOR_LAST: u8 = 0xBF;
 
     pub fn deserialize(bytes: &[u8]) -> Result<Command, Ctap2StatusCode> {
@@ -135,6 +138,10 @@ impl Command {
                     AuthenticatorConfigParameters::try_from(decoded_cbor)?,
                 ))
             }
+            Command::AUTHENTICATOR_VENDOR_IDENTIFY => {
+                // Parameters are ignored.
+                Ok(Command::AuthenticatorVendorIdentify)
+            }
             _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND),
         }
     }
@@ -311,7 +318,7 @@ pub struct AuthenticatorClientPinParameters {
     pub pin_uv_auth_param: Option<Vec<u8>>,
     pub new_pin_enc: Option<Vec<u8>>,
     pub pin_hash_enc: Option<Vec<u8>>,
-    pub permissions: Option<u8>,
+    pub permissions: Option<u32>,
     pub permissions_rp_id: Option<String>,
 }
 
@@ -339,12 +346,12 @@ impl TryFrom<cbor::Value> for AuthenticatorClientPinParameters {
         let pin_uv_auth_param = pin_uv_auth_param.map(extract_byte_string).transpose()?;
         let new_pin_enc = new_pin_enc.map(extract_byte_string).transpose()?;
         let pin_hash_enc = pin_hash_enc.map(extract_byte_string).transpose()?;
-        // We expect a bit field of 8 bits, and drop everything else.
-        // This means we ignore extensions in future versions.
+        // We expect a bit field of 32 bits, and drop everything else.
+        // This means we ignore extensions in future versions. The upper half holds vendor bits.
         let permissions = permissions
             .map(extract_unsigned)
             .transpose()?
-            .map(|p| p as u8);
+            .map(|p| p as u32);
         let permissions_rp_id = permissions_rp_id.map(extract_text_string).transpose()?;
 
         Ok(AuthenticatorClientPinParameters {
@@ -759,6 +766,13 @@ mod test {
         assert_eq!(command, Ok(Command::AuthenticatorSelection));
     }
 
+    #[test]
+    fn test_deserialize_vendor_identify() {
+        let cbor_bytes = [Command::AUTHENTICATOR_VENDOR_IDENTIFY];
+        let command = Command::deserialize(&cbor_bytes);
+        assert_eq!(command, Ok(Command::AuthenticatorVendorIdentify));
+    }
+
     #[test]
     fn test_from_cbor_large_blobs_parameters() {
         // successful get
@@ -0,0 +1,396 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Import of discoverable credentials exported by another authenticator or a passkey provider.
+//!
+//! The environment authenticates the bundle and checks user verification, then hands the CBOR
+//! plaintext to `import_credentials`:
+//!
+//! ```text
+//! {
+//!   0x01: 1,                    // format version
+//!   0x02: [{
+//!     0x01: credential ID,      // bytes, kept so that allowLists still match
+//!     0x02: RP ID,              // text
+//!     0x03: user handle,        // bytes, at most 64
+//!     0x04: algorithm,          // COSE identifier, ES256 or EdDSA if supported
+//!     0x05: private key,        // 32 bytes, the P-256 scalar or the Ed25519 seed
+//!     0x06: user name,          // optional text
+//!     0x07: user display name,  // optional text
+//!     0x08: credProtect,        // optional level
+//!   }, ...],
+//! }
+//! ```
+//!
+//! Nothing is written unless all credentials are valid and fit in the store. A credential with
+//! the RP ID and user handle of a stored one replaces it, as in makeCredential.
+
+use super::audit_log::{self, AuditEvent, Timestamp};
+use super::data_formats::{
+    extract_array, extract_byte_string, extract_integer, extract_map, extract_text_string,
+    extract_unsigned, ok_or_missing, CredentialProtectionPolicy, PublicKeyCredentialSource,
+    PublicKeyCredentialType, SignatureAlgorithm,
+};
+use super::status_code::Ctap2StatusCode;
+use super::{storage, truncate_to_char_boundary};
+use crate::api::customization::Customization;
+use crate::api::key_store::MAX_CREDENTIAL_ID_SIZE;
+use crate::api::private_key::PrivateKey;
+use crate::env::Env;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use sk_cbor as cbor;
+use sk_cbor::destructure_cbor_map;
+
+/// Version of the plaintext, checked on import.
+const FORMAT_VERSION: u64 = 1;
+
+/// Maximum length of user handles, as in makeCredential.
+const MAX_USER_HANDLE_LENGTH: usize = 64;
+
+/// A credential of the import bundle, checked but not stored yet.
+struct ImportedCredential {
+    credential_id: Vec<u8>,
+    rp_id: String,
+    user_handle: Vec<u8>,
+    private_key: PrivateKey,
+    user_name: Option<String>,
+    user_display_name: Option<String>,
+    cred_protect_policy: Option<CredentialProtectionPolicy>,
+}
+
+impl TryFrom<cbor::Value> for ImportedCredential {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => credential_id,
+                0x02 => rp_id,
+                0x03 => user_handle,
+                0x04 => algorithm,
+                0x05 => private_key,
+                0x06 => user_name,
+                0x07 => user_display_name,
+                0x08 => cred_protect_policy,
+            } = extract_map(cbor_value)?;
+        }
+        let credential_id = extract_byte_string(ok_or_missing(credential_id)?)?;
+        if credential_id.is_empty() || credential_id.len() > MAX_CREDENTIAL_ID_SIZE {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        let rp_id = extract_text_string(ok_or_missing(rp_id)?)?;
+        let user_handle = extract_byte_string(ok_or_missing(user_handle)?)?;
+        if rp_id.is_empty() || user_handle.len() > MAX_USER_HANDLE_LENGTH {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        let key_bytes = extract_byte_string(ok_or_missing(private_key)?)?;
+        let private_key =
+            match SignatureAlgorithm::from(extract_integer(ok_or_missing(algorithm)?)?) {
+                SignatureAlgorithm::Es256 => PrivateKey::new_ecdsa_from_bytes(&key_bytes),
+                #[cfg(feature = "ed25519")]
+                SignatureAlgorithm::Eddsa => PrivateKey::new_ed25519_from_bytes(&key_bytes),
+                _ => return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM),
+            }
+            .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+        let user_name = user_name.map(extract_text_string).transpose()?;
+        let user_display_name = user_display_name.map(extract_text_string).transpose()?;
+        let cred_protect_policy = cred_protect_policy
+            .map(CredentialProtectionPolicy::try_from)
+            .transpose()?;
+        Ok(ImportedCredential {
+            credential_id,
+            rp_id,
+            user_handle,
+            private_key,
+            user_name,
+            user_display_name,
+            cred_protect_policy,
+        })
+    }
+}
+
+/// Stores the credentials of an import bundle, and returns how many were imported.
+///
+/// Each credential gets at least the default credProtect level, and an audit log entry.
+pub fn import_credentials<E: Env>(
+    env: &mut E,
+    plaintext: cbor::Value,
+) -> Result<usize, Ctap2StatusCode> {
+    destructure_cbor_map! {
+        let {
+            0x01 => version,
+            0x02 => credentials,
+        } = extract_map(plaintext)?;
+    }
+    if extract_unsigned(ok_or_missing(version)?)? != FORMAT_VERSION {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let credentials = extract_array(ok_or_missing(credentials)?)?;
+    if credentials.len() > env.customization().max_supported_resident_keys() {
+        return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
+    }
+    let credentials = credentials
+        .into_iter()
+        .map(ImportedCredential::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    for credential in &credentials {
+        // Invalid scalars would only fail at the first assertion.
+        credential
+            .private_key
+            .get_pub_key::<E>()
+            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    }
+    check_quota(env, &credentials)?;
+
+    let default_cred_protect = env.customization().default_cred_protect();
+    let count = credentials.len();
+    for credential in credentials {
+        // Same as in makeCredential, credentials get at least the default level.
+        let cred_protect_policy = if credential
+            .cred_protect_policy
+            .unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
+            < default_cred_protect.unwrap_or(CredentialProtectionPolicy::UserVerificationOptional)
+        {
+            default_cred_protect
+        } else {
+            credential.cred_protect_policy
+        };
+        let credential_source = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: credential.credential_id,
+            private_key: credential.private_key,
+            rp_id: credential.rp_id,
+            user_handle: credential.user_handle,
+            user_display_name: credential
+                .user_display_name
+                .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
+            cred_protect_policy,
+            creation_order: storage::new_creation_order(env)?,
+            user_name: credential
+                .user_name
+                .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
+            user_icon: None,
+            cred_blob: None,
+            large_blob_key: None,
+            creation_time: Some(Timestamp::now(env)?),
+        };
+        storage::store_credential(env, credential_source)?;
+        audit_log::record(env, AuditEvent::CredentialImported)?;
+    }
+    Ok(count)
+}
+
+/// Checks that the credentials are unique and fit in the store.
+///
+/// Credentials replacing a stored one with the same RP ID and user handle take no space.
+fn check_quota(
+    env: &mut impl Env,
+    credentials: &[ImportedCredential],
+) -> Result<(), Ctap2StatusCode> {
+    let mut stored = Vec::new();
+    let mut iter_result = Ok(());
+    let iter = storage::iter_credentials(env, &mut iter_result)?;
+    for (_, credential) in iter {
+        stored.push((
+            credential.credential_id,
+            credential.rp_id,
+            credential.user_handle,
+        ));
+    }
+    iter_result?;
+    let mut new_count = 0;
+    for (i, credential) in credentials.iter().enumerate() {
+        if credentials[..i].iter().any(|c| {
+            c.credential_id == credential.credential_id
+                || (c.rp_id == credential.rp_id && c.user_handle == credential.user_handle)
+        }) {
+            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+        }
+        let replaced = stored.iter().find(|(_, rp_id, user_handle)| {
+            *rp_id == credential.rp_id && *user_handle == credential.user_handle
+        });
+        // An ID of another stored credential would make lookups by ID ambiguous.
+        if stored.iter().any(|(credential_id, _, _)| {
+            *credential_id == credential.credential_id
+                && replaced.map_or(true, |(replaced_id, _, _)| replaced_id != credential_id)
+        }) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_CREDENTIAL_EXCLUDED);
+        }
+        if replaced.is_none() {
+            new_count += 1;
+        }
+    }
+    if new_count > storage::remaining_credentials(env)? {
+        return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+    use alloc::vec;
+    use sk_cbor::{cbor_array_vec, cbor_map, cbor_map_options};
+
+    fn credential_cbor(credential_id: u8, rp_id: &str, user_handle: u8) -> cbor::Value {
+        cbor_map_options! {
+            0x01 => vec![credential_id; 16],
+            0x02 => rp_id,
+            0x03 => vec![user_handle],
+            0x04 => SignatureAlgorithm::Es256 as i64,
+            0x05 => vec![0x11; 32],
+            0x06 => Some("alice"),
+        }
+    }
+
+    fn bundle(credentials: Vec<cbor::Value>) -> cbor::Value {
+        cbor_map! {
+            0x01 => FORMAT_VERSION,
+            0x02 => cbor_array_vec!(credentials),
+        }
+    }
+
+    #[test]
+    fn test_import_credentials() {
+        let mut env = TestEnv::default();
+        env.customization_mut()
+            .set_default_cred_protect(Some(CredentialProtectionPolicy::UserVerificationRequired));
+        let credentials = vec![
+            credential_cbor(0x01, "example.com", 0x01),
+            credential_cbor(0x02, "example.com", 0x02),
+        ];
+        assert_eq!(import_credentials(&mut env, bundle(credentials)), Ok(2));
+
+        let credential = storage::find_credential(&mut env, "example.com", &[0x02; 16])
+            .unwrap()
+            .unwrap();
+        assert_eq!(credential.user_handle, vec![0x02]);
+        assert_eq!(credential.user_name, Some(String::from("alice")));
+        assert_eq!(
+            credential.cred_protect_policy,
+            Some(CredentialProtectionPolicy::UserVerificationRequired)
+        );
+        let events: Vec<AuditEvent> = audit_log::entries(&mut env)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                AuditEvent::CredentialImported,
+                AuditEvent::CredentialImported
+            ]
+        );
+    }
+
+    #[test]
+    fn test_import_credentials_replaces() {
+        let mut env = TestEnv::default();
+        let credentials = vec![credential_cbor(0x01, "example.com", 0x01)];
+        assert_eq!(import_credentials(&mut env, bundle(credentials)), Ok(1));
+        let credentials = vec![credential_cbor(0x02, "example.com", 0x01)];
+        assert_eq!(import_credentials(&mut env, bundle(credentials)), Ok(1));
+        assert_eq!(storage::count_credentials(&mut env), Ok(1));
+        assert!(
+            storage::find_credential(&mut env, "example.com", &[0x02; 16])
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_import_credentials_quota() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_max_supported_resident_keys(2);
+        let credentials = vec![credential_cbor(0x01, "example.com", 0x01)];
+        assert_eq!(import_credentials(&mut env, bundle(credentials)), Ok(1));
+        let credentials = vec![
+            credential_cbor(0x02, "example.com", 0x02),
+            credential_cbor(0x03, "example.com", 0x03),
+        ];
+        assert_eq!(
+            import_credentials(&mut env, bundle(credentials)),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)
+        );
+        // Nothing was written.
+        assert_eq!(storage::count_credentials(&mut env), Ok(1));
+        // Replacing takes no space.
+        let credentials = vec![
+            credential_cbor(0x02, "example.com", 0x01),
+            credential_cbor(0x03, "example.com", 0x03),
+        ];
+        assert_eq!(import_credentials(&mut env, bundle(credentials)), Ok(2));
+    }
+
+    #[test]
+    fn test_import_credentials_duplicate_id() {
+        let mut env = TestEnv::default();
+        let credentials = vec![credential_cbor(0x01, "example.com", 0x01)];
+        assert_eq!(import_credentials(&mut env, bundle(credentials)), Ok(1));
+        let credentials = vec![credential_cbor(0x01, "example.com", 0x02)];
+        assert_eq!(
+            import_credentials(&mut env, bundle(credentials)),
+            Err(Ctap2StatusCode::CTAP2_ERR_CREDENTIAL_EXCLUDED)
+        );
+        let credentials = vec![
+            credential_cbor(0x02, "example.com", 0x02),
+            credential_cbor(0x02, "example.org", 0x02),
+        ];
+        assert_eq!(
+            import_credentials(&mut env, bundle(credentials)),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        assert_eq!(storage::count_credentials(&mut env), Ok(1));
+    }
+
+    #[test]
+    fn test_import_credentials_invalid() {
+        let mut env = TestEnv::default();
+        let wrong_version = cbor_map! {
+            0x01 => FORMAT_VERSION + 1,
+            0x02 => cbor_array_vec!(vec![credential_cbor(0x01, "example.com", 0x01)]),
+        };
+        assert_eq!(
+            import_credentials(&mut env, wrong_version),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let zero_key = cbor_map! {
+            0x01 => vec![0x01; 16],
+            0x02 => "example.com",
+            0x03 => vec![0x01],
+            0x04 => SignatureAlgorithm::Es256 as i64,
+            0x05 => vec![0x00; 32],
+        };
+        assert_eq!(
+            import_credentials(&mut env, bundle(vec![zero_key])),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let unknown_algorithm = cbor_map! {
+            0x01 => vec![0x01; 16],
+            0x02 => "example.com",
+            0x03 => vec![0x01],
+            0x04 => -1,
+            0x05 => vec![0x11; 32],
+        };
+        assert_eq!(
+            import_credentials(&mut env, bundle(vec![unknown_algorithm])),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
+        );
+        assert_eq!(storage::count_credentials(&mut env), Ok(0));
+    }
+}
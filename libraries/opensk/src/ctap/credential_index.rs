@@ -0,0 +1,123 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory index of stored credentials by relying party.
+//!
+//! getAssertion without an allow list needs every credential of one relying party. Instead of
+//! decrypting the whole store on each request, the index maps a prefix of the RP ID hash to the
+//! storage keys of matching credentials. It is built lazily on the first lookup and dropped by
+//! the caller whenever credentials may have been added or removed. Lookups are only hints: the
+//! caller still loads and checks each credential.
+
+use crate::api::crypto::sha256::Sha256;
+use crate::ctap::status_code::Ctap2StatusCode;
+use crate::ctap::storage;
+use crate::env::{Env, Sha};
+use alloc::vec::Vec;
+
+/// Number of bytes of the RP ID hash kept per entry.
+const PREFIX_LENGTH: usize = 8;
+
+/// Sorted list of RP ID hash prefixes and the storage keys of their credentials.
+pub struct CredentialIndex {
+    entries: Vec<([u8; PREFIX_LENGTH], usize)>,
+}
+
+impl CredentialIndex {
+    /// Reads all stored credentials and indexes them by RP ID hash.
+    pub fn build<E: Env>(env: &mut E) -> Result<Self, Ctap2StatusCode> {
+        let mut iter_result = Ok(());
+        let iter = storage::iter_credentials(env, &mut iter_result)?;
+        let mut entries: Vec<([u8; PREFIX_LENGTH], usize)> = iter
+            .map(|(key, credential)| (prefix::<E>(credential.rp_id.as_bytes()), key))
+            .collect();
+        iter_result?;
+        entries.sort_unstable();
+        Ok(CredentialIndex { entries })
+    }
+
+    /// Returns the storage keys of credentials that may belong to the given RP ID.
+    ///
+    /// Prefix collisions can return keys of other relying parties.
+    pub fn keys<E: Env>(&self, rp_id: &str) -> Vec<usize> {
+        let prefix = prefix::<E>(rp_id.as_bytes());
+        let start = self.entries.partition_point(|(p, _)| *p < prefix);
+        self.entries[start..]
+            .iter()
+            .take_while(|(p, _)| *p == prefix)
+            .map(|&(_, key)| key)
+            .collect()
+    }
+}
+
+fn prefix<E: Env>(rp_id: &[u8]) -> [u8; PREFIX_LENGTH] {
+    let hash = Sha::<E>::digest(rp_id);
+    *array_ref!(&hash, 0, PREFIX_LENGTH)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::private_key::PrivateKey;
+    use crate::api::rng::Rng;
+    use crate::ctap::data_formats::{PublicKeyCredentialSource, PublicKeyCredentialType};
+    use crate::env::test::TestEnv;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn store_credential(env: &mut TestEnv, rp_id: &str, user_handle: Vec<u8>) -> usize {
+        let private_key = PrivateKey::new_ecdsa(env);
+        let credential = PublicKeyCredentialSource {
+            key_type: PublicKeyCredentialType::PublicKey,
+            credential_id: env.rng().gen_uniform_u8x32().to_vec(),
+            private_key,
+            rp_id: String::from(rp_id),
+            user_handle,
+            user_display_name: None,
+            cred_protect_policy: None,
+            creation_order: 0,
+            user_name: None,
+            user_icon: None,
+            cred_blob: None,
+            large_blob_key: None,
+            creation_time: None,
+        };
+        let credential_id = credential.credential_id.clone();
+        storage::store_credential(env, credential).unwrap();
+        storage::find_credential_item(env, &credential_id)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut env = TestEnv::default();
+        let index = CredentialIndex::build(&mut env).unwrap();
+        assert!(index.keys::<TestEnv>("example.com").is_empty());
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut env = TestEnv::default();
+        let key_a1 = store_credential(&mut env, "a.example.com", vec![0x01]);
+        let key_b = store_credential(&mut env, "b.example.com", vec![0x01]);
+        let key_a2 = store_credential(&mut env, "a.example.com", vec![0x02]);
+        let index = CredentialIndex::build(&mut env).unwrap();
+        let mut keys = index.keys::<TestEnv>("a.example.com");
+        keys.sort_unstable();
+        assert_eq!(keys, vec![key_a1, key_a2]);
+        assert_eq!(index.keys::<TestEnv>("b.example.com"), vec![key_b]);
+        assert!(index.keys::<TestEnv>("c.example.com").is_empty());
+    }
+}
@@ -78,6 +78,7 @@ fn enumerate_credentials_response<E: Env>(
         user_icon,
         cred_blob: _,
         large_blob_key,
+        creation_time: _,
     } = credential;
     let user = PublicKeyCredentialUserEntity {
         user_id: user_handle,
@@ -377,6 +378,7 @@ mod test {
             user_icon: Some("icon".to_string()),
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         }
     }
 
@@ -338,7 +338,7 @@ impl Ctap1Command {
             return Err(Ctap1StatusCode::SW_COND_USE_NOT_SATISFIED);
         }
         ctap_state
-            .increment_global_signature_counter(env)
+            .increment_signature_counter(env, &application)
             .map_err(|_| Ctap1StatusCode::SW_WRONG_DATA)?;
         let mut signature_data = ctap_state
             .generate_auth_data(
@@ -350,7 +350,7 @@ impl Ctap1Command {
         signature_data.extend(&challenge);
         let signature = credential_source
             .private_key
-            .sign_and_encode::<E>(&signature_data)
+            .sign_and_encode(env, &signature_data)
             .map_err(|_| Ctap1StatusCode::SW_INTERNAL_EXCEPTION)?;
 
         let mut response = signature_data[application.len()..application.len() + 5].to_vec();
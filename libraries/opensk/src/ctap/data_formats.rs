@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::audit_log::Timestamp;
 use super::status_code::Ctap2StatusCode;
 use crate::api::crypto::{ecdh, ecdsa, EC_FIELD_SIZE};
 use crate::api::private_key::PrivateKey;
@@ -558,6 +559,22 @@ pub enum CredentialProtectionPolicy {
     UserVerificationRequired = 0x03,
 }
 
+impl CredentialProtectionPolicy {
+    /// Returns whether a credential with this policy can be used.
+    ///
+    /// A credential is listed if the platform sent its ID in an allowList or excludeList.
+    /// Credentials without a policy behave like `UserVerificationOptional`.
+    pub fn allows(policy: Option<Self>, has_uv: bool, is_listed: bool) -> bool {
+        match policy.unwrap_or(CredentialProtectionPolicy::UserVerificationOptional) {
+            CredentialProtectionPolicy::UserVerificationOptional => true,
+            CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList => {
+                has_uv || is_listed
+            }
+            CredentialProtectionPolicy::UserVerificationRequired => has_uv,
+        }
+    }
+}
+
 impl From<CredentialProtectionPolicy> for cbor::Value {
     fn from(policy: CredentialProtectionPolicy) -> Self {
         (policy as i64).into()
@@ -596,6 +613,7 @@ pub struct PublicKeyCredentialSource {
     pub user_icon: Option<String>,
     pub cred_blob: Option<Vec<u8>>,
     pub large_blob_key: Option<Vec<u8>>,
+    pub creation_time: Option<Timestamp>,
 }
 
 // We serialize credentials for the persistent storage using CBOR maps. Each field of a credential
@@ -612,6 +630,7 @@ enum PublicKeyCredentialSourceField {
     CredBlob = 10,
     LargeBlobKey = 11,
     PrivateKey = 12,
+    CreationTime = 13,
     // When a field is removed, its tag should be reserved and not used for new fields. We document
     // those reserved tags below.
     // Reserved tags:
@@ -628,9 +647,7 @@ impl From<PublicKeyCredentialSourceField> for cbor::Value {
 impl PublicKeyCredentialSource {
     // Relying parties do not need to provide the credential ID in an allow_list if true.
     pub fn is_discoverable(&self) -> bool {
-        self.cred_protect_policy.is_none()
-            || self.cred_protect_policy
-                == Some(CredentialProtectionPolicy::UserVerificationOptional)
+        CredentialProtectionPolicy::allows(self.cred_protect_policy, false, false)
     }
 
     pub fn to_cbor<E: Env>(
@@ -650,6 +667,7 @@ impl PublicKeyCredentialSource {
             PublicKeyCredentialSourceField::CredBlob => self.cred_blob,
             PublicKeyCredentialSourceField::LargeBlobKey => self.large_blob_key,
             PublicKeyCredentialSourceField::PrivateKey => self.private_key.to_cbor::<E>(rng, wrap_key)?,
+            PublicKeyCredentialSourceField::CreationTime => self.creation_time,
         })
     }
 
@@ -670,6 +688,7 @@ impl PublicKeyCredentialSource {
                 PublicKeyCredentialSourceField::CredBlob => cred_blob,
                 PublicKeyCredentialSourceField::LargeBlobKey => large_blob_key,
                 PublicKeyCredentialSourceField::PrivateKey => private_key,
+                PublicKeyCredentialSourceField::CreationTime => creation_time,
             } = extract_map(cbor_value)?;
         }
 
@@ -686,6 +705,7 @@ impl PublicKeyCredentialSource {
         let cred_blob = cred_blob.map(extract_byte_string).transpose()?;
         let large_blob_key = large_blob_key.map(extract_byte_string).transpose()?;
         let private_key = PrivateKey::from_cbor::<E>(wrap_key, ok_or_missing(private_key)?)?;
+        let creation_time = creation_time.map(Timestamp::try_from).transpose()?;
 
         // We don't return whether there were unknown fields in the CBOR value. This means that
         // deserialization is not injective. In particular deserialization is only an inverse of
@@ -710,6 +730,7 @@ impl PublicKeyCredentialSource {
             user_icon,
             cred_blob,
             large_blob_key,
+            creation_time,
         })
     }
 }
@@ -1169,6 +1190,11 @@ pub fn extract_byte_string(cbor_value: cbor::Value) -> Result<Vec<u8>, Ctap2Stat
     ok_or_cbor_type(cbor_value.extract_byte_string())
 }
 
+/// Borrows the byte string instead of moving it out of the value.
+pub fn extract_byte_string_ref(cbor_value: &cbor::Value) -> Result<&[u8], Ctap2StatusCode> {
+    ok_or_cbor_type(cbor_value.as_byte_string())
+}
+
 pub fn extract_text_string(cbor_value: cbor::Value) -> Result<String, Ctap2StatusCode> {
     ok_or_cbor_type(cbor_value.extract_text_string())
 }
@@ -1177,6 +1203,11 @@ pub fn extract_array(cbor_value: cbor::Value) -> Result<Vec<cbor::Value>, Ctap2S
     ok_or_cbor_type(cbor_value.extract_array())
 }
 
+/// Borrows the array instead of moving it out of the value.
+pub fn extract_array_ref(cbor_value: &cbor::Value) -> Result<&[cbor::Value], Ctap2StatusCode> {
+    ok_or_cbor_type(cbor_value.as_array())
+}
+
 pub fn extract_map(
     cbor_value: cbor::Value,
 ) -> Result<Vec<(cbor::Value, cbor::Value)>, Ctap2StatusCode> {
@@ -1365,6 +1396,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_extract_byte_string_ref() {
+        assert_eq!(
+            extract_byte_string_ref(&cbor_int!(123)),
+            Err(CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
+        assert_eq!(
+            extract_byte_string_ref(&cbor_text!("foo")),
+            Err(CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
+        assert_eq!(
+            extract_byte_string_ref(&cbor_bytes_lit!(b"bar")),
+            Ok(&b"bar"[..])
+        );
+    }
+
+    #[test]
+    fn test_extract_array_ref() {
+        assert_eq!(
+            extract_array_ref(&cbor_bytes_lit!(b"bar")),
+            Err(CTAP2_ERR_CBOR_UNEXPECTED_TYPE)
+        );
+        assert_eq!(
+            extract_array_ref(&cbor_array![123]),
+            Ok(&[cbor_int!(123)][..])
+        );
+    }
+
     #[test]
     fn test_extract_array() {
         assert_eq!(
@@ -1580,6 +1639,34 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_cred_protection_policy_allows() {
+        for (has_uv, is_listed) in [(false, false), (false, true), (true, false), (true, true)] {
+            assert!(CredentialProtectionPolicy::allows(None, has_uv, is_listed));
+            assert!(CredentialProtectionPolicy::allows(
+                Some(CredentialProtectionPolicy::UserVerificationOptional),
+                has_uv,
+                is_listed
+            ));
+            assert_eq!(
+                CredentialProtectionPolicy::allows(
+                    Some(CredentialProtectionPolicy::UserVerificationOptionalWithCredentialIdList),
+                    has_uv,
+                    is_listed
+                ),
+                has_uv || is_listed
+            );
+            assert_eq!(
+                CredentialProtectionPolicy::allows(
+                    Some(CredentialProtectionPolicy::UserVerificationRequired),
+                    has_uv,
+                    is_listed
+                ),
+                has_uv
+            );
+        }
+    }
+
     #[test]
     fn test_from_into_cred_protection_policy() {
         let cbor_policy: cbor::Value = CredentialProtectionPolicy::UserVerificationOptional.into();
@@ -2086,6 +2173,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
 
         let cbor_value = credential
@@ -2172,6 +2260,23 @@ mod test {
             ..credential
         };
 
+        let cbor_value = credential
+            .clone()
+            .to_cbor::<TestEnv>(env.rng(), &wrap_key)
+            .unwrap();
+        assert_eq!(
+            PublicKeyCredentialSource::from_cbor::<TestEnv>(&wrap_key, cbor_value),
+            Ok(credential.clone())
+        );
+
+        let credential = PublicKeyCredentialSource {
+            creation_time: Some(Timestamp {
+                boot_count: 2,
+                uptime_ms: 1000,
+            }),
+            ..credential
+        };
+
         let cbor_value = credential
             .clone()
             .to_cbor::<TestEnv>(env.rng(), &wrap_key)
@@ -37,6 +37,10 @@ use enum_iterator::IntoEnumIterator;
 const CHANNEL_RESERVED: ChannelID = [0, 0, 0, 0];
 const CHANNEL_BROADCAST: ChannelID = [0xFF, 0xFF, 0xFF, 0xFF];
 const PACKET_TYPE_MASK: u8 = 0x80;
+// After this many malformed packets or failed replies, a channel is invalidated.
+const MAX_CHANNEL_ERRORS: usize = 8;
+// Only the most recent misbehaving channels are remembered.
+const MAX_TRACKED_CHANNELS: usize = 8;
 
 // See section 11.2.9.1.3. CTAPHID_INIT (0x06).
 const PROTOCOL_VERSION: u8 = 2;
@@ -142,13 +146,23 @@ pub struct Message {
 }
 
 /// A keepalive packet reports the reason why a command does not finish.
-#[allow(dead_code)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum KeepaliveStatus {
     Processing = 0x01,
     UpNeeded = 0x02,
 }
 
+/// How to handle a packet that arrives while a command is processed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConcurrentPacket {
+    /// The client cancelled the command in progress.
+    Cancel,
+    /// The packet is answered with this message.
+    Reply(Message),
+    /// Nothing to do.
+    Ignore,
+}
+
 /// Holds all state for receiving and sending HID packets.
 ///
 /// This includes
@@ -189,6 +203,8 @@ pub struct CtapHid<E: Env> {
     capabilities: u8,
     locked_cid: Option<ChannelID>,
     lock_timer: <E::Clock as Clock>::Timer,
+    // Error counters of misbehaving channels, oldest first.
+    channel_errors: Vec<(ChannelID, usize)>,
 }
 
 impl<E: Env> CtapHid<E> {
@@ -207,6 +223,7 @@ impl<E: Env> CtapHid<E> {
             capabilities,
             locked_cid: None,
             lock_timer: <E::Clock as Clock>::Timer::default(),
+            channel_errors: Vec::new(),
         }
     }
 
@@ -222,6 +239,11 @@ impl<E: Env> CtapHid<E> {
         self.locked_channel(env).is_some()
     }
 
+    /// Returns whether the continuation packets of a message are still expected.
+    pub fn is_receiving(&self, env: &mut E) -> bool {
+        self.assembler.is_receiving(env)
+    }
+
     /// Parses a packet, and preprocesses some messages and errors.
     ///
     /// The preprocessed commands are:
@@ -264,6 +286,11 @@ impl<E: Env> CtapHid<E> {
                 None
             }
             Err((cid, error)) => {
+                if matches!(error, CtapHidError::InvalidSeq | CtapHidError::InvalidLen)
+                    && self.is_allocated_channel(cid)
+                {
+                    self.add_channel_errors(cid, 1);
+                }
                 if matches!(error, CtapHidError::UnexpectedContinuation) {
                     None
                 } else if !self.is_allocated_channel(cid) {
@@ -306,21 +333,12 @@ impl<E: Env> CtapHid<E> {
                     // Sync the channel and discard the current transaction.
                     cid
                 };
-
-                let mut payload = vec![0; 17];
-                payload[..8].copy_from_slice(&message.payload);
-                payload[8..12].copy_from_slice(&new_cid);
-                payload[12] = PROTOCOL_VERSION;
-                payload[13] = DEVICE_VERSION_MAJOR;
-                payload[14] = DEVICE_VERSION_MINOR;
-                payload[15] = DEVICE_VERSION_BUILD;
-                payload[16] = self.capabilities;
-
-                Some(Message {
+                Some(Self::init_response(
                     cid,
-                    cmd: CtapHidCommand::Init,
-                    payload,
-                })
+                    new_cid,
+                    &message.payload,
+                    self.capabilities,
+                ))
             }
             // CTAP 2.1 from 2021-06-15, section 11.2.9.1.4.
             CtapHidCommand::Ping => {
@@ -361,6 +379,71 @@ impl<E: Env> CtapHid<E> {
         }
     }
 
+    fn init_response(
+        cid: ChannelID,
+        new_cid: ChannelID,
+        nonce: &[u8],
+        capabilities: u8,
+    ) -> Message {
+        let mut payload = vec![0; 17];
+        payload[..8].copy_from_slice(nonce);
+        payload[8..12].copy_from_slice(&new_cid);
+        payload[12] = PROTOCOL_VERSION;
+        payload[13] = DEVICE_VERSION_MAJOR;
+        payload[14] = DEVICE_VERSION_MINOR;
+        payload[15] = DEVICE_VERSION_BUILD;
+        payload[16] = capabilities;
+
+        Message {
+            cid,
+            cmd: CtapHidCommand::Init,
+            payload,
+        }
+    }
+
+    /// Handles a packet that arrives while a command is processed on `busy_cid`.
+    ///
+    /// Packets are not reassembled, so only single packet commands are answered:
+    /// - CANCEL on the busy channel aborts the command, and is ignored on others.
+    /// - INIT on another channel synchronizes it, as if no command was in progress.
+    /// - Other commands on other channels, including channel allocation, are busy.
+    ///
+    /// Pass None as `busy_cid` for packets from another transport.
+    pub fn process_concurrent_packet(
+        packet: &HidPacket,
+        busy_cid: Option<ChannelID>,
+        capabilities: u8,
+    ) -> ConcurrentPacket {
+        let (cid, processed_packet) = Self::process_single_packet(packet);
+        let (cmd, len, data) = match processed_packet {
+            ProcessedPacket::InitPacket { cmd, len, data } => (cmd, len, data),
+            ProcessedPacket::ContinuationPacket { .. } => return ConcurrentPacket::Ignore,
+        };
+        if cmd == CtapHidCommand::Cancel as u8 {
+            // Authenticators MUST NOT reply to this message.
+            return if Some(cid) == busy_cid {
+                ConcurrentPacket::Cancel
+            } else {
+                ConcurrentPacket::Ignore
+            };
+        }
+        if Some(cid) == busy_cid {
+            return ConcurrentPacket::Ignore;
+        }
+        if cmd == CtapHidCommand::Init as u8 && cid != CHANNEL_BROADCAST {
+            if len != 8 {
+                return ConcurrentPacket::Reply(Self::error_message(cid, CtapHidError::InvalidLen));
+            }
+            return ConcurrentPacket::Reply(Self::init_response(
+                cid,
+                cid,
+                &data[..8],
+                capabilities,
+            ));
+        }
+        ConcurrentPacket::Reply(Self::error_message(cid, CtapHidError::ChannelBusy))
+    }
+
     fn has_valid_channel(&self, message: &Message) -> bool {
         match message.cid {
             // Only INIT commands use the broadcast channel.
@@ -371,7 +454,41 @@ impl<E: Env> CtapHid<E> {
     }
 
     fn is_allocated_channel(&self, cid: ChannelID) -> bool {
-        cid != CHANNEL_RESERVED && u32::from_be_bytes(cid) as usize <= self.allocated_cids
+        cid != CHANNEL_RESERVED
+            && u32::from_be_bytes(cid) as usize <= self.allocated_cids
+            && self.error_count(cid) < MAX_CHANNEL_ERRORS
+    }
+
+    /// Returns the number of malformed packets received on a channel.
+    pub fn error_count(&self, cid: ChannelID) -> usize {
+        self.channel_errors
+            .iter()
+            .find(|(errored_cid, _)| *errored_cid == cid)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    fn add_channel_errors(&mut self, cid: ChannelID, count: usize) {
+        if let Some(index) = self.channel_errors.iter().position(|(c, _)| *c == cid) {
+            self.channel_errors[index].1 += count;
+            return;
+        }
+        if self.channel_errors.len() >= MAX_TRACKED_CHANNELS {
+            self.channel_errors.remove(0);
+        }
+        self.channel_errors.push((cid, count));
+    }
+
+    /// Invalidates a channel, e.g. after its reply could not be delivered.
+    ///
+    /// Clients have to allocate a new channel with INIT on the broadcast channel.
+    pub fn invalidate_channel(&mut self, cid: ChannelID) {
+        if !self.is_allocated_channel(cid) {
+            return;
+        }
+        if self.locked_cid == Some(cid) {
+            self.locked_cid = None;
+        }
+        self.add_channel_errors(cid, MAX_CHANNEL_ERRORS);
     }
 
     pub fn error_message(cid: ChannelID, error_code: CtapHidError) -> Message {
@@ -449,6 +566,7 @@ impl<E: Env> CtapHid<E> {
                 capabilities: 0x0D,
                 locked_cid: None,
                 lock_timer: <E::Clock as Clock>::Timer::default(),
+                channel_errors: Vec::new(),
             },
             [0x00, 0x00, 0x00, 0x01],
         )
@@ -819,4 +937,102 @@ mod test {
         );
         assert!(!ctap_hid.has_channel_lock(&mut env));
     }
+
+    #[test]
+    fn test_malformed_packets_invalidate_channel() {
+        let mut env = TestEnv::default();
+        let (mut ctap_hid, cid) = CtapHid::<TestEnv>::new_initialized();
+
+        let mut long_packet = [0x00; 64];
+        long_packet[..4].copy_from_slice(&cid);
+        long_packet[4..7].copy_from_slice(&[0x81, 0xFF, 0xFF]);
+        for count in 1..MAX_CHANNEL_ERRORS {
+            assert_eq!(
+                ctap_hid.parse_packet(&mut env, &long_packet, false),
+                Some(CtapHid::<TestEnv>::error_message(
+                    cid,
+                    CtapHidError::InvalidLen
+                ))
+            );
+            assert_eq!(ctap_hid.error_count(cid), count);
+        }
+        assert_eq!(
+            ctap_hid.parse_packet(&mut env, &long_packet, false),
+            Some(CtapHid::<TestEnv>::error_message(
+                cid,
+                CtapHidError::InvalidChannel
+            ))
+        );
+    }
+
+    #[test]
+    fn test_invalidate_channel() {
+        let mut env = TestEnv::default();
+        let (mut ctap_hid, cid) = CtapHid::<TestEnv>::new_initialized();
+
+        let mut lock_packet = [0x00; 64];
+        lock_packet[..4].copy_from_slice(&cid);
+        lock_packet[4..8].copy_from_slice(&[0x84, 0x00, 0x01, 0x01]);
+        ctap_hid.parse_packet(&mut env, &lock_packet, false);
+        assert!(ctap_hid.has_channel_lock(&mut env));
+
+        ctap_hid.invalidate_channel(cid);
+        assert!(!ctap_hid.has_channel_lock(&mut env));
+        let mut ping_packet = [0x00; 64];
+        ping_packet[..4].copy_from_slice(&cid);
+        ping_packet[4..9].copy_from_slice(&[0x81, 0x00, 0x02, 0x99, 0x99]);
+        assert_eq!(
+            ctap_hid.parse_packet(&mut env, &ping_packet, false),
+            Some(CtapHid::<TestEnv>::error_message(
+                cid,
+                CtapHidError::InvalidChannel
+            ))
+        );
+    }
+
+    #[test]
+    fn test_process_concurrent_packet() {
+        let busy_cid = [0x12, 0x34, 0x56, 0x78];
+        let other_cid = [0x12, 0x34, 0x56, 0x79];
+
+        let mut cancel_packet = [0x00; 64];
+        cancel_packet[..4].copy_from_slice(&busy_cid);
+        cancel_packet[4] = 0x91;
+        assert_eq!(
+            CtapHid::<TestEnv>::process_concurrent_packet(&cancel_packet, Some(busy_cid), 0x0D),
+            ConcurrentPacket::Cancel
+        );
+        assert_eq!(
+            CtapHid::<TestEnv>::process_concurrent_packet(&cancel_packet, None, 0x0D),
+            ConcurrentPacket::Ignore
+        );
+
+        let mut init_packet = [0x00; 64];
+        init_packet[..4].copy_from_slice(&other_cid);
+        init_packet[4..15].copy_from_slice(&[
+            0x86, 0x00, 0x08, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0,
+        ]);
+        let reply =
+            CtapHid::<TestEnv>::process_concurrent_packet(&init_packet, Some(busy_cid), 0x0D);
+        match reply {
+            ConcurrentPacket::Reply(message) => {
+                assert_eq!(message.cmd, CtapHidCommand::Init);
+                assert_eq!(message.payload[..8], init_packet[7..15]);
+                assert_eq!(message.payload[8..12], other_cid);
+                assert_eq!(message.payload[16], 0x0D);
+            }
+            _ => panic!("Expected an INIT response"),
+        }
+
+        let mut ping_packet = [0x00; 64];
+        ping_packet[..4].copy_from_slice(&other_cid);
+        ping_packet[4..9].copy_from_slice(&[0x81, 0x00, 0x02, 0x99, 0x99]);
+        assert_eq!(
+            CtapHid::<TestEnv>::process_concurrent_packet(&ping_packet, Some(busy_cid), 0x0D),
+            ConcurrentPacket::Reply(CtapHid::<TestEnv>::error_message(
+                other_cid,
+                CtapHidError::ChannelBusy
+            ))
+        );
+    }
 }
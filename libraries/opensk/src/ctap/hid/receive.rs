@@ -69,6 +69,11 @@ impl<E: Env> MessageAssembler<E> {
         self.payload.clear();
     }
 
+    /// Returns whether a message is partially received, and its next packet is still expected.
+    pub fn is_receiving(&self, env: &mut E) -> bool {
+        !self.idle && !env.clock().is_elapsed(&self.timer)
+    }
+
     // Returns:
     // - An Ok() result if the packet was parsed correctly. This contains either Some(Vec<u8>) if a
     // full message was assembled after this packet, or None if more packets are needed to fill the
@@ -364,6 +369,52 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_max_msg_size() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_max_msg_size(1024);
+        let mut assembler = MessageAssembler::default();
+        assert_eq!(
+            assembler.parse_packet(
+                &mut env,
+                &zero_extend(&[0x12, 0x34, 0x56, 0x78, 0x90, 0x04, 0x01]),
+                None,
+            ),
+            Err(([0x12, 0x34, 0x56, 0x78], CtapHidError::InvalidLen))
+        );
+        assert_eq!(
+            assembler.parse_packet(
+                &mut env,
+                &zero_extend(&[0x12, 0x34, 0x56, 0x78, 0x90, 0x04, 0x00]),
+                None,
+            ),
+            Ok(None)
+        );
+        // 57 bytes in the initialization packet and 59 in each continuation packet.
+        for seq in 0..16 {
+            assert_eq!(
+                assembler.parse_packet(
+                    &mut env,
+                    &zero_extend(&[0x12, 0x34, 0x56, 0x78, seq]),
+                    None
+                ),
+                Ok(None)
+            );
+        }
+        assert_eq!(
+            assembler.parse_packet(
+                &mut env,
+                &zero_extend(&[0x12, 0x34, 0x56, 0x78, 0x10]),
+                None
+            ),
+            Ok(Some(Message {
+                cid: [0x12, 0x34, 0x56, 0x78],
+                cmd: CtapHidCommand::Cbor,
+                payload: vec![0x00; 1024]
+            }))
+        );
+    }
+
     #[test]
     fn test_multiple_messages() {
         let mut env = TestEnv::default();
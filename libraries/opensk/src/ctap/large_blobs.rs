@@ -17,6 +17,7 @@ use super::command::AuthenticatorLargeBlobsParameters;
 use super::response::{AuthenticatorLargeBlobsResponse, ResponseData};
 use super::status_code::Ctap2StatusCode;
 use crate::api::crypto::sha256::Sha256;
+use crate::api::crypto::HASH_SIZE;
 use crate::api::customization::Customization;
 use crate::ctap::storage;
 use crate::env::{Env, Sha};
@@ -27,24 +28,27 @@ use byteorder::{ByteOrder, LittleEndian};
 /// The length of the truncated hash that is appended to the large blob data.
 const TRUNCATED_HASH_LEN: usize = 16;
 
-pub struct LargeBlobs {
+pub struct LargeBlobs<E: Env> {
     buffer: Vec<u8>,
     expected_length: usize,
     expected_next_offset: usize,
+    /// Hashes fragments as they arrive, so that the buffer is not hashed again on commit.
+    hasher: Sha<E>,
 }
 
 /// Implements the logic for the AuthenticatorLargeBlobs command and keeps its state.
-impl LargeBlobs {
-    pub fn new() -> LargeBlobs {
+impl<E: Env> LargeBlobs<E> {
+    pub fn new() -> LargeBlobs<E> {
         LargeBlobs {
             buffer: Vec::new(),
             expected_length: 0,
             expected_next_offset: 0,
+            hasher: Sha::<E>::new(),
         }
     }
 
     /// Process the large blob command.
-    pub fn process_command<E: Env>(
+    pub fn process_command(
         &mut self,
         env: &mut E,
         client_pin: &mut ClientPin<E>,
@@ -109,17 +113,21 @@ impl LargeBlobs {
             }
             if offset == 0 {
                 self.buffer = Vec::with_capacity(self.expected_length);
+                self.hasher = Sha::<E>::new();
             }
+            // Must be a positive number.
+            let buffer_hash_index = self.expected_length - TRUNCATED_HASH_LEN;
+            // Only hashes the part of the fragment before the truncated hash.
+            let hashed_len = core::cmp::min(buffer_hash_index.saturating_sub(offset), set.len());
+            self.hasher.update(&set[..hashed_len]);
             self.buffer.append(&mut set);
             self.expected_next_offset = self.buffer.len();
             if self.expected_next_offset == self.expected_length {
                 self.expected_length = 0;
                 self.expected_next_offset = 0;
-                // Must be a positive number.
-                let buffer_hash_index = self.buffer.len() - TRUNCATED_HASH_LEN;
-                if Sha::<E>::digest(&self.buffer[..buffer_hash_index])[..TRUNCATED_HASH_LEN]
-                    != self.buffer[buffer_hash_index..]
-                {
+                let mut computed_hash = [0; HASH_SIZE];
+                core::mem::replace(&mut self.hasher, Sha::<E>::new()).finalize(&mut computed_hash);
+                if computed_hash[..TRUNCATED_HASH_LEN] != self.buffer[buffer_hash_index..] {
                     self.buffer = Vec::new();
                     return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
                 }
@@ -245,6 +253,51 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_process_command_commit_hash_across_fragments() {
+        let mut env = TestEnv::default();
+        let key_agreement_key = EcdhSk::<TestEnv>::random(env.rng());
+        let pin_uv_auth_token = [0x55; 32];
+        let mut client_pin = ClientPin::<TestEnv>::new_test(
+            &mut env,
+            key_agreement_key,
+            pin_uv_auth_token,
+            PinUvAuthProtocol::V1,
+        );
+        let mut large_blobs = LargeBlobs::new();
+
+        const BLOB_LEN: usize = 200;
+        const DATA_LEN: usize = BLOB_LEN - TRUNCATED_HASH_LEN;
+        // The truncated hash starts in the second fragment and ends in the third.
+        const FRAGMENT_ENDS: [usize; 3] = [DATA_LEN - 10, DATA_LEN + 6, BLOB_LEN];
+        let mut large_blob = vec![0x1B; DATA_LEN];
+        large_blob
+            .extend_from_slice(&Sha::<TestEnv>::digest(&large_blob[..])[..TRUNCATED_HASH_LEN]);
+
+        let mut offset = 0;
+        for end in FRAGMENT_ENDS {
+            let large_blobs_params = AuthenticatorLargeBlobsParameters {
+                get: None,
+                set: Some(large_blob[offset..end].to_vec()),
+                offset,
+                length: if offset == 0 { Some(BLOB_LEN) } else { None },
+                pin_uv_auth_param: None,
+                pin_uv_auth_protocol: None,
+            };
+            let large_blobs_response =
+                large_blobs.process_command(&mut env, &mut client_pin, large_blobs_params);
+            assert_eq!(
+                large_blobs_response,
+                Ok(ResponseData::AuthenticatorLargeBlobs(None))
+            );
+            offset = end;
+        }
+        assert_eq!(
+            storage::get_large_blob_array(&mut env, 0, BLOB_LEN),
+            Ok(large_blob)
+        );
+    }
+
     #[test]
     fn test_process_command_commit_unexpected_offset() {
         let mut env = TestEnv::default();
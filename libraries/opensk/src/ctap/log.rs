@@ -0,0 +1,218 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Trace of recent events in RAM, for debugging devices without a debugger attached.
+//!
+//! Messages are written with the `log_ctap` macro, which tags them with the calling module. The
+//! environment keeps the lines in a ring buffer, and may dump it through a vendor command. Unlike
+//! the audit log, nothing is persisted, and a reboot clears the trace.
+
+use crate::env::Env;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+/// Size of the ring buffer, older lines are overwritten.
+pub const LOG_BUFFER_SIZE: usize = 2048;
+
+/// Importance of a message, from most to least important.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    fn tag(self) -> char {
+        match self {
+            Level::Error => 'E',
+            Level::Warn => 'W',
+            Level::Info => 'I',
+            Level::Debug => 'D',
+            Level::Trace => 'T',
+        }
+    }
+}
+
+impl TryFrom<u64> for Level {
+    type Error = ();
+
+    fn try_from(level: u64) -> Result<Self, ()> {
+        match level {
+            1 => Ok(Level::Error),
+            2 => Ok(Level::Warn),
+            3 => Ok(Level::Info),
+            4 => Ok(Level::Debug),
+            5 => Ok(Level::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Ring buffer of log lines, formatted as `<level tag> <module>: <message>`.
+pub struct LogBuffer {
+    bytes: [u8; LOG_BUFFER_SIZE],
+    /// Position of the next write.
+    end: usize,
+    /// Whether old lines were overwritten.
+    wrapped: bool,
+    max_level: Level,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        LogBuffer {
+            bytes: [0; LOG_BUFFER_SIZE],
+            end: 0,
+            wrapped: false,
+            max_level: Level::Info,
+        }
+    }
+}
+
+impl LogBuffer {
+    /// Returns the least important level that is recorded.
+    pub fn max_level(&self) -> Level {
+        self.max_level
+    }
+
+    /// Records only messages at least as important as `level`.
+    pub fn set_max_level(&mut self, level: Level) {
+        self.max_level = level;
+    }
+
+    /// Appends a line, unless its level is filtered out.
+    ///
+    /// Filtered messages are not formatted, so verbose levels cost little while disabled.
+    pub fn record(&mut self, level: Level, target: &str, args: fmt::Arguments) {
+        if level > self.max_level {
+            return;
+        }
+        // Writing to the ring buffer never fails.
+        let _ = fmt::write(self, format_args!("{} {}: {}\n", level.tag(), target, args));
+    }
+
+    /// Returns the recorded lines, oldest first.
+    ///
+    /// After overwriting, the oldest line is usually truncated and is skipped.
+    pub fn contents(&self) -> Vec<u8> {
+        if !self.wrapped {
+            return self.bytes[..self.end].to_vec();
+        }
+        let mut contents = self.bytes[self.end..].to_vec();
+        contents.extend_from_slice(&self.bytes[..self.end]);
+        match contents.iter().position(|&byte| byte == b'\n') {
+            Some(first_end) => contents.split_off(first_end + 1),
+            None => contents,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.end = 0;
+        self.wrapped = false;
+    }
+}
+
+impl fmt::Write for LogBuffer {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        for &byte in string.as_bytes() {
+            self.bytes[self.end] = byte;
+            self.end += 1;
+            if self.end == LOG_BUFFER_SIZE {
+                self.end = 0;
+                self.wrapped = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records a message in the log buffer of the environment, if it has one.
+///
+/// With the `debug_ctap` feature, messages are also written to the debug output, whatever their
+/// level. Use the `log_ctap` macro instead of calling this function.
+pub fn log(env: &mut impl Env, level: Level, target: &str, args: fmt::Arguments) {
+    #[cfg(feature = "debug_ctap")]
+    {
+        use core::fmt::Write;
+        let _ = writeln!(env.write(), "{} {}: {}", level.tag(), target, args);
+    }
+    if let Some(log_buffer) = env.log_buffer() {
+        log_buffer.record(level, target, args);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+
+    fn record(log_buffer: &mut LogBuffer, level: Level, message: &str) {
+        log_buffer.record(level, "opensk::test", format_args!("{}", message));
+    }
+
+    #[test]
+    fn test_record() {
+        let mut log_buffer = LogBuffer::default();
+        record(&mut log_buffer, Level::Warn, "first");
+        record(&mut log_buffer, Level::Debug, "filtered");
+        log_buffer.record(Level::Info, "opensk::bbs", format_args!("{} messages", 3));
+        assert_eq!(
+            log_buffer.contents(),
+            b"W opensk::test: first\nI opensk::bbs: 3 messages\n".to_vec()
+        );
+
+        log_buffer.set_max_level(Level::Trace);
+        record(&mut log_buffer, Level::Trace, "verbose");
+        assert!(log_buffer
+            .contents()
+            .ends_with(b"T opensk::test: verbose\n"));
+
+        log_buffer.clear();
+        assert!(log_buffer.contents().is_empty());
+    }
+
+    #[test]
+    fn test_wrap_skips_partial_line() {
+        let mut log_buffer = LogBuffer::default();
+        for i in 0..1000 {
+            log_buffer.record(Level::Info, "t", format_args!("{:04}", i));
+        }
+        let contents = log_buffer.contents();
+        assert!(contents.len() <= LOG_BUFFER_SIZE);
+        assert!(contents.starts_with(b"I t: "));
+        assert!(contents.ends_with(b"I t: 0999\n"));
+    }
+
+    #[test]
+    fn test_log_macro() {
+        let mut env = TestEnv::default();
+        log_ctap!(&mut env, Level::Error, "failed with {}", 0x27);
+        let contents = env.log_buffer().unwrap().contents();
+        assert_eq!(
+            contents,
+            b"E opensk::ctap::log::test: failed with 39\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_level_try_from() {
+        assert_eq!(Level::try_from(4), Ok(Level::Debug));
+        assert!(Level::try_from(0).is_err());
+        assert!(Level::try_from(6).is_err());
+    }
+}
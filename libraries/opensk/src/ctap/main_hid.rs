@@ -13,18 +13,15 @@
 // limitations under the License.
 
 use crate::api::clock::Clock;
+use crate::api::customization::Customization;
 #[cfg(feature = "with_ctap1")]
 use crate::ctap::ctap1;
-#[cfg(feature = "with_ctap1")]
-use crate::ctap::hid::ChannelID;
 use crate::ctap::hid::{
-    CtapHid, CtapHidCommand, CtapHidError, HidPacket, HidPacketIterator, Message,
+    ChannelID, CtapHid, CtapHidCommand, CtapHidError, HidPacket, HidPacketIterator, Message,
 };
 use crate::ctap::{Channel, CtapState};
 use crate::env::Env;
 
-const WINK_TIMEOUT_DURATION_MS: usize = 5000;
-
 /// Implements the standard CTAP command processing for HID.
 pub struct MainHid<E: Env> {
     hid: CtapHid<E>,
@@ -34,14 +31,7 @@ pub struct MainHid<E: Env> {
 impl<E: Env> Default for MainHid<E> {
     /// Instantiates a HID handler for CTAP1, CTAP2 and Wink.
     fn default() -> Self {
-        #[cfg(feature = "with_ctap1")]
-        let capabilities = CtapHid::<E>::CAPABILITY_WINK | CtapHid::<E>::CAPABILITY_CBOR;
-        #[cfg(not(feature = "with_ctap1"))]
-        let capabilities = CtapHid::<E>::CAPABILITY_WINK
-            | CtapHid::<E>::CAPABILITY_CBOR
-            | CtapHid::<E>::CAPABILITY_NMSG;
-
-        let hid = CtapHid::new(capabilities);
+        let hid = CtapHid::new(Self::CAPABILITIES);
         let wink_permission = <E::Clock as Clock>::Timer::default();
         MainHid {
             hid,
@@ -51,6 +41,14 @@ impl<E: Env> Default for MainHid<E> {
 }
 
 impl<E: Env> MainHid<E> {
+    /// Capabilities reported to the client in Init.
+    #[cfg(feature = "with_ctap1")]
+    pub const CAPABILITIES: u8 = CtapHid::<E>::CAPABILITY_WINK | CtapHid::<E>::CAPABILITY_CBOR;
+    #[cfg(not(feature = "with_ctap1"))]
+    pub const CAPABILITIES: u8 = CtapHid::<E>::CAPABILITY_WINK
+        | CtapHid::<E>::CAPABILITY_CBOR
+        | CtapHid::<E>::CAPABILITY_NMSG;
+
     /// Processes an incoming USB HID packet, and returns an iterator for all outgoing packets.
     pub fn process_hid_packet(
         &mut self,
@@ -108,7 +106,8 @@ impl<E: Env> MainHid<E> {
             // CTAP 2.1 from 2021-06-15, section 11.2.9.2.1.
             CtapHidCommand::Wink => {
                 if message.payload.is_empty() {
-                    self.wink_permission = env.clock().make_timer(WINK_TIMEOUT_DURATION_MS);
+                    let wink_duration_ms = env.customization().wink_duration_ms();
+                    self.wink_permission = env.clock().make_timer(wink_duration_ms);
                     // The response is empty like the request.
                     message
                 } else {
@@ -125,6 +124,16 @@ impl<E: Env> MainHid<E> {
         self.hid.has_channel_lock(env)
     }
 
+    /// Returns whether the continuation packets of a message are still expected.
+    pub fn is_receiving(&self, env: &mut E) -> bool {
+        self.hid.is_receiving(env)
+    }
+
+    /// Invalidates a channel, e.g. after its reply could not be delivered.
+    pub fn invalidate_channel(&mut self, cid: ChannelID) {
+        self.hid.invalidate_channel(cid)
+    }
+
     /// Returns whether a wink permission is currently granted.
     pub fn should_wink(&self, env: &mut E) -> bool {
         !env.clock().is_elapsed(&self.wink_permission)
@@ -156,7 +165,6 @@ impl<E: Env> MainHid<E> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ctap::hid::ChannelID;
     use crate::env::test::TestEnv;
 
     fn new_initialized() -> (MainHid<TestEnv>, ChannelID) {
@@ -218,7 +226,8 @@ mod test {
         assert_eq!(response.next(), Some(wink_packet));
         assert_eq!(response.next(), None);
         assert!(main_hid.should_wink(&mut env));
-        env.clock().advance(WINK_TIMEOUT_DURATION_MS);
+        let wink_duration_ms = env.customization().wink_duration_ms();
+        env.clock().advance(wink_duration_ms);
         assert!(!main_hid.should_wink(&mut env));
     }
 
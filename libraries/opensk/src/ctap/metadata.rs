@@ -0,0 +1,125 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! FIDO metadata the authenticator reports about itself.
+//!
+//! Relying parties look up the metadata statement of an authenticator by its AAGUID. The AAGUID
+//! is built into the firmware, unless a customer provisioned its own for the devices it deploys.
+
+use super::status_code::Ctap2StatusCode;
+use super::storage;
+use crate::api::customization::{Customization, AAGUID_LENGTH};
+use crate::env::Env;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Returns the AAGUID reported in getInfo and attested credentials.
+pub fn aaguid(env: &mut impl Env) -> Result<[u8; AAGUID_LENGTH], Ctap2StatusCode> {
+    storage::aaguid(env)
+}
+
+/// Returns whether a customer provisioned the AAGUID.
+pub fn has_custom_aaguid(env: &mut impl Env) -> Result<bool, Ctap2StatusCode> {
+    storage::has_custom_aaguid(env)
+}
+
+/// Returns the AAGUID that provisioning the given one would result in.
+///
+/// Like other provisioned values, a custom AAGUID is only written once, if allowed at all.
+pub fn provisioned_aaguid(
+    env: &mut impl Env,
+    aaguid: Option<&[u8; AAGUID_LENGTH]>,
+) -> Result<[u8; AAGUID_LENGTH], Ctap2StatusCode> {
+    match aaguid {
+        Some(aaguid) if env.customization().allows_custom_aaguid() && !has_custom_aaguid(env)? => {
+            Ok(*aaguid)
+        }
+        _ => self::aaguid(env),
+    }
+}
+
+/// Provisions the AAGUID of a customer, see `provisioned_aaguid`.
+pub fn provision_aaguid(
+    env: &mut impl Env,
+    aaguid: &[u8; AAGUID_LENGTH],
+) -> Result<(), Ctap2StatusCode> {
+    if env.customization().allows_custom_aaguid() && !has_custom_aaguid(env)? {
+        storage::set_custom_aaguid(env, aaguid)?;
+    }
+    Ok(())
+}
+
+/// Returns the certifications advertised in getInfo.
+pub fn certifications(env: &mut impl Env) -> Option<Vec<(String, i64)>> {
+    let level = env.customization().certification_level()?;
+    Some(vec![(String::from("FIDO"), level as i64)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+
+    #[test]
+    fn test_provision_aaguid() {
+        let mut env = TestEnv::default();
+        let firmware_aaguid = *env.customization().aaguid();
+        let custom_aaguid = [0x5A; AAGUID_LENGTH];
+        assert_eq!(aaguid(&mut env), Ok(firmware_aaguid));
+        assert_eq!(
+            provisioned_aaguid(&mut env, Some(&custom_aaguid)),
+            Ok(custom_aaguid)
+        );
+
+        provision_aaguid(&mut env, &custom_aaguid).unwrap();
+        assert_eq!(aaguid(&mut env), Ok(custom_aaguid));
+        assert_eq!(has_custom_aaguid(&mut env), Ok(true));
+
+        // The provisioned AAGUID is kept.
+        let other_aaguid = [0xA5; AAGUID_LENGTH];
+        assert_eq!(
+            provisioned_aaguid(&mut env, Some(&other_aaguid)),
+            Ok(custom_aaguid)
+        );
+        provision_aaguid(&mut env, &other_aaguid).unwrap();
+        assert_eq!(aaguid(&mut env), Ok(custom_aaguid));
+    }
+
+    #[test]
+    fn test_provision_aaguid_not_allowed() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_allows_custom_aaguid(false);
+        let firmware_aaguid = *env.customization().aaguid();
+        let custom_aaguid = [0x5A; AAGUID_LENGTH];
+        assert_eq!(
+            provisioned_aaguid(&mut env, Some(&custom_aaguid)),
+            Ok(firmware_aaguid)
+        );
+        provision_aaguid(&mut env, &custom_aaguid).unwrap();
+        assert_eq!(aaguid(&mut env), Ok(firmware_aaguid));
+        assert_eq!(has_custom_aaguid(&mut env), Ok(false));
+    }
+
+    #[test]
+    fn test_certifications() {
+        let mut env = TestEnv::default();
+        assert_eq!(certifications(&mut env), None);
+        env.customization_mut().set_certification_level(Some(3));
+        assert_eq!(
+            certifications(&mut env),
+            Some(vec![(String::from("FIDO"), 3)])
+        );
+    }
+}
@@ -13,10 +13,15 @@
 // limitations under the License.
 
 pub mod apdu;
+pub mod audit_log;
+#[cfg(feature = "ccid")]
+pub mod ccid;
 mod client_pin;
 pub mod command;
 #[cfg(feature = "config_command")]
 mod config_command;
+mod credential_import;
+mod credential_index;
 mod credential_management;
 pub mod crypto_wrapper;
 #[cfg(feature = "with_ctap1")]
@@ -24,7 +29,10 @@ mod ctap1;
 pub mod data_formats;
 pub mod hid;
 mod large_blobs;
+pub mod log;
 pub mod main_hid;
+pub mod metadata;
+mod pin_policy;
 mod pin_protocol;
 pub mod response;
 pub mod secret;
@@ -36,12 +44,16 @@ mod u2f_up;
 #[cfg(feature = "vendor_hid")]
 pub mod vendor_hid;
 
-use self::client_pin::{ClientPin, PinPermission};
+use self::audit_log::{AuditEvent, Timestamp};
+use self::client_pin::ClientPin;
+pub use self::client_pin::{pin_retries, uv_retries, PinPermission, PinUvAuthCheck};
 use self::command::{
     AuthenticatorGetAssertionParameters, AuthenticatorMakeCredentialParameters, Command,
 };
 #[cfg(feature = "config_command")]
 use self::config_command::process_config;
+pub use self::credential_import::import_credentials;
+use self::credential_index::CredentialIndex;
 use self::credential_management::process_credential_management;
 use self::data_formats::{
     AuthenticatorTransport, CredentialProtectionPolicy, EnterpriseAttestationMode,
@@ -49,16 +61,20 @@ use self::data_formats::{
     PublicKeyCredentialDescriptor, PublicKeyCredentialParameter, PublicKeyCredentialSource,
     PublicKeyCredentialType, PublicKeyCredentialUserEntity, SignatureAlgorithm,
 };
-use self::hid::{ChannelID, CtapHid, CtapHidCommand, KeepaliveStatus, ProcessedPacket};
+use self::hid::{ChannelID, ConcurrentPacket, CtapHid, HidPacket, KeepaliveStatus};
 use self::large_blobs::LargeBlobs;
+use self::main_hid::MainHid;
 use self::response::{
     AuthenticatorGetAssertionResponse, AuthenticatorGetInfoResponse,
     AuthenticatorMakeCredentialResponse, ResponseData,
 };
 use self::secret::Secret;
 use self::status_code::Ctap2StatusCode;
+pub use self::storage::{compact_store, is_store_corrupted, store_capacity, StoreCapacity};
 #[cfg(feature = "with_ctap1")]
 use self::u2f_up::U2fUserPresenceState;
+#[cfg(feature = "vendor_hid")]
+use self::vendor_hid::VendorHid;
 use crate::api::attestation_store::{self, Attestation, AttestationStore};
 use crate::api::clock::Clock;
 use crate::api::connection::{HidConnection, SendOrRecvStatus, UsbEndpoint};
@@ -67,12 +83,19 @@ use crate::api::crypto::hkdf256::Hkdf256;
 use crate::api::crypto::sha256::Sha256;
 use crate::api::crypto::HASH_SIZE;
 use crate::api::customization::Customization;
+use crate::api::display::{Display, Transaction};
 use crate::api::key_store::{CredentialSource, KeyStore, MAX_CREDENTIAL_ID_SIZE};
 use crate::api::private_key::PrivateKey;
 use crate::api::rng::Rng;
-use crate::api::user_presence::{UserPresence, UserPresenceError};
+use crate::api::user_feedback::{self, FeedbackState};
+use crate::api::user_presence::{
+    CommandClass, UserPresence, UserPresenceError, DEFAULT_TIMEOUT_MS,
+};
+use crate::api::user_verification::{UserVerification, UserVerificationError};
+use crate::api::watchdog::Watchdog;
 use crate::env::{EcdsaSk, Env, Hkdf, Sha};
 use alloc::boxed::Box;
+use alloc::collections::VecDeque;
 use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
@@ -96,14 +119,17 @@ const ED_FLAG: u8 = 0x80;
 const MAX_CBOR_NESTING_DEPTH: i8 = 4;
 
 pub const KEEPALIVE_DELAY_MS: usize = 100;
-pub const TOUCH_TIMEOUT_MS: usize = 30000;
+pub const TOUCH_TIMEOUT_MS: usize = DEFAULT_TIMEOUT_MS;
 const RESET_TIMEOUT_DURATION_MS: usize = 10000;
 const STATEFUL_COMMAND_TIMEOUT_DURATION_MS: usize = 30000;
+const ERROR_FEEDBACK_DURATION_MS: usize = 2000;
 
 pub const FIDO2_VERSION_STRING: &str = "FIDO_2_0";
 #[cfg(feature = "with_ctap1")]
 pub const U2F_VERSION_STRING: &str = "U2F_V2";
 pub const FIDO2_1_VERSION_STRING: &str = "FIDO_2_1";
+/// GetInfo option set while the store is corrupted, until reset wipes it.
+pub const STORE_CORRUPTED_OPTION: &str = "storeCorrupted";
 
 // We currently only support one algorithm for signatures: ES256.
 // This algorithm is requested in MakeCredential and advertized in GetInfo.
@@ -172,6 +198,9 @@ pub enum Channel {
     /// No equivalent in CTAP, used for communication outside the specification.
     #[cfg(feature = "vendor_hid")]
     VendorHid(ChannelID),
+    /// The USB smart card interface, carrying CTAP2 commands in APDUs like NFC.
+    #[cfg(feature = "ccid")]
+    Ccid,
 }
 
 // Helpers to perform CBOR read/write while respecting CTAP2 nesting limits.
@@ -190,13 +219,7 @@ pub fn filter_listed_credential(
     credential: Option<CredentialSource>,
     has_uv: bool,
 ) -> Option<CredentialSource> {
-    credential.filter(|c| {
-        has_uv
-            || !matches!(
-                c.cred_protect_policy,
-                Some(CredentialProtectionPolicy::UserVerificationRequired)
-            )
-    })
+    credential.filter(|c| CredentialProtectionPolicy::allows(c.cred_protect_policy, has_uv, true))
 }
 
 /// Filters the resident key from the option if credProtect criteria are not met.
@@ -204,13 +227,7 @@ fn filter_listed_resident_credential(
     credential: Option<PublicKeyCredentialSource>,
     has_uv: bool,
 ) -> Option<PublicKeyCredentialSource> {
-    credential.filter(|c| {
-        has_uv
-            || !matches!(
-                c.cred_protect_policy,
-                Some(CredentialProtectionPolicy::UserVerificationRequired)
-            )
-    })
+    credential.filter(|c| CredentialProtectionPolicy::allows(c.cred_protect_policy, has_uv, true))
 }
 
 /// Populates all matching fields in a `PublicKeyCredentialSource`.
@@ -231,6 +248,7 @@ fn to_public_source(
         user_icon: None,
         cred_blob: credential_source.cred_blob,
         large_blob_key: None,
+        creation_time: None,
     }
 }
 
@@ -248,77 +266,82 @@ fn truncate_to_char_boundary(s: &str, mut max: usize) -> &str {
     }
 }
 
-// Sends keepalive packet during user presence checking. If user agent replies with CANCEL response,
-// returns Err(UserPresenceError::Canceled).
-fn send_keepalive_up_needed<E: Env>(
+/// Returns the capabilities that INIT responses report on a transport.
+fn hid_capabilities<E: Env>(transport: Transport) -> u8 {
+    match transport {
+        Transport::MainHid => MainHid::<E>::CAPABILITIES,
+        #[cfg(feature = "vendor_hid")]
+        Transport::VendorHid => VendorHid::<E>::CAPABILITIES,
+    }
+}
+
+// Sends a keepalive packet while a command is processed on the channel. Packets that arrive in the
+// meantime are answered, so that other channels are not blocked. If the client cancels the
+// command, returns Err(UserPresenceError::Canceled).
+fn send_keepalive<E: Env>(
     env: &mut E,
     channel: Channel,
+    status: KeepaliveStatus,
     timeout_ms: usize,
 ) -> Result<(), UserPresenceError> {
     let (cid, transport) = match channel {
         Channel::MainHid(cid) => (cid, Transport::MainHid),
         #[cfg(feature = "vendor_hid")]
         Channel::VendorHid(cid) => (cid, Transport::VendorHid),
+        // CCID has no keepalive messages, clients just wait for the response.
+        #[cfg(feature = "ccid")]
+        Channel::Ccid => return Ok(()),
     };
-    let keepalive_msg = CtapHid::<E>::keepalive(cid, KeepaliveStatus::UpNeeded);
-    for mut pkt in keepalive_msg {
-        let ctap_hid_connection = transport.hid_connection(env);
+    // Replies to other channels are queued after the keepalive.
+    let mut outgoing: VecDeque<(Transport, HidPacket)> = CtapHid::<E>::keepalive(cid, status)
+        .map(|packet| (transport, packet))
+        .collect();
+    while let Some((tx_transport, packet)) = outgoing.pop_front() {
+        let mut pkt = packet;
+        let ctap_hid_connection = tx_transport.hid_connection(env);
         match ctap_hid_connection.send_and_maybe_recv(&mut pkt, timeout_ms) {
             Ok(SendOrRecvStatus::Timeout) => {
-                debug_ctap!(env, "Sending a KEEPALIVE packet timed out");
-                // The client is likely unresponsive, but let's retry.
+                debug_ctap!(env, "Sending a packet timed out");
+                // The client is likely unresponsive, we move on to the next packet.
             }
             Err(_) => panic!("Error sending KEEPALIVE packet"),
             Ok(SendOrRecvStatus::Sent) => {
-                debug_ctap!(env, "Sent KEEPALIVE packet");
+                debug_ctap!(env, "Sent packet while processing");
             }
             Ok(SendOrRecvStatus::Received(endpoint)) => {
+                // Our packet was not sent, so try again after handling the received one.
+                outgoing.push_front((tx_transport, packet));
                 let rx_transport = match endpoint {
                     UsbEndpoint::MainHid => Transport::MainHid,
                     #[cfg(feature = "vendor_hid")]
                     UsbEndpoint::VendorHid => Transport::VendorHid,
                 };
-                if rx_transport != transport {
-                    debug_ctap!(
-                        env,
-                        "Received a packet on transport {:?} while sending a KEEPALIVE packet on transport {:?}",
-                         rx_transport, transport
-                    );
-                    // Ignore this packet.
-                    // TODO(liamjm): Support receiving packets on both interfaces.
-                    continue;
-                }
-
-                // We only parse one packet, because we only care about CANCEL.
-                let (received_cid, processed_packet) = CtapHid::<E>::process_single_packet(&pkt);
-                if received_cid != cid {
-                    debug_ctap!(
-                        env,
-                        "Received a packet on channel ID {:?} while sending a KEEPALIVE packet",
-                        received_cid,
-                    );
-                    return Ok(());
-                }
-                match processed_packet {
-                    ProcessedPacket::InitPacket { cmd, .. } => {
-                        if cmd == CtapHidCommand::Cancel as u8 {
-                            // We ignore the payload, we can't answer with an error code anyway.
-                            debug_ctap!(env, "User presence check cancelled");
-                            return Err(UserPresenceError::Canceled);
-                        } else {
-                            debug_ctap!(
-                                env,
-                                "Discarded packet with command {} received while sending a KEEPALIVE packet",
-                                cmd,
-                            );
-                        }
+                // Channel IDs are specific to their transport.
+                let busy_cid = if rx_transport == transport {
+                    Some(cid)
+                } else {
+                    None
+                };
+                match CtapHid::<E>::process_concurrent_packet(
+                    &pkt,
+                    busy_cid,
+                    hid_capabilities::<E>(rx_transport),
+                ) {
+                    ConcurrentPacket::Cancel => {
+                        // We ignore the payload, we can't answer with an error code anyway.
+                        debug_ctap!(env, "Command cancelled");
+                        return Err(UserPresenceError::Canceled);
                     }
-                    ProcessedPacket::ContinuationPacket { .. } => {
-                        debug_ctap!(
-                            env,
-                            "Discarded continuation packet received while sending a KEEPALIVE packet",
+                    ConcurrentPacket::Reply(message) => {
+                        debug_ctap!(env, "Answering concurrent message: {:02x?}", message);
+                        outgoing.extend(
+                            CtapHid::<E>::split_message(message)
+                                .map(|packet| (rx_transport, packet)),
                         );
                     }
+                    ConcurrentPacket::Ignore => {
+                        debug_ctap!(env, "Discarded packet received while processing");
+                    }
                 }
             }
         }
@@ -326,20 +349,100 @@ fn send_keepalive_up_needed<E: Env>(
     Ok(())
 }
 
+/// Reports a long computation to the client, and checks whether it was cancelled.
+///
+/// Call this regularly from computations that take longer than the keepalive delay. Meanwhile,
+/// other channels are answered as far as possible.
+pub fn send_keepalive_processing<E: Env>(
+    env: &mut E,
+    channel: Channel,
+) -> Result<(), Ctap2StatusCode> {
+    env.watchdog().feed();
+    send_keepalive(
+        env,
+        channel,
+        KeepaliveStatus::Processing,
+        KEEPALIVE_DELAY_MS,
+    )
+    .map_err(|e| e.into())
+}
+
+/// Lets long computations notice that the client cancelled their command.
+///
+/// Checking sends a processing keepalive at most once per keepalive delay, so computations can
+/// check often.
+pub struct CancellationToken<E: Env> {
+    channel: Option<Channel>,
+    keepalive_timer: <E::Clock as Clock>::Timer,
+    cancelled: bool,
+}
+
+impl<E: Env> CancellationToken<E> {
+    /// Creates a token for a command received on the channel.
+    pub fn new(channel: Channel) -> Self {
+        CancellationToken {
+            channel: Some(channel),
+            keepalive_timer: <E::Clock as Clock>::Timer::default(),
+            cancelled: false,
+        }
+    }
+
+    /// Creates a token for commands that can't be cancelled, e.g. from other apps.
+    pub fn never() -> Self {
+        CancellationToken {
+            channel: None,
+            keepalive_timer: <E::Clock as Clock>::Timer::default(),
+            cancelled: false,
+        }
+    }
+
+    /// Returns CTAP2_ERR_KEEPALIVE_CANCEL if the client cancelled the command.
+    pub fn check(&mut self, env: &mut E) -> Result<(), Ctap2StatusCode> {
+        // Computations check regularly, even those that can't be cancelled.
+        env.watchdog().feed();
+        if self.cancelled {
+            return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+        }
+        let channel = match self.channel {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+        if !env.clock().is_elapsed(&self.keepalive_timer) {
+            return Ok(());
+        }
+        self.keepalive_timer = env.clock().make_timer(KEEPALIVE_DELAY_MS);
+        let result = send_keepalive_processing(env, channel);
+        self.cancelled = result.is_err();
+        result
+    }
+
+    /// Returns whether a previous check noticed a cancellation.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+}
+
 /// Blocks for user presence.
 ///
 /// Returns an error in case of timeout, user declining presence request, or keepalive error.
-pub fn check_user_presence<E: Env>(env: &mut E, channel: Channel) -> Result<(), Ctap2StatusCode> {
+/// The timeout depends on the command class, as chosen by the environment.
+pub fn check_user_presence<E: Env>(
+    env: &mut E,
+    channel: Channel,
+    class: CommandClass,
+) -> Result<(), Ctap2StatusCode> {
     env.user_presence().check_init();
 
     // The timeout is N times the keepalive delay.
-    const TIMEOUT_ITERATIONS: usize = TOUCH_TIMEOUT_MS / KEEPALIVE_DELAY_MS;
+    let timeout_iterations = env.user_presence().timeout_ms(class) / KEEPALIVE_DELAY_MS;
 
     // All fallible functions are called without '?' operator to always reach
     // check_complete(...) cleanup function.
 
     let mut result = Err(UserPresenceError::Timeout);
-    for i in 0..=TIMEOUT_ITERATIONS {
+    for i in 0..=timeout_iterations {
+        env.watchdog().feed();
+        user_feedback::signal(env, FeedbackState::AwaitingTouch, i);
         // First presence check is made without timeout. That way Env implementation may return
         // user presence check result immediately to client, without sending any keepalive packets.
         result = env
@@ -352,7 +455,8 @@ pub fn check_user_presence<E: Env>(env: &mut E, channel: Channel) -> Result<(),
         // accordingly, so that all wait_with_timeout invocations are separated by
         // equal time intervals. That way token indicators, such as LEDs, will blink
         // with a consistent pattern.
-        let keepalive_result = send_keepalive_up_needed(env, channel, KEEPALIVE_DELAY_MS);
+        let keepalive_result =
+            send_keepalive(env, channel, KeepaliveStatus::UpNeeded, KEEPALIVE_DELAY_MS);
         if keepalive_result.is_err() {
             debug_ctap!(
                 env,
@@ -365,9 +469,90 @@ pub fn check_user_presence<E: Env>(env: &mut E, channel: Channel) -> Result<(),
     }
 
     env.user_presence().check_complete();
+    user_feedback::signal(env, FeedbackState::Idle, 0);
     result.map_err(|e| e.into())
 }
 
+/// Shows the transaction, if the device has a display, and blocks for user presence.
+///
+/// The transaction is only built if it can be shown. The display is cleared in any case.
+pub fn confirm_transaction<E: Env>(
+    env: &mut E,
+    channel: Channel,
+    class: CommandClass,
+    transaction: impl FnOnce() -> Transaction,
+) -> Result<(), Ctap2StatusCode> {
+    if !env.display().is_supported() {
+        return check_user_presence(env, channel, class);
+    }
+    env.display().show(&transaction())?;
+    let result = check_user_presence(env, channel, class);
+    env.display().clear();
+    result
+}
+
+/// Blocks for built-in user verification.
+///
+/// Returns an error if the device has no built-in method, verification fails or is blocked, in
+/// case of timeout, or keepalive error.
+pub fn check_user_verification<E: Env>(
+    env: &mut E,
+    channel: Channel,
+) -> Result<(), Ctap2StatusCode> {
+    if !env.user_verification().is_supported() {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+    }
+    if uv_retries(env)? == 0 {
+        return Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED);
+    }
+    env.user_verification().check_init();
+
+    // Same structure as the user presence check, see check_user_presence.
+    const TIMEOUT_ITERATIONS: usize = TOUCH_TIMEOUT_MS / KEEPALIVE_DELAY_MS;
+    let mut result = Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT);
+    for i in 0..=TIMEOUT_ITERATIONS {
+        env.watchdog().feed();
+        user_feedback::signal(env, FeedbackState::AwaitingTouch, i);
+        let uv_result =
+            env.user_verification()
+                .wait_with_timeout(if i == 0 { 0 } else { KEEPALIVE_DELAY_MS });
+        if !matches!(uv_result, Err(UserVerificationError::Timeout)) {
+            result = uv_result.map_err(|e| e.into());
+            break;
+        }
+        if let Err(e) = send_keepalive(env, channel, KeepaliveStatus::UpNeeded, KEEPALIVE_DELAY_MS)
+        {
+            debug_ctap!(env, "Sending keepalive failed with error {:?}", e);
+            result = Err(e.into());
+            break;
+        }
+    }
+
+    env.user_verification().check_complete();
+    user_feedback::signal(env, FeedbackState::Idle, 0);
+    match result {
+        Ok(()) => storage::reset_uv_retries(env)?,
+        Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID) => storage::decr_uv_retries(env)?,
+        Err(_) => (),
+    }
+    result
+}
+
+/// Returns whether the status code is worth signaling to the user.
+///
+/// Most errors are part of regular protocol flows, like probing for credentials. We only show
+/// those that leave the user wondering why an operation failed.
+fn is_user_visible_error(status: u8) -> bool {
+    [
+        Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED,
+        Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT,
+        Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR,
+        Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
+    ]
+    .iter()
+    .any(|&code| code as u8 == status)
+}
+
 /// Holds data necessary to sign an assertion for a credential.
 #[derive(Clone)]
 pub struct AssertionInput {
@@ -536,12 +721,25 @@ pub struct CtapState<E: Env> {
     pub(crate) u2f_up_state: U2fUserPresenceState<E>,
     // The state initializes to Reset and its timeout, and never goes back to Reset.
     stateful_command_permission: StatefulPermission<E>,
-    large_blobs: LargeBlobs,
+    large_blobs: LargeBlobs<E>,
+    // Errors are signaled to the user until this timer elapses.
+    error_feedback_timer: <E::Clock as Clock>::Timer,
+    // The device identifies itself until this timer elapses.
+    identify_timer: <E::Clock as Clock>::Timer,
+    // Built on the first allow list-less getAssertion, dropped when credentials may change.
+    credential_index: Option<CredentialIndex>,
+    // The store failed its integrity check at boot and stays read-only until reset wipes it.
+    store_corrupted: bool,
 }
 
 impl<E: Env> CtapState<E> {
     pub fn new(env: &mut E) -> Self {
-        storage::init(env).ok().unwrap();
+        let store_corrupted = storage::is_store_corrupted(env);
+        if !store_corrupted {
+            storage::init(env).ok().unwrap();
+            storage::incr_boot_counter(env).ok().unwrap();
+            pin_policy::check_current_pin(env).ok().unwrap();
+        }
         let client_pin = ClientPin::new(env);
         CtapState {
             client_pin,
@@ -549,14 +747,23 @@ impl<E: Env> CtapState<E> {
             u2f_up_state: U2fUserPresenceState::new(),
             stateful_command_permission: StatefulPermission::new_reset(env),
             large_blobs: LargeBlobs::new(),
+            error_feedback_timer: <E::Clock as Clock>::Timer::default(),
+            identify_timer: <E::Clock as Clock>::Timer::default(),
+            credential_index: None,
+            store_corrupted,
         }
     }
 
-    pub fn increment_global_signature_counter(
+    /// Increments the signature counter that the relying party sees.
+    pub fn increment_signature_counter(
         &mut self,
         env: &mut E,
+        rp_id_hash: &[u8],
     ) -> Result<(), Ctap2StatusCode> {
-        if env.customization().use_signature_counter() {
+        if env.customization().use_rp_signature_counters() {
+            let increment = env.rng().next_u32() % 8 + 1;
+            storage::incr_rp_signature_counter(env, rp_id_hash, increment)?;
+        } else if env.customization().use_signature_counter() {
             let increment = env.rng().next_u32() % 8 + 1;
             storage::incr_global_signature_counter(env, increment)?;
         }
@@ -588,9 +795,39 @@ impl<E: Env> CtapState<E> {
         command_cbor: &[u8],
         channel: Channel,
     ) -> Vec<u8> {
-        if let Some(response) = env.process_vendor_command(command_cbor, channel) {
+        env.watchdog().feed();
+        let mut response = self.process_command_bytes(env, command_cbor, channel);
+        // Commands like BBS proofs may take long, so the next deadline starts after them.
+        env.watchdog().feed();
+        // Clients size their buffers by maxMsgSize, so a longer response would be cut off.
+        if response.len() > env.customization().max_msg_size() {
+            response = vec![Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE as u8];
+        }
+        if response
+            .first()
+            .copied()
+            .map_or(false, is_user_visible_error)
+        {
+            self.error_feedback_timer = env.clock().make_timer(ERROR_FEEDBACK_DURATION_MS);
+        }
+        response
+    }
+
+    fn process_command_bytes(
+        &mut self,
+        env: &mut E,
+        command_cbor: &[u8],
+        channel: Channel,
+    ) -> Vec<u8> {
+        // HID rejects longer messages while receiving, other transports assemble them first.
+        if command_cbor.len() > env.customization().max_msg_size() {
+            return vec![Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH as u8];
+        }
+        if let Some(response) = env.process_vendor_command(command_cbor, channel, &self.client_pin)
+        {
             self.clear_other_channels(channel);
             self.stateful_command_permission.clear();
+            self.credential_index = None;
             return response;
         }
         let cmd = Command::deserialize(command_cbor);
@@ -611,6 +848,26 @@ impl<E: Env> CtapState<E> {
         }
     }
 
+    /// Runs a slice of background maintenance, and returns whether it did any work.
+    ///
+    /// A slice compacts at most one store page, if fewer words than `store_compaction_threshold`
+    /// are writable. Compaction also rotates the pages that the store writes, which levels their
+    /// wear. Call this while no command is pending, until it does nothing.
+    pub fn process_idle_work(&mut self, env: &mut E) -> bool {
+        // Errors are left to the write that needs the space.
+        storage::compact_store_if_low(env).unwrap_or(false)
+    }
+
+    /// Returns whether a recent command failed with an error the user should see.
+    pub fn has_recent_error(&mut self, env: &mut E) -> bool {
+        !env.clock().is_elapsed(&self.error_feedback_timer)
+    }
+
+    /// Returns whether the client asked the device to identify itself.
+    pub fn is_identifying(&mut self, env: &mut E) -> bool {
+        !env.clock().is_elapsed(&self.identify_timer)
+    }
+
     /// Processed a command after parsing from CBOR, returning its structured output.
     ///
     /// This function contains the logic of `parse_command`, minus all CBOR encoding and decoding.
@@ -649,6 +906,8 @@ impl<E: Env> CtapState<E> {
             Channel::MainHid(_) => self.process_fido_command(env, command, channel),
             #[cfg(feature = "vendor_hid")]
             Channel::VendorHid(_) => self.process_vendor_command(env, command),
+            #[cfg(feature = "ccid")]
+            Channel::Ccid => self.process_fido_command(env, command, channel),
         }
     }
 
@@ -658,6 +917,13 @@ impl<E: Env> CtapState<E> {
         command: Command,
         channel: Channel,
     ) -> Result<ResponseData, Ctap2StatusCode> {
+        // Only assertions keep the credential index, other commands may add or delete credentials.
+        if !matches!(
+            command,
+            Command::AuthenticatorGetAssertion(_) | Command::AuthenticatorGetNextAssertion
+        ) {
+            self.credential_index = None;
+        }
         match command {
             Command::AuthenticatorMakeCredential(params) => {
                 self.process_make_credential(env, params, channel)
@@ -667,7 +933,9 @@ impl<E: Env> CtapState<E> {
             }
             Command::AuthenticatorGetNextAssertion => self.process_get_next_assertion(env),
             Command::AuthenticatorGetInfo => self.process_get_info(env),
-            Command::AuthenticatorClientPin(params) => self.client_pin.process_command(env, params),
+            Command::AuthenticatorClientPin(params) => {
+                self.client_pin.process_command(env, params, channel)
+            }
             Command::AuthenticatorReset => self.process_reset(env, channel),
             Command::AuthenticatorCredentialManagement(params) => process_credential_management(
                 env,
@@ -685,6 +953,7 @@ impl<E: Env> CtapState<E> {
             Command::AuthenticatorConfig(params) => {
                 process_config(env, &mut self.client_pin, params)
             }
+            Command::AuthenticatorVendorIdentify => self.process_vendor_identify(env),
         }
     }
 
@@ -696,6 +965,7 @@ impl<E: Env> CtapState<E> {
     ) -> Result<ResponseData, Ctap2StatusCode> {
         match command {
             Command::AuthenticatorGetInfo => self.process_get_info(env),
+            Command::AuthenticatorVendorIdentify => self.process_vendor_identify(env),
             _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND),
         }
     }
@@ -710,7 +980,7 @@ impl<E: Env> CtapState<E> {
         if let Some(auth_param) = &pin_uv_auth_param {
             // This case was added in FIDO 2.1.
             if auth_param.is_empty() {
-                check_user_presence(env, channel)?;
+                check_user_presence(env, channel, CommandClass::Credential)?;
                 if storage::pin_hash(env)?.is_none() {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PIN_NOT_SET);
                 } else {
@@ -774,7 +1044,6 @@ impl<E: Env> CtapState<E> {
         // MakeCredential always requires user presence.
         // User verification depends on the PIN auth inputs, which are checked here.
         // The ED flag is added later, if applicable.
-        let has_uv = pin_uv_auth_param.is_some();
         let mut flags = match pin_uv_auth_param {
             Some(pin_uv_auth_param) => {
                 // This case is not mentioned in CTAP2.1, so we keep 2.0 logic.
@@ -795,10 +1064,12 @@ impl<E: Env> CtapState<E> {
                 self.client_pin.ensure_rp_id_permission(&rp_id)?;
                 UV_FLAG
             }
+            None if options.uv => {
+                // Fails with CTAP2_ERR_INVALID_OPTION without built-in user verification.
+                check_user_verification(env, channel)?;
+                UV_FLAG
+            }
             None => {
-                if options.uv {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
-                }
                 if storage::has_always_uv(env)? {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED);
                 }
@@ -809,6 +1080,7 @@ impl<E: Env> CtapState<E> {
                 0x00
             }
         };
+        let has_uv = flags & UV_FLAG != 0;
         flags |= UP_FLAG | AT_FLAG;
 
         let rp_id_hash = Sha::<E>::digest(rp_id.as_bytes());
@@ -828,13 +1100,20 @@ impl<E: Env> CtapState<E> {
                 {
                     // Perform this check, so bad actors can't brute force exclude_list
                     // without user interaction.
-                    let _ = check_user_presence(env, channel);
+                    let _ = check_user_presence(env, channel, CommandClass::Credential);
                     return Err(Ctap2StatusCode::CTAP2_ERR_CREDENTIAL_EXCLUDED);
                 }
             }
         }
 
-        check_user_presence(env, channel)?;
+        let rp_name = &rp.rp_name;
+        confirm_transaction(env, channel, CommandClass::Credential, || {
+            Transaction::Registration {
+                rp_id: rp_id.clone(),
+                rp_name: rp_name.clone(),
+                user_name: user.user_name.clone(),
+            }
+        })?;
         self.client_pin.clear_token_flags();
 
         let default_cred_protect = env.customization().default_cred_protect();
@@ -894,8 +1173,10 @@ impl<E: Env> CtapState<E> {
                     .map(|s| truncate_to_char_boundary(&s, 64).to_string()),
                 cred_blob,
                 large_blob_key: large_blob_key.clone(),
+                creation_time: Some(Timestamp::now(env)?),
             };
             storage::store_credential(env, credential_source)?;
+            audit_log::record(env, AuditEvent::CredentialCreated)?;
             random_id
         } else {
             let credential_source = CredentialSource {
@@ -910,7 +1191,7 @@ impl<E: Env> CtapState<E> {
         };
 
         let mut auth_data = self.generate_auth_data(env, &rp_id_hash, flags)?;
-        auth_data.extend(env.customization().aaguid());
+        auth_data.extend(&metadata::aaguid(env)?);
         // The length is fixed to 0x20 or 0x80 and fits one byte.
         if credential_id.len() > 0xFF {
             return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
@@ -962,11 +1243,13 @@ impl<E: Env> CtapState<E> {
                     .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
                 let attestation_key = EcdsaSk::<E>::from_slice(&private_key).unwrap();
                 (
-                    attestation_key.sign(&signature_data).to_der(),
+                    attestation_key
+                        .sign_with_rng(env.rng(), &signature_data)
+                        .to_der(),
                     Some(vec![certificate]),
                 )
             }
-            None => (private_key.sign_and_encode::<E>(&signature_data)?, None),
+            None => (private_key.sign_and_encode(env, &signature_data)?, None),
         };
         let attestation_statement = PackedAttestationStatement {
             alg: SignatureAlgorithm::Es256 as i64,
@@ -1052,7 +1335,7 @@ impl<E: Env> CtapState<E> {
         signature_data.extend(client_data_hash);
         let signature = credential
             .private_key
-            .sign_and_encode::<E>(&signature_data)?;
+            .sign_and_encode(env, &signature_data)?;
 
         let cred_desc = PublicKeyCredentialDescriptor {
             key_type: PublicKeyCredentialType::PublicKey,
@@ -1091,6 +1374,55 @@ impl<E: Env> CtapState<E> {
         }
     }
 
+    // Returns the keys of all applicable credentials of the RP, oldest first.
+    fn get_rp_credential_keys(
+        &mut self,
+        env: &mut E,
+        rp_id: &str,
+        has_uv: bool,
+    ) -> Result<Vec<usize>, Ctap2StatusCode> {
+        let mut is_fresh = self.credential_index.is_none();
+        let mut index = match self.credential_index.take() {
+            Some(index) => index,
+            None => CredentialIndex::build(env)?,
+        };
+        let mut stored_credentials: Vec<(usize, u64)> = loop {
+            let mut stored_credentials = Vec::new();
+            let mut is_stale = false;
+            for key in index.keys::<E>(rp_id) {
+                let credential = match storage::get_credential(env, key) {
+                    Ok(credential) => credential,
+                    Err(error) if is_fresh => return Err(error),
+                    Err(_) => {
+                        is_stale = true;
+                        break;
+                    }
+                };
+                if credential.rp_id == rp_id
+                    && CredentialProtectionPolicy::allows(
+                        credential.cred_protect_policy,
+                        has_uv,
+                        false,
+                    )
+                {
+                    stored_credentials.push((key, credential.creation_order));
+                }
+            }
+            if !is_stale {
+                break stored_credentials;
+            }
+            // A credential was deleted since the index was built.
+            index = CredentialIndex::build(env)?;
+            is_fresh = true;
+        };
+        self.credential_index = Some(index);
+        stored_credentials.sort_unstable_by_key(|&(_key, order)| order);
+        Ok(stored_credentials
+            .into_iter()
+            .map(|(key, _order)| key)
+            .collect())
+    }
+
     // Returns the first applicable credential from the allow list.
     fn get_any_credential_from_allow_list(
         &mut self,
@@ -1143,9 +1475,8 @@ impl<E: Env> CtapState<E> {
             return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION);
         }
 
-        // The user verification bit depends on the existance of PIN auth, since we do
-        // not support internal UV. User presence is requested as an option.
-        let has_uv = pin_uv_auth_param.is_some();
+        // The user verification bit depends on the existance of PIN auth, or built-in UV if
+        // requested. User presence is requested as an option.
         let mut flags = match pin_uv_auth_param {
             Some(pin_uv_auth_param) => {
                 // This case is not mentioned in CTAP2.1, so we keep 2.0 logic.
@@ -1166,16 +1497,19 @@ impl<E: Env> CtapState<E> {
                 self.client_pin.ensure_rp_id_permission(&rp_id)?;
                 UV_FLAG
             }
+            None if options.uv => {
+                // Fails with CTAP2_ERR_INVALID_OPTION without built-in user verification.
+                check_user_verification(env, channel)?;
+                UV_FLAG
+            }
             None => {
-                if options.uv {
-                    return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
-                }
                 if options.up && storage::has_always_uv(env)? {
                     return Err(Ctap2StatusCode::CTAP2_ERR_PUAT_REQUIRED);
                 }
                 0x00
             }
         };
+        let has_uv = flags & UV_FLAG != 0;
         if options.up {
             flags |= UP_FLAG;
         }
@@ -1196,23 +1530,7 @@ impl<E: Env> CtapState<E> {
                 vec![],
             )
         } else {
-            let mut iter_result = Ok(());
-            let iter = storage::iter_credentials(env, &mut iter_result)?;
-            let mut stored_credentials: Vec<(usize, u64)> = iter
-                .filter_map(|(key, credential)| {
-                    if credential.rp_id == rp_id && (has_uv || credential.is_discoverable()) {
-                        Some((key, credential.creation_order))
-                    } else {
-                        None
-                    }
-                })
-                .collect();
-            iter_result?;
-            stored_credentials.sort_unstable_by_key(|&(_key, order)| order);
-            let mut stored_credentials: Vec<usize> = stored_credentials
-                .into_iter()
-                .map(|(key, _order)| key)
-                .collect();
+            let mut stored_credentials = self.get_rp_credential_keys(env, &rp_id, has_uv)?;
             let credential = stored_credentials
                 .pop()
                 .map(|key| storage::get_credential(env, key))
@@ -1224,11 +1542,16 @@ impl<E: Env> CtapState<E> {
 
         // This check comes before CTAP2_ERR_NO_CREDENTIALS in CTAP 2.0.
         if options.up {
-            check_user_presence(env, channel)?;
+            confirm_transaction(env, channel, CommandClass::Credential, || {
+                Transaction::Authentication {
+                    rp_id: rp_id.clone(),
+                    user_name: credential.user_name.clone(),
+                }
+            })?;
             self.client_pin.clear_token_flags();
         }
 
-        self.increment_global_signature_counter(env)?;
+        self.increment_signature_counter(env, &rp_id_hash)?;
 
         let assertion_input = AssertionInput {
             client_data_hash,
@@ -1266,6 +1589,9 @@ impl<E: Env> CtapState<E> {
     }
 
     fn process_get_info(&self, env: &mut E) -> Result<ResponseData, Ctap2StatusCode> {
+        if self.store_corrupted {
+            return Ok(self.process_get_info_corrupted(env));
+        }
         let has_always_uv = storage::has_always_uv(env)?;
         #[cfg_attr(not(feature = "with_ctap1"), allow(unused_mut))]
         let mut versions = vec![
@@ -1292,22 +1618,30 @@ impl<E: Env> CtapState<E> {
             (String::from("setMinPINLength"), true),
             (String::from("makeCredUvNotRqd"), !has_always_uv),
         ]);
+        if env.user_verification().is_supported() {
+            options.push((String::from("uv"), true));
+        }
         let mut pin_protocols = vec![PinUvAuthProtocol::V2 as u64];
         if env.customization().allows_pin_protocol_v1() {
             pin_protocols.push(PinUvAuthProtocol::V1 as u64);
         }
+        #[cfg_attr(not(feature = "bbs"), allow(unused_mut))]
+        let mut extensions = vec![
+            String::from("hmac-secret"),
+            String::from("credProtect"),
+            String::from("minPinLength"),
+            String::from("credBlob"),
+            String::from("largeBlobKey"),
+        ];
+        // Lets wallets discover BBS support without a vendor command on the main channel.
+        #[cfg(feature = "bbs")]
+        extensions.push(String::from(bbs::BBS_CAPABILITY_ID));
 
         Ok(ResponseData::AuthenticatorGetInfo(
             AuthenticatorGetInfoResponse {
                 versions,
-                extensions: Some(vec![
-                    String::from("hmac-secret"),
-                    String::from("credProtect"),
-                    String::from("minPinLength"),
-                    String::from("credBlob"),
-                    String::from("largeBlobKey"),
-                ]),
-                aaguid: *env.customization().aaguid(),
+                extensions: Some(extensions),
+                aaguid: metadata::aaguid(env)?,
                 options: Some(options),
                 max_msg_size: Some(env.customization().max_msg_size() as u64),
                 // The order implies preference. We favor the new V2.
@@ -1324,12 +1658,16 @@ impl<E: Env> CtapState<E> {
                 ),
                 force_pin_change: Some(storage::has_force_pin_change(env)?),
                 min_pin_length: storage::min_pin_length(env)?,
-                firmware_version: env.firmware_version(),
+                // The boot loader checked its version, unlike the one the firmware reports.
+                firmware_version: env
+                    .boot_info()
+                    .map(|boot_info| boot_info.firmware_version)
+                    .or_else(|| env.firmware_version()),
                 max_cred_blob_length: Some(env.customization().max_cred_blob_length() as u64),
                 max_rp_ids_for_set_min_pin_length: Some(
                     env.customization().max_rp_ids_length() as u64
                 ),
-                certifications: None,
+                certifications: metadata::certifications(env),
                 remaining_discoverable_credentials: Some(
                     storage::remaining_credentials(env)? as u64
                 ),
@@ -1337,6 +1675,38 @@ impl<E: Env> CtapState<E> {
         ))
     }
 
+    /// Answers getInfo without relying on the store, and flags it as corrupted.
+    ///
+    /// Platforms ignore the unknown option, vendor tools show it and offer a reset.
+    fn process_get_info_corrupted(&self, env: &mut E) -> ResponseData {
+        ResponseData::AuthenticatorGetInfo(AuthenticatorGetInfoResponse {
+            versions: vec![
+                String::from(FIDO2_VERSION_STRING),
+                String::from(FIDO2_1_VERSION_STRING),
+            ],
+            extensions: None,
+            aaguid: *env.customization().aaguid(),
+            options: Some(vec![(String::from(STORE_CORRUPTED_OPTION), true)]),
+            max_msg_size: Some(env.customization().max_msg_size() as u64),
+            pin_protocols: None,
+            max_credential_count_in_list: None,
+            max_credential_id_length: None,
+            transports: Some(vec![AuthenticatorTransport::Usb]),
+            algorithms: None,
+            max_serialized_large_blob_array: None,
+            force_pin_change: None,
+            min_pin_length: env.customization().default_min_pin_length(),
+            firmware_version: env
+                .boot_info()
+                .map(|boot_info| boot_info.firmware_version)
+                .or_else(|| env.firmware_version()),
+            max_cred_blob_length: None,
+            max_rp_ids_for_set_min_pin_length: None,
+            certifications: None,
+            remaining_discoverable_credentials: None,
+        })
+    }
+
     fn process_reset(
         &mut self,
         env: &mut E,
@@ -1348,9 +1718,15 @@ impl<E: Env> CtapState<E> {
         ) {
             return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
         }
-        check_user_presence(env, channel)?;
+        check_user_presence(env, channel, CommandClass::Reset)?;
 
-        storage::reset(env)?;
+        if self.store_corrupted {
+            storage::wipe(env)?;
+            self.store_corrupted = false;
+        } else {
+            storage::reset(env)?;
+        }
+        audit_log::record(env, AuditEvent::Reset)?;
         self.client_pin.reset(env);
         #[cfg(feature = "with_ctap1")]
         {
@@ -1366,10 +1742,16 @@ impl<E: Env> CtapState<E> {
         env: &mut E,
         channel: Channel,
     ) -> Result<ResponseData, Ctap2StatusCode> {
-        check_user_presence(env, channel)?;
+        check_user_presence(env, channel, CommandClass::Selection)?;
         Ok(ResponseData::AuthenticatorSelection)
     }
 
+    fn process_vendor_identify(&mut self, env: &mut E) -> Result<ResponseData, Ctap2StatusCode> {
+        let wink_duration_ms = env.customization().wink_duration_ms();
+        self.identify_timer = env.clock().make_timer(wink_duration_ms);
+        Ok(ResponseData::AuthenticatorVendorIdentify)
+    }
+
     pub fn generate_auth_data(
         &self,
         env: &mut E,
@@ -1381,11 +1763,13 @@ impl<E: Env> CtapState<E> {
         auth_data.push(flag_byte);
         // The global counter is only increased if use_signature_counter() is true.
         // It uses a big-endian representation.
+        let counter_value = if env.customization().use_rp_signature_counters() {
+            storage::rp_signature_counter(env, rp_id_hash)?
+        } else {
+            storage::global_signature_counter(env)?
+        };
         let mut signature_counter = [0u8; 4];
-        BigEndian::write_u32(
-            &mut signature_counter,
-            storage::global_signature_counter(env)?,
-        );
+        BigEndian::write_u32(&mut signature_counter, counter_value);
         auth_data.extend(&signature_counter);
         Ok(auth_data)
     }
@@ -1414,6 +1798,7 @@ mod test {
     };
     use super::pin_protocol::{authenticate_pin_uv_auth_token, PinProtocol};
     use super::*;
+    use crate::api::boot_info::BootInfo;
     use crate::api::crypto::ecdh::SecretKey as _;
     use crate::api::customization;
     use crate::api::key_store::CBOR_CREDENTIAL_ID_SIZE;
@@ -1421,7 +1806,10 @@ mod test {
     use crate::env::test::TestEnv;
     use crate::env::EcdhSk;
     use crate::test_helpers;
+    use alloc::rc::Rc;
     use cbor::{cbor_array, cbor_array_vec, cbor_map};
+    use core::cell::Cell;
+    use persistent_store::{StorageIndex, StoreError};
 
     // The keep-alive logic in the processing of some commands needs a channel ID to send
     // keep-alive packets to.
@@ -1488,13 +1876,15 @@ mod test {
                     String::from(FIDO2_VERSION_STRING),
                     String::from(FIDO2_1_VERSION_STRING),
                 ]],
-            0x02 => cbor_array![
+            0x02 => cbor_array_vec![vec![
                     String::from("hmac-secret"),
                     String::from("credProtect"),
                     String::from("minPinLength"),
                     String::from("credBlob"),
                     String::from("largeBlobKey"),
-                ],
+                    #[cfg(feature = "bbs")]
+                    String::from(bbs::BBS_CAPABILITY_ID),
+                ]],
             0x03 => env.customization().aaguid(),
             0x04 => cbor_map_options! {
                 "ep" => env.customization().enterprise_attestation_mode().map(|_| false),
@@ -1529,6 +1919,43 @@ mod test {
         assert_eq!(info_reponse, response_cbor);
     }
 
+    #[test]
+    fn test_get_info_metadata() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_certification_level(Some(2));
+        metadata::provision_aaguid(&mut env, &[0x5A; 16]).unwrap();
+        let ctap_state = CtapState::new(&mut env);
+        let info_response = ctap_state.process_get_info(&mut env).unwrap();
+        match info_response {
+            ResponseData::AuthenticatorGetInfo(response) => {
+                assert_eq!(response.aaguid, [0x5A; 16]);
+                assert_eq!(
+                    response.certifications,
+                    Some(vec![(String::from("FIDO"), 2)])
+                );
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
+    #[test]
+    fn test_get_info_boot_info() {
+        let mut env = TestEnv::default();
+        env.set_boot_info(Some(BootInfo {
+            measurement: [0x55; 32],
+            firmware_version: 7,
+            rollback_counter: 5,
+        }));
+        let ctap_state = CtapState::new(&mut env);
+        let info_response = ctap_state.process_get_info(&mut env).unwrap();
+        match info_response {
+            ResponseData::AuthenticatorGetInfo(response) => {
+                assert_eq!(response.firmware_version, Some(7));
+            }
+            _ => panic!("Invalid response type"),
+        }
+    }
+
     #[test]
     fn test_get_info_no_pin_protocol_v1() {
         let mut env = TestEnv::default();
@@ -1695,6 +2122,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
         assert!(storage::store_credential(&mut env, excluded_credential_source).is_ok());
 
@@ -1800,6 +2228,56 @@ mod test {
         assert!(make_credential_response.is_ok());
     }
 
+    #[test]
+    fn test_process_make_credential_default_cred_protect() {
+        let mut env = TestEnv::default();
+        env.customization_mut()
+            .set_default_cred_protect(Some(CredentialProtectionPolicy::UserVerificationRequired));
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL)
+            .is_ok());
+        // A lower level requested by the platform is raised to the default.
+        let mut make_credential_params = create_make_credential_parameters_with_cred_protect_policy(
+            CredentialProtectionPolicy::UserVerificationOptional,
+        );
+        make_credential_params.user.user_id = vec![0x2D];
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL)
+            .is_ok());
+
+        let mut iter_result = Ok(());
+        let iter = storage::iter_credentials(&mut env, &mut iter_result).unwrap();
+        for (_, credential) in iter {
+            assert_eq!(
+                credential.cred_protect_policy,
+                Some(CredentialProtectionPolicy::UserVerificationRequired)
+            );
+        }
+        iter_result.unwrap();
+
+        let get_assertion_params = AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: GetAssertionExtensions::default(),
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        let get_assertion_response =
+            ctap_state.process_get_assertion(&mut env, get_assertion_params, DUMMY_CHANNEL);
+        assert_eq!(
+            get_assertion_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_NO_CREDENTIALS),
+        );
+    }
+
     #[test]
     fn test_process_make_credential_hmac_secret() {
         let mut env = TestEnv::default();
@@ -1995,6 +2473,35 @@ mod test {
         assert_eq!(stored_credential.large_blob_key.unwrap(), large_blob_key);
     }
 
+    #[test]
+    fn test_process_make_credential_timestamped() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        env.clock().advance(1500);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL)
+            .is_ok());
+
+        let expected_time = Timestamp {
+            boot_count: 1,
+            uptime_ms: 1500,
+        };
+        let mut iter_result = Ok(());
+        let iter = storage::iter_credentials(&mut env, &mut iter_result).unwrap();
+        let (_, stored_credential) = iter.last().unwrap();
+        iter_result.unwrap();
+        assert_eq!(stored_credential.creation_time, Some(expected_time));
+        assert_eq!(
+            audit_log::entries(&mut env).unwrap(),
+            vec![audit_log::AuditEntry {
+                event: AuditEvent::CredentialCreated,
+                timestamp: expected_time,
+            }]
+        );
+    }
+
     fn test_helper_process_make_credential_with_pin_and_uv(
         pin_uv_auth_protocol: PinUvAuthProtocol,
     ) {
@@ -2054,6 +2561,201 @@ mod test {
         test_helper_process_make_credential_with_pin_and_uv(PinUvAuthProtocol::V2);
     }
 
+    #[test]
+    fn test_process_make_credential_with_built_in_uv() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        storage::set_pin(&mut env, &[0x88; 16], 4).unwrap();
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.options.uv = true;
+        let make_credential_response = ctap_state.process_make_credential(
+            &mut env,
+            make_credential_params.clone(),
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+
+        env.user_verification().set(|| Ok(()));
+        let make_credential_response = ctap_state.process_make_credential(
+            &mut env,
+            make_credential_params.clone(),
+            DUMMY_CHANNEL,
+        );
+        check_make_response(
+            &make_credential_response,
+            0x45,
+            env.customization().aaguid(),
+            0x20,
+            &[],
+        );
+
+        env.user_verification()
+            .set(|| Err(UserVerificationError::Invalid));
+        let make_credential_response =
+            ctap_state.process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL);
+        assert_eq!(
+            make_credential_response,
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+        );
+        assert_eq!(env.user_verification().retries(), 7);
+    }
+
+    #[test]
+    fn test_check_user_verification_retries() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_max_uv_retries(2);
+        env.user_verification()
+            .set(|| Err(UserVerificationError::Invalid));
+        for retries in [1, 0] {
+            assert_eq!(
+                check_user_verification(&mut env, DUMMY_CHANNEL),
+                Err(Ctap2StatusCode::CTAP2_ERR_UV_INVALID)
+            );
+            assert_eq!(uv_retries(&mut env), Ok(retries));
+        }
+        // The built-in method has retries left, but the authenticator blocks it.
+        assert_eq!(env.user_verification().retries(), 6);
+        env.user_verification().set(|| Ok(()));
+        assert_eq!(
+            check_user_verification(&mut env, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED)
+        );
+
+        storage::reset_uv_retries(&mut env).unwrap();
+        storage::decr_uv_retries(&mut env).unwrap();
+        assert_eq!(check_user_verification(&mut env, DUMMY_CHANNEL), Ok(()));
+        assert_eq!(uv_retries(&mut env), Ok(2));
+    }
+
+    #[test]
+    fn test_process_make_credential_shows_transaction() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.rp.rp_name = Some(String::from("Example"));
+        make_credential_params.user.user_name = Some(String::from("alice"));
+        // Without a display, nothing is shown.
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params.clone(), DUMMY_CHANNEL)
+            .is_ok());
+        assert!(env.display().shown().is_empty());
+
+        env.display().set_supported(true);
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL)
+            .is_ok());
+        assert_eq!(
+            env.display().shown(),
+            &[Transaction::Registration {
+                rp_id: String::from("example.com"),
+                rp_name: Some(String::from("Example")),
+                user_name: Some(String::from("alice")),
+            }]
+        );
+        assert_eq!(env.display().current(), None);
+    }
+
+    #[test]
+    fn test_confirm_transaction_declined() {
+        let mut env = TestEnv::default();
+        env.display().set_supported(true);
+        env.user_presence().set(|| Err(UserPresenceError::Declined));
+        let transaction = Transaction::Authentication {
+            rp_id: String::from("example.com"),
+            user_name: None,
+        };
+        let result = confirm_transaction(&mut env, DUMMY_CHANNEL, CommandClass::Credential, || {
+            transaction.clone()
+        });
+        assert_eq!(result, Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED));
+        assert_eq!(env.display().shown(), &[transaction]);
+        assert_eq!(env.display().current(), None);
+    }
+
+    #[test]
+    fn test_send_keepalive_processing_cancel() {
+        let mut env = TestEnv::default();
+        let mut cancel_packet = [0x00; 64];
+        cancel_packet[..4].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        cancel_packet[4] = 0x91;
+        env.push_incoming_packet(cancel_packet);
+        assert_eq!(
+            send_keepalive_processing(&mut env, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+    }
+
+    #[test]
+    fn test_send_keepalive_answers_other_channel() {
+        let mut env = TestEnv::default();
+        let other_cid = [0x12, 0x34, 0x56, 0x79];
+        let mut init_packet = [0x00; 64];
+        init_packet[..4].copy_from_slice(&other_cid);
+        init_packet[4..15].copy_from_slice(&[
+            0x86, 0x00, 0x08, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0,
+        ]);
+        env.push_incoming_packet(init_packet);
+        assert_eq!(send_keepalive_processing(&mut env, DUMMY_CHANNEL), Ok(()));
+
+        // The keepalive is still sent, followed by the INIT response.
+        let sent = env.sent_packets();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0][..4], [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(
+            sent[0][4..8],
+            [0xBB, 0x00, 0x01, KeepaliveStatus::Processing as u8]
+        );
+        assert_eq!(sent[1][..5], [0x12, 0x34, 0x56, 0x79, 0x86]);
+        assert_eq!(sent[1][15..19], other_cid);
+    }
+
+    #[test]
+    fn test_cancellation_token() {
+        let mut env = TestEnv::default();
+        let mut cancel_packet = [0x00; 64];
+        cancel_packet[..4].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        cancel_packet[4] = 0x91;
+
+        let mut token = CancellationToken::<TestEnv>::new(DUMMY_CHANNEL);
+        assert_eq!(token.check(&mut env), Ok(()));
+        assert_eq!(env.sent_packets().len(), 1);
+        // Checks within the keepalive delay don't send packets.
+        env.push_incoming_packet(cancel_packet);
+        assert_eq!(token.check(&mut env), Ok(()));
+        env.clock().advance(KEEPALIVE_DELAY_MS);
+        assert_eq!(
+            token.check(&mut env),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+        assert!(token.is_cancelled());
+        assert_eq!(
+            token.check(&mut env),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL)
+        );
+
+        let mut token = CancellationToken::<TestEnv>::never();
+        env.push_incoming_packet(cancel_packet);
+        assert_eq!(token.check(&mut env), Ok(()));
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn test_get_info_built_in_uv() {
+        let mut env = TestEnv::default();
+        let ctap_state = CtapState::<TestEnv>::new(&mut env);
+        env.user_verification().set(|| Ok(()));
+        let info = match ctap_state.process_get_info(&mut env).unwrap() {
+            ResponseData::AuthenticatorGetInfo(info) => info,
+            _ => panic!("Invalid response type"),
+        };
+        assert!(info.options.unwrap().contains(&(String::from("uv"), true)));
+    }
+
     #[test]
     fn test_non_resident_process_make_credential_with_pin() {
         let mut env = TestEnv::default();
@@ -2360,6 +3062,54 @@ mod test {
         check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
     }
 
+    #[test]
+    fn test_resident_process_get_assertion_stale_index() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+
+        let make_credential_params = create_minimal_make_credential_parameters();
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL)
+            .is_ok());
+        let mut make_credential_params = create_minimal_make_credential_parameters();
+        make_credential_params.user.user_id = vec![0x2D];
+        assert!(ctap_state
+            .process_make_credential(&mut env, make_credential_params, DUMMY_CHANNEL)
+            .is_ok());
+
+        let get_assertion_params = || AuthenticatorGetAssertionParameters {
+            rp_id: String::from("example.com"),
+            client_data_hash: vec![0xCD],
+            allow_list: None,
+            extensions: GetAssertionExtensions::default(),
+            options: GetAssertionOptions {
+                up: false,
+                uv: false,
+            },
+            pin_uv_auth_param: None,
+            pin_uv_auth_protocol: None,
+        };
+        assert!(ctap_state
+            .process_get_assertion(&mut env, get_assertion_params(), DUMMY_CHANNEL)
+            .is_ok());
+
+        // Deleting directly in storage bypasses the invalidation in process_fido_command.
+        let mut iter_result = Ok(());
+        let mut iter = storage::iter_credentials(&mut env, &mut iter_result).unwrap();
+        let credential_id = iter
+            .find(|(_, credential)| credential.user_handle == vec![0x2D])
+            .unwrap()
+            .1
+            .credential_id;
+        iter_result.unwrap();
+        storage::delete_credential(&mut env, &credential_id).unwrap();
+
+        let get_assertion_response =
+            ctap_state.process_get_assertion(&mut env, get_assertion_params(), DUMMY_CHANNEL);
+        let signature_counter = storage::global_signature_counter(&mut env).unwrap();
+        check_assertion_response(get_assertion_response, vec![0x1D], signature_counter, None);
+    }
+
     fn get_assertion_hmac_secret_params(
         key_agreement_key: EcdhSk<TestEnv>,
         key_agreement_response: ResponseData,
@@ -2443,9 +3193,10 @@ mod test {
             permissions: None,
             permissions_rp_id: None,
         };
-        let key_agreement_response = ctap_state
-            .client_pin
-            .process_command(&mut env, client_pin_params);
+        let key_agreement_response =
+            ctap_state
+                .client_pin
+                .process_command(&mut env, client_pin_params, DUMMY_CHANNEL);
         let get_assertion_params = get_assertion_hmac_secret_params(
             key_agreement_key,
             key_agreement_response.unwrap(),
@@ -2494,9 +3245,10 @@ mod test {
             permissions: None,
             permissions_rp_id: None,
         };
-        let key_agreement_response = ctap_state
-            .client_pin
-            .process_command(&mut env, client_pin_params);
+        let key_agreement_response =
+            ctap_state
+                .client_pin
+                .process_command(&mut env, client_pin_params, DUMMY_CHANNEL);
         let get_assertion_params = get_assertion_hmac_secret_params(
             key_agreement_key,
             key_agreement_response.unwrap(),
@@ -2545,6 +3297,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
         assert!(storage::store_credential(&mut env, credential).is_ok());
 
@@ -2597,6 +3350,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
         assert!(storage::store_credential(&mut env, credential).is_ok());
 
@@ -2713,6 +3467,7 @@ mod test {
             user_icon: None,
             cred_blob: Some(vec![0xCB]),
             large_blob_key: None,
+            creation_time: None,
         };
         assert!(storage::store_credential(&mut env, credential).is_ok());
 
@@ -2832,6 +3587,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: Some(vec![0x1C; 32]),
+            creation_time: None,
         };
         assert!(storage::store_credential(&mut env, credential).is_ok());
 
@@ -3106,6 +3862,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
         assert!(storage::store_credential(&mut env, credential_source).is_ok());
         assert!(storage::count_credentials(&mut env).unwrap() > 0);
@@ -3114,6 +3871,44 @@ mod test {
         let expected_response = vec![0x00];
         assert_eq!(reset_reponse, expected_response);
         assert!(storage::count_credentials(&mut env).unwrap() == 0);
+        let entries = audit_log::entries(&mut env).unwrap();
+        assert_eq!(entries.last().unwrap().event, AuditEvent::Reset);
+    }
+
+    #[test]
+    fn test_process_reset_corrupted_store() {
+        let mut env = TestEnv::default();
+        env.store().insert(100, &[0x38; 8]).unwrap();
+        env.store().insert(101, &[0x5c; 8]).unwrap();
+        // Clears the last word of the first entry, after the 2 words of page info.
+        env.corrupt_store(StorageIndex { page: 0, byte: 16 }, &[0x00; 4]);
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        assert!(storage::is_store_corrupted(&mut env));
+        assert_eq!(
+            env.store().insert(102, &[]),
+            Err(StoreError::InvalidStorage)
+        );
+        match ctap_state.process_get_info(&mut env).unwrap() {
+            ResponseData::AuthenticatorGetInfo(response) => {
+                let option = (String::from(STORE_CORRUPTED_OPTION), true);
+                assert_eq!(response.options, Some(vec![option]));
+            }
+            _ => panic!("Invalid response type"),
+        }
+
+        let reset_reponse = ctap_state.process_command(&mut env, &[0x07], DUMMY_CHANNEL);
+        assert_eq!(reset_reponse, vec![0x00]);
+        assert!(!storage::is_store_corrupted(&mut env));
+        assert_eq!(env.store().find(101), Ok(None));
+        match ctap_state.process_get_info(&mut env).unwrap() {
+            ResponseData::AuthenticatorGetInfo(response) => {
+                let options = response.options.unwrap();
+                assert!(options
+                    .iter()
+                    .all(|(name, _)| name != STORE_CORRUPTED_OPTION));
+            }
+            _ => panic!("Invalid response type"),
+        }
     }
 
     #[test]
@@ -3165,6 +3960,64 @@ mod test {
         assert_eq!(reponse, expected_response);
     }
 
+    #[test]
+    fn test_process_command_max_msg_size() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let max_msg_size = env.customization().max_msg_size();
+
+        let mut command = vec![0xDF; max_msg_size];
+        let response = ctap_state.process_command(&mut env, &command, DUMMY_CHANNEL);
+        assert_eq!(
+            response,
+            vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8]
+        );
+        command.push(0xDF);
+        let response = ctap_state.process_command(&mut env, &command, DUMMY_CHANNEL);
+        assert_eq!(
+            response,
+            vec![Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH as u8]
+        );
+    }
+
+    #[test]
+    fn test_process_command_response_max_msg_size() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let info_response = ctap_state.process_command(&mut env, &[0x04], DUMMY_CHANNEL);
+        assert_eq!(info_response[0], Ctap2StatusCode::CTAP2_OK as u8);
+
+        // GetInfo includes maxMsgSize, which has the same encoded length for both values.
+        env.customization_mut()
+            .set_max_msg_size(info_response.len());
+        let response = ctap_state.process_command(&mut env, &[0x04], DUMMY_CHANNEL);
+        assert_eq!(response.len(), info_response.len());
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        env.customization_mut()
+            .set_max_msg_size(info_response.len() - 1);
+        let response = ctap_state.process_command(&mut env, &[0x04], DUMMY_CHANNEL);
+        assert_eq!(
+            response,
+            vec![Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE as u8]
+        );
+    }
+
+    #[test]
+    fn test_process_idle_work() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        env.customization_mut().set_store_compaction_threshold(1024);
+        assert!(!ctap_state.process_idle_work(&mut env));
+        while store_capacity(&mut env).unwrap().immediate >= 1024 {
+            env.store().insert(100, &[0x55; 512]).unwrap();
+        }
+        let before = store_capacity(&mut env).unwrap();
+        assert!(ctap_state.process_idle_work(&mut env));
+        assert!(store_capacity(&mut env).unwrap().immediate > before.immediate);
+        while ctap_state.process_idle_work(&mut env) {}
+        assert!(store_capacity(&mut env).unwrap().immediate >= 1024);
+    }
+
     #[test]
     fn test_signature_counter() {
         let mut env = TestEnv::default();
@@ -3174,7 +4027,7 @@ mod test {
         assert!(last_counter > 0);
         for _ in 0..100 {
             assert!(ctap_state
-                .increment_global_signature_counter(&mut env)
+                .increment_signature_counter(&mut env, &[0x55; 32])
                 .is_ok());
             let next_counter = storage::global_signature_counter(&mut env).unwrap();
             assert!(next_counter > last_counter);
@@ -3182,6 +4035,41 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_rp_signature_counters() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_use_rp_signature_counters(true);
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        let rp_id_hash = [0x55; 32];
+        let other_rp_id_hash = [0xAA; 32];
+
+        let auth_data = ctap_state
+            .generate_auth_data(&mut env, &rp_id_hash, 0x01)
+            .unwrap();
+        assert_eq!(BigEndian::read_u32(&auth_data[33..]), 0);
+        let mut last_counter = 0;
+        for _ in 0..10 {
+            assert!(ctap_state
+                .increment_signature_counter(&mut env, &rp_id_hash)
+                .is_ok());
+            let auth_data = ctap_state
+                .generate_auth_data(&mut env, &rp_id_hash, 0x01)
+                .unwrap();
+            let next_counter = BigEndian::read_u32(&auth_data[33..]);
+            assert!(next_counter > last_counter);
+            last_counter = next_counter;
+        }
+        // Other relying parties and the global counter are unaffected.
+        let auth_data = ctap_state
+            .generate_auth_data(&mut env, &other_rp_id_hash, 0x01)
+            .unwrap();
+        assert_eq!(BigEndian::read_u32(&auth_data[33..]), 0);
+        assert_eq!(
+            storage::global_signature_counter(&mut env),
+            Ok(INITIAL_SIGNATURE_COUNTER)
+        );
+    }
+
     #[test]
     fn test_permission_timeout() {
         let mut env = TestEnv::default();
@@ -3271,6 +4159,7 @@ mod test {
             user_icon: Some("icon".to_string()),
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
 
         let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
@@ -3341,8 +4230,9 @@ mod test {
     fn test_check_user_presence() {
         // This TestEnv always returns successful user_presence checks.
         let mut env = TestEnv::default();
-        let response = check_user_presence(&mut env, DUMMY_CHANNEL);
+        let response = check_user_presence(&mut env, DUMMY_CHANNEL, CommandClass::Credential);
         assert!(matches!(response, Ok(_)));
+        assert_eq!(env.user_feedback().last_state(), Some(FeedbackState::Idle));
     }
 
     #[test]
@@ -3354,13 +4244,97 @@ mod test {
 
         let mut env = TestEnv::default();
         env.user_presence().set(user_presence_timeout);
-        let response = check_user_presence(&mut env, DUMMY_CHANNEL);
+        let response = check_user_presence(&mut env, DUMMY_CHANNEL, CommandClass::Credential);
         assert!(matches!(
             response,
             Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
         ));
     }
 
+    #[test]
+    fn test_check_user_presence_class_timeout() {
+        let waits = Rc::new(Cell::new(0));
+        let counter = waits.clone();
+        let mut env = TestEnv::default();
+        env.user_presence().set(move || {
+            counter.set(counter.get() + 1);
+            Err(UserPresenceError::Timeout)
+        });
+        env.user_presence()
+            .set_timeout_ms(CommandClass::Reset, 5 * KEEPALIVE_DELAY_MS);
+        let response = check_user_presence(&mut env, DUMMY_CHANNEL, CommandClass::Reset);
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+        );
+        // The first check is made without timeout.
+        assert_eq!(waits.get(), 6);
+
+        waits.set(0);
+        let response = check_user_presence(&mut env, DUMMY_CHANNEL, CommandClass::Selection);
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+        );
+        assert_eq!(waits.get(), TOUCH_TIMEOUT_MS / KEEPALIVE_DELAY_MS + 1);
+    }
+
+    #[test]
+    fn test_watchdog_fed_while_waiting() {
+        let mut env = TestEnv::default();
+        env.watchdog().start(1000).unwrap();
+        env.user_presence().set(|| Err(UserPresenceError::Timeout));
+        env.user_presence()
+            .set_timeout_ms(CommandClass::Selection, 5 * KEEPALIVE_DELAY_MS);
+        let response = check_user_presence(&mut env, DUMMY_CHANNEL, CommandClass::Selection);
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_USER_ACTION_TIMEOUT)
+        );
+        assert_eq!(env.watchdog().feed_count(), 6);
+    }
+
+    #[test]
+    fn test_watchdog_fed_around_command() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        env.watchdog().start(1000).unwrap();
+        ctap_state.process_command(&mut env, &[0x04], DUMMY_CHANNEL);
+        assert_eq!(env.watchdog().feed_count(), 2);
+    }
+
+    #[test]
+    fn test_watchdog_fed_by_cancellation_check() {
+        let mut env = TestEnv::default();
+        env.watchdog().start(1000).unwrap();
+        let mut cancellation = CancellationToken::<TestEnv>::never();
+        assert_eq!(cancellation.check(&mut env), Ok(()));
+        assert_eq!(cancellation.check(&mut env), Ok(()));
+        assert_eq!(env.watchdog().feed_count(), 2);
+    }
+
+    #[test]
+    fn test_error_feedback() {
+        let mut env = TestEnv::default();
+        let mut ctap_state = CtapState::<TestEnv>::new(&mut env);
+        env.user_presence().set(|| Err(UserPresenceError::Declined));
+
+        // Probing for credentials is a regular flow and not signaled.
+        let response = ctap_state.process_command(&mut env, &[0x08], DUMMY_CHANNEL);
+        assert_eq!(response, vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]);
+        assert!(!ctap_state.has_recent_error(&mut env));
+
+        // AuthenticatorSelection fails when the user declines.
+        let response = ctap_state.process_command(&mut env, &[0x0B], DUMMY_CHANNEL);
+        assert_eq!(
+            response,
+            vec![Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED as u8]
+        );
+        assert!(ctap_state.has_recent_error(&mut env));
+        env.clock().advance(ERROR_FEEDBACK_DURATION_MS);
+        assert!(!ctap_state.has_recent_error(&mut env));
+    }
+
     #[test]
     fn test_channel_interleaving() {
         let mut env = TestEnv::default();
@@ -0,0 +1,201 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! PIN complexity requirements on top of the minimum PIN length.
+//!
+//! Enterprise deployments configure them through `Customization` to match their password
+//! policies. New PINs are checked in setPIN and changePIN. The current PIN is only stored as a
+//! hash, so requirements enabled later can't be checked on it. Instead, each PIN is stored with
+//! the requirements it passed, and PINs missing one are flagged with forcePINChange at boot.
+
+use crate::api::customization::Customization;
+use crate::ctap::status_code::Ctap2StatusCode;
+use crate::ctap::storage;
+use crate::env::Env;
+use alloc::vec::Vec;
+
+/// The PIN contains both letters and digits.
+pub const ALPHANUMERIC: u8 = 0x01;
+
+/// Returns the requirements of the customization that are recorded with the PIN.
+pub fn requirements(env: &mut impl Env) -> u8 {
+    if env.customization().requires_alphanumeric_pin() {
+        ALPHANUMERIC
+    } else {
+        0
+    }
+}
+
+/// Checks a new PIN against the policy.
+///
+/// The minimum length is checked by the caller, since CTAP defines it.
+pub fn check(env: &mut impl Env, pin: &str) -> Result<(), Ctap2StatusCode> {
+    if env.customization().rejects_trivial_pins() && is_trivial(pin) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+    }
+    if env
+        .customization()
+        .pin_blocklist()
+        .iter()
+        .any(|blocked| blocked.eq_ignore_ascii_case(pin))
+    {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+    }
+    if requirements(env) & ALPHANUMERIC != 0
+        && !(pin.chars().any(char::is_alphabetic) && pin.chars().any(char::is_numeric))
+    {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION);
+    }
+    Ok(())
+}
+
+/// Forces a PIN change if the current PIN doesn't meet the policy of this firmware.
+pub fn check_current_pin(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
+    let (code_point_length, pin_requirements) = match (
+        storage::pin_code_point_length(env)?,
+        storage::pin_requirements(env)?,
+    ) {
+        (Some(length), Some(pin_requirements)) => (length, pin_requirements),
+        _ => return Ok(()),
+    };
+    let is_too_short = code_point_length < storage::min_pin_length(env)?;
+    let misses_requirements = requirements(env) & !pin_requirements != 0;
+    if (is_too_short || misses_requirements) && !storage::has_force_pin_change(env)? {
+        storage::force_pin_change(env)?;
+    }
+    Ok(())
+}
+
+/// Returns whether the PIN repeats one character or counts up or down.
+fn is_trivial(pin: &str) -> bool {
+    let code_points = pin.chars().map(u32::from).collect::<Vec<_>>();
+    let steps = code_points
+        .windows(2)
+        .map(|pair| pair[1] as i64 - pair[0] as i64)
+        .collect::<Vec<_>>();
+    match steps.first() {
+        None => true,
+        Some(step) => step.abs() <= 1 && steps.iter().all(|s| s == step),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::env::test::TestEnv;
+    use alloc::string::String;
+    use alloc::vec;
+
+    #[test]
+    fn test_is_trivial() {
+        assert!(is_trivial("0000"));
+        assert!(is_trivial("1234"));
+        assert!(is_trivial("987654"));
+        assert!(is_trivial("abcdef"));
+        assert!(is_trivial("ZYX"));
+        assert!(!is_trivial("1235"));
+        assert!(!is_trivial("1357"));
+        assert!(!is_trivial("0011"));
+        assert!(!is_trivial("4729"));
+    }
+
+    #[test]
+    fn test_check_default() {
+        let mut env = TestEnv::default();
+        assert_eq!(requirements(&mut env), 0);
+        assert_eq!(check(&mut env, "1234"), Ok(()));
+        assert_eq!(check(&mut env, "password"), Ok(()));
+    }
+
+    #[test]
+    fn test_check_trivial() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_rejects_trivial_pins(true);
+        assert_eq!(
+            check(&mut env, "1111"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(
+            check(&mut env, "4321"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(check(&mut env, "4729"), Ok(()));
+    }
+
+    #[test]
+    fn test_check_blocklist() {
+        let mut env = TestEnv::default();
+        env.customization_mut()
+            .set_pin_blocklist(vec![String::from("password"), String::from("2580")]);
+        assert_eq!(
+            check(&mut env, "PassWord"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(
+            check(&mut env, "2580"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(check(&mut env, "passwords"), Ok(()));
+    }
+
+    #[test]
+    fn test_check_alphanumeric() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_requires_alphanumeric_pin(true);
+        assert_eq!(requirements(&mut env), ALPHANUMERIC);
+        assert_eq!(
+            check(&mut env, "4729"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(
+            check(&mut env, "secret"),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_POLICY_VIOLATION)
+        );
+        assert_eq!(check(&mut env, "s3cret"), Ok(()));
+    }
+
+    #[test]
+    fn test_check_current_pin() {
+        let mut env = TestEnv::default();
+        // Without a PIN, there is nothing to change.
+        env.customization_mut().set_requires_alphanumeric_pin(true);
+        assert_eq!(check_current_pin(&mut env), Ok(()));
+        assert_eq!(storage::has_force_pin_change(&mut env), Ok(false));
+
+        // PINs checked against the requirement stay valid.
+        storage::set_pin(&mut env, &[0x55; 16], 6).unwrap();
+        assert_eq!(check_current_pin(&mut env), Ok(()));
+        assert_eq!(storage::has_force_pin_change(&mut env), Ok(false));
+
+        // PINs from before the requirement need a change.
+        env.customization_mut().set_requires_alphanumeric_pin(false);
+        storage::set_pin(&mut env, &[0x55; 16], 6).unwrap();
+        env.customization_mut().set_requires_alphanumeric_pin(true);
+        assert_eq!(check_current_pin(&mut env), Ok(()));
+        assert_eq!(storage::has_force_pin_change(&mut env), Ok(true));
+    }
+
+    #[cfg(feature = "config_command")]
+    #[test]
+    fn test_check_current_pin_too_short() {
+        let mut env = TestEnv::default();
+        storage::set_pin(&mut env, &[0x55; 16], 4).unwrap();
+        assert_eq!(check_current_pin(&mut env), Ok(()));
+        assert_eq!(storage::has_force_pin_change(&mut env), Ok(false));
+
+        storage::set_min_pin_length(&mut env, 6).unwrap();
+        assert_eq!(check_current_pin(&mut env), Ok(()));
+        assert_eq!(storage::has_force_pin_change(&mut env), Ok(true));
+    }
+}
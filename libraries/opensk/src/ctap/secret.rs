@@ -33,7 +33,7 @@ use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::ops::{Deref, DerefMut};
-use zeroize::Zeroize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 /// Defines a secret that is zeroized on Drop.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -74,6 +74,14 @@ impl<T: Zeroize + ?Sized> Drop for Secret<T> {
     }
 }
 
+impl<T: Zeroize + ?Sized> Zeroize for Secret<T> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize + ?Sized> ZeroizeOnDrop for Secret<T> {}
+
 impl<T: Zeroize + ?Sized> Deref for Secret<T> {
     type Target = T;
 
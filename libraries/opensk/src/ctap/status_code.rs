@@ -12,8 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::api::display::DisplayError;
 use crate::api::user_presence::UserPresenceError;
+use crate::api::user_verification::UserVerificationError;
 use crate::api::{attestation_store, key_store};
+#[cfg(feature = "bbs")]
+use bbs::BBSError;
+use opensk_vendor_protocol::DecodeError;
 
 // CTAP specification (version 20190130) section 6.3
 // For now, only the CTAP2 codes are here, the CTAP1 are not included.
@@ -73,6 +78,11 @@ pub enum Ctap2StatusCode {
     _CTAP2_ERR_EXTENSION_FIRST = 0xE0,
     _CTAP2_ERR_EXTENSION_LAST = 0xEF,
     _CTAP2_ERR_VENDOR_FIRST = 0xF0,
+    /// The BBS issuer is not in the issuer registry of the device.
+    ///
+    /// Devices without registered issuers accept any issuer.
+    CTAP2_ERR_VENDOR_BBS_UNKNOWN_ISSUER = 0xF1,
+
     /// An internal invariant is broken.
     ///
     /// This type of error is unexpected and the current state is undefined.
@@ -82,6 +92,39 @@ pub enum Ctap2StatusCode {
     ///
     /// It may be possible that some of those errors are actually internal errors.
     CTAP2_ERR_VENDOR_HARDWARE_FAILURE = 0xF3,
+
+    /// The device is not ready to be locked down.
+    ///
+    /// Locking it anyway could leave it unusable, e.g. without attestation material.
+    CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED = 0xF4,
+
+    // BBS errors use 0xF5 to 0xFC, so hosts can tell what to fix without a debugger.
+    /// No link secret is provisioned.
+    CTAP2_ERR_VENDOR_BBS_NO_LINK_SECRET = 0xF5,
+
+    /// No blind is stored for the issuer of the credential.
+    CTAP2_ERR_VENDOR_BBS_CREDENTIAL_NOT_FOUND = 0xF6,
+
+    /// The issuer policy forbids disclosing one of the requested messages.
+    CTAP2_ERR_VENDOR_BBS_POLICY_VIOLATION = 0xF7,
+
+    /// The credential has more messages than the device supports.
+    CTAP2_ERR_VENDOR_BBS_MESSAGE_LIMIT_EXCEEDED = 0xF8,
+
+    /// See `BBSError::CommitmentGeneration`.
+    CTAP2_ERR_VENDOR_BBS_COMMITMENT_FAILED = 0xF9,
+
+    /// See `BBSError::InvalidDisclosedIndex`.
+    CTAP2_ERR_VENDOR_BBS_INVALID_DISCLOSED_INDEX = 0xFA,
+
+    /// See `BBSError::InvalidProverBlind`.
+    CTAP2_ERR_VENDOR_BBS_INVALID_PROVER_BLIND = 0xFB,
+
+    /// See `BBSError::ProofGeneration`.
+    CTAP2_ERR_VENDOR_BBS_PROOF_FAILED = 0xFC,
+
+    /// The command would take longer than `Customization::max_command_duration_ms`.
+    CTAP2_ERR_VENDOR_TIME_BUDGET_EXCEEDED = 0xFD,
     _CTAP2_ERR_VENDOR_LAST = 0xFF,
 }
 
@@ -96,6 +139,37 @@ impl From<UserPresenceError> for Ctap2StatusCode {
     }
 }
 
+impl From<UserVerificationError> for Ctap2StatusCode {
+    fn from(user_verification_error: UserVerificationError) -> Self {
+        match user_verification_error {
+            UserVerificationError::Invalid => Self::CTAP2_ERR_UV_INVALID,
+            UserVerificationError::Blocked => Self::CTAP2_ERR_UV_BLOCKED,
+            UserVerificationError::Canceled => Self::CTAP2_ERR_KEEPALIVE_CANCEL,
+            UserVerificationError::Timeout => Self::CTAP2_ERR_USER_ACTION_TIMEOUT,
+            UserVerificationError::Fail => Self::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
+        }
+    }
+}
+
+impl From<DisplayError> for Ctap2StatusCode {
+    fn from(display_error: DisplayError) -> Self {
+        match display_error {
+            DisplayError::Fail => Self::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
+        }
+    }
+}
+
+impl From<DecodeError> for Ctap2StatusCode {
+    fn from(decode_error: DecodeError) -> Self {
+        match decode_error {
+            DecodeError::UnexpectedType => Self::CTAP2_ERR_CBOR_UNEXPECTED_TYPE,
+            DecodeError::MissingParameter => Self::CTAP2_ERR_MISSING_PARAMETER,
+            DecodeError::InvalidParameter => Self::CTAP1_ERR_INVALID_PARAMETER,
+            DecodeError::InvalidSubcommand => Self::CTAP2_ERR_INVALID_SUBCOMMAND,
+        }
+    }
+}
+
 impl From<key_store::Error> for Ctap2StatusCode {
     fn from(_: key_store::Error) -> Self {
         Self::CTAP2_ERR_VENDOR_INTERNAL_ERROR
@@ -112,3 +186,15 @@ impl From<attestation_store::Error> for Ctap2StatusCode {
         }
     }
 }
+
+#[cfg(feature = "bbs")]
+impl From<BBSError> for Ctap2StatusCode {
+    fn from(bbs_error: BBSError) -> Self {
+        match bbs_error {
+            BBSError::CommitmentGeneration => Self::CTAP2_ERR_VENDOR_BBS_COMMITMENT_FAILED,
+            BBSError::InvalidDisclosedIndex => Self::CTAP2_ERR_VENDOR_BBS_INVALID_DISCLOSED_INDEX,
+            BBSError::InvalidProverBlind => Self::CTAP2_ERR_VENDOR_BBS_INVALID_PROVER_BLIND,
+            BBSError::ProofGeneration => Self::CTAP2_ERR_VENDOR_BBS_PROOF_FAILED,
+        }
+    }
+}
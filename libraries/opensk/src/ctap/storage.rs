@@ -16,14 +16,15 @@ mod key;
 
 #[cfg(feature = "config_command")]
 use crate::api::attestation_store::{self, AttestationStore};
-use crate::api::customization::Customization;
+use crate::api::customization::{Customization, AAGUID_LENGTH};
 use crate::api::key_store::KeyStore;
+use crate::ctap::audit_log::AuditEntry;
 use crate::ctap::client_pin::PIN_AUTH_LENGTH;
 use crate::ctap::data_formats::{
     extract_array, extract_text_string, PublicKeyCredentialSource, PublicKeyCredentialUserEntity,
 };
 use crate::ctap::status_code::Ctap2StatusCode;
-use crate::ctap::INITIAL_SIGNATURE_COUNTER;
+use crate::ctap::{pin_policy, INITIAL_SIGNATURE_COUNTER};
 use crate::env::{AesKey, Env};
 use alloc::string::String;
 use alloc::vec;
@@ -40,8 +41,10 @@ struct PinProperties {
     hash: [u8; PIN_AUTH_LENGTH],
 
     /// Length of the current PIN in code points.
-    #[cfg_attr(not(feature = "config_command"), allow(dead_code))]
     code_point_length: u8,
+
+    /// Policy requirements the PIN was checked against, see `pin_policy`.
+    requirements: u8,
 }
 
 /// Initializes the store by creating missing objects.
@@ -97,8 +100,7 @@ pub fn find_credential_item(
 
 /// Returns the first matching credential.
 ///
-/// Returns `None` if no credentials are matched or if `check_cred_protect` is set and the first
-/// matched credential requires user verification.
+/// Returns `None` if no credentials are matched. The caller checks the credProtect policy.
 pub fn find_credential(
     env: &mut impl Env,
     rp_id: &str,
@@ -251,20 +253,181 @@ pub fn incr_global_signature_counter(
     Ok(())
 }
 
+/// Size of the RP ID hash in signature counter entries.
+const RP_SIGNATURE_COUNTER_HASH_SIZE: usize = 32;
+
+/// Size of a signature counter entry, the RP ID hash followed by its counter.
+const RP_SIGNATURE_COUNTER_ENTRY_SIZE: usize = RP_SIGNATURE_COUNTER_HASH_SIZE + 4;
+
+/// Maximum number of relying parties in one key of signature counters.
+///
+/// Each increment rewrites the whole key, so it is kept small.
+const MAX_RP_SIGNATURE_COUNTERS_PER_KEY: usize = 8;
+
+/// Returns the key and entries of the signature counters shared with a relying party.
+fn rp_signature_counter_entries(
+    env: &mut impl Env,
+    rp_id_hash: &[u8],
+) -> Result<(usize, Vec<u8>), Ctap2StatusCode> {
+    if rp_id_hash.len() != RP_SIGNATURE_COUNTER_HASH_SIZE {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+    }
+    let num_keys = key::RP_SIGNATURE_COUNTERS.end - key::RP_SIGNATURE_COUNTERS.start;
+    let key = key::RP_SIGNATURE_COUNTERS.start + rp_id_hash[0] as usize % num_keys;
+    let entries = env.store().find(key)?.unwrap_or_default();
+    if entries.len() % RP_SIGNATURE_COUNTER_ENTRY_SIZE != 0 {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
+    }
+    Ok((key, entries))
+}
+
+/// Returns the offset of the counter of a relying party in its entries.
+fn rp_signature_counter_offset(entries: &[u8], rp_id_hash: &[u8]) -> Option<usize> {
+    entries
+        .chunks(RP_SIGNATURE_COUNTER_ENTRY_SIZE)
+        .position(|entry| &entry[..RP_SIGNATURE_COUNTER_HASH_SIZE] == rp_id_hash)
+        .map(|index| index * RP_SIGNATURE_COUNTER_ENTRY_SIZE + RP_SIGNATURE_COUNTER_HASH_SIZE)
+}
+
+/// Returns the signature counter of a relying party.
+///
+/// Relying parties without a stored counter get 0.
+pub fn rp_signature_counter(env: &mut impl Env, rp_id_hash: &[u8]) -> Result<u32, Ctap2StatusCode> {
+    let (_, entries) = rp_signature_counter_entries(env, rp_id_hash)?;
+    Ok(match rp_signature_counter_offset(&entries, rp_id_hash) {
+        None => 0,
+        Some(offset) => u32::from_ne_bytes(*array_ref!(&entries, offset, 4)),
+    })
+}
+
+/// Increments the signature counter of a relying party.
+///
+/// If its key is full, a new relying party keeps a counter of 0.
+pub fn incr_rp_signature_counter(
+    env: &mut impl Env,
+    rp_id_hash: &[u8],
+    increment: u32,
+) -> Result<(), Ctap2StatusCode> {
+    let (key, mut entries) = rp_signature_counter_entries(env, rp_id_hash)?;
+    match rp_signature_counter_offset(&entries, rp_id_hash) {
+        Some(offset) => {
+            let old_value = u32::from_ne_bytes(*array_ref!(&entries, offset, 4));
+            // In hopes that servers handle the wrapping gracefully.
+            let new_value = old_value.wrapping_add(increment);
+            entries[offset..offset + 4].copy_from_slice(&new_value.to_ne_bytes());
+        }
+        None => {
+            if entries.len() / RP_SIGNATURE_COUNTER_ENTRY_SIZE >= MAX_RP_SIGNATURE_COUNTERS_PER_KEY
+            {
+                return Ok(());
+            }
+            entries.extend_from_slice(rp_id_hash);
+            entries.extend_from_slice(&increment.to_ne_bytes());
+        }
+    }
+    env.store().insert(key, &entries)?;
+    Ok(())
+}
+
+/// Returns the number of boots.
+pub fn boot_counter(env: &mut impl Env) -> Result<u32, Ctap2StatusCode> {
+    match env.store().find(key::BOOT_COUNTER)? {
+        None => Ok(0),
+        Some(value) if value.len() == 4 => Ok(u32::from_ne_bytes(*array_ref!(&value, 0, 4))),
+        Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+    }
+}
+
+/// Increments the boot counter and returns its new value.
+pub fn incr_boot_counter(env: &mut impl Env) -> Result<u32, Ctap2StatusCode> {
+    let new_value = boot_counter(env)?.saturating_add(1);
+    env.store()
+        .insert(key::BOOT_COUNTER, &new_value.to_ne_bytes())?;
+    Ok(new_value)
+}
+
+/// Returns the AAGUID, the provisioned one if any.
+pub fn aaguid(env: &mut impl Env) -> Result<[u8; AAGUID_LENGTH], Ctap2StatusCode> {
+    match env.store().find(key::AAGUID)? {
+        None => Ok(*env.customization().aaguid()),
+        Some(value) if value.len() == AAGUID_LENGTH => Ok(*array_ref!(&value, 0, AAGUID_LENGTH)),
+        Some(_) => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+    }
+}
+
+/// Returns whether an AAGUID was provisioned, instead of the one of the firmware.
+pub fn has_custom_aaguid(env: &mut impl Env) -> Result<bool, Ctap2StatusCode> {
+    Ok(env.store().find(key::AAGUID)?.is_some())
+}
+
+/// Provisions the AAGUID of a customer.
+///
+/// It survives resets, like the attestation material it belongs to.
+pub fn set_custom_aaguid(
+    env: &mut impl Env,
+    aaguid: &[u8; AAGUID_LENGTH],
+) -> Result<(), Ctap2StatusCode> {
+    Ok(env.store().insert(key::AAGUID, aaguid)?)
+}
+
+/// Maximum number of audit log entries before the oldest is overwritten.
+pub const MAX_AUDIT_ENTRIES: usize = key::AUDIT_LOG.end - key::AUDIT_LOG.start;
+
+/// Returns all audit log entries, in storage order.
+pub fn audit_entries(env: &mut impl Env) -> Result<Vec<AuditEntry>, Ctap2StatusCode> {
+    let mut entries = Vec::new();
+    for key in key::AUDIT_LOG {
+        if let Some(value) = env.store().find(key)? {
+            entries.push(AuditEntry::from_bytes(&value)?);
+        }
+    }
+    Ok(entries)
+}
+
+/// Writes the entry in a free slot, or replaces the oldest entry.
+pub fn append_audit_entry(env: &mut impl Env, entry: AuditEntry) -> Result<(), Ctap2StatusCode> {
+    let mut oldest: Option<(usize, AuditEntry)> = None;
+    let mut free_key = None;
+    for key in key::AUDIT_LOG {
+        match env.store().find(key)? {
+            None => {
+                free_key = Some(key);
+                break;
+            }
+            Some(value) => {
+                let stored = AuditEntry::from_bytes(&value)?;
+                if oldest.map_or(true, |(_, o)| stored.timestamp < o.timestamp) {
+                    oldest = Some((key, stored));
+                }
+            }
+        }
+    }
+    let key = free_key
+        .or_else(|| oldest.map(|(key, _)| key))
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+    env.store().insert(key, &entry.to_bytes())?;
+    Ok(())
+}
+
 /// Reads the PIN properties and wraps them into PinProperties.
 fn pin_properties(env: &mut impl Env) -> Result<Option<PinProperties>, Ctap2StatusCode> {
-    let pin_properties = match env.store().find(key::PIN_PROPERTIES)? {
+    let pin_properties = match env.secrets_store().find(key::PIN_PROPERTIES)? {
         None => return Ok(None),
         Some(pin_properties) => pin_properties,
     };
-    const PROPERTIES_LENGTH: usize = PIN_AUTH_LENGTH + 1;
-    match pin_properties.len() {
-        PROPERTIES_LENGTH => Ok(Some(PinProperties {
-            hash: *array_ref![pin_properties, 1, PIN_AUTH_LENGTH],
-            code_point_length: pin_properties[0],
-        })),
-        _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
-    }
+    // PINs set before the policy existed lack its trailing byte.
+    const LEGACY_PROPERTIES_LENGTH: usize = PIN_AUTH_LENGTH + 1;
+    const PROPERTIES_LENGTH: usize = PIN_AUTH_LENGTH + 2;
+    let requirements = match pin_properties.len() {
+        LEGACY_PROPERTIES_LENGTH => 0,
+        PROPERTIES_LENGTH => pin_properties[PROPERTIES_LENGTH - 1],
+        _ => return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+    };
+    Ok(Some(PinProperties {
+        hash: *array_ref![pin_properties, 1, PIN_AUTH_LENGTH],
+        code_point_length: pin_properties[0],
+        requirements,
+    }))
 }
 
 /// Returns the PIN hash if defined.
@@ -273,23 +436,30 @@ pub fn pin_hash(env: &mut impl Env) -> Result<Option<[u8; PIN_AUTH_LENGTH]>, Cta
 }
 
 /// Returns the length of the currently set PIN if defined.
-#[cfg(feature = "config_command")]
 pub fn pin_code_point_length(env: &mut impl Env) -> Result<Option<u8>, Ctap2StatusCode> {
     Ok(pin_properties(env)?.map(|p| p.code_point_length))
 }
 
+/// Returns the policy requirements the currently set PIN was checked against, if defined.
+pub fn pin_requirements(env: &mut impl Env) -> Result<Option<u8>, Ctap2StatusCode> {
+    Ok(pin_properties(env)?.map(|p| p.requirements))
+}
+
 /// Sets the PIN hash and length.
 ///
-/// If it was already defined, it is updated.
+/// If it was already defined, it is updated. The PIN is recorded as compliant with the current
+/// policy, so callers check it first.
 pub fn set_pin(
     env: &mut impl Env,
     pin_hash: &[u8; PIN_AUTH_LENGTH],
     pin_code_point_length: u8,
 ) -> Result<(), Ctap2StatusCode> {
-    let mut pin_properties = [0; 1 + PIN_AUTH_LENGTH];
+    let mut pin_properties = [0; 2 + PIN_AUTH_LENGTH];
     pin_properties[0] = pin_code_point_length;
-    pin_properties[1..].clone_from_slice(pin_hash);
-    Ok(env.store().transaction(&[
+    pin_properties[1..=PIN_AUTH_LENGTH].clone_from_slice(pin_hash);
+    pin_properties[1 + PIN_AUTH_LENGTH] = pin_policy::requirements(env);
+    // Both live in the secrets store, so that the transaction stays atomic.
+    Ok(env.secrets_store().transaction(&[
         StoreUpdate::Insert {
             key: key::PIN_PROPERTIES,
             value: &pin_properties[..],
@@ -324,11 +494,38 @@ pub fn reset_pin_retries(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
     Ok(env.store().remove(key::PIN_RETRIES)?)
 }
 
+/// Returns the number of remaining built-in user verification retries.
+pub fn uv_retries(env: &mut impl Env) -> Result<u8, Ctap2StatusCode> {
+    match env.store().find(key::UV_RETRIES)? {
+        None => Ok(env.customization().max_uv_retries()),
+        Some(value) if value.len() == 1 => Ok(value[0]),
+        _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
+    }
+}
+
+/// Decrements the number of remaining built-in user verification retries.
+pub fn decr_uv_retries(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
+    let old_value = uv_retries(env)?;
+    let new_value = old_value.saturating_sub(1);
+    if new_value != old_value {
+        env.store().insert(key::UV_RETRIES, &[new_value])?;
+    }
+    Ok(())
+}
+
+/// Resets the number of remaining built-in user verification retries.
+pub fn reset_uv_retries(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
+    Ok(env.store().remove(key::UV_RETRIES)?)
+}
+
 /// Returns the minimum PIN length.
+///
+/// It never drops below the customization, so that firmware updates can raise it.
 pub fn min_pin_length(env: &mut impl Env) -> Result<u8, Ctap2StatusCode> {
+    let default_min_pin_length = env.customization().default_min_pin_length();
     match env.store().find(key::MIN_PIN_LENGTH)? {
-        None => Ok(env.customization().default_min_pin_length()),
-        Some(value) if value.len() == 1 => Ok(value[0]),
+        None => Ok(default_min_pin_length),
+        Some(value) if value.len() == 1 => Ok(cmp::max(value[0], default_min_pin_length)),
         _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
     }
 }
@@ -421,6 +618,9 @@ pub fn commit_large_blob_array(
 /// In particular persistent entries are not reset.
 pub fn reset(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
     env.store().clear(key::NUM_PERSISTENT_KEYS)?;
+    if has_secrets_store(env) {
+        env.secrets_store().clear(key::NUM_PERSISTENT_KEYS)?;
+    }
     env.key_store().reset()?;
     init(env)?;
     Ok(())
@@ -428,7 +628,7 @@ pub fn reset(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
 
 /// Returns whether the PIN needs to be changed before its next usage.
 pub fn has_force_pin_change(env: &mut impl Env) -> Result<bool, Ctap2StatusCode> {
-    match env.store().find(key::FORCE_PIN_CHANGE)? {
+    match env.secrets_store().find(key::FORCE_PIN_CHANGE)? {
         None => Ok(false),
         Some(value) if value.is_empty() => Ok(true),
         _ => Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR),
@@ -436,9 +636,8 @@ pub fn has_force_pin_change(env: &mut impl Env) -> Result<bool, Ctap2StatusCode>
 }
 
 /// Marks the PIN as outdated with respect to the new PIN policy.
-#[cfg(feature = "config_command")]
 pub fn force_pin_change(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
-    Ok(env.store().insert(key::FORCE_PIN_CHANGE, &[])?)
+    Ok(env.secrets_store().insert(key::FORCE_PIN_CHANGE, &[])?)
 }
 
 /// Returns whether enterprise attestation is enabled.
@@ -502,6 +701,79 @@ pub fn toggle_always_uv(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
     }
 }
 
+/// Capacity of the store, in words.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreCapacity {
+    /// Words that can be written without compacting first.
+    pub immediate: usize,
+    /// Words that can still be stored, possibly after compaction.
+    pub remaining: usize,
+    pub total: usize,
+}
+
+/// Returns the capacity of the store.
+pub fn store_capacity(env: &mut impl Env) -> Result<StoreCapacity, Ctap2StatusCode> {
+    let store = env.store();
+    let capacity = store.capacity()?;
+    Ok(StoreCapacity {
+        immediate: store.immediate_capacity()?,
+        remaining: capacity.remaining(),
+        total: capacity.total(),
+    })
+}
+
+/// Compacts pages until `length` words can be written without compaction.
+///
+/// The length is capped at the remaining capacity. At most `max_pages` pages are compacted, so
+/// that callers can report progress between calls. Returns the number of compacted pages.
+pub fn compact_store(
+    env: &mut impl Env,
+    length: usize,
+    max_pages: usize,
+) -> Result<usize, Ctap2StatusCode> {
+    let length = cmp::min(length, env.store().capacity()?.remaining());
+    let mut pages = 0;
+    while pages < max_pages && env.store().immediate_capacity()? < length {
+        env.store().prepare(length)?;
+        pages += 1;
+    }
+    Ok(pages)
+}
+
+/// Compacts a page if fewer words than the customized threshold are writable without compaction.
+///
+/// Returns whether a page was compacted.
+pub fn compact_store_if_low(env: &mut impl Env) -> Result<bool, Ctap2StatusCode> {
+    let threshold = env.customization().store_compaction_threshold();
+    Ok(compact_store(env, threshold, 1)? > 0)
+}
+
+/// Returns whether the secrets have a store of their own, see `Env::secrets_store`.
+fn has_secrets_store(env: &mut impl Env) -> bool {
+    let store: *const _ = env.store();
+    !core::ptr::eq(store, env.secrets_store())
+}
+
+/// Returns whether the store was modified behind its back, e.g. by flash corruption.
+///
+/// A corrupted store is mounted read-only, see `persistent_store::Store::mount`.
+pub fn is_store_corrupted(env: &mut impl Env) -> bool {
+    env.store().verify().is_err()
+        || (has_secrets_store(env) && env.secrets_store().verify().is_err())
+}
+
+/// Erases the whole store, including provisioned data, and initializes it again.
+///
+/// Unlike `reset`, this also works on a corrupted store.
+pub fn wipe(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
+    env.store().wipe()?;
+    if has_secrets_store(env) {
+        env.secrets_store().wipe()?;
+    }
+    env.key_store().reset()?;
+    init(env)
+}
+
 impl From<persistent_store::StoreError> for Ctap2StatusCode {
     fn from(error: persistent_store::StoreError) -> Ctap2StatusCode {
         use persistent_store::StoreError;
@@ -513,8 +785,7 @@ impl From<persistent_store::StoreError> for Ctap2StatusCode {
             // This error is expected if we don't satisfy the store preconditions. For example we
             // try to store a credential which is too long.
             StoreError::InvalidArgument => Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR,
-            // This error is not expected. The storage has been tempered with. We could erase the
-            // storage.
+            // This error is not expected. The storage has been tempered with, and reset wipes it.
             StoreError::InvalidStorage => Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
             // This error is not expected. The kernel is failing our syscalls.
             StoreError::StorageError => Ctap2StatusCode::CTAP1_ERR_OTHER,
@@ -662,6 +933,7 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         }
     }
 
@@ -858,10 +1130,34 @@ mod test {
             user_icon: None,
             cred_blob: None,
             large_blob_key: None,
+            creation_time: None,
         };
         assert_eq!(found_credential, Some(expected_credential));
     }
 
+    #[test]
+    fn test_secrets_store() {
+        let mut env = TestEnv::default();
+        init(&mut env).unwrap();
+        env.key_store().wrap_key::<TestEnv>().unwrap();
+        set_pin(&mut env, &[0x88; PIN_AUTH_LENGTH], 4).unwrap();
+
+        // Secrets are only written to the secrets store.
+        let master_keys = crate::api::key_store::STORAGE_KEY;
+        assert!(env
+            .secrets_store()
+            .find(key::PIN_PROPERTIES)
+            .unwrap()
+            .is_some());
+        assert!(env.secrets_store().find(master_keys).unwrap().is_some());
+        assert_eq!(env.store().find(key::PIN_PROPERTIES), Ok(None));
+        assert_eq!(env.store().find(master_keys), Ok(None));
+
+        // Resetting also clears the secrets store.
+        assert_eq!(reset(&mut env), Ok(()));
+        assert_eq!(env.secrets_store().find(key::PIN_PROPERTIES), Ok(None));
+    }
+
     #[test]
     fn test_pin_hash_and_length() {
         let mut env = TestEnv::default();
@@ -890,6 +1186,30 @@ mod test {
         assert!(pin_code_point_length(&mut env).unwrap().is_none());
     }
 
+    #[test]
+    fn test_pin_requirements() {
+        let mut env = TestEnv::default();
+        let hash = [0x55; PIN_AUTH_LENGTH];
+        assert_eq!(pin_requirements(&mut env), Ok(None));
+
+        env.customization_mut().set_requires_alphanumeric_pin(true);
+        set_pin(&mut env, &hash, 4).unwrap();
+        assert_eq!(
+            pin_requirements(&mut env),
+            Ok(Some(pin_policy::ALPHANUMERIC))
+        );
+
+        // Properties written before the policy existed have no requirements.
+        let mut legacy_properties = vec![4];
+        legacy_properties.extend_from_slice(&hash);
+        env.secrets_store()
+            .insert(key::PIN_PROPERTIES, &legacy_properties)
+            .unwrap();
+        assert_eq!(pin_requirements(&mut env), Ok(Some(0)));
+        assert_eq!(pin_hash(&mut env), Ok(Some(hash)));
+        assert_eq!(pin_code_point_length(&mut env), Ok(Some(4)));
+    }
+
     #[test]
     fn test_pin_retries() {
         let mut env = TestEnv::default();
@@ -918,6 +1238,23 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_uv_retries() {
+        let mut env = TestEnv::default();
+        let max_uv_retries = env.customization().max_uv_retries();
+        assert_eq!(uv_retries(&mut env), Ok(max_uv_retries));
+
+        for retries in (0..max_uv_retries).rev() {
+            decr_uv_retries(&mut env).unwrap();
+            assert_eq!(uv_retries(&mut env), Ok(retries));
+        }
+        decr_uv_retries(&mut env).unwrap();
+        assert_eq!(uv_retries(&mut env), Ok(0));
+
+        reset_uv_retries(&mut env).unwrap();
+        assert_eq!(uv_retries(&mut env), Ok(max_uv_retries));
+    }
+
     #[test]
     fn test_persistent_keys() {
         let mut env = TestEnv::default();
@@ -1049,6 +1386,30 @@ mod test {
         assert_eq!(vec![0x3C], restored_large_blob_array);
     }
 
+    #[test]
+    fn test_boot_counter() {
+        let mut env = TestEnv::default();
+        assert_eq!(boot_counter(&mut env), Ok(0));
+        assert_eq!(incr_boot_counter(&mut env), Ok(1));
+        assert_eq!(incr_boot_counter(&mut env), Ok(2));
+        // The boot counter survives a reset.
+        reset(&mut env).unwrap();
+        assert_eq!(boot_counter(&mut env), Ok(2));
+    }
+
+    #[test]
+    fn test_aaguid() {
+        let mut env = TestEnv::default();
+        assert_eq!(aaguid(&mut env), Ok(*env.customization().aaguid()));
+        assert_eq!(has_custom_aaguid(&mut env), Ok(false));
+        set_custom_aaguid(&mut env, &[0x5A; AAGUID_LENGTH]).unwrap();
+        assert_eq!(aaguid(&mut env), Ok([0x5A; AAGUID_LENGTH]));
+        assert_eq!(has_custom_aaguid(&mut env), Ok(true));
+        // The AAGUID survives a reset.
+        reset(&mut env).unwrap();
+        assert_eq!(aaguid(&mut env), Ok([0x5A; AAGUID_LENGTH]));
+    }
+
     #[test]
     fn test_global_signature_counter() {
         let mut env = TestEnv::default();
@@ -1062,6 +1423,50 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_rp_signature_counter() {
+        let mut env = TestEnv::default();
+        let rp_id_hash = [0x11; 32];
+        let other_rp_id_hash = [0x22; 32];
+
+        assert_eq!(rp_signature_counter(&mut env, &rp_id_hash), Ok(0));
+        assert_eq!(incr_rp_signature_counter(&mut env, &rp_id_hash, 3), Ok(()));
+        assert_eq!(incr_rp_signature_counter(&mut env, &rp_id_hash, 4), Ok(()));
+        assert_eq!(rp_signature_counter(&mut env, &rp_id_hash), Ok(7));
+        assert_eq!(rp_signature_counter(&mut env, &other_rp_id_hash), Ok(0));
+        // The global counter is independent.
+        assert_eq!(
+            global_signature_counter(&mut env),
+            Ok(INITIAL_SIGNATURE_COUNTER)
+        );
+
+        reset(&mut env).unwrap();
+        assert_eq!(rp_signature_counter(&mut env, &rp_id_hash), Ok(0));
+        assert_eq!(
+            rp_signature_counter(&mut env, &[0x11; 16]),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_rp_signature_counter_full_key() {
+        let mut env = TestEnv::default();
+        // All those RP ID hashes share the same key.
+        for i in 0..MAX_RP_SIGNATURE_COUNTERS_PER_KEY {
+            let mut rp_id_hash = [0x00; 32];
+            rp_id_hash[1] = i as u8;
+            assert_eq!(incr_rp_signature_counter(&mut env, &rp_id_hash, 1), Ok(()));
+            assert_eq!(rp_signature_counter(&mut env, &rp_id_hash), Ok(1));
+        }
+        let mut rp_id_hash = [0x00; 32];
+        rp_id_hash[1] = 0xFF;
+        assert_eq!(incr_rp_signature_counter(&mut env, &rp_id_hash, 1), Ok(()));
+        assert_eq!(rp_signature_counter(&mut env, &rp_id_hash), Ok(0));
+        // Relying parties in other keys still get counters.
+        assert_eq!(incr_rp_signature_counter(&mut env, &[0x01; 32], 1), Ok(()));
+        assert_eq!(rp_signature_counter(&mut env, &[0x01; 32]), Ok(1));
+    }
+
     #[test]
     fn test_force_pin_change() {
         let mut env = TestEnv::default();
@@ -1129,6 +1534,7 @@ mod test {
             user_icon: Some(String::from("icon")),
             cred_blob: Some(vec![0xCB]),
             large_blob_key: Some(vec![0x1B]),
+            creation_time: None,
         };
         let serialized =
             serialize_credential::<TestEnv>(&mut env, &wrap_key, credential.clone()).unwrap();
@@ -1143,4 +1549,44 @@ mod test {
         let reconstructed = deserialize_min_pin_length_rp_ids(&serialized).unwrap();
         assert_eq!(rp_ids, reconstructed);
     }
+
+    #[test]
+    fn test_compact_store() {
+        let mut env = TestEnv::default();
+        let capacity = store_capacity(&mut env).unwrap();
+        assert_eq!(compact_store(&mut env, capacity.immediate, 1), Ok(0));
+
+        // Overwriting a value leaves garbage that only compaction reclaims.
+        while store_capacity(&mut env).unwrap().immediate >= 512 {
+            env.store().insert(100, &[0x55; 512]).unwrap();
+        }
+        let before = store_capacity(&mut env).unwrap();
+        assert_eq!(compact_store(&mut env, 1024, 1), Ok(1));
+        let after = store_capacity(&mut env).unwrap();
+        assert!(after.immediate > before.immediate);
+        assert_eq!(after.remaining, before.remaining);
+        assert!(compact_store(&mut env, 1024, 10).unwrap() < 10);
+        assert!(store_capacity(&mut env).unwrap().immediate >= 1024);
+
+        // Compaction stops at the remaining capacity.
+        let pages = compact_store(&mut env, usize::MAX, usize::MAX).unwrap();
+        let capacity = store_capacity(&mut env).unwrap();
+        assert!(capacity.immediate >= capacity.remaining);
+        assert_eq!(compact_store(&mut env, usize::MAX, usize::MAX), Ok(0));
+        assert!(pages > 0);
+    }
+
+    #[test]
+    fn test_compact_store_if_low() {
+        let mut env = TestEnv::default();
+        env.customization_mut().set_store_compaction_threshold(1024);
+        assert_eq!(compact_store_if_low(&mut env), Ok(false));
+        while store_capacity(&mut env).unwrap().immediate >= 1024 {
+            env.store().insert(100, &[0x55; 512]).unwrap();
+        }
+        env.customization_mut().set_store_compaction_threshold(0);
+        assert_eq!(compact_store_if_low(&mut env), Ok(false));
+        env.customization_mut().set_store_compaction_threshold(1024);
+        assert_eq!(compact_store_if_low(&mut env), Ok(true));
+    }
 }
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 /// Number of keys that persist the CTAP reset command.
-pub const NUM_PERSISTENT_KEYS: usize = 20;
+pub const NUM_PERSISTENT_KEYS: usize = 64;
 
 /// Defines a key given its name and value or range of values.
 macro_rules! make_key {
@@ -67,17 +67,76 @@ make_partition! {
     /// Used for the AAGUID before, but deprecated.
     _AAGUID = 3;
 
+    /// The number of boots.
+    ///
+    /// If the entry is absent, the device never booted.
+    BOOT_COUNTER = 4;
+
+    /// Ring buffer of audit log entries.
+    AUDIT_LOG = 5..13;
+
+    /// Reserved for the BBS blinds of the environment, one issuer per key.
+    _RESERVED_BBS_BLINDS = 13..29;
+
+    /// Reserved for the lockdown level of the environment.
+    _RESERVED_LOCKDOWN = 29;
+
+    /// Reserved for the vendor command permissions of the environment.
+    _RESERVED_PERMISSIONS = 30;
+
+    /// Reserved for the crash report of the environment.
+    _RESERVED_CRASH_REPORT = 31;
+
+    /// The AAGUID provisioned for a customer.
+    ///
+    /// If the entry is absent, the AAGUID is `Customization::aaguid()`.
+    AAGUID = 32;
+
+    /// Reserved for the presentation counts of BBS credentials of the environment.
+    _RESERVED_BBS_USAGE = 33;
+
+    /// Reserved for the minimum bundle version of upgrades of the environment.
+    _RESERVED_MIN_BUNDLE_VERSION = 34;
+
+    /// Reserved for the key of encrypted upgrade bundles of the environment.
+    _RESERVED_BUNDLE_KEY = 35;
+
+    /// Reserved for the BBS epoch of the environment.
+    _RESERVED_BBS_EPOCH = 36;
+
+    /// Reserved for the BBS issuer registry of the environment, one issuer per key.
+    _RESERVED_BBS_ISSUERS = 37..45;
+
+    /// Reserved for future BBS records of the environment.
+    _RESERVED_BBS = 45..48;
+
+    /// Reserved for persistent records of downstream forks.
+    _RESERVED_FORK = 48..64;
+
     // This is the persistent key limit:
     // - When adding a (persistent) key above this message, make sure its value is smaller than
     //   NUM_PERSISTENT_KEYS.
     // - When adding a (non-persistent) key below this message, make sure its value is bigger or
     //   equal than NUM_PERSISTENT_KEYS.
 
+    /// Reserved for the marker of the BBS epoch of the environment.
+    ///
+    /// It is not persistent, so that the environment notices resets.
+    _RESERVED_BBS_EPOCH_MARKER = 64;
+
+    /// Reserved for records of downstream forks that a reset removes.
+    _RESERVED_FORK_VOLATILE = 960..1000;
+
+    /// The number of built-in user verification retries.
+    ///
+    /// If the entry is absent, the number of retries is `Customization::max_uv_retries()`.
+    UV_RETRIES = 1000;
+
     /// Reserved for future credential-related objects.
     ///
     /// In particular, additional credentials could be added there by reducing the lower bound of
     /// the credential range below as well as the upper bound of this range in a similar manner.
-    _RESERVED_CREDENTIALS = 1000..1700;
+    _RESERVED_CREDENTIALS = 1001..1700;
 
     /// The credentials.
     ///
@@ -91,6 +150,21 @@ make_partition! {
     /// The stored large blob can be too big for one key, so it has to be sharded.
     LARGE_BLOB_SHARDS = 2000..2004;
 
+    /// Signature counters per relying party.
+    ///
+    /// Relying parties are spread over those keys by the first byte of their RP ID hash.
+    RP_SIGNATURE_COUNTERS = 2004..2036;
+
+    /// Reserved for the interrupted BBS issuance of the environment.
+    ///
+    /// It is not persistent, since its blind no longer unwraps after a reset.
+    _RESERVED_BBS_ISSUANCE_SESSION = 2036;
+
+    /// Reserved for the BBS recovery secret of the environment.
+    ///
+    /// It is not persistent, so a reset unpairs the backup device.
+    _RESERVED_BBS_RECOVERY = 2037;
+
     /// If this entry exists and is empty, alwaysUv is enabled.
     ALWAYS_UV = 2038;
 
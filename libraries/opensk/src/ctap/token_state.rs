@@ -34,7 +34,7 @@ const INITIAL_USAGE_TIME_LIMIT_MS: usize = 30000;
 /// This implementation does not use a rolling timer.
 pub struct PinUvAuthTokenState<E: Env> {
     // Relies on the fact that all permissions are represented by powers of two.
-    permissions_set: u8,
+    permissions_set: u32,
     permissions_rp_id: Option<String>,
     usage_timer: <E::Clock as Clock>::Timer,
     user_verified: bool,
@@ -60,7 +60,7 @@ impl<E: Env> PinUvAuthTokenState<E> {
 
     /// Checks if the permission is granted.
     pub fn has_permission(&self, permission: PinPermission) -> Result<(), Ctap2StatusCode> {
-        if permission as u8 & self.permissions_set != 0 {
+        if permission as u32 & self.permissions_set != 0 {
             Ok(())
         } else {
             Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
@@ -91,8 +91,8 @@ impl<E: Env> PinUvAuthTokenState<E> {
         }
     }
 
-    /// Sets the permissions, represented as bits in a byte.
-    pub fn set_permissions(&mut self, permissions: u8) {
+    /// Sets the permissions, represented as bits in a word.
+    pub fn set_permissions(&mut self, permissions: u32) {
         self.permissions_set = permissions;
     }
 
@@ -138,7 +138,7 @@ impl<E: Env> PinUvAuthTokenState<E> {
 
     /// Clears all permissions except Large Blob Write.
     pub fn clear_pin_uv_auth_token_permissions_except_lbw(&mut self) {
-        self.permissions_set &= PinPermission::LargeBlobWrite as u8;
+        self.permissions_set &= PinPermission::LargeBlobWrite as u32;
     }
 
     /// Resets to the initial state.
@@ -184,7 +184,7 @@ mod test {
     #[test]
     fn test_permissions() {
         let mut token_state = PinUvAuthTokenState::<TestEnv>::new();
-        token_state.set_permissions(0xFF);
+        token_state.set_permissions(u32::MAX);
         for permission in PinPermission::into_enum_iter() {
             assert_eq!(token_state.has_permission(permission), Ok(()));
         }
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use crate::ctap::hid::{
-    CtapHid, CtapHidCommand, CtapHidError, HidPacket, HidPacketIterator, Message,
+    ChannelID, CtapHid, CtapHidCommand, CtapHidError, HidPacket, HidPacketIterator, Message,
 };
 use crate::ctap::{Channel, CtapState};
 use crate::env::Env;
@@ -28,12 +28,15 @@ pub struct VendorHid<E: Env> {
 impl<E: Env> Default for VendorHid<E> {
     /// Instantiates a HID handler for CTAP1, CTAP2 and Wink.
     fn default() -> Self {
-        let hid = CtapHid::<E>::new(CtapHid::<E>::CAPABILITY_CBOR | CtapHid::<E>::CAPABILITY_NMSG);
+        let hid = CtapHid::<E>::new(Self::CAPABILITIES);
         VendorHid { hid }
     }
 }
 
 impl<E: Env> VendorHid<E> {
+    /// Capabilities reported to the client in Init.
+    pub const CAPABILITIES: u8 = CtapHid::<E>::CAPABILITY_CBOR | CtapHid::<E>::CAPABILITY_NMSG;
+
     /// Processes an incoming USB HID packet, and returns an iterator for all outgoing packets.
     pub fn process_hid_packet(
         &mut self,
@@ -87,12 +90,21 @@ impl<E: Env> VendorHid<E> {
     pub fn has_channel_lock(&mut self, env: &mut E) -> bool {
         self.hid.has_channel_lock(env)
     }
+
+    /// Returns whether the continuation packets of a message are still expected.
+    pub fn is_receiving(&self, env: &mut E) -> bool {
+        self.hid.is_receiving(env)
+    }
+
+    /// Invalidates a channel, e.g. after its reply could not be delivered.
+    pub fn invalidate_channel(&mut self, cid: ChannelID) {
+        self.hid.invalidate_channel(cid)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ctap::hid::ChannelID;
     use crate::env::test::TestEnv;
 
     fn new_initialized() -> (VendorHid<TestEnv>, ChannelID) {
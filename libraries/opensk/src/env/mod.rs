@@ -13,21 +13,33 @@
 // limitations under the License.
 
 use crate::api::attestation_store::AttestationStore;
+use crate::api::boot_info::BootInfo;
 use crate::api::clock::Clock;
 use crate::api::connection::HidConnection;
 use crate::api::crypto::ecdh::Ecdh;
 use crate::api::crypto::ecdsa::Ecdsa;
 use crate::api::crypto::Crypto;
 use crate::api::customization::Customization;
+use crate::api::delay::Delay;
+use crate::api::display::Display;
 use crate::api::key_store::KeyStore;
 use crate::api::rng::Rng;
+use crate::api::upgrade_storage::UpgradeStorage;
+use crate::api::user_feedback::UserFeedback;
 use crate::api::user_presence::UserPresence;
-use crate::ctap::Channel;
+use crate::api::user_verification::UserVerification;
+use crate::api::watchdog::Watchdog;
+use crate::ctap::log::LogBuffer;
+use crate::ctap::{Channel, PinUvAuthCheck};
 use alloc::vec::Vec;
 use persistent_store::{Storage, Store};
 
+#[cfg(feature = "raw")]
+pub mod raw;
 #[cfg(feature = "std")]
 pub mod test;
+#[cfg(feature = "zephyr")]
+pub mod zephyr;
 
 pub type AesKey<E> = <<E as Env>::Crypto as Crypto>::Aes256;
 pub type EcdhSk<E> = <<<E as Env>::Crypto as Crypto>::Ecdh as Ecdh>::SecretKey;
@@ -43,6 +55,8 @@ pub type Hkdf<E> = <<E as Env>::Crypto as Crypto>::Hkdf256;
 pub trait Env {
     type Rng: Rng;
     type UserPresence: UserPresence;
+    type UserVerification: UserVerification;
+    type UserFeedback: UserFeedback;
     type Storage: Storage;
     type KeyStore: KeyStore;
     type Write: core::fmt::Write;
@@ -51,13 +65,50 @@ pub trait Env {
     type AttestationStore: AttestationStore;
     type Clock: Clock;
     type Crypto: Crypto;
+    type Watchdog: Watchdog;
+    type Display: Display;
+    type Delay: Delay;
+    type UpgradeStorage: UpgradeStorage;
 
     fn rng(&mut self) -> &mut Self::Rng;
     fn user_presence(&mut self) -> &mut Self::UserPresence;
+    fn user_verification(&mut self) -> &mut Self::UserVerification;
+    fn user_feedback(&mut self) -> &mut Self::UserFeedback;
     fn store(&mut self) -> &mut Store<Self::Storage>;
+
+    /// Store for the PIN hash, master keys and link secret.
+    ///
+    /// They are rarely written, so a separate store compacts its pages independently and wears
+    /// them less than the bulk of the data. Environments without one share the main store.
+    fn secrets_store(&mut self) -> &mut Store<Self::Storage> {
+        self.store()
+    }
+
     fn key_store(&mut self) -> &mut Self::KeyStore;
     fn attestation_store(&mut self) -> &mut Self::AttestationStore;
     fn clock(&mut self) -> &mut Self::Clock;
+    fn watchdog(&mut self) -> &mut Self::Watchdog;
+    fn display(&mut self) -> &mut Self::Display;
+    fn delay(&mut self) -> &mut Self::Delay;
+
+    /// Returns the upgrade storage instance.
+    ///
+    /// Upgrade storage is optional, so implementations may return `None`. However, implementations
+    /// should either always return `None` or always return `Some`.
+    fn upgrade_storage(&mut self) -> Option<&mut Self::UpgradeStorage> {
+        None
+    }
+
+    /// Runs `f` with the upgrade storage, which is taken out of the environment meanwhile.
+    ///
+    /// This lets `f` use the environment while writing, e.g. to check for cancellation. Returns
+    /// `None` exactly when [`Self::upgrade_storage`] does.
+    fn with_upgrade_storage<T>(
+        &mut self,
+        _f: impl FnOnce(&mut Self, &mut Self::UpgradeStorage) -> T,
+    ) -> Option<T> {
+        None
+    }
 
     /// Creates a write instance for debugging.
     ///
@@ -69,6 +120,13 @@ pub trait Env {
 
     fn customization(&self) -> &Self::Customization;
 
+    /// Option to keep a trace of recent events, see the `log` module.
+    ///
+    /// Without a buffer, messages are only written to the debug output, if enabled.
+    fn log_buffer(&mut self) -> Option<&mut LogBuffer> {
+        None
+    }
+
     /// I/O connection for sending packets implementing CTAP HID protocol.
     fn main_hid_connection(&mut self) -> &mut Self::HidConnection;
 
@@ -81,6 +139,11 @@ pub trait Env {
         None
     }
 
+    /// Returns what the boot loader handed off, if it supports measured boot.
+    fn boot_info(&self) -> Option<BootInfo> {
+        None
+    }
+
     /// Option to process a CBOR command before standard parsing.
     ///
     /// Responses are sent on the same channel they were received. Return `None` to continue
@@ -90,7 +153,24 @@ pub trait Env {
     /// For standard commands, the format for bytes is one byte of CTAP2 command and a CBOR encoded
     /// map. To be able to reliably detect your payload, consider starting your messages with a
     /// vendor reserved command byte.
-    fn process_vendor_command(&mut self, _bytes: &[u8], _channel: Channel) -> Option<Vec<u8>> {
+    ///
+    /// Commands may accept a pinUvAuthParam, checked against the pinUvAuthToken through
+    /// `pin_uv_auth` with a vendor-defined permission.
+    fn process_vendor_command(
+        &mut self,
+        _bytes: &[u8],
+        _channel: Channel,
+        _pin_uv_auth: &dyn PinUvAuthCheck,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Option to process APDUs for applets other than FIDO on the CCID interface, e.g. PIV.
+    ///
+    /// Receives the SELECT command for unknown applets, and all following APDUs if the selection
+    /// succeeded. Responses include the status word. Return `None` if the applet is unknown.
+    #[cfg(feature = "ccid")]
+    fn process_vendor_apdu(&mut self, _apdu: &[u8]) -> Option<Vec<u8>> {
         None
     }
 }
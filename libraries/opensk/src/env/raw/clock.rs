@@ -0,0 +1,84 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::api::clock::Clock;
+use nrf52840_hal::pac::RTC0;
+use nrf52840_hal::rtc::Rtc;
+
+const CLOCK_FREQUENCY: u64 = 32768;
+const COUNTER_MASK: u32 = 0xff_ffff;
+
+#[derive(Default)]
+pub struct RawTimer {
+    deadline: u64,
+}
+
+/// Clock that counts the ticks of the RTC0 peripheral, driven by the low frequency clock.
+///
+/// The 24 bit counter wraps after 512 seconds, so any of its functions has to be called at least
+/// once in this interval. If you can't guarantee to regularly create or check timers, call tickle.
+pub struct RawClock {
+    rtc: Rtc<RTC0>,
+    /// Ticks since the clock started.
+    now: u64,
+    last_counter: u32,
+}
+
+impl RawClock {
+    /// Starts counting, the low frequency clock must be running.
+    pub fn new(rtc: RTC0) -> Self {
+        // A prescaler of 0 is always valid.
+        let rtc = Rtc::new(rtc, 0).ok().unwrap();
+        rtc.enable_counter();
+        RawClock {
+            rtc,
+            now: 0,
+            last_counter: 0,
+        }
+    }
+
+    /// Elapses timers before the counter wraps.
+    pub fn tickle(&mut self) {
+        let counter = self.rtc.get_counter();
+        self.now += (counter.wrapping_sub(self.last_counter) & COUNTER_MASK) as u64;
+        self.last_counter = counter;
+    }
+}
+
+impl Clock for RawClock {
+    type Timer = RawTimer;
+
+    fn make_timer(&mut self, milliseconds: usize) -> Self::Timer {
+        self.tickle();
+        let delta = milliseconds as u64 * CLOCK_FREQUENCY / 1000;
+        RawTimer {
+            deadline: self.now + delta,
+        }
+    }
+
+    fn is_elapsed(&mut self, timer: &Self::Timer) -> bool {
+        self.tickle();
+        self.now >= timer.deadline
+    }
+
+    fn uptime_ms(&mut self) -> u64 {
+        self.tickle();
+        self.now * 1000 / CLOCK_FREQUENCY
+    }
+
+    #[cfg(feature = "debug_ctap")]
+    fn timestamp_us(&mut self) -> usize {
+        (self.now * 1_000_000 / CLOCK_FREQUENCY) as usize
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Environment for a bare-metal nRF52840, without an operating system.
+//!
+//! Peripherals are driven directly through the nRF HAL: USB for CTAPHID, the NVMC for the
+//! persistent store, and the RNG, RTC and watchdog. BBS credentials use the default vendor
+//! commands. Upgrades are not available, since there is no upgrade storage.
+//!
+//! An application enables the `rt` feature of `nrf52840-hal`, sets up a global allocator, a panic
+//! handler and the clocks, then calls [`run`].
+
+use crate::api::attestation_store::{self, AttestationStore};
+use crate::api::clock::Clock;
+use crate::api::connection::{HidConnection, SendOrRecvResult, SendOrRecvStatus, UsbEndpoint};
+use crate::api::crypto::software_crypto::SoftwareCrypto;
+use crate::api::customization::{CustomizationImpl, DEFAULT_CUSTOMIZATION};
+use crate::api::delay::Delay;
+use crate::api::display::{Display, DisplayError, Transaction};
+use crate::api::key_store;
+use crate::api::rng::Rng;
+use crate::api::upgrade_storage::NoUpgradeStorage;
+use crate::api::user_feedback::{BlinkPattern, FeedbackPattern, FeedbackState, UserFeedback};
+use crate::api::user_presence::{UserPresence, UserPresenceError, UserPresenceResult};
+use crate::api::user_verification::NoUserVerification;
+use crate::api::watchdog::{self, Watchdog, WatchdogError};
+use crate::ctap::hid::HidPacketIterator;
+use crate::ctap::{Channel, PinUvAuthCheck, KEEPALIVE_DELAY_MS};
+use crate::env::Env;
+use crate::vendor::handler::{self, VendorCommandHandler};
+use crate::{Ctap, Transport};
+use alloc::vec::Vec;
+use arrayref::array_ref;
+#[cfg(feature = "bbs")]
+use bbs::LinkSecret;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use nrf52840_hal::gpio::{Input, Output, Pin, PullUp, PushPull};
+use nrf52840_hal::pac::{RNG, RTC0, WDT};
+use persistent_store::Store;
+use rand_core::{impls, CryptoRng, Error, RngCore};
+
+mod clock;
+mod storage;
+mod usb;
+
+pub use clock::{RawClock, RawTimer};
+pub use storage::RawStorage;
+pub use usb::{RawUsb, UsbBus};
+
+const SEND_TIMEOUT_MS: usize = 1000;
+// The watchdog counts ticks of the low frequency clock, and needs at least 15 of them.
+const WATCHDOG_FREQUENCY: usize = 32768;
+const WATCHDOG_MIN_TICKS: usize = 15;
+
+/// RNG backed by the RNG peripheral, with bias correction.
+pub struct RawRng(nrf52840_hal::Rng);
+
+impl CryptoRng for RawRng {}
+
+impl RngCore for RawRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.random(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl Rng for RawRng {}
+
+/// Writes nothing, debug output needs a transport that this environment doesn't have.
+pub struct RawWrite;
+
+impl core::fmt::Write for RawWrite {
+    fn write_str(&mut self, _: &str) -> core::fmt::Result {
+        Ok(())
+    }
+}
+
+/// Hardware that the environment takes over.
+pub struct RawPeripherals {
+    pub rng: RNG,
+    /// Must be driven by the running low frequency clock.
+    pub rtc: RTC0,
+    pub wdt: WDT,
+    pub usb: RawUsb,
+    pub storage: RawStorage,
+    /// Active low, like the buttons of the nRF52840 DK and dongle.
+    pub button: Pin<Input<PullUp>>,
+    /// Active low, like the LEDs of the nRF52840 DK and dongle.
+    pub leds: Vec<Pin<Output<PushPull>>>,
+}
+
+pub struct RawEnv {
+    rng: RawRng,
+    clock: RawClock,
+    wdt: WDT,
+    watchdog_started: bool,
+    usb: RawUsb,
+    store: Store<RawStorage>,
+    button: Pin<Input<PullUp>>,
+    leds: Vec<Pin<Output<PushPull>>>,
+    user_verification: NoUserVerification,
+    customization: CustomizationImpl,
+}
+
+impl RawEnv {
+    /// Returns the environment, or `None` if the store can't be mounted.
+    pub fn new(peripherals: RawPeripherals) -> Option<Self> {
+        let store = Store::new(peripherals.storage).ok()?;
+        Some(RawEnv {
+            rng: RawRng(nrf52840_hal::Rng::new(peripherals.rng)),
+            clock: RawClock::new(peripherals.rtc),
+            wdt: peripherals.wdt,
+            watchdog_started: false,
+            usb: peripherals.usb,
+            store,
+            button: peripherals.button,
+            leds: peripherals.leds,
+            user_verification: NoUserVerification,
+            customization: DEFAULT_CUSTOMIZATION,
+        })
+    }
+
+    fn is_button_pressed(&self) -> bool {
+        self.button.is_low().unwrap_or(false)
+    }
+
+    /// Switches each LED on or off, depending on its index.
+    fn set_leds(&mut self, is_on: impl Fn(usize) -> bool) {
+        for (i, led) in self.leds.iter_mut().enumerate() {
+            if is_on(i) {
+                led.set_low().ok();
+            } else {
+                led.set_high().ok();
+            }
+        }
+    }
+}
+
+impl UserPresence for RawEnv {
+    fn check_init(&mut self) {}
+
+    fn wait_with_timeout(&mut self, timeout_ms: usize) -> UserPresenceResult {
+        let timer = self.clock.make_timer(timeout_ms);
+        loop {
+            if self.is_button_pressed() {
+                return Ok(());
+            }
+            if self.clock.is_elapsed(&timer) {
+                return Err(UserPresenceError::Timeout);
+            }
+            // Keeps the device enumerated, packets wait until the check completes.
+            self.usb.poll();
+        }
+    }
+
+    fn check_complete(&mut self) {}
+}
+
+impl UserFeedback for RawEnv {
+    fn show(&mut self, _state: FeedbackState, pattern: FeedbackPattern, tick: usize) {
+        let num_leds = self.leds.len().max(1);
+        match pattern.blink {
+            BlinkPattern::Off => self.set_leds(|_| false),
+            BlinkPattern::Solid => self.set_leds(|_| true),
+            BlinkPattern::Flash => self.set_leds(|_| tick % 2 == 0),
+            BlinkPattern::Alternate => self.set_leds(|i| (i + tick) % 2 == 0),
+            BlinkPattern::Snake => self.set_leds(|i| i == tick % num_leds),
+        }
+    }
+}
+
+impl Watchdog for RawEnv {
+    fn start(&mut self, timeout_ms: usize) -> Result<(), WatchdogError> {
+        // A running watchdog can't be reconfigured until the next reset.
+        if self.wdt.runstatus.read().runstatus().bit_is_set() {
+            return Err(WatchdogError::Fail);
+        }
+        let ticks = (timeout_ms * WATCHDOG_FREQUENCY / 1000).max(WATCHDOG_MIN_TICKS);
+        // Safety: any value from the minimum up is a valid reload value.
+        self.wdt.crv.write(|w| unsafe { w.bits(ticks as u32) });
+        self.wdt.rren.write(|w| w.rr0().enabled());
+        self.wdt.config.write(|w| w.sleep().run().halt().pause());
+        // Safety: writing 1 triggers the task.
+        self.wdt.tasks_start.write(|w| unsafe { w.bits(1) });
+        self.watchdog_started = true;
+        Ok(())
+    }
+
+    fn feed(&mut self) {
+        if self.watchdog_started {
+            self.wdt.rr[0].write(|w| w.rr().reload());
+        }
+    }
+
+    fn reboot(&mut self) -> ! {
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+}
+
+impl Delay for RawEnv {
+    fn random_delay(&mut self, max_ms: u32) {
+        let delay_ms = self.rng.next_u64() % (max_ms as u64 + 1);
+        let timer = self.clock.make_timer(delay_ms as usize);
+        while !self.clock.is_elapsed(&timer) {
+            self.usb.poll();
+        }
+    }
+}
+
+impl Display for RawEnv {
+    fn is_supported(&self) -> bool {
+        false
+    }
+
+    fn show(&mut self, _transaction: &Transaction) -> Result<(), DisplayError> {
+        Err(DisplayError::Fail)
+    }
+
+    fn clear(&mut self) {}
+}
+
+impl HidConnection for RawEnv {
+    fn send_and_maybe_recv(&mut self, buf: &mut [u8; 64], timeout_ms: usize) -> SendOrRecvResult {
+        let timer = self.clock.make_timer(timeout_ms);
+        loop {
+            // Like the Tock driver, a packet from the host takes precedence over sending ours.
+            if let Some(packet) = self.usb.recv() {
+                *buf = packet;
+                return Ok(SendOrRecvStatus::Received(UsbEndpoint::MainHid));
+            }
+            if self.usb.send(buf) {
+                return Ok(SendOrRecvStatus::Sent);
+            }
+            if self.clock.is_elapsed(&timer) {
+                return Ok(SendOrRecvStatus::Timeout);
+            }
+        }
+    }
+}
+
+impl key_store::Helper for RawEnv {}
+
+impl AttestationStore for RawEnv {
+    fn get(
+        &mut self,
+        id: &attestation_store::Id,
+    ) -> Result<Option<attestation_store::Attestation>, attestation_store::Error> {
+        if !matches!(id, attestation_store::Id::Batch) {
+            return Err(attestation_store::Error::NoSupport);
+        }
+        attestation_store::helper_get(self)
+    }
+
+    fn set(
+        &mut self,
+        id: &attestation_store::Id,
+        attestation: Option<&attestation_store::Attestation>,
+    ) -> Result<(), attestation_store::Error> {
+        if !matches!(id, attestation_store::Id::Batch) {
+            return Err(attestation_store::Error::NoSupport);
+        }
+        attestation_store::helper_set(self, attestation)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn get_link_secret(&mut self) -> Result<Option<LinkSecret>, attestation_store::Error> {
+        attestation_store::helper_get_link_secret(self)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn set_link_secret(
+        &mut self,
+        link_secret: Option<&LinkSecret>,
+    ) -> Result<(), attestation_store::Error> {
+        attestation_store::helper_set_link_secret(self, link_secret)
+    }
+}
+
+// Uses the default vendor commands, without upgrade storage.
+impl VendorCommandHandler for RawEnv {}
+
+impl Env for RawEnv {
+    type Rng = RawRng;
+    type UserPresence = Self;
+    type UserVerification = NoUserVerification;
+    type UserFeedback = Self;
+    type Storage = RawStorage;
+    type KeyStore = Self;
+    type AttestationStore = Self;
+    type Clock = RawClock;
+    type Write = RawWrite;
+    type Customization = CustomizationImpl;
+    type HidConnection = Self;
+    type Crypto = SoftwareCrypto;
+    type Watchdog = Self;
+    type Display = Self;
+    type Delay = Self;
+    type UpgradeStorage = NoUpgradeStorage;
+
+    fn rng(&mut self) -> &mut Self::Rng {
+        &mut self.rng
+    }
+
+    fn user_presence(&mut self) -> &mut Self::UserPresence {
+        self
+    }
+
+    fn user_verification(&mut self) -> &mut Self::UserVerification {
+        &mut self.user_verification
+    }
+
+    fn user_feedback(&mut self) -> &mut Self::UserFeedback {
+        self
+    }
+
+    fn store(&mut self) -> &mut Store<Self::Storage> {
+        &mut self.store
+    }
+
+    fn key_store(&mut self) -> &mut Self {
+        self
+    }
+
+    fn attestation_store(&mut self) -> &mut Self {
+        self
+    }
+
+    fn clock(&mut self) -> &mut Self::Clock {
+        &mut self.clock
+    }
+
+    fn watchdog(&mut self) -> &mut Self::Watchdog {
+        self
+    }
+
+    fn display(&mut self) -> &mut Self::Display {
+        self
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        self
+    }
+
+    fn write(&mut self) -> Self::Write {
+        RawWrite
+    }
+
+    fn customization(&self) -> &Self::Customization {
+        &self.customization
+    }
+
+    fn main_hid_connection(&mut self) -> &mut Self::HidConnection {
+        self
+    }
+
+    // There is no vendor interface, so no vendor channel ever waits for a keepalive.
+    #[cfg(feature = "vendor_hid")]
+    fn vendor_hid_connection(&mut self) -> &mut Self::HidConnection {
+        self
+    }
+
+    fn process_vendor_command(
+        &mut self,
+        bytes: &[u8],
+        channel: Channel,
+        pin_uv_auth: &dyn PinUvAuthCheck,
+    ) -> Option<Vec<u8>> {
+        handler::process_vendor_command(self, bytes, channel, Some(pin_uv_auth))
+    }
+}
+
+/// Runs the authenticator forever, like the main loop of the Tock application.
+pub fn run(env: RawEnv) -> ! {
+    let mut ctap = Ctap::new(env);
+    // After a watchdog reset, it keeps running with its previous configuration.
+    let watchdog_timeout_ms = watchdog::timeout_ms(ctap.env().customization());
+    ctap.env().watchdog().start(watchdog_timeout_ms).ok();
+    let mut reply = HidPacketIterator::none();
+    let mut pending = None;
+    let mut led_counter = 0;
+    let mut led_blink_timer = RawTimer::default();
+    loop {
+        ctap.env().watchdog().feed();
+        if pending.is_none() {
+            pending = reply.next();
+        }
+        let received = match pending {
+            Some(packet) => {
+                let mut buf = packet;
+                match ctap.env().send_and_maybe_recv(&mut buf, SEND_TIMEOUT_MS) {
+                    Ok(SendOrRecvStatus::Sent) => {
+                        pending = None;
+                        None
+                    }
+                    Ok(SendOrRecvStatus::Received(_)) => Some(buf),
+                    _ => {
+                        // The client is unresponsive, so the rest of its reply is lost.
+                        pending = None;
+                        reply = HidPacketIterator::none();
+                        ctap.invalidate_channel(Transport::MainHid, *array_ref!(packet, 0, 4));
+                        None
+                    }
+                }
+            }
+            None => {
+                ctap.process_idle_work();
+                ctap.env().usb.recv()
+            }
+        };
+
+        #[cfg(feature = "with_ctap1")]
+        if ctap.env().is_button_pressed() {
+            ctap.u2f_grant_user_presence();
+        }
+
+        if let Some(packet) = received {
+            let new_reply = ctap.process_hid_packet(&packet, Transport::MainHid);
+            if new_reply.has_data() {
+                reply = new_reply;
+                pending = None;
+            }
+        }
+
+        if ctap.env().clock().is_elapsed(&led_blink_timer) {
+            led_counter += 1;
+            led_blink_timer = ctap.env().clock().make_timer(KEEPALIVE_DELAY_MS);
+        }
+        ctap.update_feedback(led_counter);
+    }
+}
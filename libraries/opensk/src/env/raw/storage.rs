@@ -0,0 +1,113 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::borrow::Cow;
+use nrf52840_hal::pac::NVMC;
+use persistent_store::{Storage, StorageError, StorageIndex, StorageResult};
+
+const WORD_SIZE: usize = 4;
+const PAGE_SIZE: usize = 0x1000;
+// From the nRF52840 product specification, section 4.3.9.
+const MAX_WORD_WRITES: usize = 2;
+const MAX_PAGE_ERASES: usize = 10000;
+
+/// Flash storage written through the NVMC peripheral.
+///
+/// The storage is a page-aligned region of the internal flash, usually reserved in the linker
+/// script. Reads go directly to the memory-mapped flash.
+pub struct RawStorage {
+    nvmc: NVMC,
+    storage: &'static [u8],
+}
+
+impl RawStorage {
+    /// Provides access to the given flash region.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CustomError` if the region is not page-aligned.
+    ///
+    /// # Safety
+    ///
+    /// The region must be in the internal flash, and nothing else may write it.
+    pub unsafe fn new(nvmc: NVMC, storage: &'static [u8]) -> StorageResult<RawStorage> {
+        if storage.as_ptr() as usize % PAGE_SIZE != 0 || storage.len() % PAGE_SIZE != 0 {
+            return Err(StorageError::CustomError);
+        }
+        Ok(RawStorage { nvmc, storage })
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().ready().is_busy() {}
+    }
+
+    fn address(&self, index: StorageIndex, length: usize) -> StorageResult<usize> {
+        let range = index.range(length, self)?;
+        Ok(self.storage.as_ptr() as usize + range.start)
+    }
+}
+
+impl Storage for RawStorage {
+    fn word_size(&self) -> usize {
+        WORD_SIZE
+    }
+
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+
+    fn num_pages(&self) -> usize {
+        self.storage.len() / PAGE_SIZE
+    }
+
+    fn max_word_writes(&self) -> usize {
+        MAX_WORD_WRITES
+    }
+
+    fn max_page_erases(&self) -> usize {
+        MAX_PAGE_ERASES
+    }
+
+    fn read_slice(&self, index: StorageIndex, length: usize) -> StorageResult<Cow<[u8]>> {
+        Ok(Cow::Borrowed(&self.storage[index.range(length, self)?]))
+    }
+
+    fn write_slice(&mut self, index: StorageIndex, value: &[u8]) -> StorageResult<()> {
+        if index.byte % WORD_SIZE != 0 || value.len() % WORD_SIZE != 0 {
+            return Err(StorageError::NotAligned);
+        }
+        let address = self.address(index, value.len())?;
+        self.nvmc.config.write(|w| w.wen().wen());
+        for (i, word) in value.chunks_exact(WORD_SIZE).enumerate() {
+            let word = u32::from_ne_bytes([word[0], word[1], word[2], word[3]]);
+            // Safety: the address is word-aligned and in the storage, which is ours to write.
+            unsafe { core::ptr::write_volatile((address + i * WORD_SIZE) as *mut u32, word) };
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+        Ok(())
+    }
+
+    fn erase_page(&mut self, page: usize) -> StorageResult<()> {
+        let address = self.address(StorageIndex { page, byte: 0 }, PAGE_SIZE)?;
+        self.nvmc.config.write(|w| w.wen().een());
+        // Safety: the address is the start of a page in the storage.
+        self.nvmc
+            .erasepage()
+            .write(|w| unsafe { w.bits(address as u32) });
+        self.wait_ready();
+        self.nvmc.config.write(|w| w.wen().ren());
+        Ok(())
+    }
+}
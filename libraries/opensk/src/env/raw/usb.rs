@@ -0,0 +1,88 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use nrf52840_hal::usbd::{UsbPeripheral, Usbd};
+use usb_device::bus::UsbBusAllocator;
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usbd_hid::hid_class::HIDClass;
+
+pub type UsbBus = Usbd<UsbPeripheral<'static>>;
+
+// Same identity as the Tock boards, see `boards/nordic/usb_identity.rs`.
+const VENDOR_ID: u16 = 0x1915;
+const PRODUCT_ID: u16 = 0x521F;
+const POLL_INTERVAL_MS: u8 = 5;
+
+// FIDO usage page with one 64 byte input and output report each.
+const CTAPHID_REPORT_DESCRIPTOR: &[u8] = &[
+    0x06, 0xD0, 0xF1, // Usage Page (FIDO Alliance)
+    0x09, 0x01, // Usage (CTAPHID)
+    0xA1, 0x01, // Collection (Application)
+    0x09, 0x20, //   Usage (Input Report Data)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x40, //   Report Count (64)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x09, 0x21, //   Usage (Output Report Data)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x26, 0xFF, 0x00, //   Logical Maximum (255)
+    0x75, 0x08, //   Report Size (8)
+    0x95, 0x40, //   Report Count (64)
+    0x91, 0x02, //   Output (Data, Variable, Absolute)
+    0xC0, // End Collection
+];
+
+/// CTAPHID interface on the USBD peripheral.
+///
+/// The device only makes progress when polled, so poll it at least every few milliseconds while
+/// the host may send requests.
+pub struct RawUsb {
+    device: UsbDevice<'static, UsbBus>,
+    hid: HIDClass<'static, UsbBus>,
+}
+
+impl RawUsb {
+    pub fn new(bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+        let hid = HIDClass::new(bus, CTAPHID_REPORT_DESCRIPTOR, POLL_INTERVAL_MS);
+        let device = UsbDeviceBuilder::new(bus, UsbVidPid(VENDOR_ID, PRODUCT_ID))
+            .manufacturer("Nordic Semiconductor ASA")
+            .product("OpenSK")
+            .serial_number("v1.0")
+            .max_packet_size_0(64)
+            .build();
+        RawUsb { device, hid }
+    }
+
+    /// Handles pending USB events, e.g. during enumeration.
+    pub fn poll(&mut self) {
+        self.device.poll(&mut [&mut self.hid]);
+    }
+
+    /// Returns a packet from the host, if one arrived.
+    pub fn recv(&mut self) -> Option<[u8; 64]> {
+        self.poll();
+        let mut packet = [0; 64];
+        match self.hid.pull_raw_output(&mut packet) {
+            Ok(64) => Some(packet),
+            _ => None,
+        }
+    }
+
+    /// Queues a packet for the host, returns whether the endpoint accepted it.
+    pub fn send(&mut self, packet: &[u8; 64]) -> bool {
+        self.poll();
+        matches!(self.hid.push_raw_input(packet), Ok(64))
+    }
+}
@@ -13,12 +13,16 @@
 // limitations under the License.
 
 use crate::api::customization::{Customization, CustomizationImpl, AAGUID_LENGTH};
+use crate::api::user_feedback::{FeedbackPattern, FeedbackPatterns, FeedbackState};
 use crate::ctap::data_formats::{CredentialProtectionPolicy, EnterpriseAttestationMode};
 use alloc::string::String;
 use alloc::vec::Vec;
 
 pub struct TestCustomization {
     aaguid: &'static [u8; AAGUID_LENGTH],
+    certification_level: Option<u64>,
+    metadata_description: &'static str,
+    allows_custom_aaguid: bool,
     allows_pin_protocol_v1: bool,
     default_cred_protect: Option<CredentialProtectionPolicy>,
     default_min_pin_length: u8,
@@ -28,13 +32,33 @@ pub struct TestCustomization {
     enterprise_rp_id_list: Vec<String>,
     max_msg_size: usize,
     max_pin_retries: u8,
+    pin_mismatches_before_power_cycle: u8,
+    resets_when_pin_blocked: bool,
+    max_uv_retries: u8,
     use_batch_attestation: bool,
     use_signature_counter: bool,
+    use_rp_signature_counters: bool,
     max_cred_blob_length: usize,
     max_credential_count_in_list: Option<usize>,
     max_large_blob_array_size: usize,
     max_rp_ids_length: usize,
     max_supported_resident_keys: usize,
+    store_compaction_threshold: usize,
+    feedback_patterns: FeedbackPatterns,
+    wink_duration_ms: usize,
+    max_command_duration_ms: Option<usize>,
+    max_bbs_messages: usize,
+    max_bbs_credentials: usize,
+    max_bbs_proof_size: usize,
+    bbs_requires_uv: bool,
+    bbs_second_touch_threshold: Option<usize>,
+    bbs_uv_threshold: Option<usize>,
+    max_bbs_presentation_token_proofs: usize,
+    max_bbs_presentation_token_ms: usize,
+    allows_external_link_secret: bool,
+    rejects_trivial_pins: bool,
+    pin_blocklist: Vec<String>,
+    requires_alphanumeric_pin: bool,
 }
 
 impl TestCustomization {
@@ -42,6 +66,68 @@ impl TestCustomization {
         self.allows_pin_protocol_v1 = is_allowed;
     }
 
+    pub fn set_certification_level(&mut self, level: Option<u64>) {
+        self.certification_level = level;
+    }
+
+    pub fn set_allows_custom_aaguid(&mut self, is_allowed: bool) {
+        self.allows_custom_aaguid = is_allowed;
+    }
+
+    pub fn set_default_cred_protect(&mut self, policy: Option<CredentialProtectionPolicy>) {
+        self.default_cred_protect = policy;
+    }
+
+    pub fn set_max_supported_resident_keys(&mut self, max_supported_resident_keys: usize) {
+        self.max_supported_resident_keys = max_supported_resident_keys;
+    }
+
+    pub fn set_store_compaction_threshold(&mut self, threshold: usize) {
+        self.store_compaction_threshold = threshold;
+    }
+
+    pub fn set_max_msg_size(&mut self, max_msg_size: usize) {
+        self.max_msg_size = max_msg_size;
+    }
+
+    pub fn set_max_command_duration_ms(&mut self, duration_ms: Option<usize>) {
+        self.max_command_duration_ms = duration_ms;
+    }
+
+    pub fn set_max_bbs_credentials(&mut self, max_bbs_credentials: usize) {
+        self.max_bbs_credentials = max_bbs_credentials;
+    }
+
+    /// Switches between the global signature counter and counters per relying party.
+    pub fn set_use_rp_signature_counters(&mut self, is_enabled: bool) {
+        self.use_signature_counter = !is_enabled;
+        self.use_rp_signature_counters = is_enabled;
+    }
+
+    pub fn set_pin_mismatches_before_power_cycle(&mut self, mismatches: u8) {
+        self.pin_mismatches_before_power_cycle = mismatches;
+    }
+
+    pub fn set_max_uv_retries(&mut self, max_uv_retries: u8) {
+        self.max_uv_retries = max_uv_retries;
+    }
+
+    pub fn set_resets_when_pin_blocked(&mut self, is_enabled: bool) {
+        self.resets_when_pin_blocked = is_enabled;
+    }
+
+    pub fn set_rejects_trivial_pins(&mut self, is_enabled: bool) {
+        self.rejects_trivial_pins = is_enabled;
+    }
+
+    pub fn set_pin_blocklist(&mut self, pin_blocklist: Vec<String>) {
+        self.pin_blocklist = pin_blocklist;
+    }
+
+    pub fn set_requires_alphanumeric_pin(&mut self, is_required: bool) {
+        self.requires_alphanumeric_pin = is_required;
+    }
+
     pub fn setup_enterprise_attestation(
         &mut self,
         mode: Option<EnterpriseAttestationMode>,
@@ -59,6 +145,18 @@ impl Customization for TestCustomization {
         self.aaguid
     }
 
+    fn certification_level(&self) -> Option<u64> {
+        self.certification_level
+    }
+
+    fn metadata_description(&self) -> &'static str {
+        self.metadata_description
+    }
+
+    fn allows_custom_aaguid(&self) -> bool {
+        self.allows_custom_aaguid
+    }
+
     fn allows_pin_protocol_v1(&self) -> bool {
         self.allows_pin_protocol_v1
     }
@@ -99,6 +197,18 @@ impl Customization for TestCustomization {
         self.max_pin_retries
     }
 
+    fn pin_mismatches_before_power_cycle(&self) -> u8 {
+        self.pin_mismatches_before_power_cycle
+    }
+
+    fn resets_when_pin_blocked(&self) -> bool {
+        self.resets_when_pin_blocked
+    }
+
+    fn max_uv_retries(&self) -> u8 {
+        self.max_uv_retries
+    }
+
     fn use_batch_attestation(&self) -> bool {
         self.use_batch_attestation
     }
@@ -107,6 +217,10 @@ impl Customization for TestCustomization {
         self.use_signature_counter
     }
 
+    fn use_rp_signature_counters(&self) -> bool {
+        self.use_rp_signature_counters
+    }
+
     fn max_cred_blob_length(&self) -> usize {
         self.max_cred_blob_length
     }
@@ -126,12 +240,79 @@ impl Customization for TestCustomization {
     fn max_supported_resident_keys(&self) -> usize {
         self.max_supported_resident_keys
     }
+
+    fn store_compaction_threshold(&self) -> usize {
+        self.store_compaction_threshold
+    }
+
+    fn feedback_pattern(&self, state: FeedbackState) -> FeedbackPattern {
+        self.feedback_patterns.get(state)
+    }
+
+    fn wink_duration_ms(&self) -> usize {
+        self.wink_duration_ms
+    }
+
+    fn max_command_duration_ms(&self) -> Option<usize> {
+        self.max_command_duration_ms
+    }
+
+    fn max_bbs_messages(&self) -> usize {
+        self.max_bbs_messages
+    }
+
+    fn max_bbs_credentials(&self) -> usize {
+        self.max_bbs_credentials
+    }
+
+    fn max_bbs_proof_size(&self) -> usize {
+        self.max_bbs_proof_size
+    }
+
+    fn bbs_requires_uv(&self) -> bool {
+        self.bbs_requires_uv
+    }
+
+    fn bbs_second_touch_threshold(&self) -> Option<usize> {
+        self.bbs_second_touch_threshold
+    }
+
+    fn bbs_uv_threshold(&self) -> Option<usize> {
+        self.bbs_uv_threshold
+    }
+
+    fn max_bbs_presentation_token_proofs(&self) -> usize {
+        self.max_bbs_presentation_token_proofs
+    }
+
+    fn max_bbs_presentation_token_ms(&self) -> usize {
+        self.max_bbs_presentation_token_ms
+    }
+
+    fn allows_external_link_secret(&self) -> bool {
+        self.allows_external_link_secret
+    }
+
+    fn rejects_trivial_pins(&self) -> bool {
+        self.rejects_trivial_pins
+    }
+
+    fn pin_blocklist(&self) -> Vec<String> {
+        self.pin_blocklist.clone()
+    }
+
+    fn requires_alphanumeric_pin(&self) -> bool {
+        self.requires_alphanumeric_pin
+    }
 }
 
 impl From<CustomizationImpl> for TestCustomization {
     fn from(c: CustomizationImpl) -> Self {
         let CustomizationImpl {
             aaguid,
+            certification_level,
+            metadata_description,
+            allows_custom_aaguid,
             allows_pin_protocol_v1,
             default_cred_protect,
             default_min_pin_length,
@@ -141,13 +322,33 @@ impl From<CustomizationImpl> for TestCustomization {
             enterprise_rp_id_list,
             max_msg_size,
             max_pin_retries,
+            pin_mismatches_before_power_cycle,
+            resets_when_pin_blocked,
+            max_uv_retries,
             use_batch_attestation,
             use_signature_counter,
+            use_rp_signature_counters,
             max_cred_blob_length,
             max_credential_count_in_list,
             max_large_blob_array_size,
             max_rp_ids_length,
             max_supported_resident_keys,
+            store_compaction_threshold,
+            feedback_patterns,
+            wink_duration_ms,
+            max_command_duration_ms,
+            max_bbs_messages,
+            max_bbs_credentials,
+            max_bbs_proof_size,
+            bbs_requires_uv,
+            bbs_second_touch_threshold,
+            bbs_uv_threshold,
+            max_bbs_presentation_token_proofs,
+            max_bbs_presentation_token_ms,
+            allows_external_link_secret,
+            rejects_trivial_pins,
+            pin_blocklist,
+            requires_alphanumeric_pin,
         } = c;
 
         let default_min_pin_length_rp_ids = default_min_pin_length_rp_ids
@@ -160,8 +361,16 @@ impl From<CustomizationImpl> for TestCustomization {
             .map(|s| String::from(*s))
             .collect::<Vec<_>>();
 
+        let pin_blocklist = pin_blocklist
+            .iter()
+            .map(|s| String::from(*s))
+            .collect::<Vec<_>>();
+
         Self {
             aaguid,
+            certification_level,
+            metadata_description,
+            allows_custom_aaguid,
             allows_pin_protocol_v1,
             default_cred_protect,
             default_min_pin_length,
@@ -171,13 +380,33 @@ impl From<CustomizationImpl> for TestCustomization {
             enterprise_rp_id_list,
             max_msg_size,
             max_pin_retries,
+            pin_mismatches_before_power_cycle,
+            resets_when_pin_blocked,
+            max_uv_retries,
             use_batch_attestation,
             use_signature_counter,
+            use_rp_signature_counters,
             max_cred_blob_length,
             max_credential_count_in_list,
             max_large_blob_array_size,
             max_rp_ids_length,
             max_supported_resident_keys,
+            store_compaction_threshold,
+            feedback_patterns,
+            wink_duration_ms,
+            max_command_duration_ms,
+            max_bbs_messages,
+            max_bbs_credentials,
+            max_bbs_proof_size,
+            bbs_requires_uv,
+            bbs_second_touch_threshold,
+            bbs_uv_threshold,
+            max_bbs_presentation_token_proofs,
+            max_bbs_presentation_token_ms,
+            allows_external_link_secret,
+            rejects_trivial_pins,
+            pin_blocklist,
+            requires_alphanumeric_pin,
         }
     }
 }
@@ -13,16 +13,32 @@
 // limitations under the License.
 
 use crate::api::attestation_store::AttestationStore;
+use crate::api::boot_info::BootInfo;
 use crate::api::clock::Clock;
-use crate::api::connection::{HidConnection, SendOrRecvResult, SendOrRecvStatus};
+use crate::api::connection::{HidConnection, SendOrRecvResult, SendOrRecvStatus, UsbEndpoint};
 use crate::api::crypto::software_crypto::SoftwareCrypto;
 use crate::api::customization::DEFAULT_CUSTOMIZATION;
+use crate::api::delay::Delay;
+use crate::api::display::{Display, DisplayError, Transaction};
 use crate::api::rng::Rng;
-use crate::api::user_presence::{UserPresence, UserPresenceResult};
+use crate::api::upgrade_storage::NoUpgradeStorage;
+use crate::api::user_feedback::{FeedbackPattern, FeedbackState, UserFeedback};
+use crate::api::user_presence::{
+    CommandClass, UserPresence, UserPresenceResult, DEFAULT_TIMEOUT_MS,
+};
+use crate::api::user_verification::{
+    UserVerification, UserVerificationError, UserVerificationResult,
+};
+use crate::api::watchdog::{Watchdog, WatchdogError};
 use crate::api::{attestation_store, key_store};
+use crate::ctap::log::LogBuffer;
 use crate::env::Env;
+use crate::vendor::handler::VendorCommandHandler;
+use alloc::collections::VecDeque;
+#[cfg(feature = "bbs")]
+use bbs::LinkSecret;
 use customization::TestCustomization;
-use persistent_store::{BufferOptions, BufferStorage, Store};
+use persistent_store::{BufferOptions, BufferStorage, Storage, StorageIndex, Store};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 
@@ -31,9 +47,19 @@ pub mod customization;
 pub struct TestEnv {
     rng: TestRng,
     user_presence: TestUserPresence,
+    user_verification: TestUserVerification,
+    user_feedback: TestUserFeedback,
     store: Store<BufferStorage>,
+    secrets_store: Store<BufferStorage>,
     customization: TestCustomization,
     clock: TestClock,
+    watchdog: TestWatchdog,
+    display: TestDisplay,
+    delay: TestDelay,
+    log_buffer: LogBuffer,
+    incoming_packets: VecDeque<[u8; 64]>,
+    sent_packets: Vec<[u8; 64]>,
+    boot_info: Option<BootInfo>,
 }
 
 pub type TestRng = StdRng;
@@ -70,6 +96,10 @@ impl Clock for TestClock {
         self.now_ms >= timer.end_ms
     }
 
+    fn uptime_ms(&mut self) -> u64 {
+        self.now_ms as u64
+    }
+
     #[cfg(feature = "debug_ctap")]
     fn timestamp_us(&mut self) -> usize {
         // Unused, but let's implement something because it's easy.
@@ -79,6 +109,128 @@ impl Clock for TestClock {
 
 pub struct TestUserPresence {
     check: Box<dyn Fn() -> UserPresenceResult>,
+    timeouts: Vec<(CommandClass, usize)>,
+}
+
+/// Built-in user verification, unsupported until a check is set.
+pub struct TestUserVerification {
+    check: Option<Box<dyn Fn() -> UserVerificationResult>>,
+    retries: usize,
+}
+
+/// Remembers the last shown feedback, for inspection in tests.
+#[derive(Debug, Default)]
+pub struct TestUserFeedback {
+    last: Option<(FeedbackState, FeedbackPattern, usize)>,
+}
+
+impl TestUserFeedback {
+    pub fn last(&self) -> Option<(FeedbackState, FeedbackPattern, usize)> {
+        self.last
+    }
+
+    pub fn last_state(&self) -> Option<FeedbackState> {
+        self.last.map(|(state, _, _)| state)
+    }
+}
+
+impl UserFeedback for TestUserFeedback {
+    fn show(&mut self, state: FeedbackState, pattern: FeedbackPattern, tick: usize) {
+        self.last = Some((state, pattern, tick));
+    }
+}
+
+/// Counts feeds, for inspection in tests.
+#[derive(Debug, Default)]
+pub struct TestWatchdog {
+    timeout_ms: Option<usize>,
+    feed_count: usize,
+}
+
+impl TestWatchdog {
+    pub fn timeout_ms(&self) -> Option<usize> {
+        self.timeout_ms
+    }
+
+    pub fn feed_count(&self) -> usize {
+        self.feed_count
+    }
+}
+
+impl Watchdog for TestWatchdog {
+    fn start(&mut self, timeout_ms: usize) -> Result<(), WatchdogError> {
+        self.timeout_ms = Some(timeout_ms);
+        Ok(())
+    }
+
+    fn feed(&mut self) {
+        if self.timeout_ms.is_some() {
+            self.feed_count += 1;
+        }
+    }
+
+    fn reboot(&mut self) -> ! {
+        panic!("Rebooted");
+    }
+}
+
+/// Records shown transactions, unsupported until enabled.
+#[derive(Debug, Default)]
+pub struct TestDisplay {
+    supported: bool,
+    shown: Vec<Transaction>,
+    current: Option<Transaction>,
+}
+
+impl TestDisplay {
+    pub fn set_supported(&mut self, supported: bool) {
+        self.supported = supported;
+    }
+
+    /// Returns all transactions shown so far.
+    pub fn shown(&self) -> &[Transaction] {
+        &self.shown
+    }
+
+    /// Returns the transaction currently on screen.
+    pub fn current(&self) -> Option<&Transaction> {
+        self.current.as_ref()
+    }
+}
+
+impl Display for TestDisplay {
+    fn is_supported(&self) -> bool {
+        self.supported
+    }
+
+    fn show(&mut self, transaction: &Transaction) -> Result<(), DisplayError> {
+        self.shown.push(transaction.clone());
+        self.current = Some(transaction.clone());
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.current = None;
+    }
+}
+
+/// Records requested delays instead of waiting, so that tests stay fast and deterministic.
+#[derive(Debug, Default)]
+pub struct TestDelay {
+    requested: Vec<u32>,
+}
+
+impl TestDelay {
+    /// Returns the maximum of all delays requested so far.
+    pub fn requested(&self) -> &[u32] {
+        &self.requested
+    }
+}
+
+impl Delay for TestDelay {
+    fn random_delay(&mut self, max_ms: u32) {
+        self.requested.push(max_ms);
+    }
 }
 
 pub struct TestWrite;
@@ -89,11 +241,10 @@ impl core::fmt::Write for TestWrite {
     }
 }
 
-fn new_storage() -> BufferStorage {
+fn new_storage(num_pages: usize) -> BufferStorage {
     // Use the Nordic configuration.
     const PAGE_SIZE: usize = 0x1000;
-    const NUM_PAGES: usize = 20;
-    let store = vec![0xff; NUM_PAGES * PAGE_SIZE].into_boxed_slice();
+    let store = vec![0xff; num_pages * PAGE_SIZE].into_boxed_slice();
     let options = BufferOptions {
         word_size: 4,
         page_size: PAGE_SIZE,
@@ -105,8 +256,13 @@ fn new_storage() -> BufferStorage {
 }
 
 impl HidConnection for TestEnv {
-    fn send_and_maybe_recv(&mut self, _buf: &mut [u8; 64], _timeout_ms: usize) -> SendOrRecvResult {
-        // TODO: Implement I/O from canned requests/responses for integration testing.
+    fn send_and_maybe_recv(&mut self, buf: &mut [u8; 64], _timeout_ms: usize) -> SendOrRecvResult {
+        // Queued packets arrive before the host reads ours, like an OUT transaction first.
+        if let Some(packet) = self.incoming_packets.pop_front() {
+            *buf = packet;
+            return Ok(SendOrRecvStatus::Received(UsbEndpoint::MainHid));
+        }
+        self.sent_packets.push(*buf);
         Ok(SendOrRecvStatus::Sent)
     }
 }
@@ -116,17 +272,33 @@ impl Default for TestEnv {
         let rng = StdRng::seed_from_u64(0);
         let user_presence = TestUserPresence {
             check: Box::new(|| Ok(())),
+            timeouts: Vec::new(),
+        };
+        let user_verification = TestUserVerification {
+            check: None,
+            retries: 8,
         };
-        let storage = new_storage();
-        let store = Store::new(storage).ok().unwrap();
+        let (store, _) = Store::mount(new_storage(20)).ok().unwrap();
+        // Tests use a separate secrets store, to catch secrets written to the main one.
+        let (secrets_store, _) = Store::mount(new_storage(3)).ok().unwrap();
         let customization = DEFAULT_CUSTOMIZATION.into();
         let clock = TestClock::default();
         TestEnv {
             rng,
             user_presence,
+            user_verification,
+            user_feedback: TestUserFeedback::default(),
             store,
+            secrets_store,
             customization,
             clock,
+            watchdog: TestWatchdog::default(),
+            display: TestDisplay::default(),
+            delay: TestDelay::default(),
+            log_buffer: LogBuffer::default(),
+            incoming_packets: VecDeque::new(),
+            sent_packets: Vec::new(),
+            boot_info: None,
         }
     }
 }
@@ -139,12 +311,41 @@ impl TestEnv {
     pub fn seed_rng_from_u64(&mut self, seed: u64) {
         self.rng = StdRng::seed_from_u64(seed);
     }
+
+    /// Queues a packet from the host, received on the main HID endpoint at the next send.
+    pub fn push_incoming_packet(&mut self, packet: [u8; 64]) {
+        self.incoming_packets.push_back(packet);
+    }
+
+    /// Returns all packets sent on HID connections so far.
+    pub fn sent_packets(&self) -> &[[u8; 64]] {
+        &self.sent_packets
+    }
+
+    pub fn set_boot_info(&mut self, boot_info: Option<BootInfo>) {
+        self.boot_info = boot_info;
+    }
+
+    /// Writes to the storage behind the store's back, then mounts it again like after a reboot.
+    pub fn corrupt_store(&mut self, index: StorageIndex, value: &[u8]) {
+        let store = core::mem::replace(&mut self.store, Store::new(new_storage(20)).ok().unwrap());
+        let mut storage = store.extract_storage();
+        storage.write_slice(index, value).unwrap();
+        let (store, _) = Store::mount(storage).ok().unwrap();
+        self.store = store;
+    }
 }
 
 impl TestUserPresence {
     pub fn set(&mut self, check: impl Fn() -> UserPresenceResult + 'static) {
         self.check = Box::new(check);
     }
+
+    /// Overrides the default timeout for a command class.
+    pub fn set_timeout_ms(&mut self, class: CommandClass, timeout_ms: usize) {
+        self.timeouts.retain(|(c, _)| *c != class);
+        self.timeouts.push((class, timeout_ms));
+    }
 }
 
 impl UserPresence for TestUserPresence {
@@ -153,10 +354,55 @@ impl UserPresence for TestUserPresence {
         (self.check)()
     }
     fn check_complete(&mut self) {}
+    fn timeout_ms(&self, class: CommandClass) -> usize {
+        self.timeouts
+            .iter()
+            .find(|(c, _)| *c == class)
+            .map_or(DEFAULT_TIMEOUT_MS, |(_, timeout_ms)| *timeout_ms)
+    }
+}
+
+impl TestUserVerification {
+    /// Enables built-in user verification with the given outcome.
+    pub fn set(&mut self, check: impl Fn() -> UserVerificationResult + 'static) {
+        self.check = Some(Box::new(check));
+    }
+
+    pub fn set_retries(&mut self, retries: usize) {
+        self.retries = retries;
+    }
+}
+
+impl UserVerification for TestUserVerification {
+    fn is_supported(&self) -> bool {
+        self.check.is_some()
+    }
+
+    fn retries(&mut self) -> usize {
+        self.retries
+    }
+
+    fn check_init(&mut self) {}
+
+    fn wait_with_timeout(&mut self, _timeout_ms: usize) -> UserVerificationResult {
+        let result = match &self.check {
+            Some(check) => check(),
+            None => Err(UserVerificationError::Fail),
+        };
+        if result == Err(UserVerificationError::Invalid) {
+            self.retries = self.retries.saturating_sub(1);
+        }
+        result
+    }
+
+    fn check_complete(&mut self) {}
 }
 
 impl key_store::Helper for TestEnv {}
 
+// Uses the default vendor commands, without upgrade storage.
+impl VendorCommandHandler for TestEnv {}
+
 impl AttestationStore for TestEnv {
     fn get(
         &mut self,
@@ -172,11 +418,26 @@ impl AttestationStore for TestEnv {
     ) -> Result<(), attestation_store::Error> {
         attestation_store::helper_set(self, attestation)
     }
+
+    #[cfg(feature = "bbs")]
+    fn get_link_secret(&mut self) -> Result<Option<LinkSecret>, attestation_store::Error> {
+        attestation_store::helper_get_link_secret(self)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn set_link_secret(
+        &mut self,
+        link_secret: Option<&LinkSecret>,
+    ) -> Result<(), attestation_store::Error> {
+        attestation_store::helper_set_link_secret(self, link_secret)
+    }
 }
 
 impl Env for TestEnv {
     type Rng = TestRng;
     type UserPresence = TestUserPresence;
+    type UserVerification = TestUserVerification;
+    type UserFeedback = TestUserFeedback;
     type Storage = BufferStorage;
     type KeyStore = Self;
     type AttestationStore = Self;
@@ -185,6 +446,10 @@ impl Env for TestEnv {
     type Customization = TestCustomization;
     type HidConnection = Self;
     type Crypto = SoftwareCrypto;
+    type Watchdog = TestWatchdog;
+    type Display = TestDisplay;
+    type Delay = TestDelay;
+    type UpgradeStorage = NoUpgradeStorage;
 
     fn rng(&mut self) -> &mut Self::Rng {
         &mut self.rng
@@ -194,10 +459,22 @@ impl Env for TestEnv {
         &mut self.user_presence
     }
 
+    fn user_verification(&mut self) -> &mut Self::UserVerification {
+        &mut self.user_verification
+    }
+
+    fn user_feedback(&mut self) -> &mut Self::UserFeedback {
+        &mut self.user_feedback
+    }
+
     fn store(&mut self) -> &mut Store<Self::Storage> {
         &mut self.store
     }
 
+    fn secrets_store(&mut self) -> &mut Store<Self::Storage> {
+        &mut self.secrets_store
+    }
+
     fn key_store(&mut self) -> &mut Self {
         self
     }
@@ -210,6 +487,18 @@ impl Env for TestEnv {
         &mut self.clock
     }
 
+    fn watchdog(&mut self) -> &mut Self::Watchdog {
+        &mut self.watchdog
+    }
+
+    fn display(&mut self) -> &mut Self::Display {
+        &mut self.display
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        &mut self.delay
+    }
+
     fn write(&mut self) -> Self::Write {
         TestWrite
     }
@@ -230,6 +519,14 @@ impl Env for TestEnv {
     fn firmware_version(&self) -> Option<u64> {
         Some(0)
     }
+
+    fn boot_info(&self) -> Option<BootInfo> {
+        self.boot_info
+    }
+
+    fn log_buffer(&mut self) -> Option<&mut LogBuffer> {
+        Some(&mut self.log_buffer)
+    }
 }
 
 #[cfg(test)]
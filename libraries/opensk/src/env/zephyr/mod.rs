@@ -0,0 +1,484 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Environment for Zephyr applications.
+//!
+//! The crate builds as a static library that the Zephyr application links, together with the C
+//! glue listed in the `sys` module. The persistent store and upgrades use flash areas of the
+//! devicetree, and upgrades go to the secondary slot of MCUboot. Debug output, user feedback and
+//! user verification are left to later work.
+//!
+//! With the `std` feature, the glue is replaced by the `stub` module, so that the environment
+//! builds and runs in CI without a Zephyr toolchain.
+
+use crate::api::attestation_store::{self, AttestationStore};
+use crate::api::clock::Clock;
+use crate::api::connection::{HidConnection, SendOrRecvResult, SendOrRecvStatus, UsbEndpoint};
+use crate::api::crypto::software_crypto::SoftwareCrypto;
+use crate::api::customization::{CustomizationImpl, DEFAULT_CUSTOMIZATION};
+use crate::api::delay::Delay;
+use crate::api::display::{Display, DisplayError, Transaction};
+use crate::api::key_store;
+use crate::api::rng::Rng;
+use crate::api::user_feedback::{FeedbackPattern, FeedbackState, UserFeedback};
+use crate::api::user_presence::{UserPresence, UserPresenceError, UserPresenceResult};
+use crate::api::user_verification::NoUserVerification;
+use crate::api::watchdog::{Watchdog, WatchdogError};
+use crate::ctap::hid::{HidPacket, HidPacketIterator};
+use crate::ctap::status_code::Ctap2StatusCode;
+use crate::ctap::{Channel, PinUvAuthCheck};
+use crate::env::Env;
+use crate::vendor::handler::{self, VendorCommandHandler};
+use crate::vendor::parameters::{VendorBootControlParameters, VendorUpgradeParameters};
+use crate::vendor::{commands, lockdown};
+use crate::{Ctap, Transport};
+use alloc::vec::Vec;
+use arrayref::array_ref;
+#[cfg(feature = "bbs")]
+use bbs::LinkSecret;
+use core::num::NonZeroU32;
+use persistent_store::Store;
+use rand_core::{impls, CryptoRng, Error, RngCore};
+
+mod storage;
+#[cfg(feature = "std")]
+pub mod stub;
+#[cfg(not(feature = "std"))]
+mod sys;
+#[cfg(feature = "std")]
+use stub as sys;
+
+pub use storage::{ZephyrStorage, ZephyrUpgradeStorage};
+
+const SEND_TIMEOUT_MS: usize = 1000;
+
+/// RNG backed by `sys_csrand_get`, which needs an entropy source in the devicetree.
+pub struct ZephyrRng;
+
+impl CryptoRng for ZephyrRng {}
+
+impl RngCore for ZephyrRng {
+    fn next_u32(&mut self) -> u32 {
+        impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        // Nothing is safe to do without randomness.
+        self.try_fill_bytes(dest).unwrap();
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        if sys::rand(dest) {
+            Ok(())
+        } else {
+            Err(Error::from(NonZeroU32::new(Error::CUSTOM_START).unwrap()))
+        }
+    }
+}
+
+impl Rng for ZephyrRng {}
+
+#[derive(Default)]
+pub struct ZephyrTimer {
+    deadline_ms: u64,
+}
+
+/// Clock over the 64 bit uptime of the kernel, which doesn't wrap.
+pub struct ZephyrClock;
+
+impl Clock for ZephyrClock {
+    type Timer = ZephyrTimer;
+
+    fn make_timer(&mut self, milliseconds: usize) -> Self::Timer {
+        ZephyrTimer {
+            deadline_ms: sys::uptime_ms() + milliseconds as u64,
+        }
+    }
+
+    fn is_elapsed(&mut self, timer: &Self::Timer) -> bool {
+        sys::uptime_ms() >= timer.deadline_ms
+    }
+
+    fn uptime_ms(&mut self) -> u64 {
+        sys::uptime_ms()
+    }
+
+    #[cfg(feature = "debug_ctap")]
+    fn timestamp_us(&mut self) -> usize {
+        (sys::uptime_ms() * 1000) as usize
+    }
+}
+
+/// Writes nothing, debug output is left to the logging of the application.
+pub struct ZephyrWrite;
+
+impl core::fmt::Write for ZephyrWrite {
+    fn write_str(&mut self, _: &str) -> core::fmt::Result {
+        Ok(())
+    }
+}
+
+pub struct ZephyrEnv {
+    rng: ZephyrRng,
+    clock: ZephyrClock,
+    store: Store<ZephyrStorage>,
+    upgrade_storage: Option<ZephyrUpgradeStorage>,
+    user_verification: NoUserVerification,
+    customization: CustomizationImpl,
+}
+
+impl ZephyrEnv {
+    /// Mounts the store in the given flash area, and writes upgrades to the other one, if any.
+    ///
+    /// Returns `None` if a flash area is invalid, or the store can't be mounted.
+    pub fn new(storage_area: u8, upgrade_area: Option<u8>) -> Option<Self> {
+        let store = Store::new(ZephyrStorage::new(storage_area).ok()?).ok()?;
+        let upgrade_storage = match upgrade_area {
+            Some(area) => Some(ZephyrUpgradeStorage::new(area).ok()?),
+            None => None,
+        };
+        Some(ZephyrEnv {
+            rng: ZephyrRng,
+            clock: ZephyrClock,
+            store,
+            upgrade_storage,
+            user_verification: NoUserVerification,
+            customization: DEFAULT_CUSTOMIZATION,
+        })
+    }
+}
+
+impl UserPresence for ZephyrEnv {
+    fn check_init(&mut self) {}
+
+    fn wait_with_timeout(&mut self, timeout_ms: usize) -> UserPresenceResult {
+        let timer = self.clock.make_timer(timeout_ms);
+        while !sys::button_pressed() {
+            if self.clock.is_elapsed(&timer) {
+                return Err(UserPresenceError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_complete(&mut self) {}
+}
+
+impl UserFeedback for ZephyrEnv {
+    // LEDs differ between boards, so applications signal through their own threads for now.
+    fn show(&mut self, _state: FeedbackState, _pattern: FeedbackPattern, _tick: usize) {}
+}
+
+impl Watchdog for ZephyrEnv {
+    // The task watchdog of Zephyr supervises threads of the application, not this library.
+    fn start(&mut self, _timeout_ms: usize) -> Result<(), WatchdogError> {
+        Err(WatchdogError::Unsupported)
+    }
+
+    fn feed(&mut self) {}
+
+    fn reboot(&mut self) -> ! {
+        sys::reboot()
+    }
+}
+
+impl Delay for ZephyrEnv {
+    fn random_delay(&mut self, max_ms: u32) {
+        let delay_ms = self.rng.next_u64() % (max_ms as u64 + 1);
+        let timer = self.clock.make_timer(delay_ms as usize);
+        while !self.clock.is_elapsed(&timer) {}
+    }
+}
+
+impl Display for ZephyrEnv {
+    fn is_supported(&self) -> bool {
+        false
+    }
+
+    fn show(&mut self, _transaction: &Transaction) -> Result<(), DisplayError> {
+        Err(DisplayError::Fail)
+    }
+
+    fn clear(&mut self) {}
+}
+
+impl HidConnection for ZephyrEnv {
+    fn send_and_maybe_recv(&mut self, buf: &mut [u8; 64], timeout_ms: usize) -> SendOrRecvResult {
+        let timer = self.clock.make_timer(timeout_ms);
+        loop {
+            // Like the Tock driver, a packet from the host takes precedence over sending ours.
+            if sys::hid_read(buf) {
+                return Ok(SendOrRecvStatus::Received(UsbEndpoint::MainHid));
+            }
+            if sys::hid_write(buf) {
+                return Ok(SendOrRecvStatus::Sent);
+            }
+            if self.clock.is_elapsed(&timer) {
+                return Ok(SendOrRecvStatus::Timeout);
+            }
+        }
+    }
+}
+
+impl key_store::Helper for ZephyrEnv {}
+
+impl AttestationStore for ZephyrEnv {
+    fn get(
+        &mut self,
+        id: &attestation_store::Id,
+    ) -> Result<Option<attestation_store::Attestation>, attestation_store::Error> {
+        if !matches!(id, attestation_store::Id::Batch) {
+            return Err(attestation_store::Error::NoSupport);
+        }
+        attestation_store::helper_get(self)
+    }
+
+    fn set(
+        &mut self,
+        id: &attestation_store::Id,
+        attestation: Option<&attestation_store::Attestation>,
+    ) -> Result<(), attestation_store::Error> {
+        if !matches!(id, attestation_store::Id::Batch) {
+            return Err(attestation_store::Error::NoSupport);
+        }
+        attestation_store::helper_set(self, attestation)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn get_link_secret(&mut self) -> Result<Option<LinkSecret>, attestation_store::Error> {
+        attestation_store::helper_get_link_secret(self)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn set_link_secret(
+        &mut self,
+        link_secret: Option<&LinkSecret>,
+    ) -> Result<(), attestation_store::Error> {
+        attestation_store::helper_set_link_secret(self, link_secret)
+    }
+}
+
+/// Upgrades use the default vendor commands, which write the secondary slot of MCUboot.
+impl VendorCommandHandler for ZephyrEnv {
+    fn process_vendor_upgrade(
+        &mut self,
+        params: VendorUpgradeParameters,
+        channel: Channel,
+    ) -> Result<(), Ctap2StatusCode> {
+        // MCUboot only checks the signature at the next boot, so the user confirms each upgrade.
+        // Lockdown is checked first, so that the user doesn't touch in vain.
+        if params.offset == 0 {
+            lockdown::check_upgrade(self)?;
+            self.check_vendor_user_presence(channel)?;
+        }
+        let mut cancellation = self.vendor_cancellation_token(channel);
+        commands::process_vendor_upgrade(self, params, &mut cancellation)
+    }
+
+    fn process_vendor_boot_control(
+        &mut self,
+        params: VendorBootControlParameters,
+        channel: Channel,
+    ) -> Result<(), Ctap2StatusCode> {
+        if params == VendorBootControlParameters::RequestRollback {
+            lockdown::check_upgrade(self)?;
+            self.check_vendor_user_presence(channel)?;
+        }
+        let mut cancellation = self.vendor_cancellation_token(channel);
+        commands::process_vendor_boot_control(self, params, &mut cancellation)
+    }
+}
+
+impl Env for ZephyrEnv {
+    type Rng = ZephyrRng;
+    type UserPresence = Self;
+    type UserVerification = NoUserVerification;
+    type UserFeedback = Self;
+    type Storage = ZephyrStorage;
+    type KeyStore = Self;
+    type AttestationStore = Self;
+    type Clock = ZephyrClock;
+    type Write = ZephyrWrite;
+    type Customization = CustomizationImpl;
+    type HidConnection = Self;
+    type Crypto = SoftwareCrypto;
+    type Watchdog = Self;
+    type Display = Self;
+    type Delay = Self;
+    type UpgradeStorage = ZephyrUpgradeStorage;
+
+    fn rng(&mut self) -> &mut Self::Rng {
+        &mut self.rng
+    }
+
+    fn user_presence(&mut self) -> &mut Self::UserPresence {
+        self
+    }
+
+    fn user_verification(&mut self) -> &mut Self::UserVerification {
+        &mut self.user_verification
+    }
+
+    fn user_feedback(&mut self) -> &mut Self::UserFeedback {
+        self
+    }
+
+    fn store(&mut self) -> &mut Store<Self::Storage> {
+        &mut self.store
+    }
+
+    fn key_store(&mut self) -> &mut Self {
+        self
+    }
+
+    fn attestation_store(&mut self) -> &mut Self {
+        self
+    }
+
+    fn clock(&mut self) -> &mut Self::Clock {
+        &mut self.clock
+    }
+
+    fn watchdog(&mut self) -> &mut Self::Watchdog {
+        self
+    }
+
+    fn display(&mut self) -> &mut Self::Display {
+        self
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        self
+    }
+
+    fn upgrade_storage(&mut self) -> Option<&mut Self::UpgradeStorage> {
+        self.upgrade_storage.as_mut()
+    }
+
+    fn with_upgrade_storage<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self, &mut Self::UpgradeStorage) -> T,
+    ) -> Option<T> {
+        let mut upgrade_storage = self.upgrade_storage.take()?;
+        let result = f(self, &mut upgrade_storage);
+        self.upgrade_storage = Some(upgrade_storage);
+        Some(result)
+    }
+
+    fn write(&mut self) -> Self::Write {
+        ZephyrWrite
+    }
+
+    fn customization(&self) -> &Self::Customization {
+        &self.customization
+    }
+
+    fn main_hid_connection(&mut self) -> &mut Self::HidConnection {
+        self
+    }
+
+    // There is no vendor interface, so no vendor channel ever waits for a keepalive.
+    #[cfg(feature = "vendor_hid")]
+    fn vendor_hid_connection(&mut self) -> &mut Self::HidConnection {
+        self
+    }
+
+    fn process_vendor_command(
+        &mut self,
+        bytes: &[u8],
+        channel: Channel,
+        pin_uv_auth: &dyn PinUvAuthCheck,
+    ) -> Option<Vec<u8>> {
+        handler::process_vendor_command(self, bytes, channel, Some(pin_uv_auth))
+    }
+}
+
+/// Main loop of the application, for a thread of its own.
+pub struct ZephyrRunner {
+    ctap: Ctap<ZephyrEnv>,
+    reply: HidPacketIterator,
+    /// Next packet of the reply, kept while a packet from the host takes precedence.
+    pending: Option<HidPacket>,
+}
+
+impl ZephyrRunner {
+    pub fn new(env: ZephyrEnv) -> Self {
+        ZephyrRunner {
+            ctap: Ctap::new(env),
+            reply: HidPacketIterator::none(),
+            pending: None,
+        }
+    }
+
+    pub fn ctap(&mut self) -> &mut Ctap<ZephyrEnv> {
+        &mut self.ctap
+    }
+
+    /// Sends the next packet of a reply, or processes a packet from the host.
+    pub fn poll(&mut self) {
+        if self.pending.is_none() {
+            self.pending = self.reply.next();
+        }
+        let received = match self.pending {
+            Some(packet) => {
+                let mut buf = packet;
+                match self
+                    .ctap
+                    .env()
+                    .send_and_maybe_recv(&mut buf, SEND_TIMEOUT_MS)
+                {
+                    Ok(SendOrRecvStatus::Sent) => {
+                        self.pending = None;
+                        None
+                    }
+                    Ok(SendOrRecvStatus::Received(_)) => Some(buf),
+                    _ => {
+                        // The client is unresponsive, so the rest of its reply is lost.
+                        self.pending = None;
+                        self.reply = HidPacketIterator::none();
+                        self.ctap
+                            .invalidate_channel(Transport::MainHid, *array_ref!(packet, 0, 4));
+                        None
+                    }
+                }
+            }
+            None => {
+                self.ctap.process_idle_work();
+                let mut buf = [0; 64];
+                sys::hid_read(&mut buf).then_some(buf)
+            }
+        };
+        #[cfg(feature = "with_ctap1")]
+        if sys::button_pressed() {
+            self.ctap.u2f_grant_user_presence();
+        }
+        if let Some(packet) = received {
+            let reply = self.ctap.process_hid_packet(&packet, Transport::MainHid);
+            if reply.has_data() {
+                self.reply = reply;
+                self.pending = None;
+            }
+        }
+    }
+
+    pub fn run(mut self) -> ! {
+        loop {
+            self.poll();
+        }
+    }
+}
@@ -0,0 +1,374 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::sys;
+use crate::api::crypto::HASH_SIZE;
+use crate::api::upgrade_storage::UpgradeStorage;
+use crate::vendor::{BundleVerification, PartitionStatus};
+use alloc::borrow::Cow;
+use alloc::vec;
+use alloc::vec::Vec;
+use arrayref::array_ref;
+use byteorder::{ByteOrder, LittleEndian};
+use persistent_store::{Storage, StorageError, StorageIndex, StorageResult};
+
+const WORD_SIZE: usize = 4;
+// The store needs at least 2, which the NOR flash of common Zephyr boards allows.
+const MAX_WORD_WRITES: usize = 2;
+const MAX_PAGE_ERASES: usize = 10000;
+
+/// Returns the size and page size of a flash area, if they fit the store.
+fn flash_info(area: u8) -> StorageResult<(usize, usize)> {
+    let (size, page_size) = sys::flash_info(area).ok_or(StorageError::CustomError)?;
+    if !page_size.is_power_of_two() || page_size % WORD_SIZE != 0 || size % page_size != 0 {
+        return Err(StorageError::CustomError);
+    }
+    Ok((size, page_size))
+}
+
+/// Persistent store in a flash area, e.g. the `storage_partition` of the devicetree.
+pub struct ZephyrStorage {
+    area: u8,
+    page_size: usize,
+    num_pages: usize,
+}
+
+impl ZephyrStorage {
+    /// Provides access to the flash area with the given ID.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CustomError` if the area doesn't exist, or its pages are not uniform powers of two.
+    pub fn new(area: u8) -> StorageResult<ZephyrStorage> {
+        let (size, page_size) = flash_info(area)?;
+        Ok(ZephyrStorage {
+            area,
+            page_size,
+            num_pages: size / page_size,
+        })
+    }
+}
+
+impl Storage for ZephyrStorage {
+    fn word_size(&self) -> usize {
+        WORD_SIZE
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    fn max_word_writes(&self) -> usize {
+        MAX_WORD_WRITES
+    }
+
+    fn max_page_erases(&self) -> usize {
+        MAX_PAGE_ERASES
+    }
+
+    fn read_slice(&self, index: StorageIndex, length: usize) -> StorageResult<Cow<[u8]>> {
+        let range = index.range(length, self)?;
+        let mut value = vec![0; length];
+        if !sys::flash_read(self.area, range.start, &mut value) {
+            return Err(StorageError::CustomError);
+        }
+        Ok(Cow::Owned(value))
+    }
+
+    fn write_slice(&mut self, index: StorageIndex, value: &[u8]) -> StorageResult<()> {
+        if index.byte % WORD_SIZE != 0 || value.len() % WORD_SIZE != 0 {
+            return Err(StorageError::NotAligned);
+        }
+        let range = index.range(value.len(), self)?;
+        if !sys::flash_write(self.area, range.start, value) {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+
+    fn erase_page(&mut self, page: usize) -> StorageResult<()> {
+        let range = StorageIndex { page, byte: 0 }.range(self.page_size, self)?;
+        if !sys::flash_erase(self.area, range.start, self.page_size) {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+}
+
+// Start of the MCUboot image header, see `struct image_header` of MCUboot.
+const IMAGE_MAGIC: u32 = 0x96f3_b83d;
+const IMAGE_VERSION_OFFSET: usize = 20;
+const IMAGE_HEADER_SIZE: usize = 32;
+
+/// Returns the version of the image that starts with `header`, if it has an MCUboot header.
+///
+/// The major, minor, revision and build numbers are packed from the most significant bits, so
+/// that newer images have larger versions.
+fn image_version(header: &[u8]) -> Option<u64> {
+    let header = header.get(..IMAGE_HEADER_SIZE)?;
+    if LittleEndian::read_u32(&header[..4]) != IMAGE_MAGIC {
+        return None;
+    }
+    let version = array_ref!(header, IMAGE_VERSION_OFFSET, 8);
+    Some(
+        (version[0] as u64) << 56
+            | (version[1] as u64) << 48
+            | (LittleEndian::read_u16(&version[2..4]) as u64) << 32
+            | LittleEndian::read_u32(&version[4..]) as u64,
+    )
+}
+
+/// Secondary image slot of MCUboot, that upgrades write.
+///
+/// MCUboot checks the image signature before it swaps slots, so this storage only parses the
+/// header, to refuse images older than allowed. Versions are only known once the signature is
+/// checked at boot, so writes never report one, and the minimum version only rises through
+/// configuration.
+pub struct ZephyrUpgradeStorage {
+    area: u8,
+    size: usize,
+    page_size: usize,
+}
+
+impl ZephyrUpgradeStorage {
+    /// Provides access to the flash area with the given ID, usually `slot1_partition`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CustomError` if the area doesn't exist, or its pages are not uniform powers of two.
+    pub fn new(area: u8) -> StorageResult<ZephyrUpgradeStorage> {
+        let (size, page_size) = flash_info(area)?;
+        Ok(ZephyrUpgradeStorage {
+            area,
+            size,
+            page_size,
+        })
+    }
+
+    /// Returns the version in the header of the slot, if it holds an image.
+    fn slot_version(&self) -> Option<u64> {
+        let mut header = [0; IMAGE_HEADER_SIZE];
+        if !sys::flash_read(self.area, 0, &mut header) {
+            return None;
+        }
+        image_version(&header)
+    }
+}
+
+impl UpgradeStorage for ZephyrUpgradeStorage {
+    /// Identifies the slot to the host, like the partition address on Tock.
+    fn bundle_identifier(&self) -> u32 {
+        self.area as u32
+    }
+
+    fn running_status(&self) -> PartitionStatus {
+        // The primary slot is not accessible, so its version is unknown.
+        PartitionStatus {
+            version: None,
+            boot_attempts: 0,
+            confirmed: sys::image_confirmed(),
+            rollback_requested: false,
+        }
+    }
+
+    fn bundle_status(&self) -> PartitionStatus {
+        PartitionStatus {
+            version: self.slot_version(),
+            boot_attempts: 0,
+            confirmed: false,
+            rollback_requested: false,
+        }
+    }
+
+    /// Writes a chunk of the image, and asks MCUboot to boot it once the header is written.
+    ///
+    /// Pages are erased when a chunk reaches their start, so chunks must arrive in order.
+    fn write_bundle(
+        &mut self,
+        offset: usize,
+        data: Vec<u8>,
+        min_version: u64,
+        _keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<Option<u64>> {
+        if offset == 0 && image_version(&data).map_or(true, |version| version < min_version) {
+            return Err(StorageError::CustomError);
+        }
+        if offset % WORD_SIZE != 0 || data.len() % WORD_SIZE != 0 {
+            return Err(StorageError::NotAligned);
+        }
+        let end = offset
+            .checked_add(data.len())
+            .filter(|&end| end <= self.size)
+            .ok_or(StorageError::OutOfBounds)?;
+        let first_page = (offset + self.page_size - 1) / self.page_size;
+        let last_page = (end + self.page_size - 1) / self.page_size;
+        for page in first_page..last_page {
+            if !sys::flash_erase(self.area, page * self.page_size, self.page_size) {
+                return Err(StorageError::CustomError);
+            }
+        }
+        if !sys::flash_write(self.area, offset, &data) {
+            return Err(StorageError::CustomError);
+        }
+        // MCUboot checks the signature before swapping, so an incomplete image never boots.
+        if offset == 0 && !sys::request_upgrade() {
+            return Err(StorageError::CustomError);
+        }
+        Ok(None)
+    }
+
+    /// MCUboot images keep their hash in the trailing TLVs, so encrypted bundles aren't supported.
+    fn bundle_hash(&mut self) -> StorageResult<[u8; HASH_SIZE]> {
+        Err(StorageError::CustomError)
+    }
+
+    fn verify_bundle(
+        &mut self,
+        min_version: u64,
+        _keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<BundleVerification> {
+        // Only MCUboot checks the signature, so a recent header is all this can tell.
+        Ok(match self.slot_version() {
+            None => BundleVerification::Missing,
+            Some(version) if version < min_version => BundleVerification::InvalidMetadata,
+            Some(_) => BundleVerification::Valid,
+        })
+    }
+
+    fn mark_running_good(&mut self) -> StorageResult<()> {
+        if !sys::confirm_image() {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+
+    /// Boots the previous image again, which MCUboot keeps in the secondary slot after a swap.
+    fn request_rollback(
+        &mut self,
+        min_version: u64,
+        _keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<()> {
+        if self
+            .slot_version()
+            .map_or(true, |version| version < min_version)
+        {
+            return Err(StorageError::CustomError);
+        }
+        if !sys::request_upgrade() {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::stub::{self, STORAGE_AREA, UPGRADE_AREA};
+    use super::*;
+
+    #[test]
+    fn test_storage_unknown_area() {
+        assert!(ZephyrStorage::new(0xFF).is_err());
+    }
+
+    #[test]
+    fn test_storage_write_read() {
+        let mut storage = ZephyrStorage::new(STORAGE_AREA).unwrap();
+        let index = StorageIndex { page: 1, byte: 8 };
+        storage
+            .write_slice(index, &[0x01, 0x02, 0x03, 0x04])
+            .unwrap();
+        assert_eq!(
+            &*storage.read_slice(index, 4).unwrap(),
+            &[0x01, 0x02, 0x03, 0x04]
+        );
+        storage.erase_page(1).unwrap();
+        assert_eq!(&*storage.read_slice(index, 4).unwrap(), &[0xFF; 4]);
+        assert_eq!(
+            storage.write_slice(index, &[0x01, 0x02]),
+            Err(StorageError::NotAligned)
+        );
+    }
+
+    /// Returns the start of an image with version 1.2.3+4, padded with zeros to `length`.
+    fn image_start(length: usize) -> Vec<u8> {
+        let mut image = vec![0x00; length];
+        LittleEndian::write_u32(&mut image[..4], IMAGE_MAGIC);
+        image[IMAGE_VERSION_OFFSET..][..8].copy_from_slice(&[1, 2, 3, 0, 4, 0, 0, 0]);
+        image
+    }
+
+    const IMAGE_VERSION: u64 = 0x0102_0003_0000_0004;
+
+    #[test]
+    fn test_upgrade_write_bundle() {
+        let mut storage = ZephyrUpgradeStorage::new(UPGRADE_AREA).unwrap();
+        let page_size = storage.page_size;
+        let image = image_start(page_size + 8);
+        assert_eq!(storage.write_bundle(0, image.clone(), 0, || true), Ok(None));
+        assert!(stub::upgrade_requested());
+        // Chunks only erase the pages that start within them.
+        assert!(storage
+            .write_bundle(page_size + 8, vec![0x55; 8], 0, || true)
+            .is_ok());
+        assert!(storage
+            .write_bundle(2 * page_size - 8, vec![0xAA; 16], 0, || true)
+            .is_ok());
+        let area = stub::flash_area(UPGRADE_AREA).unwrap();
+        assert_eq!(&area[..page_size + 8], &image[..]);
+        assert_eq!(&area[page_size + 8..][..8], &[0x55; 8]);
+        assert_eq!(&area[2 * page_size - 8..][..16], &[0xAA; 16]);
+        assert_eq!(area[2 * page_size + 8], 0xFF);
+        assert_eq!(
+            storage.write_bundle(storage.size - 4, vec![0x00; 8], 0, || true),
+            Err(StorageError::OutOfBounds)
+        );
+        assert_eq!(storage.bundle_status().version, Some(IMAGE_VERSION));
+    }
+
+    #[test]
+    fn test_upgrade_min_version() {
+        let mut storage = ZephyrUpgradeStorage::new(UPGRADE_AREA).unwrap();
+        assert_eq!(
+            storage.verify_bundle(0, || true),
+            Ok(BundleVerification::Missing)
+        );
+        // Images without a header, or older than allowed, are refused.
+        assert!(storage.write_bundle(0, vec![0x00; 64], 0, || true).is_err());
+        assert!(storage
+            .write_bundle(0, image_start(64), IMAGE_VERSION + 1, || true)
+            .is_err());
+        assert!(!stub::upgrade_requested());
+
+        assert!(storage
+            .write_bundle(0, image_start(64), IMAGE_VERSION, || true)
+            .is_ok());
+        assert_eq!(
+            storage.verify_bundle(IMAGE_VERSION, || true),
+            Ok(BundleVerification::Valid)
+        );
+        assert_eq!(
+            storage.verify_bundle(IMAGE_VERSION + 1, || true),
+            Ok(BundleVerification::InvalidMetadata)
+        );
+        assert!(storage
+            .request_rollback(IMAGE_VERSION + 1, || true)
+            .is_err());
+        assert!(storage.request_rollback(IMAGE_VERSION, || true).is_ok());
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stand-in for the C glue, so that the Zephyr environment builds and runs on the desktop.
+//!
+//! Flash areas and the HID endpoint live in memory, separately for each thread. Tests control the
+//! button and inspect reports through the functions of this module. The clock advances by a
+//! millisecond on each read, so that timeouts elapse without waiting.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+/// Flash area of the persistent store.
+pub const STORAGE_AREA: u8 = 0;
+/// Flash area of the secondary image slot, that upgrades write.
+pub const UPGRADE_AREA: u8 = 1;
+
+const PAGE_SIZE: usize = 0x1000;
+const STORAGE_PAGES: usize = 20;
+const UPGRADE_PAGES: usize = 64;
+
+struct State {
+    uptime_ms: u64,
+    rand_state: u64,
+    flash_areas: HashMap<u8, Vec<u8>>,
+    incoming_reports: VecDeque<[u8; 64]>,
+    sent_reports: Vec<[u8; 64]>,
+    button_pressed: bool,
+    image_confirmed: bool,
+    upgrade_requested: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let mut flash_areas = HashMap::new();
+        flash_areas.insert(STORAGE_AREA, vec![0xFF; STORAGE_PAGES * PAGE_SIZE]);
+        flash_areas.insert(UPGRADE_AREA, vec![0xFF; UPGRADE_PAGES * PAGE_SIZE]);
+        State {
+            uptime_ms: 0,
+            rand_state: 0,
+            flash_areas,
+            incoming_reports: VecDeque::new(),
+            sent_reports: Vec::new(),
+            button_pressed: false,
+            image_confirmed: false,
+            upgrade_requested: false,
+        }
+    }
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+}
+
+fn with_state<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    STATE.with(|state| f(&mut state.borrow_mut()))
+}
+
+/// Returns the bytes of a flash area, if it exists.
+fn with_area<T>(area: u8, f: impl FnOnce(&mut Vec<u8>) -> T) -> Option<T> {
+    with_state(|state| state.flash_areas.get_mut(&area).map(f))
+}
+
+/// Queues a report from the host.
+pub fn push_hid_report(report: [u8; 64]) {
+    with_state(|state| state.incoming_reports.push_back(report));
+}
+
+/// Returns the reports sent to the host since the last call.
+pub fn take_hid_reports() -> Vec<[u8; 64]> {
+    with_state(|state| core::mem::take(&mut state.sent_reports))
+}
+
+pub fn set_button_pressed(pressed: bool) {
+    with_state(|state| state.button_pressed = pressed);
+}
+
+/// Returns a copy of a flash area.
+pub fn flash_area(area: u8) -> Option<Vec<u8>> {
+    with_area(area, |bytes| bytes.clone())
+}
+
+pub fn upgrade_requested() -> bool {
+    with_state(|state| state.upgrade_requested)
+}
+
+pub fn rand(dst: &mut [u8]) -> bool {
+    // Like the entropy driver of a native board, this is not meant to be secure.
+    with_state(|state| {
+        for byte in dst.iter_mut() {
+            state.rand_state = state
+                .rand_state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1);
+            *byte = (state.rand_state >> 56) as u8;
+        }
+    });
+    true
+}
+
+pub fn uptime_ms() -> u64 {
+    with_state(|state| {
+        state.uptime_ms += 1;
+        state.uptime_ms
+    })
+}
+
+pub fn flash_info(area: u8) -> Option<(usize, usize)> {
+    with_area(area, |bytes| (bytes.len(), PAGE_SIZE))
+}
+
+pub fn flash_read(area: u8, offset: usize, dst: &mut [u8]) -> bool {
+    with_area(area, |bytes| match bytes.get(offset..offset + dst.len()) {
+        Some(src) => {
+            dst.copy_from_slice(src);
+            true
+        }
+        None => false,
+    })
+    .unwrap_or(false)
+}
+
+pub fn flash_write(area: u8, offset: usize, src: &[u8]) -> bool {
+    with_area(area, |bytes| {
+        match bytes.get_mut(offset..offset + src.len()) {
+            Some(dst) => {
+                // Like NOR flash, writes only clear bits.
+                for (dst, src) in dst.iter_mut().zip(src) {
+                    *dst &= src;
+                }
+                true
+            }
+            None => false,
+        }
+    })
+    .unwrap_or(false)
+}
+
+pub fn flash_erase(area: u8, offset: usize, len: usize) -> bool {
+    if offset % PAGE_SIZE != 0 || len % PAGE_SIZE != 0 {
+        return false;
+    }
+    with_area(area, |bytes| match bytes.get_mut(offset..offset + len) {
+        Some(dst) => {
+            dst.fill(0xFF);
+            true
+        }
+        None => false,
+    })
+    .unwrap_or(false)
+}
+
+pub fn hid_read(report: &mut [u8; 64]) -> bool {
+    with_state(|state| match state.incoming_reports.pop_front() {
+        Some(incoming) => {
+            *report = incoming;
+            true
+        }
+        None => false,
+    })
+}
+
+pub fn hid_write(report: &[u8; 64]) -> bool {
+    with_state(|state| state.sent_reports.push(*report));
+    true
+}
+
+pub fn button_pressed() -> bool {
+    with_state(|state| state.button_pressed)
+}
+
+pub fn image_confirmed() -> bool {
+    with_state(|state| state.image_confirmed)
+}
+
+pub fn confirm_image() -> bool {
+    with_state(|state| state.image_confirmed = true);
+    true
+}
+
+pub fn request_upgrade() -> bool {
+    with_state(|state| state.upgrade_requested = true);
+    true
+}
+
+pub fn reboot() -> ! {
+    panic!("Rebooted");
+}
@@ -0,0 +1,123 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bindings to the C glue of the Zephyr application.
+//!
+//! Many Zephyr APIs are static inline functions or macros, so the application exports a thin
+//! function for each. Return values follow Zephyr, with 0 for success and a negative errno for
+//! failures, unless noted otherwise.
+
+use core::ffi::c_int;
+
+extern "C" {
+    /// Calls `sys_csrand_get`.
+    fn opensk_zephyr_rand(dst: *mut u8, len: usize) -> c_int;
+    /// Calls `k_uptime_get`.
+    fn opensk_zephyr_uptime_ms() -> i64;
+    /// Writes the size of a flash area, and the size of its erase pages, which must be uniform.
+    fn opensk_zephyr_flash_info(area: u8, size: *mut usize, page_size: *mut usize) -> c_int;
+    /// Calls `flash_area_read`.
+    fn opensk_zephyr_flash_read(area: u8, offset: usize, dst: *mut u8, len: usize) -> c_int;
+    /// Calls `flash_area_write`.
+    fn opensk_zephyr_flash_write(area: u8, offset: usize, src: *const u8, len: usize) -> c_int;
+    /// Calls `flash_area_erase`.
+    fn opensk_zephyr_flash_erase(area: u8, offset: usize, len: usize) -> c_int;
+    /// Calls `hid_int_ep_read` for a 64 byte output report, returns the number of bytes read.
+    fn opensk_zephyr_hid_read(report: *mut u8) -> c_int;
+    /// Calls `hid_int_ep_write` for a 64 byte input report, returns the number of bytes written.
+    fn opensk_zephyr_hid_write(report: *const u8) -> c_int;
+    /// Returns 1 if the user presence button is pressed, from `gpio_pin_get_dt`.
+    fn opensk_zephyr_button_pressed() -> c_int;
+    /// Returns 1 if the running image is confirmed, from `boot_is_img_confirmed`.
+    fn opensk_zephyr_image_confirmed() -> c_int;
+    /// Calls `boot_write_img_confirmed`.
+    fn opensk_zephyr_confirm_image() -> c_int;
+    /// Calls `boot_request_upgrade` for a test swap, that MCUboot reverts unless confirmed.
+    fn opensk_zephyr_request_upgrade() -> c_int;
+    /// Calls `sys_reboot`.
+    fn opensk_zephyr_reboot() -> !;
+}
+
+pub fn rand(dst: &mut [u8]) -> bool {
+    // Safety: the glue writes at most `len` bytes to `dst`.
+    unsafe { opensk_zephyr_rand(dst.as_mut_ptr(), dst.len()) == 0 }
+}
+
+pub fn uptime_ms() -> u64 {
+    // Safety: the glue has no preconditions.
+    unsafe { opensk_zephyr_uptime_ms() as u64 }
+}
+
+/// Returns the size and page size of a flash area.
+pub fn flash_info(area: u8) -> Option<(usize, usize)> {
+    let mut size = 0;
+    let mut page_size = 0;
+    // Safety: the glue writes one `usize` to each pointer.
+    match unsafe { opensk_zephyr_flash_info(area, &mut size, &mut page_size) } {
+        0 => Some((size, page_size)),
+        _ => None,
+    }
+}
+
+pub fn flash_read(area: u8, offset: usize, dst: &mut [u8]) -> bool {
+    // Safety: the glue writes at most `len` bytes to `dst`.
+    unsafe { opensk_zephyr_flash_read(area, offset, dst.as_mut_ptr(), dst.len()) == 0 }
+}
+
+pub fn flash_write(area: u8, offset: usize, src: &[u8]) -> bool {
+    // Safety: the glue reads at most `len` bytes from `src`.
+    unsafe { opensk_zephyr_flash_write(area, offset, src.as_ptr(), src.len()) == 0 }
+}
+
+pub fn flash_erase(area: u8, offset: usize, len: usize) -> bool {
+    // Safety: the glue only accesses the flash area.
+    unsafe { opensk_zephyr_flash_erase(area, offset, len) == 0 }
+}
+
+/// Returns whether a report from the host arrived.
+pub fn hid_read(report: &mut [u8; 64]) -> bool {
+    // Safety: the glue writes at most 64 bytes to `report`.
+    unsafe { opensk_zephyr_hid_read(report.as_mut_ptr()) == 64 }
+}
+
+/// Returns whether the endpoint accepted the report.
+pub fn hid_write(report: &[u8; 64]) -> bool {
+    // Safety: the glue reads 64 bytes from `report`.
+    unsafe { opensk_zephyr_hid_write(report.as_ptr()) == 64 }
+}
+
+pub fn button_pressed() -> bool {
+    // Safety: the glue has no preconditions.
+    unsafe { opensk_zephyr_button_pressed() == 1 }
+}
+
+pub fn image_confirmed() -> bool {
+    // Safety: the glue has no preconditions.
+    unsafe { opensk_zephyr_image_confirmed() == 1 }
+}
+
+pub fn confirm_image() -> bool {
+    // Safety: the glue has no preconditions.
+    unsafe { opensk_zephyr_confirm_image() == 0 }
+}
+
+pub fn request_upgrade() -> bool {
+    // Safety: the glue has no preconditions.
+    unsafe { opensk_zephyr_request_upgrade() == 0 }
+}
+
+pub fn reboot() -> ! {
+    // Safety: the glue has no preconditions.
+    unsafe { opensk_zephyr_reboot() }
+}
@@ -18,13 +18,19 @@ extern crate alloc;
 #[macro_use]
 extern crate arrayref;
 
-use crate::ctap::hid::{HidPacket, HidPacketIterator};
+use crate::api::user_feedback::{self, FeedbackState};
+use crate::api::watchdog::Watchdog;
+#[cfg(feature = "ccid")]
+use crate::ctap::ccid::Ccid;
+use crate::ctap::hid::{ChannelID, HidPacket, HidPacketIterator};
 use crate::ctap::main_hid::MainHid;
 #[cfg(feature = "vendor_hid")]
 use crate::ctap::vendor_hid::VendorHid;
 use crate::ctap::CtapState;
 pub use crate::ctap::Transport;
 use crate::env::Env;
+#[cfg(feature = "ccid")]
+use alloc::vec::Vec;
 
 // Those macros should eventually be split into trace, debug, info, warn, and error macros when
 // adding either the defmt or log feature and crate dependency.
@@ -43,12 +49,23 @@ macro_rules! debug_ctap {
     };
 }
 
+/// Records a message in the log of the environment, tagged with the calling module.
+///
+/// For example: `log_ctap!(env, Level::Warn, "Proof failed: {:?}", error)`.
+#[macro_export]
+macro_rules! log_ctap {
+    ($env: expr, $level: expr, $($rest:tt)*) => {
+        $crate::ctap::log::log($env, $level, module_path!(), format_args!($($rest)*))
+    };
+}
+
 pub mod api;
 // TODO(kaczmarczyck): Refactor this so that ctap module isn't public.
 pub mod ctap;
 pub mod env;
 #[cfg(feature = "std")]
 pub mod test_helpers;
+pub mod vendor;
 
 /// CTAP implementation parameterized by its environment.
 pub struct Ctap<E: Env> {
@@ -57,6 +74,8 @@ pub struct Ctap<E: Env> {
     hid: MainHid<E>,
     #[cfg(feature = "vendor_hid")]
     vendor_hid: VendorHid<E>,
+    #[cfg(feature = "ccid")]
+    ccid: Ccid,
 }
 
 impl<E: Env> Ctap<E> {
@@ -74,6 +93,8 @@ impl<E: Env> Ctap<E> {
             hid,
             #[cfg(feature = "vendor_hid")]
             vendor_hid,
+            #[cfg(feature = "ccid")]
+            ccid: Ccid::default(),
         }
     }
 
@@ -94,6 +115,7 @@ impl<E: Env> Ctap<E> {
         packet: &HidPacket,
         transport: Transport,
     ) -> HidPacketIterator {
+        self.env.watchdog().feed();
         match transport {
             Transport::MainHid => {
                 #[cfg(not(feature = "vendor_hid"))]
@@ -116,10 +138,74 @@ impl<E: Env> Ctap<E> {
         }
     }
 
+    /// Processes a message from the CCID bulk-out endpoint, and returns the bulk-in answer.
+    #[cfg(feature = "ccid")]
+    pub fn process_ccid_message(&mut self, message: &[u8]) -> Vec<u8> {
+        self.env.watchdog().feed();
+        self.ccid
+            .process_message(&mut self.env, message, &mut self.state)
+    }
+
+    /// Invalidates the channel of a reply that could not be delivered.
+    ///
+    /// The client has to allocate a new channel before sending further commands.
+    pub fn invalidate_channel(&mut self, transport: Transport, cid: ChannelID) {
+        match transport {
+            Transport::MainHid => self.hid.invalidate_channel(cid),
+            #[cfg(feature = "vendor_hid")]
+            Transport::VendorHid => self.vendor_hid.invalidate_channel(cid),
+        }
+    }
+
     pub fn should_wink(&mut self) -> bool {
         self.hid.should_wink(&mut self.env)
     }
 
+    /// Returns the state that should currently be signaled to the user.
+    pub fn feedback_state(&mut self) -> FeedbackState {
+        if self.should_wink() || self.state.is_identifying(&mut self.env) {
+            return FeedbackState::Wink;
+        }
+        #[cfg(feature = "with_ctap1")]
+        if self.u2f_needs_user_presence() {
+            return FeedbackState::AwaitingTouch;
+        }
+        if self.state.has_recent_error(&mut self.env) {
+            return FeedbackState::Error;
+        }
+        FeedbackState::Idle
+    }
+
+    /// Runs a slice of background maintenance, unless a client may be waiting.
+    ///
+    /// Call this from your main loop while no packet is pending, and call it again before sleeping
+    /// as long as it returns true. Slices are short, so that packets arriving meanwhile aren't
+    /// delayed much. Nothing runs while a message is partially received, a channel is locked, or
+    /// the user is signaled something.
+    pub fn process_idle_work(&mut self) -> bool {
+        if self.hid.is_receiving(&mut self.env) || self.hid.has_channel_lock(&mut self.env) {
+            return false;
+        }
+        #[cfg(feature = "vendor_hid")]
+        if self.vendor_hid.is_receiving(&mut self.env)
+            || self.vendor_hid.has_channel_lock(&mut self.env)
+        {
+            return false;
+        }
+        if self.feedback_state() != FeedbackState::Idle {
+            return false;
+        }
+        self.state.process_idle_work(&mut self.env)
+    }
+
+    /// Signals the current state to the user.
+    ///
+    /// Call this regularly from your main loop, with an increasing tick to animate patterns.
+    pub fn update_feedback(&mut self, tick: usize) {
+        let state = self.feedback_state();
+        user_feedback::signal(&mut self.env, state, tick);
+    }
+
     #[cfg(feature = "with_ctap1")]
     pub fn u2f_grant_user_presence(&mut self) {
         self.state.u2f_grant_user_presence(&mut self.env)
@@ -134,6 +220,7 @@ impl<E: Env> Ctap<E> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::api::customization::Customization;
     use crate::env::test::TestEnv;
 
     /// Assembles a packet for a payload that fits into one packet.
@@ -176,6 +263,31 @@ mod test {
         let response_packet = lock_response.next().unwrap();
         assert_eq!(response_packet[4], 0x88);
         assert!(ctap.should_wink());
+        ctap.update_feedback(0);
+        assert_eq!(
+            ctap.env().user_feedback().last_state(),
+            Some(FeedbackState::Wink)
+        );
+    }
+
+    #[test]
+    fn test_vendor_identify() {
+        let env = TestEnv::default();
+        let mut ctap = Ctap::<TestEnv>::new(env);
+
+        let mut init_response = ctap.process_hid_packet(&init_packet(), Transport::MainHid);
+        let cid = *array_ref!(init_response.next().unwrap(), 15, 4);
+
+        // Send the identify vendor command over CBOR, receive an empty success response.
+        let identify_packet = assemble_packet(&cid, 0x10, &[0x45]);
+        let mut response = ctap.process_hid_packet(&identify_packet, Transport::MainHid);
+        let response_packet = response.next().unwrap();
+        assert_eq!(response_packet[4..8], [0x90, 0x00, 0x01, 0x00]);
+        assert_eq!(ctap.feedback_state(), FeedbackState::Wink);
+
+        let wink_duration_ms = ctap.env().customization().wink_duration_ms();
+        ctap.env().clock().advance(wink_duration_ms);
+        assert_eq!(ctap.feedback_state(), FeedbackState::Idle);
     }
 
     #[test]
@@ -200,6 +312,32 @@ mod test {
         assert_eq!(response_packet[4], 0xBF);
     }
 
+    #[test]
+    fn test_idle_work_waits_for_messages() {
+        let env = TestEnv::default();
+        let mut ctap = Ctap::<TestEnv>::new(env);
+        ctap.env()
+            .customization_mut()
+            .set_store_compaction_threshold(1024);
+        while ctap.env().store().immediate_capacity().unwrap() >= 1024 {
+            ctap.env().store().insert(100, &[0x55; 512]).unwrap();
+        }
+        let mut init_response = ctap.process_hid_packet(&init_packet(), Transport::MainHid);
+        let cid = *array_ref!(init_response.next().unwrap(), 15, 4);
+
+        // The first packet of a CBOR message announces more data than it holds.
+        let mut packet = assemble_packet(&cid, 0x10, &[0x04; 57]);
+        packet[6] = 100;
+        assert!(!ctap
+            .process_hid_packet(&packet, Transport::MainHid)
+            .has_data());
+        assert!(!ctap.process_idle_work());
+
+        // Once the message timed out, maintenance runs.
+        ctap.env().clock().advance(100);
+        assert!(ctap.process_idle_work());
+    }
+
     #[test]
     #[cfg(feature = "vendor_hid")]
     fn test_locked_transport() {
@@ -0,0 +1,172 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runs the Zephyr environment on its stubs, with CTAPHID packets like from a host.
+
+#![cfg(all(feature = "std", feature = "zephyr"))]
+
+extern crate alloc;
+
+use opensk::api::crypto::sha256::Sha256;
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::env::zephyr::stub::{self, STORAGE_AREA, UPGRADE_AREA};
+use opensk::env::zephyr::{ZephyrEnv, ZephyrRunner};
+use opensk::env::{Env, Sha};
+use opensk_vendor_protocol::command::VENDOR_COMMAND_UPGRADE;
+use sk_cbor::{cbor_bytes, cbor_map};
+
+const BROADCAST_CID: [u8; 4] = [0xFF; 4];
+const CTAPHID_INIT: u8 = 0x86;
+const CTAPHID_CBOR: u8 = 0x90;
+const INIT_PAYLOAD_SIZE: usize = 57;
+const CONT_PAYLOAD_SIZE: usize = 59;
+// Enough to receive and answer any message of these tests.
+const MAX_POLLS: usize = 1000;
+
+/// Splits a message into CTAPHID packets.
+fn split_message(cid: [u8; 4], cmd: u8, payload: &[u8]) -> Vec<[u8; 64]> {
+    let mut packet = [0; 64];
+    packet[..4].copy_from_slice(&cid);
+    packet[4] = cmd;
+    packet[5..7].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    let (first, rest) = payload.split_at(payload.len().min(INIT_PAYLOAD_SIZE));
+    packet[7..][..first.len()].copy_from_slice(first);
+    let mut packets = vec![packet];
+    for (seq, chunk) in rest.chunks(CONT_PAYLOAD_SIZE).enumerate() {
+        let mut packet = [0; 64];
+        packet[..4].copy_from_slice(&cid);
+        packet[4] = seq as u8;
+        packet[5..][..chunk.len()].copy_from_slice(chunk);
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Sends a message through the stub HID endpoint, and returns the command and payload of the reply.
+fn exchange(runner: &mut ZephyrRunner, cid: [u8; 4], cmd: u8, payload: &[u8]) -> (u8, Vec<u8>) {
+    for packet in split_message(cid, cmd, payload) {
+        stub::push_hid_report(packet);
+    }
+    let mut reports = Vec::new();
+    for _ in 0..MAX_POLLS {
+        runner.poll();
+        reports.extend(stub::take_hid_reports());
+        let first = match reports.first() {
+            Some(first) => first,
+            None => continue,
+        };
+        let length = u16::from_be_bytes([first[5], first[6]]) as usize;
+        let num_packets = 1 + length
+            .saturating_sub(INIT_PAYLOAD_SIZE)
+            .div_ceil(CONT_PAYLOAD_SIZE);
+        if reports.len() < num_packets {
+            continue;
+        }
+        assert_eq!(reports.len(), num_packets);
+        let mut reply = first[7..].to_vec();
+        for report in &reports[1..] {
+            assert_eq!(report[..4], cid);
+            reply.extend_from_slice(&report[5..]);
+        }
+        reply.truncate(length);
+        return (first[4], reply);
+    }
+    panic!("No reply after {} polls", MAX_POLLS);
+}
+
+/// Allocates a channel, and returns its ID.
+fn init_channel(runner: &mut ZephyrRunner) -> [u8; 4] {
+    let nonce = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0];
+    let (cmd, reply) = exchange(runner, BROADCAST_CID, CTAPHID_INIT, &nonce);
+    assert_eq!(cmd, CTAPHID_INIT);
+    assert_eq!(reply[..8], nonce);
+    [reply[8], reply[9], reply[10], reply[11]]
+}
+
+fn new_runner() -> ZephyrRunner {
+    ZephyrRunner::new(ZephyrEnv::new(STORAGE_AREA, Some(UPGRADE_AREA)).unwrap())
+}
+
+fn upgrade_command(offset: usize, data: &[u8], hash: &[u8; 32]) -> Vec<u8> {
+    let params = cbor_map! {
+        0x01 => offset as u64,
+        0x02 => cbor_bytes!(data.to_vec()),
+        0x03 => cbor_bytes!(hash.to_vec()),
+    };
+    let mut command = vec![VENDOR_COMMAND_UPGRADE];
+    assert!(sk_cbor::write(params, &mut command).is_ok());
+    command
+}
+
+#[test]
+fn test_get_info() {
+    let mut runner = new_runner();
+    let cid = init_channel(&mut runner);
+    // authenticatorGetInfo
+    let (cmd, reply) = exchange(&mut runner, cid, CTAPHID_CBOR, &[0x04]);
+    assert_eq!(cmd, CTAPHID_CBOR);
+    assert_eq!(reply[0], Ctap2StatusCode::CTAP2_OK as u8);
+    assert!(sk_cbor::read(&reply[1..]).is_ok());
+}
+
+#[test]
+fn test_store_persists() {
+    let mut runner = new_runner();
+    runner
+        .ctap()
+        .env()
+        .store()
+        .insert(1000, &[0x5A; 8])
+        .unwrap();
+    drop(runner);
+    let mut runner = new_runner();
+    assert_eq!(
+        runner.ctap().env().store().find(1000).unwrap(),
+        Some(vec![0x5A; 8])
+    );
+}
+
+#[test]
+fn test_upgrade() {
+    let mut runner = new_runner();
+    let cid = init_channel(&mut runner);
+    // Starts with an MCUboot image header, for version 1.0.0+0.
+    let mut data = vec![0xA5; 0x200];
+    data[..4].copy_from_slice(&0x96f3_b83du32.to_le_bytes());
+    data[20..28].copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+    let hash = Sha::<ZephyrEnv>::digest(&data);
+    stub::set_button_pressed(true);
+
+    let (_, reply) = exchange(
+        &mut runner,
+        cid,
+        CTAPHID_CBOR,
+        &upgrade_command(0, &data, &[0; 32]),
+    );
+    assert_eq!(reply, [Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE as u8]);
+    assert!(!stub::upgrade_requested());
+
+    let (_, reply) = exchange(
+        &mut runner,
+        cid,
+        CTAPHID_CBOR,
+        &upgrade_command(0, &data, &hash),
+    );
+    assert_eq!(reply, [Ctap2StatusCode::CTAP2_OK as u8]);
+    assert!(stub::upgrade_requested());
+    assert_eq!(
+        stub::flash_area(UPGRADE_AREA).unwrap()[..data.len()],
+        data[..]
+    );
+}
@@ -0,0 +1,128 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that secrets don't outlive their buffers on the heap.
+//!
+//! The global allocator of this test scans every freed block for a known secret pattern. Secrets
+//! that are zeroized on drop never show up, while plain buffers do.
+
+#![cfg(all(feature = "std", debug_assertions))]
+
+use opensk::api::key_store::{self, KeyStore};
+use opensk::ctap::secret::Secret;
+use opensk::env::test::TestEnv;
+use opensk::env::Env;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Bytes that don't appear by chance in freed memory.
+const PATTERN: [u8; 16] = [
+    0x5E, 0xC2, 0xE7, 0x00, 0xA1, 0x1C, 0x47, 0xED, 0x0F, 0xF1, 0xCE, 0x5E, 0xC2, 0xE7, 0x99, 0x17,
+];
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+static FOUND: AtomicUsize = AtomicUsize::new(0);
+static SCAN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Forwards to the system allocator, and looks for the pattern in freed blocks while armed.
+struct ScanningAllocator;
+
+unsafe impl GlobalAlloc for ScanningAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if ARMED.load(Ordering::SeqCst) {
+            let block = std::slice::from_raw_parts(ptr, layout.size());
+            if block.windows(PATTERN.len()).any(|window| window == PATTERN) {
+                FOUND.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: ScanningAllocator = ScanningAllocator;
+
+/// Returns how many freed blocks contained the pattern while running the closure.
+fn with_scan(f: impl FnOnce()) -> usize {
+    let _guard = SCAN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    FOUND.store(0, Ordering::SeqCst);
+    ARMED.store(true, Ordering::SeqCst);
+    f();
+    ARMED.store(false, Ordering::SeqCst);
+    FOUND.load(Ordering::SeqCst)
+}
+
+/// Returns the pattern repeated to the given length.
+fn pattern_bytes(length: usize) -> Vec<u8> {
+    PATTERN.iter().copied().cycle().take(length).collect()
+}
+
+#[test]
+fn test_plain_buffer_is_found() {
+    let found = with_scan(|| {
+        drop(pattern_bytes(32));
+    });
+    assert_eq!(found, 1);
+}
+
+#[test]
+fn test_secret_is_zeroized() {
+    let found = with_scan(|| {
+        let mut secret: Secret<[u8; 32]> = Secret::default();
+        secret[..16].copy_from_slice(&PATTERN);
+        drop(secret);
+    });
+    assert_eq!(found, 0);
+
+    let found = with_scan(|| {
+        let mut secret = Secret::new(32);
+        secret[..16].copy_from_slice(&PATTERN);
+        drop(secret);
+    });
+    assert_eq!(found, 0);
+}
+
+#[test]
+fn test_master_keys_are_zeroized() {
+    let mut env = TestEnv::default();
+    env.secrets_store()
+        .insert(key_store::STORAGE_KEY, &pattern_bytes(128))
+        .unwrap();
+    let found = with_scan(|| {
+        let cred_random = env.key_store().cred_random(false).unwrap();
+        drop(cred_random);
+    });
+    assert_eq!(found, 0);
+}
+
+#[cfg(feature = "bbs")]
+#[test]
+fn test_link_secret_is_zeroized() {
+    use opensk::api::attestation_store::{self, AttestationStore};
+
+    let mut env = TestEnv::default();
+    env.secrets_store()
+        .insert(attestation_store::STORAGE_KEYS[2], &pattern_bytes(32))
+        .unwrap();
+    let found = with_scan(|| {
+        let link_secret = env.attestation_store().get_link_secret().unwrap();
+        assert!(link_secret.is_some());
+    });
+    assert_eq!(found, 0);
+}
@@ -542,6 +542,7 @@ impl StoreDriverOn {
         let format = self.model.format();
         let storage = self.store.storage();
         let num_words = format.page_size() / format.word_size();
+        self.store.verify()?;
         let head = self.store.head()?;
         let tail = self.store.tail()?;
         for page in 0..format.num_pages() {
@@ -53,6 +53,8 @@
 //! -   [`Store::capacity`] returns how many words can be stored before the store is full.
 //! -   [`Store::lifetime`] returns how many words can be written before the storage lifetime is
 //!     consumed.
+//! -   [`Store::immediate_capacity`] returns how many words can be written without compaction.
+//! -   [`Store::verify`] checks that the storage was not modified outside the store.
 //!
 //! The store provides the following _mutable operations_:
 //! -   Given a set of independent updates, [`Store::transaction`] applies the sequence of updates.
@@ -62,6 +64,8 @@
 //!     words can be written without compaction. This operation has no effect on the store but may
 //!     still mutate its storage. In particular, the store has the same capacity but a possibly
 //!     reduced lifetime.
+//! -   [`Store::wipe`] erases the storage, for example after [`Store::mount`] found it corrupted.
+//!     Lifetime is kept track of.
 //!
 //! A mutable operation is _atomic_ if, when power is lost during the operation, the store is either
 //! updated (as if the operation succeeded) or left unchanged (as if the operation did not occur).
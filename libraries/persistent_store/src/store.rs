@@ -61,8 +61,7 @@ pub enum StoreError {
 
     /// Storage is invalid.
     ///
-    /// The storage should be erased and the store [recovered](Store::recover). The store would be
-    /// empty and have lost track of lifetime.
+    /// The store should be [wiped](Store::wipe). The store would be empty.
     InvalidStorage,
 }
 
@@ -207,6 +206,9 @@ pub struct Store<S: Storage> {
     ///
     /// The position is encoded as the word offset from the [head](Store::head).
     entries: Option<Vec<u16>>,
+
+    /// Whether writes are refused because the storage failed to mount.
+    read_only: bool,
 }
 
 impl<S: Storage> Store<S> {
@@ -230,6 +232,7 @@ impl<S: Storage> Store<S> {
             format,
             head: None,
             entries: None,
+            read_only: false,
         };
         if let Err(error) = store.recover() {
             return Err((error, store.storage));
@@ -237,6 +240,41 @@ impl<S: Storage> Store<S> {
         Ok(store)
     }
 
+    /// Resumes or initializes a store for a given storage, keeping it if corrupted.
+    ///
+    /// Like [`Store::new`], but the store is also [verified](Store::verify). If recovery or
+    /// verification fails, the store is still returned together with the error, but it refuses
+    /// writes with [`StoreError::InvalidStorage`] until [wiped](Store::wipe). If only verification
+    /// failed, reads still see the entries that recovery found.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StoreError::InvalidArgument`] if the storage is not
+    /// [supported](Format::is_storage_supported).
+    pub fn mount(storage: S) -> Result<(Store<S>, StoreResult<()>), (StoreError, S)> {
+        let format = match Format::new(&storage) {
+            None => return Err((StoreError::InvalidArgument, storage)),
+            Some(x) => x,
+        };
+        let mut store = Store {
+            storage,
+            format,
+            head: None,
+            entries: None,
+            read_only: false,
+        };
+        let result = match store.recover() {
+            Ok(()) => store.verify(),
+            Err(error) => {
+                store.head = None;
+                store.entries = None;
+                Err(error)
+            }
+        };
+        store.read_only = result.is_err();
+        Ok((store, result))
+    }
+
     /// Extracts the storage.
     pub fn extract_storage(self) -> S {
         self.storage
@@ -374,7 +412,7 @@ impl<S: Storage> Store<S> {
         if self.capacity()?.remaining() < length {
             return Err(StoreError::NoCapacity);
         }
-        if self.immediate_capacity()? < usize_to_nat(length) {
+        if self.immediate_capacity()? < length {
             self.compact()?;
         }
         Ok(())
@@ -391,6 +429,70 @@ impl<S: Storage> Store<S> {
         Ok(())
     }
 
+    /// Checks that the storage was not modified outside the store.
+    ///
+    /// Entries carry checksums, and recovery only expects entries failing them at the tail, where
+    /// an operation may have been interrupted. This walks the whole window again, so it also
+    /// notices such entries before the tail, and entries that are valid but unknown to the store.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StoreError::InvalidStorage`] if the storage is corrupted or failed to recover.
+    pub fn verify(&self) -> StoreResult<()> {
+        let head = or_invalid(self.head)?;
+        let mut expected = or_invalid(self.entries.clone())?;
+        let tail = self.tail()?;
+        let mut found = Vec::with_capacity(expected.len());
+        let mut pos = head;
+        while pos < tail {
+            let entry_pos = pos;
+            match self.parse_entry(&mut pos)? {
+                ParsedEntry::User(_) => {
+                    found.push(or_invalid(u16::try_from(entry_pos - head).ok())?);
+                }
+                ParsedEntry::Padding | ParsedEntry::Internal(_) => (),
+                ParsedEntry::Tail | ParsedEntry::Partial | ParsedEntry::PartialUser => {
+                    return Err(StoreError::InvalidStorage);
+                }
+            }
+        }
+        expected.sort_unstable();
+        if pos != tail || found != expected {
+            return Err(StoreError::InvalidStorage);
+        }
+        Ok(())
+    }
+
+    /// Erases the storage and initializes an empty store.
+    ///
+    /// This also works on a store that failed to [mount](Store::mount). The erase cycles of the
+    /// pages that are still readable are carried over, so lifetime is not lost.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StoreError::NoLifetime`] if a page can't be erased again.
+    pub fn wipe(&mut self) -> StoreResult<()> {
+        let mut cycle = 0;
+        for page in 0..self.format.num_pages() {
+            if let Ok(WordState::Valid(init)) = self.parse_init(page) {
+                cycle = max(cycle, init.cycle + 1);
+            }
+        }
+        if cycle > self.format.max_page_erases() {
+            return Err(StoreError::NoLifetime);
+        }
+        self.read_only = false;
+        self.head = None;
+        self.entries = None;
+        for page in 0..self.format.num_pages() {
+            self.storage_erase_page(page)?;
+        }
+        let index = self.format.index_init(0);
+        let init_info = self.format.build_init(InitInfo { cycle, prefix: 0 })?;
+        self.storage_write_slice(index, &init_info)?;
+        self.recover()
+    }
+
     /// Returns the value of an entry given its key.
     pub fn find(&self, key: usize) -> StoreResult<Option<Vec<u8>>> {
         Ok(match self.find_handle(key)? {
@@ -495,6 +597,10 @@ impl<S: Storage> Store<S> {
                 return Ok(());
             }
         }
+        if let WordState::Valid(_) = self.parse_init(0)? {
+            // The store was wiped.
+            return Ok(());
+        }
         let index = self.format.index_init(0);
         let init_info = self.format.build_init(InitInfo {
             cycle: 0,
@@ -690,7 +796,7 @@ impl<S: Storage> Store<S> {
         if self.capacity()?.remaining() < length as usize {
             return Err(StoreError::NoCapacity);
         }
-        while self.immediate_capacity()? < length {
+        while self.immediate_capacity()? < length as usize {
             self.compact()?;
         }
         Ok(())
@@ -931,10 +1037,14 @@ impl<S: Storage> Store<S> {
     }
 
     /// Returns the number of words that can be written without compaction.
-    fn immediate_capacity(&self) -> StoreResult<Nat> {
+    ///
+    /// Writes longer than that first compact pages, see [`Store::prepare`] to do it ahead of time.
+    /// This may exceed the remaining [capacity](Store::capacity), since it also counts words that
+    /// internal entries would use.
+    pub fn immediate_capacity(&self) -> StoreResult<usize> {
         let tail = self.tail()?;
         let end = or_invalid(self.head)? + self.format.virt_size();
-        Ok(end.get().saturating_sub(tail.get()))
+        Ok(end.get().saturating_sub(tail.get()) as usize)
     }
 
     /// Returns the position of the first word in the store.
@@ -1117,6 +1227,9 @@ impl<S: Storage> Store<S> {
     /// Only starts writing the slice from the first word that needs to be written (because it
     /// differs from the current value).
     fn storage_write_slice(&mut self, index: StorageIndex, value: &[u8]) -> StoreResult<()> {
+        if self.read_only {
+            return Err(StoreError::InvalidStorage);
+        }
         let word_size = self.format.word_size();
         debug_assert!(usize_to_nat(value.len()) % word_size == 0);
         let slice = self.storage.read_slice(index, value.len())?;
@@ -1142,6 +1255,9 @@ impl<S: Storage> Store<S> {
 
     /// Erases a page if not already erased.
     fn storage_erase_page(&mut self, page: Nat) -> StoreResult<()> {
+        if self.read_only {
+            return Err(StoreError::InvalidStorage);
+        }
         if !is_erased(&self.read_page(page)) {
             self.storage.erase_page(page as usize)?;
         }
@@ -1459,4 +1575,50 @@ mod tests {
         driver.remove(0).unwrap();
         assert_eq!(driver.store().entries, Some(vec![LEN as u16]));
     }
+
+    #[test]
+    fn mount_ok() {
+        let mut store = MINIMAL.new_store();
+        store.insert(0, &[0x38; 8]).unwrap();
+        store.insert(1, &[0x5c; 8]).unwrap();
+        let (store, result) = Store::mount(store.extract_storage()).ok().unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(store.find(1), Ok(Some(vec![0x5c; 8])));
+    }
+
+    #[test]
+    fn mount_corrupted() {
+        let mut store = MINIMAL.new_store();
+        store.insert(0, &[0x38; 8]).unwrap();
+        store.insert(1, &[0x5c; 8]).unwrap();
+        store.insert(2, &[0x93; 8]).unwrap();
+        let mut storage = store.extract_storage();
+        // Clear the last word of the first entry, which starts after the 2 page info words.
+        let index = StorageIndex { page: 0, byte: 16 };
+        storage.write_slice(index, &[0x00; 4]).unwrap();
+
+        // Recovery only expects corruption at the tail, so it misses the entries after it.
+        let (mut store, result) = Store::mount(storage).ok().unwrap();
+        assert_eq!(result, Err(StoreError::InvalidStorage));
+        assert_eq!(store.verify(), Err(StoreError::InvalidStorage));
+        assert_eq!(store.insert(3, &[]), Err(StoreError::InvalidStorage));
+
+        store.wipe().unwrap();
+        assert_eq!(store.verify(), Ok(()));
+        assert_eq!(store.find(1), Ok(None));
+        store.insert(3, &[0x81; 4]).unwrap();
+        assert_eq!(store.find(3), Ok(Some(vec![0x81; 4])));
+    }
+
+    #[test]
+    fn wipe_keeps_lifetime() {
+        let mut store = MINIMAL.new_store();
+        for _ in 0..10 {
+            store.insert(0, &[0x38; 28]).unwrap();
+        }
+        let lifetime = store.lifetime().unwrap().used();
+        store.wipe().unwrap();
+        assert!(store.lifetime().unwrap().used() > lifetime);
+        assert_eq!(store.capacity().unwrap().used(), 0);
+    }
 }
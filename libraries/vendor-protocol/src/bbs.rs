@@ -0,0 +1,951 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Requests and responses of the BBS vendor commands.
+//!
+//! The firmware decodes the requests and encodes the responses with these types, host tools do
+//! the opposite. The firmware checks the cryptographic values on top, in `vendor::parameters` of
+//! the `opensk` library.
+
+use crate::codec::{
+    extract_array, extract_bool, extract_byte_string, extract_map, extract_text_string,
+    extract_unsigned, ok_or_missing, DecodeError,
+};
+use crate::command::{ISSUERS_REGISTER, ISSUERS_REMOVE, NON_REVOCATION_STATUS_LIST};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use sk_cbor as cbor;
+use sk_cbor::{cbor_array_vec, cbor_map_options, destructure_cbor_map};
+
+/// Length of SHA-256 digests, like presentation contexts and salted messages.
+pub const DIGEST_SIZE: usize = 32;
+
+/// Length of the truncated SHA-256 of the signature that identifies a credential.
+pub const CREDENTIAL_ID_SIZE: usize = 16;
+
+/// Parameters of the BBS proof command.
+///
+/// The prover blind either comes with the request or the device looks it up by issuer id, and
+/// exactly one of them is present.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VendorBBSProofRequest {
+    pub public_key: Vec<u8>,
+    pub messages: Vec<Vec<u8>>,
+    pub signature: Vec<u8>,
+    pub header: Vec<u8>,
+    pub presentation_header: Vec<u8>,
+    pub disclosed_indexes: Vec<usize>,
+    /// The blind the host kept from the commitment.
+    pub secret_prover_blind: Option<Vec<u8>>,
+    /// Issuer under which the device stored the blind of the latest commitment.
+    pub issuer_id: Option<Vec<u8>>,
+    /// Salt of the relying party, for a per-device value appended to the presentation header.
+    pub context_salt: Option<[u8; DIGEST_SIZE]>,
+    /// The messages are salted digests of the attributes, and the values never reach the device.
+    pub salted_digests: bool,
+    /// The credential was issued over the link secret scoped to its issuer public key.
+    pub scoped_link_secret: bool,
+    /// The proof uses the SHA-256 digest of the presentation header, context included.
+    pub prehash_presentation_header: bool,
+    /// The device starts the presentation header with its firmware version and lockdown level.
+    pub bind_device_state: bool,
+    /// Index of the message with the status list entry of the credential, which is disclosed.
+    pub status_list_index: Option<usize>,
+}
+
+impl VendorBBSProofRequest {
+    const DISCLOSED_INDEXES: u64 = 0x06;
+
+    /// Encodes the request, with the disclosed indexes only for proofs.
+    fn encode(self, with_disclosed_indexes: bool) -> cbor::Value {
+        let VendorBBSProofRequest {
+            public_key,
+            messages,
+            signature,
+            header,
+            presentation_header,
+            disclosed_indexes,
+            secret_prover_blind,
+            issuer_id,
+            context_salt,
+            salted_digests,
+            scoped_link_secret,
+            prehash_presentation_header,
+            bind_device_state,
+            status_list_index,
+        } = self;
+        let disclosed_indexes = with_disclosed_indexes.then(|| {
+            let disclosed_indexes = disclosed_indexes
+                .into_iter()
+                .map(|index| index as u64)
+                .collect::<Vec<_>>();
+            cbor_array_vec!(disclosed_indexes)
+        });
+
+        cbor_map_options! {
+            0x01 => public_key,
+            0x02 => cbor_array_vec!(messages),
+            0x03 => signature,
+            0x04 => header,
+            0x05 => presentation_header,
+            0x06 => disclosed_indexes,
+            0x07 => secret_prover_blind,
+            0x08 => issuer_id,
+            0x09 => context_salt.as_ref().map(|salt| &salt[..]),
+            0x0A => salted_digests.then_some(true),
+            0x0B => scoped_link_secret.then_some(true),
+            0x0C => prehash_presentation_header.then_some(true),
+            0x0D => bind_device_state.then_some(true),
+            0x0E => status_list_index.map(|index| index as u64),
+        }
+    }
+}
+
+impl From<VendorBBSProofRequest> for cbor::Value {
+    fn from(vendor_bbs_proof_request: VendorBBSProofRequest) -> Self {
+        vendor_bbs_proof_request.encode(true)
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSProofRequest {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => public_key,
+                0x02 => messages,
+                0x03 => signature,
+                0x04 => header,
+                0x05 => presentation_header,
+                0x06 => disclosed_indexes,
+                0x07 => secret_prover_blind,
+                0x08 => issuer_id,
+                0x09 => context_salt,
+                0x0A => salted_digests,
+                0x0B => scoped_link_secret,
+                0x0C => prehash_presentation_header,
+                0x0D => bind_device_state,
+                0x0E => status_list_index,
+            } = extract_map(cbor_value)?;
+        }
+
+        let public_key = extract_byte_string(ok_or_missing(public_key)?)
+            .map_err(|_| DecodeError::InvalidParameter)?;
+        let scoped_link_secret = scoped_link_secret.map_or(Ok(false), extract_bool)?;
+
+        let messages =
+            extract_array(ok_or_missing(messages)?).map_err(|_| DecodeError::InvalidParameter)?;
+        let messages = messages
+            .into_iter()
+            .map(extract_byte_string)
+            .collect::<Result<Vec<_>, DecodeError>>()?;
+        let salted_digests = salted_digests.map_or(Ok(false), extract_bool)?;
+        if salted_digests && messages.iter().any(|message| message.len() != DIGEST_SIZE) {
+            return Err(DecodeError::InvalidParameter);
+        }
+
+        let signature = extract_byte_string(ok_or_missing(signature)?)
+            .map_err(|_| DecodeError::InvalidParameter)?;
+        let header = extract_byte_string(ok_or_missing(header)?)
+            .map_err(|_| DecodeError::InvalidParameter)?;
+        let presentation_header = extract_byte_string(ok_or_missing(presentation_header)?)
+            .map_err(|_| DecodeError::InvalidParameter)?;
+
+        let disclosed_indexes = extract_array(ok_or_missing(disclosed_indexes)?)
+            .map_err(|_| DecodeError::InvalidParameter)?;
+        let disclosed_indexes = disclosed_indexes
+            .into_iter()
+            .map(|index| extract_unsigned(index).map(|u| u as usize))
+            .collect::<Result<Vec<usize>, DecodeError>>()?;
+
+        let (secret_prover_blind, issuer_id) = match (secret_prover_blind, issuer_id) {
+            (Some(secret_prover_blind), None) => {
+                let secret_prover_blind = extract_byte_string(secret_prover_blind)
+                    .map_err(|_| DecodeError::InvalidParameter)?;
+                (Some(secret_prover_blind), None)
+            }
+            (None, Some(issuer_id)) => (None, Some(extract_byte_string(issuer_id)?)),
+            (None, None) => return Err(DecodeError::MissingParameter),
+            (Some(_), Some(_)) => return Err(DecodeError::InvalidParameter),
+        };
+
+        let context_salt = context_salt
+            .map(|context_salt| {
+                <[u8; DIGEST_SIZE]>::try_from(&extract_byte_string(context_salt)?[..])
+                    .map_err(|_| DecodeError::InvalidParameter)
+            })
+            .transpose()?;
+        let prehash_presentation_header =
+            prehash_presentation_header.map_or(Ok(false), extract_bool)?;
+        let bind_device_state = bind_device_state.map_or(Ok(false), extract_bool)?;
+        let status_list_index = status_list_index
+            .map(extract_unsigned)
+            .transpose()?
+            .map(|index| index as usize);
+        // Verifiers only trust the entry as part of the proof, so the proof must disclose it.
+        if let Some(index) = status_list_index {
+            if index >= messages.len() || !disclosed_indexes.contains(&index) {
+                return Err(DecodeError::InvalidParameter);
+            }
+        }
+
+        Ok(VendorBBSProofRequest {
+            public_key,
+            messages,
+            signature,
+            header,
+            presentation_header,
+            disclosed_indexes,
+            secret_prover_blind,
+            issuer_id,
+            context_salt,
+            salted_digests,
+            scoped_link_secret,
+            prehash_presentation_header,
+            bind_device_state,
+            status_list_index,
+        })
+    }
+}
+
+/// Parameters of a proof of possession, which discloses no attribute.
+///
+/// The keys are those of proofs, with the verifier nonce as presentation header at 0x05. Disclosed
+/// indexes at 0x06 are not allowed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VendorBBSPossessionRequest(pub VendorBBSProofRequest);
+
+impl From<VendorBBSPossessionRequest> for cbor::Value {
+    fn from(vendor_bbs_possession_request: VendorBBSPossessionRequest) -> Self {
+        let VendorBBSPossessionRequest(request) = vendor_bbs_possession_request;
+        request.encode(false)
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSPossessionRequest {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        let disclosed_indexes_key = cbor::Value::from(VendorBBSProofRequest::DISCLOSED_INDEXES);
+        let mut map = extract_map(cbor_value)?;
+        if map.iter().any(|(key, _)| *key == disclosed_indexes_key) {
+            return Err(DecodeError::InvalidParameter);
+        }
+        map.push((disclosed_indexes_key, cbor_array_vec!(Vec::<u64>::new())));
+        let request = VendorBBSProofRequest::try_from(cbor::Value::map(map))?;
+        Ok(VendorBBSPossessionRequest(request))
+    }
+}
+
+/// Shows verifiers that a credential is not revoked, next to its BBS proof.
+///
+/// Verifiers check it without asking the issuer. Each kind has its own type in the CBOR map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NonRevocationProof {
+    /// The BBS proof discloses the message with the status list entry of the credential.
+    ///
+    /// Verifiers look the entry up in the status list the issuer publishes.
+    StatusList {
+        message_index: usize,
+        entry: Vec<u8>,
+    },
+}
+
+impl From<NonRevocationProof> for cbor::Value {
+    fn from(non_revocation_proof: NonRevocationProof) -> Self {
+        match non_revocation_proof {
+            NonRevocationProof::StatusList {
+                message_index,
+                entry,
+            } => cbor_map_options! {
+                0x01 => NON_REVOCATION_STATUS_LIST,
+                0x02 => message_index as u64,
+                0x03 => entry,
+            },
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for NonRevocationProof {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => proof_type,
+                0x02 => message_index,
+                0x03 => entry,
+            } = extract_map(cbor_value)?;
+        }
+        // Unknown kinds of non-revocation proofs can't be checked.
+        match extract_unsigned(ok_or_missing(proof_type)?)? {
+            NON_REVOCATION_STATUS_LIST => Ok(NonRevocationProof::StatusList {
+                message_index: extract_usize(ok_or_missing(message_index)?)?,
+                entry: extract_byte_string(ok_or_missing(entry)?)?,
+            }),
+            _ => Err(DecodeError::InvalidParameter),
+        }
+    }
+}
+
+/// The context is present if the request had a salt. Verifiers append it to the presentation
+/// header. Likewise, they prepend the device state if it was requested.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VendorBBSProofResponse {
+    pub proof_bytes: Vec<u8>,
+    pub presentation_context: Option<[u8; DIGEST_SIZE]>,
+    pub device_state: Option<Vec<u8>>,
+    pub non_revocation: Option<NonRevocationProof>,
+}
+
+impl From<VendorBBSProofResponse> for cbor::Value {
+    fn from(vendor_bbs_response: VendorBBSProofResponse) -> Self {
+        let VendorBBSProofResponse {
+            proof_bytes,
+            presentation_context,
+            device_state,
+            non_revocation,
+        } = vendor_bbs_response;
+
+        cbor_map_options! {
+            0x01 => proof_bytes,
+            0x02 => presentation_context.as_ref().map(|context| &context[..]),
+            0x03 => device_state,
+            0x04 => non_revocation.map(cbor::Value::from),
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSProofResponse {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => proof_bytes,
+                0x02 => presentation_context,
+                0x03 => device_state,
+                0x04 => non_revocation,
+            } = extract_map(cbor_value)?;
+        }
+        let presentation_context = presentation_context
+            .map(|context| {
+                <[u8; DIGEST_SIZE]>::try_from(&extract_byte_string(context)?[..])
+                    .map_err(|_| DecodeError::InvalidParameter)
+            })
+            .transpose()?;
+        Ok(VendorBBSProofResponse {
+            proof_bytes: extract_byte_string(ok_or_missing(proof_bytes)?)?,
+            presentation_context,
+            device_state: device_state.map(extract_byte_string).transpose()?,
+            non_revocation: non_revocation
+                .map(NonRevocationProof::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+/// A known issuer and what its credentials look like.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issuer {
+    pub public_key: Vec<u8>,
+    pub name: String,
+    /// Number of messages of every credential of this issuer.
+    pub num_messages: u8,
+}
+
+impl From<Issuer> for cbor::Value {
+    fn from(issuer: Issuer) -> Self {
+        cbor_map_options! {
+            0x01 => issuer.public_key,
+            0x02 => issuer.name,
+            0x03 => issuer.num_messages as u64,
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for Issuer {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => public_key,
+                0x02 => name,
+                0x03 => num_messages,
+            } = extract_map(cbor_value)?;
+        }
+        Ok(Issuer {
+            public_key: extract_byte_string(ok_or_missing(public_key)?)?,
+            name: extract_text_string(ok_or_missing(name)?)?,
+            num_messages: extract_num_messages(ok_or_missing(num_messages)?)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VendorBBSInfoResponse {
+    pub max_messages: usize,
+    pub max_credentials: usize,
+    pub max_proof_size: usize,
+    pub requires_uv: bool,
+    pub allows_external_link_secret: bool,
+    /// Issuers the device accepts. Empty if it accepts all of them.
+    pub issuers: Vec<Issuer>,
+}
+
+impl From<VendorBBSInfoResponse> for cbor::Value {
+    fn from(vendor_bbs_info_response: VendorBBSInfoResponse) -> Self {
+        let VendorBBSInfoResponse {
+            max_messages,
+            max_credentials,
+            max_proof_size,
+            requires_uv,
+            allows_external_link_secret,
+            issuers,
+        } = vendor_bbs_info_response;
+        // Devices without a registry answer like before it existed.
+        let issuers = (!issuers.is_empty()).then(|| {
+            let issuers = issuers
+                .into_iter()
+                .map(cbor::Value::from)
+                .collect::<Vec<_>>();
+            cbor_array_vec!(issuers)
+        });
+
+        cbor_map_options! {
+            0x01 => max_messages as u64,
+            0x02 => max_credentials as u64,
+            0x03 => max_proof_size as u64,
+            0x04 => requires_uv,
+            0x05 => allows_external_link_secret,
+            0x06 => issuers,
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSInfoResponse {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => max_messages,
+                0x02 => max_credentials,
+                0x03 => max_proof_size,
+                0x04 => requires_uv,
+                0x05 => allows_external_link_secret,
+                0x06 => issuers,
+            } = extract_map(cbor_value)?;
+        }
+        let issuers = match issuers {
+            Some(issuers) => extract_array(issuers)?
+                .into_iter()
+                .map(Issuer::try_from)
+                .collect::<Result<Vec<_>, DecodeError>>()?,
+            None => Vec::new(),
+        };
+        Ok(VendorBBSInfoResponse {
+            max_messages: extract_usize(ok_or_missing(max_messages)?)?,
+            max_credentials: extract_usize(ok_or_missing(max_credentials)?)?,
+            max_proof_size: extract_usize(ok_or_missing(max_proof_size)?)?,
+            requires_uv: extract_bool(ok_or_missing(requires_uv)?)?,
+            allows_external_link_secret: extract_bool(ok_or_missing(allows_external_link_secret)?)?,
+            issuers,
+        })
+    }
+}
+
+/// What the device remembers about the proofs of a credential.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CredentialUsage {
+    pub credential_id: [u8; CREDENTIAL_ID_SIZE],
+    pub presentations: u32,
+    /// Boot count of the latest proof.
+    pub last_boot: u32,
+}
+
+impl From<CredentialUsage> for cbor::Value {
+    fn from(credential: CredentialUsage) -> Self {
+        cbor_map_options! {
+            0x01 => &credential.credential_id[..],
+            0x02 => credential.presentations as u64,
+            0x03 => credential.last_boot as u64,
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for CredentialUsage {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => credential_id,
+                0x02 => presentations,
+                0x03 => last_boot,
+            } = extract_map(cbor_value)?;
+        }
+        let credential_id = extract_byte_string(ok_or_missing(credential_id)?)?;
+        Ok(CredentialUsage {
+            credential_id: <[u8; CREDENTIAL_ID_SIZE]>::try_from(&credential_id[..])
+                .map_err(|_| DecodeError::InvalidParameter)?,
+            presentations: extract_u32(ok_or_missing(presentations)?)?,
+            last_boot: extract_u32(ok_or_missing(last_boot)?)?,
+        })
+    }
+}
+
+/// Presentation counts of BBS credentials, most recent first.
+///
+/// The current boot count tells how long ago the latest proofs were.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VendorBBSUsageResponse {
+    pub boot_count: u32,
+    pub credentials: Vec<CredentialUsage>,
+}
+
+impl From<VendorBBSUsageResponse> for cbor::Value {
+    fn from(vendor_bbs_usage_response: VendorBBSUsageResponse) -> Self {
+        let VendorBBSUsageResponse {
+            boot_count,
+            credentials,
+        } = vendor_bbs_usage_response;
+        let credentials = credentials
+            .into_iter()
+            .map(cbor::Value::from)
+            .collect::<Vec<_>>();
+
+        cbor_map_options! {
+            0x01 => boot_count as u64,
+            0x02 => cbor_array_vec!(credentials),
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSUsageResponse {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => boot_count,
+                0x02 => credentials,
+            } = extract_map(cbor_value)?;
+        }
+        let credentials = extract_array(ok_or_missing(credentials)?)?
+            .into_iter()
+            .map(CredentialUsage::try_from)
+            .collect::<Result<Vec<_>, DecodeError>>()?;
+        Ok(VendorBBSUsageResponse {
+            boot_count: extract_u32(ok_or_missing(boot_count)?)?,
+            credentials,
+        })
+    }
+}
+
+/// Epoch of BBS credentials after revoking them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VendorBBSEpochResponse {
+    pub epoch: u32,
+}
+
+impl From<VendorBBSEpochResponse> for cbor::Value {
+    fn from(vendor_bbs_epoch_response: VendorBBSEpochResponse) -> Self {
+        let VendorBBSEpochResponse { epoch } = vendor_bbs_epoch_response;
+        cbor_map_options! {
+            0x01 => epoch as u64,
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSEpochResponse {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => epoch,
+            } = extract_map(cbor_value)?;
+        }
+        Ok(VendorBBSEpochResponse {
+            epoch: extract_u32(ok_or_missing(epoch)?)?,
+        })
+    }
+}
+
+/// Subcommands of the BBS issuer registry.
+///
+/// Registering an issuer again renames it. The firmware also checks that registered keys decode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VendorBBSIssuersParameters {
+    Register(Issuer),
+    Remove { public_key: Vec<u8> },
+}
+
+impl From<VendorBBSIssuersParameters> for cbor::Value {
+    fn from(vendor_bbs_issuers_parameters: VendorBBSIssuersParameters) -> Self {
+        match vendor_bbs_issuers_parameters {
+            VendorBBSIssuersParameters::Register(issuer) => cbor_map_options! {
+                0x01 => ISSUERS_REGISTER,
+                0x02 => issuer.public_key,
+                0x03 => issuer.name,
+                0x04 => issuer.num_messages as u64,
+            },
+            VendorBBSIssuersParameters::Remove { public_key } => cbor_map_options! {
+                0x01 => ISSUERS_REMOVE,
+                0x02 => public_key,
+            },
+        }
+    }
+}
+
+impl TryFrom<cbor::Value> for VendorBBSIssuersParameters {
+    type Error = DecodeError;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, DecodeError> {
+        destructure_cbor_map! {
+            let {
+                0x01 => subcommand,
+                0x02 => public_key,
+                0x03 => name,
+                0x04 => num_messages,
+            } = extract_map(cbor_value)?;
+        }
+        match extract_unsigned(ok_or_missing(subcommand)?)? {
+            ISSUERS_REGISTER => Ok(VendorBBSIssuersParameters::Register(Issuer {
+                public_key: extract_byte_string(ok_or_missing(public_key)?)?,
+                name: extract_text_string(ok_or_missing(name)?)?,
+                num_messages: extract_num_messages(ok_or_missing(num_messages)?)?,
+            })),
+            // Entries are removed by their bytes, whatever the key decodes to.
+            ISSUERS_REMOVE => Ok(VendorBBSIssuersParameters::Remove {
+                public_key: extract_byte_string(ok_or_missing(public_key)?)?,
+            }),
+            _ => Err(DecodeError::InvalidSubcommand),
+        }
+    }
+}
+
+fn extract_usize(cbor_value: cbor::Value) -> Result<usize, DecodeError> {
+    usize::try_from(extract_unsigned(cbor_value)?).map_err(|_| DecodeError::InvalidParameter)
+}
+
+fn extract_u32(cbor_value: cbor::Value) -> Result<u32, DecodeError> {
+    u32::try_from(extract_unsigned(cbor_value)?).map_err(|_| DecodeError::InvalidParameter)
+}
+
+fn extract_num_messages(cbor_value: cbor::Value) -> Result<u8, DecodeError> {
+    u8::try_from(extract_unsigned(cbor_value)?).map_err(|_| DecodeError::InvalidParameter)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use sk_cbor::{cbor_array, cbor_int, cbor_map};
+
+    fn proof_request() -> VendorBBSProofRequest {
+        VendorBBSProofRequest {
+            public_key: vec![0x01; 96],
+            messages: vec![b"name=Alice".to_vec(), b"status=42".to_vec()],
+            signature: vec![0x02; 80],
+            header: b"header".to_vec(),
+            presentation_header: b"nonce".to_vec(),
+            disclosed_indexes: vec![1],
+            secret_prover_blind: Some(vec![0x03; 32]),
+            status_list_index: Some(1),
+            ..VendorBBSProofRequest::default()
+        }
+    }
+
+    #[test]
+    fn test_vendor_bbs_proof_request() {
+        let request = proof_request();
+        let request_cbor = cbor::Value::from(request.clone());
+        let expected_cbor = cbor_map! {
+            0x01 => vec![0x01; 96],
+            0x02 => cbor_array![b"name=Alice".to_vec(), b"status=42".to_vec()],
+            0x03 => vec![0x02; 80],
+            0x04 => b"header".to_vec(),
+            0x05 => b"nonce".to_vec(),
+            0x06 => cbor_array![1],
+            0x07 => vec![0x03; 32],
+            0x0E => 1,
+        };
+        assert_eq!(request_cbor, expected_cbor);
+        assert_eq!(VendorBBSProofRequest::try_from(request_cbor), Ok(request));
+
+        // The device needs exactly one source of the prover blind.
+        let request = VendorBBSProofRequest {
+            secret_prover_blind: None,
+            ..proof_request()
+        };
+        assert_eq!(
+            VendorBBSProofRequest::try_from(cbor::Value::from(request)),
+            Err(DecodeError::MissingParameter)
+        );
+        let request = VendorBBSProofRequest {
+            issuer_id: Some(b"issuer".to_vec()),
+            ..proof_request()
+        };
+        assert_eq!(
+            VendorBBSProofRequest::try_from(cbor::Value::from(request)),
+            Err(DecodeError::InvalidParameter)
+        );
+
+        // The status list entry must be disclosed.
+        let request = VendorBBSProofRequest {
+            disclosed_indexes: vec![0],
+            ..proof_request()
+        };
+        assert_eq!(
+            VendorBBSProofRequest::try_from(cbor::Value::from(request)),
+            Err(DecodeError::InvalidParameter)
+        );
+
+        // Salted digests have the size of a digest.
+        let request = VendorBBSProofRequest {
+            salted_digests: true,
+            ..proof_request()
+        };
+        assert_eq!(
+            VendorBBSProofRequest::try_from(cbor::Value::from(request)),
+            Err(DecodeError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn test_vendor_bbs_possession_request() {
+        let request = VendorBBSPossessionRequest(VendorBBSProofRequest {
+            disclosed_indexes: Vec::new(),
+            status_list_index: None,
+            ..proof_request()
+        });
+        let request_cbor = cbor::Value::from(request.clone());
+        let map = request_cbor.clone().extract_map().unwrap();
+        assert!(map.iter().all(|(key, _)| *key != cbor_int!(0x06)));
+        assert_eq!(
+            VendorBBSPossessionRequest::try_from(request_cbor),
+            Ok(request)
+        );
+
+        // Possession proofs disclose nothing.
+        let request_cbor = cbor::Value::from(proof_request());
+        assert_eq!(
+            VendorBBSPossessionRequest::try_from(request_cbor),
+            Err(DecodeError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn test_vendor_bbs_proof_response() {
+        let response = VendorBBSProofResponse {
+            proof_bytes: vec![0x01, 0x02],
+            presentation_context: Some([0x03; DIGEST_SIZE]),
+            device_state: None,
+            non_revocation: Some(NonRevocationProof::StatusList {
+                message_index: 2,
+                entry: b"status=42".to_vec(),
+            }),
+        };
+        let response_cbor = cbor::Value::from(response.clone());
+        let expected_cbor = cbor_map! {
+            0x01 => vec![0x01, 0x02],
+            0x02 => vec![0x03; DIGEST_SIZE],
+            0x04 => cbor_map! {
+                0x01 => NON_REVOCATION_STATUS_LIST,
+                0x02 => 2,
+                0x03 => b"status=42".to_vec(),
+            },
+        };
+        assert_eq!(response_cbor, expected_cbor);
+        assert_eq!(
+            VendorBBSProofResponse::try_from(response_cbor),
+            Ok(response)
+        );
+
+        // Unknown kinds of non-revocation proofs can't be checked.
+        let cbor_value = cbor_map! {
+            0x01 => vec![0x01, 0x02],
+            0x04 => cbor_map! {
+                0x01 => 0x7F,
+            },
+        };
+        assert_eq!(
+            VendorBBSProofResponse::try_from(cbor_value),
+            Err(DecodeError::InvalidParameter)
+        );
+        let cbor_value = cbor_map! {
+            0x02 => vec![0x03; DIGEST_SIZE],
+        };
+        assert_eq!(
+            VendorBBSProofResponse::try_from(cbor_value),
+            Err(DecodeError::MissingParameter)
+        );
+    }
+
+    #[test]
+    fn test_vendor_bbs_info_response() {
+        let response = VendorBBSInfoResponse {
+            max_messages: 32,
+            max_credentials: 16,
+            max_proof_size: 2048,
+            requires_uv: false,
+            allows_external_link_secret: true,
+            issuers: vec![Issuer {
+                public_key: vec![0x01; 96],
+                name: "DMV".to_string(),
+                num_messages: 3,
+            }],
+        };
+        let response_cbor = cbor::Value::from(response.clone());
+        let expected_cbor = cbor_map! {
+            0x01 => 32,
+            0x02 => 16,
+            0x03 => 2048,
+            0x04 => false,
+            0x05 => true,
+            0x06 => cbor_array![cbor_map! {
+                0x01 => vec![0x01; 96],
+                0x02 => "DMV",
+                0x03 => 3,
+            }],
+        };
+        assert_eq!(response_cbor, expected_cbor);
+        assert_eq!(VendorBBSInfoResponse::try_from(response_cbor), Ok(response));
+
+        // Devices without a registry leave the issuers out.
+        let response = VendorBBSInfoResponse {
+            issuers: Vec::new(),
+            ..VendorBBSInfoResponse::try_from(expected_cbor).unwrap()
+        };
+        let response_cbor = cbor::Value::from(response.clone());
+        let map = response_cbor.clone().extract_map().unwrap();
+        assert!(map.iter().all(|(key, _)| *key != cbor_int!(0x06)));
+        assert_eq!(VendorBBSInfoResponse::try_from(response_cbor), Ok(response));
+    }
+
+    #[test]
+    fn test_vendor_bbs_issuers_parameters() {
+        let params = VendorBBSIssuersParameters::Register(Issuer {
+            public_key: vec![0x01; 96],
+            name: "DMV".to_string(),
+            num_messages: 3,
+        });
+        let params_cbor = cbor::Value::from(params.clone());
+        let expected_cbor = cbor_map! {
+            0x01 => ISSUERS_REGISTER,
+            0x02 => vec![0x01; 96],
+            0x03 => "DMV",
+            0x04 => 3,
+        };
+        assert_eq!(params_cbor, expected_cbor);
+        assert_eq!(
+            VendorBBSIssuersParameters::try_from(params_cbor),
+            Ok(params)
+        );
+
+        let params = VendorBBSIssuersParameters::Remove {
+            public_key: vec![0x01; 96],
+        };
+        let params_cbor = cbor::Value::from(params.clone());
+        assert_eq!(
+            params_cbor,
+            cbor_map! {
+                0x01 => ISSUERS_REMOVE,
+                0x02 => vec![0x01; 96],
+            }
+        );
+        assert_eq!(
+            VendorBBSIssuersParameters::try_from(params_cbor),
+            Ok(params)
+        );
+
+        let cbor_value = cbor_map! {
+            0x01 => ISSUERS_REGISTER,
+            0x02 => vec![0x01; 96],
+            0x03 => "DMV",
+            0x04 => 256,
+        };
+        assert_eq!(
+            VendorBBSIssuersParameters::try_from(cbor_value),
+            Err(DecodeError::InvalidParameter)
+        );
+        let cbor_value = cbor_map! {
+            0x01 => 0x03,
+        };
+        assert_eq!(
+            VendorBBSIssuersParameters::try_from(cbor_value),
+            Err(DecodeError::InvalidSubcommand)
+        );
+    }
+
+    #[test]
+    fn test_vendor_bbs_usage_response() {
+        let response = VendorBBSUsageResponse {
+            boot_count: 7,
+            credentials: vec![CredentialUsage {
+                credential_id: [0x55; CREDENTIAL_ID_SIZE],
+                presentations: 3,
+                last_boot: 5,
+            }],
+        };
+        let response_cbor = cbor::Value::from(response.clone());
+        let expected_cbor = cbor_map! {
+            0x01 => 7,
+            0x02 => cbor_array![cbor_map! {
+                0x01 => vec![0x55; CREDENTIAL_ID_SIZE],
+                0x02 => 3,
+                0x03 => 5,
+            }],
+        };
+        assert_eq!(response_cbor, expected_cbor);
+        assert_eq!(
+            VendorBBSUsageResponse::try_from(response_cbor),
+            Ok(response)
+        );
+
+        let cbor_value = cbor_map! {
+            0x01 => 7,
+            0x02 => cbor_array![cbor_map! {
+                0x01 => vec![0x55; 8],
+                0x02 => 3,
+                0x03 => 5,
+            }],
+        };
+        assert_eq!(
+            VendorBBSUsageResponse::try_from(cbor_value),
+            Err(DecodeError::InvalidParameter)
+        );
+    }
+
+    #[test]
+    fn test_vendor_bbs_epoch_response() {
+        let response = VendorBBSEpochResponse { epoch: 2 };
+        let response_cbor = cbor::Value::from(response.clone());
+        assert_eq!(response_cbor, cbor_map! { 0x01 => 2 });
+        assert_eq!(
+            VendorBBSEpochResponse::try_from(response_cbor),
+            Ok(response)
+        );
+        assert_eq!(
+            VendorBBSEpochResponse::try_from(cbor_array![]),
+            Err(DecodeError::UnexpectedType)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers to decode parameters and responses, like `data_formats` of the firmware does.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use sk_cbor as cbor;
+
+/// A value doesn't have the format of its command.
+///
+/// The firmware answers with the CTAP2 status code of the same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    UnexpectedType,
+    MissingParameter,
+    InvalidParameter,
+    InvalidSubcommand,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedType => write!(f, "unexpected CBOR type"),
+            DecodeError::MissingParameter => write!(f, "missing parameter"),
+            DecodeError::InvalidParameter => write!(f, "invalid parameter"),
+            DecodeError::InvalidSubcommand => write!(f, "invalid subcommand"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+pub fn ok_or_missing<T>(value_option: Option<T>) -> Result<T, DecodeError> {
+    value_option.ok_or(DecodeError::MissingParameter)
+}
+
+pub fn extract_map(
+    cbor_value: cbor::Value,
+) -> Result<Vec<(cbor::Value, cbor::Value)>, DecodeError> {
+    cbor_value.extract_map().ok_or(DecodeError::UnexpectedType)
+}
+
+pub fn extract_array(cbor_value: cbor::Value) -> Result<Vec<cbor::Value>, DecodeError> {
+    cbor_value
+        .extract_array()
+        .ok_or(DecodeError::UnexpectedType)
+}
+
+pub fn extract_bool(cbor_value: cbor::Value) -> Result<bool, DecodeError> {
+    cbor_value.extract_bool().ok_or(DecodeError::UnexpectedType)
+}
+
+pub fn extract_unsigned(cbor_value: cbor::Value) -> Result<u64, DecodeError> {
+    cbor_value
+        .extract_unsigned()
+        .ok_or(DecodeError::UnexpectedType)
+}
+
+pub fn extract_byte_string(cbor_value: cbor::Value) -> Result<Vec<u8>, DecodeError> {
+    cbor_value
+        .extract_byte_string()
+        .ok_or(DecodeError::UnexpectedType)
+}
+
+pub fn extract_text_string(cbor_value: cbor::Value) -> Result<String, DecodeError> {
+    cbor_value
+        .extract_text_string()
+        .ok_or(DecodeError::UnexpectedType)
+}
@@ -0,0 +1,66 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Command bytes and subcommands of the vendor commands.
+//!
+//! The firmware dispatches on these in `src/env/tock/commands.rs`. Commands of optional features
+//! are listed even when the firmware leaves them out, so that their bytes are never reused.
+//! The CTAP layer already uses 0x41 for credential management and 0x45 for identify, and sees
+//! commands only after the vendor layer declined them, so these bytes are never assigned here.
+
+pub const VENDOR_COMMAND_CONFIGURE: u8 = 0x40;
+pub const VENDOR_COMMAND_UPGRADE: u8 = 0x42;
+pub const VENDOR_COMMAND_UPGRADE_INFO: u8 = 0x43;
+pub const VENDOR_COMMAND_AUDIT_LOG: u8 = 0x44;
+pub const VENDOR_COMMAND_HEAP_STATS: u8 = 0x46;
+pub const VENDOR_COMMAND_FIRMWARE_MEASUREMENT: u8 = 0x47;
+pub const VENDOR_COMMAND_SECURE_CHANNEL: u8 = 0x48;
+pub const VENDOR_COMMAND_LOG: u8 = 0x49;
+pub const VENDOR_COMMAND_CRASH_REPORT: u8 = 0x4A;
+pub const VENDOR_COMMAND_INFO: u8 = 0x4B;
+pub const VENDOR_COMMAND_COMPRESSED: u8 = 0x4C;
+pub const VENDOR_COMMAND_BOOT_CONTROL: u8 = 0x4D;
+pub const VENDOR_COMMAND_DELTA_UPGRADE: u8 = 0x4E;
+pub const VENDOR_COMMAND_VERIFY_UPGRADE: u8 = 0x4F;
+pub const VENDOR_COMMAND_BBS_COMMITMENT: u8 = 0x50;
+pub const VENDOR_COMMAND_BBS_PROOF: u8 = 0x51;
+pub const VENDOR_COMMAND_BBS_INFO: u8 = 0x52;
+pub const VENDOR_COMMAND_BBS_POSSESSION: u8 = 0x53;
+pub const VENDOR_COMMAND_BBS_MIGRATION: u8 = 0x54;
+pub const VENDOR_COMMAND_BBS_RECOVERY: u8 = 0x55;
+pub const VENDOR_COMMAND_BBS_AUTHORIZE: u8 = 0x56;
+pub const VENDOR_COMMAND_BBS_USAGE: u8 = 0x57;
+pub const VENDOR_COMMAND_CREDENTIAL_IMPORT: u8 = 0x58;
+pub const VENDOR_COMMAND_STORE_COMPACT: u8 = 0x59;
+pub const VENDOR_COMMAND_STORE_DIAGNOSTICS: u8 = 0x5A;
+pub const VENDOR_COMMAND_BBS_REVOKE_ALL: u8 = 0x5B;
+pub const VENDOR_COMMAND_BBS_ISSUERS: u8 = 0x5C;
+pub const VENDOR_COMMAND_STATS: u8 = 0x5D;
+
+/// LZSS in the bit format of heatshrink, as implemented by `sk-lzss`.
+pub const COMPRESSION_LZSS: u64 = 0x01;
+
+/// Type of non-revocation proofs that disclose a status list entry.
+pub const NON_REVOCATION_STATUS_LIST: u64 = 0x01;
+
+pub const MIGRATION_PREPARE: u64 = 0x01;
+pub const MIGRATION_EXPORT: u64 = 0x02;
+pub const MIGRATION_IMPORT: u64 = 0x03;
+
+pub const RECOVERY_SHARE: u64 = 0x01;
+pub const RECOVERY_ACCEPT: u64 = 0x02;
+pub const RECOVERY_FORGET: u64 = 0x03;
+
+pub const ISSUERS_REGISTER: u64 = 0x01;
+pub const ISSUERS_REMOVE: u64 = 0x02;
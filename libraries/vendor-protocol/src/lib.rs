@@ -0,0 +1,32 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wire format of the vendor commands of OpenSK.
+//!
+//! The firmware and host tools share these constants, requests and responses, so that they can't
+//! drift from each other. The crate is `no_std`. The `std` feature only implements the standard
+//! error trait for host tools.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod bbs;
+mod codec;
+pub mod command;
+pub mod status;
+
+pub use codec::DecodeError;
@@ -0,0 +1,53 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vendor status codes, in the range CTAP leaves to vendors.
+//!
+//! The firmware returns them as `Ctap2StatusCode`, which has the same values.
+
+pub const CTAP2_OK: u8 = 0x00;
+pub const VENDOR_BBS_UNKNOWN_ISSUER: u8 = 0xF1;
+pub const VENDOR_INTERNAL_ERROR: u8 = 0xF2;
+pub const VENDOR_HARDWARE_FAILURE: u8 = 0xF3;
+pub const VENDOR_LOCKDOWN_REFUSED: u8 = 0xF4;
+pub const VENDOR_BBS_NO_LINK_SECRET: u8 = 0xF5;
+pub const VENDOR_BBS_CREDENTIAL_NOT_FOUND: u8 = 0xF6;
+pub const VENDOR_BBS_POLICY_VIOLATION: u8 = 0xF7;
+pub const VENDOR_BBS_MESSAGE_LIMIT_EXCEEDED: u8 = 0xF8;
+pub const VENDOR_BBS_COMMITMENT_FAILED: u8 = 0xF9;
+pub const VENDOR_BBS_INVALID_DISCLOSED_INDEX: u8 = 0xFA;
+pub const VENDOR_BBS_INVALID_PROVER_BLIND: u8 = 0xFB;
+pub const VENDOR_BBS_PROOF_FAILED: u8 = 0xFC;
+pub const VENDOR_TIME_BUDGET_EXCEEDED: u8 = 0xFD;
+
+/// Describes the vendor status codes of BBS failures and time budgets, for users of host tools.
+pub fn description(code: u8) -> Option<&'static str> {
+    Some(match code {
+        VENDOR_BBS_UNKNOWN_ISSUER => {
+            "the device only accepts credentials of its registered issuers"
+        }
+        VENDOR_BBS_NO_LINK_SECRET => "no link secret is provisioned",
+        VENDOR_BBS_CREDENTIAL_NOT_FOUND => "the device has no blind for this issuer",
+        VENDOR_BBS_POLICY_VIOLATION => "the issuer policy forbids this disclosure",
+        VENDOR_BBS_MESSAGE_LIMIT_EXCEEDED => "the credential has too many messages for the device",
+        VENDOR_BBS_COMMITMENT_FAILED => "the commitment generation failed",
+        VENDOR_BBS_INVALID_DISCLOSED_INDEX => "a disclosed index is beyond the messages",
+        VENDOR_BBS_INVALID_PROVER_BLIND => "the secret prover blind is invalid",
+        VENDOR_BBS_PROOF_FAILED => "the proof generation failed, check the signature and messages",
+        VENDOR_TIME_BUDGET_EXCEEDED => {
+            "the proof would take longer than the device allows, disclose more messages"
+        }
+        _ => return None,
+    })
+}
@@ -17,10 +17,11 @@ set -ex
 
 ./fuzzing_setup.sh
 # Excludes std
-MOST_FEATURES=config_command,debug_allocations,debug_ctap,panic_console,verbose,with_ctap1,vendor_hid,ed25519
+MOST_FEATURES=bbs,compression,config_command,debug_allocations,debug_ctap,heap_stats,panic_console,verbose,with_ctap1,vendor_hid,ccid,ipc,ed25519,hardened,dual_store
 
 echo "Checking that OpenSK builds properly..."
 cargo check --release --target=thumbv7em-none-eabi
+cargo check --release --target=thumbv7em-none-eabi --features bbs
 cargo check --release --target=thumbv7em-none-eabi --features config_command
 cargo check --release --target=thumbv7em-none-eabi --features debug_allocations
 cargo check --release --target=thumbv7em-none-eabi --features debug_ctap
@@ -29,24 +30,37 @@ cargo check --release --target=thumbv7em-none-eabi --features verbose
 cargo check --release --target=thumbv7em-none-eabi --features with_ctap1
 cargo check --release --target=thumbv7em-none-eabi --features with_nfc
 cargo check --release --target=thumbv7em-none-eabi --features vendor_hid
+cargo check --release --target=thumbv7em-none-eabi --features ccid
+cargo check --release --target=thumbv7em-none-eabi --features ipc
 cargo check --release --target=thumbv7em-none-eabi --features ed25519
 cargo check --release --target=thumbv7em-none-eabi --features rust_crypto
+cargo check --release --target=thumbv7em-none-eabi --features hardened
 cargo check --release --target=thumbv7em-none-eabi --features "$MOST_FEATURES"
 cargo check --release --target=thumbv7em-none-eabi --examples
 cargo check --release --target=thumbv7em-none-eabi --examples --features with_nfc
+cargo check --release --target=thumbv7em-none-eabi --examples --features bbs
 cargo check --release --target=thumbv7em-none-eabi --manifest-path bootloader/Cargo.toml
+cargo check --release --target=thumbv7em-none-eabi --manifest-path libraries/opensk/Cargo.toml --features raw
+cargo check --release --target=thumbv7em-none-eabi --manifest-path libraries/opensk/Cargo.toml --features zephyr
 cargo check --release --manifest-path tools/heapviz/Cargo.toml
+cargo check --release --manifest-path tools/bbs_wallet/Cargo.toml
+cargo check --release --manifest-path tools/issuer/Cargo.toml
 
 echo "Checking Rust formatting..."
 cargo fmt -- --check
 cargo fmt --manifest-path libraries/opensk/Cargo.toml -- --check
+cargo fmt --manifest-path fuzz/Cargo.toml -- --check
 cargo fmt --manifest-path libraries/opensk/fuzz/Cargo.toml -- --check
 cargo fmt --manifest-path libraries/cbor/Cargo.toml -- --check
 cargo fmt --manifest-path libraries/cbor/fuzz/Cargo.toml -- --check
 cargo fmt --manifest-path libraries/persistent_store/Cargo.toml -- --check
 cargo fmt --manifest-path libraries/persistent_store/fuzz/Cargo.toml -- --check
 cargo fmt --manifest-path libraries/crypto/Cargo.toml -- --check
+cargo fmt --manifest-path libraries/lzss/Cargo.toml -- --check
+cargo fmt --manifest-path libraries/vendor-protocol/Cargo.toml -- --check
 cargo fmt --manifest-path tools/heapviz/Cargo.toml -- --check
+cargo fmt --manifest-path tools/bbs_wallet/Cargo.toml -- --check
+cargo fmt --manifest-path tools/issuer/Cargo.toml -- --check
 cargo fmt --manifest-path bootloader/Cargo.toml -- --check
 
 echo "Checking Python formatting..."
@@ -57,14 +71,20 @@ echo "Running Clippy lints..."
 cargo clippy --lib --tests --bins --benches --features std -- -D warnings
 cargo clippy --lib --tests --bins --benches --features std,"$MOST_FEATURES" -- -D warnings
 (cd libraries/opensk && cargo clippy --features std -- -D warnings)
-(cd libraries/opensk && cargo clippy --features std,config_command,debug_ctap,with_ctap1,vendor_hid,ed25519,rust_crypto  -- -D warnings)
+(cd libraries/opensk && cargo clippy --features std,hardened -- -D warnings)
+(cd libraries/opensk && cargo clippy --all-targets --features std,zephyr -- -D warnings)
+(cd libraries/opensk && cargo clippy --no-default-features --features std -- -D warnings)
+(cd libraries/opensk && cargo clippy --features std,config_command,debug_ctap,with_ctap1,vendor_hid,ccid,ed25519,rust_crypto  -- -D warnings)
 (cd libraries/cbor && cargo clippy -- -D warnings)
+(cd libraries/lzss && cargo clippy -- -D warnings)
+(cd libraries/vendor-protocol && cargo clippy --all-targets -- -D warnings)
 # Uncomment when persistent store is fixed:
 # (cd libraries/persistent_store && cargo clippy --features std -- -D warnings)
 # Probably not worth fixing:
 # (cd libraries/crypto && cargo clippy --features std -- -D warnings)
 
 echo "Checking that fuzz targets..."
+cargo fuzz check
 (cd libraries/opensk && cargo fuzz check)
 (cd libraries/cbor && cargo fuzz check)
 (cd libraries/persistent_store && cargo fuzz check)
@@ -80,6 +100,9 @@ echo "Running OpenSK library unit tests..."
 cd libraries/opensk
 cargo test --features std
 cargo test --features std,config_command,with_ctap1
+cargo test --no-default-features --features std
+cargo test --features std,hardened
+cargo test --features std,zephyr
 cargo test --all-features
 cd ../..
 
@@ -87,10 +110,16 @@ echo "Running other unit tests..."
 cargo test --lib --tests --bins --benches --features std
 cargo test --lib --tests --bins --benches --all-features
 cargo test --manifest-path libraries/cbor/Cargo.toml
+cargo test --manifest-path libraries/lzss/Cargo.toml
+cargo test --manifest-path libraries/vendor-protocol/Cargo.toml
 cargo test --manifest-path libraries/persistent_store/Cargo.toml --features std
 # Running release mode to speed up. This library is legacy anyway.
 cargo test --manifest-path libraries/crypto/Cargo.toml --features std --release
 cargo test --manifest-path tools/heapviz/Cargo.toml
+cargo test --manifest-path tools/bbs_wallet/Cargo.toml
+cargo test --manifest-path tools/bbs_wallet/Cargo.toml --features std
+cargo test --manifest-path tools/issuer/Cargo.toml
+cargo test --manifest-path third_party/bbs/Cargo.toml --features std
 
 echo "Checking that boards build properly..."
 make -C third_party/tock/boards/nordic/nrf52840dk_opensk
@@ -0,0 +1,242 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of BBS credentials from a device to its replacement.
+//!
+//! The replacement generates a transport key pair in RAM and signs the public key with its
+//! attestation key, so that the host can check it talks to a genuine device. The source wraps its
+//! issuer records to the transport key, see the `transport` module.
+//!
+//! Only issuer ids and their disclosure policies are exported. The link secret never leaves a
+//! device, and blinds are useless without it. The source then forgets its records and rotates its
+//! link secret, so its credentials stop proving. The replacement keeps the records until each
+//! issuer signs a new credential, whose commitment inherits the exported policy.
+//!
+//! Backup pairing wraps the recovery secret to a transport key the same way, see `bbs_recovery`.
+
+use super::secure_channel::PUBLIC_KEY_SIZE;
+use super::transport::{self, TransportBundle};
+use super::vendor_parameters::MAX_ISSUER_ID_SIZE;
+use alloc::vec::Vec;
+use opensk::ctap::data_formats::{
+    extract_array, extract_byte_string, extract_map, extract_unsigned, ok_or_missing,
+};
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::ctap::{cbor_read, cbor_write};
+use opensk::env::{EcdhSk, Env};
+use opensk::vendor::bbs_blinds::{DisclosurePolicy, STORAGE_KEYS};
+use sk_cbor as cbor;
+use sk_cbor::{cbor_array_vec, cbor_map, destructure_cbor_map};
+
+/// Prefixed to the transport public key signed by the replacement.
+pub const TRANSPORT_KEY_DOMAIN: &[u8] = b"OpenSK BBS migration transport key\0";
+
+/// Separates bundles of issuer records from other payloads wrapped to a transport key.
+const ENTRIES_PURPOSE: &[u8] = b"OpenSK BBS migration entries\0";
+
+/// Version of the plaintext, checked on import.
+const FORMAT_VERSION: u64 = 1;
+
+/// What the replacement learns about an issuer of the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationEntry {
+    pub issuer_id: Vec<u8>,
+    pub policy: DisclosurePolicy,
+}
+
+/// Returns what the attestation key of the replacement signs.
+pub fn transport_key_transcript(transport_public_key: &[u8; PUBLIC_KEY_SIZE]) -> Vec<u8> {
+    let mut transcript = TRANSPORT_KEY_DOMAIN.to_vec();
+    transcript.extend_from_slice(transport_public_key);
+    transcript
+}
+
+/// Wraps the entries to the transport key, on the source.
+pub fn seal<E: Env>(
+    env: &mut E,
+    transport_public_key: &[u8; PUBLIC_KEY_SIZE],
+    entries: &[MigrationEntry],
+) -> Result<TransportBundle, Ctap2StatusCode> {
+    let mut plaintext = Vec::new();
+    cbor_write(encode_entries(entries), &mut plaintext)?;
+    transport::wrap(env, transport_public_key, ENTRIES_PURPOSE, &plaintext)
+}
+
+/// Authenticates and unwraps the entries, on the replacement.
+pub fn open<E: Env>(
+    transport_key: &EcdhSk<E>,
+    bundle: &TransportBundle,
+) -> Result<Vec<MigrationEntry>, Ctap2StatusCode> {
+    let plaintext = transport::unwrap(transport_key, ENTRIES_PURPOSE, bundle)?;
+    decode_entries(cbor_read(&plaintext)?)
+}
+
+fn encode_entries(entries: &[MigrationEntry]) -> cbor::Value {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            cbor_map! {
+                0x01 => entry.issuer_id.clone(),
+                0x02 => entry.policy.never_disclosed,
+                0x03 => entry.policy.requires_uv,
+            }
+        })
+        .collect::<Vec<_>>();
+    cbor_map! {
+        0x01 => FORMAT_VERSION,
+        0x02 => cbor_array_vec!(entries),
+    }
+}
+
+fn decode_entries(cbor_value: cbor::Value) -> Result<Vec<MigrationEntry>, Ctap2StatusCode> {
+    destructure_cbor_map! {
+        let {
+            0x01 => version,
+            0x02 => entries,
+        } = extract_map(cbor_value)?;
+    }
+    if extract_unsigned(ok_or_missing(version)?)? != FORMAT_VERSION {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let entries = extract_array(ok_or_missing(entries)?)?;
+    if entries.len() > STORAGE_KEYS.len() {
+        return Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL);
+    }
+    entries
+        .into_iter()
+        .map(|entry| {
+            destructure_cbor_map! {
+                let {
+                    0x01 => issuer_id,
+                    0x02 => never_disclosed,
+                    0x03 => requires_uv,
+                } = extract_map(entry)?;
+            }
+            let issuer_id = extract_byte_string(ok_or_missing(issuer_id)?)?;
+            if issuer_id.is_empty() || issuer_id.len() > MAX_ISSUER_ID_SIZE {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            Ok(MigrationEntry {
+                issuer_id,
+                policy: DisclosurePolicy {
+                    never_disclosed: extract_unsigned(ok_or_missing(never_disclosed)?)?,
+                    requires_uv: extract_unsigned(ok_or_missing(requires_uv)?)?,
+                },
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use opensk::api::crypto::ecdh::SecretKey;
+    use opensk::env::test::TestEnv;
+
+    fn entries() -> Vec<MigrationEntry> {
+        vec![
+            MigrationEntry {
+                issuer_id: b"issuer".to_vec(),
+                policy: DisclosurePolicy {
+                    never_disclosed: 0b001,
+                    requires_uv: 0b100,
+                },
+            },
+            MigrationEntry {
+                issuer_id: b"other".to_vec(),
+                policy: DisclosurePolicy::default(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let mut env = TestEnv::default();
+        let (transport_key, transport_public_key) = transport::generate_transport_key(&mut env);
+        let bundle = seal(&mut env, &transport_public_key, &entries()).unwrap();
+        assert_eq!(open::<TestEnv>(&transport_key, &bundle), Ok(entries()));
+        // The issuer ids don't show in the bundle.
+        assert!(!bundle
+            .ciphertext
+            .windows(b"issuer".len())
+            .any(|window| window == b"issuer"));
+
+        let bundle = seal(&mut env, &transport_public_key, &[]).unwrap();
+        assert_eq!(open::<TestEnv>(&transport_key, &bundle), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_open_other_key() {
+        let mut env = TestEnv::default();
+        let (transport_key, transport_public_key) = transport::generate_transport_key(&mut env);
+        let bundle = seal(&mut env, &transport_public_key, &entries()).unwrap();
+        let other_key = EcdhSk::<TestEnv>::random(env.rng());
+        assert_eq!(
+            open::<TestEnv>(&other_key, &bundle),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
+    #[test]
+    fn test_open_tampered() {
+        let mut env = TestEnv::default();
+        let (transport_key, transport_public_key) = transport::generate_transport_key(&mut env);
+        let bundle = seal(&mut env, &transport_public_key, &entries()).unwrap();
+
+        let mut tampered = bundle.clone();
+        tampered.ciphertext[20] ^= 0x01;
+        assert_eq!(
+            open::<TestEnv>(&transport_key, &tampered),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        let mut tampered = bundle;
+        tampered.source_public_key[0] = 0x02;
+        assert_eq!(
+            open::<TestEnv>(&transport_key, &tampered),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_decode_entries() {
+        assert_eq!(decode_entries(encode_entries(&entries())), Ok(entries()));
+        let unknown_version = cbor_map! {
+            0x01 => FORMAT_VERSION + 1,
+            0x02 => cbor_array_vec!(Vec::<cbor::Value>::new()),
+        };
+        assert_eq!(
+            decode_entries(unknown_version),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let empty_issuer_id = encode_entries(&[MigrationEntry {
+            issuer_id: Vec::new(),
+            policy: DisclosurePolicy::default(),
+        }]);
+        assert_eq!(
+            decode_entries(empty_issuer_id),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        let too_many = (0..=STORAGE_KEYS.len())
+            .map(|i| MigrationEntry {
+                issuer_id: vec![i as u8 + 1],
+                policy: DisclosurePolicy::default(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decode_entries(encode_entries(&too_many)),
+            Err(Ctap2StatusCode::CTAP2_ERR_KEY_STORE_FULL)
+        );
+    }
+}
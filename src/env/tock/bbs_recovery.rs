@@ -0,0 +1,74 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pairing of a backup device with the recovery secret.
+//!
+//! At pairing, the backup generates a transport key like a migration replacement, and the primary
+//! wraps its recovery secret to it. Both devices then sign commitments with the same keys, see
+//! `opensk::vendor::bbs_recovery`.
+
+use super::secure_channel::PUBLIC_KEY_SIZE;
+use super::transport::{self, TransportBundle};
+use core::convert::TryFrom;
+use opensk::ctap::secret::Secret;
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::env::{EcdhSk, Env};
+pub use opensk::vendor::bbs_recovery::{get_or_generate_secret, set_secret, SECRET_SIZE};
+
+/// Separates wrapped recovery secrets from other payloads wrapped to a transport key.
+const SECRET_PURPOSE: &[u8] = b"OpenSK BBS recovery secret\0";
+
+/// Wraps the recovery secret to the transport key of the backup, on the primary.
+pub fn seal<E: Env>(
+    env: &mut E,
+    transport_public_key: &[u8; PUBLIC_KEY_SIZE],
+    secret: &[u8; SECRET_SIZE],
+) -> Result<TransportBundle, Ctap2StatusCode> {
+    transport::wrap(env, transport_public_key, SECRET_PURPOSE, secret)
+}
+
+/// Authenticates and unwraps the recovery secret, on the backup.
+pub fn open<E: Env>(
+    transport_key: &EcdhSk<E>,
+    bundle: &TransportBundle,
+) -> Result<Secret<[u8; SECRET_SIZE]>, Ctap2StatusCode> {
+    let plaintext = transport::unwrap(transport_key, SECRET_PURPOSE, bundle)?;
+    let secret = <[u8; SECRET_SIZE]>::try_from(&plaintext[..])
+        .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    Ok(Secret::from_exposed_secret(secret))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::bbs_migration;
+    use super::*;
+    use opensk::env::test::TestEnv;
+
+    #[test]
+    fn test_seal_open() {
+        let mut env = TestEnv::default();
+        let (transport_key, transport_public_key) = transport::generate_transport_key(&mut env);
+        let bundle = seal(&mut env, &transport_public_key, &[0x55; SECRET_SIZE]).unwrap();
+        assert_eq!(
+            *open::<TestEnv>(&transport_key, &bundle).unwrap(),
+            [0x55; SECRET_SIZE]
+        );
+        // Bundles of issuer records don't pass as recovery secrets.
+        let bundle = bbs_migration::seal(&mut env, &transport_public_key, &[]).unwrap();
+        assert_eq!(
+            open::<TestEnv>(&transport_key, &bundle).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+}
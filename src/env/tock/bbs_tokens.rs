@@ -0,0 +1,99 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Presentation tokens, so that kiosks present BBS credentials without a touch for each proof.
+//!
+//! After one user verification, the BBS authorize vendor command grants a token for some proofs
+//! and some time, both bounded by the customization. The token only lives in RAM, so unplugging
+//! the device ends it too. Proofs whose disclosure policy requires user verification still ask
+//! for it, the issuer wanted the user to confirm each of them.
+
+use super::TockEnv;
+use libtock_platform as platform;
+use libtock_platform::Syscalls;
+use opensk::api::clock::Clock;
+use opensk::env::Env;
+
+/// Authorization of the next BBS proofs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentationToken {
+    remaining: usize,
+    expires_ms: u64,
+}
+
+impl PresentationToken {
+    pub fn new(proofs: usize, now_ms: u64, duration_ms: u64) -> Self {
+        PresentationToken {
+            remaining: proofs,
+            expires_ms: now_ms.saturating_add(duration_ms),
+        }
+    }
+
+    /// Returns whether the token authorizes no more proofs at the given uptime.
+    pub fn is_exhausted(&self, now_ms: u64) -> bool {
+        self.remaining == 0 || now_ms >= self.expires_ms
+    }
+
+    /// Uses the token for one proof, and returns whether it authorized it.
+    pub fn consume(&mut self, now_ms: u64) -> bool {
+        if self.is_exhausted(now_ms) {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+/// Uses the token of the environment for one proof, and returns whether it authorized it.
+///
+/// Exhausted tokens are dropped.
+pub fn consume<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config>(
+    env: &mut TockEnv<S, C>,
+) -> bool {
+    let now_ms = env.clock().uptime_ms();
+    let token = match env.presentation_token.as_mut() {
+        Some(token) => token,
+        None => return false,
+    };
+    let authorized = token.consume(now_ms);
+    if token.is_exhausted(now_ms) {
+        env.presentation_token = None;
+    }
+    authorized
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_consume() {
+        let mut token = PresentationToken::new(2, 1000, 500);
+        assert!(token.consume(1000));
+        assert!(token.consume(1200));
+        assert!(token.is_exhausted(1200));
+        assert!(!token.consume(1200));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let mut token = PresentationToken::new(5, 1000, 500);
+        assert!(!token.is_exhausted(1499));
+        assert!(token.is_exhausted(1500));
+        assert!(!token.consume(1500));
+
+        let token = PresentationToken::new(5, u64::MAX - 1, 500);
+        assert!(!token.is_exhausted(u64::MAX - 1));
+    }
+}
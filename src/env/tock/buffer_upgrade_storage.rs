@@ -13,10 +13,19 @@
 // limitations under the License.
 
 use super::storage_helper::ModRange;
+use super::upgrade_helper::{
+    parse_metadata_hash, parse_metadata_version, parse_partition_status, reset_boot_status,
+    BundleVerification, PartitionStatus, CONFIRMED_OFFSET, METADATA_SIGN_OFFSET, ROLLBACK_OFFSET,
+};
+use super::TockEnv;
 use alloc::boxed::Box;
 use core::marker::PhantomData;
 use libtock_platform as platform;
 use libtock_platform::Syscalls;
+use opensk::api::crypto::sha256::Sha256;
+use opensk::api::crypto::HASH_SIZE;
+use opensk::api::upgrade_storage::UpgradeStorage;
+use opensk::env::Sha;
 use persistent_store::{StorageError, StorageResult};
 use platform::DefaultConfig;
 
@@ -29,6 +38,10 @@ pub struct BufferUpgradeStorage<
 > {
     /// Content of the partition storage.
     partition: Box<[u8]>,
+    /// Chunk whose write is deferred, like on the device.
+    pending: Option<(usize, Vec<u8>)>,
+    /// Metadata and firmware of the running partition, with version 0.
+    running_partition: Box<[u8]>,
     s: PhantomData<S>,
     c: PhantomData<C>,
 }
@@ -39,15 +52,25 @@ where
     C: platform::subscribe::Config + platform::allow_ro::Config,
 {
     pub fn new() -> StorageResult<Self> {
+        let mut running_partition = vec![0xff; PARTITION_LENGTH];
+        running_partition[METADATA_SIGN_OFFSET..][..8].copy_from_slice(&[0; 8]);
         Ok(BufferUpgradeStorage {
             partition: vec![0xff; PARTITION_LENGTH].into_boxed_slice(),
+            pending: None,
+            running_partition: running_partition.into_boxed_slice(),
             s: PhantomData,
             c: PhantomData,
         })
     }
 
+    /// Replaces the running partition, to test patching it.
     #[cfg(test)]
-    fn read_partition(&self, offset: usize, length: usize) -> StorageResult<&[u8]> {
+    pub fn set_running_partition(&mut self, data: &[u8]) {
+        self.running_partition[..data.len()].copy_from_slice(data);
+    }
+
+    #[cfg(test)]
+    pub fn read_partition(&self, offset: usize, length: usize) -> StorageResult<&[u8]> {
         if length == 0 {
             return Err(StorageError::OutOfBounds);
         }
@@ -59,44 +82,186 @@ where
         }
     }
 
-    pub fn write_bundle(&mut self, offset: usize, data: Vec<u8>) -> StorageResult<()> {
+    /// Writes the pending chunk, if any.
+    pub fn flush(&mut self) {
+        if let Some((offset, data)) = self.pending.take() {
+            self.partition[offset..][..data.len()].copy_from_slice(&data);
+        }
+    }
+
+    pub fn running_firmware_version(&self) -> u64 {
+        parse_metadata_version(&self.running_partition[..METADATA_LENGTH])
+    }
+
+    /// Reads the running partition, like the upgrade partition with metadata first.
+    pub fn read_running(&self, offset: usize, length: usize) -> StorageResult<&[u8]> {
+        self.running_partition
+            .get(offset..)
+            .and_then(|slice| slice.get(..length))
+            .ok_or(StorageError::OutOfBounds)
+    }
+
+    /// There is no running firmware in the buffer, so this measures an empty one.
+    pub fn running_firmware_hash(
+        &self,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<[u8; 32]> {
+        if !keep_going() {
+            return Err(StorageError::CustomError);
+        }
+        Ok(Sha::<TockEnv<S, C>>::digest(&[]))
+    }
+
+    /// There is no running firmware in the buffer, so there is nothing to check.
+    pub fn check_running_firmware(
+        &self,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<()> {
+        if !keep_going() {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+}
+
+impl<S, C> UpgradeStorage for BufferUpgradeStorage<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn bundle_identifier(&self) -> u32 {
+        0x60000
+    }
+
+    fn running_status(&self) -> PartitionStatus {
+        parse_partition_status(&self.running_partition[..METADATA_LENGTH])
+    }
+
+    fn bundle_status(&self) -> PartitionStatus {
+        parse_partition_status(&self.partition[..METADATA_LENGTH])
+    }
+
+    /// Writes a bundle chunk, unless `keep_going` returns false.
+    ///
+    /// The write is deferred to the next call to `flush` or `write_bundle`, except for the last
+    /// chunk. Bundles older than `min_version` are refused. Returns the version of the bundle once
+    /// its last chunk is committed.
+    fn write_bundle(
+        &mut self,
+        offset: usize,
+        mut data: Vec<u8>,
+        min_version: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<Option<u64>> {
+        self.flush();
+        if !keep_going() {
+            return Err(StorageError::CustomError);
+        }
         if offset == 0 && data.len() != METADATA_LENGTH {
             return Err(StorageError::OutOfBounds);
         }
+        if offset == 0 && parse_metadata_version(&data) < min_version {
+            return Err(StorageError::CustomError);
+        }
+        if offset == 0 {
+            reset_boot_status(&mut data);
+        }
         if data.is_empty() {
             return Err(StorageError::OutOfBounds);
         }
         let partition_range = ModRange::new(0, self.partition.len());
-        if partition_range.contains_range(&ModRange::new(offset, data.len())) {
-            self.partition[offset..][..data.len()].copy_from_slice(&data);
-            Ok(())
-        } else {
-            Err(StorageError::OutOfBounds)
+        if !partition_range.contains_range(&ModRange::new(offset, data.len())) {
+            return Err(StorageError::OutOfBounds);
         }
+        if offset + data.len() != self.partition.len() {
+            self.pending = Some((offset, data));
+            return Ok(None);
+        }
+        // The last chunk is written right away, unless the minimum was raised since the metadata.
+        let version = parse_metadata_version(&self.partition[..METADATA_LENGTH]);
+        if version < min_version {
+            return Err(StorageError::CustomError);
+        }
+        self.pending = Some((offset, data));
+        self.flush();
+        Ok(Some(version))
     }
 
-    pub fn bundle_identifier(&self) -> u32 {
-        0x60000
+    fn bundle_hash(&mut self) -> StorageResult<[u8; HASH_SIZE]> {
+        self.flush();
+        if self.bundle_status().version.is_none() {
+            return Err(StorageError::CustomError);
+        }
+        Ok(*parse_metadata_hash(&self.partition[..METADATA_LENGTH]))
     }
 
-    pub fn running_firmware_version(&self) -> u64 {
-        0
+    /// The buffer has no signature to check, so this only checks the version and hash.
+    fn verify_bundle(
+        &mut self,
+        min_version: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<BundleVerification> {
+        self.flush();
+        let version = match self.bundle_status().version {
+            Some(version) => version,
+            None => return Ok(BundleVerification::Missing),
+        };
+        if version < self.running_firmware_version() || version < min_version {
+            return Ok(BundleVerification::InvalidMetadata);
+        }
+        if !keep_going() {
+            return Err(StorageError::CustomError);
+        }
+        let computed_hash = Sha::<TockEnv<S, C>>::digest(&self.partition[METADATA_SIGN_OFFSET..]);
+        if &computed_hash != parse_metadata_hash(&self.partition) {
+            return Ok(BundleVerification::HashMismatch);
+        }
+        Ok(BundleVerification::Valid)
+    }
+
+    fn mark_running_good(&mut self) -> StorageResult<()> {
+        self.running_partition[CONFIRMED_OFFSET..][..4].copy_from_slice(&[0; 4]);
+        Ok(())
+    }
+
+    /// There is no bootloader for the buffer, so this only checks the status of the other image.
+    fn request_rollback(
+        &mut self,
+        min_version: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<()> {
+        self.flush();
+        let status = self.bundle_status();
+        match status.version {
+            Some(version)
+                if version >= min_version && !status.failed() && !status.rollback_requested => {}
+            _ => return Err(StorageError::CustomError),
+        }
+        if !keep_going() {
+            return Err(StorageError::CustomError);
+        }
+        self.running_partition[ROLLBACK_OFFSET..][..4].copy_from_slice(&[0; 4]);
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
     use libtock_unittest::fake::Syscalls;
 
     #[test]
     fn read_write_bundle() {
         let mut storage = BufferUpgradeStorage::<Syscalls>::new().unwrap();
         assert_eq!(storage.read_partition(0, 2).unwrap(), &[0xFF, 0xFF]);
-        assert!(storage.write_bundle(1, vec![0x88, 0x88]).is_ok());
+        assert!(storage
+            .write_bundle(1, vec![0x88, 0x88], 0, || true)
+            .is_ok());
+        storage.flush();
         assert_eq!(storage.read_partition(0, 2).unwrap(), &[0xFF, 0x88]);
         assert_eq!(
-            storage.write_bundle(PARTITION_LENGTH - 1, vec![0x88, 0x88],),
+            storage.write_bundle(PARTITION_LENGTH - 1, vec![0x88, 0x88], 0, || true),
             Err(StorageError::OutOfBounds)
         );
         assert_eq!(
@@ -108,11 +273,11 @@ mod tests {
             Err(StorageError::OutOfBounds)
         );
         assert_eq!(
-            storage.write_bundle(4, vec![]),
+            storage.write_bundle(4, vec![], 0, || true),
             Err(StorageError::OutOfBounds)
         );
         assert_eq!(
-            storage.write_bundle(PARTITION_LENGTH + 4, vec![]),
+            storage.write_bundle(PARTITION_LENGTH + 4, vec![], 0, || true),
             Err(StorageError::OutOfBounds)
         );
         assert_eq!(storage.read_partition(4, 0), Err(StorageError::OutOfBounds));
@@ -122,6 +287,104 @@ mod tests {
         );
     }
 
+    #[test]
+    fn deferred_write() {
+        let mut storage = BufferUpgradeStorage::<Syscalls>::new().unwrap();
+        assert!(storage.write_bundle(1, vec![0x88], 0, || true).is_ok());
+        assert_eq!(storage.read_partition(1, 1).unwrap(), &[0xFF]);
+        // The next chunk writes the previous one first.
+        assert!(storage.write_bundle(2, vec![0x99], 0, || true).is_ok());
+        assert_eq!(storage.read_partition(1, 2).unwrap(), &[0x88, 0xFF]);
+        storage.flush();
+        assert_eq!(storage.read_partition(1, 2).unwrap(), &[0x88, 0x99]);
+    }
+
+    #[test]
+    fn min_version() {
+        let mut storage = BufferUpgradeStorage::<Syscalls>::new().unwrap();
+        let mut metadata = vec![0xFF; METADATA_LENGTH];
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 5);
+        assert_eq!(
+            storage.write_bundle(0, metadata.clone(), 6, || true),
+            Err(StorageError::CustomError)
+        );
+        assert_eq!(storage.write_bundle(0, metadata, 5, || true), Ok(None));
+        let last_offset = PARTITION_LENGTH - METADATA_LENGTH;
+        let chunk = vec![0x88; METADATA_LENGTH];
+        // The minimum was raised after the metadata was written.
+        assert_eq!(
+            storage.write_bundle(last_offset, chunk.clone(), 6, || true),
+            Err(StorageError::CustomError)
+        );
+        assert_eq!(storage.read_partition(last_offset, 1).unwrap(), &[0xFF]);
+        assert_eq!(
+            storage.write_bundle(last_offset, chunk, 5, || true),
+            Ok(Some(5))
+        );
+    }
+
+    #[test]
+    fn boot_status() {
+        let mut storage = BufferUpgradeStorage::<Syscalls>::new().unwrap();
+        assert_eq!(storage.running_status().version, Some(0));
+        assert!(!storage.running_status().confirmed);
+        assert_eq!(storage.mark_running_good(), Ok(()));
+        assert!(storage.running_status().confirmed);
+
+        // Rolling back needs an image in the other partition.
+        assert_eq!(
+            storage.request_rollback(0, || true),
+            Err(StorageError::CustomError)
+        );
+        let mut metadata = vec![0xFF; METADATA_LENGTH];
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 5);
+        storage.write_bundle(0, metadata, 0, || true).unwrap();
+        assert_eq!(storage.bundle_status().version, Some(5));
+        assert_eq!(
+            storage.request_rollback(6, || true),
+            Err(StorageError::CustomError)
+        );
+        assert_eq!(storage.request_rollback(5, || true), Ok(()));
+        assert!(storage.running_status().rollback_requested);
+    }
+
+    #[test]
+    fn verify_bundle() {
+        let mut storage = BufferUpgradeStorage::<Syscalls>::new().unwrap();
+        assert_eq!(
+            storage.verify_bundle(0, || true),
+            Ok(BundleVerification::Missing)
+        );
+
+        let mut image = vec![0xFF; PARTITION_LENGTH];
+        LittleEndian::write_u64(&mut image[METADATA_SIGN_OFFSET..][..8], 5);
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&image[METADATA_SIGN_OFFSET..]);
+        image[..32].copy_from_slice(&hash);
+        storage
+            .write_bundle(0, image[..METADATA_LENGTH].to_vec(), 0, || true)
+            .unwrap();
+        assert_eq!(
+            storage.verify_bundle(0, || true),
+            Ok(BundleVerification::Valid)
+        );
+        assert_eq!(
+            storage.verify_bundle(6, || true),
+            Ok(BundleVerification::InvalidMetadata)
+        );
+        assert_eq!(
+            storage.verify_bundle(0, || false),
+            Err(StorageError::CustomError)
+        );
+
+        storage
+            .write_bundle(METADATA_LENGTH, vec![0x00; 0x100], 0, || true)
+            .unwrap();
+        assert_eq!(
+            storage.verify_bundle(0, || true),
+            Ok(BundleVerification::HashMismatch)
+        );
+    }
+
     #[test]
     fn partition_slice() {
         let storage = BufferUpgradeStorage::<Syscalls>::new().unwrap();
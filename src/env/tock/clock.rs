@@ -34,6 +34,11 @@ impl Timestamp {
         self.epoch += sum >> 24;
         self.tick = sum & 0xff_ffff;
     }
+
+    /// Returns the number of ticks since the clock started.
+    pub fn total_ticks(&self) -> u64 {
+        0x100_0000u64 * self.epoch as u64 + self.tick as u64
+    }
 }
 
 #[derive(Default)]
@@ -95,10 +100,16 @@ impl<S: Syscalls> Clock for TockClock<S> {
         self.now >= timer.deadline
     }
 
+    fn uptime_ms(&mut self) -> u64 {
+        self.tickle();
+        let clock_frequency = Alarm::<S>::get_frequency().ok().unwrap().0;
+        self.now.total_ticks().wrapping_mul(1000) / clock_frequency as u64
+    }
+
     #[cfg(feature = "debug_ctap")]
     fn timestamp_us(&mut self) -> usize {
         let clock_frequency = Alarm::<S>::get_frequency().ok().unwrap().0;
-        let total_ticks = 0x100_0000u64 * self.now.epoch as u64 + self.now.tick as u64;
+        let total_ticks = self.now.total_ticks();
         (total_ticks.wrapping_mul(1_000_000u64) / clock_frequency as u64) as usize
     }
 }
@@ -128,5 +139,6 @@ mod test {
         timestamp.add_ticks(1);
         let expected = Timestamp { epoch: 4, tick: 0 };
         assert_eq!(timestamp, expected);
+        assert_eq!(timestamp.total_ticks(), 0x400_0000);
     }
 }
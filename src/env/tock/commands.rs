@@ -12,38 +12,100 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::TockEnv;
+#[cfg(feature = "bbs")]
+use super::bbs_migration::{self, MigrationEntry};
+#[cfg(feature = "bbs")]
+use super::bbs_recovery;
+#[cfg(feature = "bbs")]
+use super::bbs_tokens::{self, PresentationToken};
+use super::delta::DeltaPatch;
+use super::metadata::certificate_aaguid;
+use super::permissions::{self, Permissions};
+use super::secure_channel::{SecureChannel, PUBLIC_KEY_SIZE};
+#[cfg(all(feature = "bbs", not(feature = "std")))]
+use super::vendor_parameters::VendorBBSProofParameters;
+#[cfg(feature = "heap_stats")]
+use super::vendor_parameters::VendorHeapStatsResponse;
+use super::vendor_parameters::{
+    VendorAuditLogResponse, VendorConfigureParameters, VendorConfigureResponse,
+    VendorCrashReportParameters, VendorCrashReportResponse, VendorCredentialImportParameters,
+    VendorCredentialImportPrepareResponse, VendorCredentialImportResponse,
+    VendorFirmwareMeasurementResponse, VendorInfoResponse, VendorLogParameters, VendorLogResponse,
+    VendorSecureChannelParameters, VendorSecureChannelSetupResponse, VendorStatsResponse,
+    VendorStoreCompactParameters, VendorStoreCompactResponse, VendorStoreDiagnosticsResponse,
+    VendorUpgradeParameters, COMPRESSION_LZSS,
+};
+#[cfg(feature = "bbs")]
+use super::vendor_parameters::{
+    VendorBBSAuthorizeParameters, VendorBBSAuthorizeResponse, VendorBBSMigrationExportResponse,
+    VendorBBSMigrationImportResponse, VendorBBSMigrationParameters,
+    VendorBBSMigrationPrepareResponse, VendorBBSRecoveryParameters, VendorBBSRecoveryShareResponse,
+};
+#[cfg(feature = "compression")]
+use super::vendor_parameters::{VendorCompressedParameters, VendorCompressedResponse};
+use super::{crash_report, transport, TockEnv};
 use alloc::vec;
 use alloc::vec::Vec;
-use arrayref::array_ref;
-use bbs::{
-    generate_link_secret_commitment, generate_proof, BBSCommitmentBlindFactor, BBSPublicKey,
-    BBSSignature, LinkSecret,
-};
+#[cfg(feature = "bbs")]
+use bbs::LinkSecret;
 use core::convert::TryFrom;
+#[cfg(feature = "heap_stats")]
+use lang_items::heap_stats::{self, HeapStats};
 use libtock_platform::Syscalls;
 use opensk::api::attestation_store::{self, Attestation, AttestationStore};
+#[cfg(feature = "bbs")]
+use opensk::api::clock::Clock;
 use opensk::api::crypto::sha256::Sha256;
-use opensk::api::crypto::EC_FIELD_SIZE;
-#[cfg(not(feature = "with_ctap1"))]
 use opensk::api::customization::Customization;
+use opensk::api::delay::{Delay, FAILURE_DELAY_MS};
+#[cfg(feature = "std")]
+use opensk::api::display::Transaction;
+use opensk::api::upgrade_storage::UpgradeStorage;
+use opensk::api::user_feedback::{self, FeedbackState};
+#[cfg(not(feature = "std"))]
+use opensk::api::user_presence::CommandClass;
+use opensk::api::user_verification::UserVerification;
+use opensk::ctap::audit_log::{self, Timestamp};
 #[cfg(not(feature = "std"))]
 use opensk::ctap::check_user_presence;
-use opensk::ctap::data_formats::{
-    extract_array, extract_bool, extract_byte_string, extract_map, extract_unsigned, ok_or_missing,
-};
-use opensk::ctap::secret::Secret;
+use opensk::ctap::log::Level;
 use opensk::ctap::status_code::Ctap2StatusCode;
-use opensk::ctap::{cbor_read, cbor_write, Channel};
+#[cfg(feature = "bbs")]
+use opensk::ctap::PinPermission;
+use opensk::ctap::{
+    cbor_read, cbor_write, check_user_verification, compact_store, import_credentials,
+    is_store_corrupted, metadata, pin_retries, store_capacity, uv_retries, CancellationToken,
+    Channel, PinUvAuthCheck,
+};
 use opensk::env::{Env, Sha};
-use sk_cbor::{cbor_map_options, destructure_cbor_map};
+use opensk::log_ctap;
+use opensk::vendor::commands::sign_with_attestation;
+use opensk::vendor::handler::{self, encode_cbor, VendorCommandHandler};
+use opensk::vendor::lockdown::{self, LockdownLevel};
+#[cfg(feature = "bbs")]
+use opensk::vendor::{
+    bbs_blinds::{self, BlindRecord},
+    bbs_sessions,
+};
+use opensk::vendor::{bundle_key, rollback};
+use opensk_vendor_protocol::command::*;
+#[cfg(feature = "bbs")]
+use sk_cbor::cbor_map_options;
 use {libtock_platform as platform, sk_cbor as cbor};
 
-const VENDOR_COMMAND_CONFIGURE: u8 = 0x40;
-const VENDOR_COMMAND_UPGRADE: u8 = 0x42;
-const VENDOR_COMMAND_UPGRADE_INFO: u8 = 0x43;
-const VENDOR_COMMAND_BBS_COMMITMENT: u8 = 0x50;
-const VENDOR_COMMAND_BBS_PROOF: u8 = 0x51;
+/// Prefixed to signed firmware measurements, so they can't be mistaken for other signed data.
+const MEASUREMENT_DOMAIN: &[u8] = b"OpenSK firmware measurement\0";
+/// Prefixed to the signed transport key for passkey imports.
+const CREDENTIAL_IMPORT_KEY_DOMAIN: &[u8] = b"OpenSK credential import transport key\0";
+/// Purpose of bundles that carry passkeys, see the `transport` module.
+const CREDENTIAL_IMPORT_PURPOSE: &[u8] = b"OpenSK credential import\0";
+
+/// Vendor key that provisioning sessions are opened with.
+const PROVISIONING_PUBLIC_KEY: &[u8; PUBLIC_KEY_SIZE] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/opensk_provisioning_pubkey.bin"));
+
+/// Pages that a store compaction command compacts at most, so that hosts can show progress.
+const MAX_COMPACTION_PAGES: usize = 16;
 
 pub fn process_vendor_command<
     S: Syscalls,
@@ -52,61 +114,281 @@ pub fn process_vendor_command<
     env: &mut TockEnv<S, C>,
     bytes: &[u8],
     channel: Channel,
+    pin_uv_auth: Option<&dyn PinUvAuthCheck>,
 ) -> Option<Vec<u8>> {
+    // All CBOR commands pass here first, so crash reports know what was being processed.
+    if let Some(&command) = bytes.first() {
+        lang_items::crash_report::set_last_command(command);
+    }
+    // With a vendor interface, the FIDO transports only carry CTAP.
     #[cfg(feature = "vendor_hid")]
-    if matches!(channel, Channel::MainHid(_)) {
+    if !matches!(channel, Channel::VendorHid(_)) {
         return None;
     }
-    process_cbor(env, bytes, channel).unwrap_or_else(|e| Some(vec![e as u8]))
+    handler::process_vendor_command(env, bytes, channel, pin_uv_auth)
 }
 
+/// Processes a vendor command, with the check of pinUvAuthParams if the CTAP state is available.
 fn process_cbor<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config>(
     env: &mut TockEnv<S, C>,
     bytes: &[u8],
     channel: Channel,
+    pin_uv_auth: Option<&dyn PinUvAuthCheck>,
+) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+    handler::process_vendor_cbor(env, bytes, channel, pin_uv_auth)
+}
+
+impl<S, C> VendorCommandHandler for TockEnv<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn check_vendor_permissions(&mut self, command: u8) -> Result<(), Ctap2StatusCode> {
+        match required_permissions(command) {
+            Some(required) => permissions::check(self, required),
+            None => Ok(()),
+        }
+    }
+
+    // The fake syscalls have no USB driver to send keepalives.
+    #[cfg(feature = "std")]
+    fn vendor_cancellation_token(&mut self, _channel: Channel) -> CancellationToken<Self> {
+        CancellationToken::never()
+    }
+
+    // This is removed in std so we don't need too many mocks in TockEnv.
+    #[cfg(feature = "std")]
+    fn check_vendor_user_presence(&mut self, _channel: Channel) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    #[cfg(feature = "std")]
+    fn confirm_vendor_transaction(
+        &mut self,
+        _channel: Channel,
+        _transaction: impl FnOnce() -> Transaction,
+    ) -> Result<(), Ctap2StatusCode> {
+        Ok(())
+    }
+
+    #[cfg(feature = "bbs")]
+    fn consume_bbs_presentation_token(&mut self) -> bool {
+        bbs_tokens::consume(self)
+    }
+
+    fn process_vendor_delta_upgrade(
+        &mut self,
+        params: VendorUpgradeParameters,
+        channel: Channel,
+    ) -> Result<(), Ctap2StatusCode> {
+        let mut cancellation = self.vendor_cancellation_token(channel);
+        process_vendor_delta_upgrade(self, params, &mut cancellation)
+    }
+
+    // Running out of heap during the proof aborts, so fail before bothering the user.
+    #[cfg(all(feature = "bbs", not(feature = "std")))]
+    fn check_bbs_proof_resources(
+        &mut self,
+        params: &VendorBBSProofParameters,
+    ) -> Result<(), Ctap2StatusCode> {
+        if lang_items::free_heap() < params.heap_estimate() {
+            return Err(Ctap2StatusCode::CTAP2_ERR_REQUEST_TOO_LARGE);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "bbs")]
+    fn process_vendor_bbs_migration(
+        &mut self,
+        params: VendorBBSMigrationParameters,
+        channel: Channel,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        // Every step changes which device holds the credentials.
+        self.check_vendor_user_presence(channel)?;
+        if self.customization().bbs_requires_uv() {
+            check_user_verification(self, channel)?;
+        }
+        process_vendor_bbs_migration(self, params)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn process_vendor_bbs_recovery(
+        &mut self,
+        params: VendorBBSRecoveryParameters,
+        channel: Channel,
+    ) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+        // Pairing hands over the ability to continue this device's credentials.
+        self.check_vendor_user_presence(channel)?;
+        if self.customization().bbs_requires_uv() {
+            check_user_verification(self, channel)?;
+        }
+        process_vendor_bbs_recovery(self, params)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn process_vendor_bbs_authorize(
+        &mut self,
+        params: VendorBBSAuthorizeParameters,
+        channel: Channel,
+        pin_uv_auth: Option<&dyn PinUvAuthCheck>,
+    ) -> Result<VendorBBSAuthorizeResponse, Ctap2StatusCode> {
+        process_vendor_bbs_authorize(self, params, channel, pin_uv_auth)
+    }
+
+    fn process_environment_vendor_command(
+        &mut self,
+        bytes: &[u8],
+        channel: Channel,
+        pin_uv_auth: Option<&dyn PinUvAuthCheck>,
+    ) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        process_tock_vendor_command(self, bytes, channel, pin_uv_auth)
+    }
+}
+
+/// Processes the vendor commands that only the Tock environment implements.
+fn process_tock_vendor_command<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    bytes: &[u8],
+    channel: Channel,
+    pin_uv_auth: Option<&dyn PinUvAuthCheck>,
 ) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
-    match bytes[0] {
+    let command = match bytes.first() {
+        Some(&command) => command,
+        None => return Ok(None),
+    };
+    match command {
         VENDOR_COMMAND_CONFIGURE => {
             let decoded_cbor = cbor_read(&bytes[1..])?;
             let params = VendorConfigureParameters::try_from(decoded_cbor)?;
-            let response = process_vendor_configure(env, params, channel)?;
+            let response = process_vendor_configure_settings(env, params, channel, false)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_AUDIT_LOG => {
+            let response = process_vendor_audit_log(env)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_FIRMWARE_MEASUREMENT => {
+            let mut cancellation = env.vendor_cancellation_token(channel);
+            let response = process_vendor_firmware_measurement(env, &mut cancellation)?;
             Ok(Some(encode_cbor(response.into())))
         }
-        VENDOR_COMMAND_UPGRADE => {
+        VENDOR_COMMAND_SECURE_CHANNEL => {
             let decoded_cbor = cbor_read(&bytes[1..])?;
-            let params = VendorUpgradeParameters::try_from(decoded_cbor)?;
-            process_vendor_upgrade(env, params)?;
-            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
+            let params = VendorSecureChannelParameters::try_from(decoded_cbor)?;
+            process_vendor_secure_channel(env, params, PROVISIONING_PUBLIC_KEY, channel)
         }
-        VENDOR_COMMAND_UPGRADE_INFO => {
-            let response = process_vendor_upgrade_info(env)?;
+        VENDOR_COMMAND_LOG => {
+            let params = if bytes.len() > 1 {
+                VendorLogParameters::try_from(cbor_read(&bytes[1..])?)?
+            } else {
+                VendorLogParameters::default()
+            };
+            let response = process_vendor_log(env, params);
             Ok(Some(encode_cbor(response.into())))
         }
-        VENDOR_COMMAND_BBS_COMMITMENT => {
-            #[cfg(not(feature = "std"))]
-            check_user_presence(env, channel)?;
-            let response = process_vendor_bbs_commitment(env)?;
+        VENDOR_COMMAND_CRASH_REPORT => {
+            let params = if bytes.len() > 1 {
+                VendorCrashReportParameters::try_from(cbor_read(&bytes[1..])?)?
+            } else {
+                VendorCrashReportParameters::default()
+            };
+            let response = process_vendor_crash_report(env, params)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_INFO => {
+            let response = process_vendor_info(env)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_STATS => {
+            let response = process_vendor_stats(env, pin_uv_auth)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_STORE_COMPACT => {
+            let params = if bytes.len() > 1 {
+                VendorStoreCompactParameters::try_from(cbor_read(&bytes[1..])?)?
+            } else {
+                VendorStoreCompactParameters::default()
+            };
+            let mut cancellation = env.vendor_cancellation_token(channel);
+            let response = process_vendor_store_compact(env, params, &mut cancellation)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_STORE_DIAGNOSTICS => {
+            let response = process_vendor_store_diagnostics(env);
             Ok(Some(encode_cbor(response.into())))
         }
-        VENDOR_COMMAND_BBS_PROOF => {
+        #[cfg(feature = "compression")]
+        VENDOR_COMMAND_COMPRESSED => {
             let decoded_cbor = cbor_read(&bytes[1..])?;
-            let params = VendorBBSProofParameters::try_from(decoded_cbor)?;
-            #[cfg(not(feature = "std"))]
-            check_user_presence(env, channel)?;
-            let response = process_vendor_bbs_proof(env, params)?;
+            let params = VendorCompressedParameters::try_from(decoded_cbor)?;
+            let response = process_vendor_compressed(env, params, channel, pin_uv_auth)?;
             Ok(Some(encode_cbor(response.into())))
         }
+        #[cfg(feature = "heap_stats")]
+        VENDOR_COMMAND_HEAP_STATS => {
+            let response = process_vendor_heap_stats();
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VENDOR_COMMAND_CREDENTIAL_IMPORT => {
+            let decoded_cbor = cbor_read(&bytes[1..])?;
+            let params = VendorCredentialImportParameters::try_from(decoded_cbor)?;
+            // Imported passkeys sign in as the user, so only the user imports them.
+            if let VendorCredentialImportParameters::Import(_) = params {
+                #[cfg(not(feature = "std"))]
+                check_user_presence(env, channel, CommandClass::Vendor)?;
+                check_user_verification(env, channel)?;
+            }
+            process_vendor_credential_import(env, params)
+        }
         _ => Ok(None),
     }
 }
 
-fn encode_cbor(value: cbor::Value) -> Vec<u8> {
-    let mut response_vec = vec![Ctap2StatusCode::CTAP2_OK as u8];
-    if cbor_write(value, &mut response_vec).is_err() {
-        vec![Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR as u8]
-    } else {
-        response_vec
-    }
+/// Returns the permissions of which any enables the command.
+///
+/// Unknown commands need none, they aren't vendor commands. The info command needs none either,
+/// since it tells little more than getInfo. Neither do store diagnostics, since permissions are
+/// stored there. Compressed commands need those of the inner command.
+fn required_permissions(command: u8) -> Option<Permissions> {
+    let required = match command {
+        VENDOR_COMMAND_CONFIGURE => Permissions::PROVISIONING,
+        #[cfg(feature = "bbs")]
+        VENDOR_COMMAND_BBS_ISSUERS => Permissions::PROVISIONING,
+        // Admins also open sessions to change the permissions of locked devices.
+        VENDOR_COMMAND_SECURE_CHANNEL => Permissions::PROVISIONING | Permissions::ADMIN,
+        VENDOR_COMMAND_UPGRADE
+        | VENDOR_COMMAND_DELTA_UPGRADE
+        | VENDOR_COMMAND_UPGRADE_INFO
+        | VENDOR_COMMAND_VERIFY_UPGRADE
+        | VENDOR_COMMAND_BOOT_CONTROL => Permissions::UPGRADE,
+        VENDOR_COMMAND_AUDIT_LOG
+        | VENDOR_COMMAND_FIRMWARE_MEASUREMENT
+        | VENDOR_COMMAND_LOG
+        | VENDOR_COMMAND_CRASH_REPORT
+        | VENDOR_COMMAND_STATS
+        | VENDOR_COMMAND_STORE_COMPACT => Permissions::ADMIN,
+        #[cfg(feature = "heap_stats")]
+        VENDOR_COMMAND_HEAP_STATS => Permissions::ADMIN,
+        #[cfg(feature = "bbs")]
+        VENDOR_COMMAND_BBS_COMMITMENT
+        | VENDOR_COMMAND_BBS_MIGRATION
+        | VENDOR_COMMAND_BBS_RECOVERY
+        | VENDOR_COMMAND_BBS_REVOKE_ALL => Permissions::BBS_ISSUE,
+        #[cfg(feature = "bbs")]
+        VENDOR_COMMAND_BBS_PROOF
+        | VENDOR_COMMAND_BBS_INFO
+        | VENDOR_COMMAND_BBS_POSSESSION
+        | VENDOR_COMMAND_BBS_AUTHORIZE => Permissions::BBS_PRESENT,
+        // Admins audit the usage of credentials too.
+        #[cfg(feature = "bbs")]
+        VENDOR_COMMAND_BBS_USAGE => Permissions::BBS_PRESENT | Permissions::ADMIN,
+        VENDOR_COMMAND_CREDENTIAL_IMPORT => Permissions::CREDENTIAL_IMPORT,
+        _ => return None,
+    };
+    Some(required)
 }
 
 fn process_vendor_configure<
@@ -115,408 +397,1127 @@ fn process_vendor_configure<
 >(
     env: &mut TockEnv<S, C>,
     params: VendorConfigureParameters,
-    // Unused in std only
-    _channel: Channel,
+    channel: Channel,
 ) -> Result<VendorConfigureResponse, Ctap2StatusCode> {
-    if params.attestation_material.is_some() || params.lockdown {
+    let provisions = params.attestation_material.is_some() || params.aaguid.is_some();
+    if provisions {
+        lockdown::check_config(env)?;
+    }
+    if provisions || params.lockdown != LockdownLevel::None {
         // This is removed in std so we don't need too many mocks in TockEnv.
         #[cfg(not(feature = "std"))]
-        check_user_presence(env, _channel)?;
+        check_user_presence(env, channel, CommandClass::Vendor)?;
     }
+    check_certificate_aaguid(env, &params)?;
     // This command is for U2F support and we use the batch attestation there.
     let attestation_id = attestation_store::Id::Batch;
 
-    // Sanity checks
-    let current_attestation = env.attestation_store().get(&attestation_id)?;
-    let response = match params.attestation_material {
-        None => VendorConfigureResponse {
-            cert_programmed: current_attestation.is_some(),
-            pkey_programmed: current_attestation.is_some(),
-            link_secret_programmed: current_attestation.is_some(),
-        },
-        Some(data) => {
-            // We don't overwrite the attestation if it's already set. We don't return any error
-            // to not leak information.
-            if current_attestation.is_none() {
+    // Each part is only programmed if missing, so partial provisioning can be completed later.
+    // We don't overwrite what is already set. We don't return any error to not leak information.
+    if let Some(aaguid) = params.aaguid {
+        metadata::provision_aaguid(env, &aaguid)?;
+    }
+    if let Some(data) = params.attestation_material {
+        if let (Some(certificate), Some(private_key)) = (data.certificate, data.private_key) {
+            if env.attestation_store().get(&attestation_id)?.is_none() {
                 let attestation = Attestation {
-                    private_key: Secret::from_exposed_secret(data.private_key),
-                    certificate: data.certificate,
-                    link_secret: LinkSecret::from_bytes(data.link_secret),
+                    private_key,
+                    certificate,
                 };
                 env.attestation_store()
                     .set(&attestation_id, Some(&attestation))?;
             }
-            VendorConfigureResponse {
-                cert_programmed: true,
-                pkey_programmed: true,
-                link_secret_programmed: true,
+        }
+        #[cfg(feature = "bbs")]
+        if env.attestation_store().get_link_secret()?.is_none() {
+            // A generated link secret never leaves the device, not even at provisioning.
+            let link_secret = if env.customization().allows_external_link_secret() {
+                data.link_secret
+                    .map(|link_secret| LinkSecret::from_bytes(*link_secret))
+            } else {
+                Some(LinkSecret::random(env.rng()))
+            };
+            if let Some(link_secret) = link_secret {
+                env.attestation_store()
+                    .set_link_secret(Some(&link_secret))?;
             }
         }
+    }
+    let attestation_programmed = env.attestation_store().get(&attestation_id)?.is_some();
+    #[cfg(feature = "bbs")]
+    let link_secret_programmed = env.attestation_store().get_link_secret()?.is_some();
+    #[cfg(not(feature = "bbs"))]
+    let link_secret_programmed = false;
+    let mut response = VendorConfigureResponse {
+        cert_programmed: attestation_programmed,
+        pkey_programmed: attestation_programmed,
+        link_secret_programmed,
+        lockdown_level: lockdown::get(env)?,
+        permissions: permissions::get(env)?,
+        aaguid_programmed: metadata::has_custom_aaguid(env)?,
     };
-    if params.lockdown {
-        // To avoid bricking the authenticator, we only allow lockdown
-        // to happen if both values are programmed or if both U2F/CTAP1 and
-        // batch attestation are disabled.
-        #[cfg(feature = "with_ctap1")]
-        let need_certificate = true;
-        #[cfg(not(feature = "with_ctap1"))]
-        let need_certificate = env.customization().use_batch_attestation();
-
-        if (need_certificate && !(response.pkey_programmed && response.cert_programmed))
-            || !env.lock_firmware_protection()
-        {
+    if params.lockdown > response.lockdown_level {
+        check_lockdown_prerequisites(env, &response, channel)?;
+        if params.lockdown.locks_firmware() && !env.lock_firmware_protection() {
             return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR);
         }
+        lockdown::raise(env, params.lockdown)?;
+        response.lockdown_level = params.lockdown;
     }
     Ok(response)
 }
 
-fn process_vendor_upgrade<
+/// Configures the device, and applies the permissions, the minimum bundle version and the bundle
+/// key.
+///
+/// The minimum bundle version and the bundle key are only accepted through the provisioning
+/// session, where `authenticated` is set. Everything is checked before the store changes, and these
+/// settings are only written once the rest of the configuration succeeded, so that a refused or
+/// cancelled configure leaves them as they were.
+fn process_vendor_configure_settings<
     S: Syscalls,
     C: platform::subscribe::Config + platform::allow_ro::Config,
 >(
     env: &mut TockEnv<S, C>,
-    params: VendorUpgradeParameters,
+    mut params: VendorConfigureParameters,
+    channel: Channel,
+    authenticated: bool,
+) -> Result<VendorConfigureResponse, Ctap2StatusCode> {
+    if !authenticated && (params.min_bundle_version.is_some() || params.bundle_key.is_some()) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+    }
+    let new_permissions = params.permissions.take();
+    if new_permissions.is_some() {
+        permissions::check_set(env, authenticated)?;
+    }
+    let min_bundle_version = params.min_bundle_version.take();
+    let bundle_key = params.bundle_key.take();
+    let mut response = process_vendor_configure(env, params, channel)?;
+    if let Some(new_permissions) = new_permissions {
+        permissions::write(env, new_permissions)?;
+        response.permissions = new_permissions;
+    }
+    // Recovers from a broken release, by allowing the previous bundle again.
+    if let Some(version) = min_bundle_version {
+        rollback::set(env, version, authenticated)?;
+    }
+    if let Some(key) = &bundle_key {
+        bundle_key::set(env, key, authenticated)?;
+    }
+    Ok(response)
+}
+
+/// Refuses attestation certificates that name another AAGUID than the device reports.
+///
+/// Provisioning is checked as a whole, so that a mismatch doesn't leave any part written.
+fn check_certificate_aaguid<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: &VendorConfigureParameters,
 ) -> Result<(), Ctap2StatusCode> {
-    let VendorUpgradeParameters { offset, data, hash } = params;
-    let calculated_hash = Sha::<TockEnv<S>>::digest(&data);
-    if hash != calculated_hash {
-        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    let new_certificate = params
+        .attestation_material
+        .as_ref()
+        .and_then(|data| data.certificate.as_ref());
+    if params.aaguid.is_none() && new_certificate.is_none() {
+        return Ok(());
+    }
+    // A programmed certificate is kept, so it is the one to match.
+    let certificate = match env.attestation_store().get(&attestation_store::Id::Batch)? {
+        Some(attestation) => attestation.certificate,
+        None => match new_certificate {
+            Some(certificate) => certificate.clone(),
+            None => return Ok(()),
+        },
+    };
+    let aaguid = metadata::provisioned_aaguid(env, params.aaguid.as_ref())?;
+    match certificate_aaguid(&certificate)? {
+        Some(named_aaguid) if named_aaguid != aaguid => {
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        }
+        _ => Ok(()),
     }
-    env.upgrade_storage()
-        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)?
-        .write_bundle(offset, data)
-        .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
 }
 
-fn process_vendor_upgrade_info<
+/// Refuses to lock down a device that would be unusable or unrepairable afterwards.
+fn check_lockdown_prerequisites<
     S: Syscalls,
     C: platform::subscribe::Config + platform::allow_ro::Config,
 >(
     env: &mut TockEnv<S, C>,
-) -> Result<VendorUpgradeInfoResponse, Ctap2StatusCode> {
-    let upgrade_locations = env
-        .upgrade_storage()
-        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)?;
-    Ok(VendorUpgradeInfoResponse {
-        info: upgrade_locations.bundle_identifier(),
-    })
+    response: &VendorConfigureResponse,
+    channel: Channel,
+) -> Result<(), Ctap2StatusCode> {
+    // To avoid bricking the authenticator, we only allow lockdown
+    // to happen if both values are programmed or if both U2F/CTAP1 and
+    // batch attestation are disabled.
+    #[cfg(feature = "with_ctap1")]
+    let need_certificate = true;
+    #[cfg(not(feature = "with_ctap1"))]
+    let need_certificate = env.customization().use_batch_attestation();
+    if need_certificate && !(response.pkey_programmed && response.cert_programmed) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED);
+    }
+    // Without a link secret, no credential could ever be bound to the device.
+    if cfg!(feature = "bbs") && !response.link_secret_programmed {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED);
+    }
+    // The running firmware must be intact, since it may not be replaceable afterwards.
+    let mut cancellation = env.vendor_cancellation_token(channel);
+    let check = env.with_upgrade_storage(|env, upgrade_storage| {
+        upgrade_storage.check_running_firmware(|| cancellation.check(env).is_ok())
+    });
+    if cancellation.is_cancelled() {
+        return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+    }
+    if !matches!(check, Some(Ok(()))) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED);
+    }
+    Ok(())
 }
 
-fn process_vendor_bbs_commitment<
+/// Runs the configure command with parameters sent through the provisioning session.
+fn process_vendor_secure_channel<
     S: Syscalls,
     C: platform::subscribe::Config + platform::allow_ro::Config,
 >(
     env: &mut TockEnv<S, C>,
-) -> Result<VendorBBSCommitmentResponse, Ctap2StatusCode> {
-    let link_secret = {
-        let attestation_store = env.attestation_store();
-        attestation_store
-            .get(&attestation_store::Id::Batch)?
-            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?
-            .link_secret
-    };
-    let commitment = {
-        let rng = env.rng();
-        generate_link_secret_commitment(rng, &link_secret)
-            .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?
-    };
-    Ok(VendorBBSCommitmentResponse {
-        commitment: commitment.0.to_vec(),
-        secret_prover_blind: *commitment.1,
-    })
+    params: VendorSecureChannelParameters,
+    vendor_public_key: &[u8; PUBLIC_KEY_SIZE],
+    channel: Channel,
+) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+    match params {
+        VendorSecureChannelParameters::Setup => {
+            // Sessions serve provisioning, and admins changing permissions after lockdown.
+            if lockdown::check_config(env).is_err() {
+                permissions::check(env, Permissions::ADMIN)?;
+            }
+            // A new setup replaces any open session.
+            let (secure_channel, device_public_key) = SecureChannel::setup(env, vendor_public_key)?;
+            // Hosts check the signature before they send anything, see `secure_channel`.
+            let signature = SecureChannel::sign_public_key(env, &device_public_key)?;
+            env.secure_channel = Some(secure_channel);
+            let response = VendorSecureChannelSetupResponse {
+                device_public_key,
+                signature,
+            };
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VendorSecureChannelParameters::Configure { ciphertext, mac } => {
+            let secure_channel = env
+                .secure_channel
+                .as_mut()
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+            let plaintext = match secure_channel.open::<TockEnv<S, C>>(&ciphertext, &mac) {
+                Ok(plaintext) => plaintext,
+                Err(error) => {
+                    // Don't let an attacker keep guessing against the same keys.
+                    env.secure_channel = None;
+                    env.delay().random_delay(FAILURE_DELAY_MS);
+                    return Err(error);
+                }
+            };
+            let decoded_cbor = cbor_read(&plaintext)?;
+            let params = VendorConfigureParameters::try_from(decoded_cbor)?;
+            let response = process_vendor_configure_settings(env, params, channel, true)?;
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VendorSecureChannelParameters::Teardown => {
+            env.secure_channel = None;
+            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
+        }
+    }
 }
 
-fn process_vendor_bbs_proof<
+/// Applies a chunk of a patch against the running image, and writes the result like an upgrade.
+///
+/// Chunks have the parameters of the upgrade command, with offsets into the patch. They must
+/// arrive in order, and any error drops the patch so that the host starts over.
+fn process_vendor_delta_upgrade<
     S: Syscalls,
     C: platform::subscribe::Config + platform::allow_ro::Config,
 >(
     env: &mut TockEnv<S, C>,
-    params: VendorBBSProofParameters,
-) -> Result<VendorBBSProofResponse, Ctap2StatusCode> {
-    let link_secret = {
-        let attestation_store = env.attestation_store();
-        attestation_store
-            .get(&attestation_store::Id::Batch)?
-            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?
-            .link_secret
-    };
-    let proof = {
-        let rng = env.rng();
-        let proof_response = generate_proof(
-            rng,
-            &params.public_key,
-            &params.messages,
-            &link_secret,
-            &params.signature,
-            Some(&params.header),
-            Some(&params.presentation_header),
-            &params.disclosed_indexes,
-            Some(&params.secret_prover_blind),
-        )
-        .map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
-        proof_response.proof
+    params: VendorUpgradeParameters,
+    cancellation: &mut CancellationToken<TockEnv<S, C>>,
+) -> Result<(), Ctap2StatusCode> {
+    lockdown::check_upgrade(env)?;
+    let VendorUpgradeParameters {
+        offset,
+        mut data,
+        hash,
+        encrypted,
+    } = params;
+    let chunk_index = offset.checked_div(data.len()).unwrap_or(0);
+    user_feedback::signal(env, FeedbackState::Upgrading, chunk_index);
+    let calculated_hash = Sha::<TockEnv<S>>::digest(&data);
+    if hash != calculated_hash {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE);
+    }
+    // Patches have no metadata of their own to derive an IV from, so they travel in the clear.
+    if encrypted {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let mut patch = match env.delta_patch.take() {
+        _ if offset == 0 => DeltaPatch::default(),
+        Some(patch) if patch.patch_offset() == offset => patch,
+        _ => return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER),
     };
-    Ok(VendorBBSProofResponse {
-        proof_bytes: proof.to_bytes().to_vec(),
-        // proof_bytes: link_secret.to_bytes().to_vec(),
-    })
+    cancellation.check(env)?;
+    let min_version = rollback::min_version(env)?;
+    let result = env
+        .with_upgrade_storage(|env, upgrade_storage| -> Result<_, Ctap2StatusCode> {
+            patch.apply(&data, |offset, length| {
+                upgrade_storage.read_running(offset, length)
+            })?;
+            let mut committed = None;
+            while let Some((offset, page)) = patch.take_page() {
+                committed = upgrade_storage
+                    .write_bundle(offset, page, min_version, || {
+                        cancellation.check(env).is_ok()
+                    })
+                    .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+            }
+            Ok(committed)
+        })
+        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)?;
+    if cancellation.is_cancelled() {
+        return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+    }
+    if let Some(version) = result? {
+        rollback::raise(env, version)?;
+    }
+    if !patch.is_complete() {
+        env.delta_patch = Some(patch);
+    }
+    Ok(())
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct AttestationMaterial {
-    pub certificate: Vec<u8>,
-    pub private_key: [u8; EC_FIELD_SIZE],
-    pub link_secret: [u8; LinkSecret::SIZE],
+fn process_vendor_info<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config>(
+    env: &mut TockEnv<S, C>,
+) -> Result<VendorInfoResponse, Ctap2StatusCode> {
+    Ok(VendorInfoResponse {
+        aaguid: metadata::aaguid(env)?,
+        custom_aaguid: metadata::has_custom_aaguid(env)?,
+        certification_level: env.customization().certification_level(),
+        description: env.customization().metadata_description(),
+        firmware_version: env.firmware_version(),
+        compression_algorithms: if cfg!(feature = "compression") {
+            vec![COMPRESSION_LZSS]
+        } else {
+            Vec::new()
+        },
+    })
 }
 
-impl TryFrom<cbor::Value> for AttestationMaterial {
-    type Error = Ctap2StatusCode;
+/// Reports the remaining PIN and user verification attempts.
+///
+/// The PIN itself is unknown to the help desk, the stats tell whether replugging helps or only a
+/// reset does.
+fn process_vendor_stats<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    pin_uv_auth: Option<&dyn PinUvAuthCheck>,
+) -> Result<VendorStatsResponse, Ctap2StatusCode> {
+    let threshold = env.customization().pin_mismatches_before_power_cycle();
+    let uv_retries = if env.user_verification().is_supported() {
+        Some(uv_retries(env)?)
+    } else {
+        None
+    };
+    Ok(VendorStatsResponse {
+        pin_retries: pin_retries(env)?,
+        power_cycle_required: pin_uv_auth
+            .map(|pin_uv_auth| pin_uv_auth.consecutive_pin_mismatches() >= threshold),
+        uv_retries,
+    })
+}
 
-    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
-        destructure_cbor_map! {
-            let {
-                0x01 => certificate,
-                0x02 => private_key,
-                0x03 => link_secret,
-            } = extract_map(cbor_value)?;
-        }
-        let certificate = extract_byte_string(ok_or_missing(certificate)?)?;
-        let private_key = extract_byte_string(ok_or_missing(private_key)?)?;
-        let link_secret = extract_byte_string(ok_or_missing(link_secret)?)?;
-        if private_key.len() != EC_FIELD_SIZE {
-            return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+/// Compacts the store ahead of ceremonies, see `opensk::ctap::compact_store`.
+///
+/// Each call compacts a few pages and reports the capacity before and after, so that hosts show
+/// progress instead of waiting for one long stall.
+fn process_vendor_store_compact<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorStoreCompactParameters,
+    cancellation_token: &mut CancellationToken<TockEnv<S, C>>,
+) -> Result<VendorStoreCompactResponse, Ctap2StatusCode> {
+    let before = store_capacity(env)?;
+    let length = params.length.unwrap_or(before.remaining);
+    let max_pages = core::cmp::min(params.max_pages.unwrap_or(1), MAX_COMPACTION_PAGES);
+    let mut pages = 0;
+    while pages < max_pages {
+        cancellation_token.check(env)?;
+        if compact_store(env, length, 1)? == 0 {
+            break;
         }
-        let private_key = array_ref!(private_key, 0, EC_FIELD_SIZE);
-        let link_secret = <[u8; LinkSecret::SIZE]>::try_from(link_secret)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        Ok(AttestationMaterial {
-            certificate,
-            private_key: *private_key,
-            link_secret,
-        })
+        pages += 1;
     }
+    let after = store_capacity(env)?;
+    if pages > 0 {
+        log_ctap!(env, Level::Info, "Compacted {} store pages", pages);
+    }
+    Ok(VendorStoreCompactResponse {
+        before,
+        after,
+        pages,
+        done: after.immediate >= core::cmp::min(length, after.remaining),
+    })
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct VendorConfigureParameters {
-    pub lockdown: bool,
-    pub attestation_material: Option<AttestationMaterial>,
+/// Reports whether the store is corrupted, and what is left of it.
+///
+/// A corrupted store is read-only, and getInfo flags it too. Only a reset wipes it, which also
+/// erases provisioned data.
+fn process_vendor_store_diagnostics<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+) -> VendorStoreDiagnosticsResponse {
+    let corrupted = is_store_corrupted(env);
+    let store = env.store();
+    VendorStoreDiagnosticsResponse {
+        corrupted,
+        lifetime: store
+            .lifetime()
+            .ok()
+            .map(|lifetime| (lifetime.used(), lifetime.total())),
+        entries: store.iter().ok().map(Iterator::count),
+    }
 }
 
-impl TryFrom<cbor::Value> for VendorConfigureParameters {
-    type Error = Ctap2StatusCode;
+/// Runs the decompressed inner command, and compresses its response.
+///
+/// Errors of the inner command are part of the compressed response, so that the host can tell
+/// them apart from a malformed wrapper.
+#[cfg(feature = "compression")]
+fn process_vendor_compressed<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorCompressedParameters,
+    channel: Channel,
+    pin_uv_auth: Option<&dyn PinUvAuthCheck>,
+) -> Result<VendorCompressedResponse, Ctap2StatusCode> {
+    if params.algorithm != COMPRESSION_LZSS {
+        return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM);
+    }
+    // Decompressed commands are held to the same limit as plain ones.
+    let command = sk_lzss::decompress(&params.command, env.customization().max_msg_size())
+        .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    // Nesting compresses nothing further, it only costs stack.
+    if command.first() == Some(&VENDOR_COMMAND_COMPRESSED) {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let response = match process_cbor(env, &command, channel, pin_uv_auth) {
+        Ok(Some(response)) => response,
+        Ok(None) => vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8],
+        Err(e) => vec![e as u8],
+    };
+    Ok(VendorCompressedResponse {
+        response: sk_lzss::compress(&response),
+    })
+}
 
-    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
-        destructure_cbor_map! {
-            let {
-                0x01 => lockdown,
-                0x02 => attestation_material,
-            } = extract_map(cbor_value)?;
-        }
-        let lockdown = lockdown.map_or(Ok(false), extract_bool)?;
-        let attestation_material = attestation_material
-            .map(AttestationMaterial::try_from)
-            .transpose()?;
-        Ok(VendorConfigureParameters {
-            lockdown,
-            attestation_material,
+fn process_vendor_firmware_measurement<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    cancellation: &mut CancellationToken<TockEnv<S, C>>,
+) -> Result<VendorFirmwareMeasurementResponse, Ctap2StatusCode> {
+    let result = env
+        .with_upgrade_storage(|env, upgrade_storage| {
+            let hash = upgrade_storage.running_firmware_hash(|| cancellation.check(env).is_ok());
+            (hash, upgrade_storage.bundle_identifier())
         })
+        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND)?;
+    if cancellation.is_cancelled() {
+        return Err(Ctap2StatusCode::CTAP2_ERR_KEEPALIVE_CANCEL);
+    }
+    let (hash, bundle_identifier) = result;
+    let hash = hash.map_err(|_| Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+    let boot_info = env.boot_info();
+    let mut message = MEASUREMENT_DOMAIN.to_vec();
+    message.extend_from_slice(&hash);
+    message.extend_from_slice(&bundle_identifier.to_be_bytes());
+    // Verifiers can only trust the versions if they are signed too.
+    if let Some(boot_info) = &boot_info {
+        message.extend_from_slice(&boot_info.measurement);
+        message.extend_from_slice(&boot_info.firmware_version.to_be_bytes());
+        message.extend_from_slice(&boot_info.rollback_counter.to_be_bytes());
     }
+    let (signature, certificate) = sign_with_attestation(env, &message)?;
+    Ok(VendorFirmwareMeasurementResponse {
+        hash,
+        bundle_identifier,
+        signature,
+        certificate,
+        boot_info,
+    })
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct VendorUpgradeParameters {
-    pub offset: usize,
-    pub data: Vec<u8>,
-    pub hash: [u8; 32],
+fn process_vendor_audit_log<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+) -> Result<VendorAuditLogResponse, Ctap2StatusCode> {
+    Ok(VendorAuditLogResponse {
+        now: Timestamp::now(env)?,
+        entries: audit_log::entries(env)?,
+    })
 }
 
-impl TryFrom<cbor::Value> for VendorUpgradeParameters {
-    type Error = Ctap2StatusCode;
-
-    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
-        destructure_cbor_map! {
-            let {
-                0x01 => offset,
-                0x02 => data,
-                0x03 => hash,
-            } = extract_map(cbor_value)?;
-        }
-        let offset = extract_unsigned(ok_or_missing(offset)?)? as usize;
-        let data = extract_byte_string(ok_or_missing(data)?)?;
-        let hash = <[u8; 32]>::try_from(extract_byte_string(ok_or_missing(hash)?)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        Ok(VendorUpgradeParameters { offset, data, hash })
+/// Dumps the log, then applies the changes for the next dump.
+fn process_vendor_log<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config>(
+    env: &mut TockEnv<S, C>,
+    params: VendorLogParameters,
+) -> VendorLogResponse {
+    let log_buffer = &mut env.log_buffer;
+    let response = VendorLogResponse {
+        contents: log_buffer.contents(),
+        max_level: log_buffer.max_level(),
+    };
+    if params.clear {
+        log_buffer.clear();
     }
+    if let Some(max_level) = params.max_level {
+        log_buffer.set_max_level(max_level);
+    }
+    response
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct VendorConfigureResponse {
-    pub cert_programmed: bool,
-    pub pkey_programmed: bool,
-    pub link_secret_programmed: bool,
+/// Reads the report of the last crash, then clears it if asked.
+fn process_vendor_crash_report<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorCrashReportParameters,
+) -> Result<VendorCrashReportResponse, Ctap2StatusCode> {
+    let report = crash_report::get(env)?;
+    if params.clear {
+        crash_report::clear(env)?;
+    }
+    Ok(VendorCrashReportResponse { report })
 }
 
-impl From<VendorConfigureResponse> for cbor::Value {
-    fn from(vendor_response: VendorConfigureResponse) -> Self {
-        let VendorConfigureResponse {
-            cert_programmed,
-            pkey_programmed,
-            link_secret_programmed,
-        } = vendor_response;
-
-        cbor_map_options! {
-            0x01 => cert_programmed,
-            0x02 => pkey_programmed,
-            0x03 => link_secret_programmed,
-        }
+/// Reports the heap usage, with the peak since the previous report.
+///
+/// Sending it before and after another command measures the peak heap of that command.
+#[cfg(feature = "heap_stats")]
+fn process_vendor_heap_stats() -> VendorHeapStatsResponse {
+    let HeapStats { current, peak } = heap_stats::take();
+    #[cfg(feature = "std")]
+    let free = None;
+    #[cfg(not(feature = "std"))]
+    let free = Some(lang_items::free_heap());
+    VendorHeapStatsResponse {
+        current,
+        peak,
+        free,
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct VendorUpgradeInfoResponse {
-    pub info: u32,
+/// Runs a step of the migration of BBS credentials, see the `bbs_migration` module.
+#[cfg(feature = "bbs")]
+fn process_vendor_bbs_migration<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorBBSMigrationParameters,
+) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+    match params {
+        VendorBBSMigrationParameters::Prepare => {
+            // A new transport key replaces the pending one.
+            let (transport_key, transport_public_key) = transport::generate_transport_key(env);
+            let transcript = bbs_migration::transport_key_transcript(&transport_public_key);
+            let (signature, certificate) = sign_with_attestation(env, &transcript)?;
+            env.migration_transport_key = Some(transport_key);
+            let response = VendorBBSMigrationPrepareResponse {
+                transport_public_key,
+                signature,
+                certificate,
+            };
+            Ok(Some(response.into()))
+        }
+        VendorBBSMigrationParameters::Export {
+            transport_public_key,
+        } => {
+            let entries = bbs_blinds::list(env)?
+                .into_iter()
+                .map(|(issuer_id, record)| MigrationEntry {
+                    issuer_id,
+                    policy: record.policy,
+                })
+                .collect::<Vec<_>>();
+            let bundle = bbs_migration::seal(env, &transport_public_key, &entries)?;
+            // The credentials of this device stop proving, so they can't live on both devices.
+            bbs_blinds::clear(env)?;
+            bbs_sessions::finish(env)?;
+            if env.attestation_store().get_link_secret()?.is_some() {
+                let link_secret = LinkSecret::random(env.rng());
+                env.attestation_store()
+                    .set_link_secret(Some(&link_secret))?;
+            }
+            log_ctap!(env, Level::Info, "Exported {} BBS issuers", entries.len());
+            let response = VendorBBSMigrationExportResponse {
+                bundle,
+                count: entries.len(),
+            };
+            Ok(Some(response.into()))
+        }
+        VendorBBSMigrationParameters::Import(bundle) => {
+            let transport_key = env
+                .migration_transport_key
+                .as_ref()
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+            let entries = match bbs_migration::open::<TockEnv<S, C>>(transport_key, &bundle) {
+                Ok(entries) => entries,
+                Err(error) => {
+                    // Don't let an attacker keep guessing against the same key.
+                    env.migration_transport_key = None;
+                    return Err(error);
+                }
+            };
+            // The key stays if the store is full, so that the import can be retried.
+            for entry in &entries {
+                let record = match bbs_blinds::find(env, &entry.issuer_id)? {
+                    Some(record) => BlindRecord {
+                        policy: record.policy.union(&entry.policy),
+                        ..record
+                    },
+                    None => BlindRecord::migrated(entry.policy),
+                };
+                bbs_blinds::store(env, &entry.issuer_id, &record)?;
+            }
+            env.migration_transport_key = None;
+            log_ctap!(env, Level::Info, "Imported {} BBS issuers", entries.len());
+            let response = VendorBBSMigrationImportResponse {
+                count: entries.len(),
+            };
+            Ok(Some(response.into()))
+        }
+    }
 }
 
-impl From<VendorUpgradeInfoResponse> for cbor::Value {
-    fn from(vendor_upgrade_info_response: VendorUpgradeInfoResponse) -> Self {
-        let VendorUpgradeInfoResponse { info } = vendor_upgrade_info_response;
-
-        cbor_map_options! {
-            0x01 => info as u64,
+/// Runs a step of passkey import, see the `credential_import` module of the library.
+///
+/// The host first gets a transport key signed by the attestation key, so that the exporter knows
+/// it wraps the passkeys to a genuine device. The key is held in RAM until the import.
+fn process_vendor_credential_import<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorCredentialImportParameters,
+) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+    match params {
+        VendorCredentialImportParameters::Prepare => {
+            // A new transport key replaces the pending one.
+            let (transport_key, transport_public_key) = transport::generate_transport_key(env);
+            let mut transcript = CREDENTIAL_IMPORT_KEY_DOMAIN.to_vec();
+            transcript.extend_from_slice(&transport_public_key);
+            let (signature, certificate) = sign_with_attestation(env, &transcript)?;
+            env.import_transport_key = Some(transport_key);
+            let response = VendorCredentialImportPrepareResponse {
+                transport_public_key,
+                signature,
+                certificate,
+            };
+            Ok(Some(encode_cbor(response.into())))
+        }
+        VendorCredentialImportParameters::Import(bundle) => {
+            let transport_key = env
+                .import_transport_key
+                .as_ref()
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+            let plaintext = match transport::unwrap::<TockEnv<S, C>>(
+                transport_key,
+                CREDENTIAL_IMPORT_PURPOSE,
+                &bundle,
+            ) {
+                Ok(plaintext) => plaintext,
+                Err(error) => {
+                    // Don't let an attacker keep guessing against the same key.
+                    env.import_transport_key = None;
+                    return Err(error);
+                }
+            };
+            // The key stays if the passkeys don't fit, so that the import can be retried.
+            let count = import_credentials(env, cbor_read(&plaintext)?)?;
+            env.import_transport_key = None;
+            log_ctap!(env, Level::Info, "Imported {} passkeys", count);
+            let response = VendorCredentialImportResponse { count };
+            Ok(Some(encode_cbor(response.into())))
         }
     }
 }
 
-// TODO: link_secret must be removed from the response. This is fir temporal debugging.
-#[derive(Debug, PartialEq, Eq)]
-pub struct VendorBBSCommitmentResponse {
-    pub commitment: Vec<u8>,
-    pub secret_prover_blind: [u8; 32],
+/// Runs a step of backup pairing, see the `bbs_recovery` module.
+#[cfg(feature = "bbs")]
+fn process_vendor_bbs_recovery<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorBBSRecoveryParameters,
+) -> Result<Option<cbor::Value>, Ctap2StatusCode> {
+    match params {
+        VendorBBSRecoveryParameters::Share {
+            transport_public_key,
+        } => {
+            // Sharing again pairs another backup with the same secret.
+            let secret = bbs_recovery::get_or_generate_secret(env)?;
+            let bundle = bbs_recovery::seal(env, &transport_public_key, &secret)?;
+            log_ctap!(env, Level::Info, "Shared the BBS recovery secret");
+            let response = VendorBBSRecoveryShareResponse { bundle };
+            Ok(Some(response.into()))
+        }
+        VendorBBSRecoveryParameters::Accept(bundle) => {
+            let transport_key = env
+                .migration_transport_key
+                .take()
+                .ok_or(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)?;
+            let secret = bbs_recovery::open::<TockEnv<S, C>>(&transport_key, &bundle)?;
+            bbs_recovery::set_secret(env, Some(&*secret))?;
+            log_ctap!(env, Level::Info, "Paired as a BBS backup");
+            Ok(None)
+        }
+        VendorBBSRecoveryParameters::Forget => {
+            bbs_recovery::set_secret(env, None)?;
+            log_ctap!(env, Level::Info, "Forgot the BBS recovery secret");
+            Ok(None)
+        }
+    }
 }
 
-impl From<VendorBBSCommitmentResponse> for cbor::Value {
-    fn from(vendor_bbs_response: VendorBBSCommitmentResponse) -> Self {
-        let VendorBBSCommitmentResponse {
-            commitment,
-            secret_prover_blind,
-        } = vendor_bbs_response;
-
-        cbor_map_options! {
-            0x01 => commitment,
-            0x02 => secret_prover_blind,
+/// Grants a presentation token after user verification, see the `bbs_tokens` module.
+///
+/// Instead of built-in user verification, the host can prove a PIN with a pinUvAuthToken that
+/// has the `BbsPresentation` permission.
+#[cfg(feature = "bbs")]
+fn process_vendor_bbs_authorize<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: VendorBBSAuthorizeParameters,
+    channel: Channel,
+    pin_uv_auth: Option<&dyn PinUvAuthCheck>,
+) -> Result<VendorBBSAuthorizeResponse, Ctap2StatusCode> {
+    // Revoking needs no consent, it only takes authorization away.
+    if params.proofs == 0 {
+        env.presentation_token = None;
+        return Ok(VendorBBSAuthorizeResponse {
+            proofs: 0,
+            duration_ms: 0,
+        });
+    }
+    let max_proofs = env.customization().max_bbs_presentation_token_proofs();
+    if max_proofs == 0 {
+        return Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION);
+    }
+    match (&params.pin_uv_auth_param, params.pin_uv_auth_protocol) {
+        (Some(pin_uv_auth_param), Some(pin_uv_auth_protocol)) => {
+            let pin_uv_auth = pin_uv_auth.ok_or(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)?;
+            let hmac_contents = bbs_authorize_hmac_contents(&params)?;
+            pin_uv_auth.check_pin_uv_auth(
+                PinPermission::BbsPresentation,
+                &hmac_contents,
+                pin_uv_auth_param,
+                pin_uv_auth_protocol,
+            )?;
+            #[cfg(not(feature = "std"))]
+            check_user_presence(env, channel, CommandClass::Vendor)?;
+        }
+        (None, None) => {
+            #[cfg(not(feature = "std"))]
+            check_user_presence(env, channel, CommandClass::Vendor)?;
+            check_user_verification(env, channel)?;
         }
+        _ => return Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER),
     }
+    let proofs = core::cmp::min(params.proofs, max_proofs);
+    let max_ms = env.customization().max_bbs_presentation_token_ms() as u64;
+    let duration_ms = params
+        .duration_ms
+        .map_or(max_ms, |ms| core::cmp::min(ms, max_ms));
+    let now_ms = env.clock().uptime_ms();
+    env.presentation_token = Some(PresentationToken::new(proofs, now_ms, duration_ms));
+    log_ctap!(env, Level::Info, "Authorized {} BBS proofs", proofs);
+    Ok(VendorBBSAuthorizeResponse {
+        proofs,
+        duration_ms,
+    })
 }
 
-#[derive(Debug)]
-pub struct VendorBBSProofParameters {
-    pub public_key: BBSPublicKey,
-    pub messages: Vec<Vec<u8>>,
-    pub signature: BBSSignature,
-    pub header: Vec<u8>,
-    pub presentation_header: Vec<u8>,
-    pub disclosed_indexes: Vec<usize>,
-    pub secret_prover_blind: BBSCommitmentBlindFactor,
+/// The message authenticated by the pinUvAuthParam of a presentation token request.
+///
+/// Like authenticatorConfig, it starts with 32 bytes 0xFF and the command byte, followed by the
+/// canonical CBOR of the requested proofs and duration.
+#[cfg(feature = "bbs")]
+fn bbs_authorize_hmac_contents(
+    params: &VendorBBSAuthorizeParameters,
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let mut hmac_contents = vec![0xFF; 32];
+    hmac_contents.push(VENDOR_COMMAND_BBS_AUTHORIZE);
+    let request = cbor_map_options! {
+        0x01 => params.proofs as u64,
+        0x02 => params.duration_ms,
+    };
+    cbor_write(request, &mut hmac_contents)?;
+    Ok(hmac_contents)
 }
 
-impl TryFrom<cbor::Value> for VendorBBSProofParameters {
-    type Error = Ctap2StatusCode;
-
-    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
-        destructure_cbor_map! {
-            let {
-                0x01 => public_key,
-                0x02 => messages,
-                0x03 => signature,
-                0x04 => header,
-                0x05 => presentation_header,
-                0x06 => disclosed_indexes,
-                0x07 => secret_prover_blind,
-            } = extract_map(cbor_value)?;
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "bbs")]
+    use super::super::ipc::{self, IpcCapabilities};
+    use super::super::secure_channel::{decode_public_key, encode_public_key};
+    use super::super::upgrade_helper::{
+        BundleVerification, BOOT_STATUS_LENGTH, BOOT_STATUS_OFFSET, METADATA_SIGN_OFFSET,
+    };
+    use super::super::vendor_parameters::{
+        AttestationMaterial, VendorBootControlParameters, VendorUpgradeInfoResponse,
+        VendorVerifyUpgradeResponse,
+    };
+    use super::*;
+    use alloc::boxed::Box;
+    #[cfg(feature = "bbs")]
+    use alloc::string::ToString;
+    #[cfg(feature = "bbs")]
+    use bbs::{
+        verify_link_secret_commitment, verify_presentation, BBSPublicKey, BBSSecretKey, BBS,
+    };
+    use byteorder::{ByteOrder, LittleEndian};
+    use cbor::{
+        cbor_array, cbor_array_vec, cbor_int, cbor_map, cbor_map_options, destructure_cbor_map,
+    };
+    #[cfg(feature = "bbs")]
+    use cbor::{cbor_bytes, cbor_false};
+    use lang_items::crash_report::CrashReport;
+    use libtock_unittest::fake::Syscalls;
+    use opensk::api::boot_info::BootInfo;
+    use opensk::api::crypto::ecdh::{SecretKey as _, SharedSecret as _};
+    use opensk::api::crypto::ecdsa::{SecretKey, Signature};
+    use opensk::api::crypto::{AES_KEY_SIZE, EC_FIELD_SIZE, HASH_SIZE};
+    use opensk::api::customization::AAGUID_LENGTH;
+    #[cfg(feature = "bbs")]
+    use opensk::api::user_verification::NoUserVerification;
+    use opensk::api::user_verification::UserVerificationResult;
+    use opensk::ctap::data_formats::{
+        extract_array, extract_byte_string, extract_map, extract_unsigned, PinUvAuthProtocol,
+        SignatureAlgorithm,
+    };
+    use opensk::ctap::secret::Secret;
+    use opensk::ctap::PinPermission;
+    use opensk::env::{EcdhSk, EcdsaSk};
+    use opensk::vendor::commands::{
+        process_vendor_boot_control, process_vendor_upgrade, process_vendor_upgrade_info,
+        process_vendor_verify_upgrade,
+    };
+    #[cfg(feature = "bbs")]
+    use opensk::vendor::commands::{
+        required_consent, scoped_link_secret, Consent, DEVICE_STATE_PREFIX,
+    };
+    #[cfg(feature = "bbs")]
+    use opensk::vendor::{bbs_epoch, bbs_issuers, bbs_usage};
+    #[cfg(feature = "bbs")]
+    use zkryptium::schemes::generics::BlindSignature;
+
+    const DUMMY_CHANNEL: Channel = Channel::MainHid([0x12, 0x34, 0x56, 0x78]);
+    #[cfg(feature = "vendor_hid")]
+    const VENDOR_CHANNEL: Channel = Channel::VendorHid([0x12, 0x34, 0x56, 0x78]);
+
+    #[cfg(feature = "bbs")]
+    const BBS_FIXTURE: &str = include_str!("../../../third_party/bbs/fixtures/proof.json");
+    #[cfg(feature = "bbs")]
+    const BBS_HEADER: &[u8] = b"header";
+    #[cfg(feature = "bbs")]
+    const BBS_PRESENTATION_HEADER: &[u8] = b"presentation header";
+
+    #[cfg(feature = "bbs")]
+    /// A credential as the host stores it after issuance.
+    struct Credential {
+        messages: Vec<Vec<u8>>,
+        signature: Vec<u8>,
+        /// Only known to the host if the device didn't store it for an issuer.
+        secret_prover_blind: Option<Vec<u8>>,
+        issuer_id: Option<Vec<u8>>,
+    }
+
+    #[cfg(feature = "bbs")]
+    fn fixture_hex(path: &[&str]) -> Vec<u8> {
+        let json: serde_json::Value = serde_json::from_str(BBS_FIXTURE).unwrap();
+        json_hex(&json, path)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn json_hex(json: &serde_json::Value, path: &[&str]) -> Vec<u8> {
+        let value = path.iter().fold(json, |value, key| &value[*key]);
+        hex::decode(value.as_str().unwrap()).unwrap()
+    }
+
+    #[cfg(feature = "bbs")]
+    fn provision_link_secret(env: &mut TockEnv<Syscalls>) {
+        provision_link_secret_with(env, fixture_hex(&["linkSecret"]));
+    }
+
+    #[cfg(feature = "bbs")]
+    fn provision_link_secret_with(env: &mut TockEnv<Syscalls>, link_secret: Vec<u8>) {
+        let link_secret = <[u8; LinkSecret::SIZE]>::try_from(link_secret).unwrap();
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: None,
+                private_key: None,
+                link_secret: Some(Secret::from_exposed_secret(link_secret)),
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+        assert!(process_vendor_configure(env, params, DUMMY_CHANNEL).is_ok());
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Sends a vendor command like the host, and returns the response map.
+    fn vendor_command(
+        env: &mut TockEnv<Syscalls>,
+        command: u8,
+        params: Option<cbor::Value>,
+    ) -> Vec<(cbor::Value, cbor::Value)> {
+        let mut bytes = vec![command];
+        if let Some(params) = params {
+            assert!(cbor_write(params, &mut bytes).is_ok());
         }
+        let response = process_cbor(env, &bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        extract_map(cbor_read(&response[1..]).unwrap()).unwrap()
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Gets a credential from the fixture issuer, bound to the link secret of the device.
+    fn issue_credential(env: &mut TockEnv<Syscalls>, messages: Vec<Vec<u8>>) -> Credential {
+        issue_credential_for(env, messages, None)
+    }
 
-        let public_key = extract_byte_string(ok_or_missing(public_key)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        let public_key = BBSPublicKey::from_bytes(public_key.as_slice())
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    #[cfg(feature = "bbs")]
+    /// Like `issue_credential`, but lets the device keep the blind if an issuer id is given.
+    fn issue_credential_for(
+        env: &mut TockEnv<Syscalls>,
+        messages: Vec<Vec<u8>>,
+        issuer_id: Option<&[u8]>,
+    ) -> Credential {
+        let params = issuer_id.map(|issuer_id| cbor_map! { 0x03 => issuer_id });
+        issue_credential_with(env, messages, issuer_id, params)
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Like `issue_credential_for`, with the given commitment parameters.
+    fn issue_credential_with(
+        env: &mut TockEnv<Syscalls>,
+        messages: Vec<Vec<u8>>,
+        issuer_id: Option<&[u8]>,
+        params: Option<cbor::Value>,
+    ) -> Credential {
+        destructure_cbor_map! {
+            let {
+                0x01 => commitment,
+                0x02 => secret_prover_blind,
+            } = vendor_command(env, VENDOR_COMMAND_BBS_COMMITMENT, params);
+        }
+        let commitment = extract_byte_string(commitment.unwrap()).unwrap();
+        assert!(verify_link_secret_commitment(&commitment).unwrap());
+        let secret_key =
+            BBSSecretKey::from_bytes(&fixture_hex(&["signerKeyPair", "secretKey"])).unwrap();
+        let public_key =
+            BBSPublicKey::from_bytes(&fixture_hex(&["signerKeyPair", "publicKey"])).unwrap();
+        let signature = BlindSignature::<BBS>::blind_sign(
+            &secret_key,
+            &public_key,
+            Some(&commitment),
+            Some(BBS_HEADER),
+            Some(&messages),
+            None,
+        )
+        .unwrap();
+        Credential {
+            messages,
+            signature: signature.to_bytes().to_vec(),
+            secret_prover_blind: secret_prover_blind
+                .map(|blind| extract_byte_string(blind).unwrap()),
+            issuer_id: issuer_id.map(<[u8]>::to_vec),
+        }
+    }
 
-        let messages = extract_array(ok_or_missing(messages)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        let messages = messages
+    #[cfg(feature = "bbs")]
+    fn proof_params(credential: &Credential, disclosed_indexes: &[usize]) -> cbor::Value {
+        let disclosed_indexes = disclosed_indexes
             .iter()
-            .map(|message| extract_byte_string(message.clone()).unwrap())
+            .map(|&index| index as u64)
             .collect::<Vec<_>>();
+        cbor_map_options! {
+            0x01 => fixture_hex(&["signerKeyPair", "publicKey"]),
+            0x02 => cbor_array_vec!(credential.messages.clone()),
+            0x03 => credential.signature.clone(),
+            0x04 => BBS_HEADER,
+            0x05 => BBS_PRESENTATION_HEADER,
+            0x06 => cbor_array_vec!(disclosed_indexes),
+            0x07 => credential.secret_prover_blind.clone(),
+            0x08 => credential.issuer_id.clone(),
+        }
+    }
 
-        let signature_raw = extract_byte_string(ok_or_missing(signature)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        let mut signature: [u8; 80] = [0u8; 80];
-        signature.copy_from_slice(&signature_raw);
-        let signature = BBSSignature::from_bytes(&signature)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    #[cfg(feature = "bbs")]
+    fn request_proof(
+        env: &mut TockEnv<Syscalls>,
+        credential: &Credential,
+        disclosed_indexes: &[usize],
+    ) -> Vec<u8> {
+        let params = proof_params(credential, disclosed_indexes);
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+            } = vendor_command(env, VENDOR_COMMAND_BBS_PROOF, Some(params));
+        }
+        extract_byte_string(proof.unwrap()).unwrap()
+    }
 
-        let header = extract_byte_string(ok_or_missing(header)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        let presentation_header = extract_byte_string(ok_or_missing(presentation_header)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    #[cfg(feature = "bbs")]
+    /// Verifies the proof like a relying party, which only sees the disclosed messages.
+    fn verify_proof(proof: &[u8], credential: &Credential, disclosed_indexes: &[usize]) -> bool {
+        verify_proof_with_header(
+            proof,
+            credential,
+            disclosed_indexes,
+            BBS_PRESENTATION_HEADER,
+        )
+    }
 
-        let disclosed_indexes = extract_array(ok_or_missing(disclosed_indexes)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        let disclosed_indexes = disclosed_indexes
+    #[cfg(feature = "bbs")]
+    fn verify_proof_with_header(
+        proof: &[u8],
+        credential: &Credential,
+        disclosed_indexes: &[usize],
+        presentation_header: &[u8],
+    ) -> bool {
+        let disclosed_messages = disclosed_indexes
             .iter()
-            .map(|index| extract_unsigned(index.clone()).and_then(|u| Ok(u as usize)))
-            .collect::<Result<Vec<usize>, Ctap2StatusCode>>()?;
-
-        let secret_prover_blind_raw = extract_byte_string(ok_or_missing(secret_prover_blind)?)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-        let mut secret_prover_blind = [0u8; 32];
-        secret_prover_blind.copy_from_slice(&secret_prover_blind_raw);
-        let secret_prover_blind = BBSCommitmentBlindFactor::from_bytes(&secret_prover_blind)
-            .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
-
-        Ok(VendorBBSProofParameters {
-            public_key,
-            messages,
-            signature,
-            header,
+            .map(|&index| credential.messages[index].clone())
+            .collect::<Vec<_>>();
+        verify_presentation(
+            &fixture_hex(&["signerKeyPair", "publicKey"]),
+            proof,
+            BBS_HEADER,
             presentation_header,
+            &disclosed_messages,
             disclosed_indexes,
-            secret_prover_blind,
-        })
+        )
     }
-}
-
-#[derive(Debug, PartialEq, Eq)]
-pub struct VendorBBSProofResponse {
-    pub proof_bytes: Vec<u8>,
-}
 
-impl From<VendorBBSProofResponse> for cbor::Value {
-    fn from(vendor_bbs_response: VendorBBSProofResponse) -> Self {
-        let VendorBBSProofResponse { proof_bytes } = vendor_bbs_response;
-
-        cbor_map_options! {
-            0x01 => proof_bytes,
+    #[test]
+    fn test_vendor_status_codes_match_protocol() {
+        use opensk_vendor_protocol::status;
+        let codes = [
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_UNKNOWN_ISSUER,
+                status::VENDOR_BBS_UNKNOWN_ISSUER,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR,
+                status::VENDOR_INTERNAL_ERROR,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_HARDWARE_FAILURE,
+                status::VENDOR_HARDWARE_FAILURE,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED,
+                status::VENDOR_LOCKDOWN_REFUSED,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_NO_LINK_SECRET,
+                status::VENDOR_BBS_NO_LINK_SECRET,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_CREDENTIAL_NOT_FOUND,
+                status::VENDOR_BBS_CREDENTIAL_NOT_FOUND,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_POLICY_VIOLATION,
+                status::VENDOR_BBS_POLICY_VIOLATION,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_MESSAGE_LIMIT_EXCEEDED,
+                status::VENDOR_BBS_MESSAGE_LIMIT_EXCEEDED,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_COMMITMENT_FAILED,
+                status::VENDOR_BBS_COMMITMENT_FAILED,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_INVALID_DISCLOSED_INDEX,
+                status::VENDOR_BBS_INVALID_DISCLOSED_INDEX,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_INVALID_PROVER_BLIND,
+                status::VENDOR_BBS_INVALID_PROVER_BLIND,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_PROOF_FAILED,
+                status::VENDOR_BBS_PROOF_FAILED,
+            ),
+            (
+                Ctap2StatusCode::CTAP2_ERR_VENDOR_TIME_BUDGET_EXCEEDED,
+                status::VENDOR_TIME_BUDGET_EXCEEDED,
+            ),
+        ];
+        for (code, value) in codes {
+            assert_eq!(code as u8, value);
         }
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use cbor::cbor_map;
-    use libtock_unittest::fake::Syscalls;
-
-    const DUMMY_CHANNEL: Channel = Channel::MainHid([0x12, 0x34, 0x56, 0x78]);
-    #[cfg(feature = "vendor_hid")]
-    const VENDOR_CHANNEL: Channel = Channel::VendorHid([0x12, 0x34, 0x56, 0x78]);
 
     #[test]
     fn test_process_cbor_unrelated_input() {
         let mut env = TockEnv::<Syscalls>::default();
         let cbor_bytes = vec![0x01];
-        assert_eq!(process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL), Ok(None));
+        assert_eq!(
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn test_process_cbor_empty_input() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(process_cbor(&mut env, &[], DUMMY_CHANNEL, None), Ok(None));
+    }
+
+    #[test]
+    fn test_process_cbor_vendor_credential_management() {
+        let mut env = TockEnv::<Syscalls>::default();
+        // The CTAP layer handles 0x41 as credential management, so it must pass through.
+        let cbor_bytes = vec![0x41, 0xA1, 0x01, 0x01];
+        assert_eq!(
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
+            Ok(None)
+        );
     }
 
     #[test]
@@ -524,7 +1525,7 @@ mod test {
         let mut env = TockEnv::<Syscalls>::default();
         let cbor_bytes = vec![VENDOR_COMMAND_CONFIGURE];
         assert_eq!(
-            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL),
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
             Err(Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR)
         );
     }
@@ -533,352 +1534,2545 @@ mod test {
     fn test_process_cbor_valid_input() {
         let mut env = TockEnv::<Syscalls>::default();
         let cbor_bytes = vec![VENDOR_COMMAND_UPGRADE_INFO];
-        assert!(process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL)
+        assert!(process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None)
             .unwrap()
             .is_some());
     }
 
     #[test]
-    #[cfg(feature = "vendor_hid")]
-    fn test_process_command_valid_vendor_hid() {
+    fn test_process_cbor_permissions() {
         let mut env = TockEnv::<Syscalls>::default();
-        let cbor_bytes = vec![VENDOR_COMMAND_UPGRADE_INFO];
-        assert!(process_cbor(&mut env, &cbor_bytes, VENDOR_CHANNEL)
-            .unwrap()
-            .is_some());
-        assert!(process_vendor_command(&mut env, &cbor_bytes, VENDOR_CHANNEL).is_some());
+        let mut cbor_bytes = vec![VENDOR_COMMAND_CONFIGURE];
+        let configure_params = cbor_map! {
+            0x03 => Permissions::BBS_PRESENT.bits() as u64,
+        };
+        assert!(cbor_write(configure_params, &mut cbor_bytes).is_ok());
+        assert!(process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None).is_ok());
+        assert_eq!(permissions::get(&mut env), Ok(Permissions::BBS_PRESENT));
+
+        for command in [
+            VENDOR_COMMAND_CONFIGURE,
+            VENDOR_COMMAND_UPGRADE_INFO,
+            VENDOR_COMMAND_AUDIT_LOG,
+        ] {
+            assert_eq!(
+                process_cbor(&mut env, &[command], DUMMY_CHANNEL, None),
+                Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+            );
+        }
+        #[cfg(feature = "bbs")]
+        assert!(process_cbor(&mut env, &[VENDOR_COMMAND_BBS_INFO], DUMMY_CHANNEL, None).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &[0x01], DUMMY_CHANNEL, None),
+            Ok(None)
+        );
     }
 
     #[test]
-    fn test_vendor_configure_parameters() {
-        let dummy_cert = [0xddu8; 20];
-        let dummy_pkey = [0x41u8; EC_FIELD_SIZE];
-        let dummy_link_secret = [0x42u8; LinkSecret::SIZE];
+    fn test_vendor_log() {
+        let mut env = TockEnv::<Syscalls>::default();
+        // A failing command leaves a trace.
+        let response =
+            process_vendor_command(&mut env, &[VENDOR_COMMAND_CONFIGURE], DUMMY_CHANNEL, None);
+        assert_eq!(
+            response,
+            Some(vec![Ctap2StatusCode::CTAP2_ERR_INVALID_CBOR as u8])
+        );
 
-        // Attestation key is too short.
-        let cbor_value = cbor_map! {
-            0x01 => false,
-            0x02 => cbor_map! {
-                0x01 => dummy_cert,
-                0x02 => dummy_pkey[..EC_FIELD_SIZE - 1]
-            }
+        let mut bytes = vec![VENDOR_COMMAND_LOG];
+        let params = cbor_map! {
+            0x01 => true,
+            0x02 => Level::Warn as u64,
         };
+        assert!(cbor_write(params, &mut bytes).is_ok());
+        let response = process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        destructure_cbor_map! {
+            let {
+                0x01 => contents,
+                0x02 => max_level,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        let contents = extract_byte_string(contents.unwrap()).unwrap();
+        assert!(contents.ends_with(b"Command 0x40 failed: CTAP2_ERR_INVALID_CBOR\n"));
+        assert_eq!(max_level, Some(cbor_int!(Level::Info as u64)));
+
+        // The log was cleared, and info lines are now filtered.
+        log_ctap!(&mut env, Level::Info, "filtered");
+        let response = process_vendor_log(&mut env, VendorLogParameters::default());
         assert_eq!(
-            VendorConfigureParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+            response,
+            VendorLogResponse {
+                contents: Vec::new(),
+                max_level: Level::Warn,
+            }
         );
+    }
 
-        // Missing private key
-        let cbor_value = cbor_map! {
-            0x01 => false,
-            0x02 => cbor_map! {
-                0x01 => dummy_cert
-            }
+    #[test]
+    fn test_vendor_crash_report() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let bytes = [VENDOR_COMMAND_CRASH_REPORT];
+        let response = process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None);
+        assert_eq!(response, Ok(Some(vec![0x00, 0xA0])));
+
+        let report = CrashReport::new(Some(0x51), 4096, format_args!("assertion failed"));
+        crash_report::store(&mut env, &report).unwrap();
+        let mut bytes = vec![VENDOR_COMMAND_CRASH_REPORT];
+        assert!(cbor_write(cbor_map! { 0x01 => true }, &mut bytes).is_ok());
+        let response = process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        let expected_cbor = cbor_map! {
+            0x01 => 0x51,
+            0x02 => 4096,
+            0x03 => b"assertion failed".to_vec(),
+        };
+        assert_eq!(cbor_read(&response[1..]), Ok(expected_cbor));
+        assert_eq!(crash_report::get(&mut env), Ok(None));
+    }
+
+    /// Returns certificate bytes with an AAGUID extension, enough for the extension parser.
+    fn certificate_with_aaguid(aaguid: &[u8; AAGUID_LENGTH]) -> Vec<u8> {
+        let mut certificate = vec![
+            0x30, 0x21, 0x06, 0x0B, 0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xE5, 0x1C, 0x01, 0x01,
+            0x04, 0x04, 0x12, 0x04, 0x10,
+        ];
+        certificate.extend_from_slice(aaguid);
+        certificate
+    }
+
+    #[test]
+    fn test_vendor_configure_aaguid() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let custom_aaguid = [0x5A; AAGUID_LENGTH];
+        let dummy_key = [0x41u8; EC_FIELD_SIZE];
+        let params = |aaguid, certificate: Option<Vec<u8>>| VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: certificate.map(|certificate| AttestationMaterial {
+                certificate: Some(certificate),
+                private_key: Some(Secret::from_exposed_secret(dummy_key)),
+                #[cfg(feature = "bbs")]
+                link_secret: None,
+            }),
+            permissions: None,
+            aaguid,
+            min_bundle_version: None,
+            bundle_key: None,
         };
+
+        // A certificate of the firmware AAGUID doesn't match the customer AAGUID.
+        let firmware_aaguid = *env.customization().aaguid();
+        let response = process_vendor_configure(
+            &mut env,
+            params(
+                Some(custom_aaguid),
+                Some(certificate_with_aaguid(&firmware_aaguid)),
+            ),
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+        assert_eq!(metadata::has_custom_aaguid(&mut env), Ok(false));
         assert_eq!(
-            VendorConfigureParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+            env.attestation_store().get(&attestation_store::Id::Batch),
+            Ok(None)
         );
 
-        // Missing certificate
-        let cbor_value = cbor_map! {
-            0x01 => false,
-            0x02 => cbor_map! {
-                0x02 => dummy_pkey
-            }
-        };
+        let certificate = certificate_with_aaguid(&custom_aaguid);
+        let response = process_vendor_configure(
+            &mut env,
+            params(Some(custom_aaguid), Some(certificate)),
+            DUMMY_CHANNEL,
+        )
+        .unwrap();
+        assert!(response.aaguid_programmed && response.cert_programmed);
+        assert_eq!(metadata::aaguid(&mut env), Ok(custom_aaguid));
+
+        // Like the attestation material, the customer AAGUID isn't overwritten.
+        let response = process_vendor_configure(
+            &mut env,
+            params(Some([0xA5; AAGUID_LENGTH]), None),
+            DUMMY_CHANNEL,
+        );
         assert_eq!(
-            VendorConfigureParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+            response.map(|response| response.aaguid_programmed),
+            Ok(true)
         );
+        assert_eq!(metadata::aaguid(&mut env), Ok(custom_aaguid));
+    }
 
-        // Valid
-        let cbor_value = cbor_map! {
-            0x01 => false,
-            0x02 => cbor_map! {
-                0x01 => dummy_cert,
-                0x02 => dummy_pkey,
-                0x03 => dummy_link_secret
-            },
+    #[test]
+    fn test_vendor_configure_aaguid_not_allowed() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.customization_mut().allows_custom_aaguid = false;
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: None,
+            permissions: None,
+            aaguid: Some([0x5A; AAGUID_LENGTH]),
+            min_bundle_version: None,
+            bundle_key: None,
         };
+        let response = process_vendor_configure(&mut env, params, DUMMY_CHANNEL);
         assert_eq!(
-            VendorConfigureParameters::try_from(cbor_value),
-            Ok(VendorConfigureParameters {
-                lockdown: false,
-                attestation_material: Some(AttestationMaterial {
-                    certificate: dummy_cert.to_vec(),
-                    private_key: dummy_pkey,
-                    link_secret: dummy_link_secret,
-                }),
-            })
+            response.map(|response| response.aaguid_programmed),
+            Ok(false)
+        );
+        assert_eq!(
+            metadata::aaguid(&mut env),
+            Ok(*env.customization().aaguid())
         );
     }
 
     #[test]
-    fn test_vendor_upgrade_parameters() {
-        // Missing offset
-        let cbor_value = cbor_map! {
-            0x02 => [0xFF; 0x100],
-            0x03 => [0x44; 32],
+    fn test_vendor_info() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let response = process_cbor(&mut env, &[VENDOR_COMMAND_INFO], DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        let expected_cbor = cbor_map_options! {
+            0x01 => *env.customization().aaguid(),
+            0x02 => false,
+            0x03 => env.customization().certification_level(),
+            0x04 => env.customization().metadata_description(),
+            0x05 => env.firmware_version(),
+            0x06 => if cfg!(feature = "compression") {
+                Some(cbor_array![COMPRESSION_LZSS])
+            } else {
+                None
+            },
         };
+        assert_eq!(cbor_read(&response[1..]), Ok(expected_cbor));
+
+        metadata::provision_aaguid(&mut env, &[0x5A; AAGUID_LENGTH]).unwrap();
         assert_eq!(
-            VendorUpgradeParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+            process_vendor_info(&mut env).map(|response| (response.aaguid, response.custom_aaguid)),
+            Ok(([0x5A; AAGUID_LENGTH], true))
         );
+    }
 
-        // Missing data
-        let cbor_value = cbor_map! {
-            0x01 => 0x1000,
-            0x03 => [0x44; 32],
+    #[cfg(feature = "compression")]
+    fn compressed_command(algorithm: u64, command: &[u8]) -> Vec<u8> {
+        let mut cbor_bytes = vec![VENDOR_COMMAND_COMPRESSED];
+        let compressed_params = cbor_map! {
+            0x01 => algorithm,
+            0x02 => sk_lzss::compress(command),
         };
+        assert!(cbor_write(compressed_params, &mut cbor_bytes).is_ok());
+        cbor_bytes
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_vendor_compressed() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let plain_response = process_cbor(&mut env, &[VENDOR_COMMAND_INFO], DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        let command = compressed_command(COMPRESSION_LZSS, &[VENDOR_COMMAND_INFO]);
+        let response = process_cbor(&mut env, &command, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x01 => compressed,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        let compressed = extract_byte_string(compressed.unwrap()).unwrap();
         assert_eq!(
-            VendorUpgradeParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+            sk_lzss::decompress(&compressed, plain_response.len()),
+            Ok(plain_response)
         );
 
-        // Invalid hash size
-        let cbor_value = cbor_map! {
-            0x01 => 0x1000,
-            0x02 => [0xFF; 0x100],
-            0x03 => [0x44; 33],
+        // Errors of the inner command are compressed too.
+        let command = compressed_command(COMPRESSION_LZSS, &[0x7F]);
+        let response = process_cbor(&mut env, &command, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        let expected_cbor = cbor_map! {
+            0x01 => sk_lzss::compress(&[Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8]),
         };
+        assert_eq!(cbor_read(&response[1..]), Ok(expected_cbor));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_vendor_compressed_invalid() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let command = compressed_command(0x02, &[VENDOR_COMMAND_INFO]);
         assert_eq!(
-            VendorUpgradeParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+            process_cbor(&mut env, &command, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_ALGORITHM)
         );
 
-        // Missing hash
-        let cbor_value = cbor_map! {
-            0x01 => 0x1000,
-            0x02 => [0xFF; 0x100],
-        };
+        let inner = compressed_command(COMPRESSION_LZSS, &[VENDOR_COMMAND_INFO]);
+        let command = compressed_command(COMPRESSION_LZSS, &inner);
         assert_eq!(
-            VendorUpgradeParameters::try_from(cbor_value),
-            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+            process_cbor(&mut env, &command, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
 
-        // Valid
-        let cbor_value = cbor_map! {
-            0x01 => 0x1000,
-            0x02 => [0xFF; 0x100],
-            0x03 => [0x44; 32],
-        };
+        // Decompressing past the maximum message size fails early.
+        let too_large = vec![0x00; env.customization().max_msg_size() + 1];
+        let command = compressed_command(COMPRESSION_LZSS, &too_large);
         assert_eq!(
-            VendorUpgradeParameters::try_from(cbor_value),
-            Ok(VendorUpgradeParameters {
-                offset: 0x1000,
-                data: vec![0xFF; 0x100],
-                hash: [0x44; 32],
-            })
+            process_cbor(&mut env, &command, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
     }
 
+    #[cfg(feature = "compression")]
     #[test]
-    fn test_deserialize_vendor_upgrade_info() {
+    fn test_vendor_compressed_permissions() {
         let mut env = TockEnv::<Syscalls>::default();
-        let cbor_bytes = [VENDOR_COMMAND_UPGRADE_INFO];
-        assert!(process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL)
+        permissions::set(&mut env, Permissions::BBS_PRESENT, false).unwrap();
+        let command = compressed_command(COMPRESSION_LZSS, &[VENDOR_COMMAND_AUDIT_LOG]);
+        let response = process_cbor(&mut env, &command, DUMMY_CHANNEL, None)
             .unwrap()
-            .is_some());
+            .unwrap();
+        let expected_cbor = cbor_map! {
+            0x01 => sk_lzss::compress(&[Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]),
+        };
+        assert_eq!(cbor_read(&response[1..]), Ok(expected_cbor));
     }
 
     #[test]
-    fn test_vendor_configure() {
+    fn test_vendor_configure_permissions_locked() {
         let mut env = TockEnv::<Syscalls>::default();
-
-        // Nothing should be configured at the beginning
-        let response = process_vendor_configure(
-            &mut env,
-            VendorConfigureParameters {
-                lockdown: false,
-                attestation_material: None,
-            },
-            DUMMY_CHANNEL,
-        );
+        lockdown::raise(&mut env, LockdownLevel::Config).unwrap();
+        let mut cbor_bytes = vec![VENDOR_COMMAND_CONFIGURE];
+        let configure_params = cbor_map! {
+            0x03 => Permissions::BBS_PRESENT.bits() as u64,
+        };
+        assert!(cbor_write(configure_params, &mut cbor_bytes).is_ok());
         assert_eq!(
-            response,
-            Ok(VendorConfigureResponse {
-                cert_programmed: false,
-                pkey_programmed: false,
-                link_secret_programmed: false,
-            })
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
         );
+        assert_eq!(permissions::get(&mut env), Ok(Permissions::ALL));
+    }
 
-        // Inject dummy values
-        let dummy_key = [0x41u8; EC_FIELD_SIZE];
-        let dummy_cert = [0xddu8; 20];
-        let dummy_link_secret = [0x42u8; LinkSecret::SIZE];
-        let response = process_vendor_configure(
-            &mut env,
-            VendorConfigureParameters {
-                lockdown: false,
-                attestation_material: Some(AttestationMaterial {
-                    certificate: dummy_cert.to_vec(),
-                    private_key: dummy_key,
-                    link_secret: dummy_link_secret,
-                }),
-            },
-            DUMMY_CHANNEL,
-        );
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_configure_failure_keeps_permissions() {
+        let mut env = TockEnv::<Syscalls>::default();
+        // Lockdown is refused without attestation material, so the permissions don't change.
+        let mut cbor_bytes = vec![VENDOR_COMMAND_CONFIGURE];
+        let configure_params = cbor_map! {
+            0x01 => LockdownLevel::Config as u64,
+            0x03 => Permissions::BBS_PRESENT.bits() as u64,
+        };
+        assert!(cbor_write(configure_params, &mut cbor_bytes).is_ok());
         assert_eq!(
-            response,
-            Ok(VendorConfigureResponse {
-                cert_programmed: true,
-                pkey_programmed: true,
-                link_secret_programmed: true,
-            })
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED)
         );
+        assert_eq!(permissions::get(&mut env), Ok(Permissions::ALL));
+    }
+
+    #[test]
+    fn test_vendor_configure_overrides_unauthenticated() {
+        let mut env = TockEnv::<Syscalls>::default();
+        rollback::raise(&mut env, 5).unwrap();
+        let mut cbor_bytes = vec![VENDOR_COMMAND_CONFIGURE];
+        let configure_params = cbor_map! {
+            0x03 => Permissions::BBS_PRESENT.bits() as u64,
+            0x05 => 4,
+        };
+        assert!(cbor_write(configure_params, &mut cbor_bytes).is_ok());
         assert_eq!(
-            env.attestation_store().get(&attestation_store::Id::Batch),
-            Ok(Some(Attestation {
-                private_key: Secret::from_exposed_secret(dummy_key),
-                certificate: dummy_cert.to_vec(),
-                link_secret: LinkSecret::from_bytes(dummy_link_secret),
-            }))
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
         );
+        assert_eq!(rollback::min_version(&mut env), Ok(5));
+        // Nothing else of the refused configure is applied.
+        assert_eq!(permissions::get(&mut env), Ok(Permissions::ALL));
 
-        // Try to inject other dummy values and check that initial values are retained.
-        let other_dummy_key = [0x44u8; EC_FIELD_SIZE];
-        let response = process_vendor_configure(
-            &mut env,
-            VendorConfigureParameters {
-                lockdown: false,
-                attestation_material: Some(AttestationMaterial {
-                    certificate: dummy_cert.to_vec(),
-                    private_key: other_dummy_key,
-                    link_secret: dummy_link_secret,
-                }),
-            },
-            DUMMY_CHANNEL,
-        );
+        // The key of encrypted bundles must not travel in the clear.
+        let mut cbor_bytes = vec![VENDOR_COMMAND_CONFIGURE];
+        let configure_params = cbor_map! {
+            0x03 => Permissions::BBS_PRESENT.bits() as u64,
+            0x06 => [0x55; AES_KEY_SIZE],
+        };
+        assert!(cbor_write(configure_params, &mut cbor_bytes).is_ok());
         assert_eq!(
-            response,
-            Ok(VendorConfigureResponse {
-                cert_programmed: true,
-                pkey_programmed: true,
-                link_secret_programmed: true,
-            })
+            process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
         );
+        assert_eq!(env.store().find(bundle_key::STORAGE_KEY), Ok(None));
+        assert_eq!(permissions::get(&mut env), Ok(Permissions::ALL));
+    }
+
+    #[test]
+    #[cfg(feature = "vendor_hid")]
+    fn test_process_command_valid_vendor_hid() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let cbor_bytes = vec![VENDOR_COMMAND_UPGRADE_INFO];
+        assert!(process_cbor(&mut env, &cbor_bytes, VENDOR_CHANNEL, None)
+            .unwrap()
+            .is_some());
+        assert!(process_vendor_command(&mut env, &cbor_bytes, VENDOR_CHANNEL, None).is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "vendor_hid")]
+    fn test_process_command_other_channels_with_vendor_hid() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let cbor_bytes = vec![VENDOR_COMMAND_UPGRADE_INFO];
+        assert!(process_vendor_command(&mut env, &cbor_bytes, DUMMY_CHANNEL, None).is_none());
+        #[cfg(feature = "ccid")]
+        assert!(process_vendor_command(&mut env, &cbor_bytes, Channel::Ccid, None).is_none());
+    }
+
+    #[test]
+    fn test_vendor_audit_log() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let cbor_bytes = [VENDOR_COMMAND_AUDIT_LOG];
+        let response = process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x01 => now,
+                0x02 => entries,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        assert!(Timestamp::try_from(now.unwrap()).is_ok());
+        assert_eq!(extract_array(entries.unwrap()).unwrap(), vec![]);
+    }
+
+    #[cfg(feature = "heap_stats")]
+    #[test]
+    fn test_vendor_heap_stats() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let cbor_bytes = [VENDOR_COMMAND_HEAP_STATS];
+        let response = process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x01 => current,
+                0x02 => peak,
+                0x03 => free,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        // The host allocator isn't instrumented.
+        assert_eq!(current, Some(cbor_int!(0)));
+        assert_eq!(peak, Some(cbor_int!(0)));
+        assert_eq!(free, None);
+    }
+
+    #[test]
+    fn test_vendor_store_compact() {
+        let mut env = TockEnv::<Syscalls>::default();
+        // Overwriting a value leaves garbage that only compaction reclaims.
+        while store_capacity(&mut env).unwrap().immediate >= 1024 {
+            env.store().insert(63, &[0x55; 512]).unwrap();
+        }
+        let params = VendorStoreCompactParameters {
+            length: Some(1024),
+            max_pages: None,
+        };
+        let response =
+            process_vendor_store_compact(&mut env, params, &mut CancellationToken::never())
+                .unwrap();
+        assert_eq!(response.pages, 1);
+        assert!(response.after.immediate > response.before.immediate);
+        assert_eq!(response.after.remaining, response.before.remaining);
+
+        let params = VendorStoreCompactParameters {
+            length: Some(1024),
+            max_pages: Some(MAX_COMPACTION_PAGES),
+        };
+        let response =
+            process_vendor_store_compact(&mut env, params, &mut CancellationToken::never())
+                .unwrap();
+        assert!(response.done);
+        assert!(response.after.immediate >= 1024);
+
+        // Once done, nothing is compacted.
+        let mut cbor_bytes = vec![VENDOR_COMMAND_STORE_COMPACT];
+        assert!(cbor_write(cbor_map! { 0x01 => 1024 }, &mut cbor_bytes).is_ok());
+        let response = process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x05 => pages,
+                0x06 => done,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        assert_eq!(pages, Some(cbor_int!(0)));
+        assert_eq!(done, Some(cbor::Value::from(true)));
+    }
+
+    #[test]
+    fn test_vendor_store_diagnostics() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.store().insert(63, &[0x55; 8]).unwrap();
+        let response = process_vendor_store_diagnostics(&mut env);
+        assert!(!response.corrupted);
+        assert!(response.entries.unwrap() >= 1);
+        let (used, total) = response.lifetime.unwrap();
+        assert!(used < total);
+
+        // Diagnostics need no permission, since those are stored in the store they diagnose.
+        permissions::set(&mut env, Permissions::BBS_PRESENT, false).unwrap();
+        let response = process_cbor(
+            &mut env,
+            &[VENDOR_COMMAND_STORE_DIAGNOSTICS],
+            DUMMY_CHANNEL,
+            None,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+    }
+
+    /// PIN state after the given number of failed PINs, that accepts no token.
+    struct PinMismatches(u8);
+
+    impl PinUvAuthCheck for PinMismatches {
+        fn check_pin_uv_auth(
+            &self,
+            _permission: PinPermission,
+            _hmac_contents: &[u8],
+            _pin_uv_auth_param: &[u8],
+            _pin_uv_auth_protocol: PinUvAuthProtocol,
+        ) -> Result<(), Ctap2StatusCode> {
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        }
+
+        fn consecutive_pin_mismatches(&self) -> u8 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_vendor_stats() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let stats = |env: &mut TockEnv<Syscalls>, pin_uv_auth: Option<&dyn PinUvAuthCheck>| {
+            let response = process_cbor(env, &[VENDOR_COMMAND_STATS], DUMMY_CHANNEL, pin_uv_auth)
+                .unwrap()
+                .unwrap();
+            assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+            cbor_read(&response[1..]).unwrap()
+        };
+
+        assert_eq!(
+            stats(&mut env, None),
+            cbor_map! {
+                0x01 => env.customization().max_pin_retries() as u64,
+            }
+        );
+        assert_eq!(
+            stats(&mut env, Some(&PinMismatches(2))),
+            cbor_map! {
+                0x01 => env.customization().max_pin_retries() as u64,
+                0x02 => false,
+            }
+        );
+        env.customization_mut().pin_mismatches_before_power_cycle = 2;
+        assert_eq!(
+            stats(&mut env, Some(&PinMismatches(2))),
+            cbor_map! {
+                0x01 => env.customization().max_pin_retries() as u64,
+                0x02 => true,
+            }
+        );
+    }
+
+    #[test]
+    fn test_deserialize_vendor_upgrade_info() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let cbor_bytes = [VENDOR_COMMAND_UPGRADE_INFO];
+        assert!(process_cbor(&mut env, &cbor_bytes, DUMMY_CHANNEL, None)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_vendor_configure() {
+        let mut env = TockEnv::<Syscalls>::default();
+
+        // Nothing should be configured at the beginning
+        let response = process_vendor_configure(
+            &mut env,
+            VendorConfigureParameters {
+                lockdown: LockdownLevel::None,
+                attestation_material: None,
+                permissions: None,
+                aaguid: None,
+                min_bundle_version: None,
+                bundle_key: None,
+            },
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(
+            response,
+            Ok(VendorConfigureResponse {
+                cert_programmed: false,
+                pkey_programmed: false,
+                link_secret_programmed: false,
+                lockdown_level: LockdownLevel::None,
+                permissions: Permissions::ALL,
+                aaguid_programmed: false,
+            })
+        );
+
+        // Inject dummy values, without a link secret first.
+        let dummy_key = [0x41u8; EC_FIELD_SIZE];
+        let dummy_cert = [0xddu8; 20];
+        #[cfg(feature = "bbs")]
+        let dummy_link_secret = [0x42u8; LinkSecret::SIZE];
+        let response = process_vendor_configure(
+            &mut env,
+            VendorConfigureParameters {
+                lockdown: LockdownLevel::None,
+                attestation_material: Some(AttestationMaterial {
+                    certificate: Some(dummy_cert.to_vec()),
+                    private_key: Some(Secret::from_exposed_secret(dummy_key)),
+                    #[cfg(feature = "bbs")]
+                    link_secret: None,
+                }),
+                permissions: None,
+                aaguid: None,
+                min_bundle_version: None,
+                bundle_key: None,
+            },
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(
+            response,
+            Ok(VendorConfigureResponse {
+                cert_programmed: true,
+                pkey_programmed: true,
+                link_secret_programmed: false,
+                lockdown_level: LockdownLevel::None,
+                permissions: Permissions::ALL,
+                aaguid_programmed: false,
+            })
+        );
+        assert_eq!(
+            env.attestation_store().get(&attestation_store::Id::Batch),
+            Ok(Some(Attestation {
+                private_key: Secret::from_exposed_secret(dummy_key),
+                certificate: dummy_cert.to_vec(),
+            }))
+        );
+
+        // Try to inject other dummy values and check that initial values are retained, while the
+        // missing link secret is added.
+        let other_dummy_key = [0x44u8; EC_FIELD_SIZE];
+        let response = process_vendor_configure(
+            &mut env,
+            VendorConfigureParameters {
+                lockdown: LockdownLevel::None,
+                attestation_material: Some(AttestationMaterial {
+                    certificate: Some(dummy_cert.to_vec()),
+                    private_key: Some(Secret::from_exposed_secret(other_dummy_key)),
+                    #[cfg(feature = "bbs")]
+                    link_secret: Some(Secret::from_exposed_secret(dummy_link_secret)),
+                }),
+                permissions: None,
+                aaguid: None,
+                min_bundle_version: None,
+                bundle_key: None,
+            },
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(
+            response,
+            Ok(VendorConfigureResponse {
+                cert_programmed: true,
+                pkey_programmed: true,
+                link_secret_programmed: cfg!(feature = "bbs"),
+                lockdown_level: LockdownLevel::None,
+                permissions: Permissions::ALL,
+                aaguid_programmed: false,
+            })
+        );
+        assert_eq!(
+            env.attestation_store().get(&attestation_store::Id::Batch),
+            Ok(Some(Attestation {
+                private_key: Secret::from_exposed_secret(dummy_key),
+                certificate: dummy_cert.to_vec(),
+            }))
+        );
+        #[cfg(feature = "bbs")]
+        assert_eq!(
+            env.attestation_store().get_link_secret(),
+            Ok(Some(LinkSecret::from_bytes(dummy_link_secret)))
+        );
+
+        // Now try to lock the device, but that is currently not supported.
+        let response = process_vendor_configure(
+            &mut env,
+            VendorConfigureParameters {
+                lockdown: LockdownLevel::Full,
+                attestation_material: None,
+                permissions: None,
+                aaguid: None,
+                min_bundle_version: None,
+                bundle_key: None,
+            },
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(
+            response,
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        assert_eq!(lockdown::get(&mut env), Ok(LockdownLevel::None));
+
+        // Locking provisioning doesn't need firmware protection.
+        let response = process_vendor_configure(
+            &mut env,
+            VendorConfigureParameters {
+                lockdown: LockdownLevel::Config,
+                attestation_material: None,
+                permissions: None,
+                aaguid: None,
+                min_bundle_version: None,
+                bundle_key: None,
+            },
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(
+            response.map(|response| response.lockdown_level),
+            Ok(LockdownLevel::Config)
+        );
+        let response = process_vendor_configure(
+            &mut env,
+            VendorConfigureParameters {
+                lockdown: LockdownLevel::None,
+                attestation_material: Some(AttestationMaterial {
+                    certificate: Some(dummy_cert.to_vec()),
+                    private_key: Some(Secret::from_exposed_secret(dummy_key)),
+                    #[cfg(feature = "bbs")]
+                    link_secret: None,
+                }),
+                permissions: None,
+                aaguid: None,
+                min_bundle_version: None,
+                bundle_key: None,
+            },
+            DUMMY_CHANNEL,
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+        let params = VendorSecureChannelParameters::Setup;
+        assert_eq!(
+            process_vendor_secure_channel(
+                &mut env,
+                params,
+                &[0x04; PUBLIC_KEY_SIZE],
+                DUMMY_CHANNEL
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_vendor_secure_channel() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let vendor_key = EcdhSk::<TockEnv<Syscalls>>::random(env.rng());
+        let vendor_public_key = encode_public_key::<TockEnv<Syscalls>>(&vendor_key.public_key());
+
+        // Configuring needs a session.
+        let params = VendorSecureChannelParameters::Configure {
+            ciphertext: vec![0x55; 32],
+            mac: [0x66; HASH_SIZE],
+        };
+        assert_eq!(
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+
+        // Open a session, and derive its keys like the vendor host.
+        let params = VendorSecureChannelParameters::Setup;
+        let response =
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL)
+                .unwrap()
+                .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x01 => device_public_key,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        let device_public_key = <[u8; PUBLIC_KEY_SIZE]>::try_from(
+            extract_byte_string(device_public_key.unwrap()).unwrap(),
+        )
+        .unwrap();
+        let device_key = decode_public_key::<TockEnv<Syscalls>>(&device_public_key).unwrap();
+        let mut shared_secret = [0; EC_FIELD_SIZE];
+        vendor_key
+            .diffie_hellman(&device_key)
+            .raw_secret_bytes(&mut shared_secret);
+        let mut host =
+            SecureChannel::derive::<TockEnv<Syscalls>>(&shared_secret, &device_public_key);
+
+        // Send the attestation material encrypted.
+        let dummy_key = [0x41u8; EC_FIELD_SIZE];
+        let dummy_cert = [0xddu8; 20];
+        let mut configure_bytes = Vec::new();
+        let configure_params = cbor_map! {
+            0x02 => cbor_map! {
+                0x01 => dummy_cert,
+                0x02 => dummy_key,
+            },
+        };
+        assert!(cbor_write(configure_params, &mut configure_bytes).is_ok());
+        let (ciphertext, mac) = host.seal(&mut env, &configure_bytes);
+        let params = VendorSecureChannelParameters::Configure { ciphertext, mac };
+        let response =
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL)
+                .unwrap()
+                .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
         assert_eq!(
             env.attestation_store().get(&attestation_store::Id::Batch),
             Ok(Some(Attestation {
                 private_key: Secret::from_exposed_secret(dummy_key),
                 certificate: dummy_cert.to_vec(),
-                link_secret: LinkSecret::from_bytes(dummy_link_secret),
             }))
         );
 
-        // Now try to lock the device, but that is currently not supported.
-        let response = process_vendor_configure(
-            &mut env,
-            VendorConfigureParameters {
-                lockdown: true,
-                attestation_material: None,
-            },
-            DUMMY_CHANNEL,
+        // A tampered message closes the session.
+        let (mut ciphertext, mac) = host.seal(&mut env, &configure_bytes);
+        ciphertext[0] ^= 0x01;
+        let params = VendorSecureChannelParameters::Configure { ciphertext, mac };
+        assert_eq!(
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert!(env.secure_channel.is_none());
+
+        // Once provisioned, the device signs its session key. Teardown closes a session too.
+        let params = VendorSecureChannelParameters::Setup;
+        let response =
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL)
+                .unwrap()
+                .unwrap();
+        destructure_cbor_map! {
+            let {
+                0x02 => signature,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        assert!(signature.is_some());
+        let params = VendorSecureChannelParameters::Teardown;
+        assert_eq!(
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL),
+            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
+        );
+        assert!(env.secure_channel.is_none());
+    }
+
+    #[test]
+    fn test_vendor_secure_channel_admin() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let vendor_key = EcdhSk::<TockEnv<Syscalls>>::random(env.rng());
+        let vendor_public_key = encode_public_key::<TockEnv<Syscalls>>(&vendor_key.public_key());
+        lockdown::raise(&mut env, LockdownLevel::Config).unwrap();
+
+        // After lockdown, only admins open sessions.
+        assert_eq!(
+            permissions::set(&mut env, Permissions::BBS_PRESENT, false),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        let response = process_vendor_secure_channel(
+            &mut env,
+            VendorSecureChannelParameters::Setup,
+            &vendor_public_key,
+            DUMMY_CHANNEL,
+        )
+        .unwrap()
+        .unwrap();
+        destructure_cbor_map! {
+            let {
+                0x01 => device_public_key,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        let device_public_key = <[u8; PUBLIC_KEY_SIZE]>::try_from(
+            extract_byte_string(device_public_key.unwrap()).unwrap(),
+        )
+        .unwrap();
+        let device_key = decode_public_key::<TockEnv<Syscalls>>(&device_public_key).unwrap();
+        let mut shared_secret = [0; EC_FIELD_SIZE];
+        vendor_key
+            .diffie_hellman(&device_key)
+            .raw_secret_bytes(&mut shared_secret);
+        let mut host =
+            SecureChannel::derive::<TockEnv<Syscalls>>(&shared_secret, &device_public_key);
+
+        // Admins change permissions through the session, here dropping their own. They also
+        // lower the minimum bundle version to recover from a broken release, and provision the
+        // key of encrypted bundles.
+        rollback::raise(&mut env, 5).unwrap();
+        let mut configure_bytes = Vec::new();
+        let configure_params = cbor_map! {
+            0x03 => Permissions::BBS_PRESENT.bits() as u64,
+            0x05 => 4,
+            0x06 => [0x55; AES_KEY_SIZE],
+        };
+        assert!(cbor_write(configure_params, &mut configure_bytes).is_ok());
+        let (ciphertext, mac) = host.seal(&mut env, &configure_bytes);
+        let params = VendorSecureChannelParameters::Configure { ciphertext, mac };
+        assert!(
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL)
+                .is_ok()
+        );
+        assert_eq!(permissions::get(&mut env), Ok(Permissions::BBS_PRESENT));
+        assert_eq!(rollback::min_version(&mut env), Ok(4));
+        assert_eq!(
+            env.store().find(bundle_key::STORAGE_KEY),
+            Ok(Some(vec![0x55; AES_KEY_SIZE]))
+        );
+
+        let params = VendorSecureChannelParameters::Setup;
+        assert_eq!(
+            process_vendor_secure_channel(&mut env, params, &vendor_public_key, DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_vendor_lockdown_prerequisites() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let attestation = Attestation {
+            private_key: Secret::from_exposed_secret([0x41; EC_FIELD_SIZE]),
+            certificate: vec![0xdd; 20],
+        };
+        env.attestation_store()
+            .set(&attestation_store::Id::Batch, Some(&attestation))
+            .unwrap();
+        let params = || VendorConfigureParameters {
+            lockdown: LockdownLevel::Config,
+            attestation_material: None,
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+
+        #[cfg(feature = "bbs")]
+        {
+            assert_eq!(
+                process_vendor_configure(&mut env, params(), DUMMY_CHANNEL),
+                Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED)
+            );
+            let link_secret = LinkSecret::from_bytes([0x42; LinkSecret::SIZE]);
+            env.attestation_store()
+                .set_link_secret(Some(&link_secret))
+                .unwrap();
+        }
+
+        // Without upgrade storage, the firmware can't be checked.
+        env.disable_upgrade_storage();
+        assert_eq!(
+            process_vendor_configure(&mut env, params(), DUMMY_CHANNEL),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_LOCKDOWN_REFUSED)
+        );
+        assert_eq!(lockdown::get(&mut env), Ok(LockdownLevel::None));
+    }
+
+    #[test]
+    fn test_vendor_upgrade_lockdown() {
+        let mut env = TockEnv::<Syscalls>::default();
+        lockdown::raise(&mut env, LockdownLevel::ConfigAndUpgrade).unwrap();
+        let data = vec![0xFF; 0x1000];
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0,
+                data,
+                hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED));
+    }
+
+    #[test]
+    fn test_vendor_upgrade() {
+        // The test partition storage has size 0x40000.
+        // The test metadata storage has size 0x1000.
+        // The test identifier matches partition B.
+        let mut env = TockEnv::<Syscalls>::default();
+
+        const METADATA_LEN: usize = 0x1000;
+        let metadata = vec![0xFF; METADATA_LEN];
+        let metadata_hash = Sha::<TockEnv<Syscalls>>::digest(&metadata);
+        let data = vec![0xFF; 0x1000];
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+
+        // Write to partition.
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0x20000,
+                data: data.clone(),
+                hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Ok(()));
+
+        // TockEnv doesn't check the metadata, test its parser in your Env.
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0,
+                data: metadata.clone(),
+                hash: metadata_hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Ok(()));
+
+        // TockEnv doesn't check the metadata, test its parser in your Env.
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: METADATA_LEN,
+                data: data.clone(),
+                hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Ok(()));
+
+        // Write metadata of a wrong size.
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0,
+                data: metadata[..METADATA_LEN - 1].to_vec(),
+                hash: metadata_hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+
+        // Write outside of the partition.
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0x41000,
+                data: data.clone(),
+                hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+
+        // Write a bad hash.
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0x20000,
+                data,
+                hash: [0xEE; 32],
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+    }
+
+    #[test]
+    fn test_vendor_upgrade_rollback() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let write = |env: &mut TockEnv<Syscalls>, offset, data: Vec<u8>| {
+            let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+            process_vendor_upgrade(
+                env,
+                VendorUpgradeParameters {
+                    offset,
+                    data,
+                    hash,
+                    encrypted: false,
+                },
+                &mut CancellationToken::never(),
+            )
+        };
+        let metadata = |version| {
+            let mut metadata = vec![0xFF; 0x1000];
+            LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], version);
+            metadata
+        };
+
+        // Committing the last chunk raises the minimum to the bundle version.
+        assert_eq!(write(&mut env, 0, metadata(7)), Ok(()));
+        assert_eq!(rollback::min_version(&mut env), Ok(0));
+        assert_eq!(write(&mut env, 0x40000, vec![0xFF; 0x1000]), Ok(()));
+        assert_eq!(rollback::min_version(&mut env), Ok(7));
+
+        // Older bundles are refused, the same version is accepted again.
+        assert_eq!(
+            write(&mut env, 0, metadata(6)),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        assert_eq!(write(&mut env, 0, metadata(7)), Ok(()));
+    }
+
+    #[test]
+    fn test_vendor_upgrade_resets_boot_status() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let mut metadata = vec![0xFF; 0x1000];
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 1);
+        // The host presets a confirmed image, whose boot attempts are used up.
+        metadata[BOOT_STATUS_OFFSET..][..BOOT_STATUS_LENGTH].fill(0x00);
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&metadata);
+        let params = VendorUpgradeParameters {
+            offset: 0,
+            data: metadata,
+            hash,
+            encrypted: false,
+        };
+        assert_eq!(
+            process_vendor_upgrade(&mut env, params, &mut CancellationToken::never()),
+            Ok(())
+        );
+        let upgrade_storage = env.upgrade_storage().unwrap();
+        upgrade_storage.flush();
+        let status = upgrade_storage.bundle_status();
+        assert_eq!(status.version, Some(1));
+        assert_eq!(status.boot_attempts, 0);
+        assert!(!status.confirmed);
+        assert!(!status.rollback_requested);
+    }
+
+    #[test]
+    fn test_vendor_upgrade_encrypted() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let mut metadata = vec![0xFF; 0x1000];
+        metadata[..HASH_SIZE].copy_from_slice(&[0x44; HASH_SIZE]);
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 1);
+        let firmware = vec![0x33; 0x1000];
+        let send = |env: &mut TockEnv<Syscalls>, offset, data: Vec<u8>, encrypted| {
+            let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+            process_vendor_upgrade(
+                env,
+                VendorUpgradeParameters {
+                    offset,
+                    data,
+                    hash,
+                    encrypted,
+                },
+                &mut CancellationToken::never(),
+            )
+        };
+
+        // The IV is derived from the metadata, which travels in the clear.
+        assert_eq!(
+            send(&mut env, 0, metadata.clone(), true),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        assert_eq!(send(&mut env, 0, metadata, false), Ok(()));
+
+        // Without a key, encrypted chunks are refused.
+        assert_eq!(
+            send(&mut env, 0x1000, firmware.clone(), true),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+
+        let key = Secret::from_exposed_secret([0x55; AES_KEY_SIZE]);
+        bundle_key::set(&mut env, &key, true).unwrap();
+        // Counter mode encrypts like it decrypts.
+        let iv = bundle_key::bundle_iv::<TockEnv<Syscalls>>(&[0x44; HASH_SIZE]);
+        let mut ciphertext = firmware.clone();
+        bundle_key::decrypt(&mut env, &iv, 0x1000, &mut ciphertext).unwrap();
+        assert_ne!(ciphertext, firmware);
+        assert_eq!(send(&mut env, 0x1000, ciphertext, true), Ok(()));
+        let upgrade_storage = env.upgrade_storage().unwrap();
+        upgrade_storage.flush();
+        assert_eq!(
+            upgrade_storage.read_partition(0x1000, firmware.len()),
+            Ok(&firmware[..])
+        );
+    }
+
+    #[test]
+    fn test_vendor_delta_upgrade() {
+        const PARTITION_LENGTH: usize = 0x41000;
+        let mut env = TockEnv::<Syscalls>::default();
+        let mut old = vec![0xFF; PARTITION_LENGTH];
+        LittleEndian::write_u64(&mut old[METADATA_SIGN_OFFSET..][..8], 0);
+        for (i, byte) in old[0x1000..0x3000].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        env.upgrade_storage().unwrap().set_running_partition(&old);
+        let mut new = old.clone();
+        LittleEndian::write_u64(&mut new[METADATA_SIGN_OFFSET..][..8], 3);
+        new[0x2000] = 0x42;
+
+        // A single record, that diffs the whole partition.
+        let mut patch = (PARTITION_LENGTH as u32).to_le_bytes().to_vec();
+        patch.extend_from_slice(&(PARTITION_LENGTH as u32).to_le_bytes());
+        patch.extend_from_slice(&[0; 8]);
+        patch.extend(
+            new.iter()
+                .zip(&old)
+                .map(|(new, old)| new.wrapping_sub(*old)),
+        );
+        let send = |env: &mut TockEnv<Syscalls>, offset: usize| {
+            let data = patch[offset..]
+                .iter()
+                .take(0x1000)
+                .cloned()
+                .collect::<Vec<u8>>();
+            let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+            process_vendor_delta_upgrade(
+                env,
+                VendorUpgradeParameters {
+                    offset,
+                    data,
+                    hash,
+                    encrypted: false,
+                },
+                &mut CancellationToken::never(),
+            )
+        };
+
+        assert_eq!(send(&mut env, 0), Ok(()));
+        // Chunks must arrive in order.
+        assert_eq!(
+            send(&mut env, 0x2000),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        assert_eq!(
+            send(&mut env, 0x1000),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        for offset in (0..patch.len()).step_by(0x1000) {
+            assert_eq!(send(&mut env, offset), Ok(()));
+        }
+        assert!(env.delta_patch.is_none());
+        let upgrade_storage = env.upgrade_storage().unwrap();
+        assert_eq!(
+            upgrade_storage.read_partition(0, PARTITION_LENGTH),
+            Ok(&new[..])
+        );
+        assert_eq!(rollback::min_version(&mut env), Ok(3));
+    }
+
+    #[test]
+    fn test_vendor_upgrade_no_second_partition() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.disable_upgrade_storage();
+
+        let data = vec![0xFF; 0x1000];
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+        let response = process_vendor_upgrade(
+            &mut env,
+            VendorUpgradeParameters {
+                offset: 0,
+                data,
+                hash,
+                encrypted: false,
+            },
+            &mut CancellationToken::never(),
+        );
+        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND));
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_flow() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+
+        let proof = request_proof(&mut env, &credential, &[1]);
+        assert!(verify_proof(&proof, &credential, &[1]));
+        // The proof doesn't hold for other disclosures.
+        assert!(!verify_proof(&proof, &credential, &[0]));
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_fixtures() {
+        // All fixtures of the generator, with their message counts, disclosures and headers.
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/third_party/bbs/fixtures");
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path
+                .extension()
+                .map_or(true, |extension| extension != "json")
+            {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path).unwrap();
+            let fixture: serde_json::Value = serde_json::from_str(&contents).unwrap();
+            let text = |key: &str| fixture[key].as_str().unwrap().as_bytes().to_vec();
+            let messages = fixture["messages"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|message| message.as_str().unwrap().as_bytes().to_vec())
+                .collect::<Vec<_>>();
+            let disclosed_indexes = fixture["disclosedIndexes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|index| index.as_u64().unwrap() as usize)
+                .collect::<Vec<_>>();
+
+            let mut env = TockEnv::<Syscalls>::default();
+            provision_link_secret_with(&mut env, json_hex(&fixture, &["linkSecret"]));
+            let public_key = json_hex(&fixture, &["signerKeyPair", "publicKey"]);
+            let indexes = disclosed_indexes
+                .iter()
+                .map(|&index| index as u64)
+                .collect::<Vec<_>>();
+            let params = cbor_map! {
+                0x01 => public_key.clone(),
+                0x02 => cbor_array_vec!(messages.clone()),
+                0x03 => json_hex(&fixture, &["signature"]),
+                0x04 => text("header"),
+                0x05 => text("presentationHeader"),
+                0x06 => cbor_array_vec!(indexes),
+                0x07 => json_hex(&fixture, &["proverBlindFactor"]),
+            };
+            destructure_cbor_map! {
+                let {
+                    0x01 => proof,
+                } = vendor_command(&mut env, VENDOR_COMMAND_BBS_PROOF, Some(params));
+            }
+            let proof = extract_byte_string(proof.unwrap()).unwrap();
+
+            let disclosed_messages = disclosed_indexes
+                .iter()
+                .map(|&index| messages[index].clone())
+                .collect::<Vec<_>>();
+            let verified = verify_presentation(
+                &public_key,
+                &proof,
+                &text("header"),
+                &text("presentationHeader"),
+                &disclosed_messages,
+                &disclosed_indexes,
+            );
+            assert!(verified, "{}", path.display());
+        }
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_flow_with_stored_blind() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential_for(&mut env, messages, Some(b"issuer"));
+        // The blind never left the device.
+        assert_eq!(credential.secret_prover_blind, None);
+
+        let proof = request_proof(&mut env, &credential, &[1]);
+        assert!(verify_proof(&proof, &credential, &[1]));
+
+        // Without a commitment for that issuer, there is no blind.
+        let other_issuer = Credential {
+            issuer_id: Some(b"other".to_vec()),
+            ..credential
+        };
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&other_issuer, &[1]), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_CREDENTIAL_NOT_FOUND)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_presentation_context() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+
+        let request_context = |env: &mut TockEnv<Syscalls>, salt: &[u8]| {
+            let mut params = extract_map(proof_params(&credential, &[1])).unwrap();
+            params.push((cbor_int!(0x09), cbor_bytes!(salt.to_vec())));
+            destructure_cbor_map! {
+                let {
+                    0x01 => proof,
+                    0x02 => context,
+                } = vendor_command(env, VENDOR_COMMAND_BBS_PROOF, Some(cbor::Value::map(params)));
+            }
+            (
+                extract_byte_string(proof.unwrap()).unwrap(),
+                extract_byte_string(context.unwrap()).unwrap(),
+            )
+        };
+        let (proof, context) = request_context(&mut env, &[0x55; 32]);
+        let mut presentation_header = BBS_PRESENTATION_HEADER.to_vec();
+        presentation_header.extend_from_slice(&context);
+        assert!(verify_proof_with_header(
+            &proof,
+            &credential,
+            &[1],
+            &presentation_header
+        ));
+        assert!(!verify_proof(&proof, &credential, &[1]));
+
+        // The context is stable per salt.
+        let (_, same_context) = request_context(&mut env, &[0x55; 32]);
+        assert_eq!(same_context, context);
+        let (_, other_context) = request_context(&mut env, &[0x66; 32]);
+        assert_ne!(other_context, context);
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_prehash_presentation_header() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let credential = issue_credential(&mut env, vec![b"name=Alice".to_vec()]);
+
+        let mut params = extract_map(proof_params(&credential, &[0])).unwrap();
+        params.push((cbor_int!(0x09), cbor_bytes!(vec![0x55; 32])));
+        params.push((cbor_int!(0x0C), cbor::Value::from(true)));
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+                0x02 => context,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_PROOF, Some(cbor::Value::map(params)));
+        }
+        let proof = extract_byte_string(proof.unwrap()).unwrap();
+        let mut presentation_header = BBS_PRESENTATION_HEADER.to_vec();
+        presentation_header.extend_from_slice(&extract_byte_string(context.unwrap()).unwrap());
+        let digest = Sha::<TockEnv<Syscalls>>::digest(&presentation_header);
+        assert!(verify_proof_with_header(&proof, &credential, &[0], &digest));
+        assert!(!verify_proof_with_header(
+            &proof,
+            &credential,
+            &[0],
+            &presentation_header
+        ));
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_device_state() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let credential = issue_credential(&mut env, vec![b"name=Alice".to_vec()]);
+
+        let mut params = extract_map(proof_params(&credential, &[0])).unwrap();
+        params.push((cbor_int!(0x0D), cbor::Value::from(true)));
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+                0x03 => device_state,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_PROOF, Some(cbor::Value::map(params)));
+        }
+        let proof = extract_byte_string(proof.unwrap()).unwrap();
+        let device_state = extract_byte_string(device_state.unwrap()).unwrap();
+        let mut expected_state = DEVICE_STATE_PREFIX.to_vec();
+        expected_state.extend_from_slice(&encode_cbor(cbor_map_options! {
+            0x01 => env.firmware_version(),
+            0x02 => 0,
+        }));
+        assert_eq!(device_state, expected_state);
+        let mut presentation_header = device_state;
+        presentation_header.extend_from_slice(BBS_PRESENTATION_HEADER);
+        assert!(verify_proof_with_header(
+            &proof,
+            &credential,
+            &[0],
+            &presentation_header
+        ));
+
+        // Hosts can't forge a device state in their part of the header.
+        let mut params = extract_map(proof_params(&credential, &[0])).unwrap();
+        params.retain(|(key, _)| *key != cbor_int!(0x05));
+        params.push((cbor_int!(0x05), cbor_bytes!(presentation_header)));
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(cbor::Value::map(params), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_status_list() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"status=42".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+
+        let mut params = extract_map(proof_params(&credential, &[1])).unwrap();
+        params.push((cbor_int!(0x0E), cbor_int!(1)));
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+                0x04 => non_revocation,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_PROOF, Some(cbor::Value::map(params)));
+        }
+        let proof = extract_byte_string(proof.unwrap()).unwrap();
+        assert!(verify_proof(&proof, &credential, &[1]));
+        let expected = cbor_map! {
+            0x01 => 1,
+            0x02 => 1,
+            0x03 => b"status=42".to_vec(),
+        };
+        assert_eq!(non_revocation, Some(expected));
+
+        // The entry only counts if the proof discloses it.
+        let mut params = extract_map(proof_params(&credential, &[0])).unwrap();
+        params.push((cbor_int!(0x0E), cbor_int!(1)));
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(cbor::Value::map(params), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_flow_is_deterministic() {
+        let run = |seed| {
+            let mut env = TockEnv::<Syscalls>::default();
+            env.seed_rng_from_u64(seed);
+            provision_link_secret(&mut env);
+            let credential = issue_credential(&mut env, vec![b"name=Alice".to_vec()]);
+            let proof = request_proof(&mut env, &credential, &[0]);
+            (credential.secret_prover_blind, proof)
+        };
+        assert_eq!(run(0), run(0));
+        assert_ne!(run(0), run(1));
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_commitment_challenge() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let challenge = cbor_map! {
+            0x01 => [0x55; 16],
+            0x02 => 1000,
+        };
+        let mut bytes = vec![VENDOR_COMMAND_BBS_COMMITMENT];
+        assert!(cbor_write(challenge.clone(), &mut bytes).is_ok());
+        // Without attestation material, the challenge can't be signed.
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+
+        let dummy_cert = [0xddu8; 20];
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: Some(dummy_cert.to_vec()),
+                private_key: Some(Secret::from_exposed_secret([0x41; EC_FIELD_SIZE])),
+                link_secret: None,
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+        assert!(process_vendor_configure(&mut env, params, DUMMY_CHANNEL).is_ok());
+        destructure_cbor_map! {
+            let {
+                0x01 => commitment,
+                0x03 => nonce,
+                0x04 => expiry,
+                0x05 => signature,
+                0x06 => certificate,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_COMMITMENT, Some(challenge));
+        }
+        let commitment = extract_byte_string(commitment.unwrap()).unwrap();
+        assert!(verify_link_secret_commitment(&commitment).unwrap());
+        assert_eq!(nonce, Some(cbor_bytes!(vec![0x55; 16])));
+        assert_eq!(expiry, Some(cbor_int!(1000)));
+        assert!(!extract_byte_string(signature.unwrap()).unwrap().is_empty());
+        assert_eq!(certificate, Some(cbor_bytes!(dummy_cert.to_vec())));
+
+        // Without a challenge, nothing is signed.
+        destructure_cbor_map! {
+            let {
+                0x03 => nonce,
+                0x05 => signature,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_COMMITMENT, None);
+        }
+        assert_eq!(nonce, None);
+        assert_eq!(signature, None);
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_commitment_resumed() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: Some(vec![0xdd; 20]),
+                private_key: Some(Secret::from_exposed_secret([0x41; EC_FIELD_SIZE])),
+                link_secret: None,
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+        assert!(process_vendor_configure(&mut env, params, DUMMY_CHANNEL).is_ok());
+        let challenge = cbor_map! {
+            0x01 => [0x55; 16],
+            0x02 => 1000,
+        };
+        destructure_cbor_map! {
+            let {
+                0x01 => commitment,
+                0x02 => secret_prover_blind,
+                0x09 => resumed,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_COMMITMENT, Some(challenge.clone()));
+        }
+        assert_eq!(resumed, None);
+
+        // The host repeats the request, e.g. since the device was unplugged before it answered.
+        destructure_cbor_map! {
+            let {
+                0x01 => repeated_commitment,
+                0x02 => repeated_secret_prover_blind,
+                0x05 => signature,
+                0x09 => resumed,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_COMMITMENT, Some(challenge.clone()));
+        }
+        assert_eq!(repeated_commitment, commitment);
+        assert_eq!(repeated_secret_prover_blind, secret_prover_blind);
+        assert!(signature.is_some());
+        assert_eq!(resumed, Some(cbor::Value::from(true)));
+
+        // The first proof with the signed commitment completes the issuance.
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential_with(&mut env, messages, None, Some(challenge.clone()));
+        request_proof(&mut env, &credential, &[0]);
+        destructure_cbor_map! {
+            let {
+                0x01 => fresh_commitment,
+                0x09 => resumed,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_COMMITMENT, Some(challenge));
+        }
+        assert_ne!(fresh_commitment, commitment);
+        assert_eq!(resumed, None);
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_salted_digests() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let digests = vec![vec![0x11; HASH_SIZE], vec![0x22; HASH_SIZE]];
+        let credential = issue_credential(&mut env, digests);
+        let mut params = extract_map(proof_params(&credential, &[1])).unwrap();
+        params.push((cbor_int!(0x0A), cbor::Value::from(true)));
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_PROOF, Some(cbor::Value::map(params)));
+        }
+        let proof = extract_byte_string(proof.unwrap()).unwrap();
+        assert!(verify_proof(&proof, &credential, &[1]));
+
+        // Raw attributes are rejected if digests are announced.
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+        let mut params = extract_map(proof_params(&credential, &[1])).unwrap();
+        params.push((cbor_int!(0x0A), cbor::Value::from(true)));
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(cbor::Value::map(params), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_scoped_link_secret() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let issuer_public_key = fixture_hex(&["signerKeyPair", "publicKey"]);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let params = cbor_map! {
+            0x06 => issuer_public_key.clone(),
+        };
+        let credential = issue_credential_with(&mut env, messages, None, Some(params));
+        let mut params = extract_map(proof_params(&credential, &[1])).unwrap();
+        params.push((cbor_int!(0x0B), cbor::Value::from(true)));
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_PROOF, Some(cbor::Value::map(params)));
+        }
+        let proof = extract_byte_string(proof.unwrap()).unwrap();
+        assert!(verify_proof(&proof, &credential, &[1]));
+
+        // Each issuer gets its own secret, unrelated to the global one.
+        let link_secret = env.attestation_store().get_link_secret().unwrap().unwrap();
+        let scoped = scoped_link_secret::<TockEnv<Syscalls>>(&link_secret, &issuer_public_key, 0);
+        let other = scoped_link_secret::<TockEnv<Syscalls>>(&link_secret, &[0x55; 96], 0);
+        let next = scoped_link_secret::<TockEnv<Syscalls>>(&link_secret, &issuer_public_key, 1);
+        assert_ne!(scoped.to_bytes(), link_secret.to_bytes());
+        assert_ne!(scoped.to_bytes(), other.to_bytes());
+        assert_ne!(scoped.to_bytes(), next.to_bytes());
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_revoke_all() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let issuer_public_key = fixture_hex(&["signerKeyPair", "publicKey"]);
+        let params = cbor_map! {
+            0x06 => issuer_public_key,
+        };
+        let scoped =
+            issue_credential_with(&mut env, vec![b"name=Alice".to_vec()], None, Some(params));
+        let global = issue_credential(&mut env, vec![b"name=Bob".to_vec()]);
+        let scoped_proof = |env: &mut TockEnv<Syscalls>| {
+            let mut params = extract_map(proof_params(&scoped, &[0])).unwrap();
+            params.push((cbor_int!(0x0B), cbor::Value::from(true)));
+            let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+            assert!(cbor_write(cbor::Value::map(params), &mut bytes).is_ok());
+            // Depending on the signature check, the proof fails or doesn't verify.
+            match process_cbor(env, &bytes, DUMMY_CHANNEL, None) {
+                Ok(Some(response)) if response[0] == Ctap2StatusCode::CTAP2_OK as u8 => {
+                    destructure_cbor_map! {
+                        let {
+                            0x01 => proof,
+                        } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+                    }
+                    let proof = extract_byte_string(proof.unwrap()).unwrap();
+                    verify_proof(&proof, &scoped, &[0])
+                }
+                _ => false,
+            }
+        };
+        assert!(scoped_proof(&mut env));
+
+        destructure_cbor_map! {
+            let {
+                0x01 => epoch,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_REVOKE_ALL, None);
+        }
+        assert_eq!(extract_unsigned(epoch.unwrap()).unwrap(), 1);
+        assert!(!scoped_proof(&mut env));
+        // Credentials over the global link secret stay valid.
+        request_proof(&mut env, &global, &[0]);
+
+        // A reset starts the next epoch.
+        env.store().clear(bbs_epoch::MARKER_STORAGE_KEY).unwrap();
+        destructure_cbor_map! {
+            let {
+                0x01 => epoch,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_REVOKE_ALL, None);
+        }
+        assert_eq!(extract_unsigned(epoch.unwrap()).unwrap(), 3);
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_required_consent() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(required_consent(&mut env, 10), Consent::Touch);
+        env.customization_mut().bbs_second_touch_threshold = Some(2);
+        env.customization_mut().bbs_uv_threshold = Some(4);
+        assert_eq!(required_consent(&mut env, 0), Consent::Touch);
+        assert_eq!(required_consent(&mut env, 2), Consent::SecondTouch);
+        assert_eq!(required_consent(&mut env, 4), Consent::UserVerification);
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_uv_threshold() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+        env.customization_mut().bbs_uv_threshold = Some(2);
+
+        let proof = request_proof(&mut env, &credential, &[1]);
+        assert!(verify_proof(&proof, &credential, &[1]));
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[0, 1]), &mut bytes).is_ok());
+        // The test environment has no built-in user verification.
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    /// Built-in user verification that always succeeds.
+    struct AlwaysVerified;
+
+    impl UserVerification for AlwaysVerified {
+        fn is_supported(&self) -> bool {
+            true
+        }
+
+        fn retries(&mut self) -> usize {
+            8
+        }
+
+        fn check_init(&mut self) {}
+
+        fn wait_with_timeout(&mut self, _timeout_ms: usize) -> UserVerificationResult {
+            Ok(())
+        }
+
+        fn check_complete(&mut self) {}
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_authorize() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+        // Without a token, every proof needs user verification, which the test env lacks.
+        env.customization_mut().bbs_uv_threshold = Some(1);
+        let mut proof_bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[1]), &mut proof_bytes).is_ok());
+        let authorize = |env: &mut TockEnv<Syscalls>, proofs: u64| {
+            let mut bytes = vec![VENDOR_COMMAND_BBS_AUTHORIZE];
+            assert!(cbor_write(cbor_map! { 0x01 => proofs }, &mut bytes).is_ok());
+            process_cbor(env, &bytes, DUMMY_CHANNEL, None)
+        };
+
+        assert_eq!(
+            authorize(&mut env, 5),
+            Err(Ctap2StatusCode::CTAP2_ERR_UNSUPPORTED_OPTION)
+        );
+        env.customization_mut().max_bbs_presentation_token_proofs = 2;
+        assert_eq!(
+            authorize(&mut env, 5),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+
+        env.set_user_verification(Box::new(AlwaysVerified));
+        destructure_cbor_map! {
+            let {
+                0x01 => proofs,
+                0x02 => duration_ms,
+            } = vendor_command(
+                &mut env,
+                VENDOR_COMMAND_BBS_AUTHORIZE,
+                Some(cbor_map! { 0x01 => 5 }),
+            );
+        }
+        assert_eq!(proofs, Some(cbor_int!(2)));
+        assert_eq!(duration_ms, Some(cbor_int!(300_000)));
+
+        // The token stands in for the verification of the next two proofs only.
+        env.set_user_verification(Box::new(NoUserVerification));
+        for _ in 0..2 {
+            let proof = request_proof(&mut env, &credential, &[1]);
+            assert!(verify_proof(&proof, &credential, &[1]));
+        }
+        assert_eq!(
+            process_cbor(&mut env, &proof_bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+
+        // Revoking needs no verification.
+        env.set_user_verification(Box::new(AlwaysVerified));
+        assert!(authorize(&mut env, 1).is_ok());
+        env.set_user_verification(Box::new(NoUserVerification));
+        assert!(authorize(&mut env, 0).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &proof_bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Accepts a pinUvAuthParam that is the SHA-256 of the HMAC contents, for one permission.
+    struct FakePinUvAuth(PinPermission);
+
+    #[cfg(feature = "bbs")]
+    impl PinUvAuthCheck for FakePinUvAuth {
+        fn check_pin_uv_auth(
+            &self,
+            permission: PinPermission,
+            hmac_contents: &[u8],
+            pin_uv_auth_param: &[u8],
+            _pin_uv_auth_protocol: PinUvAuthProtocol,
+        ) -> Result<(), Ctap2StatusCode> {
+            if pin_uv_auth_param != Sha::<TockEnv<Syscalls>>::digest(hmac_contents) {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            if permission != self.0 {
+                return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+            }
+            Ok(())
+        }
+
+        fn consecutive_pin_mismatches(&self) -> u8 {
+            0
+        }
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_authorize_pin_uv_auth() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.customization_mut().max_bbs_presentation_token_proofs = 2;
+        let mut hmac_contents = vec![0xFF; 32];
+        hmac_contents.push(VENDOR_COMMAND_BBS_AUTHORIZE);
+        assert!(cbor_write(cbor_map! { 0x01 => 2 }, &mut hmac_contents).is_ok());
+        let pin_uv_auth_param = Sha::<TockEnv<Syscalls>>::digest(&hmac_contents).to_vec();
+        let authorize = |env: &mut TockEnv<Syscalls>,
+                         params: cbor::Value,
+                         pin_uv_auth: Option<&dyn PinUvAuthCheck>| {
+            let mut bytes = vec![VENDOR_COMMAND_BBS_AUTHORIZE];
+            assert!(cbor_write(params, &mut bytes).is_ok());
+            process_cbor(env, &bytes, DUMMY_CHANNEL, pin_uv_auth)
+        };
+        let granted = FakePinUvAuth(PinPermission::BbsPresentation);
+        let other = FakePinUvAuth(PinPermission::GetAssertion);
+
+        // The test env has no built-in user verification to fall back to.
+        assert_eq!(
+            authorize(&mut env, cbor_map! { 0x01 => 2 }, Some(&granted)),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+        let params = cbor_map! {
+            0x01 => 2,
+            0x03 => pin_uv_auth_param.clone(),
+        };
+        assert_eq!(
+            authorize(&mut env, params, Some(&granted)),
+            Err(Ctap2StatusCode::CTAP2_ERR_MISSING_PARAMETER)
+        );
+        let params = || {
+            cbor_map! {
+                0x01 => 2,
+                0x03 => pin_uv_auth_param.clone(),
+                0x04 => 2,
+            }
+        };
+        assert_eq!(
+            authorize(&mut env, params(), None),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert_eq!(
+            authorize(&mut env, params(), Some(&other)),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        // The parameters are authenticated, a different request needs a new pinUvAuthParam.
+        let tampered = cbor_map! {
+            0x01 => 1,
+            0x03 => pin_uv_auth_param.clone(),
+            0x04 => 2,
+        };
+        assert_eq!(
+            authorize(&mut env, tampered, Some(&granted)),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert!(env.presentation_token.is_none());
+        assert!(authorize(&mut env, params(), Some(&granted)).is_ok());
+        assert!(env.presentation_token.is_some());
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_disclosure_policy() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![
+            b"name=Alice".to_vec(),
+            b"age=30".to_vec(),
+            b"id=1234".to_vec(),
+        ];
+        let params = cbor_map! {
+            0x03 => b"issuer",
+            0x04 => cbor_array_vec!(vec![2u64]),
+            0x05 => cbor_array_vec!(vec![0u64]),
+        };
+        let credential = issue_credential_with(&mut env, messages, Some(b"issuer"), Some(params));
+        let request = |env: &mut TockEnv<Syscalls>, disclosed_indexes: &[usize]| {
+            let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+            assert!(cbor_write(proof_params(&credential, disclosed_indexes), &mut bytes).is_ok());
+            process_cbor(env, &bytes, DUMMY_CHANNEL, None)
+        };
+
+        // Unrestricted attributes are disclosed as usual.
+        let proof = request_proof(&mut env, &credential, &[1]);
+        assert!(verify_proof(&proof, &credential, &[1]));
+        assert_eq!(
+            request(&mut env, &[1, 2]),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_POLICY_VIOLATION)
+        );
+        // The test environment has no built-in user verification.
+        assert_eq!(
+            request(&mut env, &[0, 1]),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+
+        // Other apps are bound by the same policy.
+        let ipc_request = |env: &mut TockEnv<Syscalls>, disclosed_indexes: &[usize]| {
+            let mut bytes = vec![ipc::IPC_COMMAND_BBS_PROOF];
+            assert!(cbor_write(proof_params(&credential, disclosed_indexes), &mut bytes).is_ok());
+            ipc::process_ipc_request(env, IpcCapabilities::BBS_PROOF, &bytes)
+        };
+        assert_eq!(
+            ipc_request(&mut env, &[1])[0],
+            Ctap2StatusCode::CTAP2_OK as u8
+        );
+        assert_eq!(
+            ipc_request(&mut env, &[1, 2]),
+            vec![Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_POLICY_VIOLATION as u8]
+        );
+        assert_eq!(
+            ipc_request(&mut env, &[0, 1]),
+            vec![Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION as u8]
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_usage() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let credential = issue_credential(&mut env, vec![b"name=Alice".to_vec()]);
+        let other = issue_credential(&mut env, vec![b"name=Bob".to_vec()]);
+        request_proof(&mut env, &credential, &[0]);
+        request_proof(&mut env, &other, &[]);
+        request_proof(&mut env, &credential, &[]);
+
+        destructure_cbor_map! {
+            let {
+                0x02 => credentials,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_USAGE, None);
+        }
+        let usage = extract_array(credentials.unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|entry| {
+                destructure_cbor_map! {
+                    let {
+                        0x01 => credential_id,
+                        0x02 => presentations,
+                    } = extract_map(entry).unwrap();
+                }
+                (
+                    extract_byte_string(credential_id.unwrap()).unwrap(),
+                    extract_unsigned(presentations.unwrap()).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let id = |credential: &Credential| {
+            bbs_usage::credential_id::<TockEnv<Syscalls>>(&credential.signature).to_vec()
+        };
+        // The latest presented credential comes first.
+        assert_eq!(usage, vec![(id(&credential), 2), (id(&other), 1)]);
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_possession() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+        let nonce = b"verifier nonce";
+        let mut params = extract_map(proof_params(&credential, &[])).unwrap();
+        params.retain(|(key, _)| *key != cbor_int!(0x05) && *key != cbor_int!(0x06));
+        params.push((cbor_int!(0x05), cbor_bytes!(nonce.to_vec())));
+        destructure_cbor_map! {
+            let {
+                0x01 => proof,
+            } = vendor_command(
+                &mut env,
+                VENDOR_COMMAND_BBS_POSSESSION,
+                Some(cbor::Value::map(params.clone())),
+            );
+        }
+        let proof = extract_byte_string(proof.unwrap()).unwrap();
+        assert!(verify_proof_with_header(&proof, &credential, &[], nonce));
+
+        // Disclosing attributes needs the proof command and its confirmation.
+        params.push((cbor_int!(0x06), cbor_array_vec!(vec![1u64])));
+        let mut bytes = vec![VENDOR_COMMAND_BBS_POSSESSION];
+        assert!(cbor_write(cbor::Value::map(params), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Provisions attestation material and a link secret of its own to a replacement device.
+    fn provision_replacement(env: &mut TockEnv<Syscalls>) {
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: Some(vec![0xdd; 20]),
+                private_key: Some(Secret::from_exposed_secret([0x41; EC_FIELD_SIZE])),
+                link_secret: Some(Secret::from_exposed_secret([0x22; LinkSecret::SIZE])),
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+        assert!(process_vendor_configure(env, params, DUMMY_CHANNEL).is_ok());
+    }
+
+    #[cfg(feature = "bbs")]
+    fn migration_command(
+        env: &mut TockEnv<Syscalls>,
+        params: cbor::Value,
+    ) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        let mut bytes = vec![VENDOR_COMMAND_BBS_MIGRATION];
+        assert!(cbor_write(params, &mut bytes).is_ok());
+        process_cbor(env, &bytes, DUMMY_CHANNEL, None)
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Prepares the replacement, and exports the source to its transport key.
+    ///
+    /// Returns the import request for the replacement.
+    fn export_to(
+        source: &mut TockEnv<Syscalls>,
+        replacement: &mut TockEnv<Syscalls>,
+    ) -> Vec<(cbor::Value, cbor::Value)> {
+        let prepare = cbor_map! { 0x01 => 0x01 };
+        destructure_cbor_map! {
+            let {
+                0x01 => transport_public_key,
+                0x02 => signature,
+                0x03 => certificate,
+            } = vendor_command(replacement, VENDOR_COMMAND_BBS_MIGRATION, Some(prepare));
+        }
+        assert!(!extract_byte_string(signature.unwrap()).unwrap().is_empty());
+        assert_eq!(certificate, Some(cbor_bytes!(vec![0xdd; 20])));
+        let params = cbor_map! {
+            0x01 => 0x02,
+            0x02 => transport_public_key.unwrap(),
+        };
+        let mut request = vendor_command(source, VENDOR_COMMAND_BBS_MIGRATION, Some(params));
+        request.push((cbor_int!(0x01), cbor_int!(0x03)));
+        request
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_migration() {
+        let mut source = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut source);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let params = cbor_map! {
+            0x03 => b"issuer",
+            0x04 => cbor_array_vec!(vec![1u64]),
+        };
+        let credential =
+            issue_credential_with(&mut source, messages.clone(), Some(b"issuer"), Some(params));
+        let link_secret = source
+            .attestation_store()
+            .get_link_secret()
+            .unwrap()
+            .unwrap();
+
+        let mut replacement = TockEnv::<Syscalls>::default();
+        // The transport key is signed with the attestation key.
+        assert_eq!(
+            migration_command(&mut replacement, cbor_map! { 0x01 => 0x01 }),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+        );
+        provision_replacement(&mut replacement);
+        let request = export_to(&mut source, &mut replacement);
+        assert!(request.contains(&(cbor_int!(0x06), cbor_int!(1))));
+
+        // The source forgot the issuer, and its credentials no longer prove.
+        assert_eq!(bbs_blinds::list(&mut source), Ok(Vec::new()));
+        let rotated = source
+            .attestation_store()
+            .get_link_secret()
+            .unwrap()
+            .unwrap();
+        assert_ne!(rotated.to_bytes(), link_secret.to_bytes());
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[0]), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut source, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_CREDENTIAL_NOT_FOUND)
+        );
+
+        let import = cbor::Value::map(request.clone());
+        destructure_cbor_map! {
+            let {
+                0x06 => count,
+            } = vendor_command(&mut replacement, VENDOR_COMMAND_BBS_MIGRATION, Some(import));
+        }
+        assert_eq!(count, Some(cbor_int!(1)));
+        let record = bbs_blinds::find(&mut replacement, b"issuer")
+            .unwrap()
+            .unwrap();
+        assert!(record.is_migrated());
+        assert_eq!(record.policy.never_disclosed, 0b10);
+        // Bundles are imported once.
+        assert_eq!(
+            migration_command(&mut replacement, cbor::Value::map(request)),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+
+        // The issuer signs again for the replacement, under the exported policy.
+        assert_eq!(
+            process_cbor(&mut replacement, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_CREDENTIAL_NOT_FOUND)
+        );
+        let credential = issue_credential_for(&mut replacement, messages, Some(b"issuer"));
+        let proof = request_proof(&mut replacement, &credential, &[0]);
+        assert!(verify_proof(&proof, &credential, &[0]));
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[1]), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut replacement, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_POLICY_VIOLATION)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_migration_tampered() {
+        let mut source = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut source);
+        issue_credential_for(&mut source, vec![b"name=Alice".to_vec()], Some(b"issuer"));
+        let mut replacement = TockEnv::<Syscalls>::default();
+        provision_replacement(&mut replacement);
+
+        let mut request = export_to(&mut source, &mut replacement);
+        for (key, value) in request.iter_mut() {
+            if *key == cbor_int!(0x05) {
+                *value = cbor_bytes!(vec![0x00; HASH_SIZE]);
+            }
+        }
+        assert_eq!(
+            migration_command(&mut replacement, cbor::Value::map(request.clone())),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
         );
+        // A failed import drops the transport key.
         assert_eq!(
-            response,
-            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
+            migration_command(&mut replacement, cbor::Value::map(request)),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
         );
+        assert_eq!(bbs_blinds::list(&mut replacement), Ok(Vec::new()));
+    }
+
+    #[cfg(feature = "bbs")]
+    fn recovery_command(
+        env: &mut TockEnv<Syscalls>,
+        params: cbor::Value,
+    ) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        let mut bytes = vec![VENDOR_COMMAND_BBS_RECOVERY];
+        assert!(cbor_write(params, &mut bytes).is_ok());
+        process_cbor(env, &bytes, DUMMY_CHANNEL, None)
+    }
+
+    #[cfg(feature = "bbs")]
+    /// Returns the recovery key that the device shows the issuer in a challenged commitment.
+    fn recovery_public_key(env: &mut TockEnv<Syscalls>, issuer_id: &[u8]) -> Option<cbor::Value> {
+        let params = cbor_map! {
+            0x01 => b"nonce",
+            0x02 => 7,
+            0x03 => issuer_id,
+        };
+        destructure_cbor_map! {
+            let {
+                0x07 => recovery_public_key,
+                0x08 => recovery_signature,
+            } = vendor_command(env, VENDOR_COMMAND_BBS_COMMITMENT, Some(params));
+        }
+        assert_eq!(recovery_public_key.is_some(), recovery_signature.is_some());
+        recovery_public_key
     }
 
+    #[cfg(feature = "bbs")]
     #[test]
-    fn test_vendor_upgrade() {
-        // The test partition storage has size 0x40000.
-        // The test metadata storage has size 0x1000.
-        // The test identifier matches partition B.
-        let mut env = TockEnv::<Syscalls>::default();
+    fn test_vendor_bbs_recovery() {
+        let mut primary = TockEnv::<Syscalls>::default();
+        provision_replacement(&mut primary);
+        let mut backup = TockEnv::<Syscalls>::default();
+        provision_replacement(&mut backup);
+        assert_eq!(recovery_public_key(&mut primary, b"issuer"), None);
 
-        const METADATA_LEN: usize = 0x1000;
-        let metadata = vec![0xFF; METADATA_LEN];
-        let metadata_hash = Sha::<TockEnv<Syscalls>>::digest(&metadata);
-        let data = vec![0xFF; 0x1000];
-        let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
+        let prepare = cbor_map! { 0x01 => 0x01 };
+        destructure_cbor_map! {
+            let {
+                0x01 => transport_public_key,
+            } = vendor_command(&mut backup, VENDOR_COMMAND_BBS_MIGRATION, Some(prepare));
+        }
+        let share = cbor_map! {
+            0x01 => 0x01,
+            0x02 => transport_public_key.unwrap(),
+        };
+        let mut accept = vendor_command(&mut primary, VENDOR_COMMAND_BBS_RECOVERY, Some(share));
+        accept.push((cbor_int!(0x01), cbor_int!(0x02)));
+        assert_eq!(
+            recovery_command(&mut backup, cbor::Value::map(accept.clone())),
+            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
+        );
+        // The transport key is used once.
+        assert_eq!(
+            recovery_command(&mut backup, cbor::Value::map(accept)),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
 
-        // Write to partition.
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: 0x20000,
-                data: data.clone(),
-                hash,
-            },
+        // Both devices show the same key to an issuer, and unrelated keys to different issuers.
+        let primary_key = recovery_public_key(&mut primary, b"issuer");
+        assert!(primary_key.is_some());
+        assert_eq!(recovery_public_key(&mut backup, b"issuer"), primary_key);
+        assert_ne!(recovery_public_key(&mut backup, b"other"), primary_key);
+        // Issuers that don't store the blind on the device name the scope directly.
+        let params = cbor_map! {
+            0x01 => b"nonce",
+            0x02 => 7,
+            0x07 => b"issuer",
+        };
+        destructure_cbor_map! {
+            let {
+                0x07 => scoped_key,
+            } = vendor_command(&mut backup, VENDOR_COMMAND_BBS_COMMITMENT, Some(params));
+        }
+        assert_eq!(scoped_key, primary_key);
+        // Without a challenge, there is nothing to sign.
+        destructure_cbor_map! {
+            let {
+                0x07 => recovery_public_key_value,
+            } = vendor_command(
+                &mut backup,
+                VENDOR_COMMAND_BBS_COMMITMENT,
+                Some(cbor_map! { 0x03 => b"issuer" }),
+            );
+        }
+        assert_eq!(recovery_public_key_value, None);
+
+        assert_eq!(
+            recovery_command(&mut backup, cbor_map! { 0x01 => 0x03 }),
+            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
         );
-        assert_eq!(response, Ok(()));
+        assert_eq!(recovery_public_key(&mut backup, b"issuer"), None);
+        assert_eq!(recovery_public_key(&mut primary, b"issuer"), primary_key);
+    }
 
-        // TockEnv doesn't check the metadata, test its parser in your Env.
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: 0,
-                data: metadata.clone(),
-                hash: metadata_hash,
-            },
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_limits() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let credential = issue_credential(&mut env, messages);
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[1]), &mut bytes).is_ok());
+        let max_proof_size = env.customization().max_bbs_proof_size;
+
+        env.customization_mut().max_bbs_messages = 1;
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_MESSAGE_LIMIT_EXCEEDED)
         );
-        assert_eq!(response, Ok(()));
+        env.customization_mut().max_bbs_messages = 2;
+        env.customization_mut().max_bbs_proof_size = 64;
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_LIMIT_EXCEEDED)
+        );
+        env.customization_mut().max_bbs_proof_size = max_proof_size;
+        env.customization_mut().max_command_duration_ms = Some(1);
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_TIME_BUDGET_EXCEEDED)
+        );
+        env.customization_mut().max_command_duration_ms = Some(usize::MAX);
+        assert!(process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None).is_ok());
+    }
 
-        // TockEnv doesn't check the metadata, test its parser in your Env.
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: METADATA_LEN,
-                data: data.clone(),
-                hash,
-            },
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_status_codes() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(
+            process_cbor(
+                &mut env,
+                &[VENDOR_COMMAND_BBS_COMMITMENT],
+                DUMMY_CHANNEL,
+                None
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_NO_LINK_SECRET)
         );
-        assert_eq!(response, Ok(()));
 
-        // Write metadata of a wrong size.
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: 0,
-                data: metadata[..METADATA_LEN - 1].to_vec(),
-                hash: metadata_hash,
-            },
+        provision_link_secret(&mut env);
+        let credential = issue_credential(&mut env, vec![b"name=Alice".to_vec()]);
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[3]), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_INVALID_DISCLOSED_INDEX)
         );
-        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+    }
 
-        // Write outside of the partition.
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: 0x41000,
-                data: data.clone(),
-                hash,
-            },
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_requires_uv() {
+        let mut env = TockEnv::<Syscalls>::default();
+        provision_link_secret(&mut env);
+        env.customization_mut().bbs_requires_uv = true;
+        // The test environment has no built-in user verification.
+        assert_eq!(
+            process_cbor(
+                &mut env,
+                &[VENDOR_COMMAND_BBS_COMMITMENT],
+                DUMMY_CHANNEL,
+                None
+            ),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
         );
-        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER));
+    }
 
-        // Write a bad hash.
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: 0x20000,
-                data,
-                hash: [0xEE; 32],
-            },
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_bbs_info() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.customization_mut().max_bbs_messages = 8;
+        env.customization_mut().allows_external_link_secret = false;
+        destructure_cbor_map! {
+            let {
+                0x01 => max_messages,
+                0x02 => max_credentials,
+                0x03 => max_proof_size,
+                0x04 => requires_uv,
+                0x05 => allows_external_link_secret,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_INFO, None);
+        }
+        let customization = env.customization();
+        assert_eq!(max_messages, Some(cbor_int!(8)));
+        assert_eq!(
+            max_credentials,
+            Some(cbor_int!(customization.max_bbs_credentials() as i64))
         );
-        assert_eq!(response, Err(Ctap2StatusCode::CTAP2_ERR_INTEGRITY_FAILURE));
+        assert_eq!(
+            max_proof_size,
+            Some(cbor_int!(customization.max_bbs_proof_size() as i64))
+        );
+        assert_eq!(requires_uv, Some(cbor_false!()));
+        assert_eq!(allows_external_link_secret, Some(cbor_false!()));
     }
 
+    #[cfg(feature = "bbs")]
     #[test]
-    fn test_vendor_upgrade_no_second_partition() {
+    fn test_vendor_bbs_issuers() {
         let mut env = TockEnv::<Syscalls>::default();
-        env.disable_upgrade_storage();
+        provision_link_secret(&mut env);
+        let issuer_public_key = fixture_hex(&["signerKeyPair", "publicKey"]);
+        let credential = issue_credential(&mut env, vec![b"name=Alice".to_vec()]);
+        let register = cbor_map! {
+            0x01 => 0x01,
+            0x02 => issuer_public_key.clone(),
+            0x03 => "Test issuer",
+            0x04 => 2,
+        };
+        let mut bytes = vec![VENDOR_COMMAND_BBS_ISSUERS];
+        assert!(cbor_write(register, &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
+        );
+        destructure_cbor_map! {
+            let {
+                0x06 => issuers,
+            } = vendor_command(&mut env, VENDOR_COMMAND_BBS_INFO, None);
+        }
+        let expected = cbor_array![cbor_map! {
+            0x01 => issuer_public_key.clone(),
+            0x02 => "Test issuer",
+            0x03 => 2,
+        }];
+        assert_eq!(issuers, Some(expected));
 
-        let data = vec![0xFF; 0x1000];
-        let hash = Sha::<TockEnv<Syscalls>>::digest(&data);
-        let response = process_vendor_upgrade(
-            &mut env,
-            VendorUpgradeParameters {
-                offset: 0,
-                data,
-                hash,
-            },
+        // The credential has one message, while the schema has two.
+        let mut bytes = vec![VENDOR_COMMAND_BBS_PROOF];
+        assert!(cbor_write(proof_params(&credential, &[0]), &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
         );
-        assert_eq!(response, Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND));
+        let credential =
+            issue_credential(&mut env, vec![b"name=Alice".to_vec(), b"age=30".to_vec()]);
+        request_proof(&mut env, &credential, &[0]);
+
+        // Other issuers are refused once the registry isn't empty.
+        let other = bbs_issuers::Issuer {
+            public_key: vec![0x55; bbs_issuers::PUBLIC_KEY_SIZE],
+            name: "Other issuer".to_string(),
+            num_messages: 1,
+        };
+        assert_eq!(bbs_issuers::register(&mut env, &other), Ok(()));
+        let remove = cbor_map! {
+            0x01 => 0x02,
+            0x02 => issuer_public_key.clone(),
+        };
+        let mut bytes = vec![VENDOR_COMMAND_BBS_ISSUERS];
+        assert!(cbor_write(remove, &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Ok(Some(vec![Ctap2StatusCode::CTAP2_OK as u8]))
+        );
+        let scoped = cbor_map! {
+            0x06 => issuer_public_key,
+        };
+        let mut bytes = vec![VENDOR_COMMAND_BBS_COMMITMENT];
+        assert!(cbor_write(scoped, &mut bytes).is_ok());
+        assert_eq!(
+            process_cbor(&mut env, &bytes, DUMMY_CHANNEL, None),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_BBS_UNKNOWN_ISSUER)
+        );
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_vendor_configure_generated_link_secret() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.customization_mut().allows_external_link_secret = false;
+        let dummy_link_secret = [0x42u8; LinkSecret::SIZE];
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: None,
+                private_key: None,
+                link_secret: Some(Secret::from_exposed_secret(dummy_link_secret)),
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+        let response = process_vendor_configure(&mut env, params, DUMMY_CHANNEL).unwrap();
+        assert!(response.link_secret_programmed);
+        let link_secret = env.attestation_store().get_link_secret().unwrap().unwrap();
+        assert_ne!(link_secret.to_bytes(), dummy_link_secret);
     }
 
     #[test]
@@ -891,49 +4085,280 @@ mod test {
             upgrade_info_reponse,
             Ok(VendorUpgradeInfoResponse {
                 info: bundle_identifier,
+                running: env.upgrade_storage().unwrap().running_status(),
+                bundle: env.upgrade_storage().unwrap().bundle_status(),
             })
         );
     }
 
     #[test]
-    fn test_vendor_response_into_cbor() {
-        let response_cbor: cbor::Value = VendorConfigureResponse {
-            cert_programmed: true,
-            pkey_programmed: false,
-            link_secret_programmed: false,
-        }
-        .into();
+    fn test_vendor_verify_upgrade() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let verify = |env: &mut TockEnv<Syscalls>| {
+            process_vendor_verify_upgrade(env, &mut CancellationToken::never())
+        };
         assert_eq!(
-            response_cbor,
-            cbor_map_options! {
-                0x01 => true,
-                0x02 => false,
-                0x03 => false,
-            }
+            verify(&mut env),
+            Ok(VendorVerifyUpgradeResponse {
+                verification: BundleVerification::Missing,
+                version: None,
+            })
+        );
+
+        // The metadata of a partial bundle doesn't match its content yet.
+        let mut metadata = vec![0xFF; 0x1000];
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 7);
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&metadata);
+        let params = VendorUpgradeParameters {
+            offset: 0,
+            data: metadata,
+            hash,
+            encrypted: false,
+        };
+        process_vendor_upgrade(&mut env, params, &mut CancellationToken::never()).unwrap();
+        assert_eq!(
+            verify(&mut env),
+            Ok(VendorVerifyUpgradeResponse {
+                verification: BundleVerification::HashMismatch,
+                version: Some(7),
+            })
+        );
+
+        // Verifying doesn't raise the minimum version, unlike committing.
+        assert_eq!(rollback::min_version(&mut env), Ok(0));
+        rollback::raise(&mut env, 8).unwrap();
+        assert_eq!(
+            verify(&mut env),
+            Ok(VendorVerifyUpgradeResponse {
+                verification: BundleVerification::InvalidMetadata,
+                version: Some(7),
+            })
+        );
+    }
+
+    #[test]
+    fn test_vendor_boot_control() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let boot_control = |env: &mut TockEnv<Syscalls>, params| {
+            process_vendor_boot_control(env, params, &mut CancellationToken::never())
+        };
+        assert_eq!(
+            boot_control(&mut env, VendorBootControlParameters::MarkGood),
+            Ok(())
+        );
+        let info = process_vendor_upgrade_info(&mut env).unwrap();
+        assert!(info.running.confirmed);
+        assert!(!info.running.rollback_requested);
+
+        // Without an image in the other partition, there is nothing to roll back to.
+        assert_eq!(
+            boot_control(&mut env, VendorBootControlParameters::RequestRollback),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        let mut metadata = vec![0xFF; 0x1000];
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 4);
+        let hash = Sha::<TockEnv<Syscalls>>::digest(&metadata);
+        let params = VendorUpgradeParameters {
+            offset: 0,
+            data: metadata,
+            hash,
+            encrypted: false,
+        };
+        process_vendor_upgrade(&mut env, params, &mut CancellationToken::never()).unwrap();
+
+        // The minimum bundle version applies to rollbacks too.
+        rollback::raise(&mut env, 5).unwrap();
+        assert_eq!(
+            boot_control(&mut env, VendorBootControlParameters::RequestRollback),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        rollback::set(&mut env, 4, true).unwrap();
+        assert_eq!(
+            boot_control(&mut env, VendorBootControlParameters::RequestRollback),
+            Ok(())
+        );
+        let info = process_vendor_upgrade_info(&mut env).unwrap();
+        assert!(info.running.rollback_requested);
+        assert_eq!(info.bundle.version, Some(4));
+
+        lockdown::raise(&mut env, LockdownLevel::ConfigAndUpgrade).unwrap();
+        assert_eq!(
+            boot_control(&mut env, VendorBootControlParameters::RequestRollback),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_vendor_firmware_measurement() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let bundle_identifier = env.upgrade_storage().unwrap().bundle_identifier();
+        let expected_hash = env
+            .upgrade_storage()
+            .unwrap()
+            .running_firmware_hash(|| true)
+            .unwrap();
+        // Without attestation material, the measurement can't be signed.
+        assert_eq!(
+            process_vendor_firmware_measurement(&mut env, &mut CancellationToken::never()),
+            Err(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)
         );
-        let response_cbor: cbor::Value = VendorConfigureResponse {
-            cert_programmed: false,
-            pkey_programmed: true,
-            link_secret_programmed: false,
+
+        let dummy_cert = [0xddu8; 20];
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: Some(dummy_cert.to_vec()),
+                private_key: Some(Secret::from_exposed_secret([0x41; EC_FIELD_SIZE])),
+                #[cfg(feature = "bbs")]
+                link_secret: None,
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
+        };
+        assert!(process_vendor_configure(&mut env, params, DUMMY_CHANNEL).is_ok());
+        let response =
+            process_vendor_firmware_measurement(&mut env, &mut CancellationToken::never()).unwrap();
+        assert_eq!(response.hash, expected_hash);
+        assert_eq!(response.bundle_identifier, bundle_identifier);
+        assert!(!response.signature.is_empty());
+        assert_eq!(response.certificate, dummy_cert.to_vec());
+        assert_eq!(response.boot_info, None);
+
+        let boot_info = BootInfo {
+            measurement: expected_hash,
+            firmware_version: 7,
+            rollback_counter: 5,
+        };
+        env.set_boot_info(boot_info);
+        let response =
+            process_vendor_firmware_measurement(&mut env, &mut CancellationToken::never()).unwrap();
+        assert_eq!(response.boot_info, Some(boot_info));
+        let mut message = MEASUREMENT_DOMAIN.to_vec();
+        message.extend_from_slice(&expected_hash);
+        message.extend_from_slice(&bundle_identifier.to_be_bytes());
+        message.extend_from_slice(&expected_hash);
+        message.extend_from_slice(&7u64.to_be_bytes());
+        message.extend_from_slice(&5u64.to_be_bytes());
+        // Signatures are deterministic.
+        let attestation_key =
+            EcdsaSk::<TockEnv<Syscalls>>::from_slice(&[0x41; EC_FIELD_SIZE]).unwrap();
+        assert_eq!(response.signature, attestation_key.sign(&message).to_der());
+    }
+
+    fn credential_import_command(
+        env: &mut TockEnv<Syscalls>,
+        params: cbor::Value,
+    ) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        let mut bytes = vec![VENDOR_COMMAND_CREDENTIAL_IMPORT];
+        assert!(cbor_write(params, &mut bytes).is_ok());
+        process_cbor(env, &bytes, DUMMY_CHANNEL, None)
+    }
+
+    /// Gets a transport key from the device, and wraps passkeys to it like an exporter.
+    fn wrap_passkeys(env: &mut TockEnv<Syscalls>, user_handles: &[u8]) -> cbor::Value {
+        let response = credential_import_command(env, cbor_map! { 0x01 => 0x01 })
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x01 => transport_public_key,
+                0x02 => signature,
+                0x03 => certificate,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
         }
-        .into();
+        let transport_public_key = extract_byte_string(transport_public_key.unwrap()).unwrap();
+        assert!(!extract_byte_string(signature.unwrap()).unwrap().is_empty());
         assert_eq!(
-            response_cbor,
-            cbor_map_options! {
-                0x01 => false,
-                0x02 => true,
-                0x03 => false,
-            }
+            extract_byte_string(certificate.unwrap()).unwrap(),
+            vec![0xdd; 20]
         );
+
+        let credentials = user_handles
+            .iter()
+            .map(|user_handle| {
+                cbor_map! {
+                    0x01 => vec![*user_handle; 16],
+                    0x02 => "example.com",
+                    0x03 => vec![*user_handle],
+                    0x04 => SignatureAlgorithm::Es256 as i64,
+                    0x05 => vec![0x11; EC_FIELD_SIZE],
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut plaintext = Vec::new();
+        let passkeys = cbor_map! {
+            0x01 => 1,
+            0x02 => cbor_array_vec!(credentials),
+        };
+        assert!(cbor_write(passkeys, &mut plaintext).is_ok());
+        let bundle = transport::wrap(
+            env,
+            &<[u8; PUBLIC_KEY_SIZE]>::try_from(transport_public_key).unwrap(),
+            CREDENTIAL_IMPORT_PURPOSE,
+            &plaintext,
+        )
+        .unwrap();
+        cbor_map! {
+            0x01 => 0x02,
+            0x03 => bundle.source_public_key,
+            0x04 => bundle.ciphertext,
+            0x05 => bundle.mac,
+        }
     }
 
     #[test]
-    fn test_vendor_upgrade_info_into_cbor() {
-        let vendor_upgrade_info_response = VendorUpgradeInfoResponse { info: 0x00060000 };
-        let response_cbor: cbor::Value = vendor_upgrade_info_response.into();
-        let expected_cbor = cbor_map! {
-            0x01 => 0x00060000,
+    fn test_vendor_credential_import() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let params = VendorConfigureParameters {
+            lockdown: LockdownLevel::None,
+            attestation_material: Some(AttestationMaterial {
+                certificate: Some(vec![0xdd; 20]),
+                private_key: Some(Secret::from_exposed_secret([0x41; EC_FIELD_SIZE])),
+                #[cfg(feature = "bbs")]
+                link_secret: None,
+            }),
+            permissions: None,
+            aaguid: None,
+            min_bundle_version: None,
+            bundle_key: None,
         };
-        assert_eq!(response_cbor, expected_cbor);
+        assert!(process_vendor_configure(&mut env, params, DUMMY_CHANNEL).is_ok());
+        let request = wrap_passkeys(&mut env, &[0x01, 0x02]);
+        // Importing requires built-in user verification, which the test env lacks.
+        assert_eq!(
+            credential_import_command(&mut env, request.clone()),
+            Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION)
+        );
+
+        env.set_user_verification(Box::new(AlwaysVerified));
+        let response = credential_import_command(&mut env, request.clone())
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x06 => count,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        assert_eq!(count, Some(cbor_int!(2)));
+        // Bundles are imported once.
+        assert_eq!(
+            credential_import_command(&mut env, request),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+    }
+
+    #[test]
+    fn test_vendor_credential_import_permission() {
+        let mut env = TockEnv::<Syscalls>::default();
+        permissions::set(&mut env, Permissions::BBS_PRESENT, false).unwrap();
+        assert_eq!(
+            credential_import_command(&mut env, cbor_map! { 0x01 => 0x01 }),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
     }
 }
@@ -0,0 +1,89 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Crash report of the last panic, persisted in the store.
+//!
+//! The panic handler of `lang_items` builds the report, and the hook installed by
+//! `TockEnv::install_crash_hook` writes it before the watchdog reboots the device.
+
+use lang_items::crash_report::{CrashReport, MAX_REPORT_SIZE};
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::env::Env;
+use opensk::vendor::storage_layout;
+
+/// Key of the environment store for the crash report.
+///
+/// It is persistent, so that a reset doesn't hide the cause of a crash.
+pub const STORAGE_KEY: usize = storage_layout::CRASH_REPORT;
+
+/// Replaces the stored report.
+pub fn store(env: &mut impl Env, report: &CrashReport) -> Result<(), Ctap2StatusCode> {
+    let mut buffer = [0; MAX_REPORT_SIZE];
+    let length = report.encode(&mut buffer);
+    Ok(env.store().insert(STORAGE_KEY, &buffer[..length])?)
+}
+
+/// Returns the stored report, ignoring malformed entries.
+pub fn get(env: &mut impl Env) -> Result<Option<CrashReport>, Ctap2StatusCode> {
+    Ok(env
+        .store()
+        .find(STORAGE_KEY)?
+        .and_then(|value| CrashReport::decode(&value)))
+}
+
+pub fn clear(env: &mut impl Env) -> Result<(), Ctap2StatusCode> {
+    Ok(env.store().remove(STORAGE_KEY)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TockEnv;
+    use super::*;
+    use libtock_unittest::fake::Syscalls;
+
+    #[test]
+    fn test_store_get_clear() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(get(&mut env), Ok(None));
+        let report = CrashReport::new(Some(0x51), 0x1234, format_args!("out of {}", "memory"));
+        assert_eq!(store(&mut env, &report), Ok(()));
+        let stored = get(&mut env).unwrap().unwrap();
+        assert_eq!(stored, report);
+        assert_eq!(stored.message(), b"out of memory");
+        assert_eq!(clear(&mut env), Ok(()));
+        assert_eq!(get(&mut env), Ok(None));
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let long = [b'x'; 300];
+        let long = core::str::from_utf8(&long).unwrap();
+        let report = CrashReport::new(None, 0, format_args!("{}", long));
+        assert_eq!(report.message().len(), 160);
+        store(&mut env, &report).unwrap();
+        assert_eq!(get(&mut env), Ok(Some(report)));
+    }
+
+    #[test]
+    fn test_malformed_entry() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.store()
+            .insert(STORAGE_KEY, &[2, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(get(&mut env), Ok(None));
+        env.store().insert(STORAGE_KEY, &[1, 0]).unwrap();
+        assert_eq!(get(&mut env), Ok(None));
+    }
+}
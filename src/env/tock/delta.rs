@@ -0,0 +1,304 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Differential upgrades, patching the running image into the other partition.
+//!
+//! The patch follows bsdiff without its compression, which the compressed vendor command adds
+//! per chunk instead. All integers are little endian. The patch starts with the 4 B length of
+//! the new image, followed by records of:
+//! - 4 B length of the diff, 4 B length of the extra and 4 B signed seek,
+//! - the diff, added bytewise to the old image from the old position on,
+//! - the extra, copied to the new image as is.
+//! The old position advances with the diff, and then moves by the seek. Images span the whole
+//! partition, metadata included, so that the upgrade checks the result like a full bundle.
+//!
+//! Patches arrive in order. They are applied on the fly, and only the page in progress is kept.
+
+use alloc::vec::Vec;
+use byteorder::{ByteOrder, LittleEndian};
+use core::cmp;
+use opensk::ctap::status_code::Ctap2StatusCode;
+use persistent_store::StorageResult;
+
+/// Size of the chunks written to the partition, and that old reads don't cross.
+pub const PAGE_SIZE: usize = 0x1000;
+
+const HEADER_SIZE: usize = 4;
+const CONTROL_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Header,
+    Control,
+    Diff { remaining: usize },
+    Extra { remaining: usize },
+}
+
+/// Progress of a patch that is being applied.
+pub struct DeltaPatch {
+    state: State,
+    /// Partially received header or control.
+    field: Vec<u8>,
+    new_size: usize,
+    /// Offset of the next patch byte.
+    patch_offset: usize,
+    old_position: usize,
+    /// Extra length of the current record, following its diff.
+    extra: usize,
+    /// Seek of the current record, applied after its extra.
+    seek: i64,
+    /// Offset in the partition of the first byte of `output`.
+    output_offset: usize,
+    output: Vec<u8>,
+}
+
+impl Default for DeltaPatch {
+    fn default() -> Self {
+        DeltaPatch {
+            state: State::Header,
+            field: Vec::new(),
+            new_size: 0,
+            patch_offset: 0,
+            old_position: 0,
+            extra: 0,
+            seek: 0,
+            output_offset: 0,
+            output: Vec::new(),
+        }
+    }
+}
+
+impl DeltaPatch {
+    /// Offset of the next chunk that the patch expects.
+    pub fn patch_offset(&self) -> usize {
+        self.patch_offset
+    }
+
+    /// Whether the new image is complete, up to the pages not yet taken.
+    pub fn is_complete(&self) -> bool {
+        self.state != State::Header && self.output_offset + self.output.len() == self.new_size
+    }
+
+    /// Applies the next chunk of the patch.
+    ///
+    /// `read_old` returns the bytes of the running image at an offset, and is never asked to
+    /// cross a page.
+    pub fn apply<'a>(
+        &mut self,
+        mut chunk: &[u8],
+        read_old: impl Fn(usize, usize) -> StorageResult<&'a [u8]>,
+    ) -> Result<(), Ctap2StatusCode> {
+        self.patch_offset += chunk.len();
+        while !chunk.is_empty() {
+            match self.state {
+                State::Header | State::Control => {
+                    let size = if self.state == State::Header {
+                        HEADER_SIZE
+                    } else {
+                        CONTROL_SIZE
+                    };
+                    let length = cmp::min(size - self.field.len(), chunk.len());
+                    self.field.extend_from_slice(&chunk[..length]);
+                    chunk = &chunk[length..];
+                    if self.field.len() < size {
+                        continue;
+                    }
+                    self.parse_field()?;
+                }
+                State::Diff { remaining } => {
+                    let in_page = PAGE_SIZE - self.old_position % PAGE_SIZE;
+                    let length = cmp::min(cmp::min(remaining, chunk.len()), in_page);
+                    let old = read_old(self.old_position, length)
+                        .map_err(|_| Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+                    self.output.extend(
+                        old.iter()
+                            .zip(&chunk[..length])
+                            .map(|(old, diff)| old.wrapping_add(*diff)),
+                    );
+                    self.old_position += length;
+                    chunk = &chunk[length..];
+                    self.state = State::Diff {
+                        remaining: remaining - length,
+                    };
+                }
+                State::Extra { remaining } => {
+                    let length = cmp::min(remaining, chunk.len());
+                    self.output.extend_from_slice(&chunk[..length]);
+                    chunk = &chunk[length..];
+                    self.state = State::Extra {
+                        remaining: remaining - length,
+                    };
+                }
+            }
+            self.finish_record()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the next page of the new image with its offset, or the rest once complete.
+    pub fn take_page(&mut self) -> Option<(usize, Vec<u8>)> {
+        let length = if self.output.len() >= PAGE_SIZE {
+            PAGE_SIZE
+        } else if self.is_complete() && !self.output.is_empty() {
+            self.output.len()
+        } else {
+            return None;
+        };
+        let rest = self.output.split_off(length);
+        let page = core::mem::replace(&mut self.output, rest);
+        let offset = self.output_offset;
+        self.output_offset += length;
+        Some((offset, page))
+    }
+
+    fn parse_field(&mut self) -> Result<(), Ctap2StatusCode> {
+        if self.state == State::Header {
+            self.new_size = LittleEndian::read_u32(&self.field) as usize;
+            self.state = State::Control;
+        } else {
+            let diff = LittleEndian::read_u32(&self.field[..4]) as usize;
+            self.extra = LittleEndian::read_u32(&self.field[4..8]) as usize;
+            self.seek = LittleEndian::read_i32(&self.field[8..]) as i64;
+            // Records must not write past the new image.
+            let end = (self.output_offset + self.output.len())
+                .checked_add(diff)
+                .and_then(|end| end.checked_add(self.extra));
+            if end.map_or(true, |end| end > self.new_size) {
+                return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+            }
+            self.state = State::Diff { remaining: diff };
+        }
+        self.field.clear();
+        Ok(())
+    }
+
+    /// Moves on from a diff or extra once it is done.
+    fn finish_record(&mut self) -> Result<(), Ctap2StatusCode> {
+        match self.state {
+            State::Diff { remaining: 0 } => {
+                self.state = State::Extra {
+                    remaining: self.extra,
+                };
+                self.finish_record()
+            }
+            State::Extra { remaining: 0 } => {
+                let position = self.old_position as i64 + self.seek;
+                if position < 0 {
+                    return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+                }
+                self.old_position = position as usize;
+                self.state = State::Control;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use persistent_store::StorageError;
+
+    /// Encodes a patch from records of diff, extra and seek.
+    fn encode_patch(new_size: usize, records: &[(Vec<u8>, Vec<u8>, i32)]) -> Vec<u8> {
+        let mut patch = (new_size as u32).to_le_bytes().to_vec();
+        for (diff, extra, seek) in records {
+            patch.extend_from_slice(&(diff.len() as u32).to_le_bytes());
+            patch.extend_from_slice(&(extra.len() as u32).to_le_bytes());
+            patch.extend_from_slice(&seek.to_le_bytes());
+            patch.extend_from_slice(diff);
+            patch.extend_from_slice(extra);
+        }
+        patch
+    }
+
+    /// Applies the patch in chunks of the given size, and returns the new image.
+    fn apply_patch(
+        old: &[u8],
+        patch: &[u8],
+        chunk_size: usize,
+    ) -> Result<Vec<u8>, Ctap2StatusCode> {
+        let read_old = |offset: usize, length: usize| {
+            assert!(offset / PAGE_SIZE == (offset + length - 1) / PAGE_SIZE);
+            old.get(offset..offset + length)
+                .ok_or(StorageError::OutOfBounds)
+        };
+        let mut delta_patch = DeltaPatch::default();
+        let mut new = Vec::new();
+        for (index, chunk) in patch.chunks(chunk_size).enumerate() {
+            assert_eq!(delta_patch.patch_offset(), index * chunk_size);
+            delta_patch.apply(chunk, read_old)?;
+            while let Some((offset, page)) = delta_patch.take_page() {
+                assert_eq!(offset, new.len());
+                new.extend_from_slice(&page);
+            }
+        }
+        assert!(delta_patch.is_complete());
+        Ok(new)
+    }
+
+    #[test]
+    fn test_apply_diff_and_extra() {
+        let old: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| i as u8).collect();
+        let mut expected = old.clone();
+        expected[5] = 0x55;
+        expected.extend_from_slice(&[0xAA; 10]);
+        let mut diff = vec![0; old.len()];
+        diff[5] = 0x55u8.wrapping_sub(old[5]);
+        let patch = encode_patch(expected.len(), &[(diff, vec![0xAA; 10], 0)]);
+        for chunk_size in [1, 7, 100, PAGE_SIZE, patch.len()] {
+            assert_eq!(apply_patch(&old, &patch, chunk_size), Ok(expected.clone()));
+        }
+    }
+
+    #[test]
+    fn test_apply_seek() {
+        // Moves the second page of the old image to the front.
+        let old: Vec<u8> = (0..2 * PAGE_SIZE).map(|i| (i / 7) as u8).collect();
+        let mut expected = old[PAGE_SIZE..].to_vec();
+        expected.extend_from_slice(&old[..PAGE_SIZE]);
+        let records = [
+            (vec![], vec![], PAGE_SIZE as i32),
+            (vec![0; PAGE_SIZE], vec![], -2 * PAGE_SIZE as i32),
+            (vec![0; PAGE_SIZE], vec![], 0),
+        ];
+        let patch = encode_patch(expected.len(), &records);
+        assert_eq!(apply_patch(&old, &patch, 33), Ok(expected));
+    }
+
+    #[test]
+    fn test_apply_invalid() {
+        let old = vec![0x11; PAGE_SIZE];
+        // Records can't write past the new image.
+        let patch = encode_patch(4, &[(vec![0; 5], vec![], 0)]);
+        assert_eq!(
+            apply_patch(&old, &patch, 64),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        // Seeks can't go before the old image.
+        let patch = encode_patch(8, &[(vec![0; 4], vec![], -5), (vec![0; 4], vec![], 0)]);
+        assert_eq!(
+            apply_patch(&old, &patch, 64),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+        // Diffs can't read past the old image.
+        let patch = encode_patch(PAGE_SIZE + 1, &[(vec![0; PAGE_SIZE + 1], vec![], 0)]);
+        assert_eq!(
+            apply_patch(&old, &patch, 64),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing service for other apps on the same board.
+//!
+//! Clients share a buffer holding a command byte followed by CBOR parameters. The response is
+//! written back into the same buffer, as a status byte followed by CBOR, like vendor commands.
+
+use super::permissions::{self, Permissions};
+#[cfg(feature = "bbs")]
+use super::vendor_parameters::VendorBBSProofParameters;
+use super::TockEnv;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use libtock_platform::Syscalls;
+use opensk::api::attestation_store::{self, AttestationStore};
+use opensk::api::crypto::ecdsa::{SecretKey, Signature};
+#[cfg(feature = "bbs")]
+use opensk::api::customization::Customization;
+#[cfg(not(feature = "std"))]
+use opensk::api::display::{Display, Transaction};
+#[cfg(any(feature = "bbs", not(feature = "std")))]
+use opensk::api::user_feedback::{self, FeedbackState};
+#[cfg(not(feature = "std"))]
+use opensk::api::user_presence::UserPresenceError;
+#[cfg(any(feature = "bbs", not(feature = "std")))]
+use opensk::api::user_presence::{CommandClass, UserPresence};
+#[cfg(feature = "bbs")]
+use opensk::api::user_verification::{UserVerification, UserVerificationError};
+#[cfg(any(feature = "bbs", not(feature = "std")))]
+use opensk::api::watchdog::Watchdog;
+use opensk::ctap::cbor_read;
+use opensk::ctap::data_formats::{extract_byte_string, extract_map, ok_or_missing};
+use opensk::ctap::status_code::Ctap2StatusCode;
+#[cfg(feature = "bbs")]
+use opensk::ctap::CancellationToken;
+#[cfg(any(feature = "bbs", not(feature = "std")))]
+use opensk::ctap::KEEPALIVE_DELAY_MS;
+use opensk::env::{EcdsaSk, Env};
+#[cfg(feature = "bbs")]
+use opensk::vendor::commands::{check_bbs_proof_limits, process_vendor_bbs_proof};
+use opensk::vendor::handler::encode_cbor;
+use sk_cbor::{cbor_map_options, destructure_cbor_map};
+use {libtock_platform as platform, sk_cbor as cbor};
+
+const IPC_COMMAND_SIGN_STATEMENT: u8 = 0x01;
+#[cfg(feature = "bbs")]
+pub(super) const IPC_COMMAND_BBS_PROOF: u8 = 0x02;
+
+/// Prefixed to signed statements, so they can't be mistaken for FIDO attestations.
+const STATEMENT_DOMAIN: &[u8] = b"OpenSK IPC statement\0";
+
+/// Commands a client app is allowed to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IpcCapabilities(u8);
+
+impl IpcCapabilities {
+    pub const NONE: IpcCapabilities = IpcCapabilities(0);
+    /// Signing statements with the attestation key.
+    pub const SIGN_STATEMENT: IpcCapabilities = IpcCapabilities(0x01);
+    /// Generating BBS proofs with the link secret.
+    pub const BBS_PROOF: IpcCapabilities = IpcCapabilities(0x02);
+
+    pub const fn union(self, other: IpcCapabilities) -> IpcCapabilities {
+        IpcCapabilities(self.0 | other.0)
+    }
+
+    pub fn contains(self, other: IpcCapabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+// Capabilities of client apps by process index, from `OPENSK_IPC_GRANTS` when building.
+include!(concat!(env!("OUT_DIR"), "/opensk_ipc_grants.rs"));
+
+pub fn process_ipc_request<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    capabilities: IpcCapabilities,
+    bytes: &[u8],
+) -> Vec<u8> {
+    process_cbor(env, capabilities, bytes).unwrap_or_else(|e| vec![e as u8])
+}
+
+fn process_cbor<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config>(
+    env: &mut TockEnv<S, C>,
+    capabilities: IpcCapabilities,
+    bytes: &[u8],
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let (command, parameters) = bytes
+        .split_first()
+        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH)?;
+    // Apps are also bound by the permissions of the device, like vendor commands.
+    let (required, permissions) = match *command {
+        // Signs with the attestation key, like the firmware measurement.
+        IPC_COMMAND_SIGN_STATEMENT => (IpcCapabilities::SIGN_STATEMENT, Permissions::ADMIN),
+        #[cfg(feature = "bbs")]
+        IPC_COMMAND_BBS_PROOF => (IpcCapabilities::BBS_PROOF, Permissions::BBS_PRESENT),
+        _ => return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND),
+    };
+    if !capabilities.contains(required) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED);
+    }
+    permissions::check(env, permissions)?;
+    let decoded_cbor = cbor_read(parameters)?;
+    match *command {
+        IPC_COMMAND_SIGN_STATEMENT => {
+            let params = IpcSignStatementParameters::try_from(decoded_cbor)?;
+            #[cfg(not(feature = "std"))]
+            check_local_user_presence(env, None)?;
+            let response = process_sign_statement(env, params)?;
+            Ok(encode_cbor(response.into()))
+        }
+        #[cfg(feature = "bbs")]
+        IPC_COMMAND_BBS_PROOF => {
+            let params = VendorBBSProofParameters::try_from(decoded_cbor)?;
+            check_bbs_proof_limits(env, &params)?;
+            // Other apps have no way to cancel.
+            let mut cancellation = CancellationToken::never();
+            let response = process_vendor_bbs_proof(
+                env,
+                params,
+                &mut cancellation,
+                |env, params, policy_requires_uv| {
+                    #[cfg(not(feature = "std"))]
+                    check_local_user_presence(env, Some(params.disclosure()))?;
+                    #[cfg(feature = "std")]
+                    let _ = params;
+                    // Like vendor commands, proofs require built-in verification where available.
+                    let verify = policy_requires_uv
+                        || env.customization().bbs_requires_uv()
+                        || UserVerification::is_supported(env);
+                    if verify {
+                        check_local_user_verification(env)?;
+                    }
+                    Ok(verify)
+                },
+            )?;
+            Ok(encode_cbor(response.into()))
+        }
+        _ => Err(Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND),
+    }
+}
+
+/// Shows the transaction, if there is a display, and blocks for user presence.
+///
+/// Unlike `confirm_transaction`, there is no transport to send keepalives on. The client just
+/// waits for its notification.
+#[cfg(not(feature = "std"))]
+fn check_local_user_presence<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    transaction: Option<Transaction>,
+) -> Result<(), Ctap2StatusCode> {
+    // Both traits have an `is_supported` function, hence the qualified calls.
+    let shown = match transaction {
+        Some(transaction) if Display::is_supported(env) => {
+            Display::show(env, &transaction)?;
+            true
+        }
+        _ => false,
+    };
+    let timeout_iterations =
+        env.user_presence().timeout_ms(CommandClass::Vendor) / KEEPALIVE_DELAY_MS;
+    env.user_presence().check_init();
+    let mut result = Err(UserPresenceError::Timeout);
+    for i in 0..=timeout_iterations {
+        env.watchdog().feed();
+        user_feedback::signal(env, FeedbackState::AwaitingTouch, i);
+        result = env.user_presence().wait_with_timeout(KEEPALIVE_DELAY_MS);
+        if !matches!(result, Err(UserPresenceError::Timeout)) {
+            break;
+        }
+    }
+    env.user_presence().check_complete();
+    if shown {
+        Display::clear(env);
+    }
+    Ok(result?)
+}
+
+/// Blocks for built-in user verification, without keepalives like the presence check.
+#[cfg(feature = "bbs")]
+fn check_local_user_verification<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+) -> Result<(), Ctap2StatusCode> {
+    if !UserVerification::is_supported(env) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_INVALID_OPTION);
+    }
+    if env.user_verification().retries() == 0 {
+        return Err(Ctap2StatusCode::CTAP2_ERR_UV_BLOCKED);
+    }
+    let timeout_iterations =
+        env.user_presence().timeout_ms(CommandClass::Vendor) / KEEPALIVE_DELAY_MS;
+    env.user_verification().check_init();
+    let mut result = Err(UserVerificationError::Timeout);
+    for i in 0..=timeout_iterations {
+        env.watchdog().feed();
+        user_feedback::signal(env, FeedbackState::AwaitingTouch, i);
+        result = env
+            .user_verification()
+            .wait_with_timeout(KEEPALIVE_DELAY_MS);
+        if !matches!(result, Err(UserVerificationError::Timeout)) {
+            break;
+        }
+    }
+    env.user_verification().check_complete();
+    Ok(result?)
+}
+
+fn process_sign_statement<
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+>(
+    env: &mut TockEnv<S, C>,
+    params: IpcSignStatementParameters,
+) -> Result<IpcSignStatementResponse, Ctap2StatusCode> {
+    let attestation = env
+        .attestation_store()
+        .get(&attestation_store::Id::Batch)?
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+    let attestation_key = EcdsaSk::<TockEnv<S, C>>::from_slice(&attestation.private_key)
+        .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+    let mut signed_data = STATEMENT_DOMAIN.to_vec();
+    signed_data.extend_from_slice(&params.statement);
+    Ok(IpcSignStatementResponse {
+        signature: attestation_key.sign(&signed_data).to_der(),
+        certificate: attestation.certificate,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IpcSignStatementParameters {
+    pub statement: Vec<u8>,
+}
+
+impl TryFrom<cbor::Value> for IpcSignStatementParameters {
+    type Error = Ctap2StatusCode;
+
+    fn try_from(cbor_value: cbor::Value) -> Result<Self, Ctap2StatusCode> {
+        destructure_cbor_map! {
+            let {
+                0x01 => statement,
+            } = extract_map(cbor_value)?;
+        }
+        let statement = extract_byte_string(ok_or_missing(statement)?)?;
+        Ok(IpcSignStatementParameters { statement })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct IpcSignStatementResponse {
+    pub signature: Vec<u8>,
+    pub certificate: Vec<u8>,
+}
+
+impl From<IpcSignStatementResponse> for cbor::Value {
+    fn from(response: IpcSignStatementResponse) -> Self {
+        let IpcSignStatementResponse {
+            signature,
+            certificate,
+        } = response;
+
+        cbor_map_options! {
+            0x01 => signature,
+            0x02 => certificate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cbor::cbor_map;
+    use libtock_unittest::fake::Syscalls;
+    use opensk::api::attestation_store::Attestation;
+    use opensk::ctap::cbor_write;
+    use opensk::ctap::secret::Secret;
+
+    fn sign_statement_request(statement: &[u8]) -> Vec<u8> {
+        let mut request = vec![IPC_COMMAND_SIGN_STATEMENT];
+        cbor_write(cbor_map! { 0x01 => statement }, &mut request).unwrap();
+        request
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let both = IpcCapabilities::SIGN_STATEMENT.union(IpcCapabilities::BBS_PROOF);
+        assert!(both.contains(IpcCapabilities::SIGN_STATEMENT));
+        assert!(both.contains(IpcCapabilities::BBS_PROOF));
+        assert!(!IpcCapabilities::SIGN_STATEMENT.contains(IpcCapabilities::BBS_PROOF));
+        assert!(IpcCapabilities::NONE.contains(IpcCapabilities::NONE));
+    }
+
+    #[test]
+    fn test_process_ipc_request_invalid() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(
+            process_ipc_request(&mut env, IpcCapabilities::SIGN_STATEMENT, &[]),
+            vec![Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH as u8]
+        );
+        assert_eq!(
+            process_ipc_request(&mut env, IpcCapabilities::SIGN_STATEMENT, &[0x7F]),
+            vec![Ctap2StatusCode::CTAP1_ERR_INVALID_COMMAND as u8]
+        );
+    }
+
+    #[test]
+    fn test_process_ipc_request_denied() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let request = sign_statement_request(&[0x55; 16]);
+        assert_eq!(
+            process_ipc_request(&mut env, IpcCapabilities::BBS_PROOF, &request),
+            vec![Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED as u8]
+        );
+        #[cfg(feature = "bbs")]
+        assert_eq!(
+            process_ipc_request(&mut env, IpcCapabilities::NONE, &[IPC_COMMAND_BBS_PROOF]),
+            vec![Ctap2StatusCode::CTAP2_ERR_OPERATION_DENIED as u8]
+        );
+    }
+
+    #[test]
+    fn test_process_ipc_request_permissions() {
+        let mut env = TockEnv::<Syscalls>::default();
+        permissions::set(&mut env, Permissions::UPGRADE, false).unwrap();
+        let request = sign_statement_request(&[0x55; 16]);
+        assert_eq!(
+            process_ipc_request(&mut env, IpcCapabilities::SIGN_STATEMENT, &request),
+            vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]
+        );
+        #[cfg(feature = "bbs")]
+        assert_eq!(
+            process_ipc_request(
+                &mut env,
+                IpcCapabilities::BBS_PROOF,
+                &[IPC_COMMAND_BBS_PROOF]
+            ),
+            vec![Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED as u8]
+        );
+    }
+
+    #[test]
+    fn test_process_sign_statement() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let request = sign_statement_request(&[0x55; 16]);
+        // No attestation material is programmed yet.
+        assert_eq!(
+            process_ipc_request(&mut env, IpcCapabilities::SIGN_STATEMENT, &request),
+            vec![Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR as u8]
+        );
+
+        let attestation = Attestation {
+            private_key: Secret::from_exposed_secret([0x41; 32]),
+            certificate: vec![0xDD; 20],
+        };
+        env.attestation_store()
+            .set(&attestation_store::Id::Batch, Some(&attestation))
+            .unwrap();
+        let response = process_ipc_request(&mut env, IpcCapabilities::SIGN_STATEMENT, &request);
+        assert_eq!(response[0], Ctap2StatusCode::CTAP2_OK as u8);
+        destructure_cbor_map! {
+            let {
+                0x01 => signature,
+                0x02 => certificate,
+            } = extract_map(cbor_read(&response[1..]).unwrap()).unwrap();
+        }
+        assert!(!extract_byte_string(signature.unwrap()).unwrap().is_empty());
+        assert_eq!(
+            extract_byte_string(certificate.unwrap()).unwrap(),
+            vec![0xDD; 20]
+        );
+    }
+}
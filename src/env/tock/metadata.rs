@@ -0,0 +1,123 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! FIDO metadata of the authenticator, embedded at build time.
+//!
+//! The build script generates `AAGUID`, `CERTIFICATION_LEVEL` and `DESCRIPTION` from
+//! `crypto_data/aaguid.txt` and the optional metadata service entry `crypto_data/metadata.json`.
+//! Attestation certificates may name the AAGUID too, and must then agree with the device.
+
+use opensk::api::customization::AAGUID_LENGTH;
+use opensk::ctap::status_code::Ctap2StatusCode;
+
+include!(concat!(env!("OUT_DIR"), "/opensk_metadata.rs"));
+
+/// DER encoding of 1.3.6.1.4.1.45724.1.1.4, the OID of the FIDO AAGUID certificate extension.
+const AAGUID_EXTENSION_OID: &[u8] = &[
+    0x06, 0x0B, 0x2B, 0x06, 0x01, 0x04, 0x01, 0x82, 0xE5, 0x1C, 0x01, 0x01, 0x04,
+];
+
+/// DER prefix of the extension value, an OCTET STRING wrapping the AAGUID OCTET STRING.
+const AAGUID_EXTENSION_PREFIX: &[u8] = &[0x04, 0x12, 0x04, 0x10];
+
+/// DER encoding of a false critical flag, which encoders usually omit.
+const NOT_CRITICAL: &[u8] = &[0x01, 0x01, 0x00];
+
+/// Returns the AAGUID named by an attestation certificate, if it has the extension.
+///
+/// The extension must not be critical, so that relying parties unaware of it accept the
+/// certificate.
+pub fn certificate_aaguid(
+    certificate: &[u8],
+) -> Result<Option<[u8; AAGUID_LENGTH]>, Ctap2StatusCode> {
+    let start = match certificate
+        .windows(AAGUID_EXTENSION_OID.len())
+        .position(|window| window == AAGUID_EXTENSION_OID)
+    {
+        None => return Ok(None),
+        Some(position) => position + AAGUID_EXTENSION_OID.len(),
+    };
+    let mut value = &certificate[start..];
+    if value.starts_with(NOT_CRITICAL) {
+        value = &value[NOT_CRITICAL.len()..];
+    }
+    if !value.starts_with(AAGUID_EXTENSION_PREFIX) {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let value = &value[AAGUID_EXTENSION_PREFIX.len()..];
+    if value.len() < AAGUID_LENGTH {
+        return Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER);
+    }
+    let mut aaguid = [0; AAGUID_LENGTH];
+    aaguid.copy_from_slice(&value[..AAGUID_LENGTH]);
+    Ok(Some(aaguid))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const SAMPLE_AAGUID: [u8; AAGUID_LENGTH] = [0x5A; AAGUID_LENGTH];
+
+    /// Returns the bytes of a certificate around an AAGUID extension with the given value.
+    fn certificate_with_extension(value: &[u8]) -> Vec<u8> {
+        let mut certificate = vec![0x30, 0x82, 0x01, 0x00];
+        certificate.extend_from_slice(AAGUID_EXTENSION_OID);
+        certificate.extend_from_slice(value);
+        certificate.extend_from_slice(&[0x30, 0x0A]);
+        certificate
+    }
+
+    #[test]
+    fn test_certificate_aaguid() {
+        let mut value = AAGUID_EXTENSION_PREFIX.to_vec();
+        value.extend_from_slice(&SAMPLE_AAGUID);
+        let certificate = certificate_with_extension(&value);
+        assert_eq!(certificate_aaguid(&certificate), Ok(Some(SAMPLE_AAGUID)));
+
+        let mut not_critical = NOT_CRITICAL.to_vec();
+        not_critical.extend_from_slice(&value);
+        let certificate = certificate_with_extension(&not_critical);
+        assert_eq!(certificate_aaguid(&certificate), Ok(Some(SAMPLE_AAGUID)));
+    }
+
+    #[test]
+    fn test_certificate_aaguid_absent() {
+        // Other extensions, like basic constraints, don't name an AAGUID.
+        let certificate = [
+            0x30, 0x0C, 0x06, 0x03, 0x55, 0x1D, 0x13, 0x04, 0x02, 0x30, 0x00,
+        ];
+        assert_eq!(certificate_aaguid(&certificate), Ok(None));
+    }
+
+    #[test]
+    fn test_certificate_aaguid_invalid() {
+        let mut critical = vec![0x01, 0x01, 0xFF];
+        critical.extend_from_slice(AAGUID_EXTENSION_PREFIX);
+        critical.extend_from_slice(&SAMPLE_AAGUID);
+        let certificate = certificate_with_extension(&critical);
+        assert_eq!(
+            certificate_aaguid(&certificate),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+
+        let certificate = certificate_with_extension(&AAGUID_EXTENSION_PREFIX[..2]);
+        assert_eq!(
+            certificate_aaguid(&certificate),
+            Err(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)
+        );
+    }
+}
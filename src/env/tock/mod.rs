@@ -12,49 +12,85 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use alloc::boxed::Box;
 use alloc::vec::Vec;
+#[cfg(feature = "bbs")]
+use bbs::LinkSecret;
 use clock::TockClock;
 use core::cell::Cell;
 use core::convert::TryFrom;
 use core::marker::PhantomData;
 #[cfg(all(target_has_atomic = "8", not(feature = "std")))]
 use core::sync::atomic::{AtomicBool, Ordering};
+use ipc::IpcCapabilities;
 use libtock_buttons::{ButtonListener, ButtonState, Buttons};
 use libtock_console::{Console, ConsoleWriter};
 use libtock_drivers::result::{FlexUnwrap, TockError};
 use libtock_drivers::timer::Duration;
 use libtock_drivers::usb_ctap_hid::UsbCtapHid;
-use libtock_drivers::{rng, timer, usb_ctap_hid};
+use libtock_drivers::{rng, timer, usb_ctap_hid, watchdog};
 use libtock_leds::Leds;
 use libtock_platform as platform;
 use libtock_platform::{ErrorCode, Syscalls};
 use opensk::api::attestation_store::AttestationStore;
+use opensk::api::boot_info::BootInfo;
 use opensk::api::connection::{
     HidConnection, SendOrRecvError, SendOrRecvResult, SendOrRecvStatus, UsbEndpoint,
 };
 use opensk::api::crypto::software_crypto::SoftwareCrypto;
-use opensk::api::customization::{CustomizationImpl, AAGUID_LENGTH, DEFAULT_CUSTOMIZATION};
+use opensk::api::customization::{CustomizationImpl, DEFAULT_CUSTOMIZATION};
+use opensk::api::delay::Delay;
+use opensk::api::display::{Display, DisplayError, Transaction};
 use opensk::api::rng::Rng;
-use opensk::api::user_presence::{UserPresence, UserPresenceError, UserPresenceResult};
+use opensk::api::user_feedback::{BlinkPattern, FeedbackPattern, FeedbackState, UserFeedback};
+use opensk::api::user_presence::{
+    UserPresence, UserPresenceError, UserPresenceResult, UserPresenceSource, UserPresenceSources,
+};
+use opensk::api::user_verification::{
+    UserVerification, UserVerificationError, UserVerificationResult,
+};
+use opensk::api::watchdog::{Watchdog, WatchdogError};
 use opensk::api::{attestation_store, key_store};
-use opensk::ctap::Channel;
-use opensk::env::Env;
+use opensk::ctap::log::LogBuffer;
+#[cfg(feature = "bbs")]
+use opensk::ctap::secret::Secret;
+use opensk::ctap::{Channel, PinUvAuthCheck};
+#[cfg(feature = "std")]
+use opensk::env::test::TestRng;
+use opensk::env::{EcdhSk, Env};
 #[cfg(feature = "std")]
 use persistent_store::BufferOptions;
 use persistent_store::{StorageResult, Store};
 use platform::{share, DefaultConfig, Subscribe};
+#[cfg(feature = "std")]
+use rand_core::SeedableRng;
 use rand_core::{impls, CryptoRng, Error, RngCore};
-
+use secure_channel::SecureChannel;
+
+#[cfg(feature = "bbs")]
+mod bbs_migration;
+#[cfg(feature = "bbs")]
+mod bbs_recovery;
+#[cfg(feature = "bbs")]
+mod bbs_tokens;
 #[cfg(feature = "std")]
 mod buffer_upgrade_storage;
 mod clock;
 mod commands;
+mod crash_report;
+mod delta;
+pub mod ipc;
+mod metadata;
+mod permissions;
 #[cfg(feature = "std")]
 mod phantom_buffer_storage;
+mod secure_channel;
 #[cfg(not(feature = "std"))]
 mod storage;
 mod storage_helper;
+mod transport;
 mod upgrade_helper;
+pub mod vendor_parameters;
 
 #[cfg(not(feature = "std"))]
 pub type Storage<S, C> = storage::TockStorage<S, C>;
@@ -66,11 +102,17 @@ type UpgradeStorage<S, C> = storage::TockUpgradeStorage<S, C>;
 #[cfg(feature = "std")]
 type UpgradeStorage<S, C> = buffer_upgrade_storage::BufferUpgradeStorage<S, C>;
 
-pub const AAGUID: &[u8; AAGUID_LENGTH] =
-    include_bytes!(concat!(env!("OUT_DIR"), "/opensk_aaguid.bin"));
+// Shortest watchdog timeout for a requested reboot.
+const REBOOT_TIMEOUT_MS: usize = 1;
+
+// Pages at the end of the storage holding the secrets store.
+#[cfg(feature = "dual_store")]
+const SECRETS_STORE_PAGES: usize = 3;
 
 const TOCK_CUSTOMIZATION: CustomizationImpl = CustomizationImpl {
-    aaguid: AAGUID,
+    aaguid: metadata::AAGUID,
+    certification_level: metadata::CERTIFICATION_LEVEL,
+    metadata_description: metadata::DESCRIPTION,
     ..DEFAULT_CUSTOMIZATION
 };
 
@@ -132,18 +174,63 @@ impl<S: Syscalls> HidConnection for TockHidConnection<S> {
     }
 }
 
+/// Environment of `install_crash_hook`, type-erased because statics can't be generic.
+static mut CRASH_ENV: *mut () = core::ptr::null_mut();
+
+fn crash_hook<S, C>(report: &lang_items::crash_report::CrashReport)
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    // Safety: `install_crash_hook` stored an environment of this type that is still alive.
+    let env = unsafe { &mut *(CRASH_ENV as *mut TockEnv<S, C>) };
+    // There is nothing left to do if this fails.
+    crash_report::store(env, report).ok();
+}
+
 pub struct TockEnv<
     S: Syscalls,
     C: platform::subscribe::Config + platform::allow_ro::Config = DefaultConfig,
 > {
+    #[cfg(not(feature = "std"))]
     rng: TockRng<S>,
+    // Fake syscalls have no RNG driver, and seeding makes tests reproducible.
+    #[cfg(feature = "std")]
+    rng: TestRng,
     store: Store<Storage<S, C>>,
+    /// Store for secrets, split off the end of the storage with the `dual_store` feature.
+    secrets_store: Option<Store<Storage<S, C>>>,
     upgrade_storage: Option<UpgradeStorage<S, C>>,
     main_connection: TockHidConnection<S>,
     #[cfg(feature = "vendor_hid")]
     vendor_connection: TockHidConnection<S>,
-    blink_pattern: usize,
     clock: TockClock<S>,
+    presence_sources: UserPresenceSources,
+    user_verification: Option<Box<dyn UserVerification>>,
+    display: Option<Box<dyn Display>>,
+    watchdog_started: bool,
+    ipc_grants: Vec<(u32, IpcCapabilities)>,
+    customization: CustomizationImpl,
+    /// Provisioning session, opened by the vendor to send secrets encrypted.
+    secure_channel: Option<SecureChannel>,
+    /// Differential upgrade in progress, restarted by a patch chunk at offset 0.
+    delta_patch: Option<delta::DeltaPatch>,
+    /// Batch attestation as last read or written, `None` until then.
+    attestation_cache: Option<Option<attestation_store::Attestation>>,
+    /// Link secret as last read or written, `None` until then.
+    #[cfg(feature = "bbs")]
+    link_secret_cache: Option<Option<Secret<[u8; LinkSecret::SIZE]>>>,
+    /// Transport key of a pending passkey import, lost on reboot.
+    import_transport_key: Option<EcdhSk<TockEnv<S, C>>>,
+    /// Transport key of a pending BBS migration to this device, lost on reboot.
+    #[cfg(feature = "bbs")]
+    migration_transport_key: Option<EcdhSk<TockEnv<S, C>>>,
+    /// Authorization of BBS proofs without consent, lost on reboot.
+    #[cfg(feature = "bbs")]
+    presentation_token: Option<bbs_tokens::PresentationToken>,
+    /// Measurement handed off by the boot loader, if the board passes one.
+    boot_info: Option<BootInfo>,
+    log_buffer: LogBuffer,
     c: PhantomData<C>,
 }
 
@@ -156,14 +243,29 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> D
     ///
     /// - If called a second time.
     fn default() -> Self {
+        #[cfg(not(feature = "std"))]
         let rng = TockRng::default();
+        #[cfg(feature = "std")]
+        let rng = TestRng::seed_from_u64(0);
         // We rely on `take_storage` to ensure that this function is called only once.
-        let storage = take_storage::<S, C>().unwrap();
-        let store = Store::new(storage).ok().unwrap();
+        #[cfg_attr(not(feature = "dual_store"), allow(unused_mut))]
+        let mut storage = take_storage::<S, C>().unwrap();
+        // A corrupted store is mounted read-only, and CTAP reports it instead of panicking.
+        #[cfg(feature = "dual_store")]
+        let secrets_store = Some(
+            Store::mount(storage.split_off(SECRETS_STORE_PAGES).unwrap())
+                .ok()
+                .unwrap()
+                .0,
+        );
+        #[cfg(not(feature = "dual_store"))]
+        let secrets_store = None;
+        let (store, _) = Store::mount(storage).ok().unwrap();
         let upgrade_storage = UpgradeStorage::new().ok();
         TockEnv {
             rng,
             store,
+            secrets_store,
             upgrade_storage,
             main_connection: TockHidConnection {
                 endpoint: UsbEndpoint::MainHid,
@@ -174,8 +276,25 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> D
                 endpoint: UsbEndpoint::VendorHid,
                 s: PhantomData,
             },
-            blink_pattern: 0,
             clock: TockClock::default(),
+            presence_sources: UserPresenceSources::default(),
+            user_verification: None,
+            display: None,
+            watchdog_started: false,
+            ipc_grants: Vec::new(),
+            customization: TOCK_CUSTOMIZATION,
+            secure_channel: None,
+            delta_patch: None,
+            attestation_cache: None,
+            #[cfg(feature = "bbs")]
+            link_secret_cache: None,
+            import_transport_key: None,
+            #[cfg(feature = "bbs")]
+            migration_transport_key: None,
+            #[cfg(feature = "bbs")]
+            presentation_token: None,
+            boot_info: None,
+            log_buffer: LogBuffer::default(),
             c: PhantomData,
         }
     }
@@ -186,12 +305,23 @@ where
     S: Syscalls,
     C: platform::subscribe::Config + platform::allow_ro::Config,
 {
-    /// Returns the upgrade storage instance.
+    /// Writes a pending upgrade chunk, if any.
     ///
-    /// Upgrade storage is optional, so implementations may return `None`. However, implementations
-    /// should either always return `None` or always return `Some`.
-    pub fn upgrade_storage(&mut self) -> Option<&mut UpgradeStorage<S, C>> {
-        self.upgrade_storage.as_mut()
+    /// Call it while idle, so that flash writes overlap with the host sending the next chunk.
+    pub fn flush_upgrade_storage(&mut self) {
+        if let Some(upgrade_storage) = self.upgrade_storage.as_mut() {
+            upgrade_storage.flush();
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn seed_rng_from_u64(&mut self, seed: u64) {
+        self.rng = TestRng::seed_from_u64(seed);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn customization_mut(&mut self) -> &mut CustomizationImpl {
+        &mut self.customization
     }
 
     pub fn disable_upgrade_storage(&mut self) {
@@ -201,6 +331,63 @@ where
     pub fn lock_firmware_protection(&mut self) -> bool {
         false
     }
+
+    /// Persists crash reports of later panics in the store.
+    ///
+    /// The hook is best effort: it writes from the panic handler, possibly in the middle of a
+    /// store operation.
+    ///
+    /// # Safety
+    ///
+    /// The environment must not move nor be dropped afterwards, e.g. because it lives in `main`
+    /// which never returns.
+    pub unsafe fn install_crash_hook(&mut self) {
+        CRASH_ENV = self as *mut Self as *mut ();
+        lang_items::crash_report::set_hook(crash_hook::<S, C>);
+    }
+
+    /// Adds a source of user presence, in addition to the buttons.
+    ///
+    /// Sources are polled whenever a wait for the buttons ends, so at least once per keepalive.
+    pub fn add_user_presence_source(&mut self, source: Box<dyn UserPresenceSource>) {
+        self.presence_sources.push(source);
+    }
+
+    /// Enables built-in user verification, e.g. with a PIN pad or a fingerprint sensor on GPIO.
+    pub fn set_user_verification(&mut self, user_verification: Box<dyn UserVerification>) {
+        self.user_verification = Some(user_verification);
+    }
+
+    /// Records what the boot loader measured, for boards whose boot loader hands it off.
+    pub fn set_boot_info(&mut self, boot_info: BootInfo) {
+        self.boot_info = Some(boot_info);
+    }
+
+    /// Attaches a screen, e.g. an e-paper or OLED, to confirm transactions on.
+    pub fn set_display(&mut self, display: Box<dyn Display>) {
+        self.display = Some(display);
+    }
+
+    /// Allows the client app with the given process index to use IPC commands.
+    ///
+    /// Apps are loaded in a fixed order, so indexes are stable for a given image. Clients
+    /// without grants are denied all commands.
+    pub fn grant_ipc_capabilities(&mut self, client: u32, capabilities: IpcCapabilities) {
+        match self.ipc_grants.iter_mut().find(|(c, _)| *c == client) {
+            Some((_, granted)) => *granted = capabilities,
+            None => self.ipc_grants.push((client, capabilities)),
+        }
+    }
+
+    /// Processes a request a client app shared over IPC, and returns the response.
+    pub fn process_ipc_request(&mut self, client: u32, request: &[u8]) -> Vec<u8> {
+        let capabilities = self
+            .ipc_grants
+            .iter()
+            .find(|(c, _)| *c == client)
+            .map_or(IpcCapabilities::NONE, |(_, granted)| *granted);
+        ipc::process_ipc_request(self, capabilities, request)
+    }
 }
 
 #[cfg(feature = "std")]
@@ -266,15 +453,16 @@ where
     C: platform::subscribe::Config + platform::allow_ro::Config,
 {
     fn check_init(&mut self) {
-        self.blink_pattern = 0;
+        self.presence_sources.enable();
     }
 
     fn wait_with_timeout(&mut self, timeout_ms: usize) -> UserPresenceResult {
+        if self.presence_sources.poll()? {
+            return Ok(());
+        }
         if timeout_ms == 0 {
             return Err(UserPresenceError::Timeout);
         }
-        blink_leds::<S>(self.blink_pattern);
-        self.blink_pattern += 1;
 
         // enable interrupts for all buttons
         let num_buttons = Buttons::<S>::count().map_err(|_| UserPresenceError::Fail)?;
@@ -345,7 +533,7 @@ where
             Ok::<(), UserPresenceError>(())
         })?;
 
-        if button_touched.get() {
+        if button_touched.get() || self.presence_sources.poll()? {
             Ok(())
         } else if keepalive_expired.get() {
             Err(UserPresenceError::Timeout)
@@ -355,7 +543,158 @@ where
     }
 
     fn check_complete(&mut self) {
-        switch_off_leds::<S>();
+        self.presence_sources.disable();
+    }
+}
+
+impl<S, C> UserVerification for TockEnv<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn is_supported(&self) -> bool {
+        self.user_verification
+            .as_ref()
+            .map_or(false, |uv| uv.is_supported())
+    }
+
+    fn retries(&mut self) -> usize {
+        self.user_verification.as_mut().map_or(0, |uv| uv.retries())
+    }
+
+    fn check_init(&mut self) {
+        if let Some(uv) = self.user_verification.as_mut() {
+            uv.check_init();
+        }
+    }
+
+    fn wait_with_timeout(&mut self, timeout_ms: usize) -> UserVerificationResult {
+        match self.user_verification.as_mut() {
+            Some(uv) => uv.wait_with_timeout(timeout_ms),
+            None => Err(UserVerificationError::Fail),
+        }
+    }
+
+    fn check_complete(&mut self) {
+        if let Some(uv) = self.user_verification.as_mut() {
+            uv.check_complete();
+        }
+    }
+}
+
+impl<S, C> Watchdog for TockEnv<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn start(&mut self, timeout_ms: usize) -> Result<(), WatchdogError> {
+        watchdog::Watchdog::<S, C>::is_available().map_err(|_| WatchdogError::Unsupported)?;
+        watchdog::Watchdog::<S, C>::start(timeout_ms).map_err(|_| WatchdogError::Fail)?;
+        self.watchdog_started = true;
+        Ok(())
+    }
+
+    fn feed(&mut self) {
+        if self.watchdog_started {
+            // A failed tickle eventually reboots, which is the best we can do anyway.
+            watchdog::Watchdog::<S, C>::tickle().ok();
+        }
+    }
+
+    fn reboot(&mut self) -> ! {
+        if !self.watchdog_started && self.start(REBOOT_TIMEOUT_MS).is_err() {
+            // The kernel's fault policy decides whether to restart the app.
+            panic!("Reboot requested without a watchdog");
+        }
+        // Stops feeding the watchdog, so that it fires.
+        loop {
+            S::yield_wait();
+        }
+    }
+}
+
+impl<S, C> Delay for TockEnv<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn random_delay(&mut self, max_ms: u32) {
+        let delay_ms = self.rng.next_u64() % (max_ms as u64 + 1);
+        // Fake syscalls have no alarm to wait for.
+        if cfg!(feature = "std") || delay_ms == 0 {
+            return;
+        }
+        let expired = Cell::new(false);
+        let mut callback = timer::with_callback::<S, C, _>(|_| expired.set(true));
+        share::scope::<
+            Subscribe<
+                S,
+                { libtock_drivers::timer::DRIVER_NUM },
+                { libtock_drivers::timer::subscribe::CALLBACK },
+            >,
+            _,
+            _,
+        >(|handle| {
+            // Failing to wait only loses the jitter, the response is still correct.
+            let mut alarm = match callback.init() {
+                Ok(alarm) => alarm,
+                Err(_) => return,
+            };
+            if callback.enable(handle).is_err()
+                || alarm
+                    .set_alarm(timer::Duration::from_ms(delay_ms as isize))
+                    .is_err()
+            {
+                return;
+            }
+            libtock_drivers::util::Util::<S>::yieldk_for(|| expired.get());
+        });
+    }
+}
+
+impl<S, C> Display for TockEnv<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn is_supported(&self) -> bool {
+        self.display.is_some()
+    }
+
+    fn show(&mut self, transaction: &Transaction) -> Result<(), DisplayError> {
+        match &mut self.display {
+            Some(display) => display.show(transaction),
+            None => Err(DisplayError::Fail),
+        }
+    }
+
+    fn clear(&mut self) {
+        if let Some(display) = &mut self.display {
+            display.clear();
+        }
+    }
+}
+
+impl<S, C> UserFeedback for TockEnv<S, C>
+where
+    S: Syscalls,
+    C: platform::subscribe::Config + platform::allow_ro::Config,
+{
+    fn show(&mut self, _state: FeedbackState, pattern: FeedbackPattern, tick: usize) {
+        // Our boards only have single color LEDs and no buzzer, so we only show the blink pattern.
+        match pattern.blink {
+            BlinkPattern::Off => switch_off_leds::<S>(),
+            BlinkPattern::Solid => switch_on_leds::<S>(),
+            BlinkPattern::Flash => {
+                if tick % 2 == 0 {
+                    switch_on_leds::<S>();
+                } else {
+                    switch_off_leds::<S>();
+                }
+            }
+            BlinkPattern::Alternate => blink_leds::<S>(tick),
+            BlinkPattern::Snake => wink_leds::<S>(tick),
+        }
     }
 }
 
@@ -378,7 +717,12 @@ where
         if !matches!(id, attestation_store::Id::Batch) {
             return Err(attestation_store::Error::NoSupport);
         }
-        attestation_store::helper_get(self)
+        if let Some(attestation) = &self.attestation_cache {
+            return Ok(attestation.clone());
+        }
+        let attestation = attestation_store::helper_get(self)?;
+        self.attestation_cache = Some(attestation.clone());
+        Ok(attestation)
     }
 
     fn set(
@@ -389,15 +733,53 @@ where
         if !matches!(id, attestation_store::Id::Batch) {
             return Err(attestation_store::Error::NoSupport);
         }
-        attestation_store::helper_set(self, attestation)
+        // A failed write may still have changed the store, so the next read goes to flash.
+        self.attestation_cache = None;
+        attestation_store::helper_set(self, attestation)?;
+        self.attestation_cache = Some(attestation.cloned());
+        Ok(())
+    }
+
+    #[cfg(feature = "bbs")]
+    fn get_link_secret(&mut self) -> Result<Option<LinkSecret>, attestation_store::Error> {
+        if let Some(link_secret) = &self.link_secret_cache {
+            return Ok(link_secret
+                .as_ref()
+                .map(|bytes| LinkSecret::from_bytes(**bytes)));
+        }
+        let link_secret = attestation_store::helper_get_link_secret(self)?;
+        self.link_secret_cache = Some(
+            link_secret
+                .as_ref()
+                .map(|link_secret| Secret::from_exposed_secret(link_secret.to_bytes())),
+        );
+        Ok(link_secret)
+    }
+
+    #[cfg(feature = "bbs")]
+    fn set_link_secret(
+        &mut self,
+        link_secret: Option<&LinkSecret>,
+    ) -> Result<(), attestation_store::Error> {
+        self.link_secret_cache = None;
+        attestation_store::helper_set_link_secret(self, link_secret)?;
+        self.link_secret_cache = Some(
+            link_secret.map(|link_secret| Secret::from_exposed_secret(link_secret.to_bytes())),
+        );
+        Ok(())
     }
 }
 
 impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> Env
     for TockEnv<S, C>
 {
+    #[cfg(not(feature = "std"))]
     type Rng = TockRng<S>;
+    #[cfg(feature = "std")]
+    type Rng = TestRng;
     type UserPresence = Self;
+    type UserVerification = Self;
+    type UserFeedback = Self;
     type Storage = Storage<S, C>;
     type KeyStore = Self;
     type AttestationStore = Self;
@@ -406,6 +788,10 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> E
     type Customization = CustomizationImpl;
     type HidConnection = TockHidConnection<S>;
     type Crypto = SoftwareCrypto;
+    type Watchdog = Self;
+    type Display = Self;
+    type Delay = Self;
+    type UpgradeStorage = UpgradeStorage<S, C>;
 
     fn rng(&mut self) -> &mut Self::Rng {
         &mut self.rng
@@ -415,10 +801,25 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> E
         self
     }
 
+    fn user_verification(&mut self) -> &mut Self::UserVerification {
+        self
+    }
+
+    fn user_feedback(&mut self) -> &mut Self::UserFeedback {
+        self
+    }
+
     fn store(&mut self) -> &mut Store<Self::Storage> {
         &mut self.store
     }
 
+    fn secrets_store(&mut self) -> &mut Store<Self::Storage> {
+        match &mut self.secrets_store {
+            Some(secrets_store) => secrets_store,
+            None => &mut self.store,
+        }
+    }
+
     fn key_store(&mut self) -> &mut Self {
         self
     }
@@ -431,12 +832,42 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> E
         &mut self.clock
     }
 
+    fn watchdog(&mut self) -> &mut Self::Watchdog {
+        self
+    }
+
+    fn display(&mut self) -> &mut Self::Display {
+        self
+    }
+
+    fn delay(&mut self) -> &mut Self::Delay {
+        self
+    }
+
+    fn upgrade_storage(&mut self) -> Option<&mut Self::UpgradeStorage> {
+        self.upgrade_storage.as_mut()
+    }
+
+    fn with_upgrade_storage<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self, &mut Self::UpgradeStorage) -> T,
+    ) -> Option<T> {
+        let mut upgrade_storage = self.upgrade_storage.take()?;
+        let result = f(self, &mut upgrade_storage);
+        self.upgrade_storage = Some(upgrade_storage);
+        Some(result)
+    }
+
     fn write(&mut self) -> Self::Write {
         Console::<S>::writer()
     }
 
     fn customization(&self) -> &Self::Customization {
-        &TOCK_CUSTOMIZATION
+        &self.customization
+    }
+
+    fn log_buffer(&mut self) -> Option<&mut LogBuffer> {
+        Some(&mut self.log_buffer)
     }
 
     fn main_hid_connection(&mut self) -> &mut Self::HidConnection {
@@ -448,8 +879,13 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> E
         &mut self.vendor_connection
     }
 
-    fn process_vendor_command(&mut self, bytes: &[u8], channel: Channel) -> Option<Vec<u8>> {
-        commands::process_vendor_command(self, bytes, channel)
+    fn process_vendor_command(
+        &mut self,
+        bytes: &[u8],
+        channel: Channel,
+        pin_uv_auth: &dyn PinUvAuthCheck,
+    ) -> Option<Vec<u8>> {
+        commands::process_vendor_command(self, bytes, channel, Some(pin_uv_auth))
     }
 
     fn firmware_version(&self) -> Option<u64> {
@@ -457,6 +893,10 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> E
             .as_ref()
             .map(|u| u.running_firmware_version())
     }
+
+    fn boot_info(&self) -> Option<BootInfo> {
+        self.boot_info
+    }
 }
 
 pub fn blink_leds<S: Syscalls>(pattern_seed: usize) {
@@ -509,13 +949,58 @@ pub fn switch_off_leds<S: Syscalls>() {
     }
 }
 
+pub fn switch_on_leds<S: Syscalls>() {
+    let count = Leds::<S>::count().unwrap();
+    for l in 0..count {
+        Leds::<S>::on(l).unwrap();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use alloc::vec;
+    use libtock_unittest::fake::Syscalls;
     use opensk::api::customization::is_valid;
+    #[cfg(not(feature = "bbs"))]
+    use opensk::ctap::secret::Secret;
 
     #[test]
     fn test_invariants() {
         assert!(is_valid(&TOCK_CUSTOMIZATION));
     }
+
+    #[test]
+    fn test_attestation_cache() {
+        let mut env = TockEnv::<Syscalls>::default();
+        let id = attestation_store::Id::Batch;
+        assert_eq!(env.get(&id), Ok(None));
+        let attestation = attestation_store::Attestation {
+            private_key: Secret::from_exposed_secret([0x41; 32]),
+            certificate: vec![0xdd; 20],
+        };
+        env.set(&id, Some(&attestation)).unwrap();
+        assert_eq!(env.get(&id), Ok(Some(attestation.clone())));
+        // Reads are served from RAM, writes through the store interface update it.
+        attestation_store::helper_set(&mut env, None).unwrap();
+        assert_eq!(env.get(&id), Ok(Some(attestation)));
+        env.set(&id, None).unwrap();
+        assert_eq!(env.get(&id), Ok(None));
+    }
+
+    #[cfg(feature = "bbs")]
+    #[test]
+    fn test_link_secret_cache() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(env.get_link_secret(), Ok(None));
+        env.set_link_secret(Some(&LinkSecret::from_bytes([0x55; 32])))
+            .unwrap();
+        attestation_store::helper_set_link_secret(&mut env, None).unwrap();
+        assert_eq!(
+            env.get_link_secret(),
+            Ok(Some(LinkSecret::from_bytes([0x55; 32])))
+        );
+        env.set_link_secret(None).unwrap();
+        assert_eq!(env.get_link_secret(), Ok(None));
+    }
 }
@@ -0,0 +1,144 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vendor commands enabled on the device, persisted in the store.
+//!
+//! Shipping devices usually only keep the presentation commands. Changing the permissions requires
+//! an unlocked device, or the admin permission through the provisioning session.
+
+use core::convert::TryFrom;
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::env::Env;
+use opensk::vendor::lockdown::{self, LockdownLevel};
+use opensk::vendor::storage_layout;
+pub use opensk::vendor::Permissions;
+
+/// Key of the environment store for the permissions.
+///
+/// It is persistent, so that a reset doesn't enable commands again.
+pub const STORAGE_KEY: usize = storage_layout::PERMISSIONS;
+
+/// Returns the enabled permissions.
+///
+/// Devices that never stored permissions keep all commands. An unreadable entry only keeps
+/// presentations, to fail closed without locking users out of their credentials.
+pub fn get(env: &mut impl Env) -> Result<Permissions, Ctap2StatusCode> {
+    match env.store().find(STORAGE_KEY)? {
+        None => Ok(Permissions::ALL),
+        Some(value) => match value[..] {
+            [bits] => Ok(Permissions::try_from(bits as u64).unwrap_or(Permissions::BBS_PRESENT)),
+            _ => Ok(Permissions::BBS_PRESENT),
+        },
+    }
+}
+
+/// Replaces the permissions.
+///
+/// Only unlocked devices accept changes, unless the caller is authenticated and holds admin.
+pub fn set(
+    env: &mut impl Env,
+    permissions: Permissions,
+    authenticated: bool,
+) -> Result<(), Ctap2StatusCode> {
+    check_set(env, authenticated)?;
+    write(env, permissions)
+}
+
+/// Returns an error unless the caller may replace the permissions, see `set`.
+///
+/// Lets commands check before they change anything, and write once everything else succeeded.
+pub fn check_set(env: &mut impl Env, authenticated: bool) -> Result<(), Ctap2StatusCode> {
+    let unlocked = lockdown::get(env)? == LockdownLevel::None;
+    if !unlocked && !(authenticated && get(env)?.contains(Permissions::ADMIN)) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+    }
+    Ok(())
+}
+
+/// Replaces the permissions, once `check_set` allowed it.
+pub fn write(env: &mut impl Env, permissions: Permissions) -> Result<(), Ctap2StatusCode> {
+    Ok(env.store().insert(STORAGE_KEY, &[permissions.bits()])?)
+}
+
+/// Returns an error unless any of the `required` permissions is enabled.
+pub fn check(env: &mut impl Env, required: Permissions) -> Result<(), Ctap2StatusCode> {
+    if !get(env)?.intersects(required) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::TockEnv;
+    use super::*;
+    use libtock_unittest::fake::Syscalls;
+
+    #[test]
+    fn test_default_allows_all() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(get(&mut env), Ok(Permissions::ALL));
+        assert_eq!(check(&mut env, Permissions::UPGRADE), Ok(()));
+    }
+
+    #[test]
+    fn test_set_unlocked() {
+        let mut env = TockEnv::<Syscalls>::default();
+        assert_eq!(set(&mut env, Permissions::BBS_PRESENT, false), Ok(()));
+        assert_eq!(get(&mut env), Ok(Permissions::BBS_PRESENT));
+        assert_eq!(check(&mut env, Permissions::BBS_PRESENT), Ok(()));
+        assert_eq!(
+            check(&mut env, Permissions::BBS_ISSUE),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(
+            check(&mut env, Permissions::BBS_ISSUE | Permissions::BBS_PRESENT),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_set_locked() {
+        let mut env = TockEnv::<Syscalls>::default();
+        lockdown::raise(&mut env, LockdownLevel::Config).unwrap();
+        assert_eq!(
+            set(&mut env, Permissions::BBS_PRESENT, false),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(
+            set(
+                &mut env,
+                Permissions::BBS_PRESENT | Permissions::ADMIN,
+                true
+            ),
+            Ok(())
+        );
+        // Without admin, permissions are final.
+        assert_eq!(set(&mut env, Permissions::BBS_PRESENT, true), Ok(()));
+        assert_eq!(
+            set(&mut env, Permissions::ALL, true),
+            Err(Ctap2StatusCode::CTAP2_ERR_NOT_ALLOWED)
+        );
+        assert_eq!(get(&mut env), Ok(Permissions::BBS_PRESENT));
+    }
+
+    #[test]
+    fn test_corrupted_entry_only_presents() {
+        let mut env = TockEnv::<Syscalls>::default();
+        env.store().insert(STORAGE_KEY, &[0xff]).unwrap();
+        assert_eq!(get(&mut env), Ok(Permissions::BBS_PRESENT));
+        env.store().insert(STORAGE_KEY, &[]).unwrap();
+        assert_eq!(get(&mut env), Ok(Permissions::BBS_PRESENT));
+    }
+}
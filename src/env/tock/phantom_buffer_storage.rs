@@ -13,11 +13,13 @@
 // limitations under the License.
 
 use alloc::borrow::Cow;
+use alloc::vec::Vec;
 use core::marker::PhantomData;
 use libtock_platform as platform;
 use libtock_platform::Syscalls;
 use persistent_store::{
-    BufferCorruptFunction, BufferOptions, BufferStorage, Storage, StorageIndex, StorageResult,
+    BufferCorruptFunction, BufferOptions, BufferStorage, Storage, StorageError, StorageIndex,
+    StorageResult,
 };
 
 /// Wrapper with phantom data for the test storage implementation.
@@ -43,6 +45,35 @@ where
         }
     }
 
+    /// Splits the last `num_pages` pages off into a storage of their own.
+    ///
+    /// Operation counters start over on both sides, so this should happen before mounting.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OutOfBounds` if no page would be left on either side.
+    pub fn split_off(&mut self, num_pages: usize) -> StorageResult<Self> {
+        let total_pages = self.storage.num_pages();
+        if num_pages == 0 || num_pages >= total_pages {
+            return Err(StorageError::OutOfBounds);
+        }
+        let options = BufferOptions {
+            word_size: self.storage.word_size(),
+            page_size: self.storage.page_size(),
+            max_word_writes: self.storage.max_word_writes(),
+            max_page_erases: self.storage.max_page_erases(),
+            strict_mode: true,
+        };
+        let mut pages = Vec::new();
+        for page in 0..total_pages {
+            let index = StorageIndex { page, byte: 0 };
+            pages.extend_from_slice(&self.storage.read_slice(index, options.page_size)?);
+        }
+        let split = pages.split_off((total_pages - num_pages) * options.page_size);
+        self.storage = BufferStorage::new(pages.into_boxed_slice(), options.clone());
+        Ok(Self::new(split.into_boxed_slice(), options))
+    }
+
     pub fn arm_interruption(&mut self, delay: usize) {
         self.storage.arm_interruption(delay);
     }
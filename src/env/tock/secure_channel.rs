@@ -0,0 +1,304 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Encrypted and authenticated session for factory provisioning.
+//!
+//! The device agrees on a secret between a fresh ECDH key and the vendor provisioning public key
+//! embedded in the firmware, so only the holder of the matching private key can open a session.
+//! Messages from the host are padded, encrypted with AES-256-CBC and authenticated with
+//! HMAC-SHA256 over a message counter and the ciphertext. The counter prevents replays within a
+//! session, and every session uses new keys.
+//!
+//! Devices that hold a batch attestation key sign their fresh public key with it, so that hosts
+//! know they talk to the device they provisioned, and not to someone in between. Blank devices
+//! have nothing to sign with, so hosts only provision them over a connection they trust.
+
+use alloc::vec::Vec;
+use arrayref::{array_ref, mut_array_refs};
+use opensk::api::attestation_store::{self, AttestationStore};
+use opensk::api::crypto::aes256::Aes256;
+use opensk::api::crypto::ecdh::{PublicKey, SecretKey, SharedSecret};
+use opensk::api::crypto::ecdsa::{SecretKey as _, Signature as _};
+use opensk::api::crypto::hkdf256::Hkdf256;
+use opensk::api::crypto::hmac256::Hmac256;
+use opensk::api::crypto::sha256::Sha256;
+use opensk::api::crypto::{AES_BLOCK_SIZE, EC_FIELD_SIZE, HASH_SIZE};
+use opensk::ctap::crypto_wrapper::aes256_cbc_decrypt;
+use opensk::ctap::secret::Secret;
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::env::{AesKey, EcdhPk, EcdhSk, EcdsaSk, Env, Hkdf, Hmac, Sha};
+
+/// Length of uncompressed P-256 points, as exchanged in this protocol.
+pub use opensk::vendor::PUBLIC_KEY_SIZE;
+
+const ENCRYPTION_KEY_INFO: &[u8] = b"OpenSK provisioning encryption key";
+const MAC_KEY_INFO: &[u8] = b"OpenSK provisioning MAC key";
+/// Prefixes the signed device public key, so that the signature isn't valid for anything else.
+pub const SIGNATURE_CONTEXT: &[u8] = b"OpenSK provisioning device key";
+
+/// Keys and state of an open session.
+pub struct SecureChannel {
+    encryption_key: Secret<[u8; HASH_SIZE]>,
+    mac_key: Secret<[u8; HASH_SIZE]>,
+    /// Number of messages opened so far, expected in the MAC of the next message.
+    counter: u64,
+}
+
+impl SecureChannel {
+    /// Opens a session with the holder of the vendor private key.
+    ///
+    /// Returns the session and the device public key, that the host needs to derive the keys.
+    pub fn setup<E: Env>(
+        env: &mut E,
+        vendor_public_key: &[u8; PUBLIC_KEY_SIZE],
+    ) -> Result<(Self, [u8; PUBLIC_KEY_SIZE]), Ctap2StatusCode> {
+        let vendor_public_key = decode_public_key::<E>(vendor_public_key)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+        let device_key = EcdhSk::<E>::random(env.rng());
+        let device_public_key = encode_public_key::<E>(&device_key.public_key());
+        let mut shared_secret = Secret::from_exposed_secret([0; EC_FIELD_SIZE]);
+        device_key
+            .diffie_hellman(&vendor_public_key)
+            .raw_secret_bytes(&mut shared_secret);
+        let channel = SecureChannel::derive::<E>(&shared_secret, &device_public_key);
+        Ok((channel, device_public_key))
+    }
+
+    /// Signs the device public key of a session with the batch attestation key, if there is one.
+    pub fn sign_public_key<E: Env>(
+        env: &mut E,
+        device_public_key: &[u8; PUBLIC_KEY_SIZE],
+    ) -> Result<Option<Vec<u8>>, Ctap2StatusCode> {
+        let attestation = match env.attestation_store().get(&attestation_store::Id::Batch)? {
+            Some(attestation) => attestation,
+            None => return Ok(None),
+        };
+        let attestation_key = EcdsaSk::<E>::from_slice(&attestation.private_key)
+            .ok_or(Ctap2StatusCode::CTAP2_ERR_VENDOR_INTERNAL_ERROR)?;
+        let mut message = SIGNATURE_CONTEXT.to_vec();
+        message.extend_from_slice(device_public_key);
+        Ok(Some(
+            attestation_key.sign_with_rng(env.rng(), &message).to_der(),
+        ))
+    }
+
+    /// Derives the session keys, on both sides of the channel.
+    ///
+    /// The device public key salts the derivation, binding the keys to this session.
+    pub fn derive<E: Env>(
+        shared_secret: &[u8; EC_FIELD_SIZE],
+        device_public_key: &[u8; PUBLIC_KEY_SIZE],
+    ) -> Self {
+        let salt = Sha::<E>::digest(device_public_key);
+        let mut encryption_key = Secret::from_exposed_secret([0; HASH_SIZE]);
+        Hkdf::<E>::hkdf_256(
+            shared_secret,
+            &salt,
+            ENCRYPTION_KEY_INFO,
+            &mut encryption_key,
+        );
+        let mut mac_key = Secret::from_exposed_secret([0; HASH_SIZE]);
+        Hkdf::<E>::hkdf_256(shared_secret, &salt, MAC_KEY_INFO, &mut mac_key);
+        SecureChannel {
+            encryption_key,
+            mac_key,
+            counter: 0,
+        }
+    }
+
+    /// Authenticates and decrypts the next message from the host.
+    ///
+    /// The ciphertext starts with the IV. Messages must be opened in the order they were sealed.
+    pub fn open<E: Env>(
+        &mut self,
+        ciphertext: &[u8],
+        mac: &[u8; HASH_SIZE],
+    ) -> Result<Secret<[u8]>, Ctap2StatusCode> {
+        if !Hmac::<E>::verify(&self.mac_key, &self.mac_input(ciphertext), mac) {
+            return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+        }
+        self.counter += 1;
+        let aes_key = AesKey::<E>::new(&self.encryption_key);
+        let padded = aes256_cbc_decrypt::<E>(&aes_key, ciphertext, true)?;
+        let length = unpad(&padded).ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+        let mut plaintext = Secret::new(length);
+        plaintext.copy_from_slice(&padded[..length]);
+        Ok(plaintext)
+    }
+
+    /// Pads and encrypts a message, and returns the ciphertext and its MAC.
+    ///
+    /// This is the host side of `open`, the device itself only receives messages.
+    #[cfg(test)]
+    pub fn seal<E: Env>(&mut self, env: &mut E, plaintext: &[u8]) -> (Vec<u8>, [u8; HASH_SIZE]) {
+        use opensk::ctap::crypto_wrapper::aes256_cbc_encrypt;
+
+        let aes_key = AesKey::<E>::new(&self.encryption_key);
+        let ciphertext =
+            aes256_cbc_encrypt::<E>(env.rng(), &aes_key, &pad(plaintext), true).unwrap();
+        let mut mac = [0; HASH_SIZE];
+        Hmac::<E>::mac(&self.mac_key, &self.mac_input(&ciphertext), &mut mac);
+        self.counter += 1;
+        (ciphertext, mac)
+    }
+
+    fn mac_input(&self, ciphertext: &[u8]) -> Vec<u8> {
+        let mut input = self.counter.to_be_bytes().to_vec();
+        input.extend_from_slice(ciphertext);
+        input
+    }
+}
+
+/// Appends PKCS#7 padding to a full AES block.
+pub fn pad(message: &[u8]) -> Vec<u8> {
+    let padding = AES_BLOCK_SIZE - message.len() % AES_BLOCK_SIZE;
+    let mut padded = message.to_vec();
+    padded.resize(message.len() + padding, padding as u8);
+    padded
+}
+
+/// Returns the length of the message without its PKCS#7 padding.
+pub fn unpad(padded: &[u8]) -> Option<usize> {
+    let padding = *padded.last()? as usize;
+    if padding == 0 || padding > AES_BLOCK_SIZE || padding > padded.len() {
+        return None;
+    }
+    let (message, padding_bytes) = padded.split_at(padded.len() - padding);
+    if padding_bytes.iter().any(|&byte| byte as usize != padding) {
+        return None;
+    }
+    Some(message.len())
+}
+
+/// Parses an uncompressed P-256 point.
+pub fn decode_public_key<E: Env>(bytes: &[u8; PUBLIC_KEY_SIZE]) -> Option<EcdhPk<E>> {
+    const B0_BYTE_MARKER: u8 = 0x04;
+    if bytes[0] != B0_BYTE_MARKER {
+        return None;
+    }
+    let x = array_ref!(bytes, 1, EC_FIELD_SIZE);
+    let y = array_ref!(bytes, 1 + EC_FIELD_SIZE, EC_FIELD_SIZE);
+    EcdhPk::<E>::from_coordinates(x, y)
+}
+
+/// Returns the uncompressed encoding of a P-256 point.
+pub fn encode_public_key<E: Env>(public_key: &EcdhPk<E>) -> [u8; PUBLIC_KEY_SIZE] {
+    const B0_BYTE_MARKER: u8 = 0x04;
+    let mut representation = [0; PUBLIC_KEY_SIZE];
+    #[allow(clippy::ptr_offset_with_cast)]
+    let (marker, x, y) = mut_array_refs![&mut representation, 1, EC_FIELD_SIZE, EC_FIELD_SIZE];
+    marker[0] = B0_BYTE_MARKER;
+    public_key.to_coordinates(x, y);
+    representation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use opensk::api::attestation_store::Attestation;
+    use opensk::env::test::TestEnv;
+
+    /// Opens a session like the device, and derives the same keys like the vendor host.
+    fn setup_both_sides(env: &mut TestEnv) -> (SecureChannel, SecureChannel) {
+        let vendor_key = EcdhSk::<TestEnv>::random(env.rng());
+        let vendor_public_key = encode_public_key::<TestEnv>(&vendor_key.public_key());
+        let (device, device_public_key) = SecureChannel::setup(env, &vendor_public_key).unwrap();
+        let device_key = decode_public_key::<TestEnv>(&device_public_key).unwrap();
+        let mut shared_secret = [0; EC_FIELD_SIZE];
+        vendor_key
+            .diffie_hellman(&device_key)
+            .raw_secret_bytes(&mut shared_secret);
+        let host = SecureChannel::derive::<TestEnv>(&shared_secret, &device_public_key);
+        (device, host)
+    }
+
+    #[test]
+    fn test_seal_open() {
+        let mut env = TestEnv::default();
+        let (mut device, mut host) = setup_both_sides(&mut env);
+        for length in [0, 15, 16, 100] {
+            let message = vec![0x55; length];
+            let (ciphertext, mac) = host.seal(&mut env, &message);
+            assert_eq!(
+                device.open::<TestEnv>(&ciphertext, &mac).unwrap()[..],
+                message[..]
+            );
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_replay_and_tampering() {
+        let mut env = TestEnv::default();
+        let (mut device, mut host) = setup_both_sides(&mut env);
+        let (ciphertext, mac) = host.seal(&mut env, b"attestation material");
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 0x01;
+        assert_eq!(
+            device.open::<TestEnv>(&tampered, &mac).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        assert!(device.open::<TestEnv>(&ciphertext, &mac).is_ok());
+        // The counter moved on, so the same message is rejected.
+        assert_eq!(
+            device.open::<TestEnv>(&ciphertext, &mac).err(),
+            Some(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+
+    #[test]
+    fn test_other_vendor_key() {
+        let mut env = TestEnv::default();
+        let (mut device, _) = setup_both_sides(&mut env);
+        let (_, mut other_host) = setup_both_sides(&mut env);
+        let (ciphertext, mac) = other_host.seal(&mut env, b"attestation material");
+        assert!(device.open::<TestEnv>(&ciphertext, &mac).is_err());
+    }
+
+    #[test]
+    fn test_sign_public_key() {
+        let mut env = TestEnv::default();
+        let device_public_key = [0x04; PUBLIC_KEY_SIZE];
+        // Blank devices have nothing to sign with.
+        assert_eq!(
+            SecureChannel::sign_public_key(&mut env, &device_public_key),
+            Ok(None)
+        );
+
+        let private_key = [0x41; EC_FIELD_SIZE];
+        let attestation = Attestation {
+            private_key: Secret::from_exposed_secret(private_key),
+            certificate: vec![0xDD; 20],
+        };
+        env.attestation_store()
+            .set(&attestation_store::Id::Batch, Some(&attestation))
+            .unwrap();
+        let mut message = SIGNATURE_CONTEXT.to_vec();
+        message.extend_from_slice(&device_public_key);
+        let attestation_key = EcdsaSk::<TestEnv>::from_slice(&private_key).unwrap();
+        assert_eq!(
+            SecureChannel::sign_public_key(&mut env, &device_public_key),
+            Ok(Some(attestation_key.sign(&message).to_der()))
+        );
+    }
+
+    #[test]
+    fn test_unpad() {
+        assert_eq!(unpad(&[0x55, 0x03, 0x03, 0x03]), Some(1));
+        assert_eq!(unpad(&[0x10; 16]), Some(0));
+        assert_eq!(unpad(&[0x55, 0x02, 0x03, 0x03]), None);
+        assert_eq!(unpad(&[0x55, 0x00]), None);
+        assert_eq!(unpad(&[]), None);
+    }
+}
@@ -14,7 +14,9 @@
 
 use super::storage_helper::{find_slice, is_aligned, ModRange, Partition};
 use super::upgrade_helper::{
-    check_metadata, parse_metadata_hash, parse_metadata_version, METADATA_SIGN_OFFSET,
+    check_metadata, parse_metadata_hash, parse_metadata_version, parse_partition_status,
+    reset_boot_status, BundleVerification, PartitionStatus, CONFIRMED_OFFSET, METADATA_SIGN_OFFSET,
+    ROLLBACK_OFFSET,
 };
 use super::TockEnv;
 use alloc::borrow::Cow;
@@ -25,6 +27,8 @@ use libtock_drivers::storage::{Storage as LibtockStorage, StorageType};
 use libtock_platform as platform;
 use libtock_platform::Syscalls;
 use opensk::api::crypto::sha256::Sha256;
+use opensk::api::crypto::HASH_SIZE;
+use opensk::api::upgrade_storage::UpgradeStorage;
 use opensk::env::Sha;
 use persistent_store::{Storage, StorageError, StorageIndex, StorageResult};
 
@@ -100,6 +104,44 @@ impl<S: Syscalls, C: platform::subscribe::Config + platform::allow_ro::Config> T
         Ok(syscall)
     }
 
+    /// Splits the last `num_pages` pages off into a storage of their own.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OutOfBounds` if no page would be left on either side.
+    pub fn split_off(&mut self, num_pages: usize) -> StorageResult<TockStorage<S, C>> {
+        if num_pages == 0 || num_pages >= self.num_pages {
+            return Err(StorageError::OutOfBounds);
+        }
+        let mut split_locations = Vec::new();
+        let mut remaining = num_pages * self.page_size;
+        while remaining > 0 {
+            // Locations are page-aligned and the split leaves pages on both sides.
+            let location = self.storage_locations.pop().unwrap();
+            if location.len() <= remaining {
+                remaining -= location.len();
+                split_locations.push(location);
+            } else {
+                let (kept, split) = location.split_at(location.len() - remaining);
+                self.storage_locations.push(kept);
+                split_locations.push(split);
+                remaining = 0;
+            }
+        }
+        split_locations.reverse();
+        self.num_pages -= num_pages;
+        Ok(TockStorage {
+            word_size: self.word_size,
+            page_size: self.page_size,
+            num_pages,
+            max_word_writes: self.max_word_writes,
+            max_page_erases: self.max_page_erases,
+            storage_locations: split_locations,
+            s: PhantomData,
+            c: PhantomData,
+        })
+    }
+
     fn is_word_aligned(&self, x: usize) -> bool {
         is_aligned(self.word_size, x)
     }
@@ -161,7 +203,13 @@ pub struct TockUpgradeStorage<
     partition: Partition,
     metadata: ModRange,
     running_metadata: ModRange,
+    /// Running metadata and firmware, hashed like the partition to measure the firmware.
+    running_partition: Partition,
     identifier: u32,
+    /// Verified chunk at its address, written to flash while the host sends the next one.
+    pending: Option<(usize, Vec<u8>)>,
+    /// Failure to write the pending chunk, reported by the next call to `write_bundle`.
+    pending_error: Option<StorageError>,
     s: PhantomData<S>,
     c: PhantomData<C>,
 }
@@ -197,7 +245,10 @@ where
             partition: Partition::default(),
             metadata: ModRange::new_empty(),
             running_metadata: ModRange::new_empty(),
+            running_partition: Partition::default(),
             identifier: Self::PARTITION_ADDRESS_A as u32,
+            pending: None,
+            pending_error: None,
             s: PhantomData,
             c: PhantomData,
         };
@@ -236,9 +287,20 @@ where
         {
             return Err(StorageError::CustomError);
         }
+        // The running firmware has the same length as the other partition.
+        let mut running_address = Self::PARTITION_ADDRESS_B;
         if firmware_range.start() == Self::PARTITION_ADDRESS_B {
             core::mem::swap(&mut locations.metadata, &mut locations.running_metadata);
             locations.identifier = Self::PARTITION_ADDRESS_B as u32;
+            running_address = Self::PARTITION_ADDRESS_A;
+        }
+        let running_firmware = ModRange::new(running_address, firmware_range.length());
+        if !locations
+            .running_partition
+            .append(locations.running_metadata.clone())
+            || !locations.running_partition.append(running_firmware)
+        {
+            return Err(StorageError::NotAligned);
         }
         if !locations.partition.append(locations.metadata.clone()) {
             return Err(StorageError::NotAligned);
@@ -270,56 +332,121 @@ where
     }
 
     /// Checks if the metadata's hash matches the partition's content.
-    fn check_partition_hash(&self, metadata: &[u8]) -> StorageResult<()> {
-        let start_address = self.metadata.start() + METADATA_SIGN_OFFSET;
+    fn check_partition_hash(
+        &self,
+        metadata: &[u8],
+        keep_going: &mut impl FnMut() -> bool,
+    ) -> StorageResult<()> {
+        let computed_hash = self.hash_partition(&self.partition, &self.metadata, keep_going)?;
+        if &computed_hash != parse_metadata_hash(metadata) {
+            return Err(StorageError::CustomError);
+        }
+        Ok(())
+    }
+
+    /// Hashes the partition from the signed part of its metadata on, like the upgrade tool.
+    fn hash_partition(
+        &self,
+        partition: &Partition,
+        metadata: &ModRange,
+        keep_going: &mut impl FnMut() -> bool,
+    ) -> StorageResult<[u8; 32]> {
+        let start_address = metadata.start() + METADATA_SIGN_OFFSET;
         let mut hasher = Sha::<TockEnv<S>>::new();
-        for range in self.partition.ranges_from(start_address) {
+        for range in partition.ranges_from(start_address) {
             let partition_slice = unsafe { read_slice(range.start(), range.length()) };
-            // The hash implementation handles this in chunks, so no memory issues.
-            hasher.update(partition_slice);
+            // Hashing page by page lets the caller abort in between.
+            for chunk in partition_slice.chunks(self.page_size) {
+                if !keep_going() {
+                    return Err(StorageError::CustomError);
+                }
+                hasher.update(chunk);
+            }
         }
         let mut computed_hash = [0; 32];
         hasher.finalize(&mut computed_hash);
-        if &computed_hash != parse_metadata_hash(metadata) {
+        Ok(computed_hash)
+    }
+
+    /// Measures the running firmware, and stops with an error once `keep_going` returns false.
+    ///
+    /// The hash is computed from flash, so it matches the metadata hash only if the running
+    /// firmware is intact.
+    pub fn running_firmware_hash(
+        &self,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<[u8; 32]> {
+        self.hash_partition(
+            &self.running_partition,
+            &self.running_metadata,
+            &mut keep_going,
+        )
+    }
+
+    /// Checks that the running firmware matches the hash in its metadata.
+    ///
+    /// Stops with an error once `keep_going` returns false.
+    pub fn check_running_firmware(&self, keep_going: impl FnMut() -> bool) -> StorageResult<()> {
+        let running_metadata = unsafe {
+            read_slice(
+                self.running_metadata.start(),
+                self.running_metadata.length(),
+            )
+        };
+        let computed_hash = self.running_firmware_hash(keep_going)?;
+        if &computed_hash != parse_metadata_hash(running_metadata) {
             return Err(StorageError::CustomError);
         }
         Ok(())
     }
 
-    pub fn write_bundle(&mut self, offset: usize, data: Vec<u8>) -> StorageResult<()> {
-        if data.is_empty() {
-            return Err(StorageError::OutOfBounds);
-        }
-        let address = self
-            .partition
-            .find_address(offset, data.len())
-            .ok_or(StorageError::OutOfBounds)?;
-        let write_range = ModRange::new(address, data.len());
-        if self.contains_metadata(&write_range)? {
-            let new_metadata = &data[self.metadata.start() - address..][..self.metadata.length()];
-            check_metadata::<TockEnv<S, C>, S, C>(self, UPGRADE_PUBLIC_KEY, new_metadata)?;
+    /// Writes the pending chunk, if any.
+    ///
+    /// Call it when idle, so that the write overlaps with the host preparing the next chunk.
+    pub fn flush(&mut self) {
+        if let Some((address, data)) = self.pending.take() {
+            if let Err(error) = self.write_chunk(address, &data, &mut || true) {
+                self.pending_error = Some(error);
+            }
         }
+    }
 
+    fn write_chunk(
+        &self,
+        address: usize,
+        data: &[u8],
+        keep_going: &mut impl FnMut() -> bool,
+    ) -> StorageResult<()> {
         // Erases all pages that have their first byte in the write range.
         // Since we expect calls in order, we don't want to erase half-written pages.
-        for address in write_range.aligned_iter(self.page_size) {
+        for address in ModRange::new(address, data.len()).aligned_iter(self.page_size) {
+            if !keep_going() {
+                return Err(StorageError::CustomError);
+            }
             to_storage_result(LibtockStorage::<S, C>::erase_page(address, self.page_size))?;
         }
-        to_storage_result(LibtockStorage::<S, C>::write_slice(address, &data))?;
+        to_storage_result(LibtockStorage::<S, C>::write_slice(address, data))?;
         let written_slice = unsafe { read_slice(address, data.len()) };
         if written_slice != data {
             return Err(StorageError::CustomError);
         }
-        // Case: Last slice is written.
-        if data.len() == self.partition.length() - offset {
-            let metadata = unsafe { read_slice(self.metadata.start(), self.metadata.length()) };
-            self.check_partition_hash(metadata)?;
-        }
         Ok(())
     }
 
-    pub fn bundle_identifier(&self) -> u32 {
-        self.identifier
+    /// Reads the running partition, like the upgrade partition with metadata first.
+    ///
+    /// The slice must not cross the end of the metadata page.
+    pub fn read_running(&self, offset: usize, length: usize) -> StorageResult<&[u8]> {
+        let address = self
+            .running_partition
+            .find_address(offset, length)
+            .ok_or(StorageError::OutOfBounds)?;
+        Ok(unsafe { read_slice(address, length) })
+    }
+
+    /// Clears a word of a boot status, which flash allows without erasing the page.
+    fn clear_status_word(&self, address: usize) -> StorageResult<()> {
+        to_storage_result(LibtockStorage::<S, C>::write_slice(address, &[0; 4]))
     }
 
     pub fn running_firmware_version(&self) -> u64 {
@@ -332,3 +459,167 @@ where
         parse_metadata_version(running_metadata)
     }
 }
+impl<S, C> UpgradeStorage for TockUpgradeStorage<S, C>
+where
+    S: Syscalls,
+    C: platform::allow_ro::Config + platform::subscribe::Config,
+{
+    fn bundle_identifier(&self) -> u32 {
+        self.identifier
+    }
+
+    /// Returns the boot status of the running partition.
+    fn running_status(&self) -> PartitionStatus {
+        let running_metadata = unsafe {
+            read_slice(
+                self.running_metadata.start(),
+                self.running_metadata.length(),
+            )
+        };
+        parse_partition_status(running_metadata)
+    }
+
+    /// Returns the boot status of the partition that upgrades write.
+    fn bundle_status(&self) -> PartitionStatus {
+        let metadata = unsafe { read_slice(self.metadata.start(), self.metadata.length()) };
+        parse_partition_status(metadata)
+    }
+
+    /// Writes a bundle chunk, and stops with an error once `keep_going` returns false.
+    ///
+    /// It is called between erasing pages and while hashing the partition after the last chunk.
+    ///
+    /// Chunks are only verified before returning, and written by the next call to `flush` or
+    /// `write_bundle`. The reply goes out while the flash is written, so the host doesn't wait
+    /// for it. A failed deferred write is reported for the following chunk. The last chunk is
+    /// written right away, because the partition hash is checked against the flash.
+    ///
+    /// Bundles older than `min_version` are refused. Returns the version of the bundle once its
+    /// last chunk is committed.
+    fn write_bundle(
+        &mut self,
+        offset: usize,
+        mut data: Vec<u8>,
+        min_version: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<Option<u64>> {
+        self.flush();
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+        if data.is_empty() {
+            return Err(StorageError::OutOfBounds);
+        }
+        let address = self
+            .partition
+            .find_address(offset, data.len())
+            .ok_or(StorageError::OutOfBounds)?;
+        let write_range = ModRange::new(address, data.len());
+        let mut new_version = None;
+        if self.contains_metadata(&write_range)? {
+            let new_metadata =
+                &mut data[self.metadata.start() - address..][..self.metadata.length()];
+            check_metadata::<TockEnv<S, C>, S, C>(
+                self,
+                UPGRADE_PUBLIC_KEY,
+                new_metadata,
+                min_version,
+            )?;
+            reset_boot_status(new_metadata);
+            new_version = Some(parse_metadata_version(new_metadata));
+        }
+
+        // Case: Last slice is written.
+        if data.len() == self.partition.length() - offset {
+            // The metadata may have been written before the minimum was raised.
+            let version = new_version.unwrap_or_else(|| {
+                parse_metadata_version(unsafe {
+                    read_slice(self.metadata.start(), self.metadata.length())
+                })
+            });
+            if version < min_version {
+                return Err(StorageError::CustomError);
+            }
+            self.write_chunk(address, &data, &mut keep_going)?;
+            let metadata = unsafe { read_slice(self.metadata.start(), self.metadata.length()) };
+            self.check_partition_hash(metadata, &mut keep_going)?;
+            Ok(Some(version))
+        } else {
+            if !keep_going() {
+                return Err(StorageError::CustomError);
+            }
+            self.pending = Some((address, data));
+            Ok(None)
+        }
+    }
+
+    /// Returns the firmware hash in the metadata of the written bundle, once it is written.
+    fn bundle_hash(&mut self) -> StorageResult<[u8; HASH_SIZE]> {
+        self.flush();
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+        if self.bundle_status().version.is_none() {
+            return Err(StorageError::CustomError);
+        }
+        let metadata = unsafe { read_slice(self.metadata.start(), self.metadata.length()) };
+        Ok(*parse_metadata_hash(metadata))
+    }
+
+    /// Verifies the written bundle like its last chunk, without changing the partition.
+    ///
+    /// Devices boot a valid bundle once they restart, so hosts may write bundles early and check
+    /// them before restarting. The previous image fails the version check once replaced. Stops
+    /// with an error once `keep_going` returns false while hashing it.
+    fn verify_bundle(
+        &mut self,
+        min_version: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<BundleVerification> {
+        self.flush();
+        if let Some(error) = self.pending_error.take() {
+            return Err(error);
+        }
+        if self.bundle_status().version.is_none() {
+            return Ok(BundleVerification::Missing);
+        }
+        let metadata = unsafe { read_slice(self.metadata.start(), self.metadata.length()) };
+        if check_metadata::<TockEnv<S, C>, S, C>(self, UPGRADE_PUBLIC_KEY, metadata, min_version)
+            .is_err()
+        {
+            return Ok(BundleVerification::InvalidMetadata);
+        }
+        let computed_hash =
+            self.hash_partition(&self.partition, &self.metadata, &mut keep_going)?;
+        if &computed_hash != parse_metadata_hash(metadata) {
+            return Ok(BundleVerification::HashMismatch);
+        }
+        Ok(BundleVerification::Valid)
+    }
+
+    /// Marks the running image good, so that the bootloader stops counting its boot attempts.
+    fn mark_running_good(&mut self) -> StorageResult<()> {
+        self.clear_status_word(self.running_metadata.start() + CONFIRMED_OFFSET)
+    }
+
+    /// Asks the bootloader to boot the other partition from the next boot on.
+    ///
+    /// The other image must be complete, healthy and at least `min_version`. Stops with an error
+    /// once `keep_going` returns false while hashing it.
+    fn request_rollback(
+        &mut self,
+        min_version: u64,
+        mut keep_going: impl FnMut() -> bool,
+    ) -> StorageResult<()> {
+        self.flush();
+        let status = self.bundle_status();
+        match status.version {
+            Some(version)
+                if version >= min_version && !status.failed() && !status.rollback_requested => {}
+            _ => return Err(StorageError::CustomError),
+        }
+        let metadata = unsafe { read_slice(self.metadata.start(), self.metadata.length()) };
+        self.check_partition_hash(metadata, &mut keep_going)?;
+        self.clear_status_word(self.running_metadata.start() + ROLLBACK_OFFSET)
+    }
+}
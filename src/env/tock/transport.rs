@@ -0,0 +1,157 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Payloads wrapped to a transport key that the receiving device holds in RAM.
+//!
+//! The sender agrees on a secret between a fresh ECDH key and the transport key, and wraps the
+//! payload like provisioning sessions: AES-256-CBC, and HMAC-SHA256 over the ciphertext.
+//!
+//! BBS migration and backup pairing wrap between devices, passkey import from an exporter on the
+//! host, see `bbs_migration`, `bbs_recovery` and the credential import vendor command.
+
+use super::secure_channel::{decode_public_key, encode_public_key, pad, unpad, PUBLIC_KEY_SIZE};
+use alloc::vec::Vec;
+use opensk::api::crypto::ecdh::{SecretKey, SharedSecret};
+use opensk::api::crypto::hkdf256::Hkdf256;
+use opensk::api::crypto::hmac256::Hmac256;
+use opensk::api::crypto::sha256::Sha256;
+use opensk::api::crypto::{EC_FIELD_SIZE, HASH_SIZE};
+use opensk::ctap::crypto_wrapper::{aes256_cbc_decrypt, aes256_cbc_encrypt};
+use opensk::ctap::secret::Secret;
+use opensk::ctap::status_code::Ctap2StatusCode;
+use opensk::env::{AesKey, EcdhSk, Env, Hkdf, Hmac, Sha};
+pub use opensk::vendor::TransportBundle;
+
+// The labels predate other purposes, and stay for compatibility with deployed devices.
+const ENCRYPTION_KEY_INFO: &[u8] = b"OpenSK BBS migration encryption key";
+const MAC_KEY_INFO: &[u8] = b"OpenSK BBS migration MAC key";
+
+/// Generates the transport key pair, and returns its encoded public key.
+pub fn generate_transport_key<E: Env>(env: &mut E) -> (EcdhSk<E>, [u8; PUBLIC_KEY_SIZE]) {
+    let transport_key = EcdhSk::<E>::random(env.rng());
+    let transport_public_key = encode_public_key::<E>(&transport_key.public_key());
+    (transport_key, transport_public_key)
+}
+
+/// Wraps a payload to the transport key.
+///
+/// The purpose is mixed into the keys, so that a bundle only opens for the payload it was made for.
+pub fn wrap<E: Env>(
+    env: &mut E,
+    transport_public_key: &[u8; PUBLIC_KEY_SIZE],
+    purpose: &[u8],
+    plaintext: &[u8],
+) -> Result<TransportBundle, Ctap2StatusCode> {
+    let transport_key = decode_public_key::<E>(transport_public_key)
+        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    let source_key = EcdhSk::<E>::random(env.rng());
+    let source_public_key = encode_public_key::<E>(&source_key.public_key());
+    let mut shared_secret = Secret::from_exposed_secret([0; EC_FIELD_SIZE]);
+    source_key
+        .diffie_hellman(&transport_key)
+        .raw_secret_bytes(&mut shared_secret);
+    let (encryption_key, mac_key) = derive_keys::<E>(
+        &shared_secret,
+        purpose,
+        &source_public_key,
+        transport_public_key,
+    );
+    let aes_key = AesKey::<E>::new(&encryption_key);
+    let ciphertext = aes256_cbc_encrypt::<E>(env.rng(), &aes_key, &pad(plaintext), true)?;
+    let mut mac = [0; HASH_SIZE];
+    Hmac::<E>::mac(&mac_key, &ciphertext, &mut mac);
+    Ok(TransportBundle {
+        source_public_key,
+        ciphertext,
+        mac,
+    })
+}
+
+/// Authenticates and unwraps a payload wrapped for the same purpose.
+pub fn unwrap<E: Env>(
+    transport_key: &EcdhSk<E>,
+    purpose: &[u8],
+    bundle: &TransportBundle,
+) -> Result<Vec<u8>, Ctap2StatusCode> {
+    let source_key = decode_public_key::<E>(&bundle.source_public_key)
+        .ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    let transport_public_key = encode_public_key::<E>(&transport_key.public_key());
+    let mut shared_secret = Secret::from_exposed_secret([0; EC_FIELD_SIZE]);
+    transport_key
+        .diffie_hellman(&source_key)
+        .raw_secret_bytes(&mut shared_secret);
+    let (encryption_key, mac_key) = derive_keys::<E>(
+        &shared_secret,
+        purpose,
+        &bundle.source_public_key,
+        &transport_public_key,
+    );
+    if !Hmac::<E>::verify(&mac_key, &bundle.ciphertext, &bundle.mac) {
+        return Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID);
+    }
+    let aes_key = AesKey::<E>::new(&encryption_key);
+    let padded = aes256_cbc_decrypt::<E>(&aes_key, &bundle.ciphertext, true)?;
+    let length = unpad(&padded).ok_or(Ctap2StatusCode::CTAP1_ERR_INVALID_PARAMETER)?;
+    Ok(padded[..length].to_vec())
+}
+
+/// Derives the encryption and MAC keys, salted with the purpose and both public keys.
+fn derive_keys<E: Env>(
+    shared_secret: &[u8; EC_FIELD_SIZE],
+    purpose: &[u8],
+    source_public_key: &[u8; PUBLIC_KEY_SIZE],
+    transport_public_key: &[u8; PUBLIC_KEY_SIZE],
+) -> (Secret<[u8; HASH_SIZE]>, Secret<[u8; HASH_SIZE]>) {
+    let mut salt_input = purpose.to_vec();
+    salt_input.extend_from_slice(source_public_key);
+    salt_input.extend_from_slice(transport_public_key);
+    let salt = Sha::<E>::digest(&salt_input);
+    let mut encryption_key = Secret::from_exposed_secret([0; HASH_SIZE]);
+    Hkdf::<E>::hkdf_256(
+        shared_secret,
+        &salt,
+        ENCRYPTION_KEY_INFO,
+        &mut encryption_key,
+    );
+    let mut mac_key = Secret::from_exposed_secret([0; HASH_SIZE]);
+    Hkdf::<E>::hkdf_256(shared_secret, &salt, MAC_KEY_INFO, &mut mac_key);
+    (encryption_key, mac_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use opensk::env::test::TestEnv;
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let mut env = TestEnv::default();
+        let (transport_key, transport_public_key) = generate_transport_key(&mut env);
+        let bundle = wrap(&mut env, &transport_public_key, b"purpose", &[0x01; 40]).unwrap();
+        assert_eq!(
+            unwrap::<TestEnv>(&transport_key, b"purpose", &bundle),
+            Ok(vec![0x01; 40])
+        );
+        assert_eq!(
+            unwrap::<TestEnv>(&transport_key, b"other purpose", &bundle),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+        let other_key = EcdhSk::<TestEnv>::random(env.rng());
+        assert_eq!(
+            unwrap::<TestEnv>(&other_key, b"purpose", &bundle),
+            Err(Ctap2StatusCode::CTAP2_ERR_PIN_AUTH_INVALID)
+        );
+    }
+}
@@ -24,11 +24,26 @@ use byteorder::{ByteOrder, LittleEndian};
 use libtock_platform as platform;
 use libtock_platform::Syscalls;
 use opensk::api::crypto::ecdsa::{PublicKey as _, Signature as _};
+use opensk::api::upgrade_storage::UpgradeStorage as _;
 use opensk::env::{EcdsaPk, EcdsaSignature, Env};
+pub use opensk::vendor::{BundleVerification, PartitionStatus, MAX_BOOT_ATTEMPTS};
 use persistent_store::{StorageError, StorageResult};
 
 pub const METADATA_SIGN_OFFSET: usize = 0x800;
 
+/// Offset of the boot status in the metadata, after the signature and not signed over.
+///
+/// The bootloader and the firmware only clear words of the status, so they never erase the page:
+/// - 4 B confirmed, cleared once the image is marked good,
+/// - 4 B rollback, cleared once the image asks to boot the other partition,
+/// - 4 B per boot attempt of an unconfirmed image, cleared by the bootloader before booting it.
+/// Writing a new bundle resets the status of its partition.
+pub const BOOT_STATUS_OFFSET: usize = 0x100;
+pub const CONFIRMED_OFFSET: usize = BOOT_STATUS_OFFSET;
+pub const ROLLBACK_OFFSET: usize = BOOT_STATUS_OFFSET + 4;
+pub const BOOT_ATTEMPTS_OFFSET: usize = BOOT_STATUS_OFFSET + 8;
+pub const BOOT_STATUS_LENGTH: usize = 8 + 4 * MAX_BOOT_ATTEMPTS as usize;
+
 /// Parses the metadata of an upgrade, and checks its correctness.
 ///
 /// The metadata is a page starting with:
@@ -40,6 +55,7 @@ pub const METADATA_SIGN_OFFSET: usize = 0x800;
 /// written at METADATA_SIGN_OFFSET.
 ///
 /// Checks signature correctness against the hash, and whether the partition offset matches.
+/// Versions older than the running firmware or `min_version` are refused.
 /// Whether the hash matches the partition content is not tested here!
 pub fn check_metadata<
     E: Env,
@@ -50,6 +66,7 @@ pub fn check_metadata<
     #[cfg(feature = "std")] upgrade_locations: &BufferUpgradeStorage<S, C>,
     public_key_bytes: &[u8],
     metadata: &[u8],
+    min_version: u64,
 ) -> StorageResult<()> {
     const METADATA_LEN: usize = 0x1000;
     if metadata.len() != METADATA_LEN {
@@ -57,7 +74,7 @@ pub fn check_metadata<
     }
 
     let version = parse_metadata_version(metadata);
-    if version < upgrade_locations.running_firmware_version() {
+    if version < upgrade_locations.running_firmware_version() || version < min_version {
         return Err(StorageError::CustomError);
     }
 
@@ -74,6 +91,13 @@ pub fn check_metadata<
     Ok(())
 }
 
+/// Erases the boot status of new metadata before it is written.
+///
+/// The status isn't signed over, so hosts could otherwise send a bundle that is already confirmed.
+pub fn reset_boot_status(metadata: &mut [u8]) {
+    metadata[BOOT_STATUS_OFFSET..][..BOOT_STATUS_LENGTH].fill(0xFF);
+}
+
 /// Parses the metadata, returns the hash.
 pub fn parse_metadata_hash(data: &[u8]) -> &[u8; 32] {
     array_ref!(data, 0, 32)
@@ -84,6 +108,20 @@ pub fn parse_metadata_version(data: &[u8]) -> u64 {
     LittleEndian::read_u64(&data[METADATA_SIGN_OFFSET..][..8])
 }
 
+/// Parses the metadata, returns the boot status of its partition.
+pub fn parse_partition_status(data: &[u8]) -> PartitionStatus {
+    let is_cleared = |offset: usize| data[offset..][..4] != [0xFF; 4];
+    let erased = data[METADATA_SIGN_OFFSET..][..8] == [0xFF; 8];
+    PartitionStatus {
+        version: Some(parse_metadata_version(data)).filter(|_| !erased),
+        boot_attempts: (0..MAX_BOOT_ATTEMPTS)
+            .filter(|i| is_cleared(BOOT_ATTEMPTS_OFFSET + 4 * *i as usize))
+            .count() as u32,
+        confirmed: is_cleared(CONFIRMED_OFFSET),
+        rollback_requested: is_cleared(ROLLBACK_OFFSET),
+    }
+}
+
 /// Verifies the signature over the given hash.
 ///
 /// The public key is COSE encoded, and the hash is a SHA256.
@@ -139,6 +177,7 @@ mod test {
         const METADATA_LEN: usize = 0x1000;
         const METADATA_SIGN_OFFSET: usize = 0x800;
         let mut metadata = vec![0xFF; METADATA_LEN];
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 5);
         LittleEndian::write_u32(&mut metadata[METADATA_SIGN_OFFSET + 8..][..4], 0x60000);
 
         let mut signed_over_data = metadata[METADATA_SIGN_OFFSET..].to_vec();
@@ -158,18 +197,31 @@ mod test {
             check_metadata::<TestEnv, Syscalls, DefaultConfig>(
                 &upgrade_locations,
                 &public_key_bytes,
-                &metadata
+                &metadata,
+                5
             ),
             Ok(())
         );
 
+        // Versions below the minimum fail.
+        assert_eq!(
+            check_metadata::<TestEnv, Syscalls, DefaultConfig>(
+                &upgrade_locations,
+                &public_key_bytes,
+                &metadata,
+                6
+            ),
+            Err(StorageError::CustomError)
+        );
+
         // Manipulating the partition address fails.
         metadata[METADATA_SIGN_OFFSET + 8] = 0x88;
         assert_eq!(
             check_metadata::<TestEnv, Syscalls, DefaultConfig>(
                 &upgrade_locations,
                 &public_key_bytes,
-                &metadata
+                &metadata,
+                0
             ),
             Err(StorageError::CustomError)
         );
@@ -179,7 +231,8 @@ mod test {
             check_metadata::<TestEnv, Syscalls, DefaultConfig>(
                 &upgrade_locations,
                 &public_key_bytes,
-                &metadata[..METADATA_LEN - 1]
+                &metadata[..METADATA_LEN - 1],
+                0
             ),
             Err(StorageError::CustomError)
         );
@@ -189,7 +242,8 @@ mod test {
             check_metadata::<TestEnv, Syscalls, DefaultConfig>(
                 &upgrade_locations,
                 &public_key_bytes,
-                &metadata
+                &metadata,
+                0
             ),
             Err(StorageError::CustomError)
         );
@@ -200,12 +254,43 @@ mod test {
             check_metadata::<TestEnv, Syscalls, DefaultConfig>(
                 &upgrade_locations,
                 &public_key_bytes,
-                &metadata
+                &metadata,
+                0
             ),
             Err(StorageError::CustomError)
         );
     }
 
+    #[test]
+    fn test_parse_partition_status() {
+        let mut metadata = vec![0xFF; 0x1000];
+        assert_eq!(
+            parse_partition_status(&metadata),
+            PartitionStatus {
+                version: None,
+                boot_attempts: 0,
+                confirmed: false,
+                rollback_requested: false,
+            }
+        );
+
+        LittleEndian::write_u64(&mut metadata[METADATA_SIGN_OFFSET..][..8], 3);
+        metadata[BOOT_ATTEMPTS_OFFSET..][..8].copy_from_slice(&[0; 8]);
+        let status = parse_partition_status(&metadata);
+        assert_eq!(status.version, Some(3));
+        assert_eq!(status.boot_attempts, 2);
+        assert!(!status.failed());
+
+        metadata[BOOT_ATTEMPTS_OFFSET + 8..][..4].copy_from_slice(&[0; 4]);
+        assert!(parse_partition_status(&metadata).failed());
+        metadata[CONFIRMED_OFFSET..][..4].copy_from_slice(&[0; 4]);
+        metadata[ROLLBACK_OFFSET..][..4].copy_from_slice(&[0; 4]);
+        let status = parse_partition_status(&metadata);
+        assert!(status.confirmed);
+        assert!(status.rollback_requested);
+        assert!(!status.failed());
+    }
+
     #[test]
     fn test_verify_signature() {
         let mut env = TestEnv::default();
@@ -0,0 +1,67 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vendor command parameters and responses of the Tock environment.
+//!
+//! Most of them are shared with fuzzers and host tools, see `opensk::vendor::parameters`. Only
+//! responses that carry types of the Tock runtime are defined here.
+
+use lang_items::crash_report::CrashReport;
+pub use opensk::vendor::parameters::*;
+use sk_cbor as cbor;
+use sk_cbor::cbor_map_options;
+
+/// Last crash report, an empty map if the device didn't crash since it was cleared.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VendorCrashReportResponse {
+    pub report: Option<CrashReport>,
+}
+
+impl From<VendorCrashReportResponse> for cbor::Value {
+    fn from(vendor_crash_report_response: VendorCrashReportResponse) -> Self {
+        let report = match vendor_crash_report_response.report {
+            Some(report) => report,
+            None => return cbor_map_options! {},
+        };
+        cbor_map_options! {
+            0x01 => report.last_command.map(|command| command as u64),
+            0x02 => report.stack_high_water as u64,
+            0x03 => report.message().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cbor::cbor_map;
+
+    #[test]
+    fn test_vendor_crash_report_into_cbor() {
+        let response = VendorCrashReportResponse { report: None };
+        let response_cbor: cbor::Value = response.into();
+        assert_eq!(response_cbor, cbor_map! {});
+
+        let report = CrashReport::new(None, 2048, format_args!("panicked"));
+        let response = VendorCrashReportResponse {
+            report: Some(report),
+        };
+        let response_cbor: cbor::Value = response.into();
+        let expected_cbor = cbor_map! {
+            0x02 => 2048,
+            0x03 => b"panicked".to_vec(),
+        };
+        assert_eq!(response_cbor, expected_cbor);
+    }
+}
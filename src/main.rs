@@ -22,39 +22,58 @@ extern crate byteorder;
 extern crate core;
 extern crate lang_items;
 
+use arrayref::array_ref;
+use core::cell::Cell;
 use core::convert::TryFrom;
 #[cfg(feature = "debug_ctap")]
 use core::fmt::Write;
-#[cfg(feature = "with_ctap1")]
-use ctap2::env::tock::blink_leds;
-use ctap2::env::tock::{switch_off_leds, wink_leds, TockEnv};
+use ctap2::env::tock::TockEnv;
 #[cfg(feature = "with_ctap1")]
 use libtock_buttons::Buttons;
 #[cfg(feature = "debug_ctap")]
 use libtock_console::Console;
-#[cfg(feature = "debug_ctap")]
-use libtock_console::ConsoleWriter;
-use libtock_drivers::result::FlexUnwrap;
+#[cfg(feature = "ipc")]
+use libtock_drivers::ipc::{Ipc, IpcRequest};
+use libtock_drivers::result::{FlexUnwrap, TockResult};
 use libtock_drivers::timer::Duration;
-use libtock_drivers::usb_ctap_hid;
+#[cfg(feature = "ccid")]
+use libtock_drivers::usb_ccid;
+use libtock_drivers::util::Util;
+use libtock_drivers::{timer, usb_ctap_hid};
+use libtock_platform::DefaultConfig;
 #[cfg(not(feature = "std"))]
 use libtock_runtime::{set_main, stack_size, TockSyscalls};
 #[cfg(feature = "std")]
 use libtock_unittest::fake;
 use opensk::api::clock::Clock;
 use opensk::api::connection::UsbEndpoint;
+use opensk::api::user_feedback::FeedbackState;
+use opensk::api::watchdog::{self, Watchdog};
 use opensk::ctap::hid::HidPacketIterator;
+use opensk::ctap::log::Level;
+#[cfg(feature = "ipc")]
+use opensk::ctap::status_code::Ctap2StatusCode;
 use opensk::ctap::KEEPALIVE_DELAY_MS;
 use opensk::env::Env;
-use opensk::Transport;
+use opensk::{log_ctap, Transport};
 
 #[cfg(not(feature = "std"))]
 stack_size! {0x8000}
+// Must match the stack size above.
+#[cfg(not(feature = "std"))]
+const STACK_SIZE: usize = 0x8000;
 #[cfg(not(feature = "std"))]
 set_main! {main}
 
 const SEND_TIMEOUT_MS: Duration<isize> = Duration::from_ms(1000);
 const KEEPALIVE_DELAY_MS_TOCK: Duration<isize> = Duration::from_ms(KEEPALIVE_DELAY_MS as isize);
+// Without any event, wake up well before the watchdog fires or the clock wraps.
+const IDLE_WAKEUP_DELAY: Duration<isize> = Duration::from_ms(10000);
+// While background maintenance remains, only check for events between its slices.
+const IDLE_WORK_DELAY: Duration<isize> = Duration::from_ms(1);
+// After this many consecutive USB driver errors without a successful transfer, the device reboots.
+// Timeouts don't count, since a suspended or unplugged host doesn't read anything either.
+const MAX_USB_FAILURES: usize = 16;
 
 #[cfg(not(feature = "vendor_hid"))]
 const NUM_ENDPOINTS: usize = 1;
@@ -124,7 +143,87 @@ impl EndpointReplies {
     }
 }
 
+fn transport(endpoint: UsbEndpoint) -> Transport {
+    match endpoint {
+        UsbEndpoint::MainHid => Transport::MainHid,
+        #[cfg(feature = "vendor_hid")]
+        UsbEndpoint::VendorHid => Transport::VendorHid,
+    }
+}
+
+/// Sources that woke up the main loop.
+#[derive(Default)]
+struct Events {
+    /// Endpoint of a received HID packet.
+    hid: Cell<Option<u32>>,
+    /// Length of a received CCID message.
+    #[cfg(feature = "ccid")]
+    ccid: Cell<Option<usize>>,
+    #[cfg(feature = "ipc")]
+    ipc: Cell<Option<IpcRequest>>,
+    /// The wakeup delay elapsed.
+    elapsed: Cell<bool>,
+}
+
+impl Events {
+    fn any(&self) -> bool {
+        #[cfg(feature = "ccid")]
+        if self.ccid.get().is_some() {
+            return true;
+        }
+        #[cfg(feature = "ipc")]
+        if self.ipc.get().is_some() {
+            return true;
+        }
+        self.hid.get().is_some() || self.elapsed.get()
+    }
+}
+
+/// Buffers that the kernel fills with incoming data.
+struct Inbox {
+    hid_packet: [u8; 64],
+    /// None if the board has no CCID interface.
+    #[cfg(feature = "ccid")]
+    ccid_message: Option<[u8; usb_ccid::MAX_MESSAGE_LENGTH]>,
+}
+
+impl Inbox {
+    /// Sleeps until data arrives on any transport, or the delay elapses.
+    ///
+    /// All sources are armed before a single yield, so the app neither wakes up periodically
+    /// nor waits on one transport while another one has data.
+    fn wait_for_events(&mut self, events: &Events, delay: Duration<isize>) -> TockResult<()> {
+        let hid_packet = &mut self.hid_packet;
+        #[cfg(feature = "ccid")]
+        let ccid_message = self.ccid_message.as_mut();
+
+        let wait = || {
+            timer::with_alarm::<SyscallImplementation, DefaultConfig, _>(
+                delay,
+                &events.elapsed,
+                || Util::<SyscallImplementation>::yieldk_for(|| events.any()),
+            )
+        };
+        #[cfg(feature = "ipc")]
+        let wait =
+            || Ipc::<SyscallImplementation>::listen(&events.ipc, wait).and_then(|result| result);
+        #[cfg(feature = "ccid")]
+        let wait = || match ccid_message {
+            Some(buffer) => {
+                usb_ccid::UsbCcid::<SyscallImplementation>::listen(buffer, &events.ccid, wait)
+                    .and_then(|result| result)
+            }
+            None => wait(),
+        };
+        usb_ctap_hid::UsbCtapHid::<SyscallImplementation>::listen(hid_packet, &events.hid, wait)
+            .and_then(|result| result)
+    }
+}
+
 fn main() {
+    // Crash reports include the stack high-water mark.
+    #[cfg(not(feature = "std"))]
+    lang_items::crash_report::watch_stack(STACK_SIZE);
     #[cfg(feature = "debug_ctap")]
     let mut writer = Console::<SyscallImplementation>::writer();
     #[cfg(feature = "debug_ctap")]
@@ -136,79 +235,97 @@ fn main() {
         panic!("Cannot setup USB driver");
     }
 
-    let env = TockEnv::<SyscallImplementation>::default();
+    let mut inbox = Inbox {
+        hid_packet: [0; 64],
+        #[cfg(feature = "ccid")]
+        ccid_message: if usb_ccid::UsbCcid::<SyscallImplementation>::setup() {
+            Some([0; usb_ccid::MAX_MESSAGE_LENGTH])
+        } else {
+            None
+        },
+    };
+
+    #[cfg_attr(not(feature = "ipc"), allow(unused_mut))]
+    let mut env = TockEnv::<SyscallImplementation>::default();
+    // Apps are denied IPC commands, unless granted when building.
+    #[cfg(feature = "ipc")]
+    for &(client, capabilities) in ctap2::env::tock::ipc::IPC_GRANTS {
+        env.grant_ipc_capabilities(client, capabilities);
+    }
     let mut ctap = opensk::Ctap::new(env);
+    // Safety: `ctap` lives until the end of `main`, which never returns.
+    unsafe { ctap.env().install_crash_hook() };
+    // Boards without a watchdog driver keep running without one.
+    let watchdog_timeout_ms = watchdog::timeout_ms(ctap.env().customization());
+    if let Err(e) = ctap.env().watchdog().start(watchdog_timeout_ms) {
+        log_ctap!(ctap.env(), Level::Warn, "Watchdog not started: {:?}", e);
+    }
 
     let mut led_counter = 0;
     let mut led_blink_timer =
         <<TockEnv<SyscallImplementation> as Env>::Clock as Clock>::Timer::default();
 
     let mut replies = EndpointReplies::new();
+    // Consecutive USB failures, reset by any successful transfer.
+    let mut usb_failures = 0;
 
-    // Main loop. If CTAP1 is used, we register button presses for U2F while receiving and waiting.
+    // Main loop. It sleeps until a transport has data, and only wakes up regularly while the
+    // user is signaled something, e.g. blinking LEDs or U2F waiting for a button press.
     // The way TockOS and apps currently interact, callbacks need a yield syscall to execute,
     // making consistent blinking patterns and sending keepalives harder.
 
-    #[cfg(feature = "debug_ctap")]
-    writeln!(writer, "Entering main ctap loop").unwrap();
+    log_ctap!(ctap.env(), Level::Info, "Entering main ctap loop");
     loop {
         #[cfg(feature = "with_ctap1")]
         let num_buttons = Buttons::<SyscallImplementation>::count().ok().unwrap();
 
-        // Variable for use in both the send_and_maybe_recv and recv cases.
-        let mut usb_endpoint: Option<UsbEndpoint> = None;
-        let mut pkt_request = [0; 64];
+        if usb_failures >= MAX_USB_FAILURES {
+            log_ctap!(
+                ctap.env(),
+                Level::Error,
+                "Persistent USB failure, rebooting"
+            );
+            ctap.env().watchdog().reboot();
+        }
 
+        let events = Events::default();
         if let Some(packet) = replies.next_packet() {
             match usb_ctap_hid::UsbCtapHid::<SyscallImplementation>::send(
                 &packet.packet,
                 SEND_TIMEOUT_MS,
                 packet.endpoint as u32,
-            )
-            .flex_unwrap()
-            {
-                usb_ctap_hid::SendOrRecvStatus::Sent => {
-                    #[cfg(feature = "debug_ctap")]
-                    print_packet_notice::<SyscallImplementation>(
-                        "Sent packet",
-                        ctap.env().clock().timestamp_us(),
-                        &mut writer,
-                    );
+            ) {
+                Ok(usb_ctap_hid::SendOrRecvStatus::Sent) => {
+                    log_packet_notice(ctap.env(), Level::Trace, "Sent packet");
+                    usb_failures = 0;
                 }
-                usb_ctap_hid::SendOrRecvStatus::Timeout => {
-                    #[cfg(feature = "debug_ctap")]
-                    print_packet_notice::<SyscallImplementation>(
-                        "Timeout while sending packet",
-                        ctap.env().clock().timestamp_us(),
-                        &mut writer,
-                    );
-                    // The client is unresponsive, so we discard all pending packets.
+                result => {
+                    log_packet_notice(ctap.env(), Level::Warn, "Failed to send packet");
+                    // The client is unresponsive, so we discard all pending packets. Its channel
+                    // is invalidated, since the rest of its reply is lost.
                     replies.clear(packet.endpoint);
+                    let cid = *array_ref!(packet.packet, 0, 4);
+                    ctap.invalidate_channel(transport(packet.endpoint), cid);
+                    if result.is_err() {
+                        usb_failures += 1;
+                    }
                 }
-                _ => panic!("Unexpected status on USB transmission"),
             };
         } else {
-            usb_endpoint =
-                match usb_ctap_hid::UsbCtapHid::<SyscallImplementation>::recv_with_timeout(
-                    &mut pkt_request,
-                    KEEPALIVE_DELAY_MS_TOCK,
-                )
-                .flex_unwrap()
-                {
-                    usb_ctap_hid::SendOrRecvStatus::Received(endpoint) => {
-                        #[cfg(feature = "debug_ctap")]
-                        print_packet_notice::<SyscallImplementation>(
-                            "Received packet",
-                            ctap.env().clock().timestamp_us(),
-                            &mut writer,
-                        );
-                        UsbEndpoint::try_from(endpoint as usize).ok()
-                    }
-                    usb_ctap_hid::SendOrRecvStatus::Sent => {
-                        panic!("Returned transmit status on receive")
-                    }
-                    usb_ctap_hid::SendOrRecvStatus::Timeout => None,
-                };
+            // Upgrade chunks are written after their reply, while the host sends the next one.
+            ctap.env().flush_upgrade_storage();
+            // Pending replies are sent first, so that multi-packet messages are not delayed.
+            // Maintenance runs one slice per iteration, so packets wait for one slice at most.
+            let delay = if ctap.process_idle_work() {
+                IDLE_WORK_DELAY
+            } else if ctap.feedback_state() == FeedbackState::Idle {
+                IDLE_WAKEUP_DELAY
+            } else {
+                KEEPALIVE_DELAY_MS_TOCK
+            };
+            if inbox.wait_for_events(&events, delay).is_err() {
+                usb_failures += 1;
+            }
         }
 
         #[cfg(feature = "with_ctap1")]
@@ -222,26 +339,26 @@ fn main() {
         // This call is making sure that even for long inactivity, wrapping clock values
         // don't cause problems with timers.
         ctap.env().clock().tickle();
+        ctap.env().watchdog().feed();
 
+        let usb_endpoint = events.hid.get().and_then(|endpoint| {
+            log_packet_notice(ctap.env(), Level::Trace, "Received packet");
+            UsbEndpoint::try_from(endpoint as usize).ok()
+        });
         if let Some(endpoint) = usb_endpoint {
-            let transport = match endpoint {
-                UsbEndpoint::MainHid => Transport::MainHid,
-                #[cfg(feature = "vendor_hid")]
-                UsbEndpoint::VendorHid => Transport::VendorHid,
-            };
-            let reply = ctap.process_hid_packet(&pkt_request, transport);
+            usb_failures = 0;
+            let reply = ctap.process_hid_packet(&inbox.hid_packet, transport(endpoint));
             if reply.has_data() {
                 // Update endpoint with the reply.
                 for ep in replies.replies.iter_mut() {
                     if ep.endpoint == endpoint {
                         if ep.reply.has_data() {
-                            #[cfg(feature = "debug_ctap")]
-                            writeln!(
-                                Console::<SyscallImplementation>::writer(),
-                                "Warning overwriting existing reply for endpoint {}",
+                            log_ctap!(
+                                ctap.env(),
+                                Level::Warn,
+                                "Overwriting existing reply for endpoint {}",
                                 endpoint as usize
-                            )
-                            .unwrap();
+                            );
                         }
                         ep.reply = reply;
                         break;
@@ -250,42 +367,61 @@ fn main() {
             }
         }
 
+        #[cfg(feature = "ccid")]
+        {
+            if let (Some(length), Some(message)) = (events.ccid.get(), &inbox.ccid_message) {
+                let response = ctap.process_ccid_message(&message[..length]);
+                if !response.is_empty() {
+                    let result = usb_ccid::UsbCcid::<SyscallImplementation>::send(
+                        &response,
+                        SEND_TIMEOUT_MS,
+                    );
+                    if !matches!(result, Ok(true)) {
+                        log_ctap!(ctap.env(), Level::Warn, "Failed to send CCID message");
+                    }
+                    if result.is_err() {
+                        usb_failures += 1;
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "ipc")]
+        {
+            if let Some(request) = events.ipc.get() {
+                // Safety: the buffer is only used until the client is notified.
+                let buffer = unsafe { request.buffer() };
+                let response = ctap.env().process_ipc_request(request.client, buffer);
+                if response.len() <= buffer.len() {
+                    buffer[..response.len()].copy_from_slice(&response);
+                } else if let Some(status) = buffer.first_mut() {
+                    *status = Ctap2StatusCode::CTAP1_ERR_INVALID_LENGTH as u8;
+                }
+                Ipc::<SyscallImplementation>::notify_client(request).flex_unwrap();
+            }
+        }
+
         if ctap.env().clock().is_elapsed(&led_blink_timer) {
-            // Loops quickly when waiting for U2F user presence, so the next LED blink
-            // state is only set if enough time has elapsed.
+            // Packets also wake up the loop, so the next LED blink state is only set if enough
+            // time has elapsed.
             led_counter += 1;
             led_blink_timer = ctap.env().clock().make_timer(KEEPALIVE_DELAY_MS)
         }
 
-        if ctap.should_wink() {
-            wink_leds::<SyscallImplementation>(led_counter);
-        } else {
-            #[cfg(not(feature = "with_ctap1"))]
-            switch_off_leds::<SyscallImplementation>();
-            #[cfg(feature = "with_ctap1")]
-            if ctap.u2f_needs_user_presence() {
-                // Flash the LEDs with an almost regular pattern. The inaccuracy comes from
-                // delay caused by processing and sending of packets.
-                blink_leds::<SyscallImplementation>(led_counter);
-            } else {
-                switch_off_leds::<SyscallImplementation>();
-            }
-        }
+        // Animates the LEDs with an almost regular pattern. The inaccuracy comes from delay caused
+        // by processing and sending of packets.
+        ctap.update_feedback(led_counter);
     }
 }
 
-#[cfg(feature = "debug_ctap")]
-fn print_packet_notice<S: libtock_platform::Syscalls>(
-    notice_text: &str,
-    now_us: usize,
-    writer: &mut ConsoleWriter<S>,
-) {
-    writeln!(
-        writer,
-        "{} at {}.{:06} s",
+fn log_packet_notice(env: &mut TockEnv<SyscallImplementation>, level: Level, notice_text: &str) {
+    let now_ms = env.clock().uptime_ms();
+    log_ctap!(
+        env,
+        level,
+        "{} at {}.{:03} s",
         notice_text,
-        now_us / 1_000_000,
-        now_us % 1_000_000
-    )
-    .unwrap();
+        now_ms / 1000,
+        now_ms % 1000
+    );
 }
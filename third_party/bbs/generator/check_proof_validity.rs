@@ -1,17 +1,26 @@
 extern crate std;
 
-use bbs::{verify_link_secret_commitment, BBSPoK};
+use bbs::{
+    generate_link_secret_commitment, verify_link_secret_commitment, verify_presentation,
+    LinkSecret, SeededRng,
+};
 use serde_json::Value;
 use std::{fs, io};
-use zkryptium::bbsplus::keys::BBSplusPublicKey;
 
-fn main() -> io::Result<()> {
-    let file_path = "fixtures/proof.json";
+const CIPHERSUITE: &str = "BLS12-381-SHAKE-256";
+const FIXTURE_DIRECTORY: &str = "fixtures";
+
+fn check_fixture(file_path: &str) -> io::Result<()> {
+    println!("Checking {}.", file_path);
     let contents = fs::read_to_string(file_path)?;
     let json: Value = serde_json::from_str(&contents)?;
 
+    // Fixtures older than the ciphersuite field all use the only implemented one.
+    if let Some(ciphersuite) = json["ciphersuite"].as_str() {
+        assert_eq!(ciphersuite, CIPHERSUITE, "Ciphersuite should be supported.");
+    }
+
     let pk_hex = json["signerKeyPair"]["publicKey"].as_str().unwrap();
-    let pk = BBSplusPublicKey::from_bytes(&hex::decode(pk_hex).unwrap()).unwrap();
 
     // check the commitment validity
     let commitment_with_proof_hex = json["commitmentWithProof"].as_str().unwrap();
@@ -20,48 +29,70 @@ fn main() -> io::Result<()> {
     assert!(result, "Commitment should be valid.");
     println!("Commitment is valid.");
 
-    // header
-    let header = json["header"].as_str().unwrap().as_bytes().to_vec();
-    let presentation_header = json["presentationHeader"]
-        .as_str()
-        .unwrap()
-        .as_bytes()
-        .to_vec();
+    // check that the fixture is reproducible from its seed
+    if let Some(seed) = json["seed"].as_u64() {
+        let mut rng = SeededRng::from_seed_u64(seed);
+        let link_secret = LinkSecret::random(&mut rng);
+        assert_eq!(
+            hex::encode(link_secret.to_bytes()),
+            json["linkSecret"].as_str().unwrap(),
+            "Link secret should match the seed."
+        );
+        let (commitment_with_proof, _) =
+            generate_link_secret_commitment(&mut rng, &link_secret).unwrap();
+        assert_eq!(
+            hex::encode(&*commitment_with_proof),
+            commitment_with_proof_hex,
+            "Commitment should match the seed."
+        );
+        println!("Fixture matches seed {}.", seed);
+    }
 
-    // proof
-    let proof_bytes = hex::decode(json["proof"].as_str().unwrap()).unwrap();
-    let proof = BBSPoK::from_bytes(&proof_bytes).unwrap();
+    // proof, verified like a relying party
+    let text = |key: &str| json[key].as_str().unwrap().as_bytes().to_vec();
     let disclosed_messages: Vec<Vec<u8>> = json["outputDisclosedMessages"]
         .as_array()
         .unwrap()
         .iter()
         .map(|s| hex::decode(s.as_str().unwrap()).unwrap())
         .collect();
-    // The reason why the indexes are incremented by 2 is because the first two indexes
-    // are reserved for `secret_prover_blind + signer_blind` and `link_secret`
-    // https://github.com/Cybersecurity-LINKS/zkryptium/blob/0e21c20f4c84473e7eb69a1aef136159c9d085b8/src/utils/util.rs#L403-L453
-    // let disclosed_indexes: Vec<usize> = disclosed_indexes.iter().map(|&i| i + 2).collect();
-    // let disclosed_indexes: &[usize] = disclosed_indexes.as_slice();
     let disclosed_indexes: Vec<usize> = json["disclosedIndexes"]
         .as_array()
         .unwrap()
         .iter()
-        .map(|v| (v.as_u64().unwrap() as usize) + 2)
+        .map(|v| v.as_u64().unwrap() as usize)
         .collect();
-    let disclosed_messages: Option<Vec<Vec<u8>>> = Some(disclosed_messages);
-    let disclosed_indexes: Option<Vec<usize>> = Some(disclosed_indexes);
-
-    let result = proof
-        .blind_proof_verify(
-            &pk,
-            disclosed_messages.as_ref().map(|v| v.as_slice()),
-            disclosed_indexes.as_ref().map(|v| v.as_slice()),
-            Some(&header),
-            Some(&presentation_header),
-        )
-        .is_ok();
+    let result = verify_presentation(
+        &hex::decode(pk_hex).unwrap(),
+        &hex::decode(json["proof"].as_str().unwrap()).unwrap(),
+        &text("header"),
+        &text("presentationHeader"),
+        &disclosed_messages,
+        &disclosed_indexes,
+    );
     assert!(result, "Proof should be valid");
     println!("Proof is valid.");
 
     Ok(())
 }
+
+/// Checks the fixtures given as arguments, or all those of the fixture directory.
+fn main() -> io::Result<()> {
+    let mut paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        for entry in fs::read_dir(FIXTURE_DIRECTORY)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .map_or(false, |extension| extension == "json")
+            {
+                paths.push(path.to_string_lossy().into_owned());
+            }
+        }
+        paths.sort();
+    }
+    for path in &paths {
+        check_fixture(path)?;
+    }
+    Ok(())
+}
@@ -1,21 +1,194 @@
 extern crate std;
 
 use bbs::{
-    generate_link_secret_commitment, generate_proof, BBSCommitmentBlindFactor, BBSPublicKey,
-    BBSSecretKey, BBSSignature, LinkSecret, BBS,
+    generate_link_secret_commitment, generate_proof, verify_presentation, BBSCommitmentBlindFactor,
+    BBSPublicKey, BBSSecretKey, BBSSignature, LinkSecret, SeededRng, BBS,
 };
-use rand_core::OsRng;
+use rand_core::{OsRng, RngCore};
 use serde_json::{json, Value};
 use std::fs;
 use std::io::{self, Write};
+use std::process::exit;
 use zkryptium::schemes::generics::BlindSignature;
 
-fn main() -> io::Result<()> {
-    let file_path = "fixtures/proof.json";
-    let contents = fs::read_to_string(file_path)?;
-    let mut json: Value = serde_json::from_str(&contents)?;
+/// The only ciphersuite of the crate, see `BBSCiphersuite`.
+const CIPHERSUITE: &str = "BLS12-381-SHAKE-256";
+const TEMPLATE_PATH: &str = "fixtures/proof.json";
+const SUITE_DIRECTORY: &str = "fixtures";
+
+const USAGE: &str = "\
+Usage: generator [SEED] [OPTIONS]
+
+Regenerates fixtures/proof.json, or writes a new fixture with the given parameters. The signer key
+pair always comes from fixtures/proof.json, and unset parameters keep its values.
+
+Options:
+    --seed N                   seed of the randomness, recorded in the fixture
+    --messages N               signs N generated messages instead of those of the template
+    --disclose none|all|I,J,.. message indexes to disclose
+    --header TEXT              header of the signature
+    --presentation-header TEXT header of the proof
+    --ciphersuite NAME         only BLS12-381-SHAKE-256 is implemented
+    --output PATH              file to write, the template by default
+    --suite                    writes all fixtures of the test suite to fixtures/";
+
+/// Which messages a fixture discloses.
+#[derive(Clone, Debug)]
+enum Disclosure {
+    None,
+    All,
+    /// Every other message, starting with the second.
+    Odd,
+    Indexes(Vec<usize>),
+}
+
+impl Disclosure {
+    fn parse(text: &str) -> Option<Disclosure> {
+        match text {
+            "none" => Some(Disclosure::None),
+            "all" => Some(Disclosure::All),
+            "odd" => Some(Disclosure::Odd),
+            _ => text
+                .split(',')
+                .map(|index| index.trim().parse().ok())
+                .collect::<Option<Vec<usize>>>()
+                .map(Disclosure::Indexes),
+        }
+    }
+
+    fn indexes(&self, message_count: usize) -> Vec<usize> {
+        match self {
+            Disclosure::None => Vec::new(),
+            Disclosure::All => (0..message_count).collect(),
+            Disclosure::Odd => (1..message_count).step_by(2).collect(),
+            Disclosure::Indexes(indexes) => indexes.clone(),
+        }
+    }
+}
+
+/// Parameters of a fixture, None keeps the value of the template.
+#[derive(Clone, Debug, Default)]
+struct Parameters {
+    seed: Option<u64>,
+    message_count: Option<usize>,
+    disclosure: Option<Disclosure>,
+    header: Option<String>,
+    presentation_header: Option<String>,
+}
+
+/// Fixtures of the suite, consumed by the unit tests of this crate and the firmware tests.
+///
+/// They cover the message counts up to the firmware limit, the disclosure patterns, and empty
+/// headers. Each has its own seed, so that regenerating them gives the same files.
+fn suite() -> Vec<(&'static str, Parameters)> {
+    let fixture =
+        |seed, message_count, disclosure, header: &str, presentation_header: &str| Parameters {
+            seed: Some(seed),
+            message_count: Some(message_count),
+            disclosure: Some(disclosure),
+            header: Some(String::from(header)),
+            presentation_header: Some(String::from(presentation_header)),
+        };
+    vec![
+        (
+            "proof-1-message.json",
+            fixture(1, 1, Disclosure::None, "header", "presentation header"),
+        ),
+        (
+            "proof-4-messages.json",
+            fixture(2, 4, Disclosure::Odd, "header", "presentation header"),
+        ),
+        (
+            "proof-4-messages-no-headers.json",
+            fixture(3, 4, Disclosure::Odd, "", ""),
+        ),
+        (
+            "proof-8-messages-all-disclosed.json",
+            fixture(4, 8, Disclosure::All, "header", "presentation header"),
+        ),
+        (
+            "proof-32-messages.json",
+            fixture(5, 32, Disclosure::Odd, "header", "presentation header"),
+        ),
+    ]
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("error: {}\n\n{}", message, USAGE);
+    exit(2)
+}
+
+/// Returns the parameters, the output path, and whether to write the suite instead.
+fn parse_args() -> (Parameters, Option<String>, bool) {
+    let mut parameters = Parameters::default();
+    let mut output = None;
+    let mut write_suite = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| usage_error(&format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value("--seed");
+                parameters.seed = Some(seed.parse().unwrap_or_else(|_| {
+                    usage_error("the seed must be a u64");
+                }));
+            }
+            "--messages" => {
+                let count = value("--messages");
+                parameters.message_count = Some(count.parse().unwrap_or_else(|_| {
+                    usage_error("the message count must be a number");
+                }));
+            }
+            "--disclose" => {
+                let disclosure = value("--disclose");
+                parameters.disclosure = Some(
+                    Disclosure::parse(&disclosure)
+                        .unwrap_or_else(|| usage_error("invalid disclosed indexes")),
+                );
+            }
+            "--header" => parameters.header = Some(value("--header")),
+            "--presentation-header" => {
+                parameters.presentation_header = Some(value("--presentation-header"))
+            }
+            "--ciphersuite" => {
+                if value("--ciphersuite") != CIPHERSUITE {
+                    usage_error(&format!("only {} is implemented", CIPHERSUITE));
+                }
+            }
+            "--output" => output = Some(value("--output")),
+            "--suite" => write_suite = true,
+            "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            // A bare seed, as accepted before the options.
+            seed => {
+                parameters.seed = Some(
+                    seed.parse()
+                        .unwrap_or_else(|_| usage_error(&format!("unknown argument {}", seed))),
+                )
+            }
+        }
+    }
+    (parameters, output, write_suite)
+}
+
+/// Builds a fixture from the template, signed by its key pair.
+fn generate(template: &Value, parameters: &Parameters) -> Value {
+    let mut json = template.clone();
+    json["ciphersuite"] = json!(CIPHERSUITE);
 
-    let mut rng = OsRng;
+    // The seed is recorded so that the same fixture can be generated again. Remove it from the
+    // template to pick a new one.
+    let seed = parameters
+        .seed
+        .or_else(|| json["seed"].as_u64())
+        .unwrap_or_else(|| OsRng.next_u64());
+    json["seed"] = json!(seed);
+    let mut rng = SeededRng::from_seed_u64(seed);
     let link_secret = LinkSecret::random(&mut rng);
     json["linkSecret"] = json!(hex::encode(link_secret.to_bytes()));
 
@@ -23,9 +196,22 @@ fn main() -> io::Result<()> {
     let sk_hex = json["signerKeyPair"]["secretKey"].as_str().unwrap();
     let pk_hex = json["signerKeyPair"]["publicKey"].as_str().unwrap();
     let sk = BBSSecretKey::from_bytes(&hex::decode(sk_hex).unwrap()).unwrap();
-    let pk = BBSPublicKey::from_bytes(&hex::decode(pk_hex).unwrap()).unwrap();
+    let pk_bytes = hex::decode(pk_hex).unwrap();
+    let pk = BBSPublicKey::from_bytes(&pk_bytes).unwrap();
 
     // header and messages and disclosed indexes
+    if let Some(header) = &parameters.header {
+        json["header"] = json!(header);
+    }
+    if let Some(presentation_header) = &parameters.presentation_header {
+        json["presentationHeader"] = json!(presentation_header);
+    }
+    if let Some(message_count) = parameters.message_count {
+        let messages: Vec<String> = (0..message_count)
+            .map(|i| format!("message {}", i))
+            .collect();
+        json["messages"] = json!(messages);
+    }
     let header = json["header"].as_str().unwrap().as_bytes().to_vec();
     let presentation_header = json["presentationHeader"]
         .as_str()
@@ -38,12 +224,21 @@ fn main() -> io::Result<()> {
         .iter()
         .map(|m| m.as_str().unwrap().as_bytes().to_vec())
         .collect();
+    if let Some(disclosure) = &parameters.disclosure {
+        json["disclosedIndexes"] = json!(disclosure.indexes(messages.len()));
+    }
     let disclosed_indexes: Vec<usize> = json["disclosedIndexes"]
         .as_array()
         .unwrap()
         .iter()
         .map(|v| v.as_u64().unwrap() as usize)
         .collect();
+    if disclosed_indexes
+        .iter()
+        .any(|&index| index >= messages.len())
+    {
+        usage_error("a disclosed index is out of range");
+    }
 
     // commitment
     let (commitment_with_proof, secret_prover_blind) =
@@ -80,17 +275,45 @@ fn main() -> io::Result<()> {
     let proof_bytes = proof_response.proof.to_bytes();
     let disclosed_messages = proof_response.disclosed_messages;
     let disclosed_indexes = proof_response.disclosed_indexes;
+    // A fixture that doesn't verify would only fail later, in every test using it.
+    assert!(
+        verify_presentation(
+            &pk_bytes,
+            &proof_bytes,
+            &header,
+            &presentation_header,
+            &disclosed_messages,
+            &disclosed_indexes,
+        ),
+        "Generated proof should be valid"
+    );
     json["proof"] = json!(hex::encode(proof_bytes));
     let disclosed_messages: Vec<String> =
         disclosed_messages.iter().map(|m| hex::encode(m)).collect();
     json["outputDisclosedMessages"] = json!(disclosed_messages);
     json["outputDisclosedIndexes"] = json!(disclosed_indexes);
+    json
+}
 
-    // ファイルに書き戻す
-    let mut file = fs::File::create(file_path)?;
-    file.write_all(serde_json::to_string_pretty(&json)?.as_bytes())?;
+fn write_fixture(path: &str, json: &Value) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(serde_json::to_string_pretty(json)?.as_bytes())?;
+    println!("{} has been updated.", path);
+    Ok(())
+}
 
-    println!("\nJSON file has been updated.");
+fn main() -> io::Result<()> {
+    let (parameters, output, write_suite) = parse_args();
+    let contents = fs::read_to_string(TEMPLATE_PATH)?;
+    let template: Value = serde_json::from_str(&contents)?;
 
-    Ok(())
+    if write_suite {
+        for (name, parameters) in suite() {
+            let path = format!("{}/{}", SUITE_DIRECTORY, name);
+            write_fixture(&path, &generate(&template, &parameters))?;
+        }
+        return Ok(());
+    }
+    let path = output.unwrap_or_else(|| String::from(TEMPLATE_PATH));
+    write_fixture(&path, &generate(&template, &parameters))
 }
@@ -0,0 +1,84 @@
+//! Exponent-blinded scalar multiplication, for bases multiplied by long-term secrets.
+//!
+//! The scalar `k` is replaced by `k + r * q`, where `q` is the group order and `r` a fresh random
+//! value. The product doesn't change, but the bits that drive the multiplication differ at each
+//! call, so that traces of many multiplications by the same secret can't be averaged. Every bit
+//! costs a doubling and an addition, whatever its value. This is about twice as slow as the
+//! multiplication of `bls12_381_plus`.
+
+use bls12_381_plus::{G1Projective, Scalar};
+use rand_core::RngCore;
+use subtle::{Choice, ConditionallySelectable};
+
+/// Order of the BLS12-381 groups, in little-endian 64-bit limbs.
+const MODULUS: [u64; 4] = [
+    0xffff_ffff_0000_0001,
+    0x53bd_a402_fffe_5bfe,
+    0x3339_d808_09a1_d805,
+    0x73ed_a753_299d_7d48,
+];
+const LIMBS: usize = MODULUS.len() + 1;
+const BLINDED_BITS: usize = 64 * LIMBS;
+
+/// Returns `scalar * base`, blinding the exponent with randomness from `rng`.
+pub fn blinded_mul<R: RngCore>(rng: &mut R, base: &G1Projective, scalar: &Scalar) -> G1Projective {
+    let exponent = blinded_exponent(scalar, rng.next_u64());
+    let mut result = G1Projective::IDENTITY;
+    for bit in (0..BLINDED_BITS).rev() {
+        result = result.double();
+        let sum = result + base;
+        let is_set = Choice::from(((exponent[bit / 64] >> (bit % 64)) & 1) as u8);
+        result = G1Projective::conditional_select(&result, &sum, is_set);
+    }
+    result
+}
+
+/// Returns `scalar + blind * MODULUS` in little-endian 64-bit limbs.
+fn blinded_exponent(scalar: &Scalar, blind: u64) -> [u64; LIMBS] {
+    let bytes = scalar.to_le_bytes();
+    let mut limbs = [0; LIMBS];
+    let mut carry = 0u128;
+    for (i, modulus) in MODULUS.iter().enumerate() {
+        let mut limb = [0; 8];
+        limb.copy_from_slice(&bytes[8 * i..8 * (i + 1)]);
+        // At most (2^64 - 1) + (2^64 - 1)^2 + (2^64 - 1), which fits.
+        let sum = u64::from_le_bytes(limb) as u128 + blind as u128 * *modulus as u128 + carry;
+        limbs[i] = sum as u64;
+        carry = sum >> 64;
+    }
+    limbs[MODULUS.len()] = carry as u64;
+    limbs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::OsRng;
+
+    #[test]
+    fn test_blinded_mul() {
+        let mut rng = OsRng;
+        let base = G1Projective::GENERATOR * Scalar::from(5u64);
+        for scalar in [
+            Scalar::from(0u64),
+            Scalar::from(1u64),
+            Scalar::from(0xdead_beefu64),
+            -Scalar::from(1u64),
+        ] {
+            assert_eq!(blinded_mul(&mut rng, &base, &scalar), base * scalar);
+        }
+    }
+
+    #[test]
+    fn test_blinded_exponent() {
+        // Blinding zero by one gives the modulus.
+        let exponent = blinded_exponent(&Scalar::from(0u64), 1);
+        assert_eq!(exponent[..4], MODULUS);
+        assert_eq!(exponent[4], 0);
+        let exponent = blinded_exponent(&Scalar::from(7u64), 0);
+        assert_eq!(exponent, [7, 0, 0, 0, 0]);
+        // The largest blind carries into the top limb.
+        let exponent = blinded_exponent(&-Scalar::from(1u64), u64::MAX);
+        assert_ne!(exponent[4], 0);
+    }
+}
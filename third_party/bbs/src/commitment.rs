@@ -1,14 +1,20 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use rand_core::RngCore;
 use zkryptium::bbsplus::ciphersuites::BbsCiphersuite;
 use zkryptium::bbsplus::generators::Generators;
 
 use crate::{BBSCiphersuite, BBSCommitment, BBSError, LinkSecret};
 
-// Is it okay that there's no Signer (Issuer) challenge as input here?
-// If we don't include a nonce prepared by the Signer, someone who intercepts this Commitment could reuse it,
-// and create something arbitrarily linked to the authenticator,
-// but without the authenticator, they can't create a VP anyway, so does it matter?
+/// Prefixed to commitment transcripts, so they can't be mistaken for other signed data.
+pub const COMMITMENT_TRANSCRIPT_DOMAIN: &[u8] = b"OpenSK BBS commitment\0";
+
+/// Prefixed to commitment transcripts signed with a recovery key instead of the attestation key.
+pub const RECOVERY_TRANSCRIPT_DOMAIN: &[u8] = b"OpenSK BBS recovery\0";
+
+// The commitment proof itself takes no Signer (Issuer) challenge as input.
+// Without one, someone who intercepts this Commitment could replay it, so the authenticator signs
+// the transcript below, which binds the Commitment to the nonce and expiry chosen by the Signer.
 pub fn generate_link_secret_commitment<R: RngCore>(
     rng: &mut R,
     link_secret: &LinkSecret,
@@ -16,7 +22,8 @@ pub fn generate_link_secret_commitment<R: RngCore>(
     let secret_messages = [link_secret.to_bytes().to_vec()];
 
     let (commitment_with_proof, secret_prover_blind) =
-        BBSCommitment::commit(rng, Some(&secret_messages)).unwrap();
+        BBSCommitment::commit(rng, Some(&secret_messages))
+            .map_err(|_| BBSError::CommitmentGeneration)?;
 
     Ok((
         commitment_with_proof.to_bytes().into_boxed_slice(),
@@ -24,6 +31,30 @@ pub fn generate_link_secret_commitment<R: RngCore>(
     ))
 }
 
+/// Binds a commitment with proof to the issuer nonce and expiry it answers.
+///
+/// Variable length fields are prefixed with their big-endian 32-bit length, the expiry is encoded
+/// in 8 big-endian bytes.
+pub fn commitment_transcript(commitment_with_proof: &[u8], nonce: &[u8], expiry: u64) -> Vec<u8> {
+    let mut transcript = COMMITMENT_TRANSCRIPT_DOMAIN.to_vec();
+    for field in [commitment_with_proof, nonce] {
+        transcript.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        transcript.extend_from_slice(field);
+    }
+    transcript.extend_from_slice(&expiry.to_be_bytes());
+    transcript
+}
+
+/// Binds a commitment to the recovery key that a device shares with its backup.
+///
+/// Paired devices derive the same key for an issuer, so a valid signature from a new device shows
+/// that it continues the device that received the earlier credential.
+pub fn recovery_transcript(commitment_with_proof: &[u8], nonce: &[u8], expiry: u64) -> Vec<u8> {
+    let mut transcript = RECOVERY_TRANSCRIPT_DOMAIN.to_vec();
+    transcript.extend(commitment_transcript(commitment_with_proof, nonce, expiry));
+    transcript
+}
+
 pub fn verify_link_secret_commitment(commitment_with_proof: &[u8]) -> Result<bool, BBSError> {
     // Only the link_secret is committed, so the length is 1
     const COMMITTED_MESSAGE_LEN: usize = 1;
@@ -45,7 +76,10 @@ pub fn verify_link_secret_commitment(commitment_with_proof: &[u8]) -> Result<boo
 mod tests {
     use rand_core::OsRng;
 
-    use crate::{generate_link_secret_commitment, verify_link_secret_commitment, LinkSecret};
+    use crate::{
+        commitment_transcript, generate_link_secret_commitment, recovery_transcript,
+        verify_link_secret_commitment, LinkSecret,
+    };
 
     #[test]
     fn test_generate_link_secret_commitment() {
@@ -65,4 +99,27 @@ mod tests {
             assert!(result, "Commitment should be valid");
         }
     }
+
+    #[test]
+    fn test_commitment_transcript() {
+        let transcript = commitment_transcript(&[0x01; 4], &[0x02; 2], 7);
+        assert_eq!(
+            transcript.len(),
+            super::COMMITMENT_TRANSCRIPT_DOMAIN.len() + 4 + 4 + 4 + 2 + 8
+        );
+        assert!(transcript.ends_with(&[0, 0, 0, 2, 0x02, 0x02, 0, 0, 0, 0, 0, 0, 0, 7]));
+        // Moving bytes between the commitment and the nonce changes the transcript.
+        assert_ne!(
+            transcript,
+            commitment_transcript(&[0x01; 3], &[0x01, 0x02, 0x02], 7)
+        );
+        assert_ne!(transcript, commitment_transcript(&[0x01; 4], &[0x02; 2], 8));
+    }
+
+    #[test]
+    fn test_recovery_transcript() {
+        let transcript = recovery_transcript(&[0x01; 4], &[0x02; 2], 7);
+        assert!(transcript.starts_with(super::RECOVERY_TRANSCRIPT_DOMAIN));
+        assert!(transcript.ends_with(&commitment_transcript(&[0x01; 4], &[0x02; 2], 7)));
+    }
 }
@@ -14,3 +14,9 @@ pub type BBSCommitment = Commitment<BBS>;
 pub type BBSCommitmentBlindFactor = BlindFactor;
 pub type BBSSignature = Signature<BBS>;
 pub type BBSPoK = PoKSignature<BBS>;
+
+/// Names the BBS support of an authenticator, e.g. among the extensions of its GetInfo.
+///
+/// It identifies the ciphersuite and the version of the commitment and proof encodings, so that
+/// clients can tell whether their credentials are compatible without sending a probe command.
+pub const BBS_CAPABILITY_ID: &str = "bbs-bls12381-shake-256-v1";
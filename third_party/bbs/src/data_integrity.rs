@@ -0,0 +1,202 @@
+//! Wraps BBS proofs into `bbs-2023` Data Integrity proofs, as in the W3C Data Integrity BBS
+//! Cryptosuites.
+//!
+//! Wallets get the raw proof from the authenticator, and need the derived proof value to put it
+//! into a verifiable presentation. Selecting the statements of the credential, i.e. the indexes
+//! and the label map, is the job of the caller.
+
+use serde_json::{json, Value};
+
+/// Name of the cryptosuite in the Data Integrity proof.
+pub const BBS_2023_CRYPTOSUITE: &str = "bbs-2023";
+
+const BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+/// Multibase prefix of base64url without padding.
+const MULTIBASE_BASE64URL: char = 'u';
+
+const CBOR_UNSIGNED: u8 = 0;
+const CBOR_BYTE_STRING: u8 = 2;
+const CBOR_ARRAY: u8 = 4;
+const CBOR_MAP: u8 = 5;
+
+/// Feature option of the derived proof, which selects the header of its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DerivedProofFeature {
+    Baseline,
+    /// The signature covers a committed secret of the holder, like the link secret of OpenSK.
+    AnonymousHolderBinding,
+}
+
+impl DerivedProofFeature {
+    fn header(self) -> [u8; 3] {
+        match self {
+            DerivedProofFeature::Baseline => [0xd9, 0x5d, 0x03],
+            DerivedProofFeature::AnonymousHolderBinding => [0xd9, 0x5d, 0x05],
+        }
+    }
+}
+
+/// Components of a derived `bbs-2023` proof.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DerivedProof<'a> {
+    pub bbs_proof: &'a [u8],
+    /// Compressed map from canonical blank node ids to HMAC based ones, e.g. `(0, 2)` for `c14n0`
+    /// to `b2`.
+    pub label_map: &'a [(u64, u64)],
+    pub mandatory_indexes: &'a [usize],
+    pub selective_indexes: &'a [usize],
+    pub presentation_header: &'a [u8],
+    pub feature: DerivedProofFeature,
+}
+
+impl DerivedProof<'_> {
+    /// Serializes the components into the multibase `proofValue`.
+    pub fn proof_value(&self) -> String {
+        let mut bytes = self.feature.header().to_vec();
+        write_head(&mut bytes, CBOR_ARRAY, 5);
+        write_bytes(&mut bytes, self.bbs_proof);
+        let mut label_map = self.label_map.to_vec();
+        label_map.sort_unstable();
+        write_head(&mut bytes, CBOR_MAP, label_map.len() as u64);
+        for (key, value) in label_map {
+            write_head(&mut bytes, CBOR_UNSIGNED, key);
+            write_head(&mut bytes, CBOR_UNSIGNED, value);
+        }
+        write_indexes(&mut bytes, self.mandatory_indexes);
+        write_indexes(&mut bytes, self.selective_indexes);
+        write_bytes(&mut bytes, self.presentation_header);
+        let mut proof_value = String::from(MULTIBASE_BASE64URL);
+        proof_value.push_str(&base64url(&bytes));
+        proof_value
+    }
+
+    /// Returns the `proof` object of a verifiable presentation.
+    pub fn to_data_integrity_proof(&self, verification_method: &str) -> Value {
+        json!({
+            "type": "DataIntegrityProof",
+            "cryptosuite": BBS_2023_CRYPTOSUITE,
+            "proofPurpose": "assertionMethod",
+            "verificationMethod": verification_method,
+            "proofValue": self.proof_value(),
+        })
+    }
+}
+
+/// Writes a CBOR head in its shortest form, as required for proof values.
+fn write_head(bytes: &mut Vec<u8>, major_type: u8, argument: u64) {
+    let major_type = major_type << 5;
+    if argument < 24 {
+        bytes.push(major_type | argument as u8);
+    } else if argument <= u8::MAX as u64 {
+        bytes.extend_from_slice(&[major_type | 24, argument as u8]);
+    } else if argument <= u16::MAX as u64 {
+        bytes.push(major_type | 25);
+        bytes.extend_from_slice(&(argument as u16).to_be_bytes());
+    } else if argument <= u32::MAX as u64 {
+        bytes.push(major_type | 26);
+        bytes.extend_from_slice(&(argument as u32).to_be_bytes());
+    } else {
+        bytes.push(major_type | 27);
+        bytes.extend_from_slice(&argument.to_be_bytes());
+    }
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_head(bytes, CBOR_BYTE_STRING, data.len() as u64);
+    bytes.extend_from_slice(data);
+}
+
+fn write_indexes(bytes: &mut Vec<u8>, indexes: &[usize]) {
+    write_head(bytes, CBOR_ARRAY, indexes.len() as u64);
+    for &index in indexes {
+        write_head(bytes, CBOR_UNSIGNED, index as u64);
+    }
+}
+
+/// Encodes in base64url without padding.
+pub(crate) fn base64url(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 4 + 2) / 3);
+    for chunk in data.chunks(3) {
+        let mut block = [0u8; 3];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, block[0], block[1], block[2]]);
+        for i in 0..=chunk.len() {
+            let sextet = (bits >> (18 - 6 * i)) & 0x3f;
+            encoded.push(BASE64URL_ALPHABET[sextet as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof(feature: DerivedProofFeature) -> DerivedProof<'static> {
+        DerivedProof {
+            bbs_proof: &[0xaa, 0xbb],
+            label_map: &[(1, 0), (0, 2)],
+            mandatory_indexes: &[0],
+            selective_indexes: &[1, 24],
+            presentation_header: b"ph",
+            feature,
+        }
+    }
+
+    #[test]
+    fn test_base64url() {
+        assert_eq!(base64url(b""), "");
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(b"foob"), "Zm9vYg");
+        assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+    }
+
+    #[test]
+    fn test_write_head() {
+        let encode = |argument| {
+            let mut bytes = Vec::new();
+            write_head(&mut bytes, CBOR_UNSIGNED, argument);
+            bytes
+        };
+        assert_eq!(encode(23), [0x17]);
+        assert_eq!(encode(24), [0x18, 0x18]);
+        assert_eq!(encode(256), [0x19, 0x01, 0x00]);
+        assert_eq!(encode(0x10000), [0x1a, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(
+            encode(0x1_0000_0000),
+            [0x1b, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_proof_value() {
+        let expected = [
+            0xd9, 0x5d, 0x05, // header
+            0x85, // array of 5
+            0x42, 0xaa, 0xbb, // BBS proof
+            0xa2, 0x00, 0x02, 0x01, 0x00, // label map, sorted by key
+            0x81, 0x00, // mandatory indexes
+            0x82, 0x01, 0x18, 0x18, // selective indexes
+            0x42, b'p', b'h', // presentation header
+        ];
+        let proof_value = proof(DerivedProofFeature::AnonymousHolderBinding).proof_value();
+        assert_eq!(proof_value, format!("u{}", base64url(&expected)));
+
+        let baseline = proof(DerivedProofFeature::Baseline).proof_value();
+        assert_eq!(&baseline[1..5], base64url(&[0xd9, 0x5d, 0x03]));
+    }
+
+    #[test]
+    fn test_to_data_integrity_proof() {
+        let proof = proof(DerivedProofFeature::Baseline);
+        let value = proof.to_data_integrity_proof("did:example:issuer#key-1");
+        assert_eq!(value["type"], "DataIntegrityProof");
+        assert_eq!(value["cryptosuite"], "bbs-2023");
+        assert_eq!(value["proofPurpose"], "assertionMethod");
+        assert_eq!(value["verificationMethod"], "did:example:issuer#key-1");
+        assert_eq!(value["proofValue"], proof.proof_value());
+    }
+}
@@ -1,2 +1,12 @@
-#[derive(Debug, PartialEq)]
-pub enum BBSError {}
+/// Failures of the BBS operations, each reported with its own status code by the authenticator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BBSError {
+    /// The commitment to the link secret couldn't be generated.
+    CommitmentGeneration,
+    /// A disclosed index is beyond the signed messages.
+    InvalidDisclosedIndex,
+    /// The secret prover blind doesn't encode a scalar.
+    InvalidProverBlind,
+    /// The proof couldn't be generated, e.g. the signature doesn't match the key and messages.
+    ProofGeneration,
+}
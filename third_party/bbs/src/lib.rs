@@ -6,14 +6,36 @@ extern crate std;
 
 extern crate alloc;
 
+mod blinded_mul;
 mod commitment;
 mod common;
+#[cfg(feature = "std")]
+mod data_integrity;
 mod errors;
 mod link_secret;
+mod msm;
+#[cfg(feature = "std")]
+mod presentation;
 mod proof;
+mod rng;
+#[cfg(feature = "std")]
+mod salted_digest;
+#[cfg(feature = "precomputed_tables")]
+mod window_table;
 
+pub use blinded_mul::*;
 pub use commitment::*;
 pub use common::*;
+#[cfg(feature = "std")]
+pub use data_integrity::*;
 pub use errors::*;
 pub use link_secret::*;
+pub use msm::*;
+#[cfg(feature = "std")]
+pub use presentation::*;
 pub use proof::*;
+pub use rng::*;
+#[cfg(feature = "std")]
+pub use salted_digest::*;
+#[cfg(feature = "precomputed_tables")]
+pub use window_table::*;
@@ -1,7 +1,7 @@
 use rand_core::RngCore;
-use zeroize::Zeroize;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-#[derive(Debug, Eq, PartialEq, Zeroize)]
+#[derive(Debug, Eq, PartialEq, Zeroize, ZeroizeOnDrop)]
 pub struct LinkSecret([u8; LinkSecret::SIZE]);
 
 impl LinkSecret {
@@ -0,0 +1,122 @@
+//! Multi-scalar multiplications that run in bounded steps.
+//!
+//! Tock apps are single-threaded, so a computation of several seconds blocks the main loop,
+//! including its answers to HID init, ping and cancel. Running it in steps lets the caller service
+//! the transport in between.
+
+use alloc::vec::Vec;
+use bls12_381_plus::{G1Projective, Scalar};
+
+/// Outcome of a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MsmStep {
+    /// There are terms left, call `step` again.
+    Pending,
+    Done(G1Projective),
+}
+
+/// Computes the sum of `scalars[i] * points[i]`, a bounded number of terms per step.
+///
+/// It owns its inputs, so that it can be kept across iterations of the main loop.
+pub struct ResumableMsm {
+    points: Vec<G1Projective>,
+    scalars: Vec<Scalar>,
+    next: usize,
+    accumulator: G1Projective,
+}
+
+impl ResumableMsm {
+    /// Panics if there isn't exactly one scalar per point.
+    pub fn new(points: Vec<G1Projective>, scalars: Vec<Scalar>) -> Self {
+        assert_eq!(points.len(), scalars.len());
+        ResumableMsm {
+            points,
+            scalars,
+            next: 0,
+            accumulator: G1Projective::IDENTITY,
+        }
+    }
+
+    /// Adds at most `max_terms` terms to the sum.
+    ///
+    /// Once done, further steps return the same sum.
+    pub fn step(&mut self, max_terms: usize) -> MsmStep {
+        let end = self.points.len().min(self.next.saturating_add(max_terms));
+        for i in self.next..end {
+            self.accumulator += self.points[i] * self.scalars[i];
+        }
+        self.next = end;
+        if self.next == self.points.len() {
+            MsmStep::Done(self.accumulator)
+        } else {
+            MsmStep::Pending
+        }
+    }
+
+    /// Returns how many terms are left.
+    pub fn remaining(&self) -> usize {
+        self.points.len() - self.next
+    }
+
+    /// Runs all remaining steps at once.
+    pub fn finish(mut self) -> G1Projective {
+        match self.step(usize::MAX) {
+            MsmStep::Done(result) => result,
+            MsmStep::Pending => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(count: u64) -> (Vec<G1Projective>, Vec<Scalar>) {
+        let points = (1..=count)
+            .map(|i| G1Projective::GENERATOR * Scalar::from(i))
+            .collect();
+        let scalars = (1..=count).map(|i| Scalar::from(i * 7 + 3)).collect();
+        (points, scalars)
+    }
+
+    fn naive(points: &[G1Projective], scalars: &[Scalar]) -> G1Projective {
+        points
+            .iter()
+            .zip(scalars)
+            .fold(G1Projective::IDENTITY, |sum, (point, scalar)| {
+                sum + point * scalar
+            })
+    }
+
+    #[test]
+    fn test_steps() {
+        let (points, scalars) = inputs(5);
+        let expected = naive(&points, &scalars);
+        let mut msm = ResumableMsm::new(points, scalars);
+        assert_eq!(msm.step(2), MsmStep::Pending);
+        assert_eq!(msm.remaining(), 3);
+        assert_eq!(msm.step(2), MsmStep::Pending);
+        assert_eq!(msm.step(2), MsmStep::Done(expected));
+        assert_eq!(msm.remaining(), 0);
+        assert_eq!(msm.step(2), MsmStep::Done(expected));
+    }
+
+    #[test]
+    fn test_finish() {
+        let (points, scalars) = inputs(4);
+        let expected = naive(&points, &scalars);
+        let mut msm = ResumableMsm::new(points, scalars);
+        assert_eq!(msm.step(1), MsmStep::Pending);
+        assert_eq!(msm.finish(), expected);
+
+        let empty = ResumableMsm::new(Vec::new(), Vec::new());
+        assert_eq!(empty.finish(), G1Projective::IDENTITY);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_length_mismatch() {
+        let (points, _) = inputs(2);
+        ResumableMsm::new(points, Vec::new());
+    }
+}
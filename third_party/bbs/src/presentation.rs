@@ -0,0 +1,117 @@
+//! Verification of presentations, as a relying party sees them.
+
+use crate::{BBSPoK, BBSPublicKey};
+use alloc::vec::Vec;
+
+/// Signed messages before those of the credential: the prover blind and the link secret.
+///
+/// See https://github.com/Cybersecurity-LINKS/zkryptium/blob/0e21c20f4c84473e7eb69a1aef136159c9d085b8/src/utils/util.rs#L403-L453
+pub const COMMITTED_MESSAGE_OFFSET: usize = 2;
+
+/// Verifies a proof against the issuer public key and the disclosed messages.
+///
+/// The disclosed indexes count the credential messages only, as in the proof request. They are
+/// shifted past the committed messages here.
+pub fn verify_presentation(
+    public_key: &[u8],
+    proof: &[u8],
+    header: &[u8],
+    presentation_header: &[u8],
+    disclosed_messages: &[Vec<u8>],
+    disclosed_indexes: &[usize],
+) -> bool {
+    let public_key = match BBSPublicKey::from_bytes(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return false,
+    };
+    let proof = match BBSPoK::from_bytes(proof) {
+        Ok(proof) => proof,
+        Err(_) => return false,
+    };
+    let disclosed_indexes = disclosed_indexes
+        .iter()
+        .map(|index| index + COMMITTED_MESSAGE_OFFSET)
+        .collect::<Vec<_>>();
+    proof
+        .blind_proof_verify(
+            &public_key,
+            Some(disclosed_messages),
+            Some(&disclosed_indexes),
+            Some(header),
+            Some(presentation_header),
+        )
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use serde_json::Value;
+
+    use super::verify_presentation;
+
+    fn fixture() -> Value {
+        serde_json::from_str(include_str!("../fixtures/proof.json")).unwrap()
+    }
+
+    fn hex_field(json: &Value) -> Vec<u8> {
+        hex::decode(json.as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_verify_presentation() {
+        let json = fixture();
+        let public_key = hex_field(&json["signerKeyPair"]["publicKey"]);
+        let proof = hex_field(&json["proof"]);
+        let header = json["header"].as_str().unwrap().as_bytes();
+        let presentation_header = json["presentationHeader"].as_str().unwrap().as_bytes();
+        let disclosed_messages: Vec<Vec<u8>> = json["outputDisclosedMessages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(hex_field)
+            .collect();
+        let disclosed_indexes: Vec<usize> = json["disclosedIndexes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|index| index.as_u64().unwrap() as usize)
+            .collect();
+        let verify = |header: &[u8], presentation_header: &[u8], messages: &[Vec<u8>]| {
+            verify_presentation(
+                &public_key,
+                &proof,
+                header,
+                presentation_header,
+                messages,
+                &disclosed_indexes,
+            )
+        };
+
+        assert!(verify(header, presentation_header, &disclosed_messages));
+        assert!(!verify(
+            b"other header",
+            presentation_header,
+            &disclosed_messages
+        ));
+        assert!(!verify(
+            header,
+            b"other presentation header",
+            &disclosed_messages
+        ));
+        if !disclosed_messages.is_empty() {
+            let mut forged_messages = disclosed_messages.clone();
+            forged_messages[0] = vec![0x00];
+            assert!(!verify(header, presentation_header, &forged_messages));
+        }
+        assert!(!verify_presentation(
+            &public_key,
+            &proof[1..],
+            header,
+            presentation_header,
+            &disclosed_messages,
+            &disclosed_indexes,
+        ));
+    }
+}
@@ -1,19 +1,30 @@
 use rand_core::RngCore;
-use zkryptium::errors::Error;
 
+use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
 
-use crate::{BBSCommitmentBlindFactor, BBSPoK, BBSPublicKey, BBSSignature, LinkSecret};
+use crate::{BBSCommitmentBlindFactor, BBSError, BBSPoK, BBSPublicKey, BBSSignature, LinkSecret};
 
 // LinkSecretProof構造体の定義
 #[derive(Debug, Eq, PartialEq)]
 pub struct BBSProofResponse {
-    pub proof: BBSPoK,
+    /// Boxed, so that the proof isn't copied through every stack frame on its way out.
+    pub proof: Box<BBSPoK>,
     pub disclosed_messages: Vec<Vec<u8>>,
     pub disclosed_indexes: Vec<usize>,
 }
 
+/// Milestones of the proof generation, in the order they are reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofStage {
+    Started,
+    /// The inputs are encoded, and the expensive part starts.
+    Prepared,
+    /// The pairings and multi-scalar multiplications are done.
+    Computed,
+}
+
 pub fn generate_proof<R: RngCore>(
     rng: &mut R,
     public_key: &BBSPublicKey,
@@ -24,26 +35,67 @@ pub fn generate_proof<R: RngCore>(
     presentation_header: Option<&[u8]>,
     disclosed_indexes: &[usize],
     secret_prover_blind: Option<&BBSCommitmentBlindFactor>,
-) -> Result<BBSProofResponse, Error> {
+) -> Result<BBSProofResponse, BBSError> {
+    generate_proof_with_progress(
+        rng,
+        public_key,
+        messages,
+        link_secret,
+        signature,
+        header,
+        presentation_header,
+        disclosed_indexes,
+        secret_prover_blind,
+        |_| (),
+    )
+}
+
+/// Like `generate_proof`, and calls `progress` at each milestone.
+///
+/// Callers use it to send keepalives or to attribute time. The computation between `Prepared` and
+/// `Computed` happens inside zkryptium, which doesn't report finer milestones.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_proof_with_progress<R: RngCore, F: FnMut(ProofStage)>(
+    rng: &mut R,
+    public_key: &BBSPublicKey,
+    messages: &[Vec<u8>],
+    link_secret: &LinkSecret,
+    signature: &BBSSignature,
+    header: Option<&[u8]>,
+    presentation_header: Option<&[u8]>,
+    disclosed_indexes: &[usize],
+    secret_prover_blind: Option<&BBSCommitmentBlindFactor>,
+    mut progress: F,
+) -> Result<BBSProofResponse, BBSError> {
+    progress(ProofStage::Started);
+    if disclosed_indexes
+        .iter()
+        .any(|&index| index >= messages.len())
+    {
+        return Err(BBSError::InvalidDisclosedIndex);
+    }
     // Only the link secret is committed
     let committed_messages = vec![link_secret.to_bytes().to_vec()];
     // Never disclose the link secret, so no indexes are disclosed
     let disclosed_commitment_indexes: Option<Vec<usize>> = None;
 
+    let signature = Box::new(signature.to_bytes());
+    progress(ProofStage::Prepared);
+
     // PoKSignatureを生成
-    let (proof, disclosed_msgs, disclosed_idxs) = BBSPoK::blind_proof_gen(
+    let (proof, disclosed_msgs, disclosed_idxs) = blind_proof_gen(
         rng,
         public_key,
-        &signature.to_bytes(),
+        &signature,
         header,
         presentation_header,
-        Some(messages),
-        Some(&committed_messages),
-        Some(&disclosed_indexes),
+        messages,
+        &committed_messages,
+        disclosed_indexes,
         disclosed_commitment_indexes.as_deref(),
         secret_prover_blind,
-        None, // signer_blindはNone
     )?;
+    progress(ProofStage::Computed);
 
     // LinkSecretProofを構築して返す
     Ok(BBSProofResponse {
@@ -52,3 +104,339 @@ pub fn generate_proof<R: RngCore>(
         disclosed_indexes: disclosed_idxs,
     })
 }
+
+/// Runs the proof generation in its own frame.
+///
+/// Its temporaries are the bulk of the stack usage. Not inlining it keeps them out of the frame
+/// of `generate_proof`, which is otherwise inlined into its callers.
+#[inline(never)]
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+fn blind_proof_gen<R: RngCore>(
+    rng: &mut R,
+    public_key: &BBSPublicKey,
+    signature: &[u8; 80],
+    header: Option<&[u8]>,
+    presentation_header: Option<&[u8]>,
+    messages: &[Vec<u8>],
+    committed_messages: &[Vec<u8>],
+    disclosed_indexes: &[usize],
+    disclosed_commitment_indexes: Option<&[usize]>,
+    secret_prover_blind: Option<&BBSCommitmentBlindFactor>,
+) -> Result<(Box<BBSPoK>, Vec<Vec<u8>>, Vec<usize>), BBSError> {
+    let (proof, disclosed_msgs, disclosed_idxs) = BBSPoK::blind_proof_gen(
+        rng,
+        public_key,
+        signature,
+        header,
+        presentation_header,
+        Some(messages),
+        Some(committed_messages),
+        Some(disclosed_indexes),
+        disclosed_commitment_indexes,
+        secret_prover_blind,
+        None, // signer_blindはNone
+    )
+    .map_err(|_| BBSError::ProofGeneration)?;
+    Ok((Box::new(proof), disclosed_msgs, disclosed_idxs))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::hint::black_box;
+    use core::ptr;
+    use serde_json::Value;
+    use zkryptium::schemes::generics::BlindSignature;
+
+    use crate::{
+        generate_link_secret_commitment, generate_proof, generate_proof_with_progress,
+        verify_presentation, BBSCommitmentBlindFactor, BBSError, BBSPublicKey, BBSSecretKey,
+        BBSSignature, LinkSecret, ProofStage, SeededRng, BBS,
+    };
+
+    const PAINT: u8 = 0xa5;
+    const PAINTED_SIZE: usize = 0x40000;
+    // Tolerance for differences in the frames of the measuring functions.
+    const STACK_SLACK: usize = 0x100;
+    // The stack of `examples/bbs_proof.rs`, which generates proofs and nothing else.
+    const MAX_PROOF_STACK: usize = 0x4000;
+
+    /// Fills the stack below the caller with a pattern, and returns its lowest address.
+    #[inline(never)]
+    fn paint_stack() -> usize {
+        let mut region = [PAINT; PAINTED_SIZE];
+        black_box(&mut region);
+        region.as_ptr() as usize
+    }
+
+    /// Returns how many bytes of the painted region were overwritten since painting.
+    #[inline(never)]
+    fn painted_stack_used(bottom: usize) -> usize {
+        let bottom = bottom as *const u8;
+        // The stack grows down, so the untouched bytes are at the bottom of the region.
+        let untouched = (0..PAINTED_SIZE)
+            .take_while(|&i| unsafe { ptr::read_volatile(bottom.add(i)) } == PAINT)
+            .count();
+        PAINTED_SIZE - untouched
+    }
+
+    /// Returns a public key, link secret, messages, signature and blind, ready for proofs.
+    fn signed_credential(
+        rng: &mut SeededRng,
+        message_count: usize,
+    ) -> (
+        BBSPublicKey,
+        LinkSecret,
+        Vec<Vec<u8>>,
+        BBSSignature,
+        BBSCommitmentBlindFactor,
+    ) {
+        let json: Value = serde_json::from_str(include_str!("../fixtures/proof.json")).unwrap();
+        let key = |name: &str| hex::decode(json["signerKeyPair"][name].as_str().unwrap()).unwrap();
+        let secret_key = BBSSecretKey::from_bytes(&key("secretKey")).unwrap();
+        let public_key = BBSPublicKey::from_bytes(&key("publicKey")).unwrap();
+
+        let link_secret = LinkSecret::random(rng);
+        let (commitment_with_proof, secret_prover_blind) =
+            generate_link_secret_commitment(rng, &link_secret).unwrap();
+        let messages: Vec<Vec<u8>> = (0..message_count).map(|i| vec![i as u8; 32]).collect();
+        let signature = BlindSignature::<BBS>::blind_sign(
+            &secret_key,
+            &public_key,
+            Some(&commitment_with_proof),
+            None,
+            Some(&messages),
+            None,
+        )
+        .unwrap();
+        let signature = BBSSignature::from_bytes(&signature.to_bytes()).unwrap();
+        let secret_prover_blind =
+            BBSCommitmentBlindFactor::from_bytes(&secret_prover_blind).unwrap();
+        (
+            public_key,
+            link_secret,
+            messages,
+            signature,
+            secret_prover_blind,
+        )
+    }
+
+    /// Returns the fixtures written by the generator, `fixtures/proof.json` and the suite.
+    fn fixtures() -> Vec<(String, Value)> {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+        let mut fixtures: Vec<(String, Value)> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "json")
+            })
+            .map(|path| {
+                let contents = std::fs::read_to_string(&path).unwrap();
+                (
+                    path.display().to_string(),
+                    serde_json::from_str(&contents).unwrap(),
+                )
+            })
+            .collect();
+        fixtures.sort_by(|a, b| a.0.cmp(&b.0));
+        fixtures
+    }
+
+    fn decode_hex(json: &Value) -> Vec<u8> {
+        hex::decode(json.as_str().unwrap()).unwrap()
+    }
+
+    fn text_bytes(json: &Value) -> Vec<u8> {
+        json.as_str().unwrap().as_bytes().to_vec()
+    }
+
+    /// Verifies a proof of a fixture, like a relying party.
+    fn verify_fixture_proof(
+        fixture: &Value,
+        proof: &[u8],
+        disclosed_messages: &[Vec<u8>],
+        disclosed_indexes: &[usize],
+    ) -> bool {
+        verify_presentation(
+            &decode_hex(&fixture["signerKeyPair"]["publicKey"]),
+            proof,
+            &text_bytes(&fixture["header"]),
+            &text_bytes(&fixture["presentationHeader"]),
+            disclosed_messages,
+            disclosed_indexes,
+        )
+    }
+
+    /// Generates a proof over `message_count` messages and returns its stack usage.
+    fn proof_stack_usage(message_count: usize) -> usize {
+        let mut rng = SeededRng::from_seed_u64(0);
+        let (public_key, link_secret, messages, signature, secret_prover_blind) =
+            signed_credential(&mut rng, message_count);
+        let disclosed_indexes: Vec<usize> = (0..message_count).step_by(2).collect();
+
+        let bottom = paint_stack();
+        let result = generate_proof(
+            &mut rng,
+            &public_key,
+            &messages,
+            &link_secret,
+            &signature,
+            None,
+            None,
+            &disclosed_indexes,
+            Some(&secret_prover_blind),
+        );
+        let used = painted_stack_used(bottom);
+        assert!(result.is_ok());
+        used
+    }
+
+    #[test]
+    fn test_proof_progress() {
+        let mut rng = SeededRng::from_seed_u64(0);
+        let (public_key, link_secret, messages, signature, secret_prover_blind) =
+            signed_credential(&mut rng, 2);
+        let mut stages = Vec::new();
+        let result = generate_proof_with_progress(
+            &mut rng,
+            &public_key,
+            &messages,
+            &link_secret,
+            &signature,
+            None,
+            None,
+            &[0],
+            Some(&secret_prover_blind),
+            |stage| stages.push(stage),
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            stages,
+            [
+                ProofStage::Started,
+                ProofStage::Prepared,
+                ProofStage::Computed
+            ]
+        );
+
+        // Invalid requests fail before the expensive part.
+        stages.clear();
+        let result = generate_proof_with_progress(
+            &mut rng,
+            &public_key,
+            &messages,
+            &link_secret,
+            &signature,
+            None,
+            None,
+            &[5],
+            Some(&secret_prover_blind),
+            |stage| stages.push(stage),
+        );
+        assert_eq!(result, Err(BBSError::InvalidDisclosedIndex));
+        assert_eq!(stages, [ProofStage::Started]);
+    }
+
+    #[test]
+    fn test_fixtures() {
+        let fixtures = fixtures();
+        assert!(!fixtures.is_empty());
+        for (path, fixture) in fixtures {
+            let indexes = |name: &str| -> Vec<usize> {
+                fixture[name]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|index| index.as_u64().unwrap() as usize)
+                    .collect()
+            };
+            let messages: Vec<Vec<u8>> = fixture["messages"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(text_bytes)
+                .collect();
+            let disclosed_indexes = indexes("disclosedIndexes");
+            let disclosed_messages: Vec<Vec<u8>> = disclosed_indexes
+                .iter()
+                .map(|&i| messages[i].clone())
+                .collect();
+
+            // The stored proof still verifies.
+            let proof = decode_hex(&fixture["proof"]);
+            assert_eq!(
+                indexes("outputDisclosedIndexes"),
+                disclosed_indexes,
+                "{}",
+                path
+            );
+            assert!(
+                verify_fixture_proof(&fixture, &proof, &disclosed_messages, &disclosed_indexes),
+                "{}",
+                path
+            );
+
+            // A fresh proof over the same credential verifies too.
+            let link_secret_bytes = decode_hex(&fixture["linkSecret"]);
+            let link_secret = LinkSecret::from_bytes(link_secret_bytes.try_into().unwrap());
+            let public_key =
+                BBSPublicKey::from_bytes(&decode_hex(&fixture["signerKeyPair"]["publicKey"]))
+                    .unwrap();
+            let signature =
+                BBSSignature::from_bytes(&decode_hex(&fixture["signature"]).try_into().unwrap())
+                    .unwrap();
+            let secret_prover_blind =
+                BBSCommitmentBlindFactor::from_bytes(&decode_hex(&fixture["proverBlindFactor"]))
+                    .unwrap();
+            let response = generate_proof(
+                &mut SeededRng::from_seed_u64(0),
+                &public_key,
+                &messages,
+                &link_secret,
+                &signature,
+                Some(&text_bytes(&fixture["header"])),
+                Some(&text_bytes(&fixture["presentationHeader"])),
+                &disclosed_indexes,
+                Some(&secret_prover_blind),
+            )
+            .unwrap();
+            assert_eq!(response.disclosed_messages, disclosed_messages, "{}", path);
+            assert!(
+                verify_fixture_proof(
+                    &fixture,
+                    &response.proof.to_bytes(),
+                    &disclosed_messages,
+                    &disclosed_indexes
+                ),
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_proof_stack_usage() {
+        let single = proof_stack_usage(1);
+        let many = proof_stack_usage(32);
+        assert!(
+            many < PAINTED_SIZE,
+            "The stack overflowed the painted region"
+        );
+        // Per message data lives on the heap, so the depth must not grow with the message count.
+        assert!(
+            many <= single + STACK_SLACK,
+            "{} bytes for 32 messages, {} bytes for 1",
+            many,
+            single
+        );
+        assert!(
+            many <= MAX_PROOF_STACK,
+            "{} bytes used, {} bytes available",
+            many,
+            MAX_PROOF_STACK
+        );
+    }
+}
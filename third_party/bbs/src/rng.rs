@@ -0,0 +1,54 @@
+use rand_chacha::ChaCha20Rng;
+use rand_core::{CryptoRng, RngCore, SeedableRng};
+
+/// Seedable RNG for commitments and proofs.
+///
+/// All outputs are determined by the seed. Seed it from a true RNG, or only use it for tests and
+/// fixtures.
+pub struct SeededRng(ChaCha20Rng);
+
+impl SeededRng {
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        SeededRng(ChaCha20Rng::from_seed(seed))
+    }
+
+    pub fn from_seed_u64(seed: u64) -> Self {
+        SeededRng(ChaCha20Rng::seed_from_u64(seed))
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for SeededRng {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate_link_secret_commitment, LinkSecret, SeededRng};
+
+    #[test]
+    fn test_seeded_commitment_is_reproducible() {
+        let commit = |seed| {
+            let mut rng = SeededRng::from_seed_u64(seed);
+            let link_secret = LinkSecret::random(&mut rng);
+            generate_link_secret_commitment(&mut rng, &link_secret).unwrap()
+        };
+        assert_eq!(commit(7), commit(7));
+        assert_ne!(commit(7).0, commit(8).0);
+    }
+}
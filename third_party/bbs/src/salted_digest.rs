@@ -0,0 +1,83 @@
+//! Salted digests of attributes, in the style of SD-JWT disclosures.
+//!
+//! The issuer signs the digests instead of the attribute values, so the authenticator only ever
+//! handles 32 byte digests. When presenting, the wallet sends the disclosures of the revealed
+//! digests along with the proof, and the verifier checks them against the disclosed messages.
+
+use rand_core::RngCore;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::data_integrity::base64url;
+
+/// Length of the digests, which are the messages of the credential.
+pub const SALTED_DIGEST_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+
+/// An attribute with its salt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SaltedDisclosure {
+    pub salt: [u8; SALT_SIZE],
+    pub name: String,
+    pub value: Value,
+}
+
+impl SaltedDisclosure {
+    /// Salts an attribute with fresh randomness.
+    pub fn new<R: RngCore>(rng: &mut R, name: &str, value: Value) -> Self {
+        let mut salt = [0; SALT_SIZE];
+        rng.fill_bytes(&mut salt);
+        SaltedDisclosure {
+            salt,
+            name: String::from(name),
+            value,
+        }
+    }
+
+    /// Returns the base64url encoded JSON array `[salt, name, value]`, as sent to verifiers.
+    pub fn encode(&self) -> String {
+        let array = json!([base64url(&self.salt), self.name, self.value]);
+        base64url(array.to_string().as_bytes())
+    }
+
+    /// Returns the SHA-256 digest of the encoded disclosure, which is the signed message.
+    pub fn digest(&self) -> [u8; SALTED_DIGEST_SIZE] {
+        salted_digest(&self.encode())
+    }
+}
+
+/// Returns the message of an encoded disclosure, e.g. for verifiers to compare with the proof.
+pub fn salted_digest(encoded_disclosure: &str) -> [u8; SALTED_DIGEST_SIZE] {
+    Sha256::digest(encoded_disclosure.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SeededRng;
+
+    #[test]
+    fn test_encode() {
+        let disclosure = SaltedDisclosure {
+            salt: [0; SALT_SIZE],
+            name: String::from("age"),
+            value: json!(30),
+        };
+        // ["AAAAAAAAAAAAAAAAAAAAAA","age",30]
+        assert_eq!(
+            disclosure.encode(),
+            "WyJBQUFBQUFBQUFBQUFBQUFBQUFBQUFBIiwiYWdlIiwzMF0"
+        );
+        assert_eq!(salted_digest(&disclosure.encode()), disclosure.digest());
+        assert_ne!(salted_digest("WyJ4IiwiYWdlIiwzMF0"), disclosure.digest());
+    }
+
+    #[test]
+    fn test_salts_hide_equal_values() {
+        let mut rng = SeededRng::from_seed_u64(0);
+        let first = SaltedDisclosure::new(&mut rng, "age", json!(30));
+        let second = SaltedDisclosure::new(&mut rng, "age", json!(30));
+        assert_ne!(first.salt, second.salt);
+        assert_ne!(first.digest(), second.digest());
+    }
+}
@@ -0,0 +1,122 @@
+//! Fixed-base window tables, trading flash for faster multiplications of the BBS generators.
+//!
+//! A table holds the multiples `d * 2^(WINDOW_BITS * i) * P` of its base `P`, for every window `i`
+//! and digit `d`. A multiplication is then one addition per window and no doubling. The tables are
+//! built once, e.g. at provisioning, and serialized to flash.
+
+use alloc::vec::Vec;
+use bls12_381_plus::{G1Affine, G1Projective, Scalar};
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+/// Bits of the scalar handled per addition.
+pub const WINDOW_BITS: usize = 2;
+const SCALAR_BITS: usize = 255;
+const WINDOWS: usize = (SCALAR_BITS + WINDOW_BITS - 1) / WINDOW_BITS;
+/// Nonzero digits per window, the zero digit is the identity and isn't stored.
+const DIGITS: usize = (1 << WINDOW_BITS) - 1;
+const POINT_SIZE: usize = 96;
+/// Flash used by the table of one generator, 36 KiB.
+pub const WINDOW_TABLE_SIZE: usize = WINDOWS * DIGITS * POINT_SIZE;
+
+/// Precomputed multiples of a fixed base point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowTable {
+    points: Vec<G1Affine>,
+}
+
+impl WindowTable {
+    pub fn new(base: &G1Projective) -> Self {
+        let mut points = Vec::with_capacity(WINDOWS * DIGITS);
+        let mut window_base = *base;
+        for _ in 0..WINDOWS {
+            let mut multiple = window_base;
+            for _ in 0..DIGITS {
+                points.push(G1Affine::from(multiple));
+                multiple += window_base;
+            }
+            // The next window starts at 2^WINDOW_BITS times this one.
+            window_base = multiple;
+        }
+        WindowTable { points }
+    }
+
+    /// Returns `scalar * base`.
+    ///
+    /// The time doesn't depend on the scalar, since proofs multiply by secret blinding values. Each
+    /// window scans all its digits instead of indexing the table.
+    pub fn mul(&self, scalar: &Scalar) -> G1Projective {
+        let bytes = scalar.to_le_bytes();
+        let mut result = G1Projective::IDENTITY;
+        for (window, multiples) in self.points.chunks(DIGITS).enumerate() {
+            let digit = window_digit(&bytes, window);
+            let mut selected = G1Affine::identity();
+            for (index, multiple) in multiples.iter().enumerate() {
+                let is_digit = (index as u8 + 1).ct_eq(&digit);
+                selected = G1Affine::conditional_select(&selected, multiple, is_digit);
+            }
+            result += selected;
+        }
+        result
+    }
+
+    /// Serializes the table, uncompressed to skip square roots when loading.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(WINDOW_TABLE_SIZE);
+        for point in &self.points {
+            bytes.extend_from_slice(&point.to_uncompressed());
+        }
+        bytes
+    }
+
+    /// Returns None if the size is wrong or a point is invalid, e.g. after a flash corruption.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != WINDOW_TABLE_SIZE {
+            return None;
+        }
+        let mut points = Vec::with_capacity(WINDOWS * DIGITS);
+        for chunk in bytes.chunks_exact(POINT_SIZE) {
+            let mut point = [0; POINT_SIZE];
+            point.copy_from_slice(chunk);
+            points.push(Option::from(G1Affine::from_uncompressed(&point))?);
+        }
+        Some(WindowTable { points })
+    }
+}
+
+/// Returns the digit of a window of a little-endian scalar.
+fn window_digit(bytes: &[u8; 32], window: usize) -> u8 {
+    let bit = window * WINDOW_BITS;
+    // Windows divide bytes evenly, so a digit never spans two bytes.
+    (bytes[bit / 8] >> (bit % 8)) & DIGITS as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mul() {
+        let base = G1Projective::GENERATOR * Scalar::from(5u64);
+        let table = WindowTable::new(&base);
+        for scalar in [
+            Scalar::from(0u64),
+            Scalar::from(1u64),
+            Scalar::from(0xdead_beefu64),
+            -Scalar::from(1u64),
+        ] {
+            assert_eq!(table.mul(&scalar), base * scalar);
+        }
+    }
+
+    #[test]
+    fn test_serialization() {
+        let table = WindowTable::new(&G1Projective::GENERATOR);
+        let bytes = table.to_bytes();
+        assert_eq!(bytes.len(), WINDOW_TABLE_SIZE);
+        assert_eq!(WindowTable::from_bytes(&bytes), Some(table));
+        assert_eq!(WindowTable::from_bytes(&bytes[1..]), None);
+        let mut corrupted = bytes;
+        corrupted[POINT_SIZE + 1] ^= 1;
+        assert_eq!(WindowTable::from_bytes(&corrupted), None);
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "heap_stats")]
+use crate::heap_stats;
 use crate::util;
 use core::alloc::{GlobalAlloc, Layout};
 #[cfg(any(feature = "debug_allocations", feature = "panic_console"))]
@@ -22,6 +24,13 @@ unsafe fn libtock_alloc_init(app_heap_bottom: *mut u8, app_heap_size: usize) {
     HEAP.init(app_heap_bottom, app_heap_size);
 }
 
+/// Returns the number of free heap bytes.
+///
+/// Free memory may be fragmented, so a single allocation of this size can still fail.
+pub fn free_heap() -> usize {
+    unsafe { HEAP.free() }
+}
+
 // With the "debug_allocations" feature, we use `AtomicUsize` to store the
 // statistics because:
 // - it is `Sync`, so we can use it in a static object (the allocator),
@@ -52,6 +61,10 @@ unsafe impl GlobalAlloc for TockAllocator {
             .allocate_first_fit(layout)
             .ok()
             .map_or(ptr::null_mut(), NonNull::as_ptr);
+        #[cfg(feature = "heap_stats")]
+        if !ptr.is_null() {
+            heap_stats::record_alloc(layout.size());
+        }
         #[cfg(feature = "debug_allocations")]
         {
             self.count.fetch_add(1, atomic::Ordering::SeqCst);
@@ -86,6 +99,8 @@ unsafe impl GlobalAlloc for TockAllocator {
             )
             .unwrap();
         }
+        #[cfg(feature = "heap_stats")]
+        heap_stats::record_dealloc(layout.size());
         HEAP.deallocate(NonNull::new_unchecked(ptr), layout)
     }
 }
@@ -0,0 +1,178 @@
+//! Crash reports, which the panic handler passes to a hook before the device reboots.
+//!
+//! A report holds the panic message, the last command byte received, and the stack high-water
+//! mark. The firmware registers a hook that persists it, since the panic handler has no access to
+//! the storage.
+
+use core::fmt;
+use core::fmt::Write;
+#[cfg(not(feature = "std"))]
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Longest panic message kept, longer messages are truncated.
+pub const MAX_MESSAGE_SIZE: usize = 160;
+/// Size of the encoded fields before the message.
+const HEADER_SIZE: usize = 6;
+/// Size of an encoded report with the longest message.
+pub const MAX_REPORT_SIZE: usize = HEADER_SIZE + MAX_MESSAGE_SIZE;
+
+const NO_COMMAND: usize = usize::MAX;
+static LAST_COMMAND: AtomicUsize = AtomicUsize::new(NO_COMMAND);
+static mut HOOK: Option<fn(&CrashReport)> = None;
+
+/// What is known about a crash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CrashReport {
+    /// First byte of the last CBOR command, if any was received since boot.
+    pub last_command: Option<u8>,
+    /// Most stack bytes used since boot, 0 if the stack isn't watched.
+    pub stack_high_water: u32,
+    message: [u8; MAX_MESSAGE_SIZE],
+    message_len: usize,
+}
+
+impl CrashReport {
+    /// Truncates the message to `MAX_MESSAGE_SIZE` bytes.
+    pub fn new(last_command: Option<u8>, stack_high_water: u32, message: fmt::Arguments) -> Self {
+        let mut report = CrashReport {
+            last_command,
+            stack_high_water,
+            message: [0; MAX_MESSAGE_SIZE],
+            message_len: 0,
+        };
+        // Truncation is not an error, see `write_str`.
+        let _ = report.write_fmt(message);
+        report
+    }
+
+    /// Returns the panic message, possibly truncated in the middle of a character.
+    pub fn message(&self) -> &[u8] {
+        &self.message[..self.message_len]
+    }
+
+    /// Writes the report into `buffer` and returns its length.
+    ///
+    /// The layout is one byte set to 1 if there is a last command, the command byte, the
+    /// big-endian high-water mark in 4 bytes, and the message.
+    pub fn encode(&self, buffer: &mut [u8; MAX_REPORT_SIZE]) -> usize {
+        buffer[0] = self.last_command.is_some() as u8;
+        buffer[1] = self.last_command.unwrap_or(0);
+        buffer[2..HEADER_SIZE].copy_from_slice(&self.stack_high_water.to_be_bytes());
+        buffer[HEADER_SIZE..][..self.message_len].copy_from_slice(self.message());
+        HEADER_SIZE + self.message_len
+    }
+
+    /// Returns None for malformed reports.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || bytes.len() > MAX_REPORT_SIZE || bytes[0] > 1 {
+            return None;
+        }
+        let mut stack_high_water = [0; 4];
+        stack_high_water.copy_from_slice(&bytes[2..HEADER_SIZE]);
+        let mut report = CrashReport {
+            last_command: if bytes[0] == 1 { Some(bytes[1]) } else { None },
+            stack_high_water: u32::from_be_bytes(stack_high_water),
+            message: [0; MAX_MESSAGE_SIZE],
+            message_len: bytes.len() - HEADER_SIZE,
+        };
+        report.message[..report.message_len].copy_from_slice(&bytes[HEADER_SIZE..]);
+        Some(report)
+    }
+}
+
+impl Write for CrashReport {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        let available = MAX_MESSAGE_SIZE - self.message_len;
+        let length = string.len().min(available);
+        self.message[self.message_len..][..length].copy_from_slice(&string.as_bytes()[..length]);
+        self.message_len += length;
+        Ok(())
+    }
+}
+
+/// Remembers the command being processed, in case it crashes.
+pub fn set_last_command(command: u8) {
+    LAST_COMMAND.store(command as usize, Ordering::SeqCst);
+}
+
+/// Registers the function that persists reports.
+///
+/// It runs inside the panic handler, so it should do as little as possible.
+pub fn set_hook(hook: fn(&CrashReport)) {
+    // Safety: apps are single-threaded, and the panic handler only reads it.
+    unsafe { HOOK = Some(hook) };
+}
+
+/// Builds the report of the current panic and passes it to the hook, only once.
+///
+/// A panic inside the hook doesn't call it again.
+#[cfg(not(feature = "std"))]
+pub(crate) fn report_panic(info: &core::panic::PanicInfo) {
+    // Safety: apps are single-threaded, taking the hook makes nested panics skip it.
+    let hook = match unsafe { HOOK.take() } {
+        Some(hook) => hook,
+        None => return,
+    };
+    let last_command = match LAST_COMMAND.load(Ordering::SeqCst) {
+        NO_COMMAND => None,
+        command => Some(command as u8),
+    };
+    hook(&CrashReport::new(
+        last_command,
+        stack::high_water(),
+        format_args!("{}", info),
+    ));
+}
+
+/// Stack high-water measurement, by painting the unused stack at boot.
+#[cfg(not(feature = "std"))]
+mod stack {
+    use super::*;
+
+    const PAINT: u8 = 0xa5;
+    /// Distance from the frame of the caller of `watch_stack` to the top of the stack, at most.
+    const TOP_SLACK: usize = 0x400;
+    /// Bytes below the painting frame that are left alone.
+    const FRAME_MARGIN: usize = 0x200;
+
+    static BOTTOM: AtomicUsize = AtomicUsize::new(0);
+    static TOP: AtomicUsize = AtomicUsize::new(0);
+
+    #[inline(never)]
+    pub fn watch(stack_size: usize) {
+        let marker = 0u8;
+        let top = &marker as *const u8 as usize;
+        // Overestimating the bottom keeps painting inside the stack.
+        let bottom = top + TOP_SLACK - stack_size;
+        for address in bottom..top - FRAME_MARGIN {
+            // Safety: the range is unused stack below the current frame.
+            unsafe { ptr::write_volatile(address as *mut u8, PAINT) };
+        }
+        BOTTOM.store(bottom, Ordering::SeqCst);
+        TOP.store(top, Ordering::SeqCst);
+    }
+
+    pub fn high_water() -> u32 {
+        let bottom = BOTTOM.load(Ordering::SeqCst);
+        let top = TOP.load(Ordering::SeqCst);
+        if bottom == 0 {
+            return 0;
+        }
+        // The stack grows down, so the untouched bytes are at the bottom.
+        let untouched = (bottom..top)
+            // Safety: the range was painted by `watch`.
+            .take_while(|&address| unsafe { ptr::read_volatile(address as *const u8) } == PAINT)
+            .count();
+        (top - bottom - untouched) as u32
+    }
+}
+
+/// Paints the unused stack, so that reports include the stack high-water mark.
+///
+/// Call it first thing in `main` with the stack size of the app. The bytes used before `main` are
+/// not counted.
+#[cfg(not(feature = "std"))]
+pub fn watch_stack(stack_size: usize) {
+    stack::watch(stack_size);
+}
@@ -0,0 +1,35 @@
+//! Heap usage statistics, kept up to date by the allocator.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Heap usage in bytes, not counting alignment and fragmentation overhead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes allocated right now.
+    pub current: usize,
+    /// Most bytes allocated at once since the previous call to `take`.
+    pub peak: usize,
+}
+
+#[cfg_attr(feature = "std", allow(dead_code))]
+pub(crate) fn record_alloc(size: usize) {
+    let current = CURRENT.fetch_add(size, Ordering::SeqCst) + size;
+    PEAK.fetch_max(current, Ordering::SeqCst);
+}
+
+#[cfg_attr(feature = "std", allow(dead_code))]
+pub(crate) fn record_dealloc(size: usize) {
+    CURRENT.fetch_sub(size, Ordering::SeqCst);
+}
+
+/// Returns the statistics and restarts the peak measurement.
+///
+/// Calling it before and after a command yields the peak usage of that command.
+pub fn take() -> HeapStats {
+    let current = CURRENT.load(Ordering::SeqCst);
+    let peak = PEAK.swap(current, Ordering::SeqCst);
+    HeapStats { current, peak }
+}
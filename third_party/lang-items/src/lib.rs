@@ -3,11 +3,17 @@
 
 #[cfg(not(feature = "std"))]
 mod allocator;
+pub mod crash_report;
+#[cfg(feature = "heap_stats")]
+pub mod heap_stats;
 #[cfg(not(feature = "std"))]
 mod panic_handler;
 #[cfg(not(feature = "std"))]
 mod util;
 
+#[cfg(not(feature = "std"))]
+pub use allocator::free_heap;
+
 #[cfg(feature = "std")]
 #[no_mangle]
 unsafe fn libtock_alloc_init(_app_heap_bottom: *mut u8, _app_heap_size: usize) {
@@ -1,6 +1,6 @@
 //! Custom panic handler for OpenSK
 
-use crate::util;
+use crate::{crash_report, util};
 #[cfg(feature = "panic_console")]
 use core::fmt::Write;
 #[cfg(feature = "panic_console")]
@@ -12,6 +12,8 @@ use libtock_runtime::TockSyscalls;
 #[panic_handler]
 fn panic_handler(_info: &core::panic::PanicInfo) -> ! {
     util::Util::<TockSyscalls>::signal_panic();
+    // The watchdog reboots the device afterwards, so persist what we know first.
+    crash_report::report_panic(_info);
 
     #[cfg(feature = "panic_console")]
     {
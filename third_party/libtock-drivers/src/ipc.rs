@@ -0,0 +1,151 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::result::{OutOfRangeError, TockError, TockResult};
+use crate::timer;
+use crate::timer::Duration;
+use crate::util::Util;
+use core::cell::Cell;
+use libtock_platform as platform;
+use libtock_platform::{share, DefaultConfig, ErrorCode, Syscalls};
+use platform::subscribe::OneId;
+use platform::{Subscribe, Upcall};
+
+const DRIVER_NUMBER: u32 = 0x10000;
+
+/// Ids for commands
+mod command_nr {
+    pub const NOTIFY_CLIENT: u32 = 3;
+}
+
+/// Ids for subscribe numbers
+mod subscribe_nr {
+    pub const SERVICE: u32 = 0;
+}
+
+/// A notification from a client process.
+///
+/// The kernel maps the buffer the client shared with this service into our memory, so it can be
+/// read and written until the client is notified.
+#[derive(Clone, Copy, Debug)]
+pub struct IpcRequest {
+    /// Index of the client process.
+    pub client: u32,
+    length: usize,
+    address: usize,
+}
+
+impl IpcRequest {
+    /// Returns the buffer shared by the client.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must not be used after `Ipc::notify_client` was called for this request, since
+    /// the client may reuse or revoke it.
+    pub unsafe fn buffer(&self) -> &mut [u8] {
+        core::slice::from_raw_parts_mut(self.address as *mut u8, self.length)
+    }
+}
+
+/// Receives the client index, buffer length and buffer address.
+pub struct IpcListener<F: Fn(u32, usize, usize)>(pub F);
+
+impl<F: Fn(u32, usize, usize)> Upcall<OneId<DRIVER_NUMBER, { subscribe_nr::SERVICE }>>
+    for IpcListener<F>
+{
+    fn upcall(&self, client: u32, length: u32, address: u32) {
+        self.0(client, length as usize, address as usize)
+    }
+}
+
+/// Service side of the kernel IPC mechanism.
+///
+/// Clients find this app by its package name and notify it after sharing a buffer.
+pub struct Ipc<S: Syscalls, C: platform::subscribe::Config = DefaultConfig>(S, C);
+
+impl<S: Syscalls, C: platform::subscribe::Config> Ipc<S, C> {
+    /// Waits for a client notification.
+    ///
+    /// Returns None if the timeout elapsed.
+    pub fn wait_for_request(timeout_delay: Duration<isize>) -> TockResult<Option<IpcRequest>> {
+        // None while waiting, Some(None) on timeout, Some(Some(request)) on notification.
+        let status: Cell<Option<Option<IpcRequest>>> = Cell::new(None);
+        let listener = IpcListener(|client, length, address| {
+            status.set(Some(Some(IpcRequest {
+                client,
+                length,
+                address,
+            })))
+        });
+        let mut timeout_callback = timer::with_callback::<S, C, _>(|_| status.set(Some(None)));
+        share::scope::<
+            (
+                Subscribe<_, DRIVER_NUMBER, { subscribe_nr::SERVICE }>,
+                Subscribe<S, { timer::DRIVER_NUM }, { timer::subscribe::CALLBACK }>,
+            ),
+            _,
+            _,
+        >(|handle| {
+            let (subscribe_ipc, subscribe_timer) = handle.split();
+            S::subscribe::<_, _, C, DRIVER_NUMBER, { subscribe_nr::SERVICE }>(
+                subscribe_ipc,
+                &listener,
+            )?;
+
+            let mut timeout = timeout_callback.init()?;
+            timeout_callback.enable(subscribe_timer)?;
+            timeout
+                .set_alarm(timeout_delay)
+                .map_err(|_| ErrorCode::Fail)?;
+
+            Util::<S>::yieldk_for(|| status.get().is_some());
+            S::unsubscribe(DRIVER_NUMBER, subscribe_nr::SERVICE);
+            match timeout.stop_alarm() {
+                Ok(()) | Err(TockError::Command(ErrorCode::Already)) => (),
+                Err(e) => return Err(e),
+            }
+            status.get().ok_or_else(|| TockError::from(OutOfRangeError))
+        })
+    }
+
+    /// Listens for client notifications while `waiting` runs.
+    ///
+    /// A notification is stored in `request`. Nothing needs to be cancelled afterwards.
+    pub fn listen<R>(
+        request: &Cell<Option<IpcRequest>>,
+        waiting: impl FnOnce() -> R,
+    ) -> TockResult<R> {
+        let listener = IpcListener(|client, length, address| {
+            request.set(Some(IpcRequest {
+                client,
+                length,
+                address,
+            }))
+        });
+        share::scope::<Subscribe<_, DRIVER_NUMBER, { subscribe_nr::SERVICE }>, _, _>(|handle| {
+            S::subscribe::<_, _, C, DRIVER_NUMBER, { subscribe_nr::SERVICE }>(handle, &listener)?;
+            let result = waiting();
+            S::unsubscribe(DRIVER_NUMBER, subscribe_nr::SERVICE);
+            Ok(result)
+        })
+    }
+
+    /// Tells the client that its shared buffer now holds the response.
+    pub fn notify_client(request: IpcRequest) -> TockResult<()> {
+        S::command(DRIVER_NUMBER, command_nr::NOTIFY_CLIENT, request.client, 0)
+            .to_result::<(), ErrorCode>()?;
+
+        Ok(())
+    }
+}
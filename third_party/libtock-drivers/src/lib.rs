@@ -1,11 +1,16 @@
 #![no_std]
 
 pub mod crp;
+#[cfg(feature = "with_ipc")]
+pub mod ipc;
 #[cfg(feature = "with_nfc")]
 pub mod nfc;
 pub mod result;
 pub mod rng;
 pub mod storage;
 pub mod timer;
+#[cfg(feature = "with_ccid")]
+pub mod usb_ccid;
 pub mod usb_ctap_hid;
 pub mod util;
+pub mod watchdog;
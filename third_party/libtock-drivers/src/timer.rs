@@ -8,12 +8,13 @@
 //!
 //! Adapted from the [libtock-rs](https://github.com/tock/libtock-rs/blob/master/apis/alarm/src/lib.rs) alarm driver interface
 
-use crate::result::{OtherError, TockResult};
+use crate::result::{OtherError, TockError, TockResult};
+use core::cell::Cell;
 use core::marker::PhantomData;
 use core::ops::{Add, AddAssign, Sub};
 use libtock_alarm::{Hz, Alarm, Milliseconds, Convert};
 use libtock_platform as platform;
-use libtock_platform::{DefaultConfig, ErrorCode, Syscalls};
+use libtock_platform::{share, DefaultConfig, ErrorCode, Syscalls};
 use platform::share::Handle;
 use platform::subscribe::OneId;
 use platform::{Subscribe, Upcall};
@@ -44,6 +45,27 @@ pub fn with_callback<S: Syscalls, C: platform::subscribe::Config, CB: Fn(ClockVa
     }
 }
 
+/// Arms an alarm while `waiting` runs, and sets `elapsed` once it fires.
+///
+/// Other drivers can be armed inside `waiting`, so that a single yield waits for all of them.
+pub fn with_alarm<S: Syscalls, C: platform::subscribe::Config, R>(
+    delay: Duration<isize>,
+    elapsed: &Cell<bool>,
+    waiting: impl FnOnce() -> R,
+) -> TockResult<R> {
+    let mut callback = with_callback::<S, C, _>(|_| elapsed.set(true));
+    share::scope::<Subscribe<S, DRIVER_NUM, { subscribe::CALLBACK }>, _, _>(|handle| {
+        let mut timer = callback.init()?;
+        callback.enable(handle)?;
+        timer.set_alarm(delay)?;
+        let result = waiting();
+        match timer.stop_alarm() {
+            Ok(()) | Err(TockError::Command(ErrorCode::Already)) => Ok(result),
+            Err(e) => Err(e),
+        }
+    })
+}
+
 pub struct TimerUpcallConsumer<S: Syscalls, C: platform::subscribe::Config, CB: Fn(ClockValue)> {
     data: WithCallback<S, C, CB>,
 }
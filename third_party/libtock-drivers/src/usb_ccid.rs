@@ -0,0 +1,236 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::result::{OutOfRangeError, TockError, TockResult};
+use crate::timer;
+use crate::timer::Duration;
+use crate::util::Util;
+use core::cell::Cell;
+use libtock_platform as platform;
+use libtock_platform::{share, DefaultConfig, ErrorCode, Syscalls};
+use platform::share::Handle;
+use platform::subscribe::OneId;
+use platform::{AllowRo, AllowRw, Subscribe, Upcall};
+
+const DRIVER_NUMBER: u32 = 0x20010;
+
+/// Largest CCID message, i.e. a short APDU with header and maximum Lc and Le.
+pub const MAX_MESSAGE_LENGTH: usize = 271;
+
+/// Ids for commands
+mod command_nr {
+    pub const CHECK: u32 = 0;
+    pub const TRANSMIT: u32 = 1;
+    pub const RECEIVE: u32 = 2;
+    pub const CANCEL: u32 = 3;
+}
+
+/// Ids for subscribe numbers
+mod subscribe_nr {
+    pub const TRANSMIT: u32 = 0;
+    pub const RECEIVE: u32 = 1;
+}
+
+mod ro_allow_nr {
+    pub const TRANSMIT: u32 = 0;
+}
+
+mod rw_allow_nr {
+    pub const RECEIVE: u32 = 0;
+}
+
+pub trait Config:
+    platform::allow_ro::Config + platform::allow_rw::Config + platform::subscribe::Config
+{
+}
+
+impl<T: platform::allow_ro::Config + platform::allow_rw::Config + platform::subscribe::Config>
+    Config for T
+{
+}
+
+/// Receives the message length for receptions, and nothing for transmissions.
+pub struct UsbCcidListener<F: Fn(usize)>(pub F);
+
+impl<const SUB_NUM: u32, F: Fn(usize)> Upcall<OneId<DRIVER_NUMBER, SUB_NUM>>
+    for UsbCcidListener<F>
+{
+    fn upcall(&self, length: u32, _: u32, _: u32) {
+        self.0(length as usize)
+    }
+}
+
+/// USB smart card interface, exchanging whole CCID messages on the bulk endpoints.
+///
+/// The kernel reassembles messages that span multiple USB packets.
+pub struct UsbCcid<S: Syscalls, C: Config = DefaultConfig>(S, C);
+
+impl<S: Syscalls, C: Config> UsbCcid<S, C> {
+    fn register_listener<'share, const SUB_NUM: u32, F: Fn(usize)>(
+        listener: &'share UsbCcidListener<F>,
+        subscribe: Handle<Subscribe<'share, S, DRIVER_NUMBER, SUB_NUM>>,
+    ) -> Result<(), ErrorCode> {
+        S::subscribe::<_, _, C, DRIVER_NUMBER, SUB_NUM>(subscribe, listener)
+    }
+
+    /// Checks whether the board exposes a CCID interface.
+    pub fn setup() -> bool {
+        S::command(DRIVER_NUMBER, command_nr::CHECK, 0, 0)
+            .to_result::<(), ErrorCode>()
+            .is_ok()
+    }
+
+    /// Waits to receive a message.
+    ///
+    /// Returns the message length, or None if the timeout elapsed.
+    pub fn recv_with_timeout(
+        buf: &mut [u8; MAX_MESSAGE_LENGTH],
+        timeout_delay: Duration<isize>,
+    ) -> TockResult<Option<usize>> {
+        // None while waiting, Some(None) on timeout, Some(Some(length)) on reception.
+        let status: Cell<Option<Option<usize>>> = Cell::new(None);
+        let listener = UsbCcidListener(|length| status.set(Some(Some(length))));
+        let mut timeout_callback = timer::with_callback::<S, C, _>(|_| status.set(Some(None)));
+        let status = share::scope::<
+            (
+                AllowRw<_, DRIVER_NUMBER, { rw_allow_nr::RECEIVE }>,
+                Subscribe<_, DRIVER_NUMBER, { subscribe_nr::RECEIVE }>,
+                Subscribe<S, { timer::DRIVER_NUM }, { timer::subscribe::CALLBACK }>,
+            ),
+            _,
+            _,
+        >(|handle| {
+            let (allow, subscribe_recv, subscribe_timer) = handle.split();
+            S::allow_rw::<C, DRIVER_NUMBER, { rw_allow_nr::RECEIVE }>(allow, buf)?;
+            Self::register_listener::<{ subscribe_nr::RECEIVE }, _>(&listener, subscribe_recv)?;
+
+            let mut timeout = timeout_callback.init()?;
+            timeout_callback.enable(subscribe_timer)?;
+            timeout
+                .set_alarm(timeout_delay)
+                .map_err(|_| ErrorCode::Fail)?;
+
+            S::command(DRIVER_NUMBER, command_nr::RECEIVE, 0, 0).to_result::<(), ErrorCode>()?;
+
+            Util::<S>::yieldk_for(|| status.get().is_some());
+            S::unsubscribe(DRIVER_NUMBER, subscribe_nr::RECEIVE);
+            Self::stop_alarm(timeout.stop_alarm());
+            status.get().ok_or_else(|| TockError::from(OutOfRangeError))
+        })?;
+        if status.is_none() {
+            Self::cancel();
+        }
+        Ok(status)
+    }
+
+    /// Arms a message reception while `waiting` runs.
+    ///
+    /// Once a message arrives, its length is stored in `received`. The reception is cancelled
+    /// when `waiting` returns without a message.
+    pub fn listen<R>(
+        buf: &mut [u8; MAX_MESSAGE_LENGTH],
+        received: &Cell<Option<usize>>,
+        waiting: impl FnOnce() -> R,
+    ) -> TockResult<R> {
+        let listener = UsbCcidListener(|length| received.set(Some(length)));
+        let result = share::scope::<
+            (
+                AllowRw<_, DRIVER_NUMBER, { rw_allow_nr::RECEIVE }>,
+                Subscribe<_, DRIVER_NUMBER, { subscribe_nr::RECEIVE }>,
+            ),
+            _,
+            _,
+        >(|handle| {
+            let (allow, subscribe_recv) = handle.split();
+            S::allow_rw::<C, DRIVER_NUMBER, { rw_allow_nr::RECEIVE }>(allow, buf)?;
+            Self::register_listener::<{ subscribe_nr::RECEIVE }, _>(&listener, subscribe_recv)?;
+            S::command(DRIVER_NUMBER, command_nr::RECEIVE, 0, 0).to_result::<(), ErrorCode>()?;
+
+            let result = waiting();
+            S::unsubscribe(DRIVER_NUMBER, subscribe_nr::RECEIVE);
+            Ok::<R, TockError>(result)
+        });
+        if received.get().is_none() {
+            Self::cancel();
+        }
+        result
+    }
+
+    /// Sends a message.
+    ///
+    /// Returns false if the timeout elapsed before the host read it.
+    pub fn send(buf: &[u8], timeout_delay: Duration<isize>) -> TockResult<bool> {
+        // None while waiting, Some(false) on timeout, Some(true) once sent.
+        let status: Cell<Option<bool>> = Cell::new(None);
+        let listener = UsbCcidListener(|_| status.set(Some(true)));
+        let mut timeout_callback = timer::with_callback::<S, C, _>(|_| status.set(Some(false)));
+        let sent = share::scope::<
+            (
+                AllowRo<_, DRIVER_NUMBER, { ro_allow_nr::TRANSMIT }>,
+                Subscribe<_, DRIVER_NUMBER, { subscribe_nr::TRANSMIT }>,
+                Subscribe<S, { timer::DRIVER_NUM }, { timer::subscribe::CALLBACK }>,
+            ),
+            _,
+            _,
+        >(|handle| {
+            let (allow, subscribe_send, subscribe_timer) = handle.split();
+            S::allow_ro::<C, DRIVER_NUMBER, { ro_allow_nr::TRANSMIT }>(allow, buf)?;
+            Self::register_listener::<{ subscribe_nr::TRANSMIT }, _>(&listener, subscribe_send)?;
+
+            let mut timeout = timeout_callback.init()?;
+            timeout_callback.enable(subscribe_timer)?;
+            timeout
+                .set_alarm(timeout_delay)
+                .map_err(|_| ErrorCode::Fail)?;
+
+            S::command(DRIVER_NUMBER, command_nr::TRANSMIT, buf.len() as u32, 0)
+                .to_result::<(), ErrorCode>()?;
+
+            Util::<S>::yieldk_for(|| status.get().is_some());
+            S::unsubscribe(DRIVER_NUMBER, subscribe_nr::TRANSMIT);
+            Self::stop_alarm(timeout.stop_alarm());
+            status.get().ok_or_else(|| TockError::from(OutOfRangeError))
+        })?;
+        if !sent {
+            Self::cancel();
+        }
+        Ok(sent)
+    }
+
+    fn stop_alarm(result: TockResult<()>) {
+        match result {
+            Ok(()) | Err(TockError::Command(ErrorCode::Already)) => (),
+            Err(_e) => {
+                #[cfg(feature = "debug_ctap")]
+                panic!("Unexpected error when stopping alarm: {:?}", _e);
+                #[cfg(not(feature = "debug_ctap"))]
+                panic!("Unexpected error when stopping alarm: <error is only visible with the debug_ctap feature>");
+            }
+        }
+    }
+
+    /// Cancels the pending USB transaction after a timeout.
+    fn cancel() {
+        let result =
+            S::command(DRIVER_NUMBER, command_nr::CANCEL, 0, 0).to_result::<(), ErrorCode>();
+        match result {
+            // - SUCCESS means that we successfully cancelled the transaction.
+            // - EALREADY means that the transaction was already completed.
+            // - EBUSY means that the transaction is in progress, but we don't wait to avoid a
+            //   deadlock.
+            Ok(_) | Err(ErrorCode::Already) | Err(ErrorCode::Busy) => (),
+            Err(e) => panic!("Unexpected error when cancelling USB transfer: {:?}", e),
+        }
+    }
+}
@@ -283,26 +283,82 @@ impl<S: Syscalls, C: Config> UsbCtapHid<S, C> {
                 "Cancelling USB receive due to timeout"
             )
             .unwrap();
-            let result =
-                S::command(DRIVER_NUMBER, command_nr::CANCEL, 0, 0).to_result::<(), ErrorCode>();
-            match result {
-                // - SUCCESS means that we successfully cancelled the transaction.
-                // - EALREADY means that the transaction was already completed.
-                Ok(_) | Err(ErrorCode::Already) => (),
-                // - EBUSY means that the transaction is in progress.
-                Err(ErrorCode::Busy) => {
-                    // The app should wait for it, but it may never happen if the remote app crashes.
-                    // We just return to avoid a deadlock.
-                    #[cfg(feature = "debug_ctap")]
-                    writeln!(Console::<S>::writer(), "Couldn't cancel the USB receive").unwrap();
-                }
-                Err(e) => panic!("Unexpected error when cancelling USB receive: {:?}", e),
-            }
+            Self::cancel_receive();
         }
 
         status
     }
 
+    /// Arms a packet reception while `waiting` runs.
+    ///
+    /// Once a packet arrives, its endpoint is stored in `received`. Contrary to
+    /// `recv_with_timeout`, this doesn't yield by itself, so that the caller can wait for other
+    /// drivers at the same time. The reception is cancelled when `waiting` returns without a packet.
+    pub fn listen<R>(
+        buf: &mut [u8; 64],
+        received: &Cell<Option<u32>>,
+        waiting: impl FnOnce() -> R,
+    ) -> TockResult<R> {
+        let listener = UsbCtapHidListener(|direction, endpoint| {
+            if direction == subscribe_nr::RECEIVE {
+                received.set(Some(endpoint));
+            }
+        });
+        let result = share::scope::<
+            (
+                AllowRw<_, DRIVER_NUMBER, { rw_allow_nr::RECEIVE }>,
+                Subscribe<_, DRIVER_NUMBER, { subscribe_nr::RECEIVE }>,
+            ),
+            _,
+            _,
+        >(|handle| {
+            let (allow, subscribe_recv) = handle.split();
+            S::allow_rw::<C, DRIVER_NUMBER, { rw_allow_nr::RECEIVE }>(allow, buf)?;
+            Self::register_listener::<{ subscribe_nr::RECEIVE }, _>(&listener, subscribe_recv)?;
+            S::command(DRIVER_NUMBER, command_nr::RECEIVE, 0, 0).to_result::<(), ErrorCode>()?;
+
+            let result = waiting();
+            Self::unregister_listener(subscribe_nr::RECEIVE);
+            Ok::<R, TockError>(result)
+        });
+
+        if received.get().is_none() {
+            Self::cancel_receive();
+        }
+
+        #[cfg(feature = "verbose_usb")]
+        if let Some(endpoint) = received.get() {
+            writeln!(
+                Console::<S>::writer(),
+                "Received packet = {:02x?} on endpoint {}",
+                buf as &[u8],
+                endpoint as u8,
+            )
+            .unwrap();
+        }
+
+        result
+    }
+
+    /// Cancels a pending reception.
+    fn cancel_receive() {
+        let result =
+            S::command(DRIVER_NUMBER, command_nr::CANCEL, 0, 0).to_result::<(), ErrorCode>();
+        match result {
+            // - SUCCESS means that we successfully cancelled the transaction.
+            // - EALREADY means that the transaction was already completed.
+            Ok(_) | Err(ErrorCode::Already) => (),
+            // - EBUSY means that the transaction is in progress.
+            Err(ErrorCode::Busy) => {
+                // The app should wait for it, but it may never happen if the remote app crashes.
+                // We just return to avoid a deadlock.
+                #[cfg(feature = "debug_ctap")]
+                writeln!(Console::<S>::writer(), "Couldn't cancel the USB receive").unwrap();
+            }
+            Err(e) => panic!("Unexpected error when cancelling USB receive: {:?}", e),
+        }
+    }
+
     fn send_detail(
         buf: &[u8; 64],
         timeout_delay: Duration<isize>,
@@ -0,0 +1,36 @@
+use crate::result::TockResult;
+use libtock_platform as platform;
+use libtock_platform::{DefaultConfig, Syscalls};
+use platform::ErrorCode;
+
+const DRIVER_NUMBER: u32 = 0x90007;
+
+mod command_nr {
+    pub const AVAILABLE: u32 = 0;
+    pub const START: u32 = 1;
+    pub const TICKLE: u32 = 2;
+}
+
+pub struct Watchdog<S: Syscalls, C: platform::subscribe::Config = DefaultConfig>(S, C);
+
+impl<S: Syscalls, C: platform::subscribe::Config> Watchdog<S, C> {
+    pub fn is_available() -> TockResult<()> {
+        S::command(DRIVER_NUMBER, command_nr::AVAILABLE, 0, 0).to_result::<(), ErrorCode>()?;
+
+        Ok(())
+    }
+
+    /// Starts the watchdog. The kernel resets the chip if not tickled within the timeout.
+    pub fn start(timeout_ms: usize) -> TockResult<()> {
+        S::command(DRIVER_NUMBER, command_nr::START, timeout_ms as u32, 0)
+            .to_result::<(), ErrorCode>()?;
+
+        Ok(())
+    }
+
+    pub fn tickle() -> TockResult<()> {
+        S::command(DRIVER_NUMBER, command_nr::TICKLE, 0, 0).to_result::<(), ErrorCode>()?;
+
+        Ok(())
+    }
+}
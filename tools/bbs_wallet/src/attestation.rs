@@ -0,0 +1,138 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks of statements that OpenSK signs with its batch attestation key.
+//!
+//! Commitments and migration transport keys are signed this way, and the host trusts them if the
+//! certificate is one of the known batch certificates.
+
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use std::fmt;
+use x509_cert::der::{Decode, DecodePem, Encode};
+use x509_cert::Certificate;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttestationError {
+    /// The certificate isn't one of the trusted batch certificates.
+    UntrustedCertificate,
+    InvalidCertificate,
+    InvalidSignature,
+}
+
+impl fmt::Display for AttestationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            AttestationError::UntrustedCertificate => "the attestation certificate isn't trusted",
+            AttestationError::InvalidCertificate => "the attestation certificate is malformed",
+            AttestationError::InvalidSignature => "the attestation signature is invalid",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Returns the DER encoding of a certificate given in DER or PEM.
+pub fn certificate_der(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if bytes.starts_with(b"-----BEGIN") {
+        let certificate = Certificate::from_pem(bytes).map_err(|e| e.to_string())?;
+        return certificate.to_der().map_err(|e| e.to_string());
+    }
+    Certificate::from_der(bytes).map_err(|e| e.to_string())?;
+    Ok(bytes.to_vec())
+}
+
+/// Checks the DER signature of the message by the key of a trusted certificate.
+pub fn verify_attestation(
+    message: &[u8],
+    signature: &[u8],
+    certificate: &[u8],
+    trusted_certificates: &[Vec<u8>],
+) -> Result<(), AttestationError> {
+    if !trusted_certificates
+        .iter()
+        .any(|trusted| trusted == certificate)
+    {
+        return Err(AttestationError::UntrustedCertificate);
+    }
+    let signature =
+        Signature::from_der(signature).map_err(|_| AttestationError::InvalidSignature)?;
+    attestation_key(certificate)?
+        .verify(message, &signature)
+        .map_err(|_| AttestationError::InvalidSignature)
+}
+
+/// Returns the P-256 key of an attestation certificate.
+fn attestation_key(certificate: &[u8]) -> Result<VerifyingKey, AttestationError> {
+    let certificate =
+        Certificate::from_der(certificate).map_err(|_| AttestationError::InvalidCertificate)?;
+    let public_key = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .raw_bytes();
+    VerifyingKey::from_sec1_bytes(public_key).map_err(|_| AttestationError::InvalidCertificate)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+
+    const SAMPLE_CERTIFICATE: &[u8] =
+        include_bytes!("../../../reproducible/sample_crypto_data/opensk_cert.pem");
+    // The key of the sample certificate, in `reproducible/sample_crypto_data/opensk.key`.
+    const SAMPLE_PRIVATE_KEY: &str =
+        "231f9c5cbb2f7cb9a7630e19ecdabc0792fa7a0a62d4f6d660dc5a594ded8d73";
+
+    #[test]
+    fn test_certificate_der() {
+        let der = certificate_der(SAMPLE_CERTIFICATE).unwrap();
+        assert_eq!(certificate_der(&der), Ok(der.clone()));
+        assert!(attestation_key(&der).is_ok());
+        assert!(certificate_der(&der[1..]).is_err());
+    }
+
+    #[test]
+    fn test_verify_attestation() {
+        let certificate = certificate_der(SAMPLE_CERTIFICATE).unwrap();
+        let trusted = vec![certificate.clone()];
+        let signing_key =
+            SigningKey::from_slice(&hex::decode(SAMPLE_PRIVATE_KEY).unwrap()).unwrap();
+        let signature: Signature = signing_key.sign(b"message");
+        let signature = signature.to_der().as_bytes().to_vec();
+
+        assert_eq!(
+            verify_attestation(b"message", &signature, &certificate, &trusted),
+            Ok(())
+        );
+        assert_eq!(
+            verify_attestation(b"other", &signature, &certificate, &trusted),
+            Err(AttestationError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_attestation(b"message", &signature[1..], &certificate, &trusted),
+            Err(AttestationError::InvalidSignature)
+        );
+        assert_eq!(
+            verify_attestation(b"message", &signature, &certificate, &[]),
+            Err(AttestationError::UntrustedCertificate)
+        );
+        let malformed = vec![0x30; 8];
+        assert_eq!(
+            verify_attestation(b"message", &signature, &malformed, &[malformed.clone()]),
+            Err(AttestationError::InvalidCertificate)
+        );
+    }
+}
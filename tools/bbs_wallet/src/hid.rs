@@ -0,0 +1,393 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Minimal CTAPHID transport to exchange CBOR messages with OpenSK.
+
+use hidapi::{DeviceInfo, HidApi, HidDevice};
+use rand_core::{OsRng, RngCore};
+use sk_cbor::destructure_cbor_map;
+use std::ffi::CString;
+use std::fmt;
+
+const OPENSK_VID: u16 = 0x1915;
+const OPENSK_PID: u16 = 0x521F;
+const FIDO_USAGE_PAGE: u16 = 0xF1D0;
+const VENDOR_USAGE_PAGE: u16 = 0xFF00;
+
+pub const PACKET_SIZE: usize = 64;
+const INIT_DATA_SIZE: usize = PACKET_SIZE - 7;
+const CONT_DATA_SIZE: usize = PACKET_SIZE - 5;
+const MAX_MESSAGE_SIZE: usize = INIT_DATA_SIZE + 128 * CONT_DATA_SIZE;
+const BROADCAST_CID: [u8; 4] = [0xFF; 4];
+
+const CMD_INIT: u8 = 0x06;
+const CMD_CBOR: u8 = 0x10;
+const CMD_KEEPALIVE: u8 = 0x3B;
+const CMD_ERROR: u8 = 0x3F;
+const TYPE_INIT: u8 = 0x80;
+
+const CTAP2_GET_INFO: u8 = 0x04;
+const CTAP2_OK: u8 = 0x00;
+/// Assumed when GetInfo doesn't advertise maxMsgSize, see CTAP 2.1 section 6.4.
+const DEFAULT_MAX_MSG_SIZE: usize = 1024;
+
+/// Waiting for the user can take a while, keepalives show the device is still busy.
+const READ_TIMEOUT_MS: i32 = 5000;
+
+#[derive(Debug)]
+pub enum HidError {
+    /// No OpenSK was found, or opening it failed.
+    NoDevice,
+    Io(hidapi::HidError),
+    /// The connection to a virtual authenticator failed.
+    Tcp(std::io::Error),
+    /// The device sent a CTAPHID_ERROR with this code.
+    Ctaphid(u8),
+    /// The device didn't answer in time.
+    Timeout,
+    /// The device answered something that doesn't follow the protocol.
+    Protocol,
+    /// The request is longer than the maxMsgSize of the device.
+    TooLarge {
+        size: usize,
+        max_msg_size: usize,
+    },
+}
+
+impl fmt::Display for HidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HidError::NoDevice => write!(f, "no OpenSK device found"),
+            HidError::Io(e) => write!(f, "HID error: {}", e),
+            HidError::Tcp(e) => write!(f, "TCP error: {}", e),
+            HidError::Ctaphid(code) => write!(f, "CTAPHID error 0x{:02X}", code),
+            HidError::Timeout => write!(f, "timeout while waiting for the device"),
+            HidError::Protocol => write!(f, "unexpected CTAPHID packet"),
+            HidError::TooLarge { size, max_msg_size } => write!(
+                f,
+                "request of {} bytes exceeds the device limit of {} bytes",
+                size, max_msg_size
+            ),
+        }
+    }
+}
+
+impl From<hidapi::HidError> for HidError {
+    fn from(e: hidapi::HidError) -> Self {
+        HidError::Io(e)
+    }
+}
+
+/// Exchanges raw CTAPHID packets with a device.
+pub trait Connection {
+    fn write_packet(&self, packet: &[u8; PACKET_SIZE]) -> Result<(), HidError>;
+
+    /// Returns None if no packet arrived in time.
+    fn read_packet(&self, timeout_ms: i32) -> Result<Option<[u8; PACKET_SIZE]>, HidError>;
+}
+
+impl Connection for HidDevice {
+    fn write_packet(&self, packet: &[u8; PACKET_SIZE]) -> Result<(), HidError> {
+        // The first byte is the report ID.
+        let mut report = vec![0x00];
+        report.extend_from_slice(packet);
+        self.write(&report)?;
+        Ok(())
+    }
+
+    fn read_packet(&self, timeout_ms: i32) -> Result<Option<[u8; PACKET_SIZE]>, HidError> {
+        let mut packet = [0u8; PACKET_SIZE];
+        let len = self.read_timeout(&mut packet, timeout_ms)?;
+        Ok((len != 0).then(|| packet))
+    }
+}
+
+/// Returns the USB ID in an environment variable, for firmware built with other IDs.
+fn usb_id(name: &str, default: u16) -> u16 {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| u16::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+        .unwrap_or(default)
+}
+
+fn is_opensk(info: &DeviceInfo) -> bool {
+    info.vendor_id() == usb_id("OPENSK_USB_VENDOR_ID", OPENSK_VID)
+        && info.product_id() == usb_id("OPENSK_USB_PRODUCT_ID", OPENSK_PID)
+}
+
+/// An OpenSK device with an allocated channel.
+pub struct Device {
+    connection: Box<dyn Connection>,
+    cid: [u8; 4],
+    max_msg_size: usize,
+    compression: bool,
+}
+
+impl Device {
+    /// Opens the first OpenSK found, preferring the vendor interface.
+    ///
+    /// Vendor commands are only accepted on the vendor interface if the firmware has one.
+    pub fn open() -> Result<Device, HidError> {
+        Device::open_usage_pages(&[VENDOR_USAGE_PAGE, FIDO_USAGE_PAGE])
+    }
+
+    /// Opens the FIDO interface of the first OpenSK found, for standard CTAP2 commands.
+    pub fn open_fido() -> Result<Device, HidError> {
+        Device::open_usage_pages(&[FIDO_USAGE_PAGE])
+    }
+
+    /// Returns the HID paths of the connected OpenSK devices, for `open_path`.
+    ///
+    /// Devices are listed by their vendor interface, or by their FIDO interface if none has one.
+    pub fn paths() -> Result<Vec<String>, HidError> {
+        let api = HidApi::new()?;
+        let candidates = api
+            .device_list()
+            .filter(|info| is_opensk(info))
+            .collect::<Vec<_>>();
+        let usage_page = if candidates
+            .iter()
+            .any(|info| info.usage_page() == VENDOR_USAGE_PAGE)
+        {
+            VENDOR_USAGE_PAGE
+        } else {
+            FIDO_USAGE_PAGE
+        };
+        Ok(candidates
+            .iter()
+            .filter(|info| info.usage_page() == usage_page)
+            .map(|info| info.path().to_string_lossy().into_owned())
+            .collect())
+    }
+
+    /// Opens the device at a path returned by `paths`, e.g. one of several connected devices.
+    pub fn open_path(path: &str) -> Result<Device, HidError> {
+        let api = HidApi::new()?;
+        let path = CString::new(path).map_err(|_| HidError::NoDevice)?;
+        Device::with_connection(Box::new(api.open_path(&path)?))
+    }
+
+    /// Opens an interface with one of the usage pages, preferring the first ones.
+    fn open_usage_pages(usage_pages: &[u16]) -> Result<Device, HidError> {
+        let api = HidApi::new()?;
+        let mut candidates = api
+            .device_list()
+            .filter(|info| is_opensk(info))
+            .filter(|info| usage_pages.contains(&info.usage_page()))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|info| usage_pages.iter().position(|&p| p == info.usage_page()));
+        let info = candidates.first().ok_or(HidError::NoDevice)?;
+        Device::with_connection(Box::new(info.open_device(&api)?))
+    }
+
+    /// Allocates a channel on an opened connection, e.g. to a simulated device.
+    pub fn with_connection(connection: Box<dyn Connection>) -> Result<Device, HidError> {
+        let mut device = Device {
+            connection,
+            cid: BROADCAST_CID,
+            max_msg_size: MAX_MESSAGE_SIZE,
+            compression: false,
+        };
+        device.init()?;
+        device.max_msg_size = device.read_max_msg_size()?;
+        Ok(device)
+    }
+
+    /// Returns the longest CBOR request the device accepts.
+    pub fn max_msg_size(&self) -> usize {
+        self.max_msg_size
+    }
+
+    /// Returns whether vendor commands are sent compressed, see `vendor::negotiate_compression`.
+    pub fn compression(&self) -> bool {
+        self.compression
+    }
+
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
+    /// Allocates a channel with CTAPHID_INIT.
+    fn init(&mut self) -> Result<(), HidError> {
+        let mut nonce = [0u8; 8];
+        OsRng.fill_bytes(&mut nonce);
+        let response = self.transact(CMD_INIT, &nonce)?;
+        if response.len() < 12 || response[..8] != nonce {
+            return Err(HidError::Protocol);
+        }
+        self.cid.copy_from_slice(&response[8..12]);
+        Ok(())
+    }
+
+    /// Reads maxMsgSize from GetInfo, which every interface answers.
+    fn read_max_msg_size(&self) -> Result<usize, HidError> {
+        let response = self.transact(CMD_CBOR, &[CTAP2_GET_INFO])?;
+        let info = match response.split_first() {
+            Some((&CTAP2_OK, data)) => sk_cbor::read(data).map_err(|_| HidError::Protocol)?,
+            _ => return Err(HidError::Protocol),
+        };
+        destructure_cbor_map! {
+            let {
+                0x05 => max_msg_size,
+            } = info.extract_map().ok_or(HidError::Protocol)?;
+        }
+        match max_msg_size {
+            None => Ok(DEFAULT_MAX_MSG_SIZE),
+            Some(value) => value
+                .extract_unsigned()
+                .map(|size| (size as usize).min(MAX_MESSAGE_SIZE))
+                .ok_or(HidError::Protocol),
+        }
+    }
+
+    /// Sends a CTAPHID_CBOR message and returns the response payload.
+    ///
+    /// Requests longer than the maxMsgSize of the device fail before sending anything.
+    pub fn cbor(&self, request: &[u8]) -> Result<Vec<u8>, HidError> {
+        if request.len() > self.max_msg_size {
+            return Err(HidError::TooLarge {
+                size: request.len(),
+                max_msg_size: self.max_msg_size,
+            });
+        }
+        self.transact(CMD_CBOR, request)
+    }
+
+    fn transact(&self, cmd: u8, payload: &[u8]) -> Result<Vec<u8>, HidError> {
+        for packet in split_message(&self.cid, cmd, payload) {
+            self.connection.write_packet(&packet)?;
+        }
+        let mut assembler = Assembler::new(self.cid, cmd);
+        loop {
+            let packet = self
+                .connection
+                .read_packet(READ_TIMEOUT_MS)?
+                .ok_or(HidError::Timeout)?;
+            if let Some(message) = assembler.push(&packet)? {
+                return Ok(message);
+            }
+        }
+    }
+}
+
+/// Splits a message into CTAPHID packets for the channel.
+fn split_message(cid: &[u8; 4], cmd: u8, payload: &[u8]) -> Vec<[u8; PACKET_SIZE]> {
+    assert!(payload.len() <= MAX_MESSAGE_SIZE);
+    let mut packets = Vec::new();
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..4].copy_from_slice(cid);
+    packet[4] = cmd | TYPE_INIT;
+    packet[5..7].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+    let first_len = payload.len().min(INIT_DATA_SIZE);
+    packet[7..7 + first_len].copy_from_slice(&payload[..first_len]);
+    packets.push(packet);
+    for (seq, chunk) in payload[first_len..].chunks(CONT_DATA_SIZE).enumerate() {
+        let mut packet = [0u8; PACKET_SIZE];
+        packet[..4].copy_from_slice(cid);
+        packet[4] = seq as u8;
+        packet[5..5 + chunk.len()].copy_from_slice(chunk);
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Reassembles the response to a command from incoming packets.
+struct Assembler {
+    cid: [u8; 4],
+    cmd: u8,
+    expected_len: usize,
+    next_seq: u8,
+    payload: Vec<u8>,
+}
+
+impl Assembler {
+    fn new(cid: [u8; 4], cmd: u8) -> Assembler {
+        Assembler {
+            cid,
+            cmd,
+            expected_len: 0,
+            next_seq: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Processes a packet, and returns the message once complete.
+    fn push(&mut self, packet: &[u8; PACKET_SIZE]) -> Result<Option<Vec<u8>>, HidError> {
+        // The answer to INIT is broadcast, other answers use our channel.
+        if packet[..4] != self.cid {
+            return Ok(None);
+        }
+        if packet[4] & TYPE_INIT != 0 {
+            let cmd = packet[4] & !TYPE_INIT;
+            let len = u16::from_be_bytes([packet[5], packet[6]]) as usize;
+            match cmd {
+                CMD_KEEPALIVE => return Ok(None),
+                CMD_ERROR => return Err(HidError::Ctaphid(packet[7])),
+                _ if cmd != self.cmd || len > MAX_MESSAGE_SIZE => return Err(HidError::Protocol),
+                _ => (),
+            }
+            self.expected_len = len;
+            self.next_seq = 0;
+            self.payload = packet[7..7 + len.min(INIT_DATA_SIZE)].to_vec();
+        } else {
+            if packet[4] != self.next_seq || self.payload.len() >= self.expected_len {
+                return Err(HidError::Protocol);
+            }
+            self.next_seq += 1;
+            let remaining = self.expected_len - self.payload.len();
+            self.payload
+                .extend_from_slice(&packet[5..5 + remaining.min(CONT_DATA_SIZE)]);
+        }
+        if self.payload.len() == self.expected_len {
+            Ok(Some(std::mem::take(&mut self.payload)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_and_assemble() {
+        let cid = [0x12, 0x34, 0x56, 0x78];
+        for len in [0, 1, INIT_DATA_SIZE, INIT_DATA_SIZE + 1, 1000] {
+            let payload = (0..len).map(|i| i as u8).collect::<Vec<_>>();
+            let packets = split_message(&cid, CMD_CBOR, &payload);
+            let mut assembler = Assembler::new(cid, CMD_CBOR);
+            let (last, others) = packets.split_last().unwrap();
+            for packet in others {
+                assert_eq!(assembler.push(packet).unwrap(), None);
+            }
+            assert_eq!(assembler.push(last).unwrap(), Some(payload));
+        }
+    }
+
+    #[test]
+    fn test_assemble_keepalive_and_error() {
+        let cid = [0x12, 0x34, 0x56, 0x78];
+        let mut assembler = Assembler::new(cid, CMD_CBOR);
+        let keepalive = split_message(&cid, CMD_KEEPALIVE, &[0x01]);
+        assert_eq!(assembler.push(&keepalive[0]).unwrap(), None);
+        let other_channel = split_message(&[0x00; 4], CMD_CBOR, &[0x00]);
+        assert_eq!(assembler.push(&other_channel[0]).unwrap(), None);
+        let error = split_message(&cid, CMD_ERROR, &[0x06]);
+        assert!(matches!(
+            assembler.push(&error[0]),
+            Err(HidError::Ctaphid(0x06))
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test issuer and verifier, to exercise the whole flow without a real issuer.
+//!
+//! Never use these keys for anything but testing.
+
+use crate::vendor::{Challenge, Commitment};
+/// Verifies a proof against the issuer public key and the disclosed messages.
+pub use bbs::verify_presentation as verify_proof;
+use bbs::{verify_link_secret_commitment, BBSPublicKey, BBSSecretKey, BBS};
+use rand_core::{OsRng, RngCore};
+use serde_json::Value;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zkryptium::schemes::generics::BlindSignature;
+
+/// How long a commitment challenge is accepted, in seconds.
+const CHALLENGE_LIFETIME: u64 = 300;
+
+pub struct TestIssuer {
+    secret_key: BBSSecretKey,
+    public_key: BBSPublicKey,
+    public_key_bytes: Vec<u8>,
+}
+
+impl TestIssuer {
+    /// Loads the key pair from a JSON file.
+    ///
+    /// The file contains `secretKey` and `publicKey` in hex, like `signerKeyPair` in
+    /// `third_party/bbs/fixtures/proof.json`, which is also accepted.
+    pub fn load(path: &str) -> Result<TestIssuer, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        let key_pair = json.get("signerKeyPair").unwrap_or(&json);
+        let secret_key_bytes = hex_field(key_pair, "secretKey")?;
+        let public_key_bytes = hex_field(key_pair, "publicKey")?;
+        let secret_key = BBSSecretKey::from_bytes(&secret_key_bytes)
+            .map_err(|_| String::from("invalid issuer secret key"))?;
+        let public_key = BBSPublicKey::from_bytes(&public_key_bytes)
+            .map_err(|_| String::from("invalid issuer public key"))?;
+        Ok(TestIssuer {
+            secret_key,
+            public_key,
+            public_key_bytes,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key_bytes
+    }
+
+    /// Creates a fresh challenge for the next commitment, expiring in a few minutes.
+    pub fn challenge(&self) -> Challenge {
+        let mut nonce = vec![0; 16];
+        OsRng.fill_bytes(&mut nonce);
+        Challenge {
+            nonce,
+            expiry: unix_time() + CHALLENGE_LIFETIME,
+        }
+    }
+
+    /// Blindly signs the messages, bound to the committed link secret.
+    ///
+    /// The commitment must answer the given challenge before it expires. This test issuer only
+    /// compares the echoed challenge, it doesn't check the attestation signature.
+    pub fn issue(
+        &self,
+        commitment: &Commitment,
+        challenge: &Challenge,
+        header: &[u8],
+        messages: &[Vec<u8>],
+    ) -> Result<Vec<u8>, String> {
+        if commitment.challenge.as_ref() != Some(challenge) || commitment.signature.is_none() {
+            return Err(String::from("commitment doesn't answer the challenge"));
+        }
+        if unix_time() > challenge.expiry {
+            return Err(String::from("commitment challenge expired"));
+        }
+        let commitment_with_proof = &commitment.commitment_with_proof;
+        if !verify_link_secret_commitment(commitment_with_proof).unwrap_or(false) {
+            return Err(String::from("invalid link secret commitment"));
+        }
+        let signature = BlindSignature::<BBS>::blind_sign(
+            &self.secret_key,
+            &self.public_key,
+            Some(commitment_with_proof),
+            Some(header),
+            Some(messages),
+            None,
+        )
+        .map_err(|_| String::from("blind signature failed"))?;
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn hex_field(json: &Value, key: &str) -> Result<Vec<u8>, String> {
+    let field = json
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("missing {}", key))?;
+    hex::decode(field).map_err(|_| format!("{} is not hex", key))
+}
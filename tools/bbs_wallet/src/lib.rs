@@ -0,0 +1,28 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Host side of the anonymous credential flow of OpenSK.
+//!
+//! The wallet binary and the conformance tests in `tests/` share it.
+
+extern crate alloc;
+
+pub mod attestation;
+pub mod hid;
+pub mod issuer;
+#[cfg(feature = "std")]
+pub mod simulation;
+pub mod tcp;
+pub mod vendor;
+pub mod wallet;
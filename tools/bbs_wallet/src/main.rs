@@ -0,0 +1,1183 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference client for the anonymous credential flow of OpenSK.
+//!
+//! The authenticator holds the link secret. It commits to it for issuance, and proves possession
+//! of credentials bound to it, without ever revealing it.
+
+use bbs::{DerivedProof, DerivedProofFeature, SaltedDisclosure};
+use bbs_wallet::attestation::{certificate_der, verify_attestation};
+use bbs_wallet::hid::Device;
+use bbs_wallet::issuer::{self, TestIssuer};
+use bbs_wallet::tcp;
+use bbs_wallet::vendor::{
+    self, AttestationMaterial, NonRevocationProof, TransportKey, VendorBBSProofRequest,
+};
+use bbs_wallet::wallet::{
+    signed_messages, AttributeSchema, AttributeType, Credential, PendingIssuance, Wallet,
+};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn parse_cli() -> ArgMatches<'static> {
+    App::new("BBS wallet")
+        .version("0.1")
+        .about("Provisions OpenSK and manages BBS credentials bound to its link secret")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("wallet")
+                .long("wallet")
+                .value_name("FILE")
+                .help("JSON file storing the credentials")
+                .takes_value(true)
+                .default_value("bbs_wallet.json"),
+        )
+        .subcommand(
+            SubCommand::with_name("provision")
+                .about("Programs attestation material and the link secret, or shows what is set")
+                .arg(
+                    Arg::with_name("certificate")
+                        .long("certificate")
+                        .value_name("FILE")
+                        .help("DER file containing the attestation certificate")
+                        .takes_value(true)
+                        .requires("private-key"),
+                )
+                .arg(
+                    Arg::with_name("private-key")
+                        .long("private-key")
+                        .value_name("FILE")
+                        .help("File containing the attestation private key, 32 bytes in hex")
+                        .takes_value(true)
+                        .requires("certificate"),
+                )
+                .arg(
+                    Arg::with_name("link-secret")
+                        .long("link-secret")
+                        .value_name("FILE")
+                        .help("File containing the link secret in hex, see generate_link_secret.py")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("lockdown")
+                        .long("lockdown")
+                        .value_name("LEVEL")
+                        .help(
+                            "Raises the lockdown level: config stops provisioning, upgrade also \
+                             stops upgrades, full also locks the firmware",
+                        )
+                        .takes_value(true)
+                        .possible_values(&["config", "upgrade", "full"]),
+                )
+                .arg(
+                    Arg::with_name("permissions")
+                        .long("permissions")
+                        .value_name("NAMES")
+                        .help(
+                            "Replaces the enabled vendor commands, before lockdown: a comma \
+                             separated list of provisioning, upgrade, bbs-issue, bbs-present, \
+                             admin and credential-import",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("aaguid")
+                        .long("aaguid")
+                        .value_name("UUID")
+                        .help(
+                            "Replaces the AAGUID of the firmware with the one of a customer, if \
+                             the firmware allows it",
+                        )
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Shows the FIDO metadata, BBS limits and policies of the device"),
+        )
+        .subcommand(
+            SubCommand::with_name("commitment")
+                .about("Requests a commitment to the link secret, requires user presence"),
+        )
+        .subcommand(
+            SubCommand::with_name("authorize")
+                .about("Lets the next proofs skip the touch, after one user verification")
+                .arg(
+                    Arg::with_name("proofs")
+                        .long("proofs")
+                        .value_name("COUNT")
+                        .help("Number of proofs to authorize, 0 revokes the authorization")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("seconds")
+                        .long("seconds")
+                        .value_name("SECONDS")
+                        .help("How long the authorization lasts, as long as allowed if omitted")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("issue")
+                .about("Gets a credential from a test issuer and stores it in the wallet")
+                .arg(
+                    Arg::with_name("issuer")
+                        .long("issuer")
+                        .value_name("FILE")
+                        .help("JSON file with the issuer key pair")
+                        .takes_value(true)
+                        .default_value("third_party/bbs/fixtures/proof.json"),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("Name of the credential in the wallet")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("header")
+                        .long("header")
+                        .value_name("TEXT")
+                        .help("Header signed with the messages")
+                        .takes_value(true)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::with_name("message")
+                        .long("message")
+                        .value_name("TEXT")
+                        .help("Attribute to sign, repeat for each attribute")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("attribute")
+                        .long("attribute")
+                        .value_name("NAME:TYPE")
+                        .help(
+                            "Describes the message at the same position, TYPE is text, \
+                             integer, boolean or date",
+                        )
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("salted-digests")
+                        .long("salted-digests")
+                        .help("Signs salted digests of NAME=VALUE attributes, like SD-JWT"),
+                )
+                .arg(
+                    Arg::with_name("valid-days")
+                        .long("valid-days")
+                        .value_name("DAYS")
+                        .help("Days until the credential expires, never if omitted")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("revocation-handle")
+                        .long("revocation-handle")
+                        .value_name("TEXT")
+                        .help("Handle of the credential in the revocation registry of the issuer")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("scoped-link-secret")
+                        .long("scoped-link-secret")
+                        .help("Binds the credential to a link secret derived for this issuer only"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("Lists the credentials in the wallet")
+                .arg(
+                    Arg::with_name("usage")
+                        .long("usage")
+                        .help("Shows how often the device presented each credential"),
+                )
+                .arg(
+                    Arg::with_name("issuer-names")
+                        .long("issuer-names")
+                        .help("Shows the issuer names registered on the device"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("issuer")
+                .about("Registers an issuer on the device, which then refuses unknown issuers")
+                .arg(
+                    Arg::with_name("public-key")
+                        .long("public-key")
+                        .value_name("HEX")
+                        .help("Public key of the issuer")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("Name shown for the issuer, at most 32 bytes")
+                        .takes_value(true)
+                        .required_unless("remove"),
+                )
+                .arg(
+                    Arg::with_name("messages")
+                        .long("messages")
+                        .value_name("COUNT")
+                        .help("Number of messages of the credentials of the issuer")
+                        .takes_value(true)
+                        .required_unless("remove"),
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .long("remove")
+                        .help("Removes the issuer instead")
+                        .conflicts_with_all(&["name", "messages"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pair")
+                .about("Shares the recovery secret of a device with a backup device")
+                .arg(
+                    Arg::with_name("primary")
+                        .long("primary")
+                        .value_name("PATH")
+                        .help("Path of the device to back up")
+                        .takes_value(true)
+                        .required_unless("forget"),
+                )
+                .arg(
+                    Arg::with_name("backup")
+                        .long("backup")
+                        .value_name("PATH")
+                        .help("Path of the backup device")
+                        .takes_value(true)
+                        .required_unless("forget"),
+                )
+                .arg(
+                    Arg::with_name("trusted-certificate")
+                        .long("trusted-certificate")
+                        .value_name("FILE")
+                        .help("Batch certificate the backup must attest with, in DER or PEM")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required_unless("forget"),
+                )
+                .arg(
+                    Arg::with_name("forget")
+                        .long("forget")
+                        .value_name("PATH")
+                        .help("Path of a device to unpair instead")
+                        .takes_value(true)
+                        .conflicts_with_all(&["primary", "backup"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Moves the BBS issuers of a device to its replacement")
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .help("Lists the paths of connected devices"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("PATH")
+                        .help("Path of the device to migrate from")
+                        .takes_value(true)
+                        .required_unless("list"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("PATH")
+                        .help("Path of the replacement device")
+                        .takes_value(true)
+                        .required_unless("list"),
+                )
+                .arg(
+                    Arg::with_name("trusted-certificate")
+                        .long("trusted-certificate")
+                        .value_name("FILE")
+                        .help("Batch certificate the replacement must attest with, in DER or PEM")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required_unless("list"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke-all")
+                .about("Revokes all credentials bound to an issuer, which can't be undone"),
+        )
+        .subcommand(
+            SubCommand::with_name("compact")
+                .about("Compacts the store of the device, so that later writes don't stall")
+                .arg(
+                    Arg::with_name("words")
+                        .long("words")
+                        .value_name("WORDS")
+                        .help("Stops once that many words are writable, instead of all")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("store-health")
+                .about("Shows whether the store of the device is corrupted"),
+        )
+        .subcommand(
+            SubCommand::with_name("crash-report")
+                .about("Shows why the device last panicked")
+                .arg(
+                    Arg::with_name("clear")
+                        .long("clear")
+                        .help("Clears the report after showing it"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("log")
+                .about("Shows the trace of recent events on the device")
+                .arg(
+                    Arg::with_name("clear")
+                        .long("clear")
+                        .help("Clears the trace after showing it"),
+                )
+                .arg(
+                    Arg::with_name("level")
+                        .long("level")
+                        .value_name("LEVEL")
+                        .help("Changes which messages are recorded from now on")
+                        .takes_value(true)
+                        .possible_values(&["error", "warn", "info", "debug", "trace"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ping")
+                .about("Proves possession of a credential without disclosing any attribute")
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("Name of the credential in the wallet")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("nonce")
+                        .long("nonce")
+                        .value_name("TEXT")
+                        .help("Nonce chosen by the verifier")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("prove")
+                .about("Requests a proof for a credential, disclosing only some attributes")
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("Name of the credential in the wallet")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("disclose")
+                        .long("disclose")
+                        .value_name("INDEX")
+                        .help("Index of an attribute to disclose, repeat for each attribute")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("presentation-header")
+                        .long("presentation-header")
+                        .value_name("TEXT")
+                        .help("Presentation header, e.g. a nonce chosen by the verifier")
+                        .takes_value(true)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::with_name("context-salt")
+                        .long("context-salt")
+                        .value_name("HEX")
+                        .help("32 byte salt of the verifier, binds the proof to a stable device context")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("prehash-presentation-header")
+                        .long("prehash-presentation-header")
+                        .help("Proves over the SHA-256 digest of the presentation header"),
+                )
+                .arg(
+                    Arg::with_name("bind-device-state")
+                        .long("bind-device-state")
+                        .help("Starts the presentation header with the firmware version and lockdown level"),
+                )
+                .arg(
+                    Arg::with_name("status-list-index")
+                        .long("status-list-index")
+                        .value_name("INDEX")
+                        .help("Discloses the attribute at INDEX as the status list entry, for verifiers to check revocation")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("verification-method")
+                        .long("verification-method")
+                        .value_name("URL")
+                        .help("Issuer key to print a bbs-2023 Data Integrity proof for")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("allow-expired")
+                        .long("allow-expired")
+                        .help("Presents the credential even if it expired"),
+                ),
+        )
+        .get_matches()
+}
+
+fn fatal(message: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    exit(1)
+}
+
+/// Opens the connected OpenSK, or the virtual authenticator at the address in `OPENSK_TCP`.
+fn open_device() -> Device {
+    let device = match std::env::var("OPENSK_TCP") {
+        Ok(address) => tcp::open(&address),
+        Err(_) => Device::open(),
+    };
+    with_compression(device.unwrap_or_else(|e| fatal(e)))
+}
+
+/// Compresses vendor payloads if the firmware supports it, others keep plain ones.
+fn with_compression(mut device: Device) -> Device {
+    vendor::negotiate_compression(&mut device).ok();
+    device
+}
+
+fn read_hex_file(path: &str) -> Vec<u8> {
+    let contents = fs::read_to_string(path).unwrap_or_else(|e| fatal(format!("{}: {}", path, e)));
+    hex::decode(contents.trim()).unwrap_or_else(|_| fatal(format!("{} is not hex", path)))
+}
+
+fn provision(matches: &ArgMatches) {
+    let certificate = matches.value_of("certificate").map(|certificate| {
+        fs::read(certificate).unwrap_or_else(|e| fatal(format!("{}: {}", certificate, e)))
+    });
+    let private_key = matches.value_of("private-key").map(read_hex_file);
+    let link_secret = matches.value_of("link-secret").map(read_hex_file);
+    let material = if certificate.is_some() || link_secret.is_some() {
+        Some(AttestationMaterial {
+            certificate,
+            private_key,
+            link_secret,
+        })
+    } else {
+        None
+    };
+    let lockdown_level = match matches.value_of("lockdown") {
+        None => 0,
+        Some("config") => 1,
+        Some("upgrade") => 2,
+        Some(_) => 3,
+    };
+    let permissions = matches.value_of("permissions").map(parse_permissions);
+    let aaguid = matches.value_of("aaguid").map(|aaguid| {
+        vendor::parse_aaguid(aaguid).unwrap_or_else(|| fatal(format!("{} is no AAGUID", aaguid)))
+    });
+    if material.is_some() || aaguid.is_some() || lockdown_level > 0 {
+        println!("Touch the device to confirm.");
+    }
+    let device = open_device();
+    let response = vendor::configure(&device, material, lockdown_level, permissions, aaguid)
+        .unwrap_or_else(|e| fatal(e));
+    println!("Certificate programmed: {}", response.cert_programmed);
+    println!("Private key programmed: {}", response.pkey_programmed);
+    println!(
+        "Link secret programmed: {}",
+        response.link_secret_programmed
+    );
+    println!("Customer AAGUID programmed: {}", response.aaguid_programmed);
+    println!("Lockdown level: {}", response.lockdown_level);
+    let enabled: Vec<&str> = vendor::PERMISSION_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| response.permissions & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    println!("Permissions: {}", enabled.join(", "));
+}
+
+fn parse_permissions(names: &str) -> u64 {
+    names
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            match vendor::PERMISSION_NAMES
+                .iter()
+                .position(|known| *known == name)
+            {
+                Some(bit) => 1 << bit,
+                None => fatal(format!("unknown permission {}", name)),
+            }
+        })
+        .fold(0, |mask, bit| mask | bit)
+}
+
+fn info() {
+    let device = open_device();
+    let metadata = vendor::info(&device).unwrap_or_else(|e| fatal(e));
+    println!(
+        "AAGUID: {}{}",
+        vendor::format_aaguid(&metadata.aaguid),
+        if metadata.custom_aaguid {
+            " (customer)"
+        } else {
+            ""
+        }
+    );
+    println!("Description: {}", metadata.description);
+    match metadata.certification_level {
+        Some(level) => println!("FIDO certification: {}", certification_name(level)),
+        None => println!("FIDO certification: none"),
+    }
+    if let Some(version) = metadata.firmware_version {
+        println!("Firmware version: {}", version);
+    }
+    println!(
+        "Compression: {}",
+        if device.compression() { "LZSS" } else { "none" }
+    );
+    let info = vendor::bbs_info(&device).unwrap_or_else(|e| fatal(e));
+    println!("Maximum messages per credential: {}", info.max_messages);
+    println!("Maximum stored credentials: {}", info.max_credentials);
+    println!("Maximum proof size: {} bytes", info.max_proof_size);
+    println!("Requires user verification: {}", info.requires_uv);
+    println!(
+        "Accepts an external link secret: {}",
+        info.allows_external_link_secret
+    );
+}
+
+/// Returns the name of a certification level, as in getInfo.
+fn certification_name(level: u64) -> String {
+    match level {
+        1 => String::from("L1"),
+        2 => String::from("L1+"),
+        3 => String::from("L2"),
+        4 => String::from("L2+"),
+        5 => String::from("L3"),
+        6 => String::from("L3+"),
+        _ => format!("unknown level {}", level),
+    }
+}
+
+fn log(matches: &ArgMatches) {
+    let max_level = matches.value_of("level").map(|level| match level {
+        "error" => 1,
+        "warn" => 2,
+        "info" => 3,
+        "debug" => 4,
+        _ => 5,
+    });
+    let contents = vendor::log(&open_device(), matches.is_present("clear"), max_level)
+        .unwrap_or_else(|e| fatal(e));
+    print!("{}", String::from_utf8_lossy(&contents));
+}
+
+fn authorize(matches: &ArgMatches) {
+    let proofs = matches.value_of("proofs").unwrap();
+    let proofs = proofs
+        .parse::<u64>()
+        .unwrap_or_else(|_| fatal(format!("invalid number of proofs {}", proofs)));
+    let duration_ms = matches.value_of("seconds").map(|seconds| {
+        seconds
+            .parse::<u64>()
+            .unwrap_or_else(|_| fatal(format!("invalid number of seconds {}", seconds)))
+            .saturating_mul(1000)
+    });
+    let (proofs, duration_ms) =
+        vendor::bbs_authorize(&open_device(), proofs, duration_ms).unwrap_or_else(|e| fatal(e));
+    if proofs == 0 {
+        return println!("Revoked the authorization.");
+    }
+    println!(
+        "Authorized {} proofs for {} seconds.",
+        proofs,
+        duration_ms / 1000
+    );
+}
+
+fn crash_report(matches: &ArgMatches) {
+    let report = vendor::crash_report(&open_device(), matches.is_present("clear"))
+        .unwrap_or_else(|e| fatal(e));
+    let report = match report {
+        Some(report) => report,
+        None => return println!("No crash recorded."),
+    };
+    match report.last_command {
+        Some(command) => println!("Last command: 0x{:02X}", command),
+        None => println!("Last command: none"),
+    }
+    println!("Stack high-water mark: {} bytes", report.stack_high_water);
+    println!("Message: {}", String::from_utf8_lossy(&report.message));
+}
+
+fn compact(matches: &ArgMatches) {
+    let length = matches.value_of("words").map(|words| {
+        words
+            .parse::<u64>()
+            .unwrap_or_else(|_| fatal(format!("invalid number of words {}", words)))
+    });
+    let device = open_device();
+    loop {
+        let progress = vendor::compact(&device, length, 4).unwrap_or_else(|e| fatal(e));
+        let target = length.map_or(progress.remaining, |length| length.min(progress.remaining));
+        println!(
+            "Compacted {} pages: {} -> {} of {} words writable ({} stored of {})",
+            progress.pages,
+            progress.immediate_before,
+            progress.immediate.min(target),
+            target,
+            progress.total - progress.remaining,
+            progress.total
+        );
+        if progress.done {
+            break;
+        }
+    }
+}
+
+fn issuer(matches: &ArgMatches) {
+    let public_key = hex::decode(matches.value_of("public-key").unwrap())
+        .unwrap_or_else(|_| fatal("the public key must be hex"));
+    let device = open_device();
+    if matches.is_present("remove") {
+        vendor::remove_issuer(&device, &public_key).unwrap_or_else(|e| fatal(e));
+        println!("Removed the issuer.");
+        return;
+    }
+    let name = matches.value_of("name").unwrap();
+    let messages = matches.value_of("messages").unwrap();
+    let num_messages = messages
+        .parse::<u8>()
+        .unwrap_or_else(|_| fatal(format!("invalid number of messages {}", messages)));
+    vendor::register_issuer(&device, &public_key, name, num_messages).unwrap_or_else(|e| fatal(e));
+    println!("Registered issuer {}.", name);
+}
+
+fn revoke_all() {
+    println!(
+        "Touch the device to confirm. Credentials bound to an issuer stop working afterwards."
+    );
+    let epoch = vendor::bbs_revoke_all(&open_device()).unwrap_or_else(|e| fatal(e));
+    println!("Revoked. The device is at epoch {}.", epoch);
+}
+
+fn store_health() {
+    let health = vendor::store_health(&open_device()).unwrap_or_else(|e| fatal(e));
+    if health.corrupted {
+        println!("The store is corrupted and read-only. Resetting the device wipes it.");
+    } else {
+        println!("The store is healthy.");
+    }
+    if let Some(entries) = health.entries {
+        println!("Entries: {}", entries);
+    }
+    if let Some((used, total)) = health.lifetime {
+        println!("Lifetime: {} of {} words written", used, total);
+    }
+}
+
+fn commitment() {
+    println!("Touch the device to confirm.");
+    let commitment =
+        vendor::bbs_commitment(&open_device(), None, None, None).unwrap_or_else(|e| fatal(e));
+    println!(
+        "Commitment with proof: {}",
+        hex::encode(&commitment.commitment_with_proof)
+    );
+    println!(
+        "Prover blind factor: {}",
+        hex::encode(&commitment.secret_prover_blind)
+    );
+}
+
+fn issue(matches: &ArgMatches, wallet_path: &Path) {
+    let issuer = TestIssuer::load(matches.value_of("issuer").unwrap()).unwrap_or_else(|e| fatal(e));
+    let mut wallet = Wallet::load(wallet_path).unwrap_or_else(|e| fatal(e));
+    let header = matches.value_of("header").unwrap();
+    let salted_digests = matches.is_present("salted-digests");
+    let messages = matches.values_of("message").map_or(Vec::new(), |values| {
+        values.map(String::from).collect::<Vec<_>>()
+    });
+    let schema = matches.values_of("attribute").map(|values| {
+        values
+            .map(|attribute| {
+                let (name, attribute_type) =
+                    attribute.split_once(':').unwrap_or((attribute, "text"));
+                AttributeSchema {
+                    name: String::from(name),
+                    attribute_type: AttributeType::parse(attribute_type)
+                        .unwrap_or_else(|| fatal(format!("unknown type {}", attribute_type))),
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+    if schema
+        .as_ref()
+        .map_or(false, |schema| schema.len() != messages.len())
+    {
+        fatal("give one --attribute per --message");
+    }
+    let valid_days = matches.value_of("valid-days").map(|days| {
+        days.parse::<u64>()
+            .unwrap_or_else(|_| fatal(format!("invalid number of days {}", days)))
+    });
+    let revocation_handle = matches.value_of("revocation-handle").map(String::from);
+    let scoped_link_secret = matches.is_present("scoped-link-secret");
+    let messages = if salted_digests {
+        messages
+            .iter()
+            .map(|message| {
+                let (name, value) = message
+                    .split_once('=')
+                    .unwrap_or_else(|| fatal(format!("{} is not NAME=VALUE", message)));
+                SaltedDisclosure::new(&mut OsRng, name, serde_json::json!(value)).encode()
+            })
+            .collect()
+    } else {
+        messages
+    };
+    let message_bytes = signed_messages(&messages, salted_digests);
+
+    let name = String::from(matches.value_of("name").unwrap());
+
+    let device = open_device();
+    let info = vendor::bbs_info(&device).unwrap_or_else(|e| fatal(e));
+    if message_bytes.len() > info.max_messages {
+        fatal(format!(
+            "The device proves at most {} messages.",
+            info.max_messages
+        ));
+    }
+    if wallet.get(&name).is_none() && wallet.names().count() >= info.max_credentials {
+        fatal(format!(
+            "The wallet already holds the maximum of {} credentials.",
+            info.max_credentials
+        ));
+    }
+    // An issuance interrupted before storing its credential continues with the same challenge.
+    let challenge = match PendingIssuance::load(wallet_path) {
+        Some(pending)
+            if pending.issuer_public_key == issuer.public_key()
+                && pending.challenge.expiry > issuer::unix_time() =>
+        {
+            pending.challenge
+        }
+        _ => {
+            let pending = PendingIssuance {
+                issuer_public_key: issuer.public_key().to_vec(),
+                challenge: issuer.challenge(),
+            };
+            pending.save(wallet_path).unwrap_or_else(|e| fatal(e));
+            pending.challenge
+        }
+    };
+    println!("Touch the device to confirm.");
+    let issuer_public_key = issuer.public_key().to_vec();
+    let scope = scoped_link_secret.then(|| &issuer_public_key[..]);
+    let recovery_scope = vendor::recovery_scope(&issuer_public_key);
+    let commitment =
+        vendor::bbs_commitment(&device, Some(&challenge), scope, Some(&recovery_scope))
+            .unwrap_or_else(|e| fatal(e));
+    if commitment.resumed {
+        println!("Resumed the interrupted issuance.");
+    }
+    let signature = issuer
+        .issue(&commitment, &challenge, header.as_bytes(), &message_bytes)
+        .unwrap_or_else(|e| fatal(e));
+    let credential = Credential {
+        name,
+        public_key: issuer.public_key().to_vec(),
+        header: String::from(header),
+        messages,
+        signature,
+        prover_blind_factor: commitment.secret_prover_blind,
+        salted_digests,
+        schema,
+        issued_at: Some(issuer::unix_time()),
+        expires_at: valid_days.map(|days| issuer::unix_time() + days * SECONDS_PER_DAY),
+        revocation_handle,
+        scoped_link_secret,
+    };
+    wallet.insert(&credential);
+    wallet.save(wallet_path).unwrap_or_else(|e| fatal(e));
+    PendingIssuance::remove(wallet_path).unwrap_or_else(|e| fatal(e));
+    println!("Stored credential {}.", credential.name);
+}
+
+fn list(matches: &ArgMatches, wallet_path: &Path) {
+    let wallet = Wallet::load(wallet_path).unwrap_or_else(|e| fatal(e));
+    let (boot_count, mut usage) = if matches.is_present("usage") {
+        vendor::bbs_usage(&open_device()).unwrap_or_else(|e| fatal(e))
+    } else {
+        (0, Vec::new())
+    };
+    let issuers = if matches.is_present("issuer-names") {
+        vendor::bbs_info(&open_device())
+            .unwrap_or_else(|e| fatal(e))
+            .issuers
+    } else {
+        Vec::new()
+    };
+    for name in wallet.names() {
+        let credential = wallet
+            .get(name)
+            .unwrap_or_else(|| fatal(format!("credential {} is corrupted", name)));
+        println!("{}:", name);
+        if let Some(issued_at) = credential.issued_at {
+            println!("  issued at: {}", issued_at);
+        }
+        if let Some(expires_at) = credential.expires_at {
+            let expired = if credential.is_expired(issuer::unix_time()) {
+                " (expired)"
+            } else {
+                ""
+            };
+            println!("  expires at: {}{}", expires_at, expired);
+        }
+        if let Some(revocation_handle) = &credential.revocation_handle {
+            println!("  revocation handle: {}", revocation_handle);
+        }
+        if matches.is_present("issuer-names") {
+            match issuers
+                .iter()
+                .find(|issuer| issuer.public_key == credential.public_key)
+            {
+                Some(issuer) => println!("  issuer: {}", issuer.name),
+                None => println!("  issuer: {}", hex::encode(&credential.public_key)),
+            }
+        }
+        for (index, message) in credential.messages.iter().enumerate() {
+            println!("  {}. {}: {}", index, credential.label(index), message);
+        }
+        if matches.is_present("usage") {
+            let credential_id = vendor::credential_id(&credential.signature);
+            match usage
+                .iter()
+                .position(|entry| entry.credential_id == credential_id)
+            {
+                Some(position) => {
+                    let entry = usage.remove(position);
+                    println!(
+                        "  presentations: {}, last {} boots ago",
+                        entry.presentations,
+                        boot_count.saturating_sub(entry.last_boot)
+                    );
+                }
+                None => println!("  presentations: none recorded"),
+            }
+        }
+    }
+    // Credentials the device presented without this wallet knowing them deserve a look.
+    for entry in usage {
+        println!(
+            "Unknown credential {}: {} presentations, last {} boots ago",
+            hex::encode(&entry.credential_id),
+            entry.presentations,
+            boot_count.saturating_sub(entry.last_boot)
+        );
+    }
+}
+
+fn read_trusted_certificates(matches: &ArgMatches) -> Vec<Vec<u8>> {
+    matches
+        .values_of("trusted-certificate")
+        .unwrap()
+        .map(|path| {
+            let bytes = fs::read(path).unwrap_or_else(|e| fatal(format!("{}: {}", path, e)));
+            certificate_der(&bytes).unwrap_or_else(|e| fatal(format!("{}: {}", path, e)))
+        })
+        .collect()
+}
+
+/// Opens a HID path, or a virtual authenticator with the `tcp:` prefix before its address.
+fn open_device_path(path: &str) -> Device {
+    let device = match path.strip_prefix("tcp:") {
+        Some(address) => tcp::open(address),
+        None => Device::open_path(path),
+    };
+    with_compression(device.unwrap_or_else(|e| fatal(e)))
+}
+
+/// Has the device generate a transport key, and checks that a trusted device signed it.
+fn prepare_transport_key(device: &Device, trusted_certificates: &[Vec<u8>]) -> TransportKey {
+    let transport_key = vendor::migration_prepare(device).unwrap_or_else(|e| fatal(e));
+    verify_attestation(
+        &transport_key.transcript(),
+        &transport_key.signature,
+        &transport_key.certificate,
+        trusted_certificates,
+    )
+    .unwrap_or_else(|e| fatal(e));
+    transport_key
+}
+
+fn pair(matches: &ArgMatches) {
+    if let Some(path) = matches.value_of("forget") {
+        println!("Touch the device to confirm.");
+        vendor::recovery_forget(&open_device_path(path)).unwrap_or_else(|e| fatal(e));
+        return println!("The device is no longer paired.");
+    }
+    let trusted_certificates = read_trusted_certificates(matches);
+    let primary = open_device_path(matches.value_of("primary").unwrap());
+    let backup = open_device_path(matches.value_of("backup").unwrap());
+
+    println!("Touch the backup device to confirm.");
+    let transport_key = prepare_transport_key(&backup, &trusted_certificates);
+    println!("Touch the primary device to confirm.");
+    let bundle =
+        vendor::recovery_share(&primary, &transport_key.public_key).unwrap_or_else(|e| fatal(e));
+    println!("Touch the backup device to confirm.");
+    vendor::recovery_accept(&backup, &bundle).unwrap_or_else(|e| fatal(e));
+    println!("Paired. Issuers of the primary device recognize the backup if you lose it.");
+}
+
+fn migrate(matches: &ArgMatches) {
+    if matches.is_present("list") {
+        for path in Device::paths().unwrap_or_else(|e| fatal(e)) {
+            println!("{}", path);
+        }
+        return;
+    }
+    let trusted_certificates = read_trusted_certificates(matches);
+    let source = open_device_path(matches.value_of("from").unwrap());
+    let replacement = open_device_path(matches.value_of("to").unwrap());
+
+    println!("Touch the replacement device to confirm.");
+    let transport_key = prepare_transport_key(&replacement, &trusted_certificates);
+    println!("Touch the old device to confirm. Its credentials stop working afterwards.");
+    let bundle =
+        vendor::migration_export(&source, &transport_key.public_key).unwrap_or_else(|e| fatal(e));
+    println!("Touch the replacement device to confirm.");
+    let count = vendor::migration_import(&replacement, &bundle).unwrap_or_else(|e| fatal(e));
+    println!(
+        "Migrated {} issuers. Ask them to issue your credentials again.",
+        count
+    );
+}
+
+fn ping(matches: &ArgMatches, wallet_path: &Path) {
+    let wallet = Wallet::load(wallet_path).unwrap_or_else(|e| fatal(e));
+    let name = matches.value_of("name").unwrap();
+    let credential = wallet
+        .get(name)
+        .unwrap_or_else(|| fatal(format!("no credential named {}", name)));
+    let nonce = matches.value_of("nonce").unwrap();
+    let messages = credential.message_bytes();
+
+    println!("Touch the device to confirm.");
+    let response = vendor::bbs_possession(
+        &open_device(),
+        VendorBBSProofRequest {
+            public_key: credential.public_key.clone(),
+            messages: messages.clone(),
+            signature: credential.signature.clone(),
+            header: credential.header.as_bytes().to_vec(),
+            presentation_header: nonce.as_bytes().to_vec(),
+            secret_prover_blind: Some(credential.prover_blind_factor.clone()),
+            salted_digests: credential.salted_digests,
+            scoped_link_secret: credential.scoped_link_secret,
+            ..VendorBBSProofRequest::default()
+        },
+    )
+    .unwrap_or_else(|e| fatal(e));
+    println!("Proof: {}", hex::encode(&response.proof_bytes));
+    if !issuer::verify_proof(
+        &credential.public_key,
+        &response.proof_bytes,
+        credential.header.as_bytes(),
+        nonce.as_bytes(),
+        &[],
+        &[],
+    ) {
+        fatal("the proof doesn't verify");
+    }
+    println!("The proof verifies.");
+}
+
+fn prove(matches: &ArgMatches, wallet_path: &Path) {
+    let wallet = Wallet::load(wallet_path).unwrap_or_else(|e| fatal(e));
+    let name = matches.value_of("name").unwrap();
+    let credential = wallet
+        .get(name)
+        .unwrap_or_else(|| fatal(format!("no credential named {}", name)));
+    if credential.is_expired(issuer::unix_time()) && !matches.is_present("allow-expired") {
+        fatal(format!(
+            "credential {} expired, pass --allow-expired to present it anyway",
+            name
+        ));
+    }
+    let mut disclosed_indexes = matches.values_of("disclose").map_or(Vec::new(), |values| {
+        values
+            .map(|index| match index.parse::<usize>() {
+                Ok(index) if index < credential.messages.len() => index,
+                _ => fatal(format!("invalid attribute index {}", index)),
+            })
+            .collect()
+    });
+    let status_list_index =
+        matches
+            .value_of("status-list-index")
+            .map(|index| match index.parse::<usize>() {
+                Ok(index) if index < credential.messages.len() => index,
+                _ => fatal(format!("invalid attribute index {}", index)),
+            });
+    disclosed_indexes.extend(status_list_index);
+    disclosed_indexes.sort_unstable();
+    disclosed_indexes.dedup();
+    let host_presentation_header = matches.value_of("presentation-header").unwrap();
+    let context_salt = matches.value_of("context-salt").map(|salt| {
+        match hex::decode(salt).map(<[u8; 32]>::try_from) {
+            Ok(Ok(salt)) => salt,
+            _ => fatal("the context salt must be 32 bytes of hex"),
+        }
+    });
+    let prehash_presentation_header = matches.is_present("prehash-presentation-header");
+    let bind_device_state = matches.is_present("bind-device-state");
+    let messages = credential.message_bytes();
+
+    println!("Confirm the disclosure on the device.");
+    let response = vendor::bbs_proof(
+        &open_device(),
+        VendorBBSProofRequest {
+            public_key: credential.public_key.clone(),
+            messages: messages.clone(),
+            signature: credential.signature.clone(),
+            header: credential.header.as_bytes().to_vec(),
+            presentation_header: host_presentation_header.as_bytes().to_vec(),
+            disclosed_indexes: disclosed_indexes.clone(),
+            secret_prover_blind: Some(credential.prover_blind_factor.clone()),
+            issuer_id: None,
+            context_salt,
+            salted_digests: credential.salted_digests,
+            scoped_link_secret: credential.scoped_link_secret,
+            prehash_presentation_header,
+            bind_device_state,
+            status_list_index,
+        },
+    )
+    .unwrap_or_else(|e| fatal(e));
+    println!("Proof: {}", hex::encode(&response.proof_bytes));
+    if let Some(NonRevocationProof::StatusList { message_index, .. }) = &response.non_revocation {
+        println!("Status list entry: {}", credential.label(*message_index));
+    }
+    let mut presentation_header = match &response.device_state {
+        Some(device_state) => {
+            println!("Device state: {}", hex::encode(device_state));
+            device_state.clone()
+        }
+        None => Vec::new(),
+    };
+    presentation_header.extend_from_slice(host_presentation_header.as_bytes());
+    if let Some(context) = &response.presentation_context {
+        println!("Presentation context: {}", hex::encode(context));
+        presentation_header.extend_from_slice(context);
+    }
+    if prehash_presentation_header {
+        presentation_header = Sha256::digest(&presentation_header).to_vec();
+    }
+    for &index in &disclosed_indexes {
+        println!(
+            "Disclosed {}: {}",
+            credential.label(index),
+            credential.messages[index]
+        );
+    }
+
+    let disclosed_messages = disclosed_indexes
+        .iter()
+        .map(|&index| messages[index].clone())
+        .collect::<Vec<_>>();
+    if !issuer::verify_proof(
+        &credential.public_key,
+        &response.proof_bytes,
+        credential.header.as_bytes(),
+        &presentation_header,
+        &disclosed_messages,
+        &disclosed_indexes,
+    ) {
+        fatal("the proof doesn't verify");
+    }
+    println!("The proof verifies.");
+
+    if let Some(verification_method) = matches.value_of("verification-method") {
+        // The messages aren't RDF statements, so there are no blank nodes or mandatory ones.
+        let derived_proof = DerivedProof {
+            bbs_proof: &response.proof_bytes,
+            label_map: &[],
+            mandatory_indexes: &[],
+            selective_indexes: &disclosed_indexes,
+            presentation_header: &presentation_header,
+            feature: DerivedProofFeature::AnonymousHolderBinding,
+        };
+        let proof = derived_proof.to_data_integrity_proof(verification_method);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&proof).unwrap_or_else(|e| fatal(e))
+        );
+    }
+}
+
+fn main() {
+    let matches = parse_cli();
+    let wallet_path = Path::new(matches.value_of("wallet").unwrap());
+    match matches.subcommand() {
+        ("provision", Some(matches)) => provision(matches),
+        ("info", Some(_)) => info(),
+        ("commitment", Some(_)) => commitment(),
+        ("authorize", Some(matches)) => authorize(matches),
+        ("issue", Some(matches)) => issue(matches, wallet_path),
+        ("list", Some(matches)) => list(matches, wallet_path),
+        ("migrate", Some(matches)) => migrate(matches),
+        ("pair", Some(matches)) => pair(matches),
+        ("log", Some(matches)) => log(matches),
+        ("issuer", Some(matches)) => issuer(matches),
+        ("revoke-all", Some(_)) => revoke_all(),
+        ("compact", Some(matches)) => compact(matches),
+        ("store-health", Some(_)) => store_health(),
+        ("crash-report", Some(matches)) => crash_report(matches),
+        ("ping", Some(matches)) => ping(matches, wallet_path),
+        ("prove", Some(matches)) => prove(matches, wallet_path),
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenSK running in the same process, to test the host side without hardware.
+//!
+//! It is the firmware stack with fake syscalls, like `examples/simulation.rs` without `uhid`.
+//! User presence is always confirmed and the storage lives in RAM.
+
+use crate::hid::{Connection, Device, HidError, PACKET_SIZE};
+use ctap2::env::tock::TockEnv;
+use libtock_unittest::fake;
+use opensk::api::user_presence::{UserPresenceError, UserPresenceSource};
+use opensk::{Ctap, Transport};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+const ALARM_FREQUENCY_HZ: u32 = 32768;
+
+/// Confirms user presence without waiting, there is no button to press.
+struct AlwaysPresent;
+
+impl UserPresenceSource for AlwaysPresent {
+    fn poll(&mut self) -> Result<bool, UserPresenceError> {
+        Ok(true)
+    }
+}
+
+/// A fresh device, answering packets as soon as they are written.
+pub struct SimulatedConnection {
+    ctap: RefCell<Ctap<TockEnv<fake::Syscalls>>>,
+    replies: RefCell<VecDeque<[u8; PACKET_SIZE]>>,
+    // The environment needs it, so it is dropped last.
+    _kernel: fake::Kernel,
+}
+
+impl SimulatedConnection {
+    /// Only one can exist per thread, since fake syscalls go to the kernel of the thread.
+    pub fn new() -> SimulatedConnection {
+        let kernel = fake::Kernel::new();
+        kernel.add_driver(&fake::Alarm::new(ALARM_FREQUENCY_HZ));
+        kernel.add_driver(&fake::Leds::<4>::new());
+        let mut env = TockEnv::<fake::Syscalls>::default();
+        env.add_user_presence_source(Box::new(AlwaysPresent));
+        SimulatedConnection {
+            ctap: RefCell::new(Ctap::new(env)),
+            replies: RefCell::new(VecDeque::new()),
+            _kernel: kernel,
+        }
+    }
+}
+
+impl Default for SimulatedConnection {
+    fn default() -> Self {
+        SimulatedConnection::new()
+    }
+}
+
+impl Connection for SimulatedConnection {
+    fn write_packet(&self, packet: &[u8; PACKET_SIZE]) -> Result<(), HidError> {
+        let replies = self
+            .ctap
+            .borrow_mut()
+            .process_hid_packet(packet, Transport::MainHid);
+        self.replies.borrow_mut().extend(replies);
+        Ok(())
+    }
+
+    fn read_packet(&self, _timeout_ms: i32) -> Result<Option<[u8; PACKET_SIZE]>, HidError> {
+        Ok(self.replies.borrow_mut().pop_front())
+    }
+}
+
+/// Opens a fresh simulated device.
+pub fn open() -> Result<Device, HidError> {
+    Device::with_connection(Box::new(SimulatedConnection::new()))
+}
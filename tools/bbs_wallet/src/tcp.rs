@@ -0,0 +1,95 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CTAPHID packets over TCP, to reach the virtual authenticator of OpenSK.
+//!
+//! The `virtual_authenticator` example serves raw 64 byte packets on localhost, for CI without
+//! HID access.
+
+use crate::hid::{Connection, Device, HidError, PACKET_SIZE};
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+pub struct TcpConnection {
+    stream: TcpStream,
+}
+
+impl TcpConnection {
+    pub fn connect(address: &str) -> Result<TcpConnection, HidError> {
+        let stream = TcpStream::connect(address).map_err(|_| HidError::NoDevice)?;
+        stream.set_nodelay(true).map_err(HidError::Tcp)?;
+        Ok(TcpConnection { stream })
+    }
+}
+
+impl Connection for TcpConnection {
+    fn write_packet(&self, packet: &[u8; PACKET_SIZE]) -> Result<(), HidError> {
+        (&self.stream).write_all(packet).map_err(HidError::Tcp)
+    }
+
+    fn read_packet(&self, timeout_ms: i32) -> Result<Option<[u8; PACKET_SIZE]>, HidError> {
+        // Like hidapi, a negative timeout blocks.
+        let timeout = u64::try_from(timeout_ms)
+            .ok()
+            .map(|ms| Duration::from_millis(ms.max(1)));
+        self.stream
+            .set_read_timeout(timeout)
+            .map_err(HidError::Tcp)?;
+        let mut packet = [0u8; PACKET_SIZE];
+        match (&self.stream).read_exact(&mut packet) {
+            Ok(()) => Ok(Some(packet)),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(HidError::Tcp(e)),
+        }
+    }
+}
+
+/// Opens the virtual authenticator listening at the address, e.g. `127.0.0.1:8080`.
+pub fn open(address: &str) -> Result<Device, HidError> {
+    Device::with_connection(Box::new(TcpConnection::connect(address)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_exchange_packets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let connection = TcpConnection::connect(&address).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        assert!(matches!(connection.read_packet(10), Ok(None)));
+        connection.write_packet(&[0x55; PACKET_SIZE]).unwrap();
+        let mut packet = [0u8; PACKET_SIZE];
+        server.read_exact(&mut packet).unwrap();
+        assert_eq!(packet, [0x55; PACKET_SIZE]);
+        server.write_all(&[0xAA; PACKET_SIZE]).unwrap();
+        assert_eq!(
+            connection.read_packet(1000).unwrap(),
+            Some([0xAA; PACKET_SIZE])
+        );
+    }
+}
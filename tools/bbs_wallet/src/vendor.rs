@@ -0,0 +1,789 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Vendor commands of OpenSK, as implemented in `src/env/tock/commands.rs`.
+
+use crate::hid::{Device, HidError};
+use opensk_vendor_protocol::bbs::{
+    VendorBBSEpochResponse, VendorBBSIssuersParameters, VendorBBSPossessionRequest,
+    VendorBBSUsageResponse, CREDENTIAL_ID_SIZE,
+};
+use opensk_vendor_protocol::command::*;
+use opensk_vendor_protocol::status::{self, CTAP2_OK};
+use opensk_vendor_protocol::DecodeError;
+use sha2::{Digest, Sha256};
+use sk_cbor::{cbor_map, cbor_map_options, destructure_cbor_map};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Prefixed to the transport public key that the replacement signs.
+pub const MIGRATION_TRANSPORT_KEY_DOMAIN: &[u8] = b"OpenSK BBS migration transport key\0";
+
+/// Bounds decompressed responses, far above the largest BBS proof.
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 20;
+
+pub const AAGUID_LENGTH: usize = 16;
+
+#[derive(Debug)]
+pub enum VendorError {
+    Hid(HidError),
+    /// The command failed with this CTAP2 status code.
+    Status(u8),
+    /// The response could not be parsed.
+    InvalidResponse,
+}
+
+impl fmt::Display for VendorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VendorError::Hid(e) => write!(f, "{}", e),
+            VendorError::Status(code) => match status::description(*code) {
+                Some(description) => write!(f, "{} (status 0x{:02X})", description, code),
+                None => write!(f, "command failed with status 0x{:02X}", code),
+            },
+            VendorError::InvalidResponse => write!(f, "invalid response"),
+        }
+    }
+}
+
+impl From<HidError> for VendorError {
+    fn from(e: HidError) -> Self {
+        VendorError::Hid(e)
+    }
+}
+
+impl From<DecodeError> for VendorError {
+    fn from(_: DecodeError) -> Self {
+        VendorError::InvalidResponse
+    }
+}
+
+/// Attestation material and link secret to provision.
+///
+/// Each part is optional, the device only programs what is still missing.
+pub struct AttestationMaterial {
+    pub certificate: Option<Vec<u8>>,
+    pub private_key: Option<Vec<u8>>,
+    pub link_secret: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub struct ConfigureResponse {
+    pub cert_programmed: bool,
+    pub pkey_programmed: bool,
+    pub link_secret_programmed: bool,
+    /// 0 if unlocked, up to 3 if fully locked.
+    pub lockdown_level: u64,
+    /// Bitmask of enabled vendor commands, see `PERMISSION_NAMES`.
+    pub permissions: u64,
+    /// Whether a customer AAGUID replaces the one of the firmware.
+    pub aaguid_programmed: bool,
+}
+
+/// Names of the permission bits, from the least significant.
+pub const PERMISSION_NAMES: [&str; 6] = [
+    "provisioning",
+    "upgrade",
+    "bbs-issue",
+    "bbs-present",
+    "admin",
+    "credential-import",
+];
+
+/// Issuer nonce and expiry a commitment answers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Challenge {
+    pub nonce: Vec<u8>,
+    pub expiry: u64,
+}
+
+/// A commitment to the link secret, to be blindly signed by an issuer.
+#[derive(Clone, Debug)]
+pub struct Commitment {
+    pub commitment_with_proof: Vec<u8>,
+    pub secret_prover_blind: Vec<u8>,
+    /// The challenge as echoed by the device.
+    pub challenge: Option<Challenge>,
+    /// Attestation signature over the transcript, see `bbs::commitment_transcript`.
+    pub signature: Option<Vec<u8>>,
+    pub certificate: Option<Vec<u8>>,
+    /// Recovery key of the issuer, if the device is paired with a backup.
+    pub recovery_public_key: Option<Vec<u8>>,
+    /// Signature over `bbs::recovery_transcript`.
+    pub recovery_signature: Option<Vec<u8>>,
+    /// The device returned the commitment of an interrupted issuance for the same request.
+    pub resumed: bool,
+}
+
+/// Transport key of a replacement device, that BBS credentials are exported to.
+#[derive(Clone, Debug)]
+pub struct TransportKey {
+    /// Uncompressed P-256 point.
+    pub public_key: Vec<u8>,
+    /// Attestation signature over `transcript`.
+    pub signature: Vec<u8>,
+    pub certificate: Vec<u8>,
+}
+
+impl TransportKey {
+    /// Returns what the attestation key of the replacement signed.
+    pub fn transcript(&self) -> Vec<u8> {
+        let mut transcript = MIGRATION_TRANSPORT_KEY_DOMAIN.to_vec();
+        transcript.extend_from_slice(&self.public_key);
+        transcript
+    }
+}
+
+/// Payload of a device, encrypted to the transport key of another one.
+///
+/// Migrations wrap issuer records, backup pairing the recovery secret.
+#[derive(Clone, Debug)]
+pub struct MigrationBundle {
+    pub source_public_key: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub mac: Vec<u8>,
+}
+
+impl MigrationBundle {
+    /// Reads the bundle from the keys 0x03 to 0x05 of a response.
+    fn from_response(response: Option<sk_cbor::Value>) -> Result<MigrationBundle, VendorError> {
+        destructure_cbor_map! {
+            let {
+                0x03 => source_public_key,
+                0x04 => ciphertext,
+                0x05 => mac,
+            } = extract_map(response)?;
+        }
+        Ok(MigrationBundle {
+            source_public_key: extract_byte_string(source_public_key)?,
+            ciphertext: extract_byte_string(ciphertext)?,
+            mac: extract_byte_string(mac)?,
+        })
+    }
+
+    /// Returns the request map of the import, for the subcommand.
+    fn to_request(&self, subcommand: u64) -> sk_cbor::Value {
+        cbor_map! {
+            0x01 => subcommand,
+            0x03 => self.source_public_key.clone(),
+            0x04 => self.ciphertext.clone(),
+            0x05 => self.mac.clone(),
+        }
+    }
+}
+
+/// Identifies a credential in the usage of the device, by its signature.
+pub fn credential_id(signature: &[u8]) -> [u8; CREDENTIAL_ID_SIZE] {
+    let mut credential_id = [0; CREDENTIAL_ID_SIZE];
+    credential_id.copy_from_slice(&Sha256::digest(signature)[..CREDENTIAL_ID_SIZE]);
+    credential_id
+}
+
+/// Scope of the recovery key for an issuer identified by its BBS public key.
+pub fn recovery_scope(issuer_public_key: &[u8]) -> Vec<u8> {
+    Sha256::digest(issuer_public_key).to_vec()
+}
+
+/// FIDO metadata of the device, consistent with getInfo.
+#[derive(Debug)]
+pub struct DeviceInfo {
+    pub aaguid: Vec<u8>,
+    /// Whether the AAGUID was provisioned for a customer.
+    pub custom_aaguid: bool,
+    /// FIDO certification level, from 1 for L1 to 6 for L3+.
+    pub certification_level: Option<u64>,
+    /// Description of the metadata statement.
+    pub description: String,
+    pub firmware_version: Option<u64>,
+    /// Algorithms of compressed vendor commands, empty for firmware without compression.
+    pub compression_algorithms: Vec<u64>,
+}
+
+pub use opensk_vendor_protocol::bbs::{
+    CredentialUsage, Issuer, NonRevocationProof, VendorBBSInfoResponse, VendorBBSProofRequest,
+    VendorBBSProofResponse,
+};
+
+/// What the device recorded about its last panic.
+#[derive(Debug)]
+pub struct CrashReport {
+    pub last_command: Option<u64>,
+    pub stack_high_water: u64,
+    pub message: Vec<u8>,
+}
+
+/// Provisions attestation material, and optionally raises the lockdown level.
+///
+/// Without material, with level 0, without permissions and without AAGUID, this only queries what
+/// is already programmed. Permissions only change before lockdown. A customer AAGUID is ignored if
+/// the firmware doesn't allow one or already has one.
+pub fn configure(
+    device: &Device,
+    material: Option<AttestationMaterial>,
+    lockdown_level: u64,
+    permissions: Option<u64>,
+    aaguid: Option<[u8; AAGUID_LENGTH]>,
+) -> Result<ConfigureResponse, VendorError> {
+    let material = material.map(|material| {
+        cbor_map_options! {
+            0x01 => material.certificate,
+            0x02 => material.private_key,
+            0x03 => material.link_secret,
+        }
+    });
+    let request = cbor_map_options! {
+        0x01 => lockdown_level,
+        0x02 => material,
+        0x03 => permissions,
+        0x04 => aaguid,
+    };
+    let response = send(device, VENDOR_COMMAND_CONFIGURE, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x01 => cert_programmed,
+            0x02 => pkey_programmed,
+            0x03 => link_secret_programmed,
+            0x04 => lockdown_level,
+            0x05 => permissions,
+            0x06 => aaguid_programmed,
+        } = extract_map(response)?;
+    }
+    Ok(ConfigureResponse {
+        cert_programmed: extract_bool(cert_programmed)?,
+        pkey_programmed: extract_bool(pkey_programmed)?,
+        link_secret_programmed: extract_bool(link_secret_programmed)?,
+        // Older firmware only knows full lockdown, and doesn't report it.
+        lockdown_level: lockdown_level.map_or(Ok(0), |level| extract_unsigned(Some(level)))?,
+        // Older firmware allows all vendor commands.
+        permissions: permissions.map_or(Ok(0x1f), |bits| extract_unsigned(Some(bits)))?,
+        // Older firmware only knows the AAGUID it was built with.
+        aaguid_programmed: aaguid_programmed.map_or(Ok(false), |b| extract_bool(Some(b)))?,
+    })
+}
+
+/// Reads the FIDO metadata of the device.
+pub fn info(device: &Device) -> Result<DeviceInfo, VendorError> {
+    let response = send(device, VENDOR_COMMAND_INFO, None)?;
+    destructure_cbor_map! {
+        let {
+            0x01 => aaguid,
+            0x02 => custom_aaguid,
+            0x03 => certification_level,
+            0x04 => description,
+            0x05 => firmware_version,
+            0x06 => compression_algorithms,
+        } = extract_map(response)?;
+    }
+    let compression_algorithms = match compression_algorithms {
+        None => Vec::new(),
+        Some(algorithms) => algorithms
+            .extract_array()
+            .ok_or(VendorError::InvalidResponse)?
+            .into_iter()
+            .map(|algorithm| extract_unsigned(Some(algorithm)))
+            .collect::<Result<_, _>>()?,
+    };
+    Ok(DeviceInfo {
+        aaguid: extract_byte_string(aaguid)?,
+        custom_aaguid: extract_bool(custom_aaguid)?,
+        certification_level: certification_level
+            .map(|level| extract_unsigned(Some(level)))
+            .transpose()?,
+        description: description
+            .and_then(|description| description.extract_text_string())
+            .ok_or(VendorError::InvalidResponse)?,
+        firmware_version: firmware_version
+            .map(|version| extract_unsigned(Some(version)))
+            .transpose()?,
+        compression_algorithms,
+    })
+}
+
+/// Compresses later vendor commands and their responses if the device supports it.
+///
+/// Returns whether compression is used. Large BBS keys, signatures and proofs then take fewer
+/// HID reports.
+pub fn negotiate_compression(device: &mut Device) -> Result<bool, VendorError> {
+    device.set_compression(false);
+    let compression = info(device)?
+        .compression_algorithms
+        .contains(&COMPRESSION_LZSS);
+    device.set_compression(compression);
+    Ok(compression)
+}
+
+/// Parses an AAGUID in the UUID format of `crypto_data/aaguid.txt`.
+pub fn parse_aaguid(text: &str) -> Option<[u8; AAGUID_LENGTH]> {
+    let bytes = hex::decode(text.trim().replace('-', "")).ok()?;
+    <[u8; AAGUID_LENGTH]>::try_from(bytes).ok()
+}
+
+/// Formats an AAGUID in the UUID format, as metadata statements list it.
+pub fn format_aaguid(aaguid: &[u8]) -> String {
+    let hex = hex::encode(aaguid);
+    if aaguid.len() != AAGUID_LENGTH {
+        return hex;
+    }
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Requests a fresh commitment to the link secret, answering the issuer challenge if any.
+///
+/// With an issuer public key, the device commits to a link secret scoped to that issuer.
+pub fn bbs_commitment(
+    device: &Device,
+    challenge: Option<&Challenge>,
+    issuer_public_key: Option<&[u8]>,
+    recovery_scope: Option<&[u8]>,
+) -> Result<Commitment, VendorError> {
+    let parameters =
+        if challenge.is_none() && issuer_public_key.is_none() && recovery_scope.is_none() {
+            None
+        } else {
+            Some(cbor_map_options! {
+                0x01 => challenge.map(|challenge| challenge.nonce.clone()),
+                0x02 => challenge.map(|challenge| challenge.expiry),
+                0x06 => issuer_public_key,
+                0x07 => recovery_scope,
+            })
+        };
+    let response = send(device, VENDOR_COMMAND_BBS_COMMITMENT, parameters)?;
+    destructure_cbor_map! {
+        let {
+            0x01 => commitment,
+            0x02 => secret_prover_blind,
+            0x03 => nonce,
+            0x04 => expiry,
+            0x05 => signature,
+            0x06 => certificate,
+            0x07 => recovery_public_key,
+            0x08 => recovery_signature,
+            0x09 => resumed,
+        } = extract_map(response)?;
+    }
+    let challenge = match nonce {
+        None => None,
+        nonce => Some(Challenge {
+            nonce: extract_byte_string(nonce)?,
+            expiry: extract_unsigned(expiry)?,
+        }),
+    };
+    Ok(Commitment {
+        commitment_with_proof: extract_byte_string(commitment)?,
+        secret_prover_blind: extract_byte_string(secret_prover_blind)?,
+        challenge,
+        signature: signature
+            .map(|s| extract_byte_string(Some(s)))
+            .transpose()?,
+        certificate: certificate
+            .map(|c| extract_byte_string(Some(c)))
+            .transpose()?,
+        recovery_public_key: recovery_public_key
+            .map(|k| extract_byte_string(Some(k)))
+            .transpose()?,
+        recovery_signature: recovery_signature
+            .map(|s| extract_byte_string(Some(s)))
+            .transpose()?,
+        // Older firmware always generates a fresh commitment.
+        resumed: resumed.map_or(Ok(false), |b| extract_bool(Some(b)))?,
+    })
+}
+
+/// Dumps the trace of recent events, then optionally clears it and changes its level.
+///
+/// Levels go from 1 for errors to 5 for traces.
+pub fn log(device: &Device, clear: bool, max_level: Option<u64>) -> Result<Vec<u8>, VendorError> {
+    let request = cbor_map_options! {
+        0x01 => if clear { Some(true) } else { None },
+        0x02 => max_level,
+    };
+    let response = send(device, VENDOR_COMMAND_LOG, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x01 => contents,
+        } = extract_map(response)?;
+    }
+    extract_byte_string(contents)
+}
+
+/// Reads the report of the last panic, if any, then optionally clears it.
+pub fn crash_report(device: &Device, clear: bool) -> Result<Option<CrashReport>, VendorError> {
+    let request = cbor_map_options! {
+        0x01 => if clear { Some(true) } else { None },
+    };
+    let response = send(device, VENDOR_COMMAND_CRASH_REPORT, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x01 => last_command,
+            0x02 => stack_high_water,
+            0x03 => message,
+        } = extract_map(response)?;
+    }
+    if stack_high_water.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(CrashReport {
+        last_command: last_command
+            .map(|command| extract_unsigned(Some(command)))
+            .transpose()?,
+        stack_high_water: extract_unsigned(stack_high_water)?,
+        message: extract_byte_string(message)?,
+    }))
+}
+
+/// Capacity of the store around a compaction step, in words.
+#[derive(Debug)]
+pub struct CompactProgress {
+    /// Words writable without compaction before the step.
+    pub immediate_before: u64,
+    /// Words writable without compaction after the step.
+    pub immediate: u64,
+    /// Words that can still be stored.
+    pub remaining: u64,
+    pub total: u64,
+    pub pages: u64,
+    /// Whether the requested words are writable without compaction.
+    pub done: bool,
+}
+
+/// Compacts a few pages of the store, until `length` words are writable without compaction.
+///
+/// Without a length, the device makes all remaining capacity writable. Repeat until done.
+pub fn compact(
+    device: &Device,
+    length: Option<u64>,
+    max_pages: u64,
+) -> Result<CompactProgress, VendorError> {
+    let request = cbor_map_options! {
+        0x01 => length,
+        0x02 => max_pages,
+    };
+    let response = send(device, VENDOR_COMMAND_STORE_COMPACT, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x01 => immediate_before,
+            0x02 => immediate,
+            0x03 => remaining,
+            0x04 => total,
+            0x05 => pages,
+            0x06 => done,
+        } = extract_map(response)?;
+    }
+    Ok(CompactProgress {
+        immediate_before: extract_unsigned(immediate_before)?,
+        immediate: extract_unsigned(immediate)?,
+        remaining: extract_unsigned(remaining)?,
+        total: extract_unsigned(total)?,
+        pages: extract_unsigned(pages)?,
+        done: extract_bool(done)?,
+    })
+}
+
+/// Health of the store of the device.
+#[derive(Debug)]
+pub struct StoreHealth {
+    /// Whether the store is read-only until a reset wipes it.
+    pub corrupted: bool,
+    /// Words of storage lifetime used and in total, if readable.
+    pub lifetime: Option<(u64, u64)>,
+    /// Number of entries, unless the store failed to mount.
+    pub entries: Option<u64>,
+}
+
+/// Reads whether the store of the device is corrupted.
+pub fn store_health(device: &Device) -> Result<StoreHealth, VendorError> {
+    let response = send(device, VENDOR_COMMAND_STORE_DIAGNOSTICS, None)?;
+    destructure_cbor_map! {
+        let {
+            0x01 => corrupted,
+            0x02 => lifetime_used,
+            0x03 => lifetime_total,
+            0x04 => entries,
+        } = extract_map(response)?;
+    }
+    let lifetime = match (lifetime_used, lifetime_total) {
+        (Some(used), Some(total)) => Some((
+            extract_unsigned(Some(used))?,
+            extract_unsigned(Some(total))?,
+        )),
+        _ => None,
+    };
+    Ok(StoreHealth {
+        corrupted: extract_bool(corrupted)?,
+        lifetime,
+        entries: entries
+            .map(|entries| extract_unsigned(Some(entries)))
+            .transpose()?,
+    })
+}
+
+/// Reads the BBS limits and policies of the device.
+pub fn bbs_info(device: &Device) -> Result<VendorBBSInfoResponse, VendorError> {
+    let response = send(device, VENDOR_COMMAND_BBS_INFO, None)?;
+    decode(response)
+}
+
+/// Adds an issuer to the registry of the device, or renames it.
+pub fn register_issuer(
+    device: &Device,
+    public_key: &[u8],
+    name: &str,
+    num_messages: u8,
+) -> Result<(), VendorError> {
+    let request = VendorBBSIssuersParameters::Register(Issuer {
+        public_key: public_key.to_vec(),
+        name: name.to_string(),
+        num_messages,
+    });
+    send(device, VENDOR_COMMAND_BBS_ISSUERS, Some(request.into()))?;
+    Ok(())
+}
+
+/// Removes an issuer from the registry of the device.
+pub fn remove_issuer(device: &Device, public_key: &[u8]) -> Result<(), VendorError> {
+    let request = VendorBBSIssuersParameters::Remove {
+        public_key: public_key.to_vec(),
+    };
+    send(device, VENDOR_COMMAND_BBS_ISSUERS, Some(request.into()))?;
+    Ok(())
+}
+
+/// Reads how often the device presented each credential, and its current boot count.
+///
+/// Entries come most recent first.
+pub fn bbs_usage(device: &Device) -> Result<(u32, Vec<CredentialUsage>), VendorError> {
+    let response = send(device, VENDOR_COMMAND_BBS_USAGE, None)?;
+    let usage: VendorBBSUsageResponse = decode(response)?;
+    Ok((usage.boot_count, usage.credentials))
+}
+
+/// Revokes all credentials issued over scoped link secrets, and returns the new epoch.
+pub fn bbs_revoke_all(device: &Device) -> Result<u32, VendorError> {
+    let response = send(device, VENDOR_COMMAND_BBS_REVOKE_ALL, None)?;
+    let response: VendorBBSEpochResponse = decode(response)?;
+    Ok(response.epoch)
+}
+
+/// Requests a proof for a credential, disclosing only some of its messages.
+pub fn bbs_proof(
+    device: &Device,
+    request: VendorBBSProofRequest,
+) -> Result<VendorBBSProofResponse, VendorError> {
+    let response = send(device, VENDOR_COMMAND_BBS_PROOF, Some(request.into()))?;
+    decode(response)
+}
+
+/// Proves possession of a credential, disclosing none of its messages.
+///
+/// The presentation header of the request carries the nonce of the verifier. Its disclosed
+/// indexes are ignored.
+pub fn bbs_possession(
+    device: &Device,
+    request: VendorBBSProofRequest,
+) -> Result<VendorBBSProofResponse, VendorError> {
+    let request = VendorBBSPossessionRequest(request);
+    let response = send(device, VENDOR_COMMAND_BBS_POSSESSION, Some(request.into()))?;
+    decode(response)
+}
+
+/// Generates a transport key on the replacement device, kept until it reboots.
+pub fn migration_prepare(device: &Device) -> Result<TransportKey, VendorError> {
+    let request = cbor_map! {
+        0x01 => MIGRATION_PREPARE,
+    };
+    let response = send(device, VENDOR_COMMAND_BBS_MIGRATION, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x01 => public_key,
+            0x02 => signature,
+            0x03 => certificate,
+        } = extract_map(response)?;
+    }
+    Ok(TransportKey {
+        public_key: extract_byte_string(public_key)?,
+        signature: extract_byte_string(signature)?,
+        certificate: extract_byte_string(certificate)?,
+    })
+}
+
+/// Exports the issuers of the source device to the transport key.
+///
+/// The source then forgets them and rotates its link secret, so its credentials stop working.
+pub fn migration_export(
+    device: &Device,
+    transport_public_key: &[u8],
+) -> Result<MigrationBundle, VendorError> {
+    let request = cbor_map! {
+        0x01 => MIGRATION_EXPORT,
+        0x02 => transport_public_key,
+    };
+    let response = send(device, VENDOR_COMMAND_BBS_MIGRATION, Some(request))?;
+    MigrationBundle::from_response(response)
+}
+
+/// Imports the bundle on the replacement device, and returns the number of issuers.
+pub fn migration_import(device: &Device, bundle: &MigrationBundle) -> Result<u64, VendorError> {
+    let request = bundle.to_request(MIGRATION_IMPORT);
+    let response = send(device, VENDOR_COMMAND_BBS_MIGRATION, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x06 => count,
+        } = extract_map(response)?;
+    }
+    extract_unsigned(count)
+}
+
+/// Wraps the recovery secret of the primary device to the transport key of its backup.
+pub fn recovery_share(
+    device: &Device,
+    transport_public_key: &[u8],
+) -> Result<MigrationBundle, VendorError> {
+    let request = cbor_map! {
+        0x01 => RECOVERY_SHARE,
+        0x02 => transport_public_key,
+    };
+    let response = send(device, VENDOR_COMMAND_BBS_RECOVERY, Some(request))?;
+    MigrationBundle::from_response(response)
+}
+
+/// Stores the shared recovery secret on the backup device, which prepared the transport key.
+pub fn recovery_accept(device: &Device, bundle: &MigrationBundle) -> Result<(), VendorError> {
+    let request = bundle.to_request(RECOVERY_ACCEPT);
+    send(device, VENDOR_COMMAND_BBS_RECOVERY, Some(request))?;
+    Ok(())
+}
+
+/// Unpairs the device from its primary or backups.
+pub fn recovery_forget(device: &Device) -> Result<(), VendorError> {
+    let request = cbor_map! {
+        0x01 => RECOVERY_FORGET,
+    };
+    send(device, VENDOR_COMMAND_BBS_RECOVERY, Some(request))?;
+    Ok(())
+}
+
+/// Lets the next proofs skip user presence and verification, after verifying the user once.
+///
+/// Returns the number of proofs and the milliseconds that the device granted. Zero proofs revoke
+/// the current authorization.
+pub fn bbs_authorize(
+    device: &Device,
+    proofs: u64,
+    duration_ms: Option<u64>,
+) -> Result<(u64, u64), VendorError> {
+    let request = cbor_map_options! {
+        0x01 => proofs,
+        0x02 => duration_ms,
+    };
+    let response = send(device, VENDOR_COMMAND_BBS_AUTHORIZE, Some(request))?;
+    destructure_cbor_map! {
+        let {
+            0x01 => proofs,
+            0x02 => duration_ms,
+        } = extract_map(response)?;
+    }
+    Ok((extract_unsigned(proofs)?, extract_unsigned(duration_ms)?))
+}
+
+/// Sends a vendor command and returns the decoded response.
+fn send(
+    device: &Device,
+    command: u8,
+    parameters: Option<sk_cbor::Value>,
+) -> Result<Option<sk_cbor::Value>, VendorError> {
+    let mut request = vec![command];
+    if let Some(parameters) = parameters {
+        sk_cbor::write(parameters, &mut request).map_err(|_| VendorError::InvalidResponse)?;
+    }
+    // Info stays plain, so that negotiating works with any firmware.
+    let response = if device.compression() && command != VENDOR_COMMAND_INFO {
+        send_compressed(device, &request)?
+    } else {
+        device.cbor(&request)?
+    };
+    match response.split_first() {
+        Some((&CTAP2_OK, [])) => Ok(None),
+        Some((&CTAP2_OK, data)) => sk_cbor::read(data)
+            .map(Some)
+            .map_err(|_| VendorError::InvalidResponse),
+        Some((&code, _)) => Err(VendorError::Status(code)),
+        None => Err(VendorError::InvalidResponse),
+    }
+}
+
+/// Wraps the request in a compressed command, and returns the decompressed response.
+fn send_compressed(device: &Device, request: &[u8]) -> Result<Vec<u8>, VendorError> {
+    let parameters = cbor_map! {
+        0x01 => COMPRESSION_LZSS,
+        0x02 => sk_lzss::compress(request),
+    };
+    let mut wrapper = vec![VENDOR_COMMAND_COMPRESSED];
+    sk_cbor::write(parameters, &mut wrapper).map_err(|_| VendorError::InvalidResponse)?;
+    let response = device.cbor(&wrapper)?;
+    let data = match response.split_first() {
+        Some((&CTAP2_OK, data)) => data,
+        Some((&code, _)) => return Err(VendorError::Status(code)),
+        None => return Err(VendorError::InvalidResponse),
+    };
+    let value = sk_cbor::read(data).map_err(|_| VendorError::InvalidResponse)?;
+    destructure_cbor_map! {
+        let {
+            0x01 => response,
+        } = extract_map(Some(value))?;
+    }
+    sk_lzss::decompress(&extract_byte_string(response)?, MAX_DECOMPRESSED_SIZE)
+        .map_err(|_| VendorError::InvalidResponse)
+}
+
+/// Decodes a response that the wire format defines.
+fn decode<T>(response: Option<sk_cbor::Value>) -> Result<T, VendorError>
+where
+    T: TryFrom<sk_cbor::Value, Error = DecodeError>,
+{
+    let response = response.ok_or(VendorError::InvalidResponse)?;
+    Ok(T::try_from(response)?)
+}
+
+fn extract_map(
+    value: Option<sk_cbor::Value>,
+) -> Result<Vec<(sk_cbor::Value, sk_cbor::Value)>, VendorError> {
+    value
+        .and_then(|value| value.extract_map())
+        .ok_or(VendorError::InvalidResponse)
+}
+
+fn extract_bool(value: Option<sk_cbor::Value>) -> Result<bool, VendorError> {
+    value
+        .and_then(|value| value.extract_bool())
+        .ok_or(VendorError::InvalidResponse)
+}
+
+fn extract_unsigned(value: Option<sk_cbor::Value>) -> Result<u64, VendorError> {
+    value
+        .and_then(|value| value.extract_unsigned())
+        .ok_or(VendorError::InvalidResponse)
+}
+
+fn extract_byte_string(value: Option<sk_cbor::Value>) -> Result<Vec<u8>, VendorError> {
+    value
+        .and_then(|value| value.extract_byte_string())
+        .ok_or(VendorError::InvalidResponse)
+}
@@ -0,0 +1,442 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stores issued credentials in a JSON file.
+//!
+//! The field names follow `third_party/bbs/fixtures/proof.json`. Messages and headers are text,
+//! binary values are hex.
+
+use crate::vendor::Challenge;
+use bbs::salted_digest;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How the value of an attribute is meant to be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+    Text,
+    Integer,
+    Boolean,
+    /// An ISO 8601 date, e.g. `2000-01-31`.
+    Date,
+}
+
+impl AttributeType {
+    pub fn parse(name: &str) -> Option<AttributeType> {
+        match name {
+            "text" => Some(AttributeType::Text),
+            "integer" => Some(AttributeType::Integer),
+            "boolean" => Some(AttributeType::Boolean),
+            "date" => Some(AttributeType::Date),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AttributeType::Text => "text",
+            AttributeType::Integer => "integer",
+            AttributeType::Boolean => "boolean",
+            AttributeType::Date => "date",
+        }
+    }
+}
+
+/// Describes the message at the same index, to label it when listing or disclosing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttributeSchema {
+    pub name: String,
+    pub attribute_type: AttributeType,
+}
+
+impl AttributeSchema {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "type": self.attribute_type.as_str(),
+        })
+    }
+
+    fn from_json(json: &Value) -> Option<AttributeSchema> {
+        Some(AttributeSchema {
+            name: String::from(json.get("name")?.as_str()?),
+            attribute_type: AttributeType::parse(json.get("type")?.as_str()?)?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credential {
+    pub name: String,
+    pub public_key: Vec<u8>,
+    pub header: String,
+    pub messages: Vec<String>,
+    pub signature: Vec<u8>,
+    pub prover_blind_factor: Vec<u8>,
+    /// The messages are encoded disclosures, and the issuer signed their digests.
+    pub salted_digests: bool,
+    /// One entry per message, if the issuer described the attributes.
+    pub schema: Option<Vec<AttributeSchema>>,
+    /// Unix time of the issuance.
+    pub issued_at: Option<u64>,
+    /// Unix time after which the credential must not be presented anymore.
+    pub expires_at: Option<u64>,
+    /// Identifies the credential in the revocation registry of the issuer.
+    pub revocation_handle: Option<String>,
+    /// The issuer signed a commitment to the link secret scoped to its public key.
+    pub scoped_link_secret: bool,
+}
+
+impl Credential {
+    /// Returns the messages as signed by the issuer.
+    pub fn message_bytes(&self) -> Vec<Vec<u8>> {
+        signed_messages(&self.messages, self.salted_digests)
+    }
+
+    /// Credentials without an expiry never expire.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| now > expires_at)
+    }
+
+    /// Returns a human-readable name for the message at the index.
+    pub fn label(&self, index: usize) -> String {
+        match self.schema.as_ref().and_then(|schema| schema.get(index)) {
+            Some(attribute) => {
+                format!("{} ({})", attribute.name, attribute.attribute_type.as_str())
+            }
+            None => format!("attribute {}", index),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut json = json!({
+            "publicKey": hex::encode(&self.public_key),
+            "header": self.header,
+            "messages": self.messages,
+            "signature": hex::encode(&self.signature),
+            "proverBlindFactor": hex::encode(&self.prover_blind_factor),
+            "saltedDigests": self.salted_digests,
+            "scopedLinkSecret": self.scoped_link_secret,
+        });
+        if let Some(schema) = &self.schema {
+            json["schema"] = schema.iter().map(AttributeSchema::to_json).collect();
+        }
+        if let Some(issued_at) = self.issued_at {
+            json["issuedAt"] = json!(issued_at);
+        }
+        if let Some(expires_at) = self.expires_at {
+            json["expiresAt"] = json!(expires_at);
+        }
+        if let Some(revocation_handle) = &self.revocation_handle {
+            json["revocationHandle"] = json!(revocation_handle);
+        }
+        json
+    }
+
+    fn from_json(name: &str, json: &Value) -> Option<Credential> {
+        let hex_field = |key: &str| json.get(key)?.as_str().and_then(|s| hex::decode(s).ok());
+        let messages = json
+            .get("messages")?
+            .as_array()?
+            .iter()
+            .map(|message| message.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()?;
+        let schema = match json.get("schema") {
+            None => None,
+            Some(schema) => {
+                let schema = schema
+                    .as_array()?
+                    .iter()
+                    .map(AttributeSchema::from_json)
+                    .collect::<Option<Vec<_>>>()?;
+                if schema.len() != messages.len() {
+                    return None;
+                }
+                Some(schema)
+            }
+        };
+        Some(Credential {
+            name: String::from(name),
+            public_key: hex_field("publicKey")?,
+            header: String::from(json.get("header")?.as_str()?),
+            messages,
+            signature: hex_field("signature")?,
+            prover_blind_factor: hex_field("proverBlindFactor")?,
+            // Older wallets only hold raw messages.
+            salted_digests: json
+                .get("saltedDigests")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            schema,
+            issued_at: json.get("issuedAt").and_then(Value::as_u64),
+            expires_at: json.get("expiresAt").and_then(Value::as_u64),
+            revocation_handle: json
+                .get("revocationHandle")
+                .and_then(Value::as_str)
+                .map(String::from),
+            // Credentials issued before scoping existed use the link secret itself.
+            scoped_link_secret: json
+                .get("scopedLinkSecret")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        })
+    }
+}
+
+/// Converts the stored messages of a credential to the ones the issuer signs.
+pub fn signed_messages(messages: &[String], salted_digests: bool) -> Vec<Vec<u8>> {
+    messages
+        .iter()
+        .map(|message| {
+            if salted_digests {
+                salted_digest(message).to_vec()
+            } else {
+                message.as_bytes().to_vec()
+            }
+        })
+        .collect()
+}
+
+/// Credentials by name, in a JSON object.
+pub struct Wallet {
+    credentials: Map<String, Value>,
+}
+
+impl Wallet {
+    /// Loads the wallet, or starts an empty one if the file doesn't exist.
+    pub fn load(path: &Path) -> Result<Wallet, String> {
+        if !path.exists() {
+            return Ok(Wallet {
+                credentials: Map::new(),
+            });
+        }
+        let contents = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        match serde_json::from_str(&contents) {
+            Ok(Value::Object(credentials)) => Ok(Wallet { credentials }),
+            _ => Err(format!("{} is not a wallet", path.display())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let contents =
+            serde_json::to_string_pretty(&self.credentials).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| e.to_string())
+    }
+
+    /// Adds a credential, replacing any credential of the same name.
+    pub fn insert(&mut self, credential: &Credential) {
+        self.credentials
+            .insert(credential.name.clone(), credential.to_json());
+    }
+
+    pub fn get(&self, name: &str) -> Option<Credential> {
+        Credential::from_json(name, self.credentials.get(name)?)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.credentials.keys()
+    }
+}
+
+/// Challenge of an issuance whose credential isn't stored yet, kept next to the wallet.
+///
+/// If the device is unplugged during the issuance, repeating the commitment request with the
+/// same challenge resumes it on the device, and the issuer gets the commitment it expects.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PendingIssuance {
+    pub issuer_public_key: Vec<u8>,
+    pub challenge: Challenge,
+}
+
+impl PendingIssuance {
+    fn path(wallet_path: &Path) -> PathBuf {
+        wallet_path.with_extension("pending.json")
+    }
+
+    /// Returns the pending issuance of the wallet, if any was saved and can be read.
+    pub fn load(wallet_path: &Path) -> Option<PendingIssuance> {
+        let contents = fs::read_to_string(PendingIssuance::path(wallet_path)).ok()?;
+        let json: Value = serde_json::from_str(&contents).ok()?;
+        let hex_field = |key: &str| json.get(key)?.as_str().and_then(|s| hex::decode(s).ok());
+        Some(PendingIssuance {
+            issuer_public_key: hex_field("publicKey")?,
+            challenge: Challenge {
+                nonce: hex_field("nonce")?,
+                expiry: json.get("expiry")?.as_u64()?,
+            },
+        })
+    }
+
+    pub fn save(&self, wallet_path: &Path) -> Result<(), String> {
+        let json = json!({
+            "publicKey": hex::encode(&self.issuer_public_key),
+            "nonce": hex::encode(&self.challenge.nonce),
+            "expiry": self.challenge.expiry,
+        });
+        let contents = serde_json::to_string_pretty(&json).map_err(|e| e.to_string())?;
+        fs::write(PendingIssuance::path(wallet_path), contents).map_err(|e| e.to_string())
+    }
+
+    /// Forgets the pending issuance once its credential is stored.
+    pub fn remove(wallet_path: &Path) -> Result<(), String> {
+        match fs::remove_file(PendingIssuance::path(wallet_path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_credential_json() {
+        let credential = Credential {
+            name: String::from("license"),
+            public_key: vec![0x01; 96],
+            header: String::from("header"),
+            messages: vec![String::from("name=Alice"), String::from("age=30")],
+            signature: vec![0x02; 80],
+            prover_blind_factor: vec![0x03; 32],
+            salted_digests: false,
+            schema: None,
+            issued_at: None,
+            expires_at: None,
+            revocation_handle: None,
+            scoped_link_secret: false,
+        };
+        let mut wallet = Wallet {
+            credentials: Map::new(),
+        };
+        wallet.insert(&credential);
+        assert_eq!(wallet.get("license"), Some(credential));
+        assert_eq!(wallet.get("passport"), None);
+        assert_eq!(wallet.names().collect::<Vec<_>>(), vec!["license"]);
+    }
+
+    #[test]
+    fn test_credential_schema() {
+        let mut credential = Credential {
+            name: String::from("license"),
+            public_key: vec![0x01; 96],
+            header: String::from("header"),
+            messages: vec![String::from("Alice"), String::from("30")],
+            signature: vec![0x02; 80],
+            prover_blind_factor: vec![0x03; 32],
+            salted_digests: false,
+            schema: Some(vec![
+                AttributeSchema {
+                    name: String::from("name"),
+                    attribute_type: AttributeType::Text,
+                },
+                AttributeSchema {
+                    name: String::from("age"),
+                    attribute_type: AttributeType::Integer,
+                },
+            ]),
+            issued_at: None,
+            expires_at: None,
+            revocation_handle: None,
+            scoped_link_secret: false,
+        };
+        let json = credential.to_json();
+        assert_eq!(
+            Credential::from_json("license", &json),
+            Some(credential.clone())
+        );
+        assert_eq!(credential.label(1), "age (integer)");
+
+        // The schema must describe every message.
+        let mut json = json;
+        json["schema"].as_array_mut().unwrap().pop();
+        assert_eq!(Credential::from_json("license", &json), None);
+
+        credential.schema = None;
+        assert_eq!(credential.label(1), "attribute 1");
+    }
+
+    #[test]
+    fn test_credential_expiry() {
+        let mut credential = Credential {
+            name: String::from("license"),
+            public_key: vec![0x01; 96],
+            header: String::from("header"),
+            messages: vec![String::from("name=Alice")],
+            signature: vec![0x02; 80],
+            prover_blind_factor: vec![0x03; 32],
+            salted_digests: false,
+            schema: None,
+            issued_at: Some(1000),
+            expires_at: Some(2000),
+            revocation_handle: Some(String::from("registry#42")),
+            scoped_link_secret: true,
+        };
+        let json = credential.to_json();
+        assert_eq!(
+            Credential::from_json("license", &json),
+            Some(credential.clone())
+        );
+        assert!(!credential.is_expired(2000));
+        assert!(credential.is_expired(2001));
+        credential.expires_at = None;
+        assert!(!credential.is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn test_salted_digest_messages() {
+        let disclosure = String::from("WyJBQUFBQUFBQUFBQUFBQUFBQUFBQUFBIiwiYWdlIiwzMF0");
+        let mut credential = Credential {
+            name: String::from("license"),
+            public_key: vec![0x01; 96],
+            header: String::from("header"),
+            messages: vec![disclosure.clone()],
+            signature: vec![0x02; 80],
+            prover_blind_factor: vec![0x03; 32],
+            salted_digests: true,
+            schema: None,
+            issued_at: None,
+            expires_at: None,
+            revocation_handle: None,
+            scoped_link_secret: false,
+        };
+        assert_eq!(
+            credential.message_bytes(),
+            vec![salted_digest(&disclosure).to_vec()]
+        );
+        credential.salted_digests = false;
+        assert_eq!(credential.message_bytes(), vec![disclosure.into_bytes()]);
+    }
+
+    #[test]
+    fn test_pending_issuance() {
+        let wallet_path =
+            std::env::temp_dir().join(format!("bbs_wallet_pending_{}.json", std::process::id()));
+        assert_eq!(PendingIssuance::load(&wallet_path), None);
+        let pending = PendingIssuance {
+            issuer_public_key: vec![0x01; 96],
+            challenge: Challenge {
+                nonce: vec![0x55; 16],
+                expiry: 1000,
+            },
+        };
+        pending.save(&wallet_path).unwrap();
+        assert_eq!(PendingIssuance::load(&wallet_path), Some(pending));
+        assert_eq!(PendingIssuance::remove(&wallet_path), Ok(()));
+        assert_eq!(PendingIssuance::load(&wallet_path), None);
+        // Removing twice is fine, issuances without interruption never left a file.
+        assert_eq!(PendingIssuance::remove(&wallet_path), Ok(()));
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conformance tests of the CTAP2 and BBS flows, from the host side.
+//!
+//! They run against a simulated device with `cargo test --features std`. With `OPENSK_HARDWARE`
+//! set, they run against the connected OpenSK instead, then add `-- --test-threads=1` and touch
+//! the device when it asks for presence.
+
+#![cfg(feature = "std")]
+
+use bbs_wallet::hid::{Device, HidError};
+use bbs_wallet::issuer::{self, TestIssuer};
+use bbs_wallet::simulation;
+use bbs_wallet::vendor::{self, AttestationMaterial, VendorBBSProofRequest};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use sk_cbor::{cbor_array, cbor_map, destructure_cbor_map, Value};
+
+const CTAP2_MAKE_CREDENTIAL: u8 = 0x01;
+const CTAP2_GET_ASSERTION: u8 = 0x02;
+const CTAP2_GET_INFO: u8 = 0x04;
+const CTAP2_UNKNOWN_COMMAND: u8 = 0xDF;
+const CTAP2_OK: u8 = 0x00;
+const CTAP1_ERR_INVALID_COMMAND: u8 = 0x01;
+const CTAP2_ERR_NO_CREDENTIALS: u8 = 0x2E;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+const FLAG_EXTENSION_DATA: u8 = 0x80;
+const ES256_ALGORITHM: i64 = -7;
+
+const RP_ID: &str = "conformance.opensk.dev";
+const ISSUER_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../third_party/bbs/fixtures/proof.json"
+);
+// Only used in tests, never deployed.
+const ATTESTATION_PRIVATE_KEY: [u8; 32] = [0x11; 32];
+
+fn use_hardware() -> bool {
+    std::env::var_os("OPENSK_HARDWARE").is_some()
+}
+
+/// Opens the interface that accepts standard CTAP2 commands.
+fn open_fido() -> Device {
+    if use_hardware() {
+        Device::open_fido().unwrap()
+    } else {
+        simulation::open().unwrap()
+    }
+}
+
+/// Opens the interface that accepts vendor commands.
+fn open_vendor() -> Device {
+    if use_hardware() {
+        Device::open().unwrap()
+    } else {
+        simulation::open().unwrap()
+    }
+}
+
+/// Sends a CTAP2 command, and returns the decoded response or the status code.
+fn send_ctap2(device: &Device, command: u8, parameters: Option<Value>) -> Result<Value, u8> {
+    let mut request = vec![command];
+    if let Some(parameters) = parameters {
+        sk_cbor::write(parameters, &mut request).unwrap();
+    }
+    let response = device.cbor(&request).unwrap();
+    match response.split_first() {
+        Some((&CTAP2_OK, data)) => Ok(sk_cbor::read(data).unwrap()),
+        Some((&code, _)) => Err(code),
+        None => panic!("empty response"),
+    }
+}
+
+/// What a new credential tells the relying party.
+struct AttestedCredential {
+    id: Vec<u8>,
+    public_key: VerifyingKey,
+}
+
+/// Checks the common part of authenticator data, and returns the flags.
+fn check_authenticator_data(auth_data: &[u8]) -> u8 {
+    assert!(auth_data.len() >= 37);
+    assert_eq!(&auth_data[..32], &Sha256::digest(RP_ID.as_bytes())[..]);
+    let flags = auth_data[32];
+    assert_ne!(flags & FLAG_USER_PRESENT, 0);
+    assert_eq!(flags & FLAG_EXTENSION_DATA, 0);
+    flags
+}
+
+fn parse_attested_credential(auth_data: &[u8]) -> AttestedCredential {
+    let flags = check_authenticator_data(auth_data);
+    assert_ne!(flags & FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+    // After the RP ID hash, flags, signature counter and AAGUID.
+    let id_length = u16::from_be_bytes([auth_data[53], auth_data[54]]) as usize;
+    let id = auth_data[55..][..id_length].to_vec();
+    destructure_cbor_map! {
+        let {
+            1 => key_type,
+            3 => algorithm,
+            -1 => curve,
+            -2 => x,
+            -3 => y,
+        } = sk_cbor::read(&auth_data[55 + id_length..]).unwrap().extract_map().unwrap();
+    }
+    assert_eq!(key_type.unwrap().extract_integer(), Some(2));
+    assert_eq!(algorithm.unwrap().extract_integer(), Some(ES256_ALGORITHM));
+    assert_eq!(curve.unwrap().extract_integer(), Some(1));
+    let mut point = vec![0x04];
+    point.extend(x.unwrap().extract_byte_string().unwrap());
+    point.extend(y.unwrap().extract_byte_string().unwrap());
+    AttestedCredential {
+        id,
+        public_key: VerifyingKey::from_sec1_bytes(&point).unwrap(),
+    }
+}
+
+fn make_credential(device: &Device) -> AttestedCredential {
+    let parameters = cbor_map! {
+        0x01 => vec![0x01u8; 32],
+        0x02 => cbor_map! { "id" => RP_ID },
+        0x03 => cbor_map! { "id" => vec![0x02u8; 16], "name" => "conformance" },
+        0x04 => cbor_array![cbor_map! { "alg" => ES256_ALGORITHM, "type" => "public-key" }],
+    };
+    let response = send_ctap2(device, CTAP2_MAKE_CREDENTIAL, Some(parameters)).unwrap();
+    destructure_cbor_map! {
+        let {
+            0x01 => format,
+            0x02 => auth_data,
+            0x03 => attestation_statement,
+        } = response.extract_map().unwrap();
+    }
+    assert!(format.unwrap().extract_text_string().is_some());
+    assert!(attestation_statement.unwrap().extract_map().is_some());
+    parse_attested_credential(&auth_data.unwrap().extract_byte_string().unwrap())
+}
+
+#[test]
+fn test_get_info() {
+    let device = open_fido();
+    let response = send_ctap2(&device, CTAP2_GET_INFO, None).unwrap();
+    destructure_cbor_map! {
+        let {
+            0x01 => versions,
+            0x03 => aaguid,
+        } = response.extract_map().unwrap();
+    }
+    let versions = versions
+        .unwrap()
+        .extract_array()
+        .unwrap()
+        .into_iter()
+        .map(|version| version.extract_text_string().unwrap())
+        .collect::<Vec<_>>();
+    assert!(versions.iter().any(|version| version == "FIDO_2_0"));
+    assert_eq!(aaguid.unwrap().extract_byte_string().unwrap().len(), 16);
+}
+
+#[test]
+fn test_max_msg_size() {
+    let device = open_fido();
+    let response = send_ctap2(&device, CTAP2_GET_INFO, None).unwrap();
+    destructure_cbor_map! {
+        let {
+            0x05 => max_msg_size,
+        } = response.extract_map().unwrap();
+    }
+    let max_msg_size = max_msg_size.unwrap().extract_unsigned().unwrap() as usize;
+    assert_eq!(device.max_msg_size(), max_msg_size);
+
+    // A request of exactly maxMsgSize arrives whole, and fails as an unknown command.
+    let request = vec![CTAP2_UNKNOWN_COMMAND; max_msg_size];
+    assert_eq!(
+        device.cbor(&request).unwrap(),
+        vec![CTAP1_ERR_INVALID_COMMAND]
+    );
+    let request = vec![CTAP2_UNKNOWN_COMMAND; max_msg_size + 1];
+    assert!(matches!(
+        device.cbor(&request),
+        Err(HidError::TooLarge { size, .. }) if size == max_msg_size + 1
+    ));
+}
+
+#[test]
+fn test_compressed_vendor_commands() {
+    let mut device = open_vendor();
+    let plain = vendor::bbs_info(&device).unwrap();
+    if !vendor::negotiate_compression(&mut device).unwrap() {
+        // Firmware built without the compression feature keeps plain payloads.
+        assert!(use_hardware());
+        return;
+    }
+    assert!(device.compression());
+    let compressed = vendor::bbs_info(&device).unwrap();
+    assert_eq!(format!("{:?}", compressed), format!("{:?}", plain));
+}
+
+#[test]
+fn test_make_credential_and_get_assertion() {
+    let device = open_fido();
+    let credential = make_credential(&device);
+
+    let client_data_hash = vec![0x03u8; 32];
+    let parameters = cbor_map! {
+        0x01 => RP_ID,
+        0x02 => client_data_hash.clone(),
+        0x03 => cbor_array![cbor_map! { "id" => credential.id.clone(), "type" => "public-key" }],
+    };
+    let response = send_ctap2(&device, CTAP2_GET_ASSERTION, Some(parameters)).unwrap();
+    destructure_cbor_map! {
+        let {
+            0x01 => used_credential,
+            0x02 => auth_data,
+            0x03 => signature,
+        } = response.extract_map().unwrap();
+    }
+    destructure_cbor_map! {
+        let {
+            "id" => used_id,
+        } = used_credential.unwrap().extract_map().unwrap();
+    }
+    assert_eq!(used_id.unwrap().extract_byte_string(), Some(credential.id));
+    let auth_data = auth_data.unwrap().extract_byte_string().unwrap();
+    let flags = check_authenticator_data(&auth_data);
+    assert_eq!(flags & FLAG_ATTESTED_CREDENTIAL_DATA, 0);
+
+    let signature = signature.unwrap().extract_byte_string().unwrap();
+    let signature = Signature::from_der(&signature).unwrap();
+    let mut signed_data = auth_data;
+    signed_data.extend_from_slice(&client_data_hash);
+    assert!(credential
+        .public_key
+        .verify(&signed_data, &signature)
+        .is_ok());
+}
+
+#[test]
+fn test_get_assertion_unknown_credential() {
+    let device = open_fido();
+    let parameters = cbor_map! {
+        0x01 => RP_ID,
+        0x02 => vec![0x03u8; 32],
+        0x03 => cbor_array![cbor_map! { "id" => vec![0x04u8; 64], "type" => "public-key" }],
+    };
+    assert_eq!(
+        send_ctap2(&device, CTAP2_GET_ASSERTION, Some(parameters)),
+        Err(CTAP2_ERR_NO_CREDENTIALS)
+    );
+}
+
+#[test]
+fn test_bbs_issuance_and_presentation() {
+    let device = open_vendor();
+    let issuer = TestIssuer::load(ISSUER_PATH).unwrap();
+    // Commitments are only signed with attestation material. Devices keep what they have.
+    let configuration = vendor::configure(
+        &device,
+        Some(AttestationMaterial {
+            certificate: Some(b"conformance test certificate".to_vec()),
+            private_key: Some(ATTESTATION_PRIVATE_KEY.to_vec()),
+            link_secret: None,
+        }),
+        0,
+        None,
+        None,
+    )
+    .unwrap();
+    assert!(configuration.link_secret_programmed);
+
+    let challenge = issuer.challenge();
+    let commitment = vendor::bbs_commitment(&device, Some(&challenge), None, None).unwrap();
+    let header = b"conformance header";
+    let messages = vec![
+        b"name=Alice".to_vec(),
+        b"birthdate=2000-01-01".to_vec(),
+        b"country=JP".to_vec(),
+    ];
+    let signature = issuer
+        .issue(&commitment, &challenge, header, &messages)
+        .unwrap();
+
+    let presentation_header = b"verifier nonce";
+    let disclosed_indexes = [0, 2];
+    let request = VendorBBSProofRequest {
+        public_key: issuer.public_key().to_vec(),
+        messages: messages.clone(),
+        signature: signature.to_vec(),
+        header: header.to_vec(),
+        presentation_header: presentation_header.to_vec(),
+        disclosed_indexes: disclosed_indexes.to_vec(),
+        secret_prover_blind: Some(commitment.secret_prover_blind.clone()),
+        ..VendorBBSProofRequest::default()
+    };
+    let response = vendor::bbs_proof(&device, request).unwrap();
+    let disclosed_messages = vec![messages[0].clone(), messages[2].clone()];
+    assert!(issuer::verify_proof(
+        issuer.public_key(),
+        &response.proof_bytes,
+        header,
+        presentation_header,
+        &disclosed_messages,
+        &disclosed_indexes,
+    ));
+    // The verifier must not accept other disclosed values.
+    let forged_messages = vec![b"name=Mallory".to_vec(), messages[2].clone()];
+    assert!(!issuer::verify_proof(
+        issuer.public_key(),
+        &response.proof_bytes,
+        header,
+        presentation_header,
+        &forged_messages,
+        &disclosed_indexes,
+    ));
+
+    let request = VendorBBSProofRequest {
+        public_key: issuer.public_key().to_vec(),
+        messages: messages.clone(),
+        signature: signature.to_vec(),
+        header: header.to_vec(),
+        presentation_header: presentation_header.to_vec(),
+        secret_prover_blind: Some(commitment.secret_prover_blind.clone()),
+        ..VendorBBSProofRequest::default()
+    };
+    let response = vendor::bbs_possession(&device, request).unwrap();
+    assert!(issuer::verify_proof(
+        issuer.public_key(),
+        &response.proof_bytes,
+        header,
+        presentation_header,
+        &[],
+        &[],
+    ));
+}
@@ -22,6 +22,7 @@ from __future__ import print_function
 import argparse
 import getpass
 import datetime
+import os
 import sys
 from unittest.mock import patch
 import uuid
@@ -30,15 +31,41 @@ import colorama
 from tqdm.auto import tqdm
 
 from cryptography import x509
+from cryptography.exceptions import InvalidSignature
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives import hmac
+from cryptography.hazmat.primitives import padding
 from cryptography.hazmat.primitives import serialization
 from cryptography.hazmat.primitives.asymmetric import ec
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives.kdf.hkdf import HKDF
 
+from fido2 import cbor
 from fido2 import ctap
 from fido2 import ctap2
 from fido2 import hid
 
-OPENSK_VID_PID = (0x1915, 0x521F)
+# Vendors building with their own USB IDs set the same variables here.
+OPENSK_VID_PID = (int(os.environ.get("OPENSK_USB_VENDOR_ID", "0x1915"), 16),
+                  int(os.environ.get("OPENSK_USB_PRODUCT_ID", "0x521F"), 16))
 OPENSK_VENDOR_CONFIGURE = 0x40
+OPENSK_VENDOR_SECURE_CHANNEL = 0x48
+
+SECURE_CHANNEL_SETUP = 0x01
+SECURE_CHANNEL_CONFIGURE = 0x02
+SECURE_CHANNEL_TEARDOWN = 0x03
+SIGNATURE_CONTEXT = b"OpenSK provisioning device key"
+
+# Each level also includes the restrictions of the previous ones.
+LOCKDOWN_LEVELS = {
+    "none": 0,
+    # Attestation material and the link secret can't be provisioned anymore.
+    "config": 1,
+    # The firmware can't be upgraded anymore.
+    "upgrade": 2,
+    # The bootloader and JTAG access are locked.
+    "full": 3,
+}
 
 
 def fatal(msg):
@@ -81,16 +108,110 @@ def get_private_key(data, password=None):
     return get_private_key(data, password=password.encode(sys.stdin.encoding))
 
 
+def derive_session_keys(provisioning_key, device_public_key):
+  """Derives the encryption and MAC keys the device derived at setup."""
+  device_key = ec.EllipticCurvePublicKey.from_encoded_point(
+      ec.SECP256R1(), device_public_key)
+  shared_secret = provisioning_key.exchange(ec.ECDH(), device_key)
+  digest = hashes.Hash(hashes.SHA256())
+  digest.update(device_public_key)
+  salt = digest.finalize()
+
+  def derive(info):
+    return HKDF(
+        algorithm=hashes.SHA256(), length=32, salt=salt,
+        info=info).derive(shared_secret)
+
+  return (derive(b"OpenSK provisioning encryption key"),
+          derive(b"OpenSK provisioning MAC key"))
+
+
+def seal(session_keys, counter, plaintext):
+  """Encrypts the counter-th message of a session, returns it and its MAC."""
+  encryption_key, mac_key = session_keys
+  padder = padding.PKCS7(128).padder()
+  padded = padder.update(plaintext) + padder.finalize()
+  iv = os.urandom(16)
+  encryptor = Cipher(algorithms.AES(encryption_key), modes.CBC(iv)).encryptor()
+  ciphertext = iv + encryptor.update(padded) + encryptor.finalize()
+  mac = hmac.HMAC(mac_key, hashes.SHA256())
+  mac.update(counter.to_bytes(8, byteorder="big") + ciphertext)
+  return ciphertext, mac.finalize()
+
+
+def check_device_key(device_public_key, signature, device_certificate,
+                     trust_blank_device):
+  """Checks that the session key comes from the device we expect.
+
+  Provisioned devices sign their session key with their batch attestation key.
+  Blank devices have no key yet, so they are only trusted when asked to.
+  """
+  if signature is None:
+    if device_certificate is not None or not trust_blank_device:
+      fatal("The device didn't sign its session key. Only provision blank "
+            "devices over a trusted connection, with --trust-blank-device.")
+    return
+  if device_certificate is None:
+    fatal("The device signed its session key, please pass its attestation "
+          "certificate using --device-certificate.")
+  try:
+    device_certificate.public_key().verify(
+        signature, SIGNATURE_CONTEXT + device_public_key,
+        ec.ECDSA(hashes.SHA256()))
+  except InvalidSignature:
+    fatal("The session key isn't signed by the device certificate.")
+
+
+def send_configure(authenticator, cbor_data, provisioning_key,
+                   device_certificate, trust_blank_device):
+  """Sends the configure command, in a secure channel if a key is given."""
+  if provisioning_key is None:
+    return authenticator.send_cbor(OPENSK_VENDOR_CONFIGURE, data=cbor_data)
+  result = authenticator.send_cbor(
+      OPENSK_VENDOR_SECURE_CHANNEL, data={1: SECURE_CHANNEL_SETUP})
+  # Nothing is sent before we know that nobody in between picked the key.
+  check_device_key(result[1], result.get(2), device_certificate,
+                   trust_blank_device)
+  session_keys = derive_session_keys(provisioning_key, result[1])
+  ciphertext, mac = seal(session_keys, 0, cbor.encode(cbor_data))
+  try:
+    return authenticator.send_cbor(
+        OPENSK_VENDOR_SECURE_CHANNEL,
+        data={
+            1: SECURE_CHANNEL_CONFIGURE,
+            2: ciphertext,
+            3: mac
+        })
+  finally:
+    authenticator.send_cbor(
+        OPENSK_VENDOR_SECURE_CHANNEL, data={1: SECURE_CHANNEL_TEARDOWN})
+
+
 def main(args):
   colorama.init()
   # We need either both the certificate and the key or none
   if bool(args.priv_key) ^ bool(args.certificate):
     fatal("Certificate and private key must be set together or both omitted.")
 
-  cbor_data = {1: args.lock}
+  lockdown_level = LOCKDOWN_LEVELS[args.lockdown_level]
+  if args.lock:
+    lockdown_level = LOCKDOWN_LEVELS["full"]
+  cbor_data = {1: lockdown_level}
+
+  provisioning_key = None
+  if args.provisioning_key:
+    provisioning_key = get_private_key(args.provisioning_key.read())
+    if not isinstance(provisioning_key, ec.EllipticCurvePrivateKey):
+      fatal("Provisioning key must be an Elliptic Curve one.")
+    if not isinstance(provisioning_key.curve, ec.SECP256R1):
+      fatal("Provisioning key must use Secp256r1 curve.")
+
+  device_certificate = None
+  if args.device_certificate:
+    device_certificate = x509.load_pem_x509_certificate(
+        args.device_certificate.read())
 
   if args.priv_key:
-    cbor_data[1] = args.lock
     priv_key = get_private_key(args.priv_key.read())
     if not isinstance(priv_key, ec.EllipticCurvePrivateKey):
       fatal("Private key must be an Elliptic Curve one.")
@@ -129,6 +250,22 @@ def main(args):
             link_secret_bytes
     }
 
+  if args.aaguid:
+    cbor_data[4] = args.aaguid.bytes
+
+  if args.min_bundle_version is not None:
+    if not provisioning_key:
+      fatal("Overriding the minimum bundle version needs the provisioning key.")
+    cbor_data[5] = args.min_bundle_version
+
+  if args.bundle_key:
+    if not provisioning_key:
+      fatal("Provisioning the bundle key needs the provisioning key.")
+    bundle_key = bytes.fromhex(args.bundle_key.read().strip().decode("utf-8"))
+    if len(bundle_key) != 32:
+      fatal("The bundle key must have 32 bytes.")
+    cbor_data[6] = bundle_key
+
   patcher = None
   if args.use_vendor_hid:
     patcher = patch.object(hid.base, "FIDO_USAGE_PAGE", 0xFF00)
@@ -148,31 +285,41 @@ def main(args):
       authenticator.device.wink()
     aaguid = uuid.UUID(bytes=authenticator.get_info().aaguid)
     info(f"Programming OpenSK device AAGUID {aaguid} ({authenticator.device}).")
-    if args.lock or args.priv_key:
+    if lockdown_level or args.priv_key or args.aaguid:
       info("Please touch the device to confirm...")
     try:
-      result = authenticator.send_cbor(
-          OPENSK_VENDOR_CONFIGURE,
-          data=cbor_data,
-      )
+      result = send_configure(authenticator, cbor_data, provisioning_key,
+                              device_certificate, args.trust_blank_device)
       status = {"cert": result[1], "pkey": result[2]}
       responses.append(status)
       # pylint: disable-next=W1405
       info(f"Certificate: {'Present' if result[1] else 'Missing'}")
       # pylint: disable-next=W1405
       info(f"Private Key: {'Present' if result[2] else 'Missing'}")
-      if args.lock:
-        info("Device is now locked down!")
+      if result.get(6):
+        aaguid = uuid.UUID(bytes=authenticator.get_info().aaguid)
+        info(f"Customer AAGUID: {aaguid}")
+      if 4 in result and result[4]:
+        level = next(
+            name for name, value in LOCKDOWN_LEVELS.items() if value == result[4])
+        info(f"Device is now locked down (level: {level})!")
     except ctap.CtapError as ex:
       if ex.code.value == ctap.CtapError.ERR.INVALID_COMMAND:
         error("Failed to configure OpenSK (unsupported command).")
       elif ex.code.value == 0xF2:  # VENDOR_INTERNAL_ERROR
-        error(("Failed to configure OpenSK (lockdown conditions not met "
-               "or hardware error)."))
+        error("Failed to configure OpenSK (hardware error).")
+      elif ex.code.value == 0xF4:  # VENDOR_LOCKDOWN_REFUSED
+        error(("Failed to configure OpenSK (lockdown conditions not met: "
+               "attestation material or link secret missing, or the running "
+               "firmware is not intact)."))
+      elif ex.code.value == ctap.CtapError.ERR.PIN_AUTH_INVALID:
+        error(("Failed to configure OpenSK (the provisioning key doesn't "
+               "match the one in the firmware)."))
       elif ex.code.value == ctap.CtapError.ERR.INVALID_PARAMETER:
         error(
             ("Failed to configure OpenSK (device is partially programmed but "
-             "the given cert/key don't match the ones currently programmed)."))
+             "the given cert/key don't match the ones currently programmed, "
+             "or the certificate names another AAGUID than the device)."))
       else:
         error(f"Failed to configure OpenSK (unknown error: {ex})")
   return responses
@@ -214,6 +361,61 @@ if __name__ == "__main__":
       dest="link_secret",
       help=("text file containing the link secret for bbs feature")
   )
+  parser.add_argument(
+      "--aaguid",
+      type=uuid.UUID,
+      default=None,
+      metavar="UUID",
+      dest="aaguid",
+      help=("AAGUID of the customer, replacing the one of the firmware if it "
+            "allows. It can't be changed once programmed."),
+  )
+  parser.add_argument(
+      "--min-bundle-version",
+      type=int,
+      default=None,
+      metavar="VERSION",
+      dest="min_bundle_version",
+      help=("Overrides the minimum version of accepted upgrade bundles, e.g. "
+            "to go back to a previous release. Needs --provisioning-key."),
+  )
+  parser.add_argument(
+      "--bundle-key",
+      type=argparse.FileType("rb"),
+      default=None,
+      metavar="HEX_FILE",
+      dest="bundle_key",
+      help=("Text file containing the hex encoded AES-256 key of encrypted "
+            "upgrade bundles. Needs --provisioning-key."),
+  )
+  parser.add_argument(
+      "--provisioning-key",
+      type=argparse.FileType("rb"),
+      default=None,
+      metavar="PEM_FILE",
+      dest="provisioning_key",
+      help=("PEM file containing the vendor provisioning private key. If set, "
+            "the configuration is sent encrypted in a secure channel."),
+  )
+  parser.add_argument(
+      "--device-certificate",
+      type=argparse.FileType("rb"),
+      default=None,
+      metavar="PEM_FILE",
+      dest="device_certificate",
+      help=("PEM file containing the attestation certificate already on the "
+            "device. The device signs its secure channel key with the matching "
+            "private key."),
+  )
+  parser.add_argument(
+      "--trust-blank-device",
+      default=False,
+      action="store_true",
+      dest="trust_blank_device",
+      help=("Opens a secure channel with a device that has no attestation key "
+            "to sign with yet. Only use it over a trusted connection, e.g. in "
+            "the factory."),
+  )
   parser.add_argument(
       "--lock-device",
       default=False,
@@ -223,6 +425,15 @@ if __name__ == "__main__":
             "This command can fail if the certificate or the private key "
             "haven't been both programmed yet."),
   )
+  parser.add_argument(
+      "--lockdown-level",
+      default="none",
+      choices=LOCKDOWN_LEVELS.keys(),
+      dest="lockdown_level",
+      help=("Raises the lockdown level: config stops provisioning, upgrade "
+            "also stops firmware upgrades, full also locks the device like "
+            "--lock-device. Levels can't be lowered."),
+  )
   parser.add_argument(
       "--vendor-hid",
       default=False,
@@ -32,17 +32,19 @@ from tqdm.auto import tqdm
 from cryptography.hazmat.primitives import hashes
 from cryptography.hazmat.primitives.asymmetric import ec
 from cryptography.hazmat.primitives.asymmetric.utils import decode_dss_signature
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
 
 from fido2 import ctap
 from fido2 import hid
 from tockloader import tab
 from tools.configure import fatal, error, info, get_opensk_devices, get_private_key
 
-OPENSK_VID_PID = (0x1915, 0x521F)
 OPENSK_VENDOR_UPGRADE = 0x42
 OPENSK_VENDOR_UPGRADE_INFO = 0x43
+OPENSK_VENDOR_VERIFY_UPGRADE = 0x4F
 PAGE_SIZE = 0x1000
 METADATA_SIGN_OFFSET = 0x800
+BUNDLE_IV_CONTEXT = b"OpenSK bundle IV"
 KERNEL_SIZE = 0x20000
 APP_SIZE = 0x20000
 PARTITION_ADDRESS = {
@@ -109,6 +111,29 @@ def check_info(partition_address: int, authenticator: Any):
     fatal(f"Failed to read OpenSK upgrade info (error: {ex})")
 
 
+def verify_bundle(authenticator: Any):
+  """Checks the written bundle, which boots at the next restart if valid."""
+  outcomes = {
+      0: "valid",
+      1: "missing",
+      2: "invalid metadata",
+      3: "hash mismatch",
+  }
+  try:
+    result = authenticator.send_cbor(
+        OPENSK_VENDOR_VERIFY_UPGRADE,
+        data={},
+    )
+  except ctap.CtapError as ex:
+    fatal(f"Failed to verify the OpenSK bundle (error: {ex})")
+  outcome = outcomes.get(result[0x01], "unknown")
+  if result[0x01] != 0:
+    error(f"Bundle version {result.get(0x02)}: {outcome}.")
+  else:
+    info(f"Bundle version {result[0x02]} is valid, it boots at the next "
+         "restart.")
+
+
 def get_kernel(board: str) -> bytes:
   """Reads the kernel binary from file."""
   kernel_file = f"third_party/tock/target/{ARCH}/release/{board}.bin"
@@ -170,17 +195,36 @@ def sign_firmware(data: bytes, priv_key: Any) -> bytes:
   return r.to_bytes(32, "big") + s.to_bytes(32, "big")
 
 
+def encrypt_partition(partition: bytes, key: bytes) -> bytes:
+  """Encrypts the firmware of the partition with AES-256 in counter mode.
+
+  The device decrypts each chunk with the key provisioned by configure.py, the
+  counter of a block follows from its offset. The metadata stays in the clear,
+  because the device derives the IV from its hash.
+  """
+  iv = hash_message(BUNDLE_IV_CONTEXT + partition[:32])[:16]
+  encryptor = Cipher(algorithms.AES(key), modes.CTR(iv)).encryptor()
+  ciphertext = encryptor.update(partition) + encryptor.finalize()
+  return partition[:PAGE_SIZE] + ciphertext[PAGE_SIZE:]
+
+
 def main(args):
   colorama.init()
   if not args.priv_key:
     fatal("Please pass in a private key file using --private-key.")
 
-  firmware_image = generate_firmware_image(args.board)
-  partition_address = PARTITION_ADDRESS[args.board]
-  priv_key = load_priv_key(args.priv_key)
-  metadata = create_metadata(firmware_image, partition_address, args.version,
-                             priv_key)
-  partition = metadata + firmware_image
+  # Verifying needs neither the firmware nor the signing key.
+  if not args.verify_only:
+    firmware_image = generate_firmware_image(args.board)
+    partition_address = PARTITION_ADDRESS[args.board]
+    priv_key = load_priv_key(args.priv_key)
+    metadata = create_metadata(firmware_image, partition_address, args.version,
+                               priv_key)
+    partition = metadata + firmware_image
+  encrypted = bool(args.bundle_key) and not args.verify_only
+  if encrypted:
+    key = bytes.fromhex(args.bundle_key.read().strip().decode("utf-8"))
+    partition = encrypt_partition(partition, key)
 
   if args.use_vendor_hid:
     patcher = patch.object(hid.base, "FIDO_USAGE_PAGE", 0xFF00)
@@ -196,6 +240,11 @@ def main(args):
     if authenticator.device.capabilities & hid.CAPABILITY.WINK:
       authenticator.device.wink()
     aaguid = uuid.UUID(bytes=authenticator.get_info().aaguid)
+    if args.verify_only:
+      info(f"Verifying OpenSK device AAGUID {aaguid} "
+           f"({authenticator.device}).")
+      verify_bundle(authenticator)
+      continue
     info(f"Upgrading OpenSK device AAGUID {aaguid} ({authenticator.device}).")
 
     running_version = authenticator.get_info().firmware_version
@@ -212,6 +261,8 @@ def main(args):
         page = partition[offset:][:PAGE_SIZE]
         info(f"Writing at offset 0x{offset:08X}...")
         cbor_data = {1: offset, 2: page, 3: hash_message(page)}
+        if encrypted and offset >= PAGE_SIZE:
+          cbor_data[4] = True
         authenticator.send_cbor(
             OPENSK_VENDOR_UPGRADE,
             data=cbor_data,
@@ -262,6 +313,23 @@ if __name__ == "__main__":
       dest="priv_key",
       help=("PEM file for signing the firmware."),
   )
+  parser.add_argument(
+      "--bundle-key",
+      type=argparse.FileType("rb"),
+      default=None,
+      metavar="HEX_FILE",
+      dest="bundle_key",
+      help=("Text file containing the hex encoded key to encrypt the bundle "
+            "with, as provisioned on the device."),
+  )
+  parser.add_argument(
+      "--verify-only",
+      default=False,
+      action="store_true",
+      dest="verify_only",
+      help=("Only verifies the bundle written earlier, e.g. before restarting "
+            "devices in a maintenance window."),
+  )
   parser.add_argument(
       "--vendor-hid",
       default=False,
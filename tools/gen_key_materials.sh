@@ -33,6 +33,12 @@ generate_crypto_materials () {
   local opensk_upgrade=crypto_data/opensk_upgrade.key
   local opensk_upgrade_pub=crypto_data/opensk_upgrade_pub.pem
 
+  # The provisioning private key stays with the vendor, the corresponding
+  # public key is embedded into the firmware to open encrypted sessions for
+  # configuring attestation material.
+  local opensk_provisioning=crypto_data/opensk_provisioning.key
+  local opensk_provisioning_pub=crypto_data/opensk_provisioning_pub.pem
+
   # Allow invoker to override the command with a full path.
   local openssl=${OPENSSL:-$(which openssl)}
 
@@ -71,6 +77,11 @@ generate_crypto_materials () {
       -sha256
   fi
 
+  if [ "${force_generate}" = "Y" -o ! -f "${aaguid_file}" ]
+  then
+    uuidgen > "${aaguid_file}"
+  fi
+
   if [ "${force_generate}" = "Y" -o ! -f "${opensk_key}" ]
   then
     "${openssl}" ecparam -genkey -name prime256v1 -out "${opensk_key}"
@@ -78,6 +89,15 @@ generate_crypto_materials () {
 
   if [ "${force_generate}" = "Y" -o ! -f "${opensk_cert_name}.pem" ]
   then
+    # The FIDO extension names the AAGUID, which the firmware checks at
+    # provisioning.
+    local opensk_cert_ext_file
+    opensk_cert_ext_file=$(mktemp)
+    {
+      cat "${openssl_ext_file}"
+      echo
+      echo "1.3.6.1.4.1.45724.1.1.4=DER:0410$(tr -d '\n-' < "${aaguid_file}")"
+    } > "${opensk_cert_ext_file}"
     "${openssl}" req \
       -new \
       -key "${opensk_key}" \
@@ -88,12 +108,13 @@ generate_crypto_materials () {
       -days 3652 \
       -in "${opensk_cert_name}.csr" \
       -CA "${ca_cert_name}.pem" \
-      -extfile "${openssl_ext_file}" \
+      -extfile "${opensk_cert_ext_file}" \
       -CAkey "${ca_priv_key}" \
       -CAcreateserial \
       -outform pem \
       -out "${opensk_cert_name}.pem" \
       -sha256
+    rm -f "${opensk_cert_ext_file}"
   fi
 
   if [ "${force_generate}" = "Y" -o ! -f "${opensk_upgrade}" ]
@@ -107,9 +128,15 @@ generate_crypto_materials () {
     "${openssl}" ec -in "${opensk_upgrade}" -pubout -out "${opensk_upgrade_pub}"
   fi
 
-  if [ "${force_generate}" = "Y" -o ! -f "${aaguid_file}" ]
+  if [ "${force_generate}" = "Y" -o ! -f "${opensk_provisioning}" ]
   then
-    uuidgen > "${aaguid_file}"
+    "${openssl}" ecparam -genkey -name prime256v1 -out "${opensk_provisioning}"
+    rm -f "${opensk_provisioning_pub}"
+  fi
+
+  if [ "${force_generate}" = "Y" -o ! -f "${opensk_provisioning_pub}" ]
+  then
+    "${openssl}" ec -in "${opensk_provisioning}" -pubout -out "${opensk_provisioning_pub}"
   fi
 }
 
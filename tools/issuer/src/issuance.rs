@@ -0,0 +1,469 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The issuer side of blinded issuance.
+//!
+//! An issuance goes through these steps:
+//! 1. The issuer sends a fresh challenge.
+//! 2. The device commits to its link secret, and signs the commitment and the challenge with its
+//!    attestation key.
+//! 3. The issuer checks the challenge, the attestation and the commitment proof.
+//! 4. The issuer blindly signs the messages, bound to the committed link secret.
+//!
+//! Devices paired with a backup also sign the commitment with their recovery key for the issuer.
+//! Issuers keep the key, so that when a holder loses the device, they recognize the backup by it.
+
+use bbs::{
+    commitment_transcript, recovery_transcript, verify_link_secret_commitment, BBSPublicKey,
+    BBSSecretKey, BBS,
+};
+use bbs_wallet::attestation::{verify_attestation, AttestationError};
+use bbs_wallet::vendor::{self, Challenge, Commitment};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use rand_core::{OsRng, RngCore};
+use serde_json::Value;
+use std::{fmt, fs};
+use zkryptium::schemes::generics::BlindSignature;
+
+/// How long a commitment challenge is accepted, in seconds.
+const CHALLENGE_LIFETIME: u64 = 300;
+const NONCE_SIZE: usize = 16;
+
+/// Why a commitment was refused, in the order of the checks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssuanceError {
+    /// The device didn't echo the challenge, e.g. it answered an older one.
+    ChallengeMismatch,
+    ChallengeExpired,
+    /// The device has no attestation material, so nothing vouches for it.
+    MissingAttestation,
+    /// The attestation certificate isn't one of the trusted batch certificates.
+    UntrustedCertificate,
+    InvalidCertificate,
+    InvalidAttestationSignature,
+    /// The proof of knowledge of the link secret doesn't verify.
+    InvalidCommitment,
+    InvalidRecoverySignature,
+    /// The device doesn't hold the recovery key of the lost device.
+    RecoveryKeyMismatch,
+    SigningFailed,
+}
+
+impl fmt::Display for IssuanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            IssuanceError::ChallengeMismatch => "the commitment doesn't answer the challenge",
+            IssuanceError::ChallengeExpired => "the challenge expired",
+            IssuanceError::MissingAttestation => "the commitment isn't attested",
+            IssuanceError::UntrustedCertificate => "the attestation certificate isn't trusted",
+            IssuanceError::InvalidCertificate => "the attestation certificate is malformed",
+            IssuanceError::InvalidAttestationSignature => "the attestation signature is invalid",
+            IssuanceError::InvalidCommitment => "the link secret commitment is invalid",
+            IssuanceError::InvalidRecoverySignature => "the recovery signature is invalid",
+            IssuanceError::RecoveryKeyMismatch => "the device isn't a backup of the lost device",
+            IssuanceError::SigningFailed => "the blind signature failed",
+        };
+        f.write_str(message)
+    }
+}
+
+impl From<AttestationError> for IssuanceError {
+    fn from(error: AttestationError) -> Self {
+        match error {
+            AttestationError::UntrustedCertificate => IssuanceError::UntrustedCertificate,
+            AttestationError::InvalidCertificate => IssuanceError::InvalidCertificate,
+            AttestationError::InvalidSignature => IssuanceError::InvalidAttestationSignature,
+        }
+    }
+}
+
+pub struct Issuer {
+    secret_key: BBSSecretKey,
+    public_key: BBSPublicKey,
+    public_key_bytes: Vec<u8>,
+    /// DER encoded batch attestation certificates of the accepted devices.
+    trusted_certificates: Vec<Vec<u8>>,
+}
+
+impl Issuer {
+    /// Loads the key pair from a JSON file, in the format of the wallet test issuer.
+    pub fn load(path: &str, trusted_certificates: Vec<Vec<u8>>) -> Result<Issuer, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Value = serde_json::from_str(&contents).map_err(|e| e.to_string())?;
+        let key_pair = json.get("signerKeyPair").unwrap_or(&json);
+        let hex_field = |key: &str| {
+            let field = key_pair
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("missing {}", key))?;
+            hex::decode(field).map_err(|_| format!("{} is not hex", key))
+        };
+        let public_key_bytes = hex_field("publicKey")?;
+        Ok(Issuer {
+            secret_key: BBSSecretKey::from_bytes(&hex_field("secretKey")?)
+                .map_err(|_| String::from("invalid issuer secret key"))?,
+            public_key: BBSPublicKey::from_bytes(&public_key_bytes)
+                .map_err(|_| String::from("invalid issuer public key"))?,
+            public_key_bytes,
+            trusted_certificates,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key_bytes
+    }
+
+    /// Scope that devices derive the recovery key for this issuer from.
+    pub fn recovery_scope(&self) -> Vec<u8> {
+        vendor::recovery_scope(&self.public_key_bytes)
+    }
+
+    /// Creates a fresh challenge for the next commitment.
+    pub fn challenge(&self, now: u64) -> Challenge {
+        let mut nonce = vec![0; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        Challenge {
+            nonce,
+            expiry: now + CHALLENGE_LIFETIME,
+        }
+    }
+
+    /// Checks that a trusted device answered the challenge with a valid commitment.
+    pub fn verify_commitment(
+        &self,
+        commitment: &Commitment,
+        challenge: &Challenge,
+        now: u64,
+    ) -> Result<(), IssuanceError> {
+        if commitment.challenge.as_ref() != Some(challenge) {
+            return Err(IssuanceError::ChallengeMismatch);
+        }
+        if now > challenge.expiry {
+            return Err(IssuanceError::ChallengeExpired);
+        }
+        let (signature, certificate) = match (&commitment.signature, &commitment.certificate) {
+            (Some(signature), Some(certificate)) => (signature, certificate),
+            _ => return Err(IssuanceError::MissingAttestation),
+        };
+        let transcript = commitment_transcript(
+            &commitment.commitment_with_proof,
+            &challenge.nonce,
+            challenge.expiry,
+        );
+        verify_attestation(
+            &transcript,
+            signature,
+            certificate,
+            &self.trusted_certificates,
+        )?;
+        if !verify_link_secret_commitment(&commitment.commitment_with_proof).unwrap_or(false) {
+            return Err(IssuanceError::InvalidCommitment);
+        }
+        match (
+            &commitment.recovery_public_key,
+            &commitment.recovery_signature,
+        ) {
+            (None, None) => Ok(()),
+            (Some(public_key), Some(signature)) => {
+                let transcript = recovery_transcript(
+                    &commitment.commitment_with_proof,
+                    &challenge.nonce,
+                    challenge.expiry,
+                );
+                verify_recovery_signature(&transcript, signature, public_key)
+            }
+            _ => Err(IssuanceError::InvalidRecoverySignature),
+        }
+    }
+
+    /// Checks that the commitment comes from a backup of the device that had the recovery key.
+    ///
+    /// Call it after `verify_commitment`, which checks the recovery signature.
+    pub fn verify_continuity(
+        &self,
+        commitment: &Commitment,
+        recovery_public_key: &[u8],
+    ) -> Result<(), IssuanceError> {
+        if commitment.recovery_public_key.as_deref() != Some(recovery_public_key) {
+            return Err(IssuanceError::RecoveryKeyMismatch);
+        }
+        Ok(())
+    }
+
+    /// Verifies the commitment, then blindly signs the messages and returns the signature.
+    pub fn issue(
+        &self,
+        commitment: &Commitment,
+        challenge: &Challenge,
+        header: &[u8],
+        messages: &[Vec<u8>],
+        now: u64,
+    ) -> Result<Vec<u8>, IssuanceError> {
+        self.verify_commitment(commitment, challenge, now)?;
+        let signature = BlindSignature::<BBS>::blind_sign(
+            &self.secret_key,
+            &self.public_key,
+            Some(&commitment.commitment_with_proof),
+            Some(header),
+            Some(messages),
+            None,
+        )
+        .map_err(|_| IssuanceError::SigningFailed)?;
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+fn verify_recovery_signature(
+    transcript: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<(), IssuanceError> {
+    let public_key = VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|_| IssuanceError::InvalidRecoverySignature)?;
+    let signature =
+        Signature::from_der(signature).map_err(|_| IssuanceError::InvalidRecoverySignature)?;
+    public_key
+        .verify(transcript, &signature)
+        .map_err(|_| IssuanceError::InvalidRecoverySignature)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bbs::{
+        generate_link_secret_commitment, generate_proof, verify_presentation,
+        BBSCommitmentBlindFactor, BBSSignature, LinkSecret,
+    };
+    use bbs_wallet::attestation::certificate_der;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+    use std::convert::TryInto;
+
+    const ISSUER_PATH: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../third_party/bbs/fixtures/proof.json"
+    );
+    const SAMPLE_CERTIFICATE: &[u8] =
+        include_bytes!("../../../reproducible/sample_crypto_data/opensk_cert.pem");
+    // The key of the sample certificate, in `reproducible/sample_crypto_data/opensk.key`.
+    const SAMPLE_PRIVATE_KEY: &str =
+        "231f9c5cbb2f7cb9a7630e19ecdabc0792fa7a0a62d4f6d660dc5a594ded8d73";
+    const NOW: u64 = 1_700_000_000;
+
+    fn issuer() -> Issuer {
+        let certificate = certificate_der(SAMPLE_CERTIFICATE).unwrap();
+        Issuer::load(ISSUER_PATH, vec![certificate]).unwrap()
+    }
+
+    /// Signs the commitment and the challenge with the attestation key of the sample device.
+    fn attest(commitment_with_proof: &[u8], challenge: &Challenge) -> Vec<u8> {
+        let transcript =
+            commitment_transcript(commitment_with_proof, &challenge.nonce, challenge.expiry);
+        let signing_key =
+            SigningKey::from_slice(&hex::decode(SAMPLE_PRIVATE_KEY).unwrap()).unwrap();
+        let signature: Signature = signing_key.sign(&transcript);
+        signature.to_der().as_bytes().to_vec()
+    }
+
+    /// Answers the challenge like the device, and returns the link secret with the commitment.
+    fn commit(challenge: &Challenge) -> (LinkSecret, Commitment) {
+        let link_secret = LinkSecret::random(&mut OsRng);
+        let (commitment_with_proof, secret_prover_blind) =
+            generate_link_secret_commitment(&mut OsRng, &link_secret).unwrap();
+        let commitment = Commitment {
+            commitment_with_proof: commitment_with_proof.to_vec(),
+            secret_prover_blind: secret_prover_blind.to_vec(),
+            challenge: Some(challenge.clone()),
+            signature: Some(attest(&commitment_with_proof, challenge)),
+            certificate: Some(certificate_der(SAMPLE_CERTIFICATE).unwrap()),
+            recovery_public_key: None,
+            recovery_signature: None,
+        };
+        (link_secret, commitment)
+    }
+
+    /// Adds the recovery signature of a paired device.
+    fn sign_recovery(commitment: &mut Commitment, challenge: &Challenge, key: &SigningKey) {
+        let transcript = recovery_transcript(
+            &commitment.commitment_with_proof,
+            &challenge.nonce,
+            challenge.expiry,
+        );
+        let signature: Signature = key.sign(&transcript);
+        let public_key = key.verifying_key().to_encoded_point(false);
+        commitment.recovery_public_key = Some(public_key.as_bytes().to_vec());
+        commitment.recovery_signature = Some(signature.to_der().as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_issue() {
+        let issuer = issuer();
+        let challenge = issuer.challenge(NOW);
+        let (link_secret, commitment) = commit(&challenge);
+        let messages = vec![b"name=Alice".to_vec(), b"age=30".to_vec()];
+        let signature = issuer
+            .issue(&commitment, &challenge, b"header", &messages, NOW)
+            .unwrap();
+
+        // The holder proves with the credential, the verifier accepts it.
+        let proof = generate_proof(
+            &mut OsRng,
+            &BBSPublicKey::from_bytes(issuer.public_key()).unwrap(),
+            &messages,
+            &link_secret,
+            &BBSSignature::from_bytes(&signature.try_into().unwrap()).unwrap(),
+            Some(&b"header"[..]),
+            Some(&b"nonce"[..]),
+            &[1],
+            Some(&BBSCommitmentBlindFactor::from_bytes(&commitment.secret_prover_blind).unwrap()),
+        )
+        .unwrap();
+        assert!(verify_presentation(
+            issuer.public_key(),
+            &proof.proof.to_bytes(),
+            b"header",
+            b"nonce",
+            &messages[1..],
+            &[1],
+        ));
+    }
+
+    #[test]
+    fn test_verify_commitment_challenge() {
+        let issuer = issuer();
+        let challenge = issuer.challenge(NOW);
+        let (_, commitment) = commit(&challenge);
+        assert_eq!(
+            issuer.verify_commitment(&commitment, &issuer.challenge(NOW), NOW),
+            Err(IssuanceError::ChallengeMismatch)
+        );
+        assert_eq!(
+            issuer.verify_commitment(&commitment, &challenge, challenge.expiry + 1),
+            Err(IssuanceError::ChallengeExpired)
+        );
+    }
+
+    #[test]
+    fn test_verify_commitment_attestation() {
+        let issuer = issuer();
+        let challenge = issuer.challenge(NOW);
+        let (_, commitment) = commit(&challenge);
+        assert_eq!(
+            issuer.verify_commitment(&commitment, &challenge, NOW),
+            Ok(())
+        );
+
+        let unattested = Commitment {
+            signature: None,
+            ..commitment.clone()
+        };
+        assert_eq!(
+            issuer.verify_commitment(&unattested, &challenge, NOW),
+            Err(IssuanceError::MissingAttestation)
+        );
+
+        let untrusting = Issuer::load(ISSUER_PATH, Vec::new()).unwrap();
+        assert_eq!(
+            untrusting.verify_commitment(&commitment, &challenge, NOW),
+            Err(IssuanceError::UntrustedCertificate)
+        );
+
+        // The signature covers the commitment, so another one can't reuse it.
+        let (_, other) = commit(&challenge);
+        let swapped = Commitment {
+            commitment_with_proof: other.commitment_with_proof,
+            ..commitment.clone()
+        };
+        assert_eq!(
+            issuer.verify_commitment(&swapped, &challenge, NOW),
+            Err(IssuanceError::InvalidAttestationSignature)
+        );
+    }
+
+    #[test]
+    fn test_verify_commitment_recovery() {
+        let issuer = issuer();
+        let recovery_key = SigningKey::from_slice(&[0x55; 32]).unwrap();
+        let challenge = issuer.challenge(NOW);
+        let (_, mut primary) = commit(&challenge);
+        sign_recovery(&mut primary, &challenge, &recovery_key);
+        assert_eq!(issuer.verify_commitment(&primary, &challenge, NOW), Ok(()));
+        let recovery_public_key = primary.recovery_public_key.clone().unwrap();
+
+        // The backup signs its own commitment with the same key.
+        let challenge = issuer.challenge(NOW);
+        let (_, mut backup) = commit(&challenge);
+        sign_recovery(&mut backup, &challenge, &recovery_key);
+        assert_eq!(issuer.verify_commitment(&backup, &challenge, NOW), Ok(()));
+        assert_eq!(
+            issuer.verify_continuity(&backup, &recovery_public_key),
+            Ok(())
+        );
+
+        // Other devices can't claim the key, neither by copying it nor the signature.
+        let (_, mut other) = commit(&challenge);
+        sign_recovery(
+            &mut other,
+            &challenge,
+            &SigningKey::from_slice(&[0x66; 32]).unwrap(),
+        );
+        assert_eq!(
+            issuer.verify_continuity(&other, &recovery_public_key),
+            Err(IssuanceError::RecoveryKeyMismatch)
+        );
+        other.recovery_public_key = Some(recovery_public_key.clone());
+        assert_eq!(
+            issuer.verify_commitment(&other, &challenge, NOW),
+            Err(IssuanceError::InvalidRecoverySignature)
+        );
+        let replayed = Commitment {
+            recovery_signature: backup.recovery_signature.clone(),
+            ..other
+        };
+        assert_eq!(
+            issuer.verify_commitment(&replayed, &challenge, NOW),
+            Err(IssuanceError::InvalidRecoverySignature)
+        );
+        let unpaired = Commitment {
+            recovery_public_key: None,
+            recovery_signature: None,
+            ..backup
+        };
+        assert_eq!(
+            issuer.verify_continuity(&unpaired, &recovery_public_key),
+            Err(IssuanceError::RecoveryKeyMismatch)
+        );
+    }
+
+    #[test]
+    fn test_verify_commitment_proof() {
+        let issuer = issuer();
+        let challenge = issuer.challenge(NOW);
+        let (_, commitment) = commit(&challenge);
+        // Even an attested device can't get a signature without a valid proof.
+        let mut commitment_with_proof = commitment.commitment_with_proof.clone();
+        let last = commitment_with_proof.len() - 1;
+        commitment_with_proof[last] ^= 0x01;
+        let forged = Commitment {
+            signature: Some(attest(&commitment_with_proof, &challenge)),
+            commitment_with_proof,
+            ..commitment
+        };
+        assert_eq!(
+            issuer.issue(&forged, &challenge, b"header", &[], NOW),
+            Err(IssuanceError::InvalidCommitment)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2023 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reference issuer of BBS credentials bound to the link secret of an OpenSK.
+//!
+//! Unlike the test issuer of the wallet, it runs every check of the protocol: the challenge, the
+//! attestation of the commitment against trusted batch certificates, and the commitment proof.
+//! It talks to the device through the vendor interface, and writes the credential to a file.
+
+mod issuance;
+
+use bbs_wallet::attestation::certificate_der;
+use bbs_wallet::hid::Device;
+use bbs_wallet::issuer::unix_time;
+use bbs_wallet::vendor;
+use clap::{App, Arg, ArgMatches};
+use issuance::Issuer;
+use serde_json::json;
+use std::fs;
+
+fn parse_cli() -> ArgMatches<'static> {
+    App::new("BBS issuer")
+        .version("0.1")
+        .about("Issues a BBS credential to the connected OpenSK, after checking its attestation")
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("FILE")
+                .help("JSON file with the issuer key pair, secretKey and publicKey in hex")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("trusted-certificate")
+                .long("trusted-certificate")
+                .value_name("FILE")
+                .help("Accepted attestation certificate in DER or PEM, repeat for each batch")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("header")
+                .long("header")
+                .value_name("TEXT")
+                .help("Header signed with the messages")
+                .takes_value(true)
+                .default_value(""),
+        )
+        .arg(
+            Arg::with_name("message")
+                .long("message")
+                .value_name("TEXT")
+                .help("Attribute to sign, repeat for each attribute")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("recovery-key")
+                .long("recovery-key")
+                .value_name("HEX")
+                .help("Recovery key of a holder who lost the device, only its backup is accepted")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FILE")
+                .help("JSON file to write the credential to")
+                .takes_value(true)
+                .default_value("credential.json"),
+        )
+        .get_matches()
+}
+
+fn fatal(message: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}", message);
+    std::process::exit(1)
+}
+
+fn main() {
+    let matches = parse_cli();
+    let trusted_certificates = matches
+        .values_of("trusted-certificate")
+        .unwrap()
+        .map(|path| {
+            let bytes = fs::read(path).unwrap_or_else(|e| fatal(format!("{}: {}", path, e)));
+            certificate_der(&bytes).unwrap_or_else(|e| fatal(format!("{}: {}", path, e)))
+        })
+        .collect();
+    let issuer = Issuer::load(matches.value_of("key").unwrap(), trusted_certificates)
+        .unwrap_or_else(|e| fatal(e));
+    let header = matches.value_of("header").unwrap();
+    let messages = matches.values_of("message").map_or(Vec::new(), |values| {
+        values.map(String::from).collect::<Vec<_>>()
+    });
+    let message_bytes = messages
+        .iter()
+        .map(|message| message.as_bytes().to_vec())
+        .collect::<Vec<_>>();
+
+    let device = Device::open().unwrap_or_else(|e| fatal(e));
+    let info = vendor::bbs_info(&device).unwrap_or_else(|e| fatal(e));
+    if message_bytes.len() as u64 > info.max_messages {
+        fatal(format!(
+            "The device proves at most {} messages.",
+            info.max_messages
+        ));
+    }
+    let recovery_key = matches
+        .value_of("recovery-key")
+        .map(|key| hex::decode(key).unwrap_or_else(|_| fatal("the recovery key is not hex")));
+
+    let challenge = issuer.challenge(unix_time());
+    println!("Touch the device to confirm.");
+    let recovery_scope = issuer.recovery_scope();
+    let commitment = vendor::bbs_commitment(&device, Some(&challenge), None, Some(&recovery_scope))
+        .unwrap_or_else(|e| fatal(e));
+    // The recovery signature itself is checked with the commitment.
+    if let Some(recovery_key) = recovery_key {
+        issuer
+            .verify_continuity(&commitment, &recovery_key)
+            .unwrap_or_else(|e| fatal(e));
+    }
+    let signature = issuer
+        .issue(
+            &commitment,
+            &challenge,
+            header.as_bytes(),
+            &message_bytes,
+            unix_time(),
+        )
+        .unwrap_or_else(|e| fatal(e));
+    match &commitment.recovery_public_key {
+        Some(key) => println!("Recovery key of the holder: {}", hex::encode(key)),
+        None => println!("The device has no backup."),
+    }
+
+    // The field names follow `third_party/bbs/fixtures/proof.json`, like the wallet.
+    let credential = json!({
+        "publicKey": hex::encode(issuer.public_key()),
+        "header": header,
+        "messages": messages,
+        "signature": hex::encode(signature),
+        "proverBlindFactor": hex::encode(&commitment.secret_prover_blind),
+    });
+    let output = matches.value_of("output").unwrap();
+    let contents = serde_json::to_string_pretty(&credential).unwrap();
+    fs::write(output, contents).unwrap_or_else(|e| fatal(format!("{}: {}", output, e)));
+    println!("Wrote the credential to {}.", output);
+}
@@ -19,13 +19,14 @@ from fido2.hid.base import CtapHidConnection
 from fido2.client import Fido2Client, UserInteraction, ClientError
 from fido2.server import Fido2Server
 import hid
+import os
 import time
 from typing import Dict, Iterable
 import unittest
 from unittest.mock import patch
 
-_OPENSK_VID = 0x1915
-_OPENSK_PID = 0x521F
+_OPENSK_VID = int(os.environ.get("OPENSK_USB_VENDOR_ID", "0x1915"), 16)
+_OPENSK_PID = int(os.environ.get("OPENSK_USB_PRODUCT_ID", "0x521F"), 16)
 _FIDO_USAGE_PAGE = 0xF1D0
 _VENDOR_USAGE_PAGE = 0xFF00
 _PACKETS = 4